@@ -0,0 +1,42 @@
+import { bytesToHex, responseMessage, sealResponse, verifySignature } from './crypto';
+
+// Opened by test_gateway_response_vector in sdk-rust/src/lib.rs
+const GATEWAY_PRIVATE_KEY = '11'.repeat(32);
+const GATEWAY_PUBLIC_KEY = '034f355bdcb7cc0af728ef3cceb9615d90684bb5b2ca5f859ab0f0b704075871aa';
+const SESSION_KEY = new Uint8Array(32).fill(7);
+const REQUEST_NONCE = new Uint8Array(12).fill(1);
+const RESPONSE_NONCE = new Uint8Array(12).fill(2);
+const VECTOR = {
+  encrypted:
+    '619699753488d0546935e295d8f6be0faf3f4a8e19db4fc7b26b9e3938ab8a44' +
+    'b2e6a6b103c80ca3f386c19b2608b3de87a43c1c7631d3a9a1d4d9d5',
+  nonce: bytesToHex(RESPONSE_NONCE),
+  signature:
+    '1eee1cce233b247553c3da7acf08adaff8b5fc94957621a0765e67f5044942bc' +
+    '5b397f83433de7d65f77d3ac9574285a7a66f53a20acbbd5910975183672baac',
+};
+
+describe('sealResponse', () => {
+  it('produces the response the Rust SDK opens', async () => {
+    const sealed = await sealResponse(
+      { processed: true, timestamp: 1700000000000 },
+      SESSION_KEY,
+      REQUEST_NONCE,
+      GATEWAY_PRIVATE_KEY,
+      RESPONSE_NONCE
+    );
+    expect(sealed).toEqual(VECTOR);
+  });
+
+  it('signs over the nonce of the request answered', async () => {
+    const sealed = await sealResponse({ ok: true }, SESSION_KEY, REQUEST_NONCE, GATEWAY_PRIVATE_KEY);
+    const signed = (requestNonce: Uint8Array) =>
+      verifySignature(
+        responseMessage(requestNonce, Buffer.from(sealed.nonce, 'hex'), Buffer.from(sealed.encrypted, 'hex')),
+        sealed.signature,
+        GATEWAY_PUBLIC_KEY
+      );
+    expect(await signed(REQUEST_NONCE)).toBe(true);
+    expect(await signed(new Uint8Array(12).fill(9))).toBe(false);
+  });
+});
@@ -1,4 +1,4 @@
-import { randomBytes } from 'crypto';
+import { randomBytes, createCipheriv } from 'crypto';
 import * as secp from '@noble/secp256k1';
 import { sha256 } from '@noble/hashes/sha256';
 import { hkdf } from '@noble/hashes/hkdf';
@@ -68,6 +68,66 @@ export function deriveSessionKey(sharedSecret: Uint8Array): Uint8Array {
   return sessionKey;
 }
 
+/**
+ * Encrypt data with AES-256-GCM under a session key
+ *
+ * The 16-byte tag is appended to the ciphertext, as the SDKs expect.
+ */
+export function encryptPayload(
+  plaintext: Uint8Array,
+  sessionKey: Uint8Array,
+  nonce: Uint8Array = randomBytes(12)
+): { encrypted: Uint8Array; nonce: Uint8Array } {
+  const cipher = createCipheriv('aes-256-gcm', sessionKey, nonce);
+  const encrypted = concatBytes(cipher.update(plaintext), cipher.final(), cipher.getAuthTag());
+  return { encrypted, nonce };
+}
+
+/**
+ * Sign a message (compact secp256k1 ECDSA over its SHA256)
+ */
+export async function signMessage(message: Uint8Array, privateKey: string): Promise<string> {
+  const signature = await secp.signAsync(sha256(message), privateKey);
+  return signature.toCompactHex();
+}
+
+/**
+ * Message the gateway signs for an encrypted response
+ *
+ * Covers the nonce of the request answered, so a response cannot be
+ * replayed as the answer to another request.
+ */
+export function responseMessage(
+  requestNonce: Uint8Array,
+  nonce: Uint8Array,
+  encrypted: Uint8Array
+): Uint8Array {
+  return concatBytes(new TextEncoder().encode('H3DAC-response'), requestNonce, nonce, encrypted);
+}
+
+/**
+ * Encrypt a response body with the session key and sign it
+ */
+export async function sealResponse(
+  body: object,
+  sessionKey: Uint8Array,
+  requestNonce: Uint8Array,
+  privateKey: string,
+  nonce?: Uint8Array
+): Promise<{ encrypted: string; nonce: string; signature: string }> {
+  const plaintext = new TextEncoder().encode(JSON.stringify(body));
+  const sealed = encryptPayload(plaintext, sessionKey, nonce);
+  const signature = await signMessage(
+    responseMessage(requestNonce, sealed.nonce, sealed.encrypted),
+    privateKey
+  );
+  return {
+    encrypted: bytesToHex(sealed.encrypted),
+    nonce: bytesToHex(sealed.nonce),
+    signature,
+  };
+}
+
 /**
  * Hash data with SHA256
  */
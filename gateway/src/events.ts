@@ -0,0 +1,78 @@
+import { Response } from 'express';
+import { logger } from './logger';
+import { sendToClient } from './websocket';
+
+/**
+ * Server-pushed events, delivered over Server-Sent Events (GET /events)
+ * and to WebSocket connections of the same client
+ */
+export type GatewayEvent =
+  | { type: 'proof_confirmed'; blockTime: number; txHash?: string }
+  | { type: 'session_expiring'; expiresAt: number }
+  | { type: 'session_expired' };
+
+const subscribers = new Map<string, Set<Response>>();
+
+// Comment lines keep proxies from closing idle streams
+const KEEPALIVE_MS = 15000;
+
+/**
+ * Open an event stream for an authenticated client
+ *
+ * `initial` events are sent to this stream only. The stream announces the
+ * session's expiry a minute ahead and ends when the session expires.
+ */
+export function subscribeEvents(
+  clientId: string,
+  res: Response,
+  expiresAt: number,
+  initial: GatewayEvent[] = []
+) {
+  res.writeHead(200, {
+    'Content-Type': 'text/event-stream',
+    'Cache-Control': 'no-cache',
+    Connection: 'keep-alive',
+  });
+  res.write(': connected\n\n');
+  initial.forEach((event) => writeEvent(res, event));
+
+  const streams = subscribers.get(clientId) || new Set<Response>();
+  streams.add(res);
+  subscribers.set(clientId, streams);
+
+  const keepalive = setInterval(() => res.write(': keepalive\n\n'), KEEPALIVE_MS);
+  const remaining = expiresAt - Date.now();
+  const expiring = setTimeout(
+    () => writeEvent(res, { type: 'session_expiring', expiresAt }),
+    Math.max(0, remaining - 60000)
+  );
+  const expired = setTimeout(() => {
+    writeEvent(res, { type: 'session_expired' });
+    res.end();
+  }, Math.max(0, remaining));
+
+  res.on('close', () => {
+    clearInterval(keepalive);
+    clearTimeout(expiring);
+    clearTimeout(expired);
+    streams.delete(res);
+    if (streams.size === 0) {
+      subscribers.delete(clientId);
+    }
+    logger.info(`Event stream closed: ${clientId}`);
+  });
+
+  logger.info(`Event stream opened: ${clientId}`);
+}
+
+function writeEvent(res: Response, event: GatewayEvent) {
+  res.write(`event: ${event.type}\ndata: ${JSON.stringify(event)}\n\n`);
+}
+
+/**
+ * Push an event to all of a client's streams and WebSocket connection
+ */
+export function publishEvent(clientId: string, event: GatewayEvent) {
+  subscribers.get(clientId)?.forEach((res) => writeEvent(res, event));
+  sendToClient(clientId, event);
+}
@@ -9,6 +9,7 @@ import { initRedis } from './redis';
 import { logger } from './logger';
 import { generateGatewayKeys } from './crypto';
 import { setupWebSocket } from './websocket';
+import { rateLimit } from './ratelimit';
 
 // Load environment variables from parent directory's .env file
 dotenv.config({ path: path.resolve(__dirname, '../.env') });
@@ -23,7 +24,8 @@ const PORT = process.env.PORT || 3000;
 // Middleware
 app.use(helmet());
 app.use(cors());
-app.use(express.json());
+// Room for hex-encoded upload chunks (256 KiB by default in the SDKs)
+app.use(express.json({ limit: '1mb' }));
 
 // Request logging
 app.use((req, res, next) => {
@@ -32,6 +34,7 @@ app.use((req, res, next) => {
 });
 
 // Routes
+app.use(rateLimit);
 app.use('/', routes);
 
 // Error handling
@@ -42,9 +45,18 @@ app.use(
     res: express.Response,
     next: express.NextFunction
   ) => {
+    if ((err as any).type === 'entity.too.large') {
+      return res.status(413).json({
+        status: 'failed',
+        code: 'payload_too_large',
+        message: 'Payload too large',
+      });
+    }
+
     logger.error('Unhandled error:', err);
     res.status(500).json({
       status: 'error',
+      code: 'internal_error',
       message: 'Internal server error',
     });
   }
@@ -0,0 +1,53 @@
+import { Request, Response, NextFunction } from 'express';
+import { getRedisClient } from './redis';
+import { logger } from './logger';
+
+const WINDOW_SECONDS = 60;
+
+// Paths clients must always reach
+const EXEMPT = new Set(['/health', '/events']);
+
+/**
+ * Fixed-window rate limit per client address
+ *
+ * Every response carries RateLimit-Limit, RateLimit-Remaining and
+ * RateLimit-Reset (seconds); rejected requests get 429 with Retry-After.
+ */
+export async function rateLimit(req: Request, res: Response, next: NextFunction) {
+  const limit = parseInt(process.env.RATE_LIMIT_PER_MINUTE || '600');
+  if (EXEMPT.has(req.path) || limit <= 0) {
+    return next();
+  }
+
+  try {
+    const now = Math.floor(Date.now() / 1000);
+    const window = Math.floor(now / WINDOW_SECONDS);
+    const reset = (window + 1) * WINDOW_SECONDS - now;
+    const key = `ratelimit:${req.ip}:${window}`;
+
+    const client = getRedisClient();
+    const count = await client.incr(key);
+    if (count === 1) {
+      await client.expire(key, WINDOW_SECONDS);
+    }
+
+    res.setHeader('RateLimit-Limit', limit.toString());
+    res.setHeader('RateLimit-Remaining', Math.max(limit - count, 0).toString());
+    res.setHeader('RateLimit-Reset', reset.toString());
+
+    if (count > limit) {
+      logger.warn(`Rate limit exceeded: ${req.ip}`);
+      res.setHeader('Retry-After', reset.toString());
+      return res.status(429).json({
+        status: 'failed',
+        code: 'rate_limited',
+        message: 'Rate limit exceeded',
+        retryAfter: reset,
+      });
+    }
+  } catch (error) {
+    // Serve requests rather than fail them when Redis is unavailable
+    logger.error('Rate limiting failed:', error);
+  }
+  next();
+}
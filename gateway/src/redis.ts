@@ -91,16 +91,261 @@ export async function getSession(clientId: string): Promise<string | null> {
   return await client.get(`session:${clientId}`);
 }
 
+/**
+ * Record whether a session's payload responses are encrypted
+ */
+export async function storeSessionEncryption(
+  clientId: string,
+  encryptResponses: boolean,
+  expirySeconds: number = 3600
+): Promise<void> {
+  const client = getRedisClient();
+  const key = `session-encrypted:${clientId}`;
+  if (encryptResponses) {
+    await client.set(key, '1', { EX: expirySeconds });
+  } else {
+    await client.del(key);
+  }
+}
+
+/**
+ * Whether the client's session negotiated encrypted payload responses
+ */
+export async function sessionEncryptsResponses(clientId: string): Promise<boolean> {
+  const client = getRedisClient();
+  return (await client.get(`session-encrypted:${clientId}`)) === '1';
+}
+
+/**
+ * Store the public key a client authenticated with
+ */
+export async function storeClientKey(
+  clientId: string,
+  clientPubKey: string,
+  expirySeconds: number = 3600
+): Promise<void> {
+  const client = getRedisClient();
+  await client.set(`clientkey:${clientId}`, clientPubKey, { EX: expirySeconds });
+}
+
+/**
+ * Get the public key a client authenticated with
+ */
+export async function getClientKey(clientId: string): Promise<string | null> {
+  const client = getRedisClient();
+  return await client.get(`clientkey:${clientId}`);
+}
+
+/**
+ * Replace a client's public key if it still matches the expected one,
+ * keeping the key's remaining lifetime
+ */
+export async function rotateClientKey(
+  clientId: string,
+  currentPubKey: string,
+  newPubKey: string
+): Promise<boolean> {
+  const client = getRedisClient();
+  const swapped = await client.eval(
+    `if redis.call('GET', KEYS[1]) == ARGV[1] then
+       redis.call('SET', KEYS[1], ARGV[2], 'KEEPTTL')
+       return 1
+     end
+     return 0`,
+    { keys: [`clientkey:${clientId}`], arguments: [currentPubKey, newPubKey] }
+  );
+  return swapped === 1;
+}
+
+/**
+ * Open a chunked upload owned by a client
+ */
+export async function createUpload(
+  uploadId: string,
+  clientId: string,
+  expirySeconds: number = 3600
+): Promise<void> {
+  const client = getRedisClient();
+  await client.set(`upload:${uploadId}`, clientId, { EX: expirySeconds });
+}
+
+/**
+ * Get the client owning an upload
+ */
+export async function getUploadOwner(uploadId: string): Promise<string | null> {
+  const client = getRedisClient();
+  return await client.get(`upload:${uploadId}`);
+}
+
+/**
+ * Record a received chunk's ciphertext hash and length, returning whether
+ * the index was new
+ */
+export async function storeUploadChunk(
+  uploadId: string,
+  index: number,
+  hash: string,
+  bytes: number,
+  expirySeconds: number = 3600
+): Promise<boolean> {
+  const client = getRedisClient();
+  const added = await client.hSet(`upload:${uploadId}:chunks`, index.toString(), `${hash}:${bytes}`);
+  await client.expire(`upload:${uploadId}:chunks`, expirySeconds);
+  return added > 0;
+}
+
+/**
+ * Get received chunks by index
+ */
+export async function getUploadChunks(
+  uploadId: string
+): Promise<Record<string, { hash: string; bytes: number }>> {
+  const client = getRedisClient();
+  const chunks = await client.hGetAll(`upload:${uploadId}:chunks`);
+  return Object.fromEntries(
+    Object.entries(chunks).map(([index, value]) => {
+      const [hash, bytes] = value.split(':');
+      return [index, { hash, bytes: parseInt(bytes) }];
+    })
+  );
+}
+
+/**
+ * Store the receipt of a completed upload
+ */
+export async function storeUploadReceipt(
+  uploadId: string,
+  receipt: object,
+  expirySeconds: number = 86400
+): Promise<void> {
+  const client = getRedisClient();
+  await client.set(`upload:${uploadId}:receipt`, JSON.stringify(receipt), { EX: expirySeconds });
+  await client.del(`upload:${uploadId}:chunks`);
+}
+
+/**
+ * Get the receipt of a completed upload
+ */
+export async function getUploadReceipt(uploadId: string): Promise<object | null> {
+  const client = getRedisClient();
+  const data = await client.get(`upload:${uploadId}:receipt`);
+  return data ? JSON.parse(data) : null;
+}
+
+/**
+ * Get remaining session lifetime in seconds (negative if none)
+ */
+export async function getSessionTtl(clientId: string): Promise<number> {
+  const client = getRedisClient();
+  return await client.ttl(`session:${clientId}`);
+}
+
 /**
  * Delete session
  */
 export async function deleteSession(clientId: string): Promise<void> {
   const client = getRedisClient();
-  await client.del(`session:${clientId}`);
+  await client.del([`session:${clientId}`, `session-encrypted:${clientId}`]);
+}
+
+/**
+ * Store the response to an idempotent request, completing its claim
+ */
+export async function storeIdempotentResponse(
+  clientId: string,
+  idempotencyKey: string,
+  response: object,
+  expirySeconds: number = 86400
+): Promise<void> {
+  const client = getRedisClient();
+  await client.set(
+    `idempotency:${clientId}:${idempotencyKey}`,
+    JSON.stringify(response),
+    { EX: expirySeconds }
+  );
+}
+
+// Stored under an idempotency key while its request is being processed
+const IDEMPOTENCY_PENDING = 'pending';
+
+/**
+ * Claim an idempotency key before processing its request
+ *
+ * The claim is a single SET NX, so of several concurrent requests with the
+ * same key exactly one gets to process it.
+ *
+ * @returns null once claimed, 'pending' while another request holds the
+ * claim, or the stored response of the request that completed it
+ */
+export async function claimIdempotencyKey(
+  clientId: string,
+  idempotencyKey: string,
+  expirySeconds: number = 86400
+): Promise<object | 'pending' | null> {
+  const client = getRedisClient();
+  const key = `idempotency:${clientId}:${idempotencyKey}`;
+  const claimed = await client.set(key, IDEMPOTENCY_PENDING, { NX: true, EX: expirySeconds });
+  if (claimed) {
+    return null;
+  }
+  const data = await client.get(key);
+  if (data === null) {
+    // Released between the SET and the GET; let the client retry
+    return IDEMPOTENCY_PENDING;
+  }
+  return data === IDEMPOTENCY_PENDING ? IDEMPOTENCY_PENDING : JSON.parse(data);
+}
+
+/**
+ * Release a claimed idempotency key whose request was not completed
+ */
+export async function releaseIdempotencyKey(
+  clientId: string,
+  idempotencyKey: string
+): Promise<void> {
+  const client = getRedisClient();
+  await client.del(`idempotency:${clientId}:${idempotencyKey}`);
+}
+
+/**
+ * Add to a client's usage counters for one day (UTC day number)
+ */
+export async function incrementUsage(
+  clientId: string,
+  day: number,
+  usage: { bytes?: number; payloads?: number; proofs?: number }
+): Promise<void> {
+  const client = getRedisClient();
+  const key = `usage:${clientId}:${day}`;
+  for (const [field, amount] of Object.entries(usage)) {
+    if (amount) {
+      await client.hIncrBy(key, field, amount);
+    }
+  }
+  await client.expire(key, 2 * 86400);
+}
+
+/**
+ * Get a client's usage counters for one day (UTC day number)
+ */
+export async function getUsage(
+  clientId: string,
+  day: number
+): Promise<{ bytes: number; payloads: number; proofs: number }> {
+  const client = getRedisClient();
+  const usage = await client.hGetAll(`usage:${clientId}:${day}`);
+  return {
+    bytes: parseInt(usage.bytes || '0'),
+    payloads: parseInt(usage.payloads || '0'),
+    proofs: parseInt(usage.proofs || '0'),
+  };
 }
 
 /**
  * Store proof hash
+ *
+ * A proof carrying a transaction hash has been anchored on-chain and counts
+ * once toward the client's anchored proofs for the day of its block time.
  */
 export async function storeProof(
   clientId: string,
@@ -116,6 +361,15 @@ export async function storeProof(
     JSON.stringify(proofData),
     { EX: 86400 } // 24 hours
   );
+  if (proofData.txHash) {
+    const first = await client.set(`anchored:${proofData.txHash}`, clientId, {
+      NX: true,
+      EX: 2 * 86400,
+    });
+    if (first) {
+      await incrementUsage(clientId, Math.floor(proofData.blockTime / 86400000), { proofs: 1 });
+    }
+  }
 }
 
 /**
@@ -8,17 +8,36 @@ import {
   hexToBytes,
   bytesToHex,
   hashData,
+  sealResponse,
 } from './crypto';
 import {
   storeNonce,
   verifyAndConsumeNonce,
   storeSession,
+  storeSessionEncryption,
+  sessionEncryptsResponses,
   verifySession,
   getSession,
   storeProof,
   getProof,
+  storeIdempotentResponse,
+  claimIdempotencyKey,
+  releaseIdempotencyKey,
+  getSessionTtl,
+  storeClientKey,
+  getClientKey,
+  rotateClientKey,
+  incrementUsage,
+  getUsage,
+  createUpload,
+  getUploadOwner,
+  storeUploadChunk,
+  getUploadChunks,
+  storeUploadReceipt,
+  getUploadReceipt,
 } from './redis';
 import { logger } from './logger';
+import { publishEvent, subscribeEvents } from './events';
 
 const router = Router();
 
@@ -31,8 +50,32 @@ const getGatewayKeys = () => ({
 const getConfig = () => ({
   nonceExpiry: parseInt(process.env.NONCE_EXPIRY_SECONDS || '30'),
   sessionExpiry: parseInt(process.env.SESSION_EXPIRY_SECONDS || '3600'),
+  // Ciphertext bytes a client may submit per UTC day (0 = unlimited)
+  quotaBytesPerDay: parseInt(process.env.QUOTA_BYTES_PER_DAY || '0'),
 });
 
+const DAY_MS = 86400000;
+const usageDay = () => Math.floor(Date.now() / DAY_MS);
+
+/**
+ * Whether a client may submit `bytes` more ciphertext today
+ */
+async function withinQuota(clientId: string, bytes: number): Promise<boolean> {
+  const { quotaBytesPerDay } = getConfig();
+  if (quotaBytesPerDay <= 0) {
+    return true;
+  }
+  const usage = await getUsage(clientId, usageDay());
+  return usage.bytes + bytes <= quotaBytesPerDay;
+}
+
+const quotaExceeded = (res: Response) =>
+  res.status(403).json({
+    status: 'failed',
+    code: 'quota_exceeded',
+    message: 'Daily quota exceeded',
+  });
+
 /**
  * GET /nonce
  * Generate and return a new nonce
@@ -59,6 +102,7 @@ router.get('/nonce', async (req: Request, res: Response) => {
     logger.error('Error generating nonce:', error);
     res.status(500).json({
       status: 'failed',
+      code: 'internal_error',
       message: 'Internal server error',
     });
   }
@@ -67,15 +111,19 @@ router.get('/nonce', async (req: Request, res: Response) => {
 /**
  * POST /auth
  * Authenticate client and establish session
+ *
+ * Clients sending `encryptResponses: true` get `/payload` responses
+ * encrypted with the session key and signed by the gateway.
  */
 router.post('/auth', async (req: Request, res: Response) => {
   try {
-    const { clientId, signature, nonce, clientPubKey, timestamp } = req.body;
+    const { clientId, signature, nonce, clientPubKey, timestamp, encryptResponses } = req.body;
 
     // Validate required fields
     if (!clientId || !signature || !nonce || !clientPubKey || !timestamp) {
       return res.status(400).json({
         status: 'failed',
+        code: 'missing_fields',
         message: 'Missing required fields',
       });
     }
@@ -86,6 +134,7 @@ router.post('/auth', async (req: Request, res: Response) => {
       logger.warn(`Invalid or expired nonce: ${nonce.substring(0, 8)}...`);
       return res.status(401).json({
         status: 'failed',
+        code: 'nonce_expired',
         message: 'Invalid or expired nonce',
       });
     }
@@ -98,6 +147,7 @@ router.post('/auth', async (req: Request, res: Response) => {
       logger.warn(`Timestamp out of range for client: ${clientId}`);
       return res.status(401).json({
         status: 'failed',
+        code: 'timestamp_out_of_range',
         message: 'Timestamp out of valid range',
       });
     }
@@ -129,6 +179,7 @@ router.post('/auth', async (req: Request, res: Response) => {
       logger.warn(`Invalid signature for client: ${clientId}`);
       return res.status(401).json({
         status: 'failed',
+        code: 'invalid_signature',
         message: 'Invalid signature',
       });
     }
@@ -144,13 +195,17 @@ router.post('/auth', async (req: Request, res: Response) => {
     // Store session
     const expiresAt = Date.now() + sessionExpiry * 1000;
     await storeSession(clientId, sessionKeyHex, sessionExpiry);
+    await storeSessionEncryption(clientId, encryptResponses === true, sessionExpiry);
+    await storeClientKey(clientId, clientPubKey, sessionExpiry);
 
     // Store proof (for on-chain commitment)
     const sessionKeyHash = hashData(sessionKey);
+    const blockTime = Date.now();
     await storeProof(clientId, {
       sessionKeyHash,
-      blockTime: Date.now(),
+      blockTime,
     });
+    publishEvent(clientId, { type: 'proof_confirmed', blockTime });
 
     logger.info(`Client authenticated: ${clientId}`);
 
@@ -158,11 +213,13 @@ router.post('/auth', async (req: Request, res: Response) => {
       sessionKey: sessionKeyHex,
       status: 'success',
       expiresAt,
+      encryptedResponses: encryptResponses === true,
     });
   } catch (error) {
     logger.error('Error during authentication:', error);
     res.status(500).json({
       status: 'failed',
+      code: 'internal_error',
       message: 'Internal server error',
     });
   }
@@ -171,6 +228,14 @@ router.post('/auth', async (req: Request, res: Response) => {
 /**
  * POST /payload
  * Receive and verify encrypted payload
+ *
+ * Sessions that negotiated encrypted responses get the result encrypted
+ * with the session key, signed over the request's nonce (see `sealResponse`).
+ *
+ * A retried submission carrying the same Idempotency-Key header gets the
+ * stored response instead of being processed again. The key is claimed
+ * before processing, so a retry arriving while the first submission is still
+ * in flight gets 409 request_in_progress rather than a second processing.
  */
 router.post('/payload', async (req: Request, res: Response) => {
   try {
@@ -180,6 +245,7 @@ router.post('/payload', async (req: Request, res: Response) => {
     if (!clientId || !encrypted || !nonce || !signature) {
       return res.status(400).json({
         status: 'failed',
+        code: 'missing_fields',
         message: 'Missing required fields',
       });
     }
@@ -190,30 +256,356 @@ router.post('/payload', async (req: Request, res: Response) => {
       logger.warn(`No active session for client: ${clientId}`);
       return res.status(401).json({
         status: 'failed',
+        code: 'no_session',
         message: 'No active session',
       });
     }
 
-    // Verify signature on encrypted payload
-    const encryptedBytes = hexToBytes(encrypted);
-    
-    // Note: In production, you'd decrypt and process the payload here
-    // For now, we just acknowledge receipt
+    const idempotencyKey = req.header('Idempotency-Key');
+    if (idempotencyKey) {
+      const previous = await claimIdempotencyKey(clientId, idempotencyKey);
+      if (previous === 'pending') {
+        res.setHeader('Retry-After', '1');
+        return res.status(409).json({
+          status: 'failed',
+          code: 'request_in_progress',
+          message: 'A request with this idempotency key is in progress',
+        });
+      }
+      if (previous) {
+        logger.info(`Replaying payload response for client: ${clientId}`);
+        return res.json(previous);
+      }
+    }
 
-    logger.info(`Received payload from client: ${clientId}`);
+    try {
+      // Verify signature on encrypted payload
+      const encryptedBytes = hexToBytes(encrypted);
+      if (!(await withinQuota(clientId, encryptedBytes.length))) {
+        if (idempotencyKey) {
+          await releaseIdempotencyKey(clientId, idempotencyKey);
+        }
+        return quotaExceeded(res);
+      }
 
-    res.json({
-      status: 'success',
-      message: 'Payload received and verified',
-      data: {
+      // Note: In production, you'd decrypt and process the payload here
+      // For now, we just acknowledge receipt
+
+      logger.info(`Received payload from client: ${clientId}`);
+
+      const data = {
         processed: true,
         timestamp: Date.now(),
-      },
-    });
+      };
+      const response = (await sessionEncryptsResponses(clientId))
+        ? {
+            status: 'success',
+            message: 'Payload received and verified',
+            ...(await sealResponse(
+              data,
+              hexToBytes(sessionKey),
+              hexToBytes(nonce),
+              getGatewayKeys().privateKey
+            )),
+          }
+        : { status: 'success', message: 'Payload received and verified', data };
+      if (idempotencyKey) {
+        await storeIdempotentResponse(clientId, idempotencyKey, response);
+      }
+      await incrementUsage(clientId, usageDay(), { bytes: encryptedBytes.length, payloads: 1 });
+      res.json(response);
+    } catch (error) {
+      // Unclaim so the client's retry is processed rather than left pending
+      if (idempotencyKey) {
+        await releaseIdempotencyKey(clientId, idempotencyKey).catch(() => {});
+      }
+      throw error;
+    }
   } catch (error) {
     logger.error('Error processing payload:', error);
     res.status(500).json({
       status: 'failed',
+      code: 'internal_error',
+      message: 'Internal server error',
+    });
+  }
+});
+
+// Bytes AES-GCM adds to each encrypted chunk
+const CHUNK_OVERHEAD = 16;
+
+const u64be = (value: number) => {
+  const bytes = new Uint8Array(8);
+  new DataView(bytes.buffer).setBigUint64(0, BigInt(value));
+  return bytes;
+};
+
+/**
+ * Look up the session key and public key of an upload's owner
+ */
+async function uploadClient(uploadId: string, clientId: string) {
+  const owner = await getUploadOwner(uploadId);
+  const sessionKey = await getSession(clientId);
+  const clientPubKey = await getClientKey(clientId);
+  if (owner !== clientId || !sessionKey || !clientPubKey) {
+    return null;
+  }
+  return { sessionKey, clientPubKey };
+}
+
+/**
+ * POST /upload
+ * Open a chunked upload for payloads too large for /payload
+ */
+router.post('/upload', async (req: Request, res: Response) => {
+  try {
+    const { clientId } = req.body;
+    if (!clientId || !(await getSession(clientId))) {
+      return res.status(401).json({
+        status: 'failed',
+        code: 'no_session',
+        message: 'No active session',
+      });
+    }
+
+    const uploadId = generateNonce();
+    await createUpload(uploadId, clientId);
+    res.json({ status: 'success', uploadId });
+  } catch (error) {
+    logger.error('Error starting upload:', error);
+    res.status(500).json({
+      status: 'failed',
+      code: 'internal_error',
+      message: 'Internal server error',
+    });
+  }
+});
+
+/**
+ * POST /upload/:uploadId/chunk
+ * Receive one encrypted chunk, signed with its upload ID and index
+ */
+router.post('/upload/:uploadId/chunk', async (req: Request, res: Response) => {
+  try {
+    const { uploadId } = req.params;
+    const { clientId, index, encrypted, nonce, signature } = req.body;
+
+    if (!clientId || !Number.isInteger(index) || index < 0 || !encrypted || !nonce || !signature) {
+      return res.status(400).json({
+        status: 'failed',
+        code: 'missing_fields',
+        message: 'Missing required fields',
+      });
+    }
+
+    const client = await uploadClient(uploadId, clientId);
+    if (!client) {
+      return res.status(401).json({
+        status: 'failed',
+        code: 'unknown_upload',
+        message: 'Unknown upload or no active session',
+      });
+    }
+
+    const encryptedBytes = hexToBytes(encrypted);
+    const message = concatBytes(
+      new TextEncoder().encode(uploadId),
+      u64be(index),
+      encryptedBytes
+    );
+    if (!(await verifySignature(message, signature, client.clientPubKey))) {
+      return res.status(401).json({
+        status: 'failed',
+        code: 'invalid_signature',
+        message: 'Invalid chunk signature',
+      });
+    }
+
+    if (!(await withinQuota(clientId, encryptedBytes.length))) {
+      return quotaExceeded(res);
+    }
+
+    // Note: In production, you'd decrypt and store the chunk here
+    // A retried index replaces the chunk but is only counted once
+    const added = await storeUploadChunk(uploadId, index, hashData(encryptedBytes), encryptedBytes.length);
+    if (added) {
+      await incrementUsage(clientId, usageDay(), { bytes: encryptedBytes.length });
+    }
+    res.json({ status: 'success' });
+  } catch (error) {
+    logger.error('Error receiving chunk:', error);
+    res.status(500).json({
+      status: 'failed',
+      code: 'internal_error',
+      message: 'Internal server error',
+    });
+  }
+});
+
+/**
+ * POST /upload/:uploadId/complete
+ * Finalize an upload once its signed manifest matches the received chunks
+ */
+router.post('/upload/:uploadId/complete', async (req: Request, res: Response) => {
+  try {
+    const { uploadId } = req.params;
+    const { clientId, chunks, size, manifestHash, signature } = req.body;
+
+    if (!clientId || !Number.isInteger(chunks) || !Number.isInteger(size) || !manifestHash || !signature) {
+      return res.status(400).json({
+        status: 'failed',
+        code: 'missing_fields',
+        message: 'Missing required fields',
+      });
+    }
+
+    const client = await uploadClient(uploadId, clientId);
+    if (!client) {
+      return res.status(401).json({
+        status: 'failed',
+        code: 'unknown_upload',
+        message: 'Unknown upload or no active session',
+      });
+    }
+
+    // Retried completions get the stored receipt
+    const completed = await getUploadReceipt(uploadId);
+    if (completed) {
+      return res.json(completed);
+    }
+
+    // Recompute the manifest over the chunks actually received
+    const received = await getUploadChunks(uploadId);
+    const hashes: Uint8Array[] = [];
+    let cipherBytes = 0;
+    for (let index = 0; index < chunks; index++) {
+      const chunk = received[index.toString()];
+      if (!chunk) {
+        return res.status(400).json({
+          status: 'failed',
+          code: 'manifest_mismatch',
+          message: `Missing chunk ${index}`,
+        });
+      }
+      hashes.push(hexToBytes(chunk.hash));
+      cipherBytes += chunk.bytes;
+    }
+    const expected = hashData(
+      concatBytes(new TextEncoder().encode(uploadId), u64be(chunks), u64be(size), ...hashes)
+    );
+
+    if (
+      Object.keys(received).length !== chunks ||
+      cipherBytes !== size + chunks * CHUNK_OVERHEAD ||
+      expected !== manifestHash
+    ) {
+      return res.status(400).json({
+        status: 'failed',
+        code: 'manifest_mismatch',
+        message: 'Manifest does not match received chunks',
+      });
+    }
+    if (!(await verifySignature(hexToBytes(manifestHash), signature, client.clientPubKey))) {
+      return res.status(401).json({
+        status: 'failed',
+        code: 'invalid_signature',
+        message: 'Invalid manifest signature',
+      });
+    }
+
+    const receipt = {
+      status: 'success',
+      uploadId,
+      chunks,
+      size,
+      manifestHash,
+    };
+    await storeUploadReceipt(uploadId, receipt);
+    logger.info(`Upload completed: ${uploadId} (${chunks} chunks, ${size} bytes)`);
+    res.json(receipt);
+  } catch (error) {
+    logger.error('Error completing upload:', error);
+    res.status(500).json({
+      status: 'failed',
+      code: 'internal_error',
+      message: 'Internal server error',
+    });
+  }
+});
+
+/**
+ * POST /rotate-key
+ * Replace the key a client signs with for the rest of its session
+ *
+ * The rotation message is signed by both the current key, proving the
+ * request comes from the client, and the new key, proving the client holds
+ * it. The session key is unchanged.
+ */
+router.post('/rotate-key', async (req: Request, res: Response) => {
+  try {
+    const { clientId, newPubKey, timestamp, signature, newSignature } = req.body;
+
+    if (!clientId || !newPubKey || !timestamp || !signature || !newSignature) {
+      return res.status(400).json({
+        status: 'failed',
+        code: 'missing_fields',
+        message: 'Missing required fields',
+      });
+    }
+
+    const clientPubKey = await getClientKey(clientId);
+    if (!clientPubKey || !(await getSession(clientId))) {
+      return res.status(401).json({
+        status: 'failed',
+        code: 'no_session',
+        message: 'No active session',
+      });
+    }
+
+    if (Math.abs(Date.now() - timestamp) > 60000) {
+      return res.status(401).json({
+        status: 'failed',
+        code: 'timestamp_out_of_range',
+        message: 'Timestamp out of valid range',
+      });
+    }
+
+    const message = concatBytes(
+      new TextEncoder().encode('H3DAC-rotate'),
+      new TextEncoder().encode(clientId),
+      hexToBytes(newPubKey),
+      u64be(timestamp)
+    );
+    // A retried rotation that already went through only needs the new key
+    const alreadyRotated = clientPubKey === newPubKey;
+    const valid =
+      (alreadyRotated || (await verifySignature(message, signature, clientPubKey))) &&
+      (await verifySignature(message, newSignature, newPubKey));
+    if (!valid) {
+      logger.warn(`Invalid key rotation signature for client: ${clientId}`);
+      return res.status(401).json({
+        status: 'failed',
+        code: 'invalid_signature',
+        message: 'Invalid signature',
+      });
+    }
+
+    // Fails if another rotation replaced the key since it was read
+    if (!alreadyRotated && !(await rotateClientKey(clientId, clientPubKey, newPubKey))) {
+      return res.status(409).json({
+        status: 'failed',
+        code: 'key_conflict',
+        message: 'Client key changed during rotation',
+      });
+    }
+
+    logger.info(`Rotated key for client: ${clientId}`);
+    res.json({ status: 'success', message: 'Key rotated' });
+  } catch (error) {
+    logger.error('Error rotating key:', error);
+    res.status(500).json({
+      status: 'failed',
+      code: 'internal_error',
       message: 'Internal server error',
     });
   }
@@ -239,6 +631,87 @@ router.post('/verify-session', async (req: Request, res: Response) => {
   }
 });
 
+/**
+ * GET /events
+ * Server-Sent Events stream of proof confirmations and session notices
+ *
+ * Authenticated with the X-Client-Id header and the session key as a
+ * bearer token.
+ */
+router.get('/events', async (req: Request, res: Response) => {
+  try {
+    const clientId = req.header('X-Client-Id');
+    const sessionKey = req.header('Authorization')?.replace(/^Bearer /, '');
+
+    if (!clientId || !sessionKey || !(await verifySession(clientId, sessionKey))) {
+      return res.status(401).json({
+        status: 'failed',
+        code: 'invalid_session',
+        message: 'Invalid session',
+      });
+    }
+
+    // Catch up on a proof recorded before the stream was opened
+    const proof = await getProof(clientId);
+    const initial = proof
+      ? [{ type: 'proof_confirmed' as const, blockTime: proof.blockTime, txHash: proof.txHash }]
+      : [];
+
+    const ttl = await getSessionTtl(clientId);
+    subscribeEvents(clientId, res, Date.now() + Math.max(ttl, 0) * 1000, initial);
+  } catch (error) {
+    logger.error('Error opening event stream:', error);
+    if (!res.headersSent) {
+      res.status(500).json({
+        status: 'failed',
+        code: 'internal_error',
+        message: 'Internal server error',
+      });
+    }
+  }
+});
+
+/**
+ * GET /usage
+ * Today's usage and remaining quota of the authenticated client
+ *
+ * Authenticated like /events. Counters reset at midnight UTC.
+ */
+router.get('/usage', async (req: Request, res: Response) => {
+  try {
+    const clientId = req.header('X-Client-Id');
+    const sessionKey = req.header('Authorization')?.replace(/^Bearer /, '');
+
+    if (!clientId || !sessionKey || !(await verifySession(clientId, sessionKey))) {
+      return res.status(401).json({
+        status: 'failed',
+        code: 'invalid_session',
+        message: 'Invalid session',
+      });
+    }
+
+    const { quotaBytesPerDay } = getConfig();
+    const day = usageDay();
+    const usage = await getUsage(clientId, day);
+    res.json({
+      clientId,
+      bytesSubmitted: usage.bytes,
+      payloads: usage.payloads,
+      proofsAnchored: usage.proofs,
+      quotaBytes: quotaBytesPerDay > 0 ? quotaBytesPerDay : null,
+      remainingBytes: quotaBytesPerDay > 0 ? Math.max(quotaBytesPerDay - usage.bytes, 0) : null,
+      resetAt: (day + 1) * DAY_MS,
+    });
+  } catch (error) {
+    logger.error('Error getting usage:', error);
+    res.status(500).json({
+      status: 'failed',
+      code: 'internal_error',
+      message: 'Internal server error',
+    });
+  }
+});
+
 /**
  * GET /proof/:clientId
  * Get on-chain proof status
@@ -0,0 +1,61 @@
+//! CBOR frame codec throughput
+//!
+//! Compares `CBORCodec` (ciborium) with serde_cbor, the previous implementation.
+//! Run with `cargo bench --bench codec`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use opacus_sdk::{CBORCodec, ContentType, FrameType, OpacusFrame, Priority};
+
+fn frame(payload_len: usize) -> OpacusFrame {
+    OpacusFrame {
+        version: 1,
+        frame_type: FrameType::Msg,
+        from: "agent-7f3a9c1e4b2d".to_string(),
+        to: "agent-0b8e6d5c2a1f".to_string(),
+        seq: 4242,
+        ts: 1_700_000_000_000,
+        nonce: "1700000000000-9f8e7d6c5b4a".to_string(),
+        payload: (0..payload_len).map(|i| i as u8).collect::<Vec<_>>().into(),
+        hmac: Some("ab".repeat(32)),
+        sig: Some(vec![0x5a; 64]),
+        key_epoch: 2,
+        compressed: None,
+        id: Some(OpacusFrame::new_id(1_700_000_000_000)),
+        priority: Priority::High,
+        content_type: ContentType::Json,
+        extensions: Default::default(),
+    }
+}
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    for size in [64, 1024, 16 * 1024] {
+        let frame = frame(size);
+        group.throughput(Throughput::Bytes(CBORCodec::encode(&frame).unwrap().len() as u64));
+        group.bench_with_input(BenchmarkId::new("ciborium", size), &frame, |b, f| {
+            b.iter(|| CBORCodec::encode(black_box(f)).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("serde_cbor", size), &frame, |b, f| {
+            b.iter(|| serde_cbor::to_vec(black_box(f)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    for size in [64, 1024, 16 * 1024] {
+        let data = CBORCodec::encode(&frame(size)).unwrap();
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_with_input(BenchmarkId::new("ciborium", size), &data, |b, d| {
+            b.iter(|| CBORCodec::decode(black_box(d)).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("serde_cbor", size), &data, |b, d| {
+            b.iter(|| serde_cbor::from_slice::<OpacusFrame>(black_box(d)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encode, decode);
+criterion_main!(benches);
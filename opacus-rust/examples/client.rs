@@ -2,14 +2,14 @@
 //! 
 //! Run with: cargo run --example client
 
-use opacus_sdk::{OpacusClient, OpacusConfig, Network};
-use tracing_subscriber;
+use opacus_sdk::{KeyManager, OpacusClient, OpacusConfig, Network, RedactingFields};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    // Initialize logging
+    // Initialize logging, keeping keys and payloads out of the log
     tracing_subscriber::fmt()
         .with_max_level(tracing::Level::INFO)
+        .fmt_fields(RedactingFields)
         .init();
     
     // Create configuration
@@ -30,11 +30,11 @@ async fn main() -> anyhow::Result<()> {
     println!("  Address: {}", identity.address);
     println!("  Chain: {}", identity.chain_id);
     
-    // Export keys (for restoration later)
-    if let Some((ed_priv, x_priv)) = client.export_identity() {
-        println!("\n💾 Private Keys (save securely!):");
-        println!("  Ed25519: {}", ed_priv);
-        println!("  X25519: {}", x_priv);
+    // Save the keys for restoration later, encrypted instead of printed
+    if let Ok(password) = std::env::var("OPACUS_PASSWORD") {
+        let keystore = KeyManager::export_encrypted(identity, &password).map_err(anyhow::Error::msg)?;
+        std::fs::write("agent-keystore.json", keystore)?;
+        println!("\n💾 Keys saved to agent-keystore.json");
     }
     
     // Connect to relay
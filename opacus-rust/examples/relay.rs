@@ -3,7 +3,6 @@
 //! Run with: cargo run --example relay
 
 use opacus_sdk::OpacusRelayServer;
-use tracing_subscriber;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -0,0 +1,23 @@
+//! Untrusted bytes through every frame decoder: none may panic, and any frame
+//! that decodes must survive re-encoding
+
+#![no_main]
+
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use opacus_sdk::{CBORCodec, FrameBatch, LengthPrefixedCodec, RoutingHeader, WireFormat};
+
+fuzz_target!(|data: &[u8]| {
+    for format in WireFormat::supported() {
+        let codec = format.codec().unwrap();
+        let Ok(frame) = RoutingHeader::decode(codec, data) else { continue };
+        let _ = frame.decompressed_payload();
+        let _ = frame.batch_entries();
+        let encoded = RoutingHeader::encode(codec, &frame).expect("decoded frame re-encodes");
+        let again = RoutingHeader::decode(codec, &encoded).expect("re-encoded frame decodes");
+        assert_eq!(CBORCodec::encode_canonical(&again).unwrap(), CBORCodec::encode_canonical(&frame).unwrap());
+    }
+    let _ = CBORCodec::decode(data);
+    let _ = FrameBatch::decode(data);
+    let _ = LengthPrefixedCodec::default().decode(&mut BytesMut::from(data));
+});
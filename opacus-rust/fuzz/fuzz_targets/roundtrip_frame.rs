@@ -0,0 +1,17 @@
+//! Arbitrary frames through every wire format: encoding and decoding must
+//! preserve them exactly
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use opacus_sdk::{CBORCodec, OpacusFrame, RoutingHeader, WireFormat};
+
+fuzz_target!(|frame: OpacusFrame| {
+    let canonical = CBORCodec::encode_canonical(&frame).unwrap();
+    assert_eq!(CBORCodec::encoded_size(&frame).unwrap(), CBORCodec::encode(&frame).unwrap().len());
+    for format in WireFormat::supported() {
+        let codec = format.codec().unwrap();
+        let decoded = RoutingHeader::decode(codec, &RoutingHeader::encode(codec, &frame).unwrap()).unwrap();
+        assert_eq!(CBORCodec::encode_canonical(&decoded).unwrap(), canonical, "{:?}", format);
+    }
+});
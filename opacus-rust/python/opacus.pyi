@@ -0,0 +1,70 @@
+from typing import Any, Optional
+
+class OpacusError(Exception): ...
+
+class Identity:
+    @staticmethod
+    def generate(network: str = "testnet") -> Identity: ...
+    @staticmethod
+    def from_seed(seed: bytes, network: str = "testnet") -> Identity: ...
+    @staticmethod
+    def from_keys(ed_priv: bytes, x_priv: bytes, network: str = "testnet") -> Identity: ...
+    @staticmethod
+    def import_encrypted(keystore: str, password: str) -> Identity: ...
+    def export_encrypted(self, password: str) -> str: ...
+    @property
+    def id(self) -> str: ...
+    @property
+    def address(self) -> str: ...
+    @property
+    def chain_id(self) -> int: ...
+    @property
+    def ed_pub(self) -> bytes: ...
+    @property
+    def x_pub(self) -> bytes: ...
+    @property
+    def ed_priv(self) -> bytes: ...
+    @property
+    def x_priv(self) -> bytes: ...
+
+class Frame:
+    @property
+    def frame_type(self) -> str: ...
+    @property
+    def from_(self) -> str: ...
+    @property
+    def to(self) -> str: ...
+    @property
+    def id(self) -> Optional[str]: ...
+    @property
+    def seq(self) -> int: ...
+    @property
+    def ts(self) -> int: ...
+    @property
+    def priority(self) -> str: ...
+    @property
+    def content_type(self) -> str: ...
+    @property
+    def payload(self) -> bytes: ...
+    def text(self) -> str: ...
+    def json(self) -> Any: ...
+
+class Client:
+    def __init__(
+        self,
+        relay_url: str,
+        network: str = "testnet",
+        chain_rpc: Optional[str] = None,
+        private_key: Optional[str] = None,
+    ) -> None: ...
+    async def init(self, identity: Optional[Identity] = None) -> Identity: ...
+    @property
+    def identity(self) -> Optional[Identity]: ...
+    async def connect(self) -> None: ...
+    async def send_message(self, to: str, payload: bytes, priority: Optional[str] = None) -> None: ...
+    async def send_text(self, to: str, text: str) -> None: ...
+    async def send_json(self, to: str, value: Any) -> None: ...
+    async def recv(self) -> Optional[Frame]: ...
+    async def ping(self, agent_id: str) -> float: ...
+    def is_connected(self) -> bool: ...
+    async def disconnect(self) -> None: ...
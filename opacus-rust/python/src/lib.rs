@@ -0,0 +1,372 @@
+//! Python bindings for the Opacus SDK
+//!
+//! The `opacus` module wraps [`OpacusClient`] for asyncio (its coroutines run
+//! on a Tokio runtime through `pyo3-async-runtimes`), together with identity
+//! management and typed frames:
+//!
+//! ```python
+//! import asyncio, opacus
+//!
+//! async def main():
+//!     client = opacus.Client("quic://relay.opacus.io:4242", network="testnet")
+//!     identity = await client.init()
+//!     await client.connect()
+//!     await client.send_text("agent-id", "hello")
+//!     frame = await client.recv()
+//!     print(frame.frame_type, frame.from_, frame.text())
+//!
+//! asyncio.run(main())
+//! ```
+
+use std::sync::Arc;
+use std::time::Duration;
+use opacus_sdk::{
+    AgentIdentity, KeyManager, Network, OpacusClient, OpacusConfig, OpacusFrame, Priority,
+};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use tokio::sync::Mutex;
+
+create_exception!(opacus, OpacusError, PyException, "Error raised by the Opacus SDK");
+
+/// How long `recv` holds the client before letting other calls through
+const RECV_SLICE: Duration = Duration::from_millis(20);
+
+fn to_py(e: impl std::fmt::Display) -> PyErr {
+    OpacusError::new_err(e.to_string())
+}
+
+fn network(name: &str) -> PyResult<Network> {
+    match name.to_ascii_lowercase().as_str() {
+        "mainnet" => Ok(Network::Mainnet),
+        "testnet" => Ok(Network::Testnet),
+        "devnet" => Ok(Network::Devnet),
+        _ => Err(PyValueError::new_err(format!("Unknown network: {}", name))),
+    }
+}
+
+fn key(bytes: &[u8], name: &str) -> PyResult<[u8; 32]> {
+    bytes.try_into().map_err(|_| PyValueError::new_err(format!("{} must be 32 bytes", name)))
+}
+
+/// Agent identity: Ed25519 signing and X25519 encryption keys
+#[pyclass(module = "opacus", frozen)]
+#[derive(Clone)]
+struct Identity {
+    inner: AgentIdentity,
+}
+
+#[pymethods]
+impl Identity {
+    /// Generate a new identity
+    #[staticmethod]
+    #[pyo3(signature = (network = "testnet"))]
+    fn generate(network: &str) -> PyResult<Self> {
+        let chain_id = self::network(network)?.chain_id();
+        Ok(Self { inner: KeyManager::generate_identity(chain_id) })
+    }
+
+    /// Restore an identity from a 32-byte seed
+    #[staticmethod]
+    #[pyo3(signature = (seed, network = "testnet"))]
+    fn from_seed(seed: &[u8], network: &str) -> PyResult<Self> {
+        let chain_id = self::network(network)?.chain_id();
+        Ok(Self { inner: KeyManager::identity_from_seed(&key(seed, "Seed")?, chain_id) })
+    }
+
+    /// Restore an identity from its private keys
+    #[staticmethod]
+    #[pyo3(signature = (ed_priv, x_priv, network = "testnet"))]
+    fn from_keys(ed_priv: &[u8], x_priv: &[u8], network: &str) -> PyResult<Self> {
+        let chain_id = self::network(network)?.chain_id();
+        let inner = KeyManager::identity_from_keys(key(ed_priv, "ed_priv")?, key(x_priv, "x_priv")?, chain_id);
+        Ok(Self { inner })
+    }
+
+    /// Restore an identity from a password-encrypted JSON keystore
+    #[staticmethod]
+    fn import_encrypted(keystore: &str, password: &str) -> PyResult<Self> {
+        Ok(Self { inner: KeyManager::import_encrypted(keystore, password).map_err(to_py)? })
+    }
+
+    /// Export as a password-encrypted JSON keystore
+    fn export_encrypted(&self, password: &str) -> PyResult<String> {
+        KeyManager::export_encrypted(&self.inner, password).map_err(to_py)
+    }
+
+    /// Agent ID
+    #[getter]
+    fn id(&self) -> &str {
+        &self.inner.id
+    }
+
+    /// Ethereum-compatible address
+    #[getter]
+    fn address(&self) -> &str {
+        &self.inner.address
+    }
+
+    /// Chain ID
+    #[getter]
+    fn chain_id(&self) -> u64 {
+        self.inner.chain_id
+    }
+
+    /// Ed25519 public key
+    #[getter]
+    fn ed_pub<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.inner.ed_pub)
+    }
+
+    /// X25519 public key
+    #[getter]
+    fn x_pub<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.inner.x_pub)
+    }
+
+    /// Ed25519 private key
+    #[getter]
+    fn ed_priv<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.inner.ed_priv)
+    }
+
+    /// X25519 private key
+    #[getter]
+    fn x_priv<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.inner.x_priv)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Identity(id={:?}, address={:?})", self.inner.id, self.inner.address)
+    }
+}
+
+/// Frame received from the relay
+#[pyclass(module = "opacus", frozen)]
+struct Frame {
+    inner: OpacusFrame,
+    payload: Vec<u8>,
+}
+
+impl Frame {
+    fn new(inner: OpacusFrame) -> PyResult<Self> {
+        let payload = inner.decompressed_payload().map_err(to_py)?.into_owned();
+        Ok(Self { inner, payload })
+    }
+}
+
+#[pymethods]
+impl Frame {
+    /// Frame type wire name (`"msg"`, `"ack"`, ...; `"unknown"` for newer types)
+    #[getter]
+    fn frame_type(&self) -> &'static str {
+        self.inner.frame_type.name().unwrap_or("unknown")
+    }
+
+    /// Sender agent ID
+    #[getter]
+    fn from_(&self) -> &str {
+        &self.inner.from
+    }
+
+    /// Recipient agent ID
+    #[getter]
+    fn to(&self) -> &str {
+        &self.inner.to
+    }
+
+    /// Message ID, if the frame has one
+    #[getter]
+    fn id(&self) -> Option<String> {
+        self.inner.id.map(|id| id.to_string())
+    }
+
+    /// Sequence number
+    #[getter]
+    fn seq(&self) -> u64 {
+        self.inner.seq
+    }
+
+    /// Sender timestamp (milliseconds since the Unix epoch)
+    #[getter]
+    fn ts(&self) -> u64 {
+        self.inner.ts
+    }
+
+    /// Priority name
+    #[getter]
+    fn priority(&self) -> &'static str {
+        self.inner.priority.as_str()
+    }
+
+    /// Content type name (`"raw"`, `"json"`, `"text"`, ...)
+    #[getter]
+    fn content_type(&self) -> &'static str {
+        self.inner.content_type.as_str()
+    }
+
+    /// Decompressed payload
+    #[getter]
+    fn payload<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.payload)
+    }
+
+    /// Payload as UTF-8 text
+    fn text(&self) -> PyResult<String> {
+        String::from_utf8(self.payload.clone()).map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    /// Payload parsed as JSON
+    fn json<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        py.import("json")?.call_method1("loads", (PyBytes::new(py, &self.payload),))
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Frame(frame_type={:?}, from_={:?}, to={:?}, id={:?})",
+            self.frame_type(), self.inner.from, self.inner.to, self.id(),
+        )
+    }
+}
+
+/// Opacus client for asyncio
+///
+/// Methods returning awaitables run on a shared Tokio runtime. `recv` can
+/// wait in one task while others send.
+#[pyclass(module = "opacus", frozen)]
+struct Client {
+    inner: Arc<Mutex<OpacusClient>>,
+}
+
+#[pymethods]
+impl Client {
+    /// Create a client for a relay (`quic://host:port` or `local://<name>`)
+    #[new]
+    #[pyo3(signature = (relay_url, network = "testnet", chain_rpc = None, private_key = None))]
+    fn new(relay_url: String, network: &str, chain_rpc: Option<String>, private_key: Option<String>) -> PyResult<Self> {
+        let network = self::network(network)?;
+        let config = OpacusConfig {
+            chain_rpc: chain_rpc.unwrap_or_else(|| network.profile().rpc.clone()),
+            network,
+            relay_url,
+            private_key,
+        };
+        Ok(Self { inner: Arc::new(Mutex::new(OpacusClient::new(config))) })
+    }
+
+    /// Initialize with an identity, generating one if none is given
+    #[pyo3(signature = (identity = None))]
+    fn init<'py>(&self, py: Python<'py>, identity: Option<Identity>) -> PyResult<Bound<'py, PyAny>> {
+        let inner = self.inner.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            let mut client = inner.lock().await;
+            let identity = match identity {
+                Some(identity) => client.init_from_keys(identity.inner.ed_priv, identity.inner.x_priv).await.map_err(to_py)?,
+                None => client.init().await,
+            };
+            Ok(Identity { inner: identity.clone() })
+        })
+    }
+
+    /// Identity (`None` before `init`)
+    #[getter]
+    fn identity(&self, py: Python<'_>) -> Option<Identity> {
+        let client = py.allow_threads(|| self.inner.blocking_lock());
+        client.identity().map(|identity| Identity { inner: identity.clone() })
+    }
+
+    /// Connect to the relay
+    fn connect<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let inner = self.inner.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            inner.lock().await.connect().await.map_err(to_py)
+        })
+    }
+
+    /// Send a binary message
+    #[pyo3(signature = (to, payload, priority = None))]
+    fn send_message<'py>(
+        &self,
+        py: Python<'py>,
+        to: String,
+        payload: Vec<u8>,
+        priority: Option<&str>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let priority = priority.map(str::parse::<Priority>).transpose().map_err(PyValueError::new_err)?;
+        let inner = self.inner.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            let mut client = inner.lock().await;
+            match priority {
+                Some(priority) => client.send_message_with_priority(&to, payload, priority).await,
+                None => client.send_message(&to, payload).await,
+            }
+            .map_err(to_py)
+        })
+    }
+
+    /// Send a UTF-8 text message
+    fn send_text<'py>(&self, py: Python<'py>, to: String, text: String) -> PyResult<Bound<'py, PyAny>> {
+        let inner = self.inner.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            inner.lock().await.send_text(&to, &text).await.map_err(to_py)
+        })
+    }
+
+    /// Send a JSON-serializable value as a JSON message
+    fn send_json<'py>(&self, py: Python<'py>, to: String, value: &Bound<'py, PyAny>) -> PyResult<Bound<'py, PyAny>> {
+        let json: String = py.import("json")?.call_method1("dumps", (value,))?.extract()?;
+        let value: serde_json::Value = serde_json::from_str(&json).map_err(to_py)?;
+        let inner = self.inner.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            inner.lock().await.send_json(&to, &value).await.map_err(to_py)
+        })
+    }
+
+    /// Receive the next frame (`None` once disconnected)
+    fn recv<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let inner = self.inner.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            loop {
+                // Release the client between slices so sends are not held up
+                let mut client = inner.lock().await;
+                if let Ok(frame) = tokio::time::timeout(RECV_SLICE, client.recv()).await {
+                    return frame.map(Frame::new).transpose();
+                }
+            }
+        })
+    }
+
+    /// Measure the round trip to an agent (`"relay"` for the relay), in seconds
+    fn ping<'py>(&self, py: Python<'py>, agent_id: String) -> PyResult<Bound<'py, PyAny>> {
+        let inner = self.inner.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            let rtt = inner.lock().await.ping(&agent_id).await.map_err(to_py)?;
+            Ok(rtt.as_secs_f64())
+        })
+    }
+
+    /// Check connection status
+    fn is_connected(&self, py: Python<'_>) -> bool {
+        py.allow_threads(|| self.inner.blocking_lock().is_connected())
+    }
+
+    /// Disconnect from the relay
+    fn disconnect<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let inner = self.inner.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            inner.lock().await.disconnect().await;
+            Ok(())
+        })
+    }
+}
+
+#[pymodule]
+fn opacus(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Identity>()?;
+    m.add_class::<Frame>()?;
+    m.add_class::<Client>()?;
+    m.add("OpacusError", m.py().get_type::<OpacusError>())?;
+    Ok(())
+}
@@ -0,0 +1,49 @@
+import asyncio
+import unittest
+
+import opacus
+
+
+class IdentityTest(unittest.TestCase):
+    def test_seed_and_keystore(self):
+        identity = opacus.Identity.from_seed(bytes(32))
+        self.assertEqual(identity.id, opacus.Identity.from_seed(bytes(32)).id)
+        self.assertEqual(len(identity.ed_pub), 32)
+
+        keystore = identity.export_encrypted("secret")
+        self.assertEqual(opacus.Identity.import_encrypted(keystore, "secret").id, identity.id)
+        with self.assertRaises(opacus.OpacusError):
+            opacus.Identity.import_encrypted(keystore, "wrong")
+        with self.assertRaises(ValueError):
+            opacus.Identity.from_seed(b"short")
+
+
+class ClientTest(unittest.IsolatedAsyncioTestCase):
+    async def test_local_exchange(self):
+        alice = opacus.Client("local://python-test", network="devnet")
+        bob = opacus.Client("local://python-test", network="devnet")
+        await alice.init()
+        bob_id = (await bob.init(opacus.Identity.generate("devnet"))).id
+        self.assertEqual(bob.identity.id, bob_id)
+
+        for client in (alice, bob):
+            await client.connect()
+            self.assertEqual((await client.recv()).frame_type, "ack")
+
+        # bob waits while alice sends
+        pending = asyncio.ensure_future(bob.recv())
+        await alice.send_json(bob_id, {"task": "summarize"})
+        frame = await asyncio.wait_for(pending, 5)
+        self.assertEqual((frame.frame_type, frame.from_, frame.content_type), ("msg", alice.identity.id, "json"))
+        self.assertEqual(frame.json(), {"task": "summarize"})
+
+        await alice.send_text(bob_id, "hello")
+        self.assertEqual((await bob.recv()).text(), "hello")
+
+        await alice.disconnect()
+        self.assertFalse(alice.is_connected())
+        self.assertIsNone(await alice.recv())
+
+
+if __name__ == "__main__":
+    unittest.main()
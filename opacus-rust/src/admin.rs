@@ -0,0 +1,201 @@
+//! Relay admin listener
+//!
+//! With [`OpacusRelayServer::with_admin`](crate::OpacusRelayServer::with_admin)
+//! the relay serves plain HTTP on a separate address:
+//!
+//! * `GET /healthz`, `GET /readyz` - health checks ([`HealthReport`])
+//! * `GET /events` - live [`RelayEvent`]s as Server-Sent Events, one
+//!   `event: <type>` / `data: <json>` message per event
+//!
+//! The listener has no authentication; bind it to a private interface.
+
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, warn};
+use crate::events::{RelayEvent, RelayEvents};
+use crate::health::HealthReport;
+
+/// Longest request head the admin listener reads
+const MAX_REQUEST: usize = 4096;
+
+/// How long a client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Interval of comment lines on idle event streams, so proxies keep them
+/// open and closed clients are noticed
+const EVENT_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// What the admin listener serves
+#[derive(Clone)]
+pub(crate) struct AdminState {
+    pub(crate) report: Arc<dyn Fn() -> HealthReport + Send + Sync>,
+    pub(crate) events: RelayEvents,
+}
+
+/// Serve admin requests until the listener fails
+pub(crate) async fn serve(listener: TcpListener, state: AdminState) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle(stream, &state).await {
+                        debug!("Admin request from {} failed: {}", peer, e);
+                    }
+                });
+            }
+            Err(e) => {
+                warn!("Admin listener failed: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+async fn handle(mut stream: TcpStream, state: &AdminState) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let read = tokio::time::timeout(REQUEST_TIMEOUT, async {
+        let mut buf = [0u8; 1024];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+        Ok::<_, std::io::Error>(())
+    })
+    .await;
+    match read {
+        Ok(result) => result?,
+        Err(_) => return Ok(()),
+    }
+
+    let line = request.split(|&b| b == b'\n').next().unwrap_or_default();
+    let line = String::from_utf8_lossy(line);
+    let mut parts = line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    if method == "GET" && target.split('?').next() == Some("/events") {
+        return stream_events(stream, state.events.subscribe()).await;
+    }
+    let (status, body) = respond(method, target, &*state.report);
+    stream.write_all(&response(status, method, &body)).await?;
+    stream.shutdown().await
+}
+
+/// Write events to a client until it disconnects
+async fn stream_events(mut stream: TcpStream, mut events: broadcast::Receiver<RelayEvent>) -> std::io::Result<()> {
+    stream
+        .write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-store\r\nConnection: keep-alive\r\n\r\n: connected\n\n")
+        .await?;
+    let mut keep_alive = tokio::time::interval_at(tokio::time::Instant::now() + EVENT_KEEP_ALIVE, EVENT_KEEP_ALIVE);
+    loop {
+        let message = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => format!("event: {}\ndata: {}\n\n", event.kind.name(), serde_json::to_string(&event).unwrap_or_default()),
+                Err(RecvError::Lagged(skipped)) => format!("event: lagged\ndata: {{\"skipped\":{}}}\n\n", skipped),
+                Err(RecvError::Closed) => return stream.shutdown().await,
+            },
+            _ = keep_alive.tick() => ": keep-alive\n\n".to_string(),
+        };
+        stream.write_all(message.as_bytes()).await?;
+    }
+}
+
+/// Status and JSON body for a health check request
+fn respond(method: &str, target: &str, report: &dyn Fn() -> HealthReport) -> (u16, String) {
+    let path = target.split('?').next().unwrap_or(target);
+    let check: fn(&HealthReport) -> bool = match path {
+        "/healthz" => |report| report.live,
+        "/readyz" => |report| report.ready,
+        "/events" => return (405, r#"{"error":"method not allowed"}"#.to_string()),
+        _ => return (404, r#"{"error":"not found"}"#.to_string()),
+    };
+    if method != "GET" && method != "HEAD" {
+        return (405, r#"{"error":"method not allowed"}"#.to_string());
+    }
+    let report = report();
+    let status = if check(&report) { 200 } else { 503 };
+    (status, serde_json::to_string(&report).unwrap_or_default())
+}
+
+fn response(status: u16, method: &str, body: &str) -> Vec<u8> {
+    let reason = match status {
+        200 => "OK",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Service Unavailable",
+    };
+    let mut response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n",
+        status,
+        reason,
+        body.len(),
+    );
+    if status == 405 {
+        response.push_str("Allow: GET, HEAD\r\n");
+    }
+    response.push_str("\r\n");
+    if method != "HEAD" {
+        response.push_str(body);
+    }
+    response.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use crate::events::RelayEventKind;
+    use crate::health::{AcceptLoopState, RelayHealth};
+
+    #[tokio::test]
+    async fn test_admin_listener() {
+        let health = Arc::new(RelayHealth::default());
+        let events = RelayEvents::default();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shared = health.clone();
+        tokio::spawn(serve(listener, AdminState { report: Arc::new(move || shared.report(2, 0)), events: events.clone() }));
+
+        let get = |request: &'static str| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            let (head, body) = response.split_once("\r\n\r\n").unwrap();
+            (head.lines().next().unwrap().to_string(), body.to_string())
+        };
+
+        let (status, body) = get("GET /readyz HTTP/1.1\r\nHost: relay\r\n\r\n").await;
+        assert_eq!(status, "HTTP/1.1 503 Service Unavailable");
+        assert_eq!(serde_json::from_str::<HealthReport>(&body).unwrap().accept_loop, AcceptLoopState::Starting);
+
+        health.start();
+        let (status, body) = get("GET /readyz?verbose HTTP/1.1\r\n\r\n").await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let report: HealthReport = serde_json::from_str(&body).unwrap();
+        assert_eq!((report.ready, report.agents), (true, 2));
+
+        assert_eq!(get("GET /healthz HTTP/1.0\r\n\r\n").await.0, "HTTP/1.1 200 OK");
+        assert_eq!(get("HEAD /healthz HTTP/1.1\r\n\r\n").await, ("HTTP/1.1 200 OK".to_string(), String::new()));
+        assert_eq!(get("POST /healthz HTTP/1.1\r\n\r\n").await.0, "HTTP/1.1 405 Method Not Allowed");
+        assert_eq!(get("POST /events HTTP/1.1\r\n\r\n").await.0, "HTTP/1.1 405 Method Not Allowed");
+        assert_eq!(get("GET / HTTP/1.1\r\n\r\n").await.0, "HTTP/1.1 404 Not Found");
+
+        // Event stream
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET /events HTTP/1.1\r\n\r\n").await.unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        while lines.next_line().await.unwrap().unwrap() != ": connected" {}
+        events.emit(|| RelayEventKind::AgentConnected { agent: "alice".into() });
+        lines.next_line().await.unwrap();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "event: agentConnected");
+        let data = lines.next_line().await.unwrap().unwrap();
+        let event: RelayEvent = serde_json::from_str(data.strip_prefix("data: ").unwrap()).unwrap();
+        assert_eq!(event.kind, RelayEventKind::AgentConnected { agent: "alice".into() });
+    }
+}
@@ -0,0 +1,158 @@
+//! Batch frames
+//!
+//! A `Batch` frame carries several small frames in one datagram. The payload
+//! is the entries' CBOR encodings, each prefixed with its 4-byte big-endian
+//! length. Senders sign the batch and every entry. The relay unpacks
+//! batches addressed to `relay` and forwards each entry unchanged, so
+//! recipients check the sender's own signature.
+
+use std::collections::BTreeMap;
+use bytes::{Bytes, BytesMut};
+use crate::content::ContentType;
+use crate::proto::{CBORCodec, CodecError, LengthPrefixedCodec, FRAME_VERSION};
+use crate::qos::Priority;
+use crate::types::{FrameType, OpacusFrame};
+
+/// Maximum number of frames in one batch
+pub const MAX_BATCH_FRAMES: usize = 256;
+
+/// Frames carried by a `Batch` frame
+#[derive(Debug, Clone, Default)]
+pub struct FrameBatch {
+    /// Entries, in sending order
+    pub frames: Vec<OpacusFrame>,
+}
+
+impl FrameBatch {
+    /// Create batch from frames
+    pub fn new(frames: Vec<OpacusFrame>) -> Self {
+        Self { frames }
+    }
+
+    /// Encode entries into a batch payload
+    pub fn encode(&self) -> Result<Bytes, CodecError> {
+        if self.frames.len() > MAX_BATCH_FRAMES {
+            return Err(CodecError::LimitExceeded(format!("{} frames in batch", self.frames.len())));
+        }
+        let codec = LengthPrefixedCodec::new(&CBORCodec);
+        let mut buf = BytesMut::new();
+        for frame in &self.frames {
+            if frame.frame_type == FrameType::Batch {
+                return Err(CodecError::Encode("Batches cannot be nested".into()));
+            }
+            codec.encode(frame, &mut buf)?;
+        }
+        Ok(buf.freeze())
+    }
+
+    /// Decode a batch payload
+    pub fn decode(payload: &[u8]) -> Result<Self, CodecError> {
+        let codec = LengthPrefixedCodec::new(&CBORCodec);
+        let mut buf = BytesMut::from(payload);
+        let mut frames = Vec::new();
+        while let Some(frame) = codec.decode(&mut buf)? {
+            if frames.len() == MAX_BATCH_FRAMES {
+                return Err(CodecError::LimitExceeded(format!("More than {} frames in batch", MAX_BATCH_FRAMES)));
+            }
+            if frame.frame_type == FrameType::Batch {
+                return Err(CodecError::Decode("Nested batch".into()));
+            }
+            frames.push(frame);
+        }
+        if !buf.is_empty() {
+            return Err(CodecError::Decode("Truncated batch entry".into()));
+        }
+        Ok(Self { frames })
+    }
+
+    /// Group entries by recipient, keeping their order
+    pub fn split_by_recipient(self) -> BTreeMap<String, Vec<OpacusFrame>> {
+        let mut groups: BTreeMap<String, Vec<OpacusFrame>> = BTreeMap::new();
+        for frame in self.frames {
+            groups.entry(frame.to.clone()).or_default().push(frame);
+        }
+        groups
+    }
+
+    /// Build an unsigned `Batch` frame
+    ///
+    /// The batch takes the highest priority of its entries.
+    pub fn to_frame(&self, from: &str, to: &str, ts: u64) -> Result<OpacusFrame, CodecError> {
+        Ok(OpacusFrame {
+            version: FRAME_VERSION,
+            frame_type: FrameType::Batch,
+            from: from.to_string(),
+            to: to.to_string(),
+            seq: 0,
+            ts,
+            nonce: "".to_string(),
+            payload: self.encode()?,
+            hmac: None,
+            sig: None,
+            key_epoch: 0,
+            compressed: None,
+            id: Some(OpacusFrame::new_id(ts)),
+            priority: self.priority(),
+            content_type: ContentType::Raw,
+            extensions: Default::default(),
+        })
+    }
+
+    /// Highest priority among the entries
+    pub fn priority(&self) -> Priority {
+        self.frames.iter().map(|f| f.priority).max().unwrap_or_default()
+    }
+}
+
+impl OpacusFrame {
+    /// Unpack the entries of a `Batch` frame
+    pub fn batch_entries(&self) -> Result<Vec<OpacusFrame>, CodecError> {
+        if self.frame_type != FrameType::Batch {
+            return Err(CodecError::Decode(format!("Not a batch: {:?}", self.frame_type)));
+        }
+        FrameBatch::decode(&self.payload).map(|b| b.frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(to: &str, seq: u64) -> OpacusFrame {
+        OpacusFrame {
+            to: to.to_string(),
+            payload: vec![seq as u8; 8].into(),
+            hmac: Some("mac".to_string()),
+            ..OpacusFrame::test(seq)
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_and_split() {
+        let mut urgent = frame("carol", 3);
+        urgent.priority = Priority::High;
+        let batch = FrameBatch::new(vec![frame("bob", 1), frame("carol", 2), urgent, frame("bob", 4)]);
+
+        let packed = batch.to_frame("alice", "relay", 1000).unwrap();
+        assert_eq!(packed.priority, Priority::High);
+        let entries = packed.batch_entries().unwrap();
+        assert_eq!(entries.iter().map(|f| f.seq).collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+
+        let groups = FrameBatch::new(entries).split_by_recipient();
+        assert_eq!(groups["bob"].iter().map(|f| f.seq).collect::<Vec<_>>(), vec![1, 4]);
+        assert_eq!(groups["carol"].len(), 2);
+    }
+
+    #[test]
+    fn test_malformed() {
+        let payload = FrameBatch::new(vec![frame("bob", 1)]).encode().unwrap();
+        assert!(FrameBatch::decode(&payload[..payload.len() - 1]).is_err());
+
+        let nested = FrameBatch::new(vec![frame("bob", 1)]).to_frame("alice", "bob", 0).unwrap();
+        assert!(FrameBatch::new(vec![nested]).encode().is_err());
+
+        let too_many = FrameBatch::new((0..=MAX_BATCH_FRAMES as u64).map(|i| frame("bob", i)).collect());
+        assert!(matches!(too_many.encode(), Err(CodecError::LimitExceeded(_))));
+        assert!(frame("bob", 1).batch_entries().is_err());
+    }
+}
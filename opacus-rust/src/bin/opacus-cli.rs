@@ -0,0 +1,932 @@
+//! Opacus command-line tool (`cli` feature)
+//!
+//! Run with: cargo run --features cli --bin opacus-cli -- --help
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{bail, Context};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use tokio::time::Instant;
+use opacus_sdk::{
+    AgentIdentity, CaptureRecord, FrameCapture, FrameType, KeyManager, LifecycleLog, Network,
+    OpacusClient, OpacusConfig, OpacusFrame, OpacusRelayServer, RedactingFields, RelayConfig, ReplayOptions, RoutingHeader, WireFormat,
+};
+
+/// How long to wait for the relay to acknowledge a connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Parser)]
+#[command(name = "opacus-cli", version, about = "Opacus agent and relay tool")]
+struct Cli {
+    /// Log level (error, warn, info, debug, trace)
+    #[arg(long, global = true, env = "OPACUS_LOG", default_value = "warn")]
+    log: tracing::Level,
+    /// Show private keys and payloads in logs (local debugging only)
+    #[arg(long, global = true)]
+    log_secrets: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create or export agent identities
+    #[command(subcommand)]
+    Keygen(Keygen),
+    /// Run a relay server
+    Relay(RelayArgs),
+    /// Send one message to an agent
+    Send(SendArgs),
+    /// Print incoming frames, answering pings
+    Listen(ListenArgs),
+    /// Measure round-trip time to an agent, or to the relay with `ping relay`
+    Ping(PingArgs),
+    /// Send every message back to its sender
+    Echo(EchoArgs),
+    /// Send messages to an `echo` agent at a fixed rate and report throughput and latency
+    Load(LoadArgs),
+    /// Decode a frame from hex or a file
+    Inspect(InspectArgs),
+    /// Re-inject a frame capture into a relay
+    Replay(ReplayArgs),
+}
+
+#[derive(Subcommand)]
+enum Keygen {
+    /// Generate an identity, printing its keys or writing a keystore
+    New {
+        /// Derive the identity from a 32-byte hex seed
+        #[arg(long)]
+        seed: Option<String>,
+        /// Write a password-encrypted keystore instead of printing the keys
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// Keystore password
+        #[arg(long, env = "OPACUS_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+        #[arg(long, value_enum, default_value_t = NetworkArg::Testnet)]
+        network: NetworkArg,
+    },
+    /// Print the keys stored in a keystore
+    Export {
+        /// Keystore written by `keygen new --out`
+        #[arg(long)]
+        identity: PathBuf,
+        /// Keystore password
+        #[arg(long, env = "OPACUS_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+    },
+}
+
+/// Options for commands that connect to a relay as an agent
+#[derive(Args)]
+struct AgentArgs {
+    /// Relay URL
+    #[arg(long, env = "OPACUS_RELAY", default_value = "quic://127.0.0.1:4242")]
+    relay: String,
+    #[arg(long, value_enum, default_value_t = NetworkArg::Testnet)]
+    network: NetworkArg,
+    /// Keystore to act as; a new identity is generated without one
+    #[arg(long)]
+    identity: Option<PathBuf>,
+    /// Keystore password
+    #[arg(long, env = "OPACUS_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+    /// Frame encoding used with the relay
+    #[arg(long, value_enum, default_value_t = FormatArg::Cbor)]
+    format: FormatArg,
+    #[command(flatten)]
+    capture: CaptureArgs,
+    /// Append frame lifecycle events (enqueued, sent, acked, delivered, failed) to this file as JSON lines
+    #[arg(long)]
+    lifecycle_log: Option<PathBuf>,
+}
+
+/// Options for recording frames to a capture file
+#[derive(Args)]
+struct CaptureArgs {
+    /// Append sent and received frames to this file as JSON lines
+    #[arg(long)]
+    capture: Option<PathBuf>,
+    /// Include the encoded frames, so the capture replays with payloads
+    #[arg(long, requires = "capture")]
+    capture_payloads: bool,
+}
+
+#[derive(Args)]
+struct RelayArgs {
+    /// TOML relay configuration (default: `OPACUS_RELAY_*` variables)
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Port to listen on, overriding the configuration
+    #[arg(long)]
+    port: Option<u16>,
+    /// Serve /healthz, /readyz and /events on this address, overriding the configuration
+    #[arg(long)]
+    admin: Option<SocketAddr>,
+    #[command(flatten)]
+    capture: CaptureArgs,
+}
+
+#[derive(Args)]
+struct SendArgs {
+    #[command(flatten)]
+    agent: AgentArgs,
+    /// Recipient agent ID
+    to: String,
+    /// Message text; read from stdin if omitted
+    message: Option<String>,
+    /// Send the message as a JSON document
+    #[arg(long)]
+    json: bool,
+    /// How long to wait for a rejection before exiting
+    #[arg(long, default_value = "500ms", value_parser = parse_duration)]
+    wait: Duration,
+}
+
+#[derive(Args)]
+struct ListenArgs {
+    #[command(flatten)]
+    agent: AgentArgs,
+    /// Print each frame as a line of JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct PingArgs {
+    #[command(flatten)]
+    agent: AgentArgs,
+    /// Agent to ping
+    to: String,
+    /// Number of pings
+    #[arg(long, short = 'c', default_value_t = 4)]
+    count: u32,
+    /// Time between pings
+    #[arg(long, default_value = "1s", value_parser = parse_duration)]
+    interval: Duration,
+    /// How long to wait for each reply
+    #[arg(long, default_value = "5s", value_parser = parse_duration)]
+    timeout: Duration,
+}
+
+#[derive(Args)]
+struct EchoArgs {
+    #[command(flatten)]
+    agent: AgentArgs,
+}
+
+#[derive(Args)]
+struct LoadArgs {
+    #[command(flatten)]
+    agent: AgentArgs,
+    /// Agent running `echo`
+    to: String,
+    /// Messages per second, across all connections
+    #[arg(long, default_value_t = 100)]
+    rate: u32,
+    /// Payload size in bytes (at least 8)
+    #[arg(long, default_value_t = 256)]
+    size: usize,
+    /// Agent connections sending in parallel, each with its own identity
+    #[arg(long, default_value_t = 1)]
+    concurrency: u32,
+    /// How long to send
+    #[arg(long, default_value = "10s", value_parser = parse_duration)]
+    duration: Duration,
+    /// How long to wait for outstanding replies once sending stops
+    #[arg(long, default_value = "2s", value_parser = parse_duration)]
+    drain: Duration,
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct InspectArgs {
+    /// Frame as hex; read from stdin if neither this nor --file is given
+    hex: Option<String>,
+    /// Read the encoded frame from a file
+    #[arg(long, conflicts_with = "hex")]
+    file: Option<PathBuf>,
+    /// Encoding of the frame body
+    #[arg(long, value_enum, default_value_t = FormatArg::Cbor)]
+    format: FormatArg,
+}
+
+#[derive(Args)]
+struct ReplayArgs {
+    /// Capture written with --capture
+    file: PathBuf,
+    /// Relay to replay against
+    #[arg(long, env = "OPACUS_RELAY", default_value = "quic://127.0.0.1:4242")]
+    relay: String,
+    /// Playback speed relative to the capture (0 sends as fast as possible)
+    #[arg(long, default_value_t = 1.0)]
+    speed: f64,
+    /// Do not connect the captured recipients
+    #[arg(long)]
+    no_recipients: bool,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum NetworkArg {
+    Mainnet,
+    Testnet,
+    Devnet,
+}
+
+impl From<NetworkArg> for Network {
+    fn from(network: NetworkArg) -> Self {
+        match network {
+            NetworkArg::Mainnet => Network::Mainnet,
+            NetworkArg::Testnet => Network::Testnet,
+            NetworkArg::Devnet => Network::Devnet,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum FormatArg {
+    Cbor,
+    Msgpack,
+    Protobuf,
+}
+
+impl From<FormatArg> for WireFormat {
+    fn from(format: FormatArg) -> Self {
+        match format {
+            FormatArg::Cbor => WireFormat::Cbor,
+            FormatArg::Msgpack => WireFormat::MsgPack,
+            FormatArg::Protobuf => WireFormat::Protobuf,
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    tracing_subscriber::fmt()
+        .with_max_level(cli.log)
+        .with_writer(std::io::stderr)
+        .fmt_fields(RedactingFields)
+        .init();
+    opacus_sdk::set_log_secrets(cli.log_secrets);
+    // quinn and the opacus features can enable several rustls providers
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    match cli.command {
+        Command::Keygen(keygen) => run_keygen(keygen),
+        Command::Relay(args) => run_relay(args).await,
+        Command::Send(args) => run_send(args).await,
+        Command::Listen(args) => run_listen(args).await,
+        Command::Ping(args) => run_ping(args).await,
+        Command::Echo(args) => run_echo(args).await,
+        Command::Load(args) => run_load(args).await,
+        Command::Inspect(args) => run_inspect(args),
+        Command::Replay(args) => run_replay(args).await,
+    }
+}
+
+fn run_keygen(keygen: Keygen) -> anyhow::Result<()> {
+    match keygen {
+        Keygen::New { seed, out, password, network } => {
+            let chain_id = Network::from(network).chain_id();
+            let identity = match seed {
+                Some(seed) => {
+                    let seed: [u8; 32] = decode_hex(&seed)?
+                        .try_into()
+                        .map_err(|_| anyhow::anyhow!("Seed must be 32 bytes"))?;
+                    KeyManager::identity_from_seed(&seed, chain_id)
+                }
+                None => KeyManager::generate_identity(chain_id),
+            };
+            match out {
+                Some(path) => {
+                    let password = password.context("A keystore needs --password or OPACUS_PASSWORD")?;
+                    let json = KeyManager::export_encrypted(&identity, &password).map_err(anyhow::Error::msg)?;
+                    std::fs::write(&path, json).with_context(|| format!("Cannot write {}", path.display()))?;
+                    println!("{}", serde_json::to_string_pretty(&identity_json(&identity, false))?);
+                }
+                None => println!("{}", serde_json::to_string_pretty(&identity_json(&identity, true))?),
+            }
+        }
+        Keygen::Export { identity, password } => {
+            let identity = load_identity(&identity, password.as_deref())?;
+            println!("{}", serde_json::to_string_pretty(&identity_json(&identity, true))?);
+        }
+    }
+    Ok(())
+}
+
+async fn run_relay(args: RelayArgs) -> anyhow::Result<()> {
+    let mut config = match &args.config {
+        Some(path) => RelayConfig::from_file(path)?,
+        None => RelayConfig::from_env()?,
+    };
+    if let Some(port) = args.port {
+        config.port = port;
+    }
+    if let Some(addr) = args.admin {
+        config.admin_addr = Some(addr);
+    }
+
+    let mut relay = OpacusRelayServer::from_config(&config);
+    if let Some(capture) = args.capture.open()? {
+        relay = relay.with_capture(capture);
+    }
+    relay.start().await?;
+    println!("Relay listening on 0.0.0.0:{}", config.port);
+    if let Some(addr) = relay.get_admin_addr() {
+        println!("Admin endpoints on http://{}", addr);
+    }
+
+    let mut stats = (config.stats_interval_secs > 0).then(|| {
+        let period = Duration::from_secs(config.stats_interval_secs);
+        tokio::time::interval_at(tokio::time::Instant::now() + period, period)
+    });
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+            _ = tick(&mut stats) => {
+                println!("agents={} pending={}", relay.get_agent_count(), relay.get_pending_count());
+            }
+        }
+    }
+}
+
+async fn run_send(args: SendArgs) -> anyhow::Result<()> {
+    let message = match args.message {
+        Some(message) => message,
+        None => std::io::read_to_string(std::io::stdin())?,
+    };
+    let mut client = args.agent.connect().await?;
+    if args.json {
+        let value: serde_json::Value = serde_json::from_str(&message).context("Message is not JSON")?;
+        client.send_json(&args.to, &value).await?;
+    } else {
+        client.send_text(&args.to, &message).await?;
+    }
+
+    // The relay reports undeliverable messages with an `Error` frame
+    let rejection = tokio::time::timeout(args.wait, async {
+        loop {
+            match client.recv_checked().await {
+                Some(Err(error)) => return Some(error),
+                Some(Ok(_)) => continue,
+                None => return None,
+            }
+        }
+    })
+    .await;
+    client.disconnect().await;
+    if let Ok(Some(error)) = rejection {
+        bail!("Rejected: {}", error);
+    }
+    println!("Sent to {}", args.to);
+    Ok(())
+}
+
+async fn run_listen(args: ListenArgs) -> anyhow::Result<()> {
+    let mut client = args.agent.connect().await?;
+    let id = client.get_identity().map(|identity| identity.id.clone()).unwrap_or_default();
+    eprintln!("Listening as {}", id);
+
+    // Pings are answered by `recv`
+    while let Some(frame) = client.recv().await {
+        if args.json {
+            println!("{}", frame_json(&frame));
+        } else {
+            println!("{}", frame_line(&frame));
+        }
+    }
+    bail!("Connection to {} closed", args.agent.relay)
+}
+
+async fn run_ping(args: PingArgs) -> anyhow::Result<()> {
+    let mut client = args.agent.connect().await?;
+
+    for seq in 0..args.count {
+        if seq > 0 {
+            tokio::time::sleep(args.interval).await;
+        }
+        match client.ping_with_timeout(&args.to, args.timeout).await {
+            Ok(rtt) => println!("pong from {}: seq={} time={:.1} ms", args.to, seq, millis(rtt)),
+            Err(e) => println!("seq={}: {}", seq, e),
+        }
+    }
+    client.disconnect().await;
+
+    let rtts = client.latency().rtt(&args.to).cloned().unwrap_or_default();
+    println!("{} sent, {} received", args.count, rtts.count());
+    if let (Some(min), Some(avg), Some(max)) = (rtts.min(), rtts.mean(), rtts.max()) {
+        println!("rtt min/avg/max = {:.1}/{:.1}/{:.1} ms", millis(min), millis(avg), millis(max));
+    }
+    if rtts.count() == 0 {
+        bail!("No replies from {}", args.to);
+    }
+    Ok(())
+}
+
+async fn run_echo(args: EchoArgs) -> anyhow::Result<()> {
+    let mut client = args.agent.connect().await?;
+    let id = client.get_identity().map(|identity| identity.id.clone()).unwrap_or_default();
+    eprintln!("Echoing as {}", id);
+    let echoed = echo(&mut client).await;
+    bail!("Connection to {} closed after echoing {} messages", args.agent.relay, echoed)
+}
+
+/// Send every message back to its sender until the connection closes
+///
+/// # Returns
+/// Number of messages echoed
+async fn echo(client: &mut OpacusClient) -> u64 {
+    let mut echoed = 0;
+    while let Some(frame) = client.recv().await {
+        if frame.frame_type != FrameType::Msg {
+            continue;
+        }
+        let payload = match frame.decompressed_payload() {
+            Ok(payload) => payload.into_owned(),
+            Err(e) => {
+                tracing::warn!("Cannot echo message from {}: {}", frame.from, e);
+                continue;
+            }
+        };
+        match client.send_message_uncompressed(&frame.from, payload).await {
+            Ok(()) => echoed += 1,
+            Err(e) => tracing::warn!("Cannot echo message to {}: {}", frame.from, e),
+        }
+    }
+    echoed
+}
+
+async fn run_load(args: LoadArgs) -> anyhow::Result<()> {
+    if args.rate == 0 || args.concurrency == 0 {
+        bail!("Rate and concurrency must be positive");
+    }
+    if args.size < 8 {
+        bail!("Payload size must be at least 8 bytes");
+    }
+    if args.concurrency > 1 && args.agent.identity.is_some() {
+        bail!("Concurrent connections need their own identities; drop --identity");
+    }
+    let interval = Duration::from_secs_f64(args.concurrency as f64 / args.rate as f64);
+    let mut clients = Vec::new();
+    for _ in 0..args.concurrency {
+        clients.push(args.agent.connect().await?);
+    }
+    eprintln!("Sending {} msg/s of {} bytes to {} over {} connections for {:?}", args.rate, args.size, args.to, args.concurrency, args.duration);
+
+    let start = Instant::now();
+    let workers: Vec<_> = clients
+        .into_iter()
+        .map(|mut client| {
+            let (to, size, duration, drain) = (args.to.clone(), args.size, args.duration, args.drain);
+            tokio::spawn(async move {
+                let result = generate_load(&mut client, &to, interval, size, duration, drain).await;
+                client.disconnect().await;
+                result
+            })
+        })
+        .collect();
+    let mut total = LoadResult::default();
+    for worker in workers {
+        total.merge(worker.await??);
+    }
+    let received = total.received;
+    let report = total.report(start.elapsed(), args.size);
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_load_report(&report);
+    }
+    if received == 0 {
+        bail!("No replies from {}", args.to);
+    }
+    Ok(())
+}
+
+/// Messages sent and replies received by load-generating connections
+#[derive(Debug, Default)]
+struct LoadResult {
+    sent: u64,
+    received: u64,
+    /// Messages the relay answered with an `Error` frame
+    rejected: u64,
+    /// Messages still unanswered when the connection stopped waiting
+    lost: u64,
+    /// Round trip of each reply
+    latencies: Vec<Duration>,
+}
+
+impl LoadResult {
+    fn merge(&mut self, other: LoadResult) {
+        self.sent += other.sent;
+        self.received += other.received;
+        self.rejected += other.rejected;
+        self.lost += other.lost;
+        self.latencies.extend(other.latencies);
+    }
+
+    /// Counts, throughput and latency percentiles as JSON
+    fn report(mut self, elapsed: Duration, size: usize) -> serde_json::Value {
+        self.latencies.sort();
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        let quantile = |q: f64| percentile(&self.latencies, q).map(millis);
+        serde_json::json!({
+            "sent": self.sent,
+            "received": self.received,
+            "rejected": self.rejected,
+            "lost": self.lost,
+            "elapsedMs": millis(elapsed),
+            "messagesPerSec": self.received as f64 / secs,
+            "bytesPerSec": (self.received * size as u64) as f64 / secs,
+            "latencyMs": {
+                "p50": quantile(0.5),
+                "p90": quantile(0.9),
+                "p99": quantile(0.99),
+                "p999": quantile(0.999),
+                "max": self.latencies.last().copied().map(millis),
+            },
+        })
+    }
+}
+
+/// Send numbered messages to an echo agent every `interval` for `duration`,
+/// matching the echoed payloads to measure round trips
+async fn generate_load(
+    client: &mut OpacusClient,
+    to: &str,
+    interval: Duration,
+    size: usize,
+    duration: Duration,
+    drain: Duration,
+) -> anyhow::Result<LoadResult> {
+    let start = Instant::now();
+    let (stop, give_up) = (start + duration, start + duration + drain);
+    let mut result = LoadResult::default();
+    let mut in_flight: HashMap<u64, Instant> = HashMap::new();
+    let mut next_send = start;
+    let mut payload = vec![0u8; size];
+    loop {
+        let now = Instant::now();
+        let sending = now < stop;
+        if sending && now >= next_send {
+            payload[..8].copy_from_slice(&result.sent.to_be_bytes());
+            client.send_message_uncompressed(to, payload.clone()).await?;
+            in_flight.insert(result.sent, now);
+            result.sent += 1;
+            next_send += interval;
+            continue;
+        }
+        if !sending && (in_flight.is_empty() || now >= give_up) {
+            break;
+        }
+        let wake = if sending { next_send.min(stop) } else { give_up };
+        let frame = match tokio::time::timeout_at(wake, client.recv()).await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => bail!("Connection closed"),
+            Err(_) => continue,
+        };
+        if frame.error_payload().is_some() {
+            result.rejected += 1;
+            continue;
+        }
+        if frame.frame_type != FrameType::Msg || frame.from != to {
+            continue;
+        }
+        let seq = frame.payload.get(..8).and_then(|seq| seq.try_into().ok()).map(u64::from_be_bytes);
+        if let Some(sent) = seq.and_then(|seq| in_flight.remove(&seq)) {
+            result.latencies.push(sent.elapsed());
+            result.received += 1;
+        }
+    }
+    result.lost = in_flight.len() as u64;
+    Ok(result)
+}
+
+/// Nearest-rank percentile of sorted samples
+fn percentile(sorted: &[Duration], q: f64) -> Option<Duration> {
+    let rank = (q * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.clamp(1, sorted.len().max(1)) - 1).copied()
+}
+
+fn print_load_report(report: &serde_json::Value) {
+    println!(
+        "{} sent, {} received, {} rejected, {} lost in {:.1} s",
+        report["sent"],
+        report["received"],
+        report["rejected"],
+        report["lost"],
+        report["elapsedMs"].as_f64().unwrap_or_default() / 1000.0,
+    );
+    println!(
+        "throughput {:.1} msg/s, {:.1} KiB/s",
+        report["messagesPerSec"].as_f64().unwrap_or_default(),
+        report["bytesPerSec"].as_f64().unwrap_or_default() / 1024.0,
+    );
+    let latency = &report["latencyMs"];
+    if let Some(max) = latency["max"].as_f64() {
+        let ms = |key: &str| latency[key].as_f64().unwrap_or_default();
+        println!(
+            "latency p50/p90/p99/p99.9/max = {:.2}/{:.2}/{:.2}/{:.2}/{:.2} ms",
+            ms("p50"),
+            ms("p90"),
+            ms("p99"),
+            ms("p999"),
+            max,
+        );
+    }
+}
+
+fn run_inspect(args: InspectArgs) -> anyhow::Result<()> {
+    let data = match (&args.hex, &args.file) {
+        (Some(hex), _) => decode_hex(hex)?,
+        (None, Some(path)) => std::fs::read(path).with_context(|| format!("Cannot read {}", path.display()))?,
+        (None, None) => {
+            let mut input = String::new();
+            std::io::stdin().read_to_string(&mut input)?;
+            decode_hex(&input)?
+        }
+    };
+    let format = WireFormat::from(args.format);
+    let codec = format
+        .codec()
+        .with_context(|| format!("Wire format {:?} is not compiled in", format))?;
+    let frame = RoutingHeader::decode(codec, &data)?;
+    println!("{}", serde_json::to_string_pretty(&frame_json(&frame))?);
+    Ok(())
+}
+
+async fn run_replay(args: ReplayArgs) -> anyhow::Result<()> {
+    if args.speed.is_nan() || args.speed < 0.0 {
+        bail!("Speed must not be negative");
+    }
+    let records = CaptureRecord::load(&args.file)?;
+    let options = ReplayOptions {
+        speed: args.speed,
+        connect_recipients: !args.no_recipients,
+    };
+    let report = opacus_sdk::replay(&args.relay, &records, options).await?;
+    println!(
+        "{} records: {} sent ({} without payloads), {} skipped, {} delivered",
+        records.len(),
+        report.sent,
+        report.synthesized,
+        report.skipped,
+        report.delivered,
+    );
+    Ok(())
+}
+
+impl CaptureArgs {
+    /// Open the capture file, if one was given
+    fn open(&self) -> anyhow::Result<Option<Arc<FrameCapture>>> {
+        let Some(path) = &self.capture else { return Ok(None) };
+        let capture = FrameCapture::create(path, self.capture_payloads)
+            .with_context(|| format!("Cannot write {}", path.display()))?;
+        Ok(Some(Arc::new(capture)))
+    }
+}
+
+impl AgentArgs {
+    /// Connect as an agent and wait for the relay to acknowledge it
+    async fn connect(&self) -> anyhow::Result<OpacusClient> {
+        let network = Network::from(self.network);
+        let mut client = OpacusClient::new(OpacusConfig {
+            chain_rpc: network.rpc().to_string(),
+            network,
+            relay_url: self.relay.clone(),
+            private_key: None,
+        });
+        match &self.identity {
+            Some(path) => {
+                let identity = load_identity(path, self.password.as_deref())?;
+                client.init_from_keys(identity.ed_priv, identity.x_priv).await?;
+            }
+            None => {
+                client.init().await;
+            }
+        }
+        client.set_wire_format(self.format.into());
+        client.set_capture(self.capture.open()?);
+        if let Some(path) = &self.lifecycle_log {
+            let log = LifecycleLog::create(path).with_context(|| format!("Cannot write {}", path.display()))?;
+            client.set_lifecycle_log(Some(Arc::new(log)));
+        }
+        client.connect().await?;
+
+        let acked = tokio::time::timeout(CONNECT_TIMEOUT, async {
+            while let Some(frame) = client.recv().await {
+                if frame.frame_type == FrameType::Ack && frame.from == "relay" {
+                    return true;
+                }
+            }
+            false
+        })
+        .await;
+        if acked != Ok(true) {
+            bail!("No acknowledgment from relay {}", self.relay);
+        }
+        Ok(client)
+    }
+}
+
+fn load_identity(path: &Path, password: Option<&str>) -> anyhow::Result<AgentIdentity> {
+    let password = password.context("The keystore needs --password or OPACUS_PASSWORD")?;
+    let json = std::fs::read_to_string(path).with_context(|| format!("Cannot read {}", path.display()))?;
+    KeyManager::import_encrypted(&json, password).map_err(anyhow::Error::msg)
+}
+
+fn identity_json(identity: &AgentIdentity, private: bool) -> serde_json::Value {
+    let mut json = serde_json::json!({
+        "id": identity.id,
+        "address": identity.address,
+        "chainId": identity.chain_id,
+        "edPub": KeyManager::to_hex(&identity.ed_pub),
+        "xPub": KeyManager::to_hex(&identity.x_pub),
+    });
+    if private {
+        json["edPriv"] = KeyManager::to_hex(&identity.ed_priv).into();
+        json["xPriv"] = KeyManager::to_hex(&identity.x_priv).into();
+    }
+    json
+}
+
+/// Frame header fields, extensions and rendered payload
+fn frame_json(frame: &OpacusFrame) -> serde_json::Value {
+    let extensions: serde_json::Map<String, serde_json::Value> = frame
+        .extensions
+        .iter()
+        .map(|(key, value)| (key.clone(), value.deserialized().unwrap_or(serde_json::Value::Null)))
+        .collect();
+    serde_json::json!({
+        "version": frame.version,
+        "type": frame_type_name(frame.frame_type),
+        "id": frame.id.map(|id| id.to_string()),
+        "from": frame.from,
+        "to": frame.to,
+        "seq": frame.seq,
+        "ts": frame.ts,
+        "nonce": frame.nonce,
+        "priority": frame.priority.as_str(),
+        "contentType": frame.content_type.as_str(),
+        "compressed": frame.compressed.map(|alg| alg.as_str()),
+        "keyEpoch": frame.key_epoch,
+        "hmac": frame.hmac,
+        "sig": frame.sig.as_ref().map(hex::encode),
+        "extensions": extensions,
+        "payloadSize": frame.payload.len(),
+        "payload": frame.render_payload(),
+    })
+}
+
+/// One-line summary of a received frame
+fn frame_line(frame: &OpacusFrame) -> String {
+    let id = frame.id.map(|id| id.to_string()).unwrap_or_else(|| "-".to_string());
+    let payload = match frame.payload_as::<serde_json::Value>() {
+        Ok(value) => value.to_string(),
+        Err(_) => frame.render_payload(),
+    };
+    format!("{} {} from {}: {}", id, frame_type_name(frame.frame_type), frame.from, payload)
+}
+
+fn frame_type_name(frame_type: FrameType) -> String {
+    match frame_type.name() {
+        Some(name) => name.to_string(),
+        None => format!("unknown({})", frame_type.code()),
+    }
+}
+
+/// Decode hex, ignoring whitespace and a `0x` prefix
+fn decode_hex(text: &str) -> anyhow::Result<Vec<u8>> {
+    let text: String = text.split_whitespace().collect();
+    let text = text.strip_prefix("0x").unwrap_or(&text);
+    hex::decode(text).context("Invalid hex")
+}
+
+/// Parse a duration such as `500ms`, `2s` or `1m` (plain numbers are seconds)
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid duration: {}", text);
+    let (number, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(split) => text.split_at(split),
+        None => (text, "s"),
+    };
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    match unit {
+        "ms" => Ok(Duration::from_millis(number)),
+        "s" => Ok(Duration::from_secs(number)),
+        "m" => Ok(Duration::from_secs(number * 60)),
+        _ => Err(invalid()),
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Next tick of an optional interval, pending forever without one
+async fn tick(interval: &mut Option<tokio::time::Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+    use opacus_sdk::CBORCodec;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+        let cli = Cli::try_parse_from(["opacus-cli", "ping", "agent", "-c", "2", "--interval", "250ms"]).unwrap();
+        let Command::Ping(args) = cli.command else { panic!("expected ping") };
+        assert_eq!((args.to.as_str(), args.count, args.interval), ("agent", 2, Duration::from_millis(250)));
+
+        assert_eq!(parse_duration("2"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_duration("1m"), Ok(Duration::from_secs(60)));
+        assert!(parse_duration("1h").is_err());
+        assert!(parse_duration("ms").is_err());
+
+        let cli = Cli::try_parse_from(["opacus-cli", "replay", "frames.jsonl", "--speed", "0", "--no-recipients"]).unwrap();
+        let Command::Replay(args) = cli.command else { panic!("expected replay") };
+        assert_eq!((args.speed, args.no_recipients), (0.0, true));
+        assert!(Cli::try_parse_from(["opacus-cli", "listen", "--capture-payloads"]).is_err());
+
+        let cli = Cli::try_parse_from(["opacus-cli", "load", "echo", "--rate", "500", "--concurrency", "4", "--duration", "30s"]).unwrap();
+        let Command::Load(args) = cli.command else { panic!("expected load") };
+        assert_eq!((args.rate, args.size, args.concurrency, args.duration), (500, 256, 4, Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_inspect() {
+        let identity = KeyManager::identity_from_seed(&[1u8; 32], 16602);
+        let mut frame = opacus_sdk::ErrorPayload::new(opacus_sdk::ErrorCode::Unavailable, "down").to_frame(&identity.id, "bob", 7);
+        frame.id = Some(OpacusFrame::new_id(7));
+        let encoded = RoutingHeader::encode(&CBORCodec, &frame).unwrap();
+
+        for data in [encoded.clone(), CBORCodec::encode(&frame).unwrap()] {
+            let decoded = RoutingHeader::decode(&CBORCodec, &decode_hex(&format!("0x{}\n", hex::encode(&data))).unwrap()).unwrap();
+            let json = frame_json(&decoded);
+            assert_eq!(json["type"], "error");
+            assert_eq!(json["from"], identity.id);
+            assert_eq!(json["id"], frame.id.unwrap().to_string());
+            assert_eq!(json["contentType"], "json");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_echo_load() {
+        let relay = opacus_sdk::MemoryRelay::new();
+        let connect = || async {
+            let mut client = OpacusClient::new(OpacusConfig {
+                network: Network::Devnet,
+                relay_url: "memory".to_string(),
+                chain_rpc: String::new(),
+                private_key: None,
+            });
+            let id = client.init().await.id.clone();
+            client.connect_with(relay.transport()).await.unwrap();
+            (client, id)
+        };
+        let (mut echo_client, echo_id) = connect().await;
+        tokio::spawn(async move { echo(&mut echo_client).await });
+
+        let (mut client, _) = connect().await;
+        let result = generate_load(&mut client, &echo_id, Duration::from_millis(5), 64, Duration::from_millis(100), Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert!(result.sent >= 10, "{:?}", result);
+        assert_eq!((result.received, result.lost, result.latencies.len()), (result.sent, 0, result.sent as usize));
+
+        let report = result.report(Duration::from_millis(100), 64);
+        assert_eq!(report["sent"], report["received"]);
+        assert!(report["latencyMs"]["p50"].as_f64().unwrap() <= report["latencyMs"]["max"].as_f64().unwrap());
+
+        // Messages for an agent that never connects wait in the relay queue
+        let result = generate_load(&mut client, "nobody", Duration::from_millis(50), 8, Duration::from_millis(20), Duration::from_millis(20))
+            .await
+            .unwrap();
+        assert_eq!((result.received, result.lost), (0, result.sent));
+    }
+
+    #[test]
+    fn test_percentile() {
+        let samples: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&samples, 0.5), Some(Duration::from_millis(50)));
+        assert_eq!(percentile(&samples, 0.99), Some(Duration::from_millis(99)));
+        assert_eq!(percentile(&samples, 0.0), Some(Duration::from_millis(1)));
+        assert_eq!(percentile(&samples[..1], 0.999), Some(Duration::from_millis(1)));
+        assert_eq!(percentile(&[], 0.5), None);
+    }
+}
@@ -0,0 +1,173 @@
+//! Blocking client for code without an async runtime (`blocking` feature)
+//!
+//! Covers the basics of [`crate::OpacusClient`]: identity, connecting, and
+//! sending and receiving messages. The async client is driven on a runtime
+//! owned by this one; call anything else on it with [`OpacusClient::run`].
+//! Do not call it from inside an async runtime.
+
+use std::future::Future;
+use std::time::Duration;
+use serde::Serialize;
+use tokio::runtime::Runtime;
+use crate::error::OpacusError;
+use crate::qos::Priority;
+use crate::types::{AgentIdentity, OpacusConfig, OpacusFrame};
+
+/// Blocking facade over [`crate::OpacusClient`]
+pub struct OpacusClient {
+    inner: crate::OpacusClient,
+    runtime: Runtime,
+}
+
+impl OpacusClient {
+    /// Create new client with configuration
+    pub fn new(config: OpacusConfig) -> anyhow::Result<Self> {
+        Self::from_async(crate::OpacusClient::new(config))
+    }
+
+    /// Wrap a configured async client
+    ///
+    /// The QUIC connection is driven by one worker thread between calls.
+    pub fn from_async(inner: crate::OpacusClient) -> anyhow::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()?;
+        Ok(Self { inner, runtime })
+    }
+
+    /// The wrapped async client
+    pub fn inner(&self) -> &crate::OpacusClient {
+        &self.inner
+    }
+
+    /// Call an async method without a blocking counterpart
+    ///
+    /// ```rust,no_run
+    /// # fn publish(client: &mut opacus_sdk::blocking::OpacusClient) -> anyhow::Result<()> {
+    /// client.run(|inner| inner.publish_prekeys(10))?;
+    /// # Ok(()) }
+    /// ```
+    pub fn run<'a, F, Fut>(&'a mut self, call: F) -> Fut::Output
+    where
+        F: FnOnce(&'a mut crate::OpacusClient) -> Fut,
+        Fut: Future + 'a,
+    {
+        self.runtime.block_on(call(&mut self.inner))
+    }
+
+    /// Initialize client with new identity
+    pub fn init(&mut self) -> &AgentIdentity {
+        self.runtime.block_on(self.inner.init())
+    }
+
+    /// Initialize from existing identity
+    pub fn init_from_keys(&mut self, ed_priv: [u8; 32], x_priv: [u8; 32]) -> anyhow::Result<&AgentIdentity> {
+        self.runtime.block_on(self.inner.init_from_keys(ed_priv, x_priv))
+    }
+
+    /// Initialize from a 32-byte seed
+    pub fn init_from_seed(&mut self, seed: &[u8; 32]) -> &AgentIdentity {
+        self.runtime.block_on(self.inner.init_from_seed(seed))
+    }
+
+    /// Export identity to hex strings
+    pub fn export_identity(&self) -> Option<(String, String)> {
+        self.inner.export_identity()
+    }
+
+    /// Connect to relay server
+    pub fn connect(&mut self) -> anyhow::Result<()> {
+        self.runtime.block_on(self.inner.connect())
+    }
+
+    /// Check connection status
+    pub fn is_connected(&self) -> bool {
+        self.inner.is_connected()
+    }
+
+    /// Disconnect from relay
+    pub fn disconnect(&mut self) {
+        self.runtime.block_on(self.inner.disconnect())
+    }
+
+    /// Send message to another agent
+    pub fn send_message(&mut self, to: &str, payload: Vec<u8>) -> anyhow::Result<()> {
+        self.runtime.block_on(self.inner.send_message(to, payload))
+    }
+
+    /// Send a value as a JSON message
+    pub fn send_json<T: Serialize>(&mut self, to: &str, value: &T) -> anyhow::Result<()> {
+        self.runtime.block_on(self.inner.send_json(to, value))
+    }
+
+    /// Send a UTF-8 text message
+    pub fn send_text(&mut self, to: &str, text: &str) -> anyhow::Result<()> {
+        self.runtime.block_on(self.inner.send_text(to, text))
+    }
+
+    /// Send message with an explicit priority
+    pub fn send_message_with_priority(&mut self, to: &str, payload: Vec<u8>, priority: Priority) -> anyhow::Result<()> {
+        self.runtime.block_on(self.inner.send_message_with_priority(to, payload, priority))
+    }
+
+    /// Send queued frames, highest priority first
+    pub fn flush(&mut self) -> anyhow::Result<usize> {
+        self.runtime.block_on(self.inner.flush())
+    }
+
+    /// Number of frames waiting in the send queue
+    pub fn queued(&self) -> usize {
+        self.inner.queued()
+    }
+
+    /// Receive next frame, waiting until one arrives or the connection closes
+    pub fn recv(&mut self) -> Option<OpacusFrame> {
+        self.runtime.block_on(self.inner.recv())
+    }
+
+    /// Receive next frame, giving up after `timeout`
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Option<OpacusFrame> {
+        self.runtime.block_on(async { tokio::time::timeout(timeout, self.inner.recv()).await.ok().flatten() })
+    }
+
+    /// Receive next frame, mapping `Error` frames to `OpacusError`
+    pub fn recv_checked(&mut self) -> Option<Result<OpacusFrame, OpacusError>> {
+        self.runtime.block_on(self.inner.recv_checked())
+    }
+}
+
+impl From<OpacusClient> for crate::OpacusClient {
+    fn from(client: OpacusClient) -> Self {
+        client.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyManager;
+    use crate::types::Network;
+
+    fn config() -> OpacusConfig {
+        OpacusConfig {
+            network: Network::Devnet,
+            relay_url: "quic://127.0.0.1:9".to_string(),
+            chain_rpc: "http://127.0.0.1:9".to_string(),
+            private_key: None,
+        }
+    }
+
+    #[test]
+    fn test_blocking_client() {
+        let mut client = OpacusClient::new(config()).unwrap();
+        let seed = [7u8; 32];
+        let id = client.init_from_seed(&seed).id.clone();
+        assert_eq!(id, KeyManager::identity_from_seed(&seed, Network::Devnet.chain_id()).id);
+        assert!(client.export_identity().is_some());
+
+        assert!(!client.is_connected());
+        assert!(client.recv_timeout(Duration::from_millis(10)).is_none());
+        assert_eq!(client.queued(), 0);
+    }
+}
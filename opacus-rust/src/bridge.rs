@@ -0,0 +1,246 @@
+//! Bridge between relay agents and an H3DAC gateway (`h3dac-bridge` feature)
+//!
+//! Lets the HTTP gateway and the QUIC relay run side by side while agents
+//! migrate. The bridge is itself an agent on the relay: selected messages
+//! sent to it are forwarded to the gateway over one authenticated H3DAC
+//! session, and the gateway's response goes back to the sender as a
+//! [`BridgeMessage::Reply`]. Gateway failures come back as `Error` frames.
+//! Events the gateway pushes are passed on to every agent that has used the
+//! bridge.
+
+use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+use h3_dac_sdk::error::H3DACError;
+use h3_dac_sdk::events::{EventStream, GatewayEvent};
+use h3_dac_sdk::H3DACClient;
+use crate::client::OpacusClient;
+use crate::content::ContentType;
+use crate::error::{ErrorCode, ErrorPayload};
+use crate::types::{FrameType, OpacusFrame, Ulid};
+
+/// Message the bridge sends to agents (JSON)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BridgeMessage {
+    /// Gateway response to a forwarded message
+    #[serde(rename_all = "camelCase")]
+    Reply {
+        /// Message ID of the forwarded frame
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        related_id: Option<Ulid>,
+        /// Decrypted gateway response
+        response: serde_json::Value,
+    },
+    /// Event pushed by the gateway
+    Event {
+        event: GatewayEvent,
+    },
+}
+
+/// Chooses the frames forwarded to the gateway
+pub type BridgeSelector = Box<dyn Fn(&OpacusFrame) -> bool + Send + Sync>;
+
+/// Agent forwarding messages between the relay and an H3DAC gateway
+pub struct GatewayBridge {
+    agent: OpacusClient,
+    gateway: H3DACClient,
+    client_id: String,
+    selector: BridgeSelector,
+    /// Agents that sent through the bridge, which receive gateway events
+    peers: HashSet<String>,
+}
+
+impl GatewayBridge {
+    /// Create a bridge from a connected agent and a gateway client
+    ///
+    /// The gateway session is established as `client_id` on first use and
+    /// re-established when it expires. By default `Msg` frames with JSON
+    /// payloads are forwarded.
+    pub fn new(agent: OpacusClient, gateway: H3DACClient, client_id: &str) -> Self {
+        Self {
+            agent,
+            gateway,
+            client_id: client_id.to_string(),
+            selector: Box::new(|frame| frame.frame_type == FrameType::Msg && frame.content_type == ContentType::Json),
+            peers: HashSet::new(),
+        }
+    }
+
+    /// Choose which frames are forwarded; others are left to the caller
+    pub fn with_selector(mut self, selector: impl Fn(&OpacusFrame) -> bool + Send + Sync + 'static) -> Self {
+        self.selector = Box::new(selector);
+        self
+    }
+
+    /// The relay agent
+    pub fn agent(&mut self) -> &mut OpacusClient {
+        &mut self.agent
+    }
+
+    /// The gateway client
+    pub fn gateway(&self) -> &H3DACClient {
+        &self.gateway
+    }
+
+    /// Agents that receive gateway events
+    pub fn peers(&self) -> impl Iterator<Item = &str> {
+        self.peers.iter().map(String::as_str)
+    }
+
+    /// Forward a frame to the gateway if it is selected, replying to its sender
+    ///
+    /// # Returns
+    /// `false` if the frame was not selected
+    pub async fn handle(&mut self, frame: &OpacusFrame) -> anyhow::Result<bool> {
+        if !(self.selector)(frame) {
+            return Ok(false);
+        }
+        self.peers.insert(frame.from.clone());
+
+        let result = match frame.decompressed_payload() {
+            Ok(payload) => self.forward(&payload).await.map_err(|e| gateway_error(&e)),
+            Err(e) => Err(ErrorPayload::new(ErrorCode::Unsupported, e)),
+        };
+        match result {
+            Ok(response) => {
+                let reply = BridgeMessage::Reply { related_id: frame.id, response };
+                self.agent.send_json(&frame.from, &reply).await?;
+            }
+            Err(error) => {
+                warn!("Bridging message from {} failed: {}", frame.from, error.message);
+                self.agent.send_error(&frame.from, &error.related_to(frame.id)).await?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Pass a gateway event on to the agents that used the bridge
+    pub async fn forward_event(&mut self, event: &GatewayEvent) -> anyhow::Result<()> {
+        let message = BridgeMessage::Event { event: event.clone() };
+        let peers: Vec<String> = self.peers.iter().cloned().collect();
+        for peer in peers {
+            self.agent.send_json(&peer, &message).await?;
+        }
+        Ok(())
+    }
+
+    /// Bridge until the relay connection closes
+    ///
+    /// Frames that are not selected are dropped.
+    pub async fn run(&mut self) -> anyhow::Result<()> {
+        self.ensure_session().await?;
+        let mut events = self.subscribe().await;
+        info!("Bridging relay agent to gateway {}", self.gateway.active_gateway());
+
+        loop {
+            tokio::select! {
+                frame = self.agent.recv() => {
+                    let Some(frame) = frame else { return Ok(()) };
+                    if !self.handle(&frame).await? {
+                        debug!("Not bridged: {:?} frame from {}", frame.frame_type, frame.from);
+                    }
+                }
+                event = next_event(&mut events) => match event {
+                    Some(Ok(event)) => self.forward_event(&event).await?,
+                    Some(Err(e)) => {
+                        warn!("Gateway event stream failed: {}", e);
+                        events = None;
+                    }
+                    // The gateway closes the stream once the session expired
+                    None if !self.gateway.is_authenticated() => {
+                        self.ensure_session().await?;
+                        events = self.subscribe().await;
+                    }
+                    None => events = None,
+                },
+            }
+        }
+    }
+
+    /// Send a payload through the gateway session, re-authenticating once if it expired
+    async fn forward(&mut self, payload: &[u8]) -> Result<serde_json::Value, H3DACError> {
+        self.ensure_session().await?;
+        match self.gateway.send_raw(payload).await {
+            Err(H3DACError::SessionExpired | H3DACError::NotAuthenticated) => {
+                self.gateway.clear_session();
+                self.ensure_session().await?;
+                self.gateway.send_raw(payload).await
+            }
+            result => result,
+        }
+    }
+
+    async fn ensure_session(&mut self) -> Result<(), H3DACError> {
+        if !self.gateway.is_authenticated() {
+            self.gateway.authenticate(&self.client_id).await?;
+            info!("Bridge authenticated to gateway as {}", self.client_id);
+        }
+        Ok(())
+    }
+
+    async fn subscribe(&self) -> Option<EventStream> {
+        match self.gateway.subscribe_events().await {
+            Ok(events) => Some(events),
+            Err(e) => {
+                warn!("Gateway events unavailable: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Next event of a stream, pending forever without one
+async fn next_event(events: &mut Option<EventStream>) -> Option<h3_dac_sdk::error::Result<GatewayEvent>> {
+    match events {
+        Some(events) => events.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// `Error` frame payload reporting a gateway failure
+fn gateway_error(error: &H3DACError) -> ErrorPayload {
+    let code = match error {
+        H3DACError::RateLimited { .. } | H3DACError::QuotaExceeded(_) => ErrorCode::RateLimited,
+        H3DACError::PayloadTooLarge(_) => ErrorCode::TooLarge,
+        H3DACError::InvalidSignature(_) | H3DACError::AuthError(_) => ErrorCode::Unauthorized,
+        _ => ErrorCode::Unavailable,
+    };
+    let payload = ErrorPayload::new(code, format!("Gateway: {}", error));
+    match error {
+        H3DACError::RateLimited { retry_after } => payload.with_retry_after(*retry_after),
+        _ => payload,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::error::OpacusError;
+
+    #[test]
+    fn test_bridge_message() {
+        let related = OpacusFrame::new_id(1);
+        let reply = BridgeMessage::Reply { related_id: Some(related), response: serde_json::json!({ "processed": true }) };
+        let json = serde_json::to_value(&reply).unwrap();
+        assert_eq!(json["kind"], "reply");
+        assert_eq!(json["response"]["processed"], true);
+        assert_eq!(serde_json::from_value::<BridgeMessage>(json).unwrap(), reply);
+
+        let event = BridgeMessage::Event { event: GatewayEvent::SessionExpiring { expires_at: 5 } };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json, serde_json::json!({ "kind": "event", "event": { "type": "session_expiring", "expiresAt": 5 } }));
+    }
+
+    #[test]
+    fn test_gateway_error() {
+        let error = gateway_error(&H3DACError::RateLimited { retry_after: Duration::from_secs(3) });
+        assert!(matches!(
+            OpacusError::from(error),
+            OpacusError::RateLimited { retry_after: Some(retry_after), .. } if retry_after == Duration::from_secs(3)
+        ));
+        assert_eq!(gateway_error(&H3DACError::PayloadTooLarge("big".into())).code, ErrorCode::TooLarge);
+        assert_eq!(gateway_error(&H3DACError::HttpError("502".into())).code, ErrorCode::Unavailable);
+    }
+}
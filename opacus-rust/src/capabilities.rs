@@ -0,0 +1,154 @@
+//! Capability advertisement
+//!
+//! After connecting, a client publishes its [`Capabilities`] in a
+//! `Capabilities` frame to `"relay"`, which keeps the latest set each agent
+//! published. Other agents look them up with a `Capabilities` frame to
+//! `"relay"` whose payload is a [`CapabilityQuery`]; the relay answers with the
+//! stored set (empty payload if the agent published none), marked as the
+//! reply to the query (see [`REPLY_TO_EXTENSION`](crate::REPLY_TO_EXTENSION)).
+//! Agents can also send their capabilities straight to a peer.
+//!
+//! Senders use a peer's capabilities to adapt what they send, e.g. pick a
+//! compression algorithm or content type the peer decodes, or the schema
+//! version of a service it runs. Capabilities are advisory: they are not
+//! covered by any registry, and a relay only stores the set an agent
+//! publishes about itself on its own connection.
+
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use crate::compression::Compression;
+use crate::content::ContentType;
+use crate::types::{FrameType, OpacusFrame};
+
+/// Features an agent supports
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Capabilities {
+    /// Agent the capabilities belong to
+    pub agent_id: String,
+    /// Highest frame version the agent speaks
+    pub version: u8,
+    /// Payload content types the agent decodes
+    #[serde(default)]
+    pub content_types: Vec<ContentType>,
+    /// Compression algorithms the agent decodes, in order of preference
+    #[serde(default)]
+    pub compression: Vec<Compression>,
+    /// Frame extensions the agent interprets
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    /// Data channels the agent offers
+    #[serde(default)]
+    pub channels: Vec<String>,
+    /// Application services the agent runs, with their schema version
+    #[serde(default)]
+    pub services: BTreeMap<String, String>,
+    /// Whether the agent published prekeys for offline sessions
+    #[serde(default)]
+    pub prekeys: bool,
+}
+
+/// Lookup of another agent's capabilities, sent to `"relay"`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CapabilityQuery {
+    /// Agent whose capabilities are requested
+    pub fetch: String,
+}
+
+impl Capabilities {
+    /// Capabilities with no content types, extensions, channels or services
+    pub fn new(agent_id: &str, version: u8) -> Self {
+        Self {
+            agent_id: agent_id.to_string(),
+            version,
+            content_types: Vec::new(),
+            compression: Vec::new(),
+            extensions: Vec::new(),
+            channels: Vec::new(),
+            services: BTreeMap::new(),
+            prekeys: false,
+        }
+    }
+
+    /// Whether the agent decodes payloads of a content type
+    ///
+    /// Raw payloads are always accepted.
+    pub fn accepts(&self, content_type: ContentType) -> bool {
+        content_type == ContentType::Raw || self.content_types.contains(&content_type)
+    }
+
+    /// Whether the agent interprets a frame extension
+    pub fn supports_extension(&self, name: &str) -> bool {
+        self.extensions.iter().any(|e| e == name)
+    }
+
+    /// Schema version of a service the agent runs
+    pub fn service_version(&self, name: &str) -> Option<&str> {
+        self.services.get(name).map(String::as_str)
+    }
+
+    /// Preferred compression algorithm of this build that the agent also decodes
+    pub fn compression(&self) -> Option<Compression> {
+        Compression::negotiate(&self.compression)
+    }
+}
+
+impl OpacusFrame {
+    /// Decode the capabilities carried by a `Capabilities` frame
+    ///
+    /// # Returns
+    /// `None` for other frames, queries, and empty relay answers
+    pub fn capabilities(&self) -> Option<Capabilities> {
+        if self.frame_type != FrameType::Capabilities || self.payload.is_empty() {
+            return None;
+        }
+        serde_json::from_slice(&self.payload).ok()
+    }
+
+    /// Decode the query of a `Capabilities` frame
+    pub fn capability_query(&self) -> Option<CapabilityQuery> {
+        if self.frame_type != FrameType::Capabilities {
+            return None;
+        }
+        serde_json::from_slice(&self.payload).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(payload: &[u8]) -> OpacusFrame {
+        OpacusFrame {
+            frame_type: FrameType::Capabilities,
+            from: "a".to_string(),
+            to: "relay".to_string(),
+            payload: payload.to_vec().into(),
+            ..OpacusFrame::test(1)
+        }
+    }
+
+    #[test]
+    fn test_capabilities_payload() {
+        let mut caps = Capabilities::new("a", 2);
+        caps.content_types = vec![ContentType::Json, ContentType::Cbor];
+        caps.compression = Compression::supported();
+        caps.services.insert("summarize".into(), "1.2".into());
+
+        let decoded = frame(&serde_json::to_vec(&caps).unwrap()).capabilities().unwrap();
+        assert_eq!(decoded, caps);
+        assert!(decoded.accepts(ContentType::Raw) && decoded.accepts(ContentType::Cbor));
+        assert!(!decoded.accepts(ContentType::Protobuf));
+        assert_eq!(decoded.service_version("summarize"), Some("1.2"));
+        assert_eq!(decoded.compression(), Compression::supported().first().copied());
+
+        // Fields added later default, queries and empty answers are not capabilities
+        let minimal = frame(br#"{"agentId":"a","version":1}"#).capabilities().unwrap();
+        assert!(minimal.content_types.is_empty() && !minimal.prekeys);
+        let query = frame(br#"{"fetch":"b"}"#);
+        assert_eq!(query.capabilities(), None);
+        assert_eq!(query.capability_query(), Some(CapabilityQuery { fetch: "b".into() }));
+        assert_eq!(frame(b"").capabilities(), None);
+    }
+}
@@ -0,0 +1,567 @@
+//! Frame capture and replay
+//!
+//! A [`FrameCapture`] records the frames a client or relay sends and
+//! receives to a JSON-lines file, one [`CaptureRecord`] per frame with its
+//! routing metadata and, optionally, the whole encoded frame. With the
+//! `client` feature, `replay` re-injects a capture against a test relay to
+//! reproduce traffic for debugging or load tests.
+//!
+//! Captures with payloads hold message contents, HMACs and signatures; treat
+//! them like the traffic itself. Bearer tokens are the exception: the JWT of
+//! a `Connect` frame is removed before it is recorded.
+
+#[cfg(feature = "client")]
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+#[cfg(feature = "client")]
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "client")]
+use tracing::debug;
+use tracing::warn;
+use crate::clock::{Clock, SystemClock};
+use crate::compression::Compression;
+use crate::content::ContentType;
+use crate::proto::CBORCodec;
+#[cfg(feature = "client")]
+use crate::proto::FRAME_VERSION;
+use crate::qos::Priority;
+#[cfg(feature = "client")]
+use crate::transport::QUICTransport;
+use crate::types::{FrameType, OpacusFrame, Ulid};
+
+/// How long replay waits for a relay to acknowledge an agent
+#[cfg(feature = "client")]
+const REPLAY_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long replay keeps counting deliveries after the last frame
+#[cfg(feature = "client")]
+const REPLAY_DRAIN: Duration = Duration::from_millis(500);
+
+/// Whether a frame was received or sent by the capturing side
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureDirection {
+    In,
+    Out,
+}
+
+/// One captured frame
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptureRecord {
+    /// When the frame was captured (milliseconds since the Unix epoch)
+    pub captured_at: u64,
+    pub direction: CaptureDirection,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Ulid>,
+    pub version: u8,
+    pub frame_type: FrameType,
+    pub from: String,
+    pub to: String,
+    pub seq: u64,
+    pub ts: u64,
+    pub priority: Priority,
+    pub content_type: ContentType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compressed: Option<Compression>,
+    /// Payload length as sent
+    pub size: usize,
+    /// Whole frame, CBOR-encoded (hex), if payloads are captured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frame: Option<String>,
+}
+
+impl CaptureRecord {
+    /// Record a frame
+    ///
+    /// # Arguments
+    /// * `frame` - Captured frame
+    /// * `direction` - Whether it was received or sent
+    /// * `captured_at` - Capture time (milliseconds)
+    /// * `payload` - Whether to keep the whole encoded frame
+    pub fn new(frame: &OpacusFrame, direction: CaptureDirection, captured_at: u64, payload: bool) -> Self {
+        Self {
+            captured_at,
+            direction,
+            id: frame.id,
+            version: frame.version,
+            frame_type: frame.frame_type,
+            from: frame.from.clone(),
+            to: frame.to.clone(),
+            seq: frame.seq,
+            ts: frame.ts,
+            priority: frame.priority,
+            content_type: frame.content_type,
+            compressed: frame.compressed,
+            size: frame.payload.len(),
+            frame: payload.then(|| CBORCodec::encode(&without_token(frame)).ok().map(hex::encode)).flatten(),
+        }
+    }
+
+    /// Frame to replay: the captured frame, or one with a zeroed payload of
+    /// the captured size if only metadata was recorded
+    pub fn to_frame(&self) -> Result<OpacusFrame, String> {
+        if let Some(encoded) = &self.frame {
+            let data = hex::decode(encoded).map_err(|e| format!("Invalid captured frame: {}", e))?;
+            return CBORCodec::decode(&data).map_err(|e| e.to_string());
+        }
+        Ok(OpacusFrame {
+            version: self.version,
+            frame_type: self.frame_type,
+            from: self.from.clone(),
+            to: self.to.clone(),
+            seq: self.seq,
+            ts: self.ts,
+            nonce: String::new(),
+            payload: vec![0u8; self.size].into(),
+            hmac: None,
+            sig: None,
+            key_epoch: 0,
+            compressed: None,
+            id: self.id,
+            priority: self.priority,
+            content_type: ContentType::Raw,
+            extensions: Default::default(),
+        })
+    }
+
+    /// Read a capture file
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Vec<CaptureRecord>> {
+        let file = std::fs::File::open(path.as_ref())?;
+        Self::read(std::io::BufReader::new(file))
+    }
+
+    /// Read capture records, one JSON object per line
+    pub fn read(reader: impl BufRead) -> anyhow::Result<Vec<CaptureRecord>> {
+        let mut records = Vec::new();
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = serde_json::from_str(&line)
+                .map_err(|e| anyhow::anyhow!("Invalid capture record on line {}: {}", number + 1, e))?;
+            records.push(record);
+        }
+        Ok(records)
+    }
+}
+
+/// Records queued for the capture writer before further ones are dropped
+pub const CAPTURE_QUEUE_LEN: usize = 4096;
+
+/// Request to the capture writer thread
+enum CaptureCommand {
+    Record(Box<CaptureRecord>),
+    Flush(mpsc::Sender<std::io::Result<()>>),
+}
+
+/// Writes captured frames as JSON lines
+///
+/// Records are written by a dedicated thread, so recording never waits on
+/// the writer. If it falls more than [`CAPTURE_QUEUE_LEN`] records behind,
+/// further records are dropped with a warning until it catches up. The
+/// thread finishes the queued records when the capture is dropped.
+pub struct FrameCapture {
+    queue: Option<SyncSender<CaptureCommand>>,
+    writer: Option<JoinHandle<()>>,
+    payloads: bool,
+    clock: Arc<dyn Clock>,
+}
+
+impl FrameCapture {
+    /// Capture to a new file, replacing any existing one
+    ///
+    /// Each record is flushed once the writer thread has written it.
+    ///
+    /// # Arguments
+    /// * `path` - Capture file
+    /// * `payloads` - Keep whole frames (needed to replay contents), not only metadata
+    pub fn create(path: impl AsRef<Path>, payloads: bool) -> std::io::Result<Self> {
+        let file = std::fs::File::create(path)?;
+        Ok(Self::new(std::io::LineWriter::new(file), payloads))
+    }
+
+    /// Capture to any writer
+    pub fn new(mut writer: impl Write + Send + 'static, payloads: bool) -> Self {
+        let (queue, commands) = mpsc::sync_channel(CAPTURE_QUEUE_LEN);
+        let thread = std::thread::Builder::new()
+            .name("opacus-capture".into())
+            .spawn(move || {
+                for command in commands {
+                    match command {
+                        CaptureCommand::Record(record) => {
+                            let Ok(line) = serde_json::to_string(&record) else { continue };
+                            if let Err(e) = writeln!(writer, "{}", line) {
+                                warn!("Frame capture failed: {}", e);
+                            }
+                        }
+                        CaptureCommand::Flush(done) => {
+                            let _ = done.send(writer.flush());
+                        }
+                    }
+                }
+                if let Err(e) = writer.flush() {
+                    warn!("Frame capture failed: {}", e);
+                }
+            })
+            .expect("Capture writer thread");
+        Self {
+            queue: Some(queue),
+            writer: Some(thread),
+            payloads,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Use a custom time source for capture times
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Queue a frame for the writer; write errors are logged, not returned
+    pub fn record(&self, direction: CaptureDirection, frame: &OpacusFrame) {
+        let record = CaptureRecord::new(frame, direction, self.clock.now_ms(), self.payloads);
+        let Some(queue) = &self.queue else { return };
+        match queue.try_send(CaptureCommand::Record(Box::new(record))) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => warn!("Frame capture behind, dropped {:?} frame {:?}", direction, frame.id),
+            Err(TrySendError::Disconnected(_)) => warn!("Frame capture writer stopped"),
+        }
+    }
+
+    /// Write and flush the records queued so far
+    pub fn flush(&self) -> std::io::Result<()> {
+        let stopped = || std::io::Error::other("Frame capture writer stopped");
+        let (done, flushed) = mpsc::channel();
+        self.queue.as_ref().ok_or_else(stopped)?.send(CaptureCommand::Flush(done)).map_err(|_| stopped())?;
+        flushed.recv().map_err(|_| stopped())?
+    }
+}
+
+impl Drop for FrameCapture {
+    fn drop(&mut self) {
+        // Closing the queue lets the writer finish what is queued and exit
+        self.queue.take();
+        if let Some(thread) = self.writer.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Frame without the bearer token a `Connect` payload may carry
+fn without_token(frame: &OpacusFrame) -> std::borrow::Cow<'_, OpacusFrame> {
+    use std::borrow::Cow;
+    if frame.frame_type != FrameType::Connect {
+        return Cow::Borrowed(frame);
+    }
+    let Ok(mut payload) = serde_json::from_slice::<serde_json::Value>(&frame.payload) else { return Cow::Borrowed(frame) };
+    if payload.as_object_mut().and_then(|fields| fields.remove(crate::types::CONNECT_TOKEN_FIELD)).is_none() {
+        return Cow::Borrowed(frame);
+    }
+    let mut frame = frame.clone();
+    frame.payload = serde_json::to_vec(&payload).unwrap_or_default().into();
+    Cow::Owned(frame)
+}
+
+/// Replay settings
+#[cfg(feature = "client")]
+#[derive(Debug, Clone, Copy)]
+pub struct ReplayOptions {
+    /// Pace relative to the capture: 1.0 keeps the captured timing, 2.0
+    /// replays twice as fast, 0 sends as fast as possible
+    pub speed: f64,
+    /// Connect the recipients too, so frames are delivered and counted
+    /// instead of queued for offline agents
+    pub connect_recipients: bool,
+}
+
+#[cfg(feature = "client")]
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self { speed: 1.0, connect_recipients: true }
+    }
+}
+
+/// Outcome of a replay
+#[cfg(feature = "client")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    /// Frames sent to the relay
+    pub sent: usize,
+    /// Sent frames rebuilt from metadata, with zeroed payloads
+    pub synthesized: usize,
+    /// Records not replayed: duplicates, relay-originated frames, and
+    /// frames that could not be decoded or sent
+    pub skipped: usize,
+    /// Frames received by the replayed recipients
+    pub delivered: usize,
+}
+
+/// Frames of a capture to re-inject
+#[cfg(feature = "client")]
+struct ReplayPlan {
+    /// Capture time, frame, and whether it was rebuilt from metadata, in capture order
+    frames: Vec<(u64, OpacusFrame, bool)>,
+    /// Captured `Connect` frames by agent
+    connects: HashMap<String, OpacusFrame>,
+}
+
+/// Plan the replay of a capture
+///
+/// Each frame is replayed once even if it was captured on both sides of the
+/// relay. Frames the relay itself sent are left out, and captured `Connect`
+/// frames are kept so replay can connect with the original keys.
+#[cfg(feature = "client")]
+fn replay_plan(records: &[CaptureRecord], report: &mut ReplayReport) -> ReplayPlan {
+    let mut seen = HashSet::new();
+    let mut frames = Vec::new();
+    let mut connects = HashMap::new();
+    for record in records {
+        let key = match record.id {
+            Some(id) => id.to_string(),
+            None => format!("{}/{}/{}", record.from, record.seq, record.ts),
+        };
+        if record.from == "relay" || !seen.insert(key) {
+            report.skipped += 1;
+            continue;
+        }
+        let frame = match record.to_frame() {
+            Ok(frame) => frame,
+            Err(e) => {
+                warn!("Skipping captured frame from {}: {}", record.from, e);
+                report.skipped += 1;
+                continue;
+            }
+        };
+        if frame.frame_type == FrameType::Connect {
+            if record.frame.is_some() {
+                connects.insert(frame.from.clone(), frame);
+            }
+            continue;
+        }
+        frames.push((record.captured_at, frame, record.frame.is_none()));
+    }
+    ReplayPlan { frames, connects }
+}
+
+/// Re-inject captured frames against a relay
+///
+/// Connects as each sender (and, if enabled, recipient) in the capture,
+/// using its captured `Connect` frame when the capture has payloads, and
+/// sends every frame on its sender's connection. Frames keep their original
+/// IDs, timestamps and signatures, so replay against a test relay; relays
+/// verifying signatures accept them only if payloads and `Connect` frames
+/// were captured.
+///
+/// # Arguments
+/// * `relay_addr` - Relay address (`host:port`, optionally `quic://`)
+/// * `records` - Captured frames
+/// * `options` - Pace and recipients
+#[cfg(feature = "client")]
+pub async fn replay(relay_addr: &str, records: &[CaptureRecord], options: ReplayOptions) -> anyhow::Result<ReplayReport> {
+    let mut report = ReplayReport::default();
+    let ReplayPlan { frames, mut connects } = replay_plan(records, &mut report);
+
+    let mut agents: Vec<String> = Vec::new();
+    for (_, frame, _) in &frames {
+        agents.push(frame.from.clone());
+        if options.connect_recipients && frame.to != "relay" && !frame.to.is_empty() {
+            agents.push(frame.to.clone());
+        }
+    }
+    let mut unique = HashSet::new();
+    agents.retain(|agent| unique.insert(agent.clone()));
+
+    let mut transports = HashMap::new();
+    for agent in agents {
+        let connect = connects.remove(&agent).unwrap_or_else(|| connect_frame(&agent));
+        transports.insert(agent, connect_agent(relay_addr, &connect).await?);
+    }
+    debug!("Replaying {} frames as {} agents", frames.len(), transports.len());
+
+    let start = tokio::time::Instant::now();
+    let first = frames.first().map(|(at, _, _)| *at).unwrap_or_default();
+    for (captured_at, frame, synthesized) in &frames {
+        if options.speed > 0.0 {
+            let offset = Duration::from_millis(captured_at.saturating_sub(first)).div_f64(options.speed);
+            tokio::time::sleep_until(start + offset).await;
+        }
+        let transport = &transports[&frame.from];
+        match transport.send(frame).await {
+            Ok(()) => {
+                report.sent += 1;
+                report.synthesized += *synthesized as usize;
+            }
+            Err(e) => {
+                warn!("Cannot replay {:?} from {}: {}", frame.frame_type, frame.from, e);
+                report.skipped += 1;
+            }
+        }
+    }
+
+    let delivered = futures::future::join_all(transports.values_mut().map(|transport| async move {
+        let mut delivered = 0;
+        while let Ok(Some(frame)) = tokio::time::timeout(REPLAY_DRAIN, transport.recv()).await {
+            if frame.from != "relay" {
+                delivered += 1;
+            }
+        }
+        delivered
+    }))
+    .await;
+    report.delivered = delivered.into_iter().sum();
+
+    for transport in transports.values_mut() {
+        transport.close().await;
+    }
+    Ok(report)
+}
+
+/// `Connect` frame for an agent whose own was not captured
+#[cfg(feature = "client")]
+fn connect_frame(agent: &str) -> OpacusFrame {
+    let ts = SystemClock.now_ms();
+    let payload = serde_json::json!({
+        "edPub": hex::encode([0u8; 32]),
+        "xPub": hex::encode([0u8; 32]),
+        "compression": Compression::supported(),
+    });
+    OpacusFrame {
+        version: FRAME_VERSION,
+        frame_type: FrameType::Connect,
+        from: agent.to_string(),
+        to: "relay".to_string(),
+        seq: 0,
+        ts,
+        nonce: String::new(),
+        payload: serde_json::to_vec(&payload).unwrap_or_default().into(),
+        hmac: None,
+        sig: None,
+        key_epoch: 0,
+        compressed: None,
+        id: Some(OpacusFrame::new_id(ts)),
+        priority: Priority::Control,
+        content_type: ContentType::Json,
+        extensions: Default::default(),
+    }
+}
+
+/// Connect to the relay as the sender of a `Connect` frame and wait for its ACK
+#[cfg(feature = "client")]
+async fn connect_agent(relay_addr: &str, connect: &OpacusFrame) -> anyhow::Result<QUICTransport> {
+    let mut transport = QUICTransport::new("0.0.0.0:0", relay_addr.trim_start_matches("quic://")).await?;
+    transport.connect().await?;
+    transport.send(connect).await?;
+    let acked = tokio::time::timeout(REPLAY_CONNECT_TIMEOUT, async {
+        while let Some(frame) = transport.recv().await {
+            if frame.frame_type == FrameType::Ack && frame.from == "relay" {
+                return true;
+            }
+        }
+        false
+    })
+    .await;
+    if acked != Ok(true) {
+        anyhow::bail!("Relay {} did not acknowledge {}", relay_addr, connect.from);
+    }
+    Ok(transport)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Writer whose contents the test can read back
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn frame(from: &str, to: &str, seq: u64) -> OpacusFrame {
+        OpacusFrame {
+            from: from.to_string(),
+            to: to.to_string(),
+            id: Some(OpacusFrame::new_id(1234567890)),
+            payload: br#"{"message":"down"}"#.to_vec().into(),
+            content_type: ContentType::Json,
+            ..OpacusFrame::test(seq)
+        }
+    }
+
+    #[test]
+    fn test_capture_roundtrip() {
+        let out = Shared::default();
+        let capture = FrameCapture::new(out.clone(), true).with_clock(Arc::new(crate::clock::ManualClock::new(42)));
+        let sent = frame("alice", "bob", 1);
+        capture.record(CaptureDirection::Out, &sent);
+        capture.flush().unwrap();
+        let metadata_only = FrameCapture::new(out.clone(), false);
+        metadata_only.record(CaptureDirection::In, &sent);
+        drop(metadata_only);
+
+        let records = CaptureRecord::read(out.0.lock().unwrap().as_slice()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].captured_at, 42);
+        assert_eq!(records[0].direction, CaptureDirection::Out);
+        assert_eq!((records[0].id, records[0].size), (sent.id, sent.payload.len()));
+
+        let replayed = records[0].to_frame().unwrap();
+        assert_eq!(replayed.payload, sent.payload);
+        assert_eq!(replayed.content_type, ContentType::Json);
+
+        assert!(records[1].frame.is_none());
+        let synthesized = records[1].to_frame().unwrap();
+        assert_eq!((synthesized.id, synthesized.payload.len()), (sent.id, sent.payload.len()));
+        assert!(synthesized.payload.iter().all(|&b| b == 0));
+
+        assert!(CaptureRecord::read(&b"{}\n"[..]).is_err());
+
+        // Bearer tokens stay out of captures
+        let mut connect = frame("alice", "relay", 2);
+        connect.frame_type = FrameType::Connect;
+        connect.payload = br#"{"edPub":"ab","jwt":"secret"}"#.to_vec().into();
+        let record = CaptureRecord::new(&connect, CaptureDirection::In, 1, true);
+        assert_eq!(record.to_frame().unwrap().payload, &br#"{"edPub":"ab"}"#[..]);
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_replay_plan() {
+        let ack = frame("relay", "alice", 0);
+        let first = frame("alice", "bob", 1);
+        let second = frame("bob", "alice", 2);
+        let records: Vec<_> = [
+            CaptureRecord::new(&connect_frame("alice"), CaptureDirection::In, 1, true),
+            CaptureRecord::new(&ack, CaptureDirection::Out, 2, true),
+            // Captured by the relay when it arrived and when it was delivered
+            CaptureRecord::new(&first, CaptureDirection::In, 3, true),
+            CaptureRecord::new(&first, CaptureDirection::Out, 4, true),
+            CaptureRecord::new(&second, CaptureDirection::In, 5, false),
+        ]
+        .into_iter()
+        .collect();
+
+        let mut report = ReplayReport::default();
+        let plan = replay_plan(&records, &mut report);
+        assert_eq!(report.skipped, 2);
+        assert!(plan.connects.contains_key("alice"));
+        let planned: Vec<_> = plan.frames.iter().map(|(at, frame, synthesized)| (*at, frame.id, *synthesized)).collect();
+        assert_eq!(planned, vec![(3, first.id, false), (5, second.id, true)]);
+    }
+}
@@ -0,0 +1,366 @@
+//! Solidity ABI encoding for contract calls
+//!
+//! Covers the types the Opacus contracts use. `uint256` values are limited to
+//! `u128`; larger return values are rejected when decoding.
+
+use super::{keccak256, Address, ChainError};
+
+/// ABI value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    /// `address`
+    Address(Address),
+    /// `uint256` (and smaller `uint`s)
+    Uint(u128),
+    /// `bool`
+    Bool(bool),
+    /// `bytes32`; shorter fixed byte types are left-aligned
+    FixedBytes([u8; 32]),
+    /// `bytes`
+    Bytes(Vec<u8>),
+    /// `string`
+    String(String),
+    /// `T[]`
+    Array(Vec<Token>),
+    /// Struct or tuple
+    Tuple(Vec<Token>),
+}
+
+/// ABI type, for decoding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamType {
+    /// `address`
+    Address,
+    /// `uint256` (and smaller `uint`s)
+    Uint,
+    /// `bool`
+    Bool,
+    /// `bytes32`
+    FixedBytes,
+    /// `bytes`
+    Bytes,
+    /// `string`
+    String,
+    /// `T[]`
+    Array(Box<ParamType>),
+    /// Struct or tuple
+    Tuple(Vec<ParamType>),
+}
+
+impl ParamType {
+    fn is_dynamic(&self) -> bool {
+        match self {
+            ParamType::Bytes | ParamType::String | ParamType::Array(_) => true,
+            ParamType::Tuple(items) => items.iter().any(ParamType::is_dynamic),
+            _ => false,
+        }
+    }
+
+    /// Size of the head of a static value
+    fn head_len(&self) -> usize {
+        match self {
+            ParamType::Tuple(items) if !self.is_dynamic() => items.iter().map(ParamType::head_len).sum(),
+            _ => 32,
+        }
+    }
+}
+
+impl Token {
+    fn is_dynamic(&self) -> bool {
+        match self {
+            Token::Bytes(_) | Token::String(_) | Token::Array(_) => true,
+            Token::Tuple(items) => items.iter().any(Token::is_dynamic),
+            _ => false,
+        }
+    }
+
+    /// Value as `bytes32`
+    pub fn into_fixed_bytes(self) -> Option<[u8; 32]> {
+        match self {
+            Token::FixedBytes(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    /// Value as `uint`
+    pub fn into_uint(self) -> Option<u128> {
+        match self {
+            Token::Uint(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Value as `address`
+    pub fn into_address(self) -> Option<Address> {
+        match self {
+            Token::Address(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    /// Value as `bool`
+    pub fn into_bool(self) -> Option<bool> {
+        match self {
+            Token::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    /// Value as `string`
+    pub fn into_string(self) -> Option<String> {
+        match self {
+            Token::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Value as `bytes`
+    pub fn into_bytes(self) -> Option<Vec<u8>> {
+        match self {
+            Token::Bytes(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    /// Items of an array or tuple
+    pub fn into_items(self) -> Option<Vec<Token>> {
+        match self {
+            Token::Array(items) | Token::Tuple(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+/// 4-byte function selector
+///
+/// # Arguments
+/// * `signature` - Canonical signature, e.g. "transfer(address,uint256)"
+pub fn selector(signature: &str) -> [u8; 4] {
+    keccak256(signature.as_bytes())[..4].try_into().expect("4-byte prefix")
+}
+
+/// Topic identifying an event
+///
+/// # Arguments
+/// * `signature` - Canonical event signature, e.g. "Transfer(address,address,uint256)"
+pub fn event_topic(signature: &str) -> [u8; 32] {
+    keccak256(signature.as_bytes())
+}
+
+/// Encode a function call: selector followed by the encoded arguments
+pub fn encode_call(signature: &str, args: &[Token]) -> Vec<u8> {
+    let mut out = selector(signature).to_vec();
+    out.extend_from_slice(&encode(args));
+    out
+}
+
+/// Encode values as a tuple (`abi.encode`)
+pub fn encode(tokens: &[Token]) -> Vec<u8> {
+    let head_len: usize = tokens.iter().map(|t| if t.is_dynamic() { 32 } else { static_len(t) }).sum();
+    let mut head = Vec::with_capacity(head_len);
+    let mut tail = Vec::new();
+    for token in tokens {
+        if token.is_dynamic() {
+            head.extend_from_slice(&uint_word((head_len + tail.len()) as u128));
+            tail.extend_from_slice(&encode_one(token));
+        } else {
+            head.extend_from_slice(&encode_one(token));
+        }
+    }
+    head.extend_from_slice(&tail);
+    head
+}
+
+fn static_len(token: &Token) -> usize {
+    match token {
+        Token::Tuple(items) => items.iter().map(static_len).sum(),
+        _ => 32,
+    }
+}
+
+fn encode_one(token: &Token) -> Vec<u8> {
+    match token {
+        Token::Address(a) => {
+            let mut word = [0u8; 32];
+            word[12..].copy_from_slice(&a.0);
+            word.to_vec()
+        }
+        Token::Uint(v) => uint_word(*v).to_vec(),
+        Token::Bool(b) => uint_word(*b as u128).to_vec(),
+        Token::FixedBytes(b) => b.to_vec(),
+        Token::Bytes(data) => encode_bytes(data),
+        Token::String(s) => encode_bytes(s.as_bytes()),
+        Token::Array(items) => {
+            let mut out = uint_word(items.len() as u128).to_vec();
+            out.extend_from_slice(&encode(items));
+            out
+        }
+        Token::Tuple(items) => encode(items),
+    }
+}
+
+fn encode_bytes(data: &[u8]) -> Vec<u8> {
+    let mut out = uint_word(data.len() as u128).to_vec();
+    out.extend_from_slice(data);
+    out.resize(32 + data.len().div_ceil(32) * 32, 0);
+    out
+}
+
+/// 32-byte big-endian word
+pub(crate) fn uint_word(value: u128) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[16..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+/// Decode return data
+///
+/// # Arguments
+/// * `types` - Types of the returned values
+/// * `data` - Return data of `eth_call`
+pub fn decode(types: &[ParamType], data: &[u8]) -> Result<Vec<Token>, ChainError> {
+    decode_tuple(types, data, 0)
+}
+
+fn invalid(what: &str) -> ChainError {
+    ChainError::InvalidResponse(format!("Invalid ABI data: {}", what))
+}
+
+fn word(data: &[u8], offset: usize) -> Result<&[u8; 32], ChainError> {
+    offset
+        .checked_add(32)
+        .and_then(|end| data.get(offset..end))
+        .map(|w| w.try_into().expect("32-byte word"))
+        .ok_or_else(|| invalid("out of bounds"))
+}
+
+fn read_usize(data: &[u8], offset: usize) -> Result<usize, ChainError> {
+    let w = word(data, offset)?;
+    if w[..24].iter().any(|&b| b != 0) {
+        return Err(invalid("offset or length too large"));
+    }
+    Ok(u64::from_be_bytes(w[24..].try_into().unwrap()) as usize)
+}
+
+fn decode_tuple(types: &[ParamType], data: &[u8], base: usize) -> Result<Vec<Token>, ChainError> {
+    let mut offset = base;
+    let mut out = Vec::with_capacity(types.len());
+    for ty in types {
+        if ty.is_dynamic() {
+            let start = base.checked_add(read_usize(data, offset)?).ok_or_else(|| invalid("offset overflow"))?;
+            out.push(decode_one(ty, data, start)?);
+            offset += 32;
+        } else {
+            out.push(decode_one(ty, data, offset)?);
+            offset += ty.head_len();
+        }
+    }
+    Ok(out)
+}
+
+fn decode_one(ty: &ParamType, data: &[u8], offset: usize) -> Result<Token, ChainError> {
+    match ty {
+        ParamType::Address => {
+            let w = word(data, offset)?;
+            Ok(Token::Address(Address(w[12..].try_into().unwrap())))
+        }
+        ParamType::Uint => {
+            let w = word(data, offset)?;
+            if w[..16].iter().any(|&b| b != 0) {
+                return Err(invalid("uint256 exceeds u128"));
+            }
+            Ok(Token::Uint(u128::from_be_bytes(w[16..].try_into().unwrap())))
+        }
+        ParamType::Bool => Ok(Token::Bool(word(data, offset)?[31] != 0)),
+        ParamType::FixedBytes => Ok(Token::FixedBytes(*word(data, offset)?)),
+        ParamType::Bytes | ParamType::String => {
+            let len = read_usize(data, offset)?;
+            let bytes = (offset + 32)
+                .checked_add(len)
+                .and_then(|end| data.get(offset + 32..end))
+                .ok_or_else(|| invalid("bytes out of bounds"))?
+                .to_vec();
+            if *ty == ParamType::Bytes {
+                return Ok(Token::Bytes(bytes));
+            }
+            String::from_utf8(bytes).map(Token::String).map_err(|_| invalid("string is not UTF-8"))
+        }
+        ParamType::Array(item) => {
+            let len = read_usize(data, offset)?;
+            if len > data.len() / 32 {
+                return Err(invalid("array length exceeds data"));
+            }
+            let types = vec![(**item).clone(); len];
+            decode_tuple(&types, data, offset + 32).map(Token::Array)
+        }
+        ParamType::Tuple(items) => decode_tuple(items, data, offset).map(Token::Tuple),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selector() {
+        assert_eq!(hex::encode(selector("transfer(address,uint256)")), "a9059cbb");
+        assert_eq!(hex::encode(selector("balanceOf(address)")), "70a08231");
+    }
+
+    #[test]
+    fn test_encode_dynamic() {
+        // Solidity docs example: f(uint256,uint32[],bytes10,bytes) with (0x123, [0x456, 0x789], "1234567890", "Hello, world!")
+        let mut bytes10 = [0u8; 32];
+        bytes10[..10].copy_from_slice(b"1234567890");
+        let args = [
+            Token::Uint(0x123),
+            Token::Array(vec![Token::Uint(0x456), Token::Uint(0x789)]),
+            Token::FixedBytes(bytes10),
+            Token::Bytes(b"Hello, world!".to_vec()),
+        ];
+        let expected = concat!(
+            "0000000000000000000000000000000000000000000000000000000000000123",
+            "0000000000000000000000000000000000000000000000000000000000000080",
+            "3132333435363738393000000000000000000000000000000000000000000000",
+            "00000000000000000000000000000000000000000000000000000000000000e0",
+            "0000000000000000000000000000000000000000000000000000000000000002",
+            "0000000000000000000000000000000000000000000000000000000000000456",
+            "0000000000000000000000000000000000000000000000000000000000000789",
+            "000000000000000000000000000000000000000000000000000000000000000d",
+            "48656c6c6f2c20776f726c642100000000000000000000000000000000000000",
+        );
+        let encoded = encode(&args);
+        assert_eq!(hex::encode(&encoded), expected);
+
+        let types = [
+            ParamType::Uint,
+            ParamType::Array(Box::new(ParamType::Uint)),
+            ParamType::FixedBytes,
+            ParamType::Bytes,
+        ];
+        assert_eq!(decode(&types, &encoded).unwrap(), args);
+        assert!(decode(&types, &encoded[..100]).is_err());
+    }
+
+    #[test]
+    fn test_tuples() {
+        let owner: Address = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".parse().unwrap();
+        let value = Token::Tuple(vec![
+            Token::FixedBytes([7; 32]),
+            Token::Address(owner),
+            Token::String("ipfs://meta".into()),
+            Token::Bool(true),
+        ]);
+        let types = [ParamType::Tuple(vec![ParamType::FixedBytes, ParamType::Address, ParamType::String, ParamType::Bool])];
+        let encoded = encode(std::slice::from_ref(&value));
+        // Dynamic tuple: offset word first
+        assert_eq!(encoded[31], 0x20);
+        assert_eq!(decode(&types, &encoded).unwrap(), vec![value]);
+
+        let static_pair = [Token::Tuple(vec![Token::Uint(1), Token::Bool(false)]), Token::Uint(2)];
+        let encoded = encode(&static_pair);
+        assert_eq!(encoded.len(), 96);
+        let types = [ParamType::Tuple(vec![ParamType::Uint, ParamType::Bool]), ParamType::Uint];
+        assert_eq!(decode(&types, &encoded).unwrap(), static_pair);
+    }
+}
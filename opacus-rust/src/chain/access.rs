@@ -0,0 +1,229 @@
+//! Token-gated data channels
+//!
+//! A [`HoldingProof`] is an EIP-712 signature over the channel ID, the
+//! subscribing agent's ID and the signing time, in a domain whose verifying
+//! contract is the gating token or allowlist. The publisher recovers the
+//! holding account from it and queries the contract for the account's
+//! holding, so an agent can subscribe with holdings it controls but nobody
+//! can replay another agent's proof.
+
+use crate::subscription::{HoldingProof, SubscribeRequest};
+use crate::types::{AccessRule, DataChannel};
+use super::abi::{ParamType, Token};
+use super::{keccak256, parse_data, recover_typed_data, Address, ChainClient, ChainError, ChainSigner, Eip712Domain, TypedData};
+
+/// EIP-712 domain name of holding proofs
+pub const ACCESS_DOMAIN_NAME: &str = "Opacus Channel Access";
+
+/// EIP-712 domain version of holding proofs
+pub const ACCESS_DOMAIN_VERSION: &str = "1";
+
+/// Signed part of a [`HoldingProof`]
+struct HoldingClaim<'a> {
+    channel_id: &'a str,
+    agent_id: &'a str,
+    issued_at: u64,
+}
+
+impl TypedData for HoldingClaim<'_> {
+    const ENCODED_TYPE: &'static str = "HoldingClaim(string channelId,string agentId,uint256 issuedAt)";
+
+    fn members(&self) -> Vec<Token> {
+        vec![
+            Token::FixedBytes(keccak256(self.channel_id.as_bytes())),
+            Token::FixedBytes(keccak256(self.agent_id.as_bytes())),
+            Token::Uint(self.issued_at.into()),
+        ]
+    }
+}
+
+impl AccessRule {
+    /// Contract the rule is checked against
+    pub fn contract(&self) -> Result<Address, ChainError> {
+        let (AccessRule::Erc20 { token: contract, .. } | AccessRule::Erc721 { token: contract } | AccessRule::Allowlist { contract }) = self;
+        contract.parse().map_err(ChainError::Access)
+    }
+
+    /// Signing domain of holding proofs for this rule
+    pub fn domain(&self, chain_id: u64) -> Result<Eip712Domain, ChainError> {
+        Ok(Eip712Domain::new(ACCESS_DOMAIN_NAME, ACCESS_DOMAIN_VERSION, chain_id, self.contract()?))
+    }
+}
+
+impl HoldingProof {
+    /// Let an agent subscribe to a channel with the signer's holdings
+    ///
+    /// # Arguments
+    /// * `domain` - Domain of the channel's rule ([`AccessRule::domain`])
+    /// * `channel_id` - Gated channel
+    /// * `agent_id` - Subscribing agent
+    /// * `issued_at` - Current time (Unix seconds)
+    /// * `signer` - Holding account's key
+    pub fn sign(
+        domain: &Eip712Domain,
+        channel_id: &str,
+        agent_id: &str,
+        issued_at: u64,
+        signer: &ChainSigner,
+    ) -> Result<Self, ChainError> {
+        let signature = signer.sign_typed_data(domain, &HoldingClaim { channel_id, agent_id, issued_at })?;
+        Ok(Self {
+            account: signer.address().to_string(),
+            issued_at,
+            signature: format!("0x{}", hex::encode(signature)),
+        })
+    }
+
+    /// Account that signed the proof for `agent_id` on `channel_id`
+    ///
+    /// # Returns
+    /// `Err` if the signature is malformed or was not made by the claimed account
+    pub fn signer(&self, domain: &Eip712Domain, channel_id: &str, agent_id: &str) -> Result<Address, ChainError> {
+        let account: Address = self.account.parse().map_err(ChainError::Access)?;
+        let signature: [u8; 65] = parse_data(&self.signature)
+            .ok()
+            .and_then(|s| s.try_into().ok())
+            .ok_or_else(|| ChainError::Access("Malformed proof signature".into()))?;
+        let claim = HoldingClaim { channel_id, agent_id, issued_at: self.issued_at };
+        let signer = recover_typed_data(domain, &claim, &signature)?;
+        if signer != account {
+            return Err(ChainError::Access(format!("Proof for {} was signed by {}", account, signer)));
+        }
+        Ok(account)
+    }
+}
+
+impl ChainClient {
+    /// Whether an account meets an access rule
+    pub async fn holds(&self, rule: &AccessRule, account: Address) -> Result<bool, ChainError> {
+        let (signature, output) = match rule {
+            AccessRule::Erc20 { .. } | AccessRule::Erc721 { .. } => ("balanceOf(address)", ParamType::Uint),
+            AccessRule::Allowlist { .. } => ("isAllowed(address)", ParamType::Bool),
+        };
+        let value = self
+            .call(rule.contract()?, signature, &[Token::Address(account)], &[output])
+            .await?
+            .into_iter()
+            .next();
+        Ok(match (rule, value) {
+            (AccessRule::Erc20 { min_balance, .. }, Some(Token::Uint(balance))) => balance >= *min_balance,
+            (AccessRule::Erc721 { .. }, Some(Token::Uint(balance))) => balance > 0,
+            (AccessRule::Allowlist { .. }, Some(Token::Bool(allowed))) => allowed,
+            _ => false,
+        })
+    }
+
+    /// Check a subscribe request against a channel's access rule
+    ///
+    /// # Arguments
+    /// * `channel` - Requested channel
+    /// * `request` - Subscriber's request
+    /// * `agent_id` - Subscribing agent (the `Subscribe` frame's sender)
+    /// * `now` - Current time (Unix seconds)
+    ///
+    /// # Returns
+    /// Holding account, or `None` for open channels; `Err(ChainError::Access)`
+    /// if the proof is missing, stale, not signed by its account, or the
+    /// account does not hold what the rule requires
+    pub async fn check_subscription(
+        &self,
+        channel: &DataChannel,
+        request: &SubscribeRequest,
+        agent_id: &str,
+        now: u64,
+    ) -> Result<Option<Address>, ChainError> {
+        let Some(rule) = &channel.access else {
+            return Ok(None);
+        };
+        if request.channel_id != channel.id {
+            return Err(ChainError::Access(format!("Request is for channel {}", request.channel_id)));
+        }
+        let proof = request.proof.as_ref().ok_or_else(|| ChainError::Access("Missing holding proof".into()))?;
+        if !proof.is_fresh(now) {
+            return Err(ChainError::Access("Holding proof expired".into()));
+        }
+        let account = proof.signer(&rule.domain(self.chain_id())?, &channel.id, agent_id)?;
+        if !self.holds(rule, account).await? {
+            return Err(ChainError::Access(format!("{} does not hold the required tokens", account)));
+        }
+        Ok(Some(account))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use crate::chain::abi::{encode, selector};
+    use crate::chain::mock;
+    use crate::types::ChannelType;
+
+    const KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+    const TOKEN: &str = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+
+    fn channel(access: AccessRule) -> DataChannel {
+        DataChannel {
+            id: "signals".into(),
+            channel_type: ChannelType::Output,
+            price_per_byte: 0,
+            price_per_msg: 0,
+            access: Some(access),
+            plan: None,
+        }
+    }
+
+    /// Token where only the test key's account holds 100 units and is allowlisted
+    fn handle(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        assert_eq!(method, "eth_call");
+        let data = parse_data(params[0]["data"].as_str().unwrap()).unwrap();
+        let holder = ChainSigner::from_hex(KEY).unwrap().address();
+        let is_holder = data[16..36] == holder.0;
+        let result = if data[..4] == selector("isAllowed(address)") {
+            Token::Bool(is_holder)
+        } else {
+            Token::Uint(if is_holder { 100 } else { 0 })
+        };
+        Ok(json!(format!("0x{}", hex::encode(encode(&[result])))))
+    }
+
+    #[tokio::test]
+    async fn test_check_subscription() {
+        let url = mock::serve(handle).await;
+        let chain = ChainClient::new(&url, 16661).unwrap();
+        let holder = ChainSigner::from_hex(KEY).unwrap();
+        let outsider = ChainSigner::random();
+
+        let erc20 = channel(AccessRule::Erc20 { token: TOKEN.into(), min_balance: 50 });
+        let domain = erc20.access.as_ref().unwrap().domain(16661).unwrap();
+        let request = |signer: &ChainSigner, agent_id: &str, issued_at: u64| SubscribeRequest {
+            channel_id: "signals".into(),
+            proof: Some(HoldingProof::sign(&domain, "signals", agent_id, issued_at, signer).unwrap()),
+            authorization: None,
+            since: None,
+        };
+        let accepted = chain.check_subscription(&erc20, &request(&holder, "agent-a", 1_000), "agent-a", 1_010).await.unwrap();
+        assert_eq!(accepted, Some(holder.address()));
+
+        let denied = |result: Result<Option<Address>, ChainError>| matches!(result, Err(ChainError::Access(_)));
+        // Not enough tokens, proof for another agent, stale proof, no proof
+        assert!(denied(chain.check_subscription(&erc20, &request(&outsider, "agent-a", 1_000), "agent-a", 1_010).await));
+        assert!(denied(chain.check_subscription(&erc20, &request(&holder, "agent-b", 1_000), "agent-a", 1_010).await));
+        assert!(denied(chain.check_subscription(&erc20, &request(&holder, "agent-a", 1_000), "agent-a", 2_000).await));
+        let unproven = SubscribeRequest { channel_id: "signals".into(), proof: None, authorization: None, since: None };
+        assert!(denied(chain.check_subscription(&erc20, &unproven, "agent-a", 1_010).await));
+
+        // Claiming someone else's account
+        let mut borrowed = request(&outsider, "agent-a", 1_000);
+        borrowed.proof.as_mut().unwrap().account = holder.address().to_string();
+        assert!(denied(chain.check_subscription(&erc20, &borrowed, "agent-a", 1_010).await));
+
+        let strict = channel(AccessRule::Erc20 { token: TOKEN.into(), min_balance: 101 });
+        assert!(denied(chain.check_subscription(&strict, &request(&holder, "agent-a", 1_000), "agent-a", 1_010).await));
+        assert_eq!(chain.check_subscription(&DataChannel { access: None, ..erc20 }, &unproven, "agent-a", 0).await.unwrap(), None);
+
+        assert!(chain.holds(&AccessRule::Erc721 { token: TOKEN.into() }, holder.address()).await.unwrap());
+        assert!(!chain.holds(&AccessRule::Erc721 { token: TOKEN.into() }, outsider.address()).await.unwrap());
+        assert!(chain.holds(&AccessRule::Allowlist { contract: TOKEN.into() }, holder.address()).await.unwrap());
+        assert!(!chain.holds(&AccessRule::Allowlist { contract: TOKEN.into() }, outsider.address()).await.unwrap());
+    }
+}
@@ -0,0 +1,563 @@
+//! Agent registry: chain-rooted agent keys
+//!
+//! The `AgentRegistry` contract stores the Keccak-256 hashes of each agent's
+//! Ed25519 and X25519 keys, its owner and whether it is still active. The
+//! keys themselves are published in a profile document in a
+//! [`ContentStore`]. A key is trusted when its hash matches the registry
+//! entry of an active agent, so peers can be authenticated without trusting
+//! the first key they present. The document can also carry the agent's
+//! [`SignedProfile`], for discovery without a relay.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+use crate::crypto::KeyManager;
+use crate::profile::SignedProfile;
+use crate::types::AgentIdentity;
+use super::abi::{event_topic, ParamType, Token};
+use super::{hex_bytes, keccak256, Address, ChainClient, ChainError, ContentStore, LogFilter, TransactionReceipt};
+
+/// Event emitted for each new agent
+const AGENT_REGISTERED: &str = "AgentRegistered(bytes32,address,bytes32)";
+
+/// How long resolved keys are used before the registry is checked again
+pub const KEY_CACHE_TTL: Duration = Duration::from_secs(600);
+
+/// Hash of a public key as stored in the registry
+pub fn key_hash(key: &[u8; 32]) -> [u8; 32] {
+    keccak256(key)
+}
+
+/// Registry record of an agent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgentRecord {
+    /// Registry ID, assigned at registration
+    pub id: [u8; 32],
+    /// Hash of the Ed25519 public key
+    pub ed_pub_hash: [u8; 32],
+    /// Hash of the X25519 public key
+    pub x_pub_hash: [u8; 32],
+    /// Owning account
+    pub owner: Address,
+    /// URI of the profile document
+    pub metadata_uri: String,
+    /// Registration time (Unix seconds)
+    pub registered: u64,
+    /// Last key rotation (Unix seconds)
+    pub last_key_rotation: u64,
+    /// Cleared when the owner deactivates the agent
+    pub active: bool,
+    /// Reputation score
+    pub reputation: u128,
+    /// Stake in wei
+    pub stake: u128,
+}
+
+impl AgentRecord {
+    /// Check that the agent is active and registered with these keys
+    pub fn check_keys(&self, ed_pub: &[u8; 32], x_pub: &[u8; 32]) -> Result<(), ChainError> {
+        if !self.active {
+            return Err(ChainError::Identity(format!("Agent 0x{} is deactivated", hex::encode(self.id))));
+        }
+        if self.ed_pub_hash != key_hash(ed_pub) || self.x_pub_hash != key_hash(x_pub) {
+            return Err(ChainError::Identity(format!("Keys not registered for agent 0x{}", hex::encode(self.id))));
+        }
+        Ok(())
+    }
+}
+
+/// Profile document published with a registration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentProfile {
+    /// Agent ID used in frames
+    pub agent_id: String,
+    /// Ed25519 public key
+    #[serde(with = "hex_bytes")]
+    pub ed_pub: [u8; 32],
+    /// X25519 public key
+    #[serde(with = "hex_bytes")]
+    pub x_pub: [u8; 32],
+    /// Metadata profile at registration
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<SignedProfile>,
+}
+
+/// Keys of an agent, checked against the registry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgentKeys {
+    /// Agent ID used in frames
+    pub agent_id: String,
+    /// Registry ID
+    pub registry_id: [u8; 32],
+    /// Ed25519 public key
+    pub ed_pub: [u8; 32],
+    /// X25519 public key
+    pub x_pub: [u8; 32],
+    /// Owning account
+    pub owner: Address,
+}
+
+/// Client for the `AgentRegistry` contract
+#[derive(Debug)]
+pub struct AgentRegistry<S> {
+    chain: Arc<ChainClient>,
+    contract: Address,
+    store: S,
+}
+
+impl<S: ContentStore> AgentRegistry<S> {
+    /// Create registry client
+    ///
+    /// # Arguments
+    /// * `chain` - Chain client; registration needs a signer
+    /// * `contract` - `AgentRegistry` address
+    /// * `store` - Store for profile documents
+    pub fn new(chain: Arc<ChainClient>, contract: Address, store: S) -> Self {
+        Self { chain, contract, store }
+    }
+
+    /// Register an agent's keys, owned by the signer's account
+    ///
+    /// # Arguments
+    /// * `identity` - Agent to register
+    /// * `stake` - Stake in wei (at least the registry's `minStake`)
+    ///
+    /// # Returns
+    /// Assigned registry ID
+    pub async fn register(&self, identity: &AgentIdentity, stake: u128) -> Result<[u8; 32], ChainError> {
+        self.register_with_profile(identity, None, stake).await
+    }
+
+    /// Register an agent's keys along with its metadata profile
+    ///
+    /// # Arguments
+    /// * `identity` - Agent to register
+    /// * `metadata` - Profile signed by the agent
+    /// * `stake` - Stake in wei (at least the registry's `minStake`)
+    ///
+    /// # Returns
+    /// Assigned registry ID
+    pub async fn register_with_profile(
+        &self,
+        identity: &AgentIdentity,
+        metadata: Option<&SignedProfile>,
+        stake: u128,
+    ) -> Result<[u8; 32], ChainError> {
+        let profile = AgentProfile {
+            agent_id: identity.id.clone(),
+            ed_pub: identity.ed_pub,
+            x_pub: identity.x_pub,
+            metadata: metadata.cloned(),
+        };
+        let document = serde_json::to_vec(&profile).map_err(|e| ChainError::Storage(e.to_string()))?;
+        let uri = self.store.put(document).await?;
+        let args = [
+            Token::FixedBytes(key_hash(&identity.ed_pub)),
+            Token::FixedBytes(key_hash(&identity.x_pub)),
+            Token::String(uri),
+        ];
+        let receipt = self.chain.transact(self.contract, "registerAgent(bytes32,bytes32,string)", &args, stake).await?;
+        let topic = event_topic(AGENT_REGISTERED);
+        receipt
+            .logs
+            .iter()
+            .find(|log| log.address == self.contract && log.topics.first() == Some(&topic))
+            .and_then(|log| log.topics.get(1).copied())
+            .ok_or_else(|| ChainError::InvalidResponse("No AgentRegistered event in receipt".into()))
+    }
+
+    /// Deactivate an agent (owner only); its keys are no longer trusted
+    pub async fn deactivate(&self, id: [u8; 32]) -> Result<TransactionReceipt, ChainError> {
+        self.chain.transact(self.contract, "deactivateAgent(bytes32)", &[Token::FixedBytes(id)], 0).await
+    }
+
+    /// Registry record of an agent (`None` if unknown)
+    pub async fn record(&self, id: [u8; 32]) -> Result<Option<AgentRecord>, ChainError> {
+        let agent = ParamType::Tuple(vec![
+            ParamType::FixedBytes,
+            ParamType::FixedBytes,
+            ParamType::FixedBytes,
+            ParamType::Address,
+            ParamType::String,
+            ParamType::Uint,
+            ParamType::Uint,
+            ParamType::Bool,
+            ParamType::Uint,
+            ParamType::Uint,
+        ]);
+        let values = self.chain.call(self.contract, "getAgent(bytes32)", &[Token::FixedBytes(id)], &[agent]).await?;
+        let mut fields = values.into_iter().next().and_then(Token::into_items).unwrap_or_default().into_iter();
+        let mut next = || fields.next().ok_or_else(|| ChainError::InvalidResponse("Short agent record".into()));
+        next()?; // ID, as requested
+        let record = AgentRecord {
+            id,
+            ed_pub_hash: next()?.into_fixed_bytes().unwrap_or_default(),
+            x_pub_hash: next()?.into_fixed_bytes().unwrap_or_default(),
+            owner: next()?.into_address().unwrap_or_default(),
+            metadata_uri: next()?.into_string().unwrap_or_default(),
+            registered: next()?.into_uint().unwrap_or_default() as u64,
+            last_key_rotation: next()?.into_uint().unwrap_or_default() as u64,
+            active: next()?.into_bool().unwrap_or_default(),
+            reputation: next()?.into_uint().unwrap_or_default(),
+            stake: next()?.into_uint().unwrap_or_default(),
+        };
+        Ok((record.registered > 0).then_some(record))
+    }
+
+    /// Registry record of the agent with an Ed25519 key (`None` if unregistered)
+    pub async fn record_by_key(&self, ed_pub: &[u8; 32]) -> Result<Option<AgentRecord>, ChainError> {
+        let args = [Token::FixedBytes(key_hash(ed_pub))];
+        let values = self.chain.call(self.contract, "pubKeyToAgent(bytes32)", &args, &[ParamType::FixedBytes]).await?;
+        match values.into_iter().next().and_then(Token::into_fixed_bytes) {
+            Some(id) if id != [0; 32] => self.record(id).await,
+            _ => Ok(None),
+        }
+    }
+
+    /// Agents registered by an account
+    ///
+    /// # Arguments
+    /// * `owner` - Owning account
+    /// * `from_block` - First block to search for registrations
+    pub async fn owned_by(&self, owner: &Address, from_block: u64) -> Result<Vec<[u8; 32]>, ChainError> {
+        let mut owner_topic = [0u8; 32];
+        owner_topic[12..].copy_from_slice(&owner.0);
+        let filter = LogFilter {
+            address: Some(self.contract),
+            topics: vec![Some(event_topic(AGENT_REGISTERED)), None, Some(owner_topic)],
+            from_block,
+            to_block: None,
+        };
+        let mut seen = HashSet::new();
+        Ok(self
+            .chain
+            .rpc()
+            .logs(&filter)
+            .await?
+            .into_iter()
+            .filter_map(|log| log.topics.get(1).copied())
+            .filter(|id| seen.insert(*id))
+            .collect())
+    }
+
+    /// Resolve an active agent's keys from its profile
+    ///
+    /// # Returns
+    /// Keys whose hashes match the registry; an `Identity` error if the agent
+    /// is deactivated or the profile does not match
+    pub async fn resolve(&self, id: [u8; 32]) -> Result<AgentKeys, ChainError> {
+        let (record, profile) = self.document(id).await?;
+        Ok(AgentKeys {
+            agent_id: profile.agent_id,
+            registry_id: id,
+            ed_pub: profile.ed_pub,
+            x_pub: profile.x_pub,
+            owner: record.owner,
+        })
+    }
+
+    /// Metadata profile an active agent registered with
+    ///
+    /// # Returns
+    /// `None` if it registered without one; an `Identity` error if the agent
+    /// is deactivated or the profile is not signed by its registered key
+    pub async fn profile(&self, id: [u8; 32]) -> Result<Option<SignedProfile>, ChainError> {
+        let (_, profile) = self.document(id).await?;
+        let Some(metadata) = profile.metadata else {
+            return Ok(None);
+        };
+        if metadata.agent_id != profile.agent_id || metadata.ed_pub != profile.ed_pub {
+            return Err(ChainError::Identity(format!("Metadata of 0x{} belongs to another agent", hex::encode(id))));
+        }
+        metadata.verify().map_err(ChainError::Identity)?;
+        Ok(Some(metadata))
+    }
+
+    /// Registry record and profile document of an active agent, checked against each other
+    async fn document(&self, id: [u8; 32]) -> Result<(AgentRecord, AgentProfile), ChainError> {
+        let record = self
+            .record(id)
+            .await?
+            .ok_or_else(|| ChainError::Identity(format!("Agent 0x{} is not registered", hex::encode(id))))?;
+        let document = self.store.get(&record.metadata_uri).await?;
+        let profile: AgentProfile = serde_json::from_slice(&document)
+            .map_err(|e| ChainError::Storage(format!("Invalid agent profile at {}: {}", record.metadata_uri, e)))?;
+        record.check_keys(&profile.ed_pub, &profile.x_pub)?;
+        if KeyManager::agent_id(&profile.ed_pub) != profile.agent_id {
+            return Err(ChainError::Identity(format!("Profile of 0x{} has a foreign agent ID", hex::encode(id))));
+        }
+        Ok((record, profile))
+    }
+
+    /// Check keys presented by a peer (e.g. in a prekey bundle) against the registry
+    ///
+    /// # Returns
+    /// The keys with their registry entry; an `Identity` error if they are
+    /// not registered to an active agent
+    pub async fn verify_keys(&self, ed_pub: &[u8; 32], x_pub: &[u8; 32]) -> Result<AgentKeys, ChainError> {
+        let agent_id = KeyManager::agent_id(ed_pub);
+        let record = self
+            .record_by_key(ed_pub)
+            .await?
+            .ok_or_else(|| ChainError::Identity(format!("Keys of {} are not registered", agent_id)))?;
+        record.check_keys(ed_pub, x_pub)?;
+        Ok(AgentKeys { agent_id, registry_id: record.id, ed_pub: *ed_pub, x_pub: *x_pub, owner: record.owner })
+    }
+}
+
+/// Registry-backed key lookups with caching
+///
+/// Resolved keys are reused for `ttl`; after that the registry entry is
+/// read again, and keys that were rotated or deactivated are dropped.
+#[derive(Debug)]
+pub struct KeyResolver<S> {
+    registry: AgentRegistry<S>,
+    ttl: Duration,
+    /// Keys and when they were last checked, by agent ID
+    cache: DashMap<String, (AgentKeys, Instant)>,
+}
+
+impl<S: ContentStore> KeyResolver<S> {
+    /// Create resolver
+    ///
+    /// # Arguments
+    /// * `registry` - Registry to resolve from
+    /// * `ttl` - How long keys are used before being checked again
+    pub fn new(registry: AgentRegistry<S>, ttl: Duration) -> Self {
+        Self { registry, ttl, cache: DashMap::new() }
+    }
+
+    /// Underlying registry client
+    pub fn registry(&self) -> &AgentRegistry<S> {
+        &self.registry
+    }
+
+    /// Resolve an agent by registry ID, bypassing the cache
+    pub async fn resolve(&self, registry_id: [u8; 32]) -> Result<AgentKeys, ChainError> {
+        let keys = self.registry.resolve(registry_id).await?;
+        self.cache.insert(keys.agent_id.clone(), (keys.clone(), Instant::now()));
+        Ok(keys)
+    }
+
+    /// Keys of a previously resolved or verified agent
+    ///
+    /// # Returns
+    /// `None` if the agent was never resolved; an `Identity` error if its
+    /// keys have been rotated or it was deactivated since
+    pub async fn keys(&self, agent_id: &str) -> Result<Option<AgentKeys>, ChainError> {
+        let Some((keys, checked)) = self.cache.get(agent_id).map(|entry| entry.clone()) else {
+            return Ok(None);
+        };
+        if checked.elapsed() < self.ttl {
+            return Ok(Some(keys));
+        }
+        self.revalidate(keys).await.map(Some)
+    }
+
+    /// Check keys presented by a peer, using the cache while it is fresh
+    pub async fn verify(&self, ed_pub: &[u8; 32], x_pub: &[u8; 32]) -> Result<AgentKeys, ChainError> {
+        let agent_id = KeyManager::agent_id(ed_pub);
+        if let Some(keys) = self.keys(&agent_id).await? {
+            if keys.ed_pub == *ed_pub && keys.x_pub == *x_pub {
+                return Ok(keys);
+            }
+        }
+        let keys = self.registry.verify_keys(ed_pub, x_pub).await?;
+        self.cache.insert(agent_id, (keys.clone(), Instant::now()));
+        Ok(keys)
+    }
+
+    /// Forget cached keys of an agent
+    pub fn invalidate(&self, agent_id: &str) {
+        self.cache.remove(agent_id);
+    }
+
+    async fn revalidate(&self, keys: AgentKeys) -> Result<AgentKeys, ChainError> {
+        let checked = match self.registry.record(keys.registry_id).await? {
+            Some(record) => record.check_keys(&keys.ed_pub, &keys.x_pub),
+            None => Err(ChainError::Identity(format!("Agent 0x{} is not registered", hex::encode(keys.registry_id)))),
+        };
+        if let Err(e) = checked {
+            debug!("Revoked cached keys of {}: {}", keys.agent_id, e);
+            self.cache.remove(&keys.agent_id);
+            return Err(e);
+        }
+        self.cache.insert(keys.agent_id.clone(), (keys.clone(), Instant::now()));
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use serde_json::{json, Value};
+    use crate::chain::abi::{self, encode, selector};
+    use crate::chain::storage::MemoryStore;
+    use crate::chain::{mock, parse_data, ChainSigner};
+    use crate::profile::Profile;
+
+    const KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    fn registry() -> Address {
+        "0xD7f91B117918f3968C715A9440123b9B6eD83500".parse().unwrap()
+    }
+
+    /// Registry contract backed by a list of `(ed hash, x hash, uri, active)` agents
+    #[derive(Default)]
+    struct Contract {
+        agents: Vec<([u8; 32], [u8; 32], String, bool)>,
+    }
+
+    fn agent_id(index: usize) -> [u8; 32] {
+        keccak256(&index.to_be_bytes())
+    }
+
+    fn hex_data(data: &[u8]) -> Value {
+        json!(format!("0x{}", hex::encode(data)))
+    }
+
+    fn handle(contract: &Mutex<Contract>, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        let owner = ChainSigner::from_hex(KEY).unwrap().address();
+        let mut owner_topic = [0u8; 32];
+        owner_topic[12..].copy_from_slice(&owner.0);
+        let registered = |i: usize| {
+            json!({
+                "address": registry(),
+                "topics": [hex_data(&event_topic(AGENT_REGISTERED)), hex_data(&agent_id(i)), hex_data(&owner_topic)],
+                "data": "0x",
+                "blockNumber": "0x11",
+                "transactionHash": hex_data(&[i as u8; 32]),
+            })
+        };
+        let mut contract = contract.lock().unwrap();
+        match method {
+            "eth_getTransactionCount" => Ok(json!("0x0")),
+            "eth_getBlockByNumber" => Ok(json!({ "number": "0x10", "baseFeePerGas": "0x1" })),
+            "eth_maxPriorityFeePerGas" => Ok(json!("0x1")),
+            "eth_estimateGas" => Ok(json!("0x186a0")),
+            "eth_call" => {
+                let data = parse_data(params[0]["data"].as_str().unwrap()).unwrap();
+                if data[..4] == selector("pubKeyToAgent(bytes32)") {
+                    let index = contract.agents.iter().position(|a| data[4..] == a.0);
+                    return Ok(hex_data(&index.map(agent_id).unwrap_or_default()));
+                }
+                let index = (0..contract.agents.len()).find(|&i| data[4..] == agent_id(i));
+                let agent = match index {
+                    Some(i) => {
+                        let (ed, x, uri, active) = contract.agents[i].clone();
+                        (agent_id(i), ed, x, uri, active, 1_700_000_000)
+                    }
+                    None => ([0; 32], [0; 32], [0; 32], String::new(), false, 0),
+                };
+                Ok(hex_data(&encode(&[Token::Tuple(vec![
+                    Token::FixedBytes(agent.0),
+                    Token::FixedBytes(agent.1),
+                    Token::FixedBytes(agent.2),
+                    Token::Address(owner),
+                    Token::String(agent.3),
+                    Token::Uint(agent.5),
+                    Token::Uint(agent.5),
+                    Token::Bool(agent.4),
+                    Token::Uint(100),
+                    Token::Uint(10),
+                ])])))
+            }
+            "eth_sendRawTransaction" => {
+                let raw = parse_data(params[0].as_str().unwrap()).unwrap();
+                let call = |signature: &str| raw.windows(4).position(|w| w == selector(signature));
+                if let Some(at) = call("registerAgent(bytes32,bytes32,string)") {
+                    let types = [ParamType::FixedBytes, ParamType::FixedBytes, ParamType::String];
+                    let args = abi::decode(&types, &raw[at + 4..]).unwrap();
+                    contract.agents.push((
+                        args[0].clone().into_fixed_bytes().unwrap(),
+                        args[1].clone().into_fixed_bytes().unwrap(),
+                        args[2].clone().into_string().unwrap(),
+                        true,
+                    ));
+                } else if let Some(at) = call("deactivateAgent(bytes32)") {
+                    let i = (0..contract.agents.len()).find(|&i| raw[at + 4..at + 36] == agent_id(i));
+                    contract.agents[i.unwrap()].3 = false;
+                }
+                Ok(hex_data(&keccak256(&raw)))
+            }
+            "eth_getTransactionReceipt" => Ok(json!({
+                "transactionHash": params[0],
+                "blockNumber": "0x11",
+                "gasUsed": "0x186a0",
+                "status": "0x1",
+                "contractAddress": Value::Null,
+                "logs": [registered(contract.agents.len() - 1)],
+            })),
+            "eth_getLogs" => {
+                assert_eq!(params[0]["topics"][2], hex_data(&owner_topic));
+                Ok(json!((0..contract.agents.len()).map(registered).collect::<Vec<_>>()))
+            }
+            _ => Err((-32601, format!("method {} not found", method))),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_and_verify_keys() {
+        let contract = Arc::new(Mutex::new(Contract::default()));
+        let state = contract.clone();
+        let url = mock::serve(move |method, params| handle(&state, method, params)).await;
+        let signer = ChainSigner::from_hex(KEY).unwrap();
+        let chain = Arc::new(ChainClient::new(&url, 16661).unwrap().with_signer(signer.clone()));
+        let registry = AgentRegistry::new(chain, registry(), MemoryStore::default());
+
+        let alice = KeyManager::generate_identity(16661);
+        let id = registry.register(&alice, 10).await.unwrap();
+        assert_eq!(id, agent_id(0));
+        assert_eq!(registry.owned_by(&signer.address(), 0).await.unwrap(), vec![id]);
+
+        let keys = registry.resolve(id).await.unwrap();
+        assert_eq!((keys.agent_id.as_str(), keys.ed_pub, keys.x_pub), (alice.id.as_str(), alice.ed_pub, alice.x_pub));
+        assert_eq!(registry.profile(id).await.unwrap(), None);
+        assert_eq!(keys.owner, signer.address());
+        assert_eq!(registry.verify_keys(&alice.ed_pub, &alice.x_pub).await.unwrap(), keys);
+        assert!(matches!(registry.resolve(agent_id(5)).await, Err(ChainError::Identity(_))));
+
+        // Presented keys must match the registration
+        let mallory = KeyManager::generate_identity(16661);
+        assert!(matches!(registry.verify_keys(&mallory.ed_pub, &mallory.x_pub).await, Err(ChainError::Identity(_))));
+        assert!(registry.verify_keys(&alice.ed_pub, &mallory.x_pub).await.is_err());
+
+        // Cached keys are rechecked once stale
+        let resolver = KeyResolver::new(registry, Duration::ZERO);
+        assert_eq!(resolver.keys(&alice.id).await.unwrap(), None);
+        resolver.resolve(id).await.unwrap();
+        assert_eq!(resolver.keys(&alice.id).await.unwrap(), Some(keys.clone()));
+        assert_eq!(resolver.verify(&alice.ed_pub, &alice.x_pub).await.unwrap(), keys);
+
+        resolver.registry().deactivate(id).await.unwrap();
+        assert!(matches!(resolver.keys(&alice.id).await, Err(ChainError::Identity(_))));
+        assert_eq!(resolver.keys(&alice.id).await.unwrap(), None);
+        assert!(resolver.verify(&alice.ed_pub, &alice.x_pub).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_registered_profile() {
+        let contract = Arc::new(Mutex::new(Contract::default()));
+        let state = contract.clone();
+        let url = mock::serve(move |method, params| handle(&state, method, params)).await;
+        let chain = Arc::new(ChainClient::new(&url, 16661).unwrap().with_signer(ChainSigner::from_hex(KEY).unwrap()));
+        let registry = AgentRegistry::new(chain, registry(), MemoryStore::default());
+
+        let alice = KeyManager::generate_identity(16661);
+        let profile = Profile { name: "Alice".into(), tags: vec!["nlp".into()], ..Default::default() };
+        let signed = SignedProfile::sign(&alice, profile.clone(), 1_700_000_000_000);
+        let id = registry.register_with_profile(&alice, Some(&signed), 10).await.unwrap();
+        assert_eq!(registry.profile(id).await.unwrap(), Some(signed));
+
+        // Another agent's profile is not accepted as the registrant's
+        let bob = KeyManager::generate_identity(16661);
+        let foreign = SignedProfile::sign(&bob, profile, 1_700_000_000_000);
+        let id = registry.register_with_profile(&alice, Some(&foreign), 10).await.unwrap();
+        assert!(matches!(registry.profile(id).await, Err(ChainError::Identity(_))));
+    }
+}
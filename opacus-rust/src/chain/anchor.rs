@@ -0,0 +1,449 @@
+//! Merkle-root anchoring of exchanged messages
+//!
+//! A [`MessageAnchor`] collects the hashes of sent and received frames.
+//! Each anchoring round seals the collected hashes into a batch and posts
+//! the batch's Merkle root to an anchor contract exposing
+//!
+//! ```solidity
+//! function anchor(bytes32 root, uint256 count) external;
+//! function anchoredAt(bytes32 root) external view returns (uint256);
+//! ```
+//!
+//! where `anchoredAt` is the block timestamp of the anchoring, or zero.
+//! [`MessageAnchor::prove`] then gives a [`MessageProof`] that a frame was in
+//! an anchored batch.
+//!
+//! The tree follows OpenZeppelin's `MerkleProof`: leaves are
+//! `keccak256(message_hash)`, inner nodes hash their children in sorted
+//! order, and a node without a sibling moves up unchanged. Proofs can
+//! therefore also be checked by a contract.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+use crate::proto::CBORCodec;
+use crate::types::{FrameType, OpacusFrame, Ulid};
+use super::abi::{ParamType, Token};
+use super::{hex_bytes, keccak256, ChainClient, ChainError, Address, TransactionReceipt, TxHash};
+
+/// Anchoring function of the anchor contract
+pub const ANCHOR_SIGNATURE: &str = "anchor(bytes32,uint256)";
+
+/// Anchoring time lookup of the anchor contract
+pub const ANCHORED_AT_SIGNATURE: &str = "anchoredAt(bytes32)";
+
+/// Hash identifying a frame (Keccak-256 of its canonical CBOR encoding)
+pub fn message_hash(frame: &OpacusFrame) -> Result<[u8; 32], String> {
+    let encoded = CBORCodec::encode_canonical(frame).map_err(|e| e.to_string())?;
+    Ok(keccak256(&encoded))
+}
+
+fn hash_pair(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let (low, high) = if a <= b { (a, b) } else { (b, a) };
+    let mut data = [0u8; 64];
+    data[..32].copy_from_slice(low);
+    data[32..].copy_from_slice(high);
+    keccak256(&data)
+}
+
+/// Next level of a Merkle tree
+fn parent_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [a, b] => hash_pair(a, b),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+/// Merkle root of message hashes (zero for none)
+pub fn merkle_root(hashes: &[[u8; 32]]) -> [u8; 32] {
+    let mut level: Vec<[u8; 32]> = hashes.iter().map(|h| keccak256(h)).collect();
+    while level.len() > 1 {
+        level = parent_level(&level);
+    }
+    level.first().copied().unwrap_or_default()
+}
+
+/// Sibling hashes from the leaf at `index` up to the root
+fn merkle_path(hashes: &[[u8; 32]], mut index: usize) -> Vec<[u8; 32]> {
+    let mut level: Vec<[u8; 32]> = hashes.iter().map(|h| keccak256(h)).collect();
+    let mut path = Vec::new();
+    while level.len() > 1 {
+        if let Some(sibling) = level.get(index ^ 1) {
+            path.push(*sibling);
+        }
+        level = parent_level(&level);
+        index /= 2;
+    }
+    path
+}
+
+/// Transaction that anchored a batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnchorTx {
+    /// Anchoring transaction
+    #[serde(with = "hex_bytes")]
+    pub tx_hash: TxHash,
+    /// Block containing the transaction
+    pub block_number: u64,
+}
+
+/// Sealed set of message hashes
+#[derive(Debug, Clone)]
+pub struct AnchorBatch {
+    /// Merkle root of the hashes
+    pub root: [u8; 32],
+    /// Message hashes, in the order they were recorded
+    pub hashes: Vec<[u8; 32]>,
+    /// Anchoring transaction, once posted
+    pub anchor: Option<AnchorTx>,
+}
+
+/// Proof that a message is in an anchored batch
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageProof {
+    /// Message ID
+    pub message_id: Ulid,
+    /// Hash of the frame ([`message_hash`])
+    #[serde(with = "hex_bytes")]
+    pub message_hash: [u8; 32],
+    /// Sibling hashes from the leaf up
+    pub path: Vec<MerkleNode>,
+    /// Batch root posted on chain
+    #[serde(with = "hex_bytes")]
+    pub root: [u8; 32],
+    /// Anchoring transaction (`None` while the batch is not yet anchored)
+    pub anchor: Option<AnchorTx>,
+}
+
+/// Hash in a Merkle path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct MerkleNode(#[serde(with = "hex_bytes")] pub [u8; 32]);
+
+impl MessageProof {
+    /// Root the path leads to from the message hash
+    pub fn computed_root(&self) -> [u8; 32] {
+        self.path.iter().fold(keccak256(&self.message_hash), |node, sibling| hash_pair(&node, &sibling.0))
+    }
+
+    /// Check that `frame` is the proven message and the path leads to `root`
+    pub fn verify(&self, frame: &OpacusFrame) -> bool {
+        frame.id == Some(self.message_id)
+            && message_hash(frame).is_ok_and(|hash| hash == self.message_hash)
+            && self.computed_root() == self.root
+    }
+}
+
+/// Message hashes collected for anchoring
+///
+/// Keeps every batch so that proofs can be produced later.
+#[derive(Debug, Default)]
+pub struct MessageAnchor {
+    pending: Vec<(Ulid, [u8; 32])>,
+    batches: Vec<AnchorBatch>,
+    /// Batch and leaf index of sealed messages
+    index: HashMap<Ulid, (usize, usize)>,
+}
+
+impl MessageAnchor {
+    /// Create empty anchor
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a sent or received frame
+    ///
+    /// `Batch` frames are recorded along with their entries. Frames without
+    /// a message ID and IDs recorded before are skipped.
+    ///
+    /// # Returns
+    /// Number of messages recorded
+    pub fn record(&mut self, frame: &OpacusFrame) -> usize {
+        let mut recorded = 0;
+        if frame.frame_type == FrameType::Batch {
+            if let Ok(entries) = frame.batch_entries() {
+                recorded += entries.iter().map(|entry| self.record(entry)).sum::<usize>();
+            }
+        }
+        let Some(id) = frame.id else {
+            return recorded;
+        };
+        if self.index.contains_key(&id) || self.pending.iter().any(|(pending, _)| *pending == id) {
+            return recorded;
+        }
+        match message_hash(frame) {
+            Ok(hash) => {
+                self.pending.push((id, hash));
+                recorded + 1
+            }
+            Err(e) => {
+                warn!("Cannot hash message {}: {}", id, e);
+                recorded
+            }
+        }
+    }
+
+    /// Number of messages waiting to be sealed
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Sealed batches, oldest first
+    pub fn batches(&self) -> &[AnchorBatch] {
+        &self.batches
+    }
+
+    /// Seal the pending messages into a batch
+    ///
+    /// # Returns
+    /// Root of the new batch, or `None` if nothing was pending
+    pub fn seal(&mut self) -> Option<[u8; 32]> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let batch = self.batches.len();
+        let (ids, hashes): (Vec<Ulid>, Vec<[u8; 32]>) = std::mem::take(&mut self.pending).into_iter().unzip();
+        for (leaf, id) in ids.into_iter().enumerate() {
+            self.index.insert(id, (batch, leaf));
+        }
+        let root = merkle_root(&hashes);
+        self.batches.push(AnchorBatch { root, hashes, anchor: None });
+        Some(root)
+    }
+
+    /// Sealed batches not yet anchored, as `(batch index, root, message count)`
+    pub fn unanchored(&self) -> Vec<(usize, [u8; 32], usize)> {
+        self.batches
+            .iter()
+            .enumerate()
+            .filter(|(_, batch)| batch.anchor.is_none())
+            .map(|(i, batch)| (i, batch.root, batch.hashes.len()))
+            .collect()
+    }
+
+    /// Record the transaction that anchored a batch
+    pub fn mark_anchored(&mut self, batch: usize, receipt: &TransactionReceipt) {
+        if let Some(batch) = self.batches.get_mut(batch) {
+            batch.anchor = Some(AnchorTx { tx_hash: receipt.transaction_hash, block_number: receipt.block_number });
+        }
+    }
+
+    /// Merkle proof of a recorded message
+    ///
+    /// # Returns
+    /// `None` if the message was not recorded or its batch is not sealed yet
+    pub fn prove(&self, message_id: &Ulid) -> Option<MessageProof> {
+        let &(batch, leaf) = self.index.get(message_id)?;
+        let batch = &self.batches[batch];
+        Some(MessageProof {
+            message_id: *message_id,
+            message_hash: batch.hashes[leaf],
+            path: merkle_path(&batch.hashes, leaf).into_iter().map(MerkleNode).collect(),
+            root: batch.root,
+            anchor: batch.anchor,
+        })
+    }
+}
+
+/// Seal the pending messages and anchor every unanchored batch
+///
+/// Batches that fail to anchor stay unanchored and are retried on the next call.
+///
+/// # Returns
+/// Number of batches anchored
+pub async fn anchor_pending(
+    anchor: &Mutex<MessageAnchor>,
+    chain: &ChainClient,
+    contract: Address,
+) -> Result<usize, ChainError> {
+    let batches = {
+        let mut anchor = anchor.lock().unwrap();
+        anchor.seal();
+        anchor.unanchored()
+    };
+    for (anchored, (batch, root, count)) in batches.iter().enumerate() {
+        let receipt = match chain.anchor_root(contract, *root, *count).await {
+            Ok(receipt) => receipt,
+            Err(e) if anchored > 0 => {
+                warn!("Anchoring batch {} failed: {}", batch, e);
+                return Ok(anchored);
+            }
+            Err(e) => return Err(e),
+        };
+        info!("Anchored {} messages as 0x{} in block {}", count, hex::encode(root), receipt.block_number);
+        anchor.lock().unwrap().mark_anchored(*batch, &receipt);
+    }
+    Ok(batches.len())
+}
+
+/// Anchor pending messages every `interval` until the task is aborted
+pub fn spawn_anchoring(
+    anchor: Arc<Mutex<MessageAnchor>>,
+    chain: Arc<ChainClient>,
+    contract: Address,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            match anchor_pending(&anchor, &chain, contract).await {
+                Ok(0) => {}
+                Ok(n) => debug!("Anchored {} batches", n),
+                Err(e) => warn!("Anchoring failed: {}", e),
+            }
+        }
+    })
+}
+
+impl ChainClient {
+    /// Post a batch root to the anchor contract and wait until it is mined
+    ///
+    /// # Arguments
+    /// * `contract` - Anchor contract
+    /// * `root` - Merkle root of the batch
+    /// * `count` - Number of messages in the batch
+    pub async fn anchor_root(
+        &self,
+        contract: Address,
+        root: [u8; 32],
+        count: usize,
+    ) -> Result<TransactionReceipt, ChainError> {
+        self.transact(contract, ANCHOR_SIGNATURE, &[Token::FixedBytes(root), Token::Uint(count as u128)], 0).await
+    }
+
+    /// When a root was anchored
+    ///
+    /// # Returns
+    /// Block timestamp of the anchoring, or `None` if the root was never anchored
+    pub async fn anchored_at(&self, contract: Address, root: [u8; 32]) -> Result<Option<u64>, ChainError> {
+        let outputs = self.call(contract, ANCHORED_AT_SIGNATURE, &[Token::FixedBytes(root)], &[ParamType::Uint]).await?;
+        let ts = outputs
+            .into_iter()
+            .next()
+            .and_then(Token::into_uint)
+            .ok_or_else(|| ChainError::InvalidResponse("anchoredAt result".into()))?;
+        Ok((ts > 0).then_some(ts as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use crate::chain::abi::{self, selector};
+    use crate::chain::{mock, parse_data, ChainSigner};
+    use crate::types::FrameOptions;
+    use crate::crypto::{KeyManager, SecurityManager};
+
+    const KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    fn frames(n: usize) -> Vec<OpacusFrame> {
+        let identity = KeyManager::generate_identity(16602);
+        let mut security = SecurityManager::new();
+        (0..n)
+            .map(|i| {
+                let payload = format!("message {}", i).into_bytes();
+                security.create_auth_frame_with(&identity, &[0; 32], FrameType::Msg, "bob", payload, FrameOptions::default())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_prove_messages() {
+        let mut anchor = MessageAnchor::new();
+        let frames = frames(5);
+        for frame in &frames {
+            assert_eq!(anchor.record(frame), 1);
+        }
+        assert_eq!(anchor.record(&frames[0]), 0);
+        assert!(anchor.prove(&frames[0].id.unwrap()).is_none());
+
+        let root = anchor.seal().unwrap();
+        assert_eq!((anchor.pending(), anchor.seal()), (0, None));
+        let hashes: Vec<_> = frames.iter().map(|f| message_hash(f).unwrap()).collect();
+        assert_eq!(root, merkle_root(&hashes));
+        // Duplicates of sealed messages are skipped too
+        assert_eq!(anchor.record(&frames[4]), 0);
+
+        for frame in &frames {
+            let proof = anchor.prove(&frame.id.unwrap()).unwrap();
+            assert_eq!(proof.root, root);
+            assert!(proof.verify(frame));
+
+            let json = serde_json::to_string(&proof).unwrap();
+            assert_eq!(serde_json::from_str::<MessageProof>(&json).unwrap(), proof);
+        }
+
+        // A proof does not cover another message or an altered one
+        let proof = anchor.prove(&frames[1].id.unwrap()).unwrap();
+        assert!(!proof.verify(&frames[2]));
+        let mut altered = frames[1].clone();
+        altered.payload = b"message 9".to_vec().into();
+        assert!(!proof.verify(&altered));
+        let mut forged = proof.clone();
+        forged.path[0] = MerkleNode([7; 32]);
+        assert!(!forged.verify(&frames[1]));
+
+        assert_eq!(merkle_root(&hashes[..1]), keccak256(&hashes[0]));
+        assert_eq!(merkle_root(&[]), [0; 32]);
+    }
+
+    #[tokio::test]
+    async fn test_anchor_pending() {
+        let url = mock::serve(|method, params| match method {
+            "eth_getTransactionCount" => Ok(json!("0x0")),
+            "eth_getBlockByNumber" => Ok(json!({ "number": "0x10", "baseFeePerGas": "0x1" })),
+            "eth_maxPriorityFeePerGas" => Ok(json!("0x1")),
+            "eth_estimateGas" => {
+                let data = parse_data(params[0]["data"].as_str().unwrap()).unwrap();
+                assert_eq!(data[..4], selector(ANCHOR_SIGNATURE));
+                let args = abi::decode(&[ParamType::FixedBytes, ParamType::Uint], &data[4..]).unwrap();
+                assert_eq!(args[1], Token::Uint(3));
+                Ok(json!("0x186a0"))
+            }
+            "eth_sendRawTransaction" => {
+                let raw = parse_data(params[0].as_str().unwrap()).unwrap();
+                Ok(json!(format!("0x{}", hex::encode(keccak256(&raw)))))
+            }
+            "eth_getTransactionReceipt" => Ok(json!({
+                "transactionHash": params[0],
+                "blockNumber": "0x11",
+                "gasUsed": "0x186a0",
+                "status": "0x1",
+                "contractAddress": Value::Null,
+            })),
+            "eth_call" => {
+                let data = parse_data(params[0]["data"].as_str().unwrap()).unwrap();
+                let ts = if data[4..] == [0; 32] { 0 } else { 1_700_000_000 };
+                Ok(json!(format!("0x{}", hex::encode(abi::encode(&[Token::Uint(ts)])))))
+            }
+            _ => Err((-32601, format!("method {} not found", method))),
+        })
+        .await;
+        let chain = ChainClient::new(&url, 16602).unwrap().with_signer(ChainSigner::from_hex(KEY).unwrap());
+        let contract: Address = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".parse().unwrap();
+
+        let anchor = Mutex::new(MessageAnchor::new());
+        let frames = frames(3);
+        for frame in &frames {
+            anchor.lock().unwrap().record(frame);
+        }
+        assert_eq!(anchor_pending(&anchor, &chain, contract).await.unwrap(), 1);
+        assert_eq!(anchor_pending(&anchor, &chain, contract).await.unwrap(), 0);
+
+        let proof = anchor.lock().unwrap().prove(&frames[2].id.unwrap()).unwrap();
+        assert_eq!(proof.anchor.unwrap().block_number, 17);
+        assert_eq!(chain.anchored_at(contract, proof.root).await.unwrap(), Some(1_700_000_000));
+        assert_eq!(chain.anchored_at(contract, [0; 32]).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,199 @@
+//! EIP-712 forms of delivery receipts and usage statements
+//!
+//! Frames and usage statements are signed with Ed25519 for the protocol;
+//! contracts cannot check those. The types here carry a second, secp256k1
+//! signature over the same facts as EIP-712 typed data, so a contract (e.g.
+//! an escrow releasing funds on delivery) can verify them with `ecrecover`.
+//! The verifying contract is the domain's `verifying_contract`.
+
+use serde::{Deserialize, Serialize};
+use crate::metering::UsageStatement;
+use crate::types::{OpacusFrame, Ulid};
+use super::abi::Token;
+use super::{hex_bytes, keccak256, message_hash, recover_typed_data, Address, ChainError, ChainSigner, Eip712Domain, TypedData};
+
+fn string_hash(value: &str) -> Token {
+    Token::FixedBytes(keccak256(value.as_bytes()))
+}
+
+impl TypedData for UsageStatement {
+    const ENCODED_TYPE: &'static str = "UsageStatement(string channelId,string provider,string consumer,\
+        uint256 messages,uint256 bytes,uint256 cost,uint256 ts)";
+
+    fn members(&self) -> Vec<Token> {
+        vec![
+            string_hash(&self.channel_id),
+            string_hash(&self.provider),
+            string_hash(&self.consumer),
+            Token::Uint(self.usage.messages.into()),
+            Token::Uint(self.usage.bytes.into()),
+            Token::Uint(self.usage.cost),
+            Token::Uint(self.ts.into()),
+        ]
+    }
+}
+
+/// Usage statement countersigned by the provider's chain key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedUsageStatement {
+    /// Statement, with its Ed25519 signature
+    pub statement: UsageStatement,
+    /// Provider's EIP-712 signature (`r || s || v`)
+    #[serde(with = "hex_bytes")]
+    pub signature: [u8; 65],
+}
+
+impl SignedUsageStatement {
+    /// Sign a statement
+    pub fn sign(statement: UsageStatement, domain: &Eip712Domain, signer: &ChainSigner) -> Result<Self, ChainError> {
+        let signature = signer.sign_typed_data(domain, &statement)?;
+        Ok(Self { statement, signature })
+    }
+
+    /// Account that signed the statement
+    pub fn signer(&self, domain: &Eip712Domain) -> Result<Address, ChainError> {
+        recover_typed_data(domain, &self.statement, &self.signature)
+    }
+}
+
+/// Recipient's acknowledgement that a message was delivered
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeliveryReceipt {
+    /// Hash of the delivered frame ([`message_hash`])
+    #[serde(with = "hex_bytes")]
+    pub message_hash: [u8; 32],
+    /// Message ID
+    pub message_id: Ulid,
+    /// Sender agent ID
+    pub from: String,
+    /// Recipient agent ID
+    pub to: String,
+    /// Delivery time (milliseconds)
+    pub received_at: u64,
+}
+
+impl DeliveryReceipt {
+    /// Receipt for a received frame
+    ///
+    /// # Returns
+    /// `Err` if the frame has no message ID or cannot be encoded
+    pub fn for_frame(frame: &OpacusFrame, received_at: u64) -> Result<Self, ChainError> {
+        let message_id = frame.id.ok_or_else(|| ChainError::InvalidResponse("Frame has no message ID".into()))?;
+        Ok(Self {
+            message_hash: message_hash(frame).map_err(ChainError::InvalidResponse)?,
+            message_id,
+            from: frame.from.clone(),
+            to: frame.to.clone(),
+            received_at,
+        })
+    }
+
+    /// Whether the receipt is for `frame`
+    pub fn matches(&self, frame: &OpacusFrame) -> bool {
+        frame.id == Some(self.message_id) && message_hash(frame).is_ok_and(|hash| hash == self.message_hash)
+    }
+}
+
+impl TypedData for DeliveryReceipt {
+    const ENCODED_TYPE: &'static str =
+        "DeliveryReceipt(bytes32 messageHash,string messageId,string from,string to,uint256 receivedAt)";
+
+    fn members(&self) -> Vec<Token> {
+        vec![
+            Token::FixedBytes(self.message_hash),
+            string_hash(&self.message_id.to_string()),
+            string_hash(&self.from),
+            string_hash(&self.to),
+            Token::Uint(self.received_at.into()),
+        ]
+    }
+}
+
+/// Delivery receipt signed by the recipient's chain key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedDeliveryReceipt {
+    /// Signed receipt
+    pub receipt: DeliveryReceipt,
+    /// Recipient's EIP-712 signature (`r || s || v`)
+    #[serde(with = "hex_bytes")]
+    pub signature: [u8; 65],
+}
+
+impl SignedDeliveryReceipt {
+    /// Sign a receipt
+    pub fn sign(receipt: DeliveryReceipt, domain: &Eip712Domain, signer: &ChainSigner) -> Result<Self, ChainError> {
+        let signature = signer.sign_typed_data(domain, &receipt)?;
+        Ok(Self { receipt, signature })
+    }
+
+    /// Account that signed the receipt
+    pub fn signer(&self, domain: &Eip712Domain) -> Result<Address, ChainError> {
+        recover_typed_data(domain, &self.receipt, &self.signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{KeyManager, SecurityManager};
+    use crate::metering::UsageMeter;
+    use crate::types::{ChannelType, DataChannel, FrameOptions, FrameType};
+
+    const KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    fn domain() -> Eip712Domain {
+        Eip712Domain::new("Opacus Escrow", "1", 16602, "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".parse().unwrap())
+    }
+
+    #[test]
+    fn test_delivery_receipt() {
+        let alice = KeyManager::generate_identity(16602);
+        let mut security = SecurityManager::new();
+        let frame = security.create_auth_frame_with(&alice, &[0; 32], FrameType::Msg, "bob", b"report".to_vec(), FrameOptions::default());
+        let other = security.create_auth_frame_with(&alice, &[0; 32], FrameType::Msg, "bob", b"report".to_vec(), FrameOptions::default());
+
+        let signer = ChainSigner::from_hex(KEY).unwrap();
+        let receipt = DeliveryReceipt::for_frame(&frame, 1_000).unwrap();
+        assert!(receipt.matches(&frame) && !receipt.matches(&other));
+        let signed = SignedDeliveryReceipt::sign(receipt, &domain(), &signer).unwrap();
+        assert_eq!(signed.signer(&domain()).unwrap(), signer.address());
+
+        let json = serde_json::to_string(&signed).unwrap();
+        assert_eq!(serde_json::from_str::<SignedDeliveryReceipt>(&json).unwrap(), signed);
+
+        let mut tampered = signed.clone();
+        tampered.receipt.received_at = 2_000;
+        assert_ne!(tampered.signer(&domain()).unwrap(), signer.address());
+        assert_ne!(signed.signer(&Eip712Domain { chain_id: 16661, ..domain() }).unwrap(), signer.address());
+
+        let mut unidentified = frame;
+        unidentified.id = None;
+        assert!(DeliveryReceipt::for_frame(&unidentified, 1_000).is_err());
+    }
+
+    #[test]
+    fn test_signed_usage_statement() {
+        let provider = KeyManager::generate_identity(16602);
+        let meter = UsageMeter::new();
+        meter.set_pricing(&DataChannel {
+            id: "prices".into(),
+            channel_type: ChannelType::Output,
+            price_per_byte: 2,
+            price_per_msg: 100,
+            access: None,
+            plan: None,
+        });
+        meter.record("prices", "alice", 10);
+
+        let signer = ChainSigner::from_hex(KEY).unwrap();
+        let signed = SignedUsageStatement::sign(meter.statement(&provider, "prices", "alice", 1_000), &domain(), &signer).unwrap();
+        assert_eq!(signed.signer(&domain()).unwrap(), signer.address());
+        // The Ed25519 signature stays valid alongside
+        signed.statement.verify().unwrap();
+
+        let mut inflated = signed.clone();
+        inflated.statement.usage.cost = 1_000;
+        assert_ne!(inflated.signer(&domain()).unwrap(), signer.address());
+    }
+}
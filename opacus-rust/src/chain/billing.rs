@@ -0,0 +1,276 @@
+//! Subscription billing
+//!
+//! A [`StreamingAuthorization`] is EIP-712 typed data in the payments
+//! contract's domain ([`payment_domain`](super::payment_domain)). The
+//! contract additionally exposes
+//!
+//! ```solidity
+//! function collectSubscription(Subscription calldata sub, bytes calldata signature, uint256 index) external;
+//! ```
+//!
+//! which pays `amountPerPeriod` from the payer to the payee for the period
+//! `index`, once per period, and only while `start + index * period` has
+//! passed and `index < periods`.
+
+use crate::subscription::StreamingAuthorization;
+use crate::types::{DataChannel, SubscriptionPlan};
+use super::abi::Token;
+use super::{keccak256, parse_data, recover_typed_data, Address, ChainClient, ChainError, ChainSigner, Eip712Domain, TransactionReceipt, TypedData};
+
+/// Collection function of the payments contract
+pub const COLLECT_SUBSCRIPTION_SIGNATURE: &str =
+    "collectSubscription((string,address,address,address,uint256,uint256,uint256,uint256),bytes,uint256)";
+
+/// Signed part of a [`StreamingAuthorization`]
+struct Subscription<'a> {
+    channel_id: &'a str,
+    payer: Address,
+    payee: Address,
+    token: Address,
+    amount_per_period: u128,
+    period: u64,
+    start: u64,
+    periods: u64,
+}
+
+impl Subscription<'_> {
+    /// Members as passed to the contract (the channel ID unhashed)
+    fn call_members(&self) -> Vec<Token> {
+        let mut members = self.members();
+        members[0] = Token::String(self.channel_id.to_string());
+        members
+    }
+}
+
+impl TypedData for Subscription<'_> {
+    const ENCODED_TYPE: &'static str = "Subscription(string channelId,address payer,address payee,address token,\
+        uint256 amountPerPeriod,uint256 period,uint256 start,uint256 periods)";
+
+    fn members(&self) -> Vec<Token> {
+        vec![
+            Token::FixedBytes(keccak256(self.channel_id.as_bytes())),
+            Token::Address(self.payer),
+            Token::Address(self.payee),
+            Token::Address(self.token),
+            Token::Uint(self.amount_per_period),
+            Token::Uint(self.period.into()),
+            Token::Uint(self.start.into()),
+            Token::Uint(self.periods.into()),
+        ]
+    }
+}
+
+fn parse_address(value: &str) -> Result<Address, ChainError> {
+    value.parse().map_err(ChainError::Payment)
+}
+
+impl StreamingAuthorization {
+    /// Authorize paying a channel's plan for `periods` periods
+    ///
+    /// # Arguments
+    /// * `domain` - Domain of the payments contract
+    /// * `channel_id` - Paid channel
+    /// * `plan` - The channel's plan
+    /// * `start` - Start of the first period (Unix seconds)
+    /// * `periods` - Number of periods
+    /// * `signer` - Payer's key
+    pub fn sign(
+        domain: &Eip712Domain,
+        channel_id: &str,
+        plan: &SubscriptionPlan,
+        start: u64,
+        periods: u64,
+        signer: &ChainSigner,
+    ) -> Result<Self, ChainError> {
+        let mut authorization = Self {
+            payer: signer.address().to_string(),
+            payee: plan.payee.clone(),
+            token: plan.token.clone(),
+            amount_per_period: plan.price,
+            period: plan.period,
+            start,
+            periods,
+            signature: String::new(),
+        };
+        let signature = signer.sign_typed_data(domain, &authorization.subscription(channel_id)?)?;
+        authorization.signature = format!("0x{}", hex::encode(signature));
+        Ok(authorization)
+    }
+
+    fn subscription<'a>(&self, channel_id: &'a str) -> Result<Subscription<'a>, ChainError> {
+        Ok(Subscription {
+            channel_id,
+            payer: parse_address(&self.payer)?,
+            payee: parse_address(&self.payee)?,
+            token: parse_address(&self.token)?,
+            amount_per_period: self.amount_per_period,
+            period: self.period,
+            start: self.start,
+            periods: self.periods,
+        })
+    }
+
+    fn signature_bytes(&self) -> Result<[u8; 65], ChainError> {
+        parse_data(&self.signature)
+            .ok()
+            .and_then(|s| s.try_into().ok())
+            .ok_or_else(|| ChainError::Payment("Malformed authorization signature".into()))
+    }
+
+    /// Check an authorization against a channel's plan
+    ///
+    /// # Arguments
+    /// * `domain` - Domain of the payments contract
+    /// * `channel` - Paid channel
+    /// * `now` - Current time (Unix seconds)
+    ///
+    /// # Returns
+    /// Paying account; `Err(ChainError::Payment)` if the channel has no plan,
+    /// the terms fall short of it, the authorization is not active at `now`,
+    /// or the payer did not sign it
+    pub fn verify(&self, domain: &Eip712Domain, channel: &DataChannel, now: u64) -> Result<Address, ChainError> {
+        let plan = channel.plan.as_ref().ok_or_else(|| ChainError::Payment(format!("Channel {} has no plan", channel.id)))?;
+        let subscription = self.subscription(&channel.id)?;
+        if subscription.payee != parse_address(&plan.payee)? || subscription.token != parse_address(&plan.token)? {
+            return Err(ChainError::Payment("Authorization pays another account or token".into()));
+        }
+        if self.amount_per_period < plan.price || self.period != plan.period {
+            return Err(ChainError::Payment(format!("Authorization does not cover {} per {}s", plan.price, plan.period)));
+        }
+        if !self.is_active(now) {
+            return Err(ChainError::Payment(format!("Authorization runs from {} to {}", self.start, self.expires_at())));
+        }
+        let signer = recover_typed_data(domain, &subscription, &self.signature_bytes()?)
+            .map_err(|e| ChainError::Payment(format!("Invalid signature: {}", e)))?;
+        if signer != subscription.payer {
+            return Err(ChainError::Payment(format!("Signed by {}, not by payer {}", signer, subscription.payer)));
+        }
+        Ok(signer)
+    }
+}
+
+impl ChainClient {
+    /// Collect one period of a subscription and wait until it is mined
+    ///
+    /// # Arguments
+    /// * `contract` - Payments contract
+    /// * `channel_id` - Paid channel
+    /// * `authorization` - Subscriber's authorization
+    /// * `index` - Period to collect
+    pub async fn collect_subscription(
+        &self,
+        contract: Address,
+        channel_id: &str,
+        authorization: &StreamingAuthorization,
+        index: u64,
+    ) -> Result<TransactionReceipt, ChainError> {
+        let args = [
+            Token::Tuple(authorization.subscription(channel_id)?.call_members()),
+            Token::Bytes(authorization.signature_bytes()?.to_vec()),
+            Token::Uint(index.into()),
+        ];
+        self.transact(contract, COLLECT_SUBSCRIPTION_SIGNATURE, &args, 0).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use crate::chain::abi::{self, selector, ParamType};
+    use crate::chain::{mock, payment_domain};
+    use crate::types::ChannelType;
+
+    const KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+    const CONTRACT: &str = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+
+    fn channel(payee: Address) -> DataChannel {
+        DataChannel {
+            id: "prices".into(),
+            channel_type: ChannelType::Output,
+            price_per_byte: 0,
+            price_per_msg: 0,
+            access: None,
+            plan: Some(SubscriptionPlan {
+                payee: payee.to_string(),
+                token: Address::default().to_string(),
+                price: 1_000,
+                period: 3_600,
+            }),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_streaming_authorization() {
+        let domain = payment_domain(16602, CONTRACT.parse().unwrap());
+        let subscriber = ChainSigner::from_hex(KEY).unwrap();
+        let publisher = ChainSigner::random().address();
+        let channel = channel(publisher);
+        let plan = channel.plan.as_ref().unwrap();
+
+        let authorization = StreamingAuthorization::sign(&domain, "prices", plan, 10_000, 3, &subscriber).unwrap();
+        assert_eq!(authorization.expires_at(), 20_800);
+        assert_eq!(authorization.verify(&domain, &channel, 10_000).unwrap(), subscriber.address());
+        assert_eq!(authorization.period_at(17_300), Some(2));
+
+        let denied = |result: Result<Address, ChainError>| matches!(result, Err(ChainError::Payment(_)));
+        // Before the start, after expiry, for another channel
+        assert!(denied(authorization.verify(&domain, &channel, 9_999)));
+        assert!(denied(authorization.verify(&domain, &channel, 20_800)));
+        assert!(denied(authorization.verify(&domain, &DataChannel { id: "signals".into(), ..channel.clone() }, 10_000)));
+        // Underpaying, or forging a longer subscription
+        let cheap = SubscriptionPlan { price: 999, ..plan.clone() };
+        let underpaid = StreamingAuthorization::sign(&domain, "prices", &cheap, 10_000, 3, &subscriber).unwrap();
+        assert!(denied(underpaid.verify(&domain, &channel, 10_000)));
+        let extended = StreamingAuthorization { periods: 30, ..authorization.clone() };
+        assert!(denied(extended.verify(&domain, &channel, 10_000)));
+        let free = DataChannel { plan: None, ..channel.clone() };
+        assert!(denied(authorization.verify(&domain, &free, 10_000)));
+
+        let json = serde_json::to_value(&authorization).unwrap();
+        assert_eq!(json["amountPerPeriod"], "1000");
+        assert_eq!(serde_json::from_value::<StreamingAuthorization>(json).unwrap(), authorization);
+
+        let url = mock::serve(move |method, params| match method {
+            "eth_getTransactionCount" => Ok(json!("0x0")),
+            "eth_getBlockByNumber" => Ok(json!({ "number": "0x10", "baseFeePerGas": "0x1" })),
+            "eth_maxPriorityFeePerGas" => Ok(json!("0x1")),
+            "eth_estimateGas" => {
+                let data = parse_data(params[0]["data"].as_str().unwrap()).unwrap();
+                assert_eq!(data[..4], selector(COLLECT_SUBSCRIPTION_SIGNATURE));
+                let subscription = ParamType::Tuple(vec![
+                    ParamType::String,
+                    ParamType::Address,
+                    ParamType::Address,
+                    ParamType::Address,
+                    ParamType::Uint,
+                    ParamType::Uint,
+                    ParamType::Uint,
+                    ParamType::Uint,
+                ]);
+                let args = abi::decode(&[subscription, ParamType::Bytes, ParamType::Uint], &data[4..]).unwrap();
+                let fields = args[0].clone().into_items().unwrap();
+                assert_eq!(fields[0], Token::String("prices".into()));
+                assert_eq!(fields[2], Token::Address(publisher));
+                assert_eq!(args[2], Token::Uint(2));
+                Ok(json!("0x20000"))
+            }
+            "eth_sendRawTransaction" => {
+                let raw = parse_data(params[0].as_str().unwrap()).unwrap();
+                Ok(json!(format!("0x{}", hex::encode(keccak256(&raw)))))
+            }
+            "eth_getTransactionReceipt" => Ok(json!({
+                "transactionHash": params[0],
+                "blockNumber": "0x11",
+                "gasUsed": "0x20000",
+                "status": "0x1",
+                "contractAddress": Value::Null,
+            })),
+            _ => Err((-32601, format!("method {} not found", method))),
+        })
+        .await;
+        let chain = ChainClient::new(&url, 16602).unwrap().with_signer(ChainSigner::random());
+        let receipt = chain.collect_subscription(CONTRACT.parse().unwrap(), "prices", &authorization, 2).await.unwrap();
+        assert_eq!(receipt.block_number, 17);
+    }
+}
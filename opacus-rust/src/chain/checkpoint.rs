@@ -0,0 +1,487 @@
+//! Relay checkpoints: signed Merkle roots of routed messages
+//!
+//! Federated relays are run by different parties, so a claim that a relay
+//! routed (or handed to a peer relay) some message must be checkable by
+//! others. A relay running a [`RelayCheckpointer`] records the hash of every
+//! frame it routes and periodically seals them into a [`RelayCheckpoint`]:
+//! the batch's Merkle root (see [`MessageAnchor`]), signed as EIP-712 typed
+//! data and chained to the previous checkpoint by its hash. Checkpoints are
+//! posted to a checkpoint contract exposing
+//!
+//! ```solidity
+//! function postCheckpoint(address relay, uint256 sequence, bytes32 root, uint256 count,
+//!     bytes32 previous, uint256 createdAt, bytes signature) external;
+//! function checkpointOf(address relay, uint256 sequence) external view
+//!     returns (bytes32 root, uint256 count, uint256 postedAt);
+//! ```
+//!
+//! which checks the relay's signature, so anyone holding a checkpoint can
+//! post it. A [`RoutingProof`] from the relay then shows that a frame is
+//! under a posted root; a relay that signs two checkpoints with the same
+//! sequence has forked its history.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+#[cfg(feature = "relay")]
+use crate::relay::FrameNotary;
+use crate::types::{OpacusFrame, Ulid};
+use super::abi::{ParamType, Token};
+use super::{
+    hex_bytes, recover_typed_data, Address, ChainClient, ChainError, ChainSigner, Eip712Domain, MessageAnchor,
+    MessageProof, TransactionReceipt, TypedData,
+};
+
+/// EIP-712 domain name of relay checkpoints
+pub const CHECKPOINT_DOMAIN_NAME: &str = "Opacus Relay Checkpoints";
+
+/// EIP-712 domain version of relay checkpoints
+pub const CHECKPOINT_DOMAIN_VERSION: &str = "1";
+
+/// Posting function of the checkpoint contract
+pub const POST_CHECKPOINT_SIGNATURE: &str = "postCheckpoint(address,uint256,bytes32,uint256,bytes32,uint256,bytes)";
+
+/// Signing domain of checkpoints posted to `contract`
+pub fn checkpoint_domain(chain_id: u64, contract: Address) -> Eip712Domain {
+    Eip712Domain::new(CHECKPOINT_DOMAIN_NAME, CHECKPOINT_DOMAIN_VERSION, chain_id, contract)
+}
+
+/// Relay-signed Merkle root of the messages routed since its previous checkpoint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayCheckpoint {
+    /// Relay's account
+    pub relay: Address,
+    /// Position in the relay's history, from zero
+    pub sequence: u64,
+    /// Merkle root of the routed messages' hashes
+    #[serde(with = "hex_bytes")]
+    pub root: [u8; 32],
+    /// Number of messages under the root
+    pub count: u64,
+    /// Hash of the previous checkpoint (zero for the first)
+    #[serde(with = "hex_bytes")]
+    pub previous: [u8; 32],
+    /// Sealing time (milliseconds)
+    pub created_at: u64,
+    /// Relay's EIP-712 signature (`r || s || v`)
+    #[serde(with = "hex_bytes")]
+    pub signature: [u8; 65],
+}
+
+impl TypedData for RelayCheckpoint {
+    const ENCODED_TYPE: &'static str = "RelayCheckpoint(address relay,uint256 sequence,bytes32 root,uint256 count,\
+        bytes32 previous,uint256 createdAt)";
+
+    fn members(&self) -> Vec<Token> {
+        vec![
+            Token::Address(self.relay),
+            Token::Uint(self.sequence.into()),
+            Token::FixedBytes(self.root),
+            Token::Uint(self.count.into()),
+            Token::FixedBytes(self.previous),
+            Token::Uint(self.created_at.into()),
+        ]
+    }
+}
+
+impl RelayCheckpoint {
+    /// Hash the next checkpoint chains to
+    pub fn hash(&self) -> [u8; 32] {
+        self.struct_hash()
+    }
+
+    /// Check that the relay signed the checkpoint
+    pub fn verify(&self, domain: &Eip712Domain) -> Result<(), ChainError> {
+        let signer = recover_typed_data(domain, self, &self.signature)?;
+        if signer != self.relay {
+            return Err(ChainError::Signer(format!("Checkpoint of {} signed by {}", self.relay, signer)));
+        }
+        Ok(())
+    }
+
+    /// Whether this checkpoint directly follows `previous` in the relay's history
+    pub fn follows(&self, previous: &RelayCheckpoint) -> bool {
+        self.relay == previous.relay && self.sequence == previous.sequence + 1 && self.previous == previous.hash()
+    }
+}
+
+/// Proof that a relay routed a message, under one of its checkpoints
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoutingProof {
+    /// Merkle proof of the message hash
+    pub proof: MessageProof,
+    /// Checkpoint whose root the proof leads to
+    pub checkpoint: RelayCheckpoint,
+}
+
+impl RoutingProof {
+    /// Check that `frame` is under the relay's signed checkpoint
+    ///
+    /// # Returns
+    /// The relay's account; `Err(ChainError::Signer)` if the checkpoint is
+    /// not signed by it or the proof does not lead from `frame` to its root
+    pub fn verify(&self, domain: &Eip712Domain, frame: &OpacusFrame) -> Result<Address, ChainError> {
+        self.checkpoint.verify(domain)?;
+        if self.proof.root != self.checkpoint.root || !self.proof.verify(frame) {
+            return Err(ChainError::Signer(format!("Frame is not under checkpoint {}", self.checkpoint.sequence)));
+        }
+        Ok(self.checkpoint.relay)
+    }
+}
+
+/// Checkpoint as recorded by the contract
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PostedCheckpoint {
+    /// Merkle root
+    pub root: [u8; 32],
+    /// Number of messages under the root
+    pub count: u64,
+    /// Block timestamp of the posting
+    pub posted_at: u64,
+}
+
+#[derive(Debug, Default)]
+struct CheckpointLog {
+    anchor: MessageAnchor,
+    checkpoints: Vec<RelayCheckpoint>,
+    /// Checkpoints posted so far, which are always a prefix
+    posted: usize,
+}
+
+/// Relay-side recorder sealing routed messages into signed checkpoints
+///
+/// Install on a relay with `OpacusRelayServer::with_notary`; every
+/// checkpoint is kept so routing proofs can be given later.
+#[derive(Debug)]
+pub struct RelayCheckpointer {
+    signer: ChainSigner,
+    domain: Eip712Domain,
+    log: Mutex<CheckpointLog>,
+}
+
+impl RelayCheckpointer {
+    /// Checkpointer signing with the relay's key in `domain` ([`checkpoint_domain`])
+    pub fn new(signer: ChainSigner, domain: Eip712Domain) -> Self {
+        Self { signer, domain, log: Mutex::new(CheckpointLog::default()) }
+    }
+
+    /// Relay's account
+    pub fn address(&self) -> Address {
+        self.signer.address()
+    }
+
+    /// Record a routed frame (and the entries of a `Batch` frame)
+    ///
+    /// # Returns
+    /// Number of messages recorded
+    pub fn record(&self, frame: &OpacusFrame) -> usize {
+        self.log.lock().unwrap().anchor.record(frame)
+    }
+
+    /// Seal the recorded messages into the next checkpoint
+    ///
+    /// # Returns
+    /// The checkpoint, or `None` if nothing was routed since the last one
+    pub fn checkpoint(&self, now: u64) -> Result<Option<RelayCheckpoint>, ChainError> {
+        let mut log = self.log.lock().unwrap();
+        let Some(root) = log.anchor.seal() else {
+            return Ok(None);
+        };
+        let count = log.anchor.batches().last().map_or(0, |batch| batch.hashes.len() as u64);
+        let mut checkpoint = RelayCheckpoint {
+            relay: self.address(),
+            sequence: log.checkpoints.len() as u64,
+            root,
+            count,
+            previous: log.checkpoints.last().map(RelayCheckpoint::hash).unwrap_or_default(),
+            created_at: now,
+            signature: [0; 65],
+        };
+        checkpoint.signature = self.signer.sign_typed_data(&self.domain, &checkpoint)?;
+        log.checkpoints.push(checkpoint.clone());
+        Ok(Some(checkpoint))
+    }
+
+    /// Every checkpoint so far, oldest first
+    pub fn checkpoints(&self) -> Vec<RelayCheckpoint> {
+        self.log.lock().unwrap().checkpoints.clone()
+    }
+
+    /// Checkpoints not yet posted, oldest first
+    pub fn unposted(&self) -> Vec<RelayCheckpoint> {
+        let log = self.log.lock().unwrap();
+        log.checkpoints[log.posted..].to_vec()
+    }
+
+    /// Record that the checkpoint `sequence` (and every earlier one) was posted
+    pub fn mark_posted(&self, sequence: u64) {
+        let mut log = self.log.lock().unwrap();
+        log.posted = log.posted.max(sequence as usize + 1).min(log.checkpoints.len());
+    }
+
+    /// Proof that a routed message is under a checkpoint
+    ///
+    /// # Returns
+    /// `None` if the message was not routed or is not checkpointed yet
+    pub fn prove(&self, message_id: &Ulid) -> Option<RoutingProof> {
+        let log = self.log.lock().unwrap();
+        let proof = log.anchor.prove(message_id)?;
+        let checkpoint = log.checkpoints.iter().find(|checkpoint| checkpoint.root == proof.root)?.clone();
+        Some(RoutingProof { proof, checkpoint })
+    }
+}
+
+#[cfg(feature = "relay")]
+impl FrameNotary for RelayCheckpointer {
+    fn notarize(&self, frame: &mut OpacusFrame) -> bool {
+        self.record(frame);
+        false
+    }
+}
+
+/// Seal the routed messages into a checkpoint and post every unposted one
+///
+/// Checkpoints are posted in order; one that fails to post is retried, with
+/// those after it, on the next call.
+///
+/// # Returns
+/// Number of checkpoints posted
+pub async fn checkpoint_pending(checkpointer: &RelayCheckpointer, chain: &ChainClient) -> Result<usize, ChainError> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    checkpointer.checkpoint(now)?;
+    let contract = checkpointer.domain.verifying_contract;
+    let checkpoints = checkpointer.unposted();
+    for (posted, checkpoint) in checkpoints.iter().enumerate() {
+        let receipt = match chain.post_checkpoint(contract, checkpoint).await {
+            Ok(receipt) => receipt,
+            Err(e) if posted > 0 => {
+                warn!("Posting checkpoint {} failed: {}", checkpoint.sequence, e);
+                return Ok(posted);
+            }
+            Err(e) => return Err(e),
+        };
+        info!(
+            "Posted checkpoint {} of {} messages as 0x{} in block {}",
+            checkpoint.sequence,
+            checkpoint.count,
+            hex::encode(checkpoint.root),
+            receipt.block_number
+        );
+        checkpointer.mark_posted(checkpoint.sequence);
+    }
+    Ok(checkpoints.len())
+}
+
+/// Checkpoint routed messages every `interval` until the task is aborted
+pub fn spawn_checkpointing(
+    checkpointer: Arc<RelayCheckpointer>,
+    chain: Arc<ChainClient>,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            match checkpoint_pending(&checkpointer, &chain).await {
+                Ok(0) => {}
+                Ok(n) => debug!("Posted {} checkpoints", n),
+                Err(e) => warn!("Checkpointing failed: {}", e),
+            }
+        }
+    })
+}
+
+impl ChainClient {
+    /// Post a signed checkpoint and wait until it is mined
+    ///
+    /// # Arguments
+    /// * `contract` - Checkpoint contract
+    /// * `checkpoint` - Checkpoint signed by its relay
+    pub async fn post_checkpoint(&self, contract: Address, checkpoint: &RelayCheckpoint) -> Result<TransactionReceipt, ChainError> {
+        let args = [
+            Token::Address(checkpoint.relay),
+            Token::Uint(checkpoint.sequence.into()),
+            Token::FixedBytes(checkpoint.root),
+            Token::Uint(checkpoint.count.into()),
+            Token::FixedBytes(checkpoint.previous),
+            Token::Uint(checkpoint.created_at.into()),
+            Token::Bytes(checkpoint.signature.to_vec()),
+        ];
+        self.transact(contract, POST_CHECKPOINT_SIGNATURE, &args, 0).await
+    }
+
+    /// Checkpoint a relay posted for a sequence number
+    ///
+    /// # Returns
+    /// `None` if it was never posted
+    pub async fn checkpoint_of(&self, contract: Address, relay: Address, sequence: u64) -> Result<Option<PostedCheckpoint>, ChainError> {
+        let args = [Token::Address(relay), Token::Uint(sequence.into())];
+        let outputs = [ParamType::FixedBytes, ParamType::Uint, ParamType::Uint];
+        let mut values = self.call(contract, "checkpointOf(address,uint256)", &args, &outputs).await?.into_iter();
+        let mut next = || values.next().ok_or_else(|| ChainError::InvalidResponse("Short checkpoint record".into()));
+        let posted = PostedCheckpoint {
+            root: next()?.into_fixed_bytes().unwrap_or_default(),
+            count: next()?.into_uint().unwrap_or_default() as u64,
+            posted_at: next()?.into_uint().unwrap_or_default() as u64,
+        };
+        Ok((posted.posted_at > 0).then_some(posted))
+    }
+
+    /// Audit a relay's claim that it routed a frame
+    ///
+    /// Checks the routing proof and that the relay posted the same root
+    /// for that checkpoint.
+    ///
+    /// # Arguments
+    /// * `domain` - Checkpoint domain ([`checkpoint_domain`])
+    /// * `proof` - Routing proof given by the relay
+    /// * `frame` - Frame claimed to be routed
+    ///
+    /// # Returns
+    /// Block timestamp of the posting; `Err(ChainError::Signer)` if the proof
+    /// is invalid, the checkpoint is not posted, or another root was posted
+    pub async fn audit_routing(&self, domain: &Eip712Domain, proof: &RoutingProof, frame: &OpacusFrame) -> Result<u64, ChainError> {
+        let relay = proof.verify(domain, frame)?;
+        let sequence = proof.checkpoint.sequence;
+        let posted = self
+            .checkpoint_of(domain.verifying_contract, relay, sequence)
+            .await?
+            .ok_or_else(|| ChainError::Signer(format!("Checkpoint {} of {} was never posted", sequence, relay)))?;
+        if posted.root != proof.checkpoint.root {
+            return Err(ChainError::Signer(format!("{} posted another root for checkpoint {}", relay, sequence)));
+        }
+        Ok(posted.posted_at)
+    }
+}
+
+#[cfg(all(test, feature = "relay"))]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use serde_json::{json, Value};
+    use crate::chain::abi::{self, decode, selector};
+    use crate::chain::{keccak256, mock, parse_data};
+    use crate::crypto::{KeyManager, SecurityManager};
+    use crate::types::{FrameOptions, FrameType};
+
+    const KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    /// Roots posted to the mock contract, by relay and sequence
+    type PostedRoots = Arc<Mutex<HashMap<(Address, u128), [u8; 32]>>>;
+
+    fn frames(n: usize) -> Vec<OpacusFrame> {
+        let identity = KeyManager::generate_identity(16602);
+        let mut security = SecurityManager::new();
+        (0..n)
+            .map(|i| {
+                let payload = format!("message {}", i).into_bytes();
+                security.create_auth_frame_with(&identity, &[0; 32], FrameType::Msg, "bob", payload, FrameOptions::default())
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_relay_checkpoints() {
+        let contract_state = PostedRoots::default();
+        let posted = contract_state.clone();
+        let url = mock::serve(move |method, params| {
+            let data = || parse_data(params[0]["data"].as_str().unwrap()).unwrap();
+            match method {
+                "eth_call" => {
+                    let data = data();
+                    assert_eq!(data[..4], selector("checkpointOf(address,uint256)"));
+                    let args = decode(&[ParamType::Address, ParamType::Uint], &data[4..]).unwrap();
+                    let key = (args[0].clone().into_address().unwrap(), args[1].clone().into_uint().unwrap());
+                    let root = contract_state.lock().unwrap().get(&key).copied();
+                    let record = [
+                        Token::FixedBytes(root.unwrap_or_default()),
+                        Token::Uint(2),
+                        Token::Uint(if root.is_some() { 1_700_000_000 } else { 0 }),
+                    ];
+                    Ok(json!(format!("0x{}", hex::encode(abi::encode(&record)))))
+                }
+                "eth_getTransactionCount" => Ok(json!("0x0")),
+                "eth_getBlockByNumber" => Ok(json!({ "number": "0x10", "baseFeePerGas": "0x1" })),
+                "eth_maxPriorityFeePerGas" => Ok(json!("0x1")),
+                "eth_estimateGas" => {
+                    let data = data();
+                    assert_eq!(data[..4], selector(POST_CHECKPOINT_SIGNATURE));
+                    let types = [
+                        ParamType::Address,
+                        ParamType::Uint,
+                        ParamType::FixedBytes,
+                        ParamType::Uint,
+                        ParamType::FixedBytes,
+                        ParamType::Uint,
+                        ParamType::Bytes,
+                    ];
+                    let args = decode(&types, &data[4..]).unwrap();
+                    let key = (args[0].clone().into_address().unwrap(), args[1].clone().into_uint().unwrap());
+                    contract_state.lock().unwrap().insert(key, args[2].clone().into_fixed_bytes().unwrap());
+                    Ok(json!("0x30000"))
+                }
+                "eth_sendRawTransaction" => {
+                    let raw = parse_data(params[0].as_str().unwrap()).unwrap();
+                    Ok(json!(format!("0x{}", hex::encode(keccak256(&raw)))))
+                }
+                "eth_getTransactionReceipt" => Ok(json!({
+                    "transactionHash": params[0],
+                    "blockNumber": "0x11",
+                    "gasUsed": "0x30000",
+                    "status": "0x1",
+                    "contractAddress": Value::Null,
+                })),
+                _ => Err((-32601, format!("method {} not found", method))),
+            }
+        })
+        .await;
+        let domain = checkpoint_domain(16602, "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".parse().unwrap());
+        let chain = ChainClient::new(&url, 16602).unwrap().with_signer(ChainSigner::random());
+        let checkpointer = RelayCheckpointer::new(ChainSigner::from_hex(KEY).unwrap(), domain.clone());
+
+        let frames = frames(4);
+        for frame in &frames[..2] {
+            assert!(!checkpointer.notarize(&mut frame.clone()));
+        }
+        assert_eq!(checkpoint_pending(&checkpointer, &chain).await.unwrap(), 1);
+        assert_eq!(checkpoint_pending(&checkpointer, &chain).await.unwrap(), 0);
+        assert_eq!(posted.lock().unwrap().len(), 1);
+        for frame in &frames[2..] {
+            checkpointer.record(frame);
+        }
+        let second = checkpointer.checkpoint(2_000).unwrap().unwrap();
+        assert_eq!(checkpointer.unposted(), vec![second.clone()]);
+
+        let checkpoints = checkpointer.checkpoints();
+        assert_eq!((second.sequence, second.count), (1, 2));
+        assert!(second.follows(&checkpoints[0]) && !checkpoints[0].follows(&second));
+        second.verify(&domain).unwrap();
+        assert!(second.verify(&Eip712Domain { chain_id: 16661, ..domain.clone() }).is_err());
+
+        // The first message was checkpointed and posted; the last not yet posted
+        let proof = checkpointer.prove(&frames[0].id.unwrap()).unwrap();
+        assert_eq!(proof.verify(&domain, &frames[0]).unwrap(), checkpointer.address());
+        assert!(proof.verify(&domain, &frames[1]).is_err());
+        assert_eq!(chain.audit_routing(&domain, &proof, &frames[0]).await.unwrap(), 1_700_000_000);
+        let unposted = checkpointer.prove(&frames[3].id.unwrap()).unwrap();
+        assert!(matches!(chain.audit_routing(&domain, &unposted, &frames[3]).await, Err(ChainError::Signer(_))));
+
+        // A checkpoint whose root differs from the posted one fails the audit
+        let mut forked = proof.checkpoint.clone();
+        forked.root = unposted.checkpoint.root;
+        forked.signature = ChainSigner::from_hex(KEY).unwrap().sign_typed_data(&domain, &forked).unwrap();
+        let forked = RoutingProof { proof: MessageProof { root: forked.root, ..unposted.proof.clone() }, checkpoint: forked };
+        forked.verify(&domain, &frames[3]).unwrap();
+        assert!(chain.audit_routing(&domain, &forked, &frames[3]).await.is_err());
+
+        let json = serde_json::to_string(&proof).unwrap();
+        assert_eq!(serde_json::from_str::<RoutingProof>(&json).unwrap(), proof);
+        assert!(checkpointer.prove(&OpacusFrame::new_id(1_000)).is_none());
+    }
+}
@@ -0,0 +1,525 @@
+//! Account-level chain client: fees, nonces and transaction submission
+
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+use crate::types::OpacusConfig;
+use super::abi::{self, ParamType, Token};
+use super::pending::AccountState;
+use super::{
+    Address, ChainError, ChainRpc, ChainSigner, Eip1559Transaction, FeeBumpPolicy, PendingTransaction, TransactionReceipt,
+    TransactionRequest, TxHash,
+};
+
+/// Safety margin added to gas estimates (percent)
+const GAS_ESTIMATE_MARGIN: u64 = 20;
+
+/// Interval between receipt polls
+const RECEIPT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time `transact` waits for a transaction to be mined
+const TRANSACT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Gas of a plain value transfer
+const TRANSFER_GAS: u64 = 21_000;
+
+/// Chain client for one account
+///
+/// Fills in gas, fees and nonces, signs with the account key and submits
+/// transactions. Nonces are handed out locally so concurrent sends do not
+/// collide; the counter is re-read from the node after a failed submission.
+/// Submitted transactions are tracked until mined and replaced with higher
+/// fees when they get stuck (see [`FeeBumpPolicy`]). Share one client per
+/// account, e.g. through `OpacusClient::chain`.
+#[derive(Debug)]
+pub struct ChainClient {
+    rpc: ChainRpc,
+    chain_id: u64,
+    signer: Option<ChainSigner>,
+    account: Mutex<AccountState>,
+    fee_bump: Option<FeeBumpPolicy>,
+}
+
+impl ChainClient {
+    /// Create read-only client
+    ///
+    /// # Arguments
+    /// * `rpc_url` - JSON-RPC endpoint
+    /// * `chain_id` - Chain ID signed into transactions
+    pub fn new(rpc_url: &str, chain_id: u64) -> Result<Self, ChainError> {
+        Ok(Self {
+            rpc: ChainRpc::new(rpc_url)?,
+            chain_id,
+            signer: None,
+            account: Mutex::new(AccountState::default()),
+            fee_bump: Some(FeeBumpPolicy::default()),
+        })
+    }
+
+    /// Create client from the SDK configuration
+    ///
+    /// Uses `chain_rpc` (or the network's default endpoint when empty) and
+    /// signs with `private_key` when set.
+    pub fn from_config(config: &OpacusConfig) -> Result<Self, ChainError> {
+        let url = if config.chain_rpc.is_empty() { config.network.rpc() } else { &config.chain_rpc };
+        let client = Self::new(url, config.network.chain_id())?;
+        match &config.private_key {
+            Some(key) => Ok(client.with_signer(ChainSigner::from_hex(key)?)),
+            None => Ok(client),
+        }
+    }
+
+    /// Sign transactions with this key
+    pub fn with_signer(mut self, signer: ChainSigner) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Replace stuck transactions according to `policy` (`None` never replaces them)
+    pub fn with_fee_bump(mut self, policy: Option<FeeBumpPolicy>) -> Self {
+        self.fee_bump = policy;
+        self
+    }
+
+    /// Underlying JSON-RPC client
+    pub fn rpc(&self) -> &ChainRpc {
+        &self.rpc
+    }
+
+    /// Chain ID
+    pub fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    /// Account signer
+    pub fn signer(&self) -> Result<&ChainSigner, ChainError> {
+        self.signer.as_ref().ok_or_else(|| ChainError::Signer("No private key configured".into()))
+    }
+
+    /// Account address
+    pub fn address(&self) -> Option<Address> {
+        self.signer.as_ref().map(ChainSigner::address)
+    }
+
+    /// Balance of an account in wei
+    pub async fn balance(&self, address: &Address) -> Result<u128, ChainError> {
+        self.rpc.balance(address).await
+    }
+
+    /// Nonce the next transaction from this account will use
+    pub async fn nonce(&self) -> Result<u64, ChainError> {
+        let mut account = self.account.lock().await;
+        self.load_nonce(&mut account.next_nonce).await
+    }
+
+    /// Forget the local nonce and re-read it from the node on the next send
+    pub async fn reset_nonce(&self) {
+        self.account.lock().await.next_nonce = None;
+    }
+
+    /// Transactions submitted by this client and not yet seen mined, by nonce
+    pub async fn pending_transactions(&self) -> Vec<PendingTransaction> {
+        self.account.lock().await.pending.values().cloned().collect()
+    }
+
+    async fn load_nonce(&self, next: &mut Option<u64>) -> Result<u64, ChainError> {
+        if let Some(nonce) = *next {
+            return Ok(nonce);
+        }
+        let nonce = self.rpc.transaction_count(&self.signer()?.address()).await?;
+        *next = Some(nonce);
+        Ok(nonce)
+    }
+
+    /// Estimate gas for a transaction from this account, with a safety margin
+    pub async fn estimate_gas(&self, tx: &TransactionRequest) -> Result<u64, ChainError> {
+        let estimate = self.rpc.estimate_gas(self.address(), tx).await?;
+        Ok(estimate.saturating_add(estimate * GAS_ESTIMATE_MARGIN / 100))
+    }
+
+    /// Current fees per gas
+    ///
+    /// # Returns
+    /// `(max_fee_per_gas, max_priority_fee_per_gas)`: twice the latest base
+    /// fee plus the suggested tip, or the legacy gas price on nodes without
+    /// EIP-1559 support
+    pub async fn fees(&self) -> Result<(u128, u128), ChainError> {
+        let base_fee = self.rpc.base_fee().await?;
+        let tip = match self.rpc.max_priority_fee().await {
+            Ok(tip) => tip,
+            Err(ChainError::Rpc { .. }) | Err(ChainError::InvalidResponse(_)) if base_fee.is_none() => 0,
+            Err(e) => return Err(e),
+        };
+        match base_fee {
+            Some(base) => Ok((base.saturating_mul(2).saturating_add(tip), tip)),
+            None => {
+                let price = self.rpc.gas_price().await?;
+                Ok((price, price))
+            }
+        }
+    }
+
+    /// Fill, sign and submit a transaction
+    ///
+    /// # Returns
+    /// Hash of the submitted transaction
+    pub async fn send_transaction(&self, tx: TransactionRequest) -> Result<TxHash, ChainError> {
+        let signer = self.signer()?;
+        let gas_limit = match tx.gas_limit {
+            Some(gas) => gas,
+            None => self.estimate_gas(&tx).await?,
+        };
+        let (max_fee_per_gas, max_priority_fee_per_gas) = match (tx.max_fee_per_gas, tx.max_priority_fee_per_gas) {
+            (Some(max_fee), Some(tip)) => (max_fee, tip),
+            (max_fee, tip) => {
+                let (suggested_fee, suggested_tip) = self.fees().await?;
+                (max_fee.unwrap_or(suggested_fee), tip.unwrap_or(suggested_tip))
+            }
+        };
+
+        // Hold the nonce until the node accepted the transaction
+        let mut account = self.account.lock().await;
+        let nonce = match tx.nonce {
+            Some(nonce) => nonce,
+            None => self.load_nonce(&mut account.next_nonce).await?,
+        };
+        let signed = Eip1559Transaction {
+            chain_id: self.chain_id,
+            nonce,
+            max_priority_fee_per_gas,
+            max_fee_per_gas,
+            gas_limit,
+            to: tx.to,
+            value: tx.value,
+            data: tx.data,
+        };
+        let raw = signer.sign_transaction(&signed)?;
+        match self.rpc.send_raw_transaction(&raw).await {
+            Ok(hash) => {
+                if tx.nonce.is_none() {
+                    account.next_nonce = Some(nonce + 1);
+                }
+                debug!("Submitted transaction 0x{} (nonce {})", hex::encode(hash), nonce);
+                account.submitted(signed, hash);
+                Ok(hash)
+            }
+            Err(e) => {
+                warn!("Transaction with nonce {} rejected: {}", nonce, e);
+                account.next_nonce = None;
+                Err(e)
+            }
+        }
+    }
+
+    /// Resubmit a pending transaction with higher fees
+    ///
+    /// Fees rise by the fee bump percentage (15% without a policy), and at
+    /// least to the current suggested fees.
+    ///
+    /// # Returns
+    /// Hash of the replacement
+    pub async fn speed_up(&self, nonce: u64) -> Result<TxHash, ChainError> {
+        let tx = self.pending_tx(nonce).await?;
+        self.replace(tx).await
+    }
+
+    /// Replace a pending transaction with an empty transfer to this account
+    ///
+    /// Whichever version is mined uses up the nonce, so the original call
+    /// only takes effect if it was mined first.
+    ///
+    /// # Returns
+    /// Hash of the replacement
+    pub async fn cancel_transaction(&self, nonce: u64) -> Result<TxHash, ChainError> {
+        let tx = self.pending_tx(nonce).await?;
+        let cancel = Eip1559Transaction {
+            gas_limit: TRANSFER_GAS,
+            to: Some(self.signer()?.address()),
+            value: 0,
+            data: Vec::new(),
+            ..tx
+        };
+        self.replace(cancel).await
+    }
+
+    async fn pending_tx(&self, nonce: u64) -> Result<Eip1559Transaction, ChainError> {
+        self.account
+            .lock()
+            .await
+            .pending
+            .get(&nonce)
+            .map(|p| p.tx.clone())
+            .ok_or_else(|| ChainError::Signer(format!("No pending transaction with nonce {}", nonce)))
+    }
+
+    /// Sign and submit `tx` with bumped fees in place of the pending transaction with its nonce
+    async fn replace(&self, tx: Eip1559Transaction) -> Result<TxHash, ChainError> {
+        let policy = self.fee_bump.unwrap_or_default();
+        let (suggested_fee, suggested_tip) = self.fees().await?;
+        let tip = policy.bump(tx.max_priority_fee_per_gas).max(suggested_tip);
+        let replacement = Eip1559Transaction {
+            max_priority_fee_per_gas: tip,
+            max_fee_per_gas: policy.bump(tx.max_fee_per_gas).max(suggested_fee).max(tip),
+            ..tx
+        };
+        let raw = self.signer()?.sign_transaction(&replacement)?;
+
+        let mut account = self.account.lock().await;
+        let hash = self.rpc.send_raw_transaction(&raw).await?;
+        debug!(
+            "Replaced transaction with nonce {} by 0x{} ({} wei/gas)",
+            replacement.nonce,
+            hex::encode(hash),
+            replacement.max_fee_per_gas
+        );
+        account.submitted(replacement, hash);
+        Ok(hash)
+    }
+
+    /// Wait until a transaction is mined
+    ///
+    /// For transactions submitted by this client, any version of it counts,
+    /// so the receipt may be for a replacement. Versions unmined for longer
+    /// than the fee bump policy allows are replaced meanwhile.
+    ///
+    /// # Arguments
+    /// * `hash` - Transaction hash
+    /// * `timeout` - Maximum time to wait
+    pub async fn wait_for_receipt(&self, hash: &TxHash, timeout: Duration) -> Result<TransactionReceipt, ChainError> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let pending = self.account.lock().await.find(hash).cloned();
+            let hashes = pending.as_ref().map_or_else(|| vec![*hash], |p| p.hashes.clone());
+            for version in hashes.iter().rev() {
+                if let Some(receipt) = self.rpc.transaction_receipt(version).await? {
+                    if let Some(pending) = &pending {
+                        self.account.lock().await.mined(pending.nonce());
+                    }
+                    return Ok(receipt);
+                }
+            }
+            if let (Some(pending), Some(policy)) = (&pending, self.fee_bump) {
+                if pending.submitted_at.elapsed() >= policy.replace_after && pending.replacements() < policy.max_replacements {
+                    if let Err(e) = self.speed_up(pending.nonce()).await {
+                        warn!("Replacing transaction with nonce {} failed: {}", pending.nonce(), e);
+                    }
+                }
+            }
+            if tokio::time::Instant::now() + RECEIPT_POLL_INTERVAL > deadline {
+                return Err(ChainError::Timeout(format!("transaction 0x{}", hex::encode(hash))));
+            }
+            tokio::time::sleep(RECEIPT_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Call a contract function without submitting a transaction
+    ///
+    /// # Arguments
+    /// * `contract` - Contract address
+    /// * `signature` - Canonical function signature, e.g. "balanceOf(address)"
+    /// * `args` - Arguments
+    /// * `outputs` - Types of the return values
+    pub async fn call(
+        &self,
+        contract: Address,
+        signature: &str,
+        args: &[Token],
+        outputs: &[ParamType],
+    ) -> Result<Vec<Token>, ChainError> {
+        let tx = TransactionRequest::call(contract, abi::encode_call(signature, args));
+        let data = self.rpc.call(self.address(), &tx).await?;
+        abi::decode(outputs, &data)
+    }
+
+    /// Submit a contract call and wait until it is mined
+    ///
+    /// # Arguments
+    /// * `contract` - Contract address
+    /// * `signature` - Canonical function signature
+    /// * `args` - Arguments
+    /// * `value` - Value sent with the call in wei
+    ///
+    /// # Returns
+    /// Receipt of the transaction; a reverted one is a `Reverted` error
+    pub async fn transact(
+        &self,
+        contract: Address,
+        signature: &str,
+        args: &[Token],
+        value: u128,
+    ) -> Result<TransactionReceipt, ChainError> {
+        let tx = TransactionRequest { value, ..TransactionRequest::call(contract, abi::encode_call(signature, args)) };
+        let hash = self.send_transaction(tx).await?;
+        let receipt = self.wait_for_receipt(&hash, TRANSACT_TIMEOUT).await?;
+        if !receipt.success {
+            return Err(ChainError::Reverted(format!("{} in transaction 0x{}", signature, hex::encode(hash))));
+        }
+        Ok(receipt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex as StdMutex};
+    use serde_json::{json, Value};
+    use crate::chain::{keccak256, mock, parse_data};
+
+    const KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    /// Node with nonce 5 that accepts every transaction except the second
+    async fn node(sent: Arc<StdMutex<Vec<Vec<u8>>>>) -> String {
+        mock::serve(move |method, params| match method {
+            "eth_getTransactionCount" => Ok(json!("0x5")),
+            "eth_getBalance" => Ok(json!("0xde0b6b3a7640000")),
+            "eth_getBlockByNumber" => Ok(json!({ "number": "0x10", "baseFeePerGas": "0x3b9aca00" })),
+            "eth_maxPriorityFeePerGas" => Ok(json!("0x77359400")),
+            "eth_estimateGas" => Ok(json!("0x5208")),
+            "eth_sendRawTransaction" => {
+                let raw = parse_data(params[0].as_str().unwrap()).unwrap();
+                let mut sent = sent.lock().unwrap();
+                sent.push(raw.clone());
+                if sent.len() == 2 {
+                    return Err((-32000, "nonce too low".into()));
+                }
+                Ok(json!(format!("0x{}", hex::encode(keccak256(&raw)))))
+            }
+            "eth_getTransactionReceipt" => Ok(json!({
+                "transactionHash": params[0],
+                "blockNumber": "0x11",
+                "gasUsed": "0x5208",
+                "status": "0x1",
+                "contractAddress": Value::Null,
+            })),
+            _ => Err((-32601, format!("method {} not found", method))),
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_send_transaction() {
+        let sent = Arc::new(StdMutex::new(Vec::new()));
+        let url = node(sent.clone()).await;
+        let client = ChainClient::new(&url, 16602).unwrap().with_signer(ChainSigner::from_hex(KEY).unwrap());
+        let to: Address = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".parse().unwrap();
+
+        assert_eq!(client.balance(&to).await.unwrap(), 10u128.pow(18));
+        assert_eq!(client.fees().await.unwrap(), (4_000_000_000, 2_000_000_000));
+        assert_eq!(client.estimate_gas(&TransactionRequest::transfer(to, 1)).await.unwrap(), 25_200);
+
+        // Nonces are handed out locally
+        let hash = client.send_transaction(TransactionRequest::transfer(to, 1)).await.unwrap();
+        assert_eq!(client.nonce().await.unwrap(), 6);
+        let receipt = client.wait_for_receipt(&hash, Duration::from_secs(1)).await.unwrap();
+        assert_eq!((receipt.transaction_hash, receipt.block_number, receipt.success), (hash, 17, true));
+
+        // A rejected transaction makes the client re-read the nonce
+        assert!(matches!(
+            client.send_transaction(TransactionRequest::transfer(to, 1)).await,
+            Err(ChainError::Rpc { code: -32000, .. })
+        ));
+        assert_eq!(client.nonce().await.unwrap(), 5);
+
+        let sent = sent.lock().unwrap();
+        let expected = Eip1559Transaction {
+            chain_id: 16602,
+            nonce: 5,
+            max_priority_fee_per_gas: 2_000_000_000,
+            max_fee_per_gas: 4_000_000_000,
+            gas_limit: 25_200,
+            to: Some(to),
+            value: 1,
+            data: vec![],
+        };
+        assert_eq!(sent[0], ChainSigner::from_hex(KEY).unwrap().sign_transaction(&expected).unwrap());
+        let second = Eip1559Transaction { nonce: 6, ..expected };
+        assert_eq!(sent[1], ChainSigner::from_hex(KEY).unwrap().sign_transaction(&second).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_replace_stuck_transaction() {
+        // Node that only mines replacements
+        let sent = Arc::new(StdMutex::new(Vec::<Vec<u8>>::new()));
+        let node_sent = sent.clone();
+        let url = mock::serve(move |method, params| match method {
+            "eth_getTransactionCount" => Ok(json!("0x5")),
+            "eth_getBlockByNumber" => Ok(json!({ "number": "0x10", "baseFeePerGas": "0x3b9aca00" })),
+            "eth_maxPriorityFeePerGas" => Ok(json!("0x77359400")),
+            "eth_sendRawTransaction" => {
+                let raw = parse_data(params[0].as_str().unwrap()).unwrap();
+                node_sent.lock().unwrap().push(raw.clone());
+                Ok(json!(format!("0x{}", hex::encode(keccak256(&raw)))))
+            }
+            "eth_getTransactionReceipt" => {
+                let sent = node_sent.lock().unwrap();
+                let first = format!("0x{}", hex::encode(keccak256(&sent[0])));
+                if params[0] == json!(first) {
+                    return Ok(Value::Null);
+                }
+                Ok(json!({
+                    "transactionHash": params[0],
+                    "blockNumber": "0x11",
+                    "gasUsed": "0x5208",
+                    "status": "0x1",
+                    "contractAddress": Value::Null,
+                }))
+            }
+            _ => Err((-32601, format!("method {} not found", method))),
+        })
+        .await;
+        let policy = FeeBumpPolicy { replace_after: Duration::ZERO, ..Default::default() };
+        let signer = ChainSigner::from_hex(KEY).unwrap();
+        let client = ChainClient::new(&url, 16602).unwrap().with_signer(signer.clone()).with_fee_bump(Some(policy));
+        let to: Address = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".parse().unwrap();
+
+        let request = TransactionRequest { gas_limit: Some(21_000), ..TransactionRequest::transfer(to, 1) };
+        let hash = client.send_transaction(request.clone()).await.unwrap();
+        let pending = client.pending_transactions().await;
+        assert_eq!((pending.len(), pending[0].nonce(), pending[0].hash()), (1, 5, hash));
+
+        // Waiting on the original returns the replacement's receipt
+        let receipt = client.wait_for_receipt(&hash, Duration::from_secs(5)).await.unwrap();
+        assert_ne!(receipt.transaction_hash, hash);
+        assert!(client.pending_transactions().await.is_empty());
+        let original = Eip1559Transaction {
+            chain_id: 16602,
+            nonce: 5,
+            max_priority_fee_per_gas: 2_000_000_000,
+            max_fee_per_gas: 4_000_000_000,
+            gas_limit: 21_000,
+            to: Some(to),
+            value: 1,
+            data: vec![],
+        };
+        let bumped = Eip1559Transaction { max_priority_fee_per_gas: 2_300_000_000, max_fee_per_gas: 4_600_000_000, ..original.clone() };
+        assert_eq!(sent.lock().unwrap()[1], signer.sign_transaction(&bumped).unwrap());
+        assert_eq!(receipt.transaction_hash, keccak256(&signer.sign_transaction(&bumped).unwrap()));
+
+        // Cancelling sends an empty transfer to the account with the same nonce
+        let hash = client.send_transaction(request).await.unwrap();
+        assert!(matches!(client.speed_up(7).await, Err(ChainError::Signer(_))));
+        let cancel = client.cancel_transaction(6).await.unwrap();
+        assert_eq!(client.pending_transactions().await[0].hashes, vec![hash, cancel]);
+        let expected = Eip1559Transaction { nonce: 6, to: Some(signer.address()), value: 0, ..bumped };
+        assert_eq!(sent.lock().unwrap()[3], signer.sign_transaction(&expected).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_read_only_client() {
+        let url = node(Default::default()).await;
+        let config = OpacusConfig {
+            network: crate::types::Network::Testnet,
+            relay_url: String::new(),
+            chain_rpc: url,
+            private_key: None,
+        };
+        let client = ChainClient::from_config(&config).unwrap();
+        assert_eq!(client.chain_id(), 16602);
+        assert!(client.address().is_none());
+        assert!(matches!(
+            client.send_transaction(TransactionRequest::default()).await,
+            Err(ChainError::Signer(_))
+        ));
+        assert!(matches!(client.rpc().chain_id().await, Err(ChainError::Rpc { code: -32601, .. })));
+    }
+}
@@ -0,0 +1,391 @@
+//! DAC registry: on-chain publication and discovery of `DACConfig`s
+//!
+//! The `DACRegistry` contract stores an ID, owner, stake and metadata URI per
+//! DAC; the full configuration (metadata, tags, channels and pricing) is a
+//! JSON document in a [`ContentStore`]. Discovery walks `DACRegistered`
+//! events and filters the fetched configurations by tag.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use tracing::warn;
+use crate::types::DACConfig;
+use super::abi::{event_topic, ParamType, Token};
+use super::{Address, ChainClient, ChainError, ContentStore, LogFilter, TransactionReceipt};
+
+/// Event emitted for each new DAC
+const DAC_REGISTERED: &str = "DACRegistered(bytes32,address,string)";
+
+/// Registry record of a DAC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DacRecord {
+    /// DAC ID, assigned by the registry
+    pub id: [u8; 32],
+    /// Owning account
+    pub owner: Address,
+    /// URI of the configuration document
+    pub metadata_uri: String,
+    /// Registration time (Unix seconds)
+    pub created: u64,
+    /// Last update (Unix seconds)
+    pub updated: u64,
+    /// Cleared when the owner deprecates the DAC
+    pub active: bool,
+    /// Stake in wei
+    pub stake: u128,
+}
+
+/// DAC found in the registry, with its configuration
+#[derive(Debug, Clone)]
+pub struct RegisteredDac {
+    /// Registry record
+    pub record: DacRecord,
+    /// Published configuration; `id` and `owner` are taken from the record
+    pub config: DACConfig,
+}
+
+/// Client for the `DACRegistry` contract
+#[derive(Debug)]
+pub struct DacRegistry<S> {
+    chain: Arc<ChainClient>,
+    contract: Address,
+    store: S,
+}
+
+impl<S: ContentStore> DacRegistry<S> {
+    /// Create registry client
+    ///
+    /// # Arguments
+    /// * `chain` - Chain client; publishing needs a signer
+    /// * `contract` - `DACRegistry` address
+    /// * `store` - Store for configuration documents
+    pub fn new(chain: Arc<ChainClient>, contract: Address, store: S) -> Self {
+        Self { chain, contract, store }
+    }
+
+    /// Publish a new DAC
+    ///
+    /// Uploads the configuration and registers it with `stake` plus the
+    /// registry's fee. Sets `config.id` and `config.owner`.
+    ///
+    /// # Returns
+    /// Assigned DAC ID
+    pub async fn publish(&self, config: &mut DACConfig, stake: u128) -> Result<[u8; 32], ChainError> {
+        config.owner = self.chain.signer()?.address().to_string();
+        let fee = self.uint("registrationFee()").await?;
+        let uri = self.upload(config).await?;
+        let receipt = self.chain.transact(self.contract, "registerDAC(string)", &[Token::String(uri)], stake + fee).await?;
+        let topic = event_topic(DAC_REGISTERED);
+        let id = receipt
+            .logs
+            .iter()
+            .find(|log| log.address == self.contract && log.topics.first() == Some(&topic))
+            .and_then(|log| log.topics.get(1).copied())
+            .ok_or_else(|| ChainError::InvalidResponse("No DACRegistered event in receipt".into()))?;
+        config.id = format!("0x{}", hex::encode(id));
+        Ok(id)
+    }
+
+    /// Publish a new version of a DAC's configuration (owner only)
+    pub async fn update(&self, config: &DACConfig) -> Result<TransactionReceipt, ChainError> {
+        let id = parse_id(&config.id)?;
+        let uri = self.upload(config).await?;
+        let args = [Token::FixedBytes(id), Token::String(uri)];
+        self.chain.transact(self.contract, "updateDAC(bytes32,string)", &args, 0).await
+    }
+
+    /// Deprecate a DAC (owner only); it stays readable but is no longer discovered
+    pub async fn deprecate(&self, id: [u8; 32]) -> Result<TransactionReceipt, ChainError> {
+        self.chain.transact(self.contract, "deactivateDAC(bytes32)", &[Token::FixedBytes(id)], 0).await
+    }
+
+    /// Registry record of a DAC (`None` if unknown)
+    pub async fn record(&self, id: [u8; 32]) -> Result<Option<DacRecord>, ChainError> {
+        let dac = ParamType::Tuple(vec![
+            ParamType::FixedBytes,
+            ParamType::Address,
+            ParamType::String,
+            ParamType::Uint,
+            ParamType::Uint,
+            ParamType::Bool,
+            ParamType::Uint,
+        ]);
+        let values = self.chain.call(self.contract, "getDAC(bytes32)", &[Token::FixedBytes(id)], &[dac]).await?;
+        let mut fields = values.into_iter().next().and_then(Token::into_items).unwrap_or_default().into_iter();
+        let mut next = || fields.next().ok_or_else(|| ChainError::InvalidResponse("Short DAC record".into()));
+        next()?; // ID, as requested
+        let record = DacRecord {
+            id,
+            owner: next()?.into_address().unwrap_or_default(),
+            metadata_uri: next()?.into_string().unwrap_or_default(),
+            created: next()?.into_uint().unwrap_or_default() as u64,
+            updated: next()?.into_uint().unwrap_or_default() as u64,
+            active: next()?.into_bool().unwrap_or_default(),
+            stake: next()?.into_uint().unwrap_or_default(),
+        };
+        Ok((record.created > 0).then_some(record))
+    }
+
+    /// DAC with its configuration (`None` if unknown)
+    pub async fn get(&self, id: [u8; 32]) -> Result<Option<RegisteredDac>, ChainError> {
+        let Some(record) = self.record(id).await? else {
+            return Ok(None);
+        };
+        let document = self.store.get(&record.metadata_uri).await?;
+        let mut config: DACConfig = serde_json::from_slice(&document)
+            .map_err(|e| ChainError::Storage(format!("Invalid DAC config at {}: {}", record.metadata_uri, e)))?;
+        config.id = format!("0x{}", hex::encode(id));
+        config.owner = record.owner.to_string();
+        Ok(Some(RegisteredDac { record, config }))
+    }
+
+    /// DACs owned by an account
+    pub async fn owned_by(&self, owner: &Address) -> Result<Vec<[u8; 32]>, ChainError> {
+        let ids = ParamType::Array(Box::new(ParamType::FixedBytes));
+        let values = self.chain.call(self.contract, "getOwnerDACs(address)", &[Token::Address(*owner)], &[ids]).await?;
+        Ok(values
+            .into_iter()
+            .next()
+            .and_then(Token::into_items)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(Token::into_fixed_bytes)
+            .collect())
+    }
+
+    /// Find active DACs carrying all of `tags` (case-insensitive)
+    ///
+    /// DACs whose configuration cannot be fetched are skipped.
+    ///
+    /// # Arguments
+    /// * `tags` - Required tags; empty matches every DAC
+    /// * `from_block` - First block to search for registrations
+    pub async fn search(&self, tags: &[&str], from_block: u64) -> Result<Vec<RegisteredDac>, ChainError> {
+        let filter = LogFilter {
+            address: Some(self.contract),
+            topics: vec![Some(event_topic(DAC_REGISTERED))],
+            from_block,
+            to_block: None,
+        };
+        let mut seen = HashSet::new();
+        let mut found = Vec::new();
+        for log in self.chain.rpc().logs(&filter).await? {
+            let Some(&id) = log.topics.get(1) else { continue };
+            if !seen.insert(id) {
+                continue;
+            }
+            match self.get(id).await {
+                Ok(Some(dac)) if dac.record.active && has_tags(&dac.config, tags) => found.push(dac),
+                Ok(_) => {}
+                Err(e) => warn!("Skipping DAC 0x{}: {}", hex::encode(id), e),
+            }
+        }
+        Ok(found)
+    }
+
+    async fn upload(&self, config: &DACConfig) -> Result<String, ChainError> {
+        let document = serde_json::to_vec(config).map_err(|e| ChainError::Storage(e.to_string()))?;
+        self.store.put(document).await
+    }
+
+    async fn uint(&self, signature: &str) -> Result<u128, ChainError> {
+        let values = self.chain.call(self.contract, signature, &[], &[ParamType::Uint]).await?;
+        values
+            .into_iter()
+            .next()
+            .and_then(Token::into_uint)
+            .ok_or_else(|| ChainError::InvalidResponse(format!("{} returned nothing", signature)))
+    }
+}
+
+fn has_tags(config: &DACConfig, tags: &[&str]) -> bool {
+    tags.iter().all(|tag| config.metadata.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)))
+}
+
+fn parse_id(id: &str) -> Result<[u8; 32], ChainError> {
+    hex::decode(id.strip_prefix("0x").unwrap_or(id))
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| ChainError::InvalidResponse(format!("Invalid DAC ID: {}", id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use serde_json::{json, Value};
+    use crate::chain::abi::{self, encode, selector};
+    use crate::chain::storage::MemoryStore;
+    use crate::chain::{keccak256, mock, parse_data, parse_quantity, ChainSigner};
+    use crate::types::{ChannelType, DACMetadata, DataChannel};
+
+    const KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    fn registry() -> Address {
+        "0x12fEbDd82739D88A731b2a0f308644eA0F99d9cE".parse().unwrap()
+    }
+
+    fn config(name: &str, tags: &[&str]) -> DACConfig {
+        DACConfig {
+            id: String::new(),
+            owner: String::new(),
+            metadata: DACMetadata {
+                name: name.into(),
+                description: String::new(),
+                version: "1.0.0".into(),
+                tags: tags.iter().map(|t| t.to_string()).collect(),
+            },
+            channels: vec![DataChannel {
+                id: "prices".into(),
+                channel_type: ChannelType::Output,
+                price_per_byte: 2,
+                price_per_msg: 100,
+                access: None,
+                plan: None,
+            }],
+        }
+    }
+
+    /// Registry contract backed by a list of `(uri, active)` DACs
+    #[derive(Default)]
+    struct Contract {
+        dacs: Vec<(String, bool)>,
+        values: Vec<u128>,
+    }
+
+    fn dac_id(index: usize) -> [u8; 32] {
+        keccak256(&index.to_be_bytes())
+    }
+
+    fn hex_data(data: &[u8]) -> Value {
+        json!(format!("0x{}", hex::encode(data)))
+    }
+
+    fn handle(contract: &Mutex<Contract>, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        let owner = ChainSigner::from_hex(KEY).unwrap().address();
+        let mut contract = contract.lock().unwrap();
+        match method {
+            "eth_getTransactionCount" => Ok(json!("0x0")),
+            "eth_getBlockByNumber" => Ok(json!({ "number": "0x10", "baseFeePerGas": "0x1" })),
+            "eth_maxPriorityFeePerGas" => Ok(json!("0x1")),
+            "eth_estimateGas" => {
+                let data = parse_data(params[0]["data"].as_str().unwrap()).unwrap();
+                if data[..4] == selector("registerDAC(string)") {
+                    contract.values.push(parse_quantity(params[0]["value"].as_str().unwrap()).unwrap());
+                }
+                Ok(json!("0x186a0"))
+            }
+            "eth_call" => {
+                let data = parse_data(params[0]["data"].as_str().unwrap()).unwrap();
+                if data[..4] == selector("registrationFee()") {
+                    return Ok(hex_data(&encode(&[Token::Uint(2)])));
+                }
+                if data[..4] == selector("getOwnerDACs(address)") {
+                    let ids = (0..contract.dacs.len()).map(|i| Token::FixedBytes(dac_id(i))).collect();
+                    return Ok(hex_data(&encode(&[Token::Array(ids)])));
+                }
+                let index = (0..contract.dacs.len()).find(|&i| data[4..] == dac_id(i));
+                let (id, uri, active, created) = match index {
+                    Some(i) => (dac_id(i), contract.dacs[i].0.clone(), contract.dacs[i].1, 1_700_000_000),
+                    None => ([0; 32], String::new(), false, 0),
+                };
+                Ok(hex_data(&encode(&[Token::Tuple(vec![
+                    Token::FixedBytes(id),
+                    Token::Address(owner),
+                    Token::String(uri),
+                    Token::Uint(created),
+                    Token::Uint(created),
+                    Token::Bool(active),
+                    Token::Uint(10),
+                ])])))
+            }
+            "eth_sendRawTransaction" => {
+                let raw = parse_data(params[0].as_str().unwrap()).unwrap();
+                // Apply the call in the transaction to the contract state
+                let call = |signature: &str| raw.windows(4).position(|w| w == selector(signature));
+                if let Some(at) = call("registerDAC(string)") {
+                    let args = abi::decode(&[ParamType::String], &raw[at + 4..]).unwrap();
+                    contract.dacs.push((args[0].clone().into_string().unwrap(), true));
+                } else if let Some(at) = call("updateDAC(bytes32,string)") {
+                    let args = abi::decode(&[ParamType::FixedBytes, ParamType::String], &raw[at + 4..]).unwrap();
+                    let i = (0..contract.dacs.len()).find(|&i| Some(dac_id(i)) == args[0].clone().into_fixed_bytes());
+                    contract.dacs[i.unwrap()].0 = args[1].clone().into_string().unwrap();
+                } else if let Some(at) = call("deactivateDAC(bytes32)") {
+                    let i = (0..contract.dacs.len()).find(|&i| raw[at + 4..at + 36] == dac_id(i));
+                    contract.dacs[i.unwrap()].1 = false;
+                }
+                Ok(hex_data(&keccak256(&raw)))
+            }
+            "eth_getTransactionReceipt" => {
+                let registered = dac_id(contract.dacs.len() - 1);
+                Ok(json!({
+                    "transactionHash": params[0],
+                    "blockNumber": "0x11",
+                    "gasUsed": "0x186a0",
+                    "status": "0x1",
+                    "contractAddress": Value::Null,
+                    "logs": [{
+                        "address": registry(),
+                        "topics": [hex_data(&event_topic(DAC_REGISTERED)), hex_data(&registered), hex_data(&[0; 32])],
+                        "data": "0x",
+                        "blockNumber": "0x11",
+                        "transactionHash": params[0],
+                    }],
+                }))
+            }
+            "eth_getLogs" => {
+                assert_eq!(params[0]["topics"][0], hex_data(&event_topic(DAC_REGISTERED)));
+                let logs: Vec<Value> = (0..contract.dacs.len())
+                    .map(|i| {
+                        json!({
+                            "address": registry(),
+                            "topics": [hex_data(&event_topic(DAC_REGISTERED)), hex_data(&dac_id(i)), hex_data(&[0; 32])],
+                            "data": "0x",
+                            "blockNumber": "0x11",
+                            "transactionHash": hex_data(&[i as u8; 32]),
+                        })
+                    })
+                    .collect();
+                Ok(json!(logs))
+            }
+            _ => Err((-32601, format!("method {} not found", method))),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_publish_and_search() {
+        let contract = Arc::new(Mutex::new(Contract::default()));
+        let state = contract.clone();
+        let url = mock::serve(move |method, params| handle(&state, method, params)).await;
+        let signer = ChainSigner::from_hex(KEY).unwrap();
+        let chain = Arc::new(ChainClient::new(&url, 16661).unwrap().with_signer(signer.clone()));
+        let registry = DacRegistry::new(chain, registry(), MemoryStore::default());
+
+        let mut oracle = config("Price Oracle", &["defi", "Prices"]);
+        let id = registry.publish(&mut oracle, 5).await.unwrap();
+        assert_eq!((id, oracle.id.clone()), (dac_id(0), format!("0x{}", hex::encode(dac_id(0)))));
+        assert_eq!(oracle.owner, signer.address().to_string());
+        // Stake plus registration fee
+        assert_eq!(contract.lock().unwrap().values, vec![7]);
+
+        let mut weather = config("Weather", &["data"]);
+        registry.publish(&mut weather, 5).await.unwrap();
+        assert_eq!(registry.owned_by(&signer.address()).await.unwrap(), vec![dac_id(0), dac_id(1)]);
+
+        let found = registry.get(id).await.unwrap().unwrap();
+        assert_eq!(found.config.id, oracle.id);
+        assert_eq!(found.config.channels[0].price(10), 120);
+        assert!(registry.get([9; 32]).await.unwrap().is_none());
+
+        let names = |dacs: Vec<RegisteredDac>| dacs.into_iter().map(|d| d.config.metadata.name).collect::<Vec<_>>();
+        assert_eq!(names(registry.search(&["prices", "DEFI"], 0).await.unwrap()), ["Price Oracle"]);
+        assert_eq!(names(registry.search(&[], 0).await.unwrap()), ["Price Oracle", "Weather"]);
+
+        oracle.metadata.tags.push("feeds".into());
+        registry.update(&oracle).await.unwrap();
+        assert_eq!(names(registry.search(&["feeds"], 0).await.unwrap()), ["Price Oracle"]);
+
+        registry.deprecate(id).await.unwrap();
+        assert!(registry.search(&["defi"], 0).await.unwrap().is_empty());
+        assert!(!registry.record(id).await.unwrap().unwrap().active);
+    }
+}
@@ -0,0 +1,168 @@
+//! EIP-712 typed structured data
+//!
+//! Payment intents and other off-chain authorizations are signed as typed
+//! data so contracts can check them with `ecrecover`.
+
+use serde::{Deserialize, Serialize};
+use super::abi::{self, Token};
+use super::{keccak256, recover_address, Address, ChainError, ChainSigner};
+
+/// Type string of the domain (all fields used)
+const DOMAIN_TYPE: &str = "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)";
+
+/// Signing domain: binds signatures to one contract on one chain
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Eip712Domain {
+    /// Protocol name
+    pub name: String,
+    /// Protocol version
+    pub version: String,
+    /// Chain ID
+    pub chain_id: u64,
+    /// Contract that verifies the signatures
+    pub verifying_contract: Address,
+}
+
+impl Eip712Domain {
+    /// Create domain
+    pub fn new(name: &str, version: &str, chain_id: u64, verifying_contract: Address) -> Self {
+        Self {
+            name: name.to_string(),
+            version: version.to_string(),
+            chain_id,
+            verifying_contract,
+        }
+    }
+
+    /// Domain separator
+    pub fn separator(&self) -> [u8; 32] {
+        keccak256(&abi::encode(&[
+            Token::FixedBytes(keccak256(DOMAIN_TYPE.as_bytes())),
+            Token::FixedBytes(keccak256(self.name.as_bytes())),
+            Token::FixedBytes(keccak256(self.version.as_bytes())),
+            Token::Uint(self.chain_id.into()),
+            Token::Address(self.verifying_contract),
+        ]))
+    }
+}
+
+/// Struct signed as EIP-712 typed data
+pub trait TypedData {
+    /// Encoded type, e.g. `"Mail(address from,address to,string contents)"`,
+    /// followed by referenced struct types in alphabetical order
+    const ENCODED_TYPE: &'static str;
+
+    /// Member values in type order (`encodeData` without the type hash)
+    ///
+    /// `string` and `bytes` members are passed as the keccak256 of their
+    /// contents and nested structs as their `struct_hash`, both as `FixedBytes`.
+    fn members(&self) -> Vec<Token>;
+
+    /// Type hash
+    fn type_hash() -> [u8; 32] {
+        keccak256(Self::ENCODED_TYPE.as_bytes())
+    }
+
+    /// Struct hash (`hashStruct`)
+    fn struct_hash(&self) -> [u8; 32] {
+        let mut tokens = vec![Token::FixedBytes(Self::type_hash())];
+        tokens.extend(self.members());
+        keccak256(&abi::encode(&tokens))
+    }
+}
+
+/// Hash to sign for a typed value
+pub fn typed_data_hash<T: TypedData>(domain: &Eip712Domain, value: &T) -> [u8; 32] {
+    let mut data = Vec::with_capacity(66);
+    data.extend_from_slice(&[0x19, 0x01]);
+    data.extend_from_slice(&domain.separator());
+    data.extend_from_slice(&value.struct_hash());
+    keccak256(&data)
+}
+
+impl ChainSigner {
+    /// Sign typed data
+    ///
+    /// # Returns
+    /// `r || s || v` with `v` 27 or 28, as expected by `ecrecover`
+    pub fn sign_typed_data<T: TypedData>(&self, domain: &Eip712Domain, value: &T) -> Result<[u8; 65], ChainError> {
+        let mut signature = self.sign_hash(&typed_data_hash(domain, value))?;
+        signature[64] += 27;
+        Ok(signature)
+    }
+}
+
+/// Recover the signer of typed data
+pub fn recover_typed_data<T: TypedData>(domain: &Eip712Domain, value: &T, signature: &[u8; 65]) -> Result<Address, ChainError> {
+    recover_address(&typed_data_hash(domain, value), signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Person {
+        name: &'static str,
+        wallet: Address,
+    }
+
+    impl TypedData for Person {
+        const ENCODED_TYPE: &'static str = "Person(string name,address wallet)";
+
+        fn members(&self) -> Vec<Token> {
+            vec![Token::FixedBytes(keccak256(self.name.as_bytes())), Token::Address(self.wallet)]
+        }
+    }
+
+    struct Mail {
+        from: Person,
+        to: Person,
+        contents: &'static str,
+    }
+
+    impl TypedData for Mail {
+        const ENCODED_TYPE: &'static str = "Mail(Person from,Person to,string contents)Person(string name,address wallet)";
+
+        fn members(&self) -> Vec<Token> {
+            vec![
+                Token::FixedBytes(self.from.struct_hash()),
+                Token::FixedBytes(self.to.struct_hash()),
+                Token::FixedBytes(keccak256(self.contents.as_bytes())),
+            ]
+        }
+    }
+
+    /// Example from the EIP-712 specification
+    #[test]
+    fn test_eip712_spec_vector() {
+        let domain = Eip712Domain::new(
+            "Ether Mail",
+            "1",
+            1,
+            "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC".parse().unwrap(),
+        );
+        let mail = Mail {
+            from: Person { name: "Cow", wallet: "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826".parse().unwrap() },
+            to: Person { name: "Bob", wallet: "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB".parse().unwrap() },
+            contents: "Hello, Bob!",
+        };
+        assert_eq!(hex::encode(domain.separator()), "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f");
+        assert_eq!(hex::encode(mail.struct_hash()), "c52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e");
+        assert_eq!(hex::encode(typed_data_hash(&domain, &mail)), "be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2");
+
+        let signer = ChainSigner::from_bytes(&keccak256(b"cow")).unwrap();
+        assert_eq!(signer.address(), mail.from.wallet);
+        let signature = signer.sign_typed_data(&domain, &mail).unwrap();
+        assert_eq!(
+            hex::encode(signature),
+            "4355c47d63924e8a72e509b65029052eb6c299d53a04e167c5775fd466751c9d\
+             07299936d304c153f6443dfa05f40ff007d72911b6f72307f996231605b91562\
+             1c"
+        );
+        assert_eq!(recover_typed_data(&domain, &mail, &signature).unwrap(), signer.address());
+
+        let other = Eip712Domain { chain_id: 2, ..domain.clone() };
+        assert_ne!(recover_typed_data(&other, &mail, &signature).unwrap(), signer.address());
+    }
+}
@@ -0,0 +1,400 @@
+//! Escrowed paid messaging (`MsgEscrow` contract)
+//!
+//! 1. The requester locks ERC-20 tokens for the responder under the request
+//!    frame's lock ID ([`escrow_lock_id`]) with the frame's [`message_hash`],
+//!    then sends the request.
+//! 2. The responder finds the lock from the request ([`EscrowLock::check_request`])
+//!    and delivers the response.
+//! 3. The requester signs an [`EscrowRelease`] over a [`DeliveryReceipt`] for
+//!    the response and hands it to the responder.
+//! 4. An authorized relayer checks the release against the lock and calls
+//!    `release`, paying the responder (minus the protocol fee).
+//!
+//! Without a release the requester can `cancel` the lock once it expires;
+//! until then the responder can still be paid, which is the dispute window.
+
+use serde::{Deserialize, Serialize};
+use crate::types::{OpacusFrame, Ulid};
+use super::abi::{ParamType, Token};
+use super::{
+    hex_bytes, keccak256, message_hash, recover_typed_data, Address, ChainClient, ChainError, ChainSigner,
+    DeliveryReceipt, Eip712Domain, TransactionReceipt, TypedData,
+};
+
+/// EIP-712 domain name of release authorizations
+pub const ESCROW_DOMAIN_NAME: &str = "Opacus Message Escrow";
+
+/// EIP-712 domain version of release authorizations
+pub const ESCROW_DOMAIN_VERSION: &str = "1";
+
+/// Signing domain of an escrow contract
+pub fn escrow_domain(chain_id: u64, contract: Address) -> Eip712Domain {
+    Eip712Domain::new(ESCROW_DOMAIN_NAME, ESCROW_DOMAIN_VERSION, chain_id, contract)
+}
+
+/// Frame extension carrying an [`EscrowRelease`]
+pub const ESCROW_RELEASE_EXTENSION: &str = "escrowRelease";
+
+/// Lock ID of an escrowed request
+pub fn escrow_lock_id(message_id: &Ulid) -> [u8; 32] {
+    keccak256(&message_id.to_bytes())
+}
+
+/// Escrow lock, as stored by the contract
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EscrowLock {
+    /// Lock ID
+    pub id: [u8; 32],
+    /// Requester account
+    pub payer: Address,
+    /// Responder account
+    pub payee: Address,
+    /// Locked amount in the contract's payment token
+    pub amount: u128,
+    /// Hash of the request frame
+    pub message_hash: [u8; 32],
+    /// Lock time (Unix seconds)
+    pub created: u64,
+    /// Time after which the payer can cancel (Unix seconds)
+    pub expiry: u64,
+    /// Paid out to the payee
+    pub released: bool,
+    /// Refunded to the payer
+    pub cancelled: bool,
+}
+
+impl EscrowLock {
+    /// Whether the lock is neither released nor cancelled
+    pub fn is_open(&self) -> bool {
+        !self.released && !self.cancelled
+    }
+
+    /// Check that the lock pays `payee` for `request`
+    pub fn check_request(&self, request: &OpacusFrame, payee: Address) -> Result<(), ChainError> {
+        if !self.is_open() {
+            return Err(ChainError::Payment("Escrow already settled".into()));
+        }
+        if self.payee != payee {
+            return Err(ChainError::Payment(format!("Escrow pays {}, not {}", self.payee, payee)));
+        }
+        if request.id.map(|id| escrow_lock_id(&id)) != Some(self.id) {
+            return Err(ChainError::Payment("Escrow is for another request".into()));
+        }
+        if message_hash(request).map_err(ChainError::Payment)? != self.message_hash {
+            return Err(ChainError::Payment("Request does not match escrowed hash".into()));
+        }
+        Ok(())
+    }
+}
+
+/// Requester's authorization to release an escrow for a delivered response
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EscrowRelease {
+    /// Escrow lock
+    #[serde(with = "hex_bytes")]
+    pub lock_id: [u8; 32],
+    /// Receipt for the response
+    pub receipt: DeliveryReceipt,
+    /// Requester's EIP-712 signature (`r || s || v`)
+    #[serde(with = "hex_bytes")]
+    pub signature: [u8; 65],
+}
+
+/// Signed part of an [`EscrowRelease`]
+struct ReleaseData<'a> {
+    lock_id: [u8; 32],
+    receipt: &'a DeliveryReceipt,
+}
+
+impl TypedData for ReleaseData<'_> {
+    const ENCODED_TYPE: &'static str = "EscrowRelease(bytes32 lockId,DeliveryReceipt receipt)\
+        DeliveryReceipt(bytes32 messageHash,string messageId,string from,string to,uint256 receivedAt)";
+
+    fn members(&self) -> Vec<Token> {
+        vec![Token::FixedBytes(self.lock_id), Token::FixedBytes(self.receipt.struct_hash())]
+    }
+}
+
+impl EscrowRelease {
+    /// Authorize the release of a lock
+    ///
+    /// # Arguments
+    /// * `lock_id` - Escrow lock
+    /// * `receipt` - Receipt for the delivered response
+    /// * `domain` - Domain of the escrow contract
+    /// * `signer` - Requester's key (the lock's payer)
+    pub fn sign(
+        lock_id: [u8; 32],
+        receipt: DeliveryReceipt,
+        domain: &Eip712Domain,
+        signer: &ChainSigner,
+    ) -> Result<Self, ChainError> {
+        let signature = signer.sign_typed_data(domain, &ReleaseData { lock_id, receipt: &receipt })?;
+        Ok(Self { lock_id, receipt, signature })
+    }
+
+    /// Check that the release was signed by the lock's payer and the lock is still open
+    pub fn verify(&self, domain: &Eip712Domain, lock: &EscrowLock) -> Result<(), ChainError> {
+        if lock.id != self.lock_id {
+            return Err(ChainError::Payment("Release is for another lock".into()));
+        }
+        if !lock.is_open() {
+            return Err(ChainError::Payment("Escrow already settled".into()));
+        }
+        let data = ReleaseData { lock_id: self.lock_id, receipt: &self.receipt };
+        let signer = recover_typed_data(domain, &data, &self.signature)
+            .map_err(|e| ChainError::Payment(format!("Invalid signature: {}", e)))?;
+        if signer != lock.payer {
+            return Err(ChainError::Payment(format!("Signed by {}, not by payer {}", signer, lock.payer)));
+        }
+        Ok(())
+    }
+}
+
+impl ChainClient {
+    /// Lock payment for a request frame
+    ///
+    /// Approves the contract to take `amount` of its payment token first if
+    /// the current allowance is lower.
+    ///
+    /// # Arguments
+    /// * `contract` - `MsgEscrow` address
+    /// * `request` - Request frame, as it will be sent
+    /// * `payee` - Responder account
+    /// * `amount` - Amount in the payment token's smallest unit
+    pub async fn lock_escrow(
+        &self,
+        contract: Address,
+        request: &OpacusFrame,
+        payee: Address,
+        amount: u128,
+    ) -> Result<EscrowLock, ChainError> {
+        let id = request.id.ok_or_else(|| ChainError::Payment("Request has no message ID".into()))?;
+        let lock_id = escrow_lock_id(&id);
+        let hash = message_hash(request).map_err(ChainError::Payment)?;
+        let owner = self.signer()?.address();
+
+        let token = self.address_of(contract, "paymentToken()").await?;
+        let allowance = self
+            .call(token, "allowance(address,address)", &[Token::Address(owner), Token::Address(contract)], &[ParamType::Uint])
+            .await?
+            .into_iter()
+            .next()
+            .and_then(Token::into_uint)
+            .unwrap_or_default();
+        if allowance < amount {
+            self.transact(token, "approve(address,uint256)", &[Token::Address(contract), Token::Uint(amount)], 0).await?;
+        }
+
+        let args = [Token::FixedBytes(lock_id), Token::Address(payee), Token::Uint(amount), Token::FixedBytes(hash)];
+        self.transact(contract, "lock(bytes32,address,uint256,bytes32)", &args, 0).await?;
+        self.escrow_lock(contract, lock_id)
+            .await?
+            .ok_or_else(|| ChainError::InvalidResponse("Lock missing after locking".into()))
+    }
+
+    /// Read a lock (`None` if unknown)
+    pub async fn escrow_lock(&self, contract: Address, lock_id: [u8; 32]) -> Result<Option<EscrowLock>, ChainError> {
+        let lock = ParamType::Tuple(vec![
+            ParamType::FixedBytes,
+            ParamType::Address,
+            ParamType::Address,
+            ParamType::Uint,
+            ParamType::FixedBytes,
+            ParamType::Uint,
+            ParamType::Uint,
+            ParamType::Bool,
+            ParamType::Bool,
+        ]);
+        let values = self.call(contract, "getLock(bytes32)", &[Token::FixedBytes(lock_id)], &[lock]).await?;
+        let mut fields = values.into_iter().next().and_then(Token::into_items).unwrap_or_default().into_iter();
+        let mut next = || fields.next().ok_or_else(|| ChainError::InvalidResponse("Short escrow lock".into()));
+        next()?; // ID, as requested
+        let lock = EscrowLock {
+            id: lock_id,
+            payer: next()?.into_address().unwrap_or_default(),
+            payee: next()?.into_address().unwrap_or_default(),
+            amount: next()?.into_uint().unwrap_or_default(),
+            message_hash: next()?.into_fixed_bytes().unwrap_or_default(),
+            created: next()?.into_uint().unwrap_or_default() as u64,
+            expiry: next()?.into_uint().unwrap_or_default() as u64,
+            released: next()?.into_bool().unwrap_or_default(),
+            cancelled: next()?.into_bool().unwrap_or_default(),
+        };
+        Ok((lock.payer != Address::default()).then_some(lock))
+    }
+
+    /// Release an escrow to its payee (authorized relayers only)
+    ///
+    /// Checks the release against the current lock before submitting.
+    pub async fn release_escrow(&self, contract: Address, release: &EscrowRelease) -> Result<TransactionReceipt, ChainError> {
+        let lock = self
+            .escrow_lock(contract, release.lock_id)
+            .await?
+            .ok_or_else(|| ChainError::Payment("Unknown escrow lock".into()))?;
+        release.verify(&escrow_domain(self.chain_id(), contract), &lock)?;
+        self.transact(contract, "release(bytes32)", &[Token::FixedBytes(release.lock_id)], 0).await
+    }
+
+    /// Refund an expired, unreleased escrow to its payer
+    pub async fn cancel_escrow(&self, contract: Address, lock_id: [u8; 32]) -> Result<TransactionReceipt, ChainError> {
+        self.transact(contract, "cancel(bytes32)", &[Token::FixedBytes(lock_id)], 0).await
+    }
+
+    async fn address_of(&self, contract: Address, signature: &str) -> Result<Address, ChainError> {
+        self.call(contract, signature, &[], &[ParamType::Address])
+            .await?
+            .into_iter()
+            .next()
+            .and_then(Token::into_address)
+            .ok_or_else(|| ChainError::InvalidResponse(format!("{} returned nothing", signature)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use serde_json::{json, Value};
+    use crate::chain::abi::{self, encode, selector};
+    use crate::chain::{mock, parse_data};
+    use crate::crypto::{KeyManager, SecurityManager};
+    use crate::types::{FrameOptions, FrameType};
+
+    const KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    fn escrow() -> Address {
+        "0xE2b6bfA4b9E6BEe8DFd9c8E9b2Ce6906c27e750E".parse().unwrap()
+    }
+
+    fn token() -> Address {
+        "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".parse().unwrap()
+    }
+
+    #[derive(Default)]
+    struct Contracts {
+        approved: u128,
+        locks: Vec<EscrowLock>,
+    }
+
+    fn handle(state: &Mutex<Contracts>, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        let hex_data = |data: &[u8]| json!(format!("0x{}", hex::encode(data)));
+        let mut state = state.lock().unwrap();
+        match method {
+            "eth_getTransactionCount" => Ok(json!("0x0")),
+            "eth_getBlockByNumber" => Ok(json!({ "number": "0x10", "baseFeePerGas": "0x1" })),
+            "eth_maxPriorityFeePerGas" => Ok(json!("0x1")),
+            "eth_estimateGas" => Ok(json!("0x186a0")),
+            "eth_call" => {
+                let data = parse_data(params[0]["data"].as_str().unwrap()).unwrap();
+                if data[..4] == selector("paymentToken()") {
+                    return Ok(hex_data(&encode(&[Token::Address(token())])));
+                }
+                if data[..4] == selector("allowance(address,address)") {
+                    return Ok(hex_data(&encode(&[Token::Uint(state.approved)])));
+                }
+                let lock = state.locks.iter().find(|l| data[4..] == l.id).cloned().unwrap_or(EscrowLock {
+                    id: [0; 32],
+                    payer: Address::default(),
+                    payee: Address::default(),
+                    amount: 0,
+                    message_hash: [0; 32],
+                    created: 0,
+                    expiry: 0,
+                    released: false,
+                    cancelled: false,
+                });
+                Ok(hex_data(&encode(&[Token::Tuple(vec![
+                    Token::FixedBytes(lock.id),
+                    Token::Address(lock.payer),
+                    Token::Address(lock.payee),
+                    Token::Uint(lock.amount),
+                    Token::FixedBytes(lock.message_hash),
+                    Token::Uint(lock.created.into()),
+                    Token::Uint(lock.expiry.into()),
+                    Token::Bool(lock.released),
+                    Token::Bool(lock.cancelled),
+                ])])))
+            }
+            "eth_sendRawTransaction" => {
+                let raw = parse_data(params[0].as_str().unwrap()).unwrap();
+                let call = |signature: &str| raw.windows(4).position(|w| w == selector(signature));
+                if let Some(at) = call("approve(address,uint256)") {
+                    let args = abi::decode(&[ParamType::Address, ParamType::Uint], &raw[at + 4..]).unwrap();
+                    state.approved = args[1].clone().into_uint().unwrap();
+                } else if let Some(at) = call("lock(bytes32,address,uint256,bytes32)") {
+                    let types = [ParamType::FixedBytes, ParamType::Address, ParamType::Uint, ParamType::FixedBytes];
+                    let args = abi::decode(&types, &raw[at + 4..]).unwrap();
+                    let amount = args[2].clone().into_uint().unwrap();
+                    assert!(state.approved >= amount);
+                    state.locks.push(EscrowLock {
+                        id: args[0].clone().into_fixed_bytes().unwrap(),
+                        payer: ChainSigner::from_hex(KEY).unwrap().address(),
+                        payee: args[1].clone().into_address().unwrap(),
+                        amount,
+                        message_hash: args[3].clone().into_fixed_bytes().unwrap(),
+                        created: 1_700_000_000,
+                        expiry: 1_700_086_400,
+                        released: false,
+                        cancelled: false,
+                    });
+                } else if let Some(at) = call("release(bytes32)") {
+                    let lock = state.locks.iter_mut().find(|l| raw[at + 4..at + 36] == l.id).unwrap();
+                    lock.released = true;
+                }
+                Ok(hex_data(&keccak256(&raw)))
+            }
+            "eth_getTransactionReceipt" => Ok(json!({
+                "transactionHash": params[0],
+                "blockNumber": "0x11",
+                "gasUsed": "0x186a0",
+                "status": "0x1",
+                "contractAddress": Value::Null,
+            })),
+            _ => Err((-32601, format!("method {} not found", method))),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_escrowed_request() {
+        let state = Arc::new(Mutex::new(Contracts::default()));
+        let contracts = state.clone();
+        let url = mock::serve(move |method, params| handle(&contracts, method, params)).await;
+        let requester = ChainClient::new(&url, 16661).unwrap().with_signer(ChainSigner::from_hex(KEY).unwrap());
+        let payee = ChainSigner::random();
+
+        let alice = KeyManager::generate_identity(16661);
+        let bob = KeyManager::generate_identity(16661);
+        let mut security = SecurityManager::new();
+        let request = security.create_auth_frame_with(&alice, &[0; 32], FrameType::Msg, &bob.id, b"quote?".to_vec(), FrameOptions::default());
+        let lock = requester.lock_escrow(escrow(), &request, payee.address(), 5_000).await.unwrap();
+        assert_eq!(state.lock().unwrap().approved, 5_000);
+        assert_eq!(lock.amount, 5_000);
+
+        // Responder side
+        lock.check_request(&request, payee.address()).unwrap();
+        assert!(lock.check_request(&request, requester.address().unwrap()).is_err());
+        let other = security.create_auth_frame_with(&alice, &[0; 32], FrameType::Msg, &bob.id, b"quote?".to_vec(), FrameOptions::default());
+        assert!(lock.check_request(&other, payee.address()).is_err());
+
+        let response = security.create_auth_frame_with(&bob, &[0; 32], FrameType::Msg, &alice.id, b"42".to_vec(), FrameOptions::default());
+        let domain = escrow_domain(16661, escrow());
+        let receipt = DeliveryReceipt::for_frame(&response, 2_000).unwrap();
+        let release = EscrowRelease::sign(lock.id, receipt.clone(), &domain, requester.signer().unwrap()).unwrap();
+        release.verify(&domain, &lock).unwrap();
+        let json = serde_json::to_string(&release).unwrap();
+        assert_eq!(serde_json::from_str::<EscrowRelease>(&json).unwrap(), release);
+
+        // Only the payer can authorize, and only for this lock
+        let forged = EscrowRelease::sign(lock.id, receipt, &domain, &payee).unwrap();
+        assert!(matches!(forged.verify(&domain, &lock), Err(ChainError::Payment(_))));
+        let moved = EscrowRelease { lock_id: [1; 32], ..release.clone() };
+        assert!(moved.verify(&domain, &EscrowLock { id: [1; 32], ..lock.clone() }).is_err());
+
+        requester.release_escrow(escrow(), &release).await.unwrap();
+        assert!(requester.escrow_lock(escrow(), lock.id).await.unwrap().unwrap().released);
+        assert!(matches!(requester.release_escrow(escrow(), &release).await, Err(ChainError::Payment(_))));
+        assert_eq!(requester.escrow_lock(escrow(), [9; 32]).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,274 @@
+//! Chain event bridge
+//!
+//! An [`EventBridge`] polls `eth_getLogs` for configured contract events and
+//! hands each new one out as a [`ChainEvent`] tagged with the data channel it
+//! is republished on. Blocks are read up to `confirmations` behind the head,
+//! each block range once, so every event is delivered once unless the chain
+//! reorganizes deeper than that.
+
+use std::sync::Arc;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+use super::abi::event_topic;
+use super::{hex_bytes, Address, ChainClient, ChainError, Log, LogFilter, TxHash};
+
+/// Most blocks searched by one `eth_getLogs` request
+pub const MAX_LOG_RANGE: u64 = 1000;
+
+/// Contract event to republish on a data channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventWatch {
+    /// Data channel the events are published on
+    pub channel_id: String,
+    /// Emitting contract
+    pub address: Address,
+    /// Event signature, e.g. `Transfer(address,address,uint256)`
+    pub event: String,
+}
+
+impl EventWatch {
+    /// Watch `event` emitted by `address`
+    pub fn new(channel_id: &str, address: Address, event: &str) -> Self {
+        Self { channel_id: channel_id.to_string(), address, event: event.to_string() }
+    }
+}
+
+/// Contract event, as published in `Stream` frames
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainEvent {
+    /// Chain the event was emitted on
+    pub chain_id: u64,
+    /// Emitting contract
+    pub address: Address,
+    /// Event signature
+    pub event: String,
+    /// Event signature hash followed by the indexed arguments
+    #[serde(with = "hex_topics")]
+    pub topics: Vec<[u8; 32]>,
+    /// ABI-encoded non-indexed arguments
+    #[serde(with = "hex_bytes")]
+    pub data: Vec<u8>,
+    /// Block containing the event
+    pub block_number: u64,
+    /// Transaction that emitted the event
+    #[serde(with = "hex_bytes")]
+    pub transaction_hash: TxHash,
+}
+
+impl ChainEvent {
+    fn from_log(chain_id: u64, event: &str, log: Log) -> Self {
+        Self {
+            chain_id,
+            address: log.address,
+            event: event.to_string(),
+            topics: log.topics,
+            data: log.data,
+            block_number: log.block_number,
+            transaction_hash: log.transaction_hash,
+        }
+    }
+}
+
+mod hex_topics {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(topics: &[[u8; 32]], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(topics.iter().map(|t| format!("0x{}", hex::encode(t))))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<[u8; 32]>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|s| {
+                let bytes = hex::decode(s.strip_prefix("0x").unwrap_or(s)).map_err(de::Error::custom)?;
+                bytes.try_into().map_err(|_| de::Error::custom(format!("invalid topic {}", s)))
+            })
+            .collect()
+    }
+}
+
+/// Poller for watched contract events
+pub struct EventBridge {
+    chain: Arc<ChainClient>,
+    watches: Vec<EventWatch>,
+    confirmations: u64,
+    next_block: Option<u64>,
+}
+
+impl EventBridge {
+    /// Bridge without watches that starts at the current head
+    pub fn new(chain: Arc<ChainClient>) -> Self {
+        Self { chain, watches: Vec::new(), confirmations: 0, next_block: None }
+    }
+
+    /// Add an event to watch
+    pub fn watch(mut self, watch: EventWatch) -> Self {
+        self.watches.push(watch);
+        self
+    }
+
+    /// Only publish events this many blocks behind the head
+    pub fn with_confirmations(mut self, confirmations: u64) -> Self {
+        self.confirmations = confirmations;
+        self
+    }
+
+    /// Also publish past events, starting at `block`
+    pub fn from_block(mut self, block: u64) -> Self {
+        self.next_block = Some(block);
+        self
+    }
+
+    /// Watched events
+    pub fn watches(&self) -> &[EventWatch] {
+        &self.watches
+    }
+
+    /// Fetch events emitted since the last poll
+    ///
+    /// Reads at most `MAX_LOG_RANGE` blocks; the rest is read by the next
+    /// polls. A failed poll is retried from the same block.
+    ///
+    /// # Returns
+    /// `(channel ID, event)` pairs in block order
+    pub async fn poll(&mut self) -> Result<Vec<(String, ChainEvent)>, ChainError> {
+        let safe = self.chain.rpc().block_number().await?.saturating_sub(self.confirmations);
+        let from = *self.next_block.get_or_insert(safe + 1);
+        if from > safe {
+            return Ok(Vec::new());
+        }
+        let to = safe.min(from + MAX_LOG_RANGE - 1);
+
+        let mut events = Vec::new();
+        for watch in &self.watches {
+            let filter = LogFilter {
+                address: Some(watch.address),
+                topics: vec![Some(event_topic(&watch.event))],
+                from_block: from,
+                to_block: Some(to),
+            };
+            for log in self.chain.rpc().logs(&filter).await? {
+                events.push((watch.channel_id.clone(), ChainEvent::from_log(self.chain.chain_id(), &watch.event, log)));
+            }
+        }
+        events.sort_by_key(|(_, event)| event.block_number);
+        self.next_block = Some(to + 1);
+        Ok(events)
+    }
+}
+
+/// Poll a bridge every `interval` and send its events to `events`
+///
+/// The task ends when the receiver is dropped.
+pub fn spawn_event_bridge(
+    mut bridge: EventBridge,
+    interval: Duration,
+    events: mpsc::Sender<(String, ChainEvent)>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            if events.is_closed() {
+                return;
+            }
+            match bridge.poll().await {
+                Ok(polled) => {
+                    if !polled.is_empty() {
+                        debug!("Bridging {} chain events", polled.len());
+                    }
+                    for event in polled {
+                        if events.send(event).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(e) => warn!("Polling chain events failed: {}", e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use serde_json::{json, Value};
+    use crate::chain::mock;
+
+    const TRANSFER: &str = "Transfer(address,address,uint256)";
+
+    fn token() -> Address {
+        "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".parse().unwrap()
+    }
+
+    /// Chain with one `Transfer` per block, at the head set in `head`
+    fn handle(head: &AtomicU64, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        let quantity = |n: u64| json!(format!("0x{:x}", n));
+        match method {
+            "eth_blockNumber" => Ok(quantity(head.load(Ordering::SeqCst))),
+            "eth_getLogs" => {
+                let filter = &params[0];
+                let block = |name: &str| u64::from_str_radix(filter[name].as_str().unwrap().trim_start_matches("0x"), 16).unwrap();
+                assert_eq!(filter["topics"][0], json!(format!("0x{}", hex::encode(event_topic(TRANSFER)))));
+                let logs: Vec<Value> = (block("fromBlock")..=block("toBlock"))
+                    .map(|n| {
+                        json!({
+                            "address": filter["address"],
+                            "topics": filter["topics"],
+                            "data": format!("0x{:064x}", n),
+                            "blockNumber": quantity(n),
+                            "transactionHash": format!("0x{:064x}", n),
+                        })
+                    })
+                    .collect();
+                Ok(json!(logs))
+            }
+            _ => Err((-32601, format!("method {} not found", method))),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_event_bridge() {
+        let head = Arc::new(AtomicU64::new(100));
+        let chain_head = head.clone();
+        let url = mock::serve(move |method, params| handle(&chain_head, method, params)).await;
+        let chain = Arc::new(ChainClient::new(&url, 16661).unwrap());
+
+        let mut bridge = EventBridge::new(chain.clone())
+            .watch(EventWatch::new("transfers", token(), TRANSFER))
+            .with_confirmations(2);
+        // Starts at the head
+        assert!(bridge.poll().await.unwrap().is_empty());
+        head.store(105, Ordering::SeqCst);
+        let events = bridge.poll().await.unwrap();
+        let blocks: Vec<u64> = events.iter().map(|(_, e)| e.block_number).collect();
+        assert_eq!(blocks, vec![99, 100, 101, 102, 103]);
+        assert!(bridge.poll().await.unwrap().is_empty());
+
+        let (channel, event) = &events[0];
+        assert_eq!(channel, "transfers");
+        assert_eq!((event.chain_id, event.address, event.event.as_str()), (16661, token(), TRANSFER));
+        let json = serde_json::to_value(event).unwrap();
+        assert_eq!(json["transactionHash"], format!("0x{:064x}", 99));
+        assert_eq!(&serde_json::from_value::<ChainEvent>(json).unwrap(), event);
+
+        // Backfill in ranges of MAX_LOG_RANGE
+        let mut backfill = EventBridge::new(chain).watch(EventWatch::new("transfers", token(), TRANSFER)).from_block(0);
+        head.store(MAX_LOG_RANGE + 10, Ordering::SeqCst);
+        assert_eq!(backfill.poll().await.unwrap().len() as u64, MAX_LOG_RANGE);
+        assert_eq!(backfill.poll().await.unwrap().len(), 11);
+
+        let (tx, mut rx) = mpsc::channel(16);
+        let task = spawn_event_bridge(bridge, Duration::from_millis(10), tx);
+        head.store(110, Ordering::SeqCst);
+        for block in 104..=108 {
+            assert_eq!(rx.recv().await.unwrap().1.block_number, block);
+        }
+        drop(rx);
+        tokio::time::timeout(Duration::from_secs(1), task).await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,248 @@
+//! EVM chain access (`chain` feature)
+//!
+//! A small JSON-RPC client for the network's EVM endpoint (`chain_rpc`):
+//! balance queries, nonce management, gas estimation and EIP-1559
+//! transaction signing with the secp256k1 key from `OpacusConfig::private_key`.
+//! Amounts are in wei as `u128`.
+
+pub mod abi;
+mod access;
+mod agents;
+mod anchor;
+mod attestation;
+mod billing;
+mod checkpoint;
+mod client;
+mod dac;
+mod eip712;
+mod escrow;
+mod events;
+#[cfg(test)]
+mod mock;
+mod names;
+mod notary;
+mod payment;
+mod payment_channel;
+mod pending;
+mod relays;
+mod reputation;
+mod rlp;
+mod rpc;
+mod storage;
+mod tx;
+mod wallet;
+
+pub use access::*;
+pub use agents::*;
+pub use anchor::*;
+pub use attestation::*;
+pub use billing::*;
+pub use checkpoint::*;
+pub use client::*;
+pub use dac::*;
+pub use eip712::*;
+pub use escrow::*;
+pub use events::*;
+pub use names::*;
+pub use notary::*;
+pub use payment::*;
+pub use payment_channel::*;
+pub use pending::*;
+pub use relays::*;
+pub use reputation::*;
+pub use rpc::*;
+pub use storage::*;
+pub use tx::*;
+pub use wallet::*;
+
+use std::fmt;
+use std::str::FromStr;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha3::{Digest, Keccak256};
+
+/// Chain access error
+#[derive(Debug, thiserror::Error)]
+pub enum ChainError {
+    /// HTTP request to the RPC endpoint failed
+    #[error("transport error: {0}")]
+    Transport(String),
+    /// Node returned a JSON-RPC error
+    #[error("RPC error {code}: {message}")]
+    Rpc { code: i64, message: String },
+    /// Node returned a malformed result
+    #[error("invalid response: {0}")]
+    InvalidResponse(String),
+    /// Missing or invalid signing key
+    #[error("signer error: {0}")]
+    Signer(String),
+    /// Transaction was not mined in time
+    #[error("timed out waiting for {0}")]
+    Timeout(String),
+    /// Transaction was mined but reverted
+    #[error("transaction reverted: {0}")]
+    Reverted(String),
+    /// Payment intent failed validation
+    #[error("invalid payment: {0}")]
+    Payment(String),
+    /// Off-chain content could not be stored or fetched
+    #[error("storage error: {0}")]
+    Storage(String),
+    /// Agent keys are not backed by an active registration
+    #[error("identity error: {0}")]
+    Identity(String),
+    /// Account does not meet a channel's access rule
+    #[error("access denied: {0}")]
+    Access(String),
+}
+
+impl From<reqwest::Error> for ChainError {
+    fn from(e: reqwest::Error) -> Self {
+        ChainError::Transport(e.to_string())
+    }
+}
+
+/// Keccak-256 hash
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+    Keccak256::digest(data).into()
+}
+
+/// 20-byte EVM account address
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Address(pub [u8; 20]);
+
+impl Address {
+    /// Address owning an uncompressed secp256k1 public key (64 bytes, no prefix)
+    pub fn from_public_key(public: &[u8; 64]) -> Self {
+        let hash = keccak256(public);
+        Address(hash[12..].try_into().expect("20-byte suffix"))
+    }
+
+    /// Mixed-case checksum encoding (EIP-55)
+    pub fn to_checksum(&self) -> String {
+        let lower = hex::encode(self.0);
+        let hash = keccak256(lower.as_bytes());
+        let mut out = String::with_capacity(42);
+        out.push_str("0x");
+        for (i, c) in lower.chars().enumerate() {
+            let nibble = (hash[i / 2] >> (if i % 2 == 0 { 4 } else { 0 })) & 0x0f;
+            out.push(if nibble >= 8 { c.to_ascii_uppercase() } else { c });
+        }
+        out
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_checksum())
+    }
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    /// Parse a `0x`-prefixed hex address; mixed-case input must carry a valid checksum
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s.strip_prefix("0x").unwrap_or(s);
+        let bytes = hex::decode(digits).map_err(|e| format!("Invalid address {}: {}", s, e))?;
+        let address = Address(bytes.try_into().map_err(|_| format!("Invalid address length: {}", s))?);
+        let mixed = digits.chars().any(|c| c.is_ascii_lowercase()) && digits.chars().any(|c| c.is_ascii_uppercase());
+        if mixed && address.to_checksum()[2..] != *digits {
+            return Err(format!("Invalid address checksum: {}", s));
+        }
+        Ok(address)
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_checksum())
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Encode a JSON-RPC quantity (`0x`-prefixed hex, no leading zeros)
+pub(crate) fn to_quantity(value: u128) -> String {
+    format!("{:#x}", value)
+}
+
+/// Parse a JSON-RPC quantity
+pub(crate) fn parse_quantity(s: &str) -> Result<u128, ChainError> {
+    let digits = s
+        .strip_prefix("0x")
+        .ok_or_else(|| ChainError::InvalidResponse(format!("Quantity without 0x prefix: {}", s)))?;
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u128::from_str_radix(digits, 16).map_err(|e| ChainError::InvalidResponse(format!("Invalid quantity {}: {}", s, e)))
+}
+
+/// Parse `0x`-prefixed hex data
+pub(crate) fn parse_data(s: &str) -> Result<Vec<u8>, ChainError> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    hex::decode(digits).map_err(|e| ChainError::InvalidResponse(format!("Invalid hex data: {}", e)))
+}
+
+/// Serde helpers for byte arrays as `0x`-prefixed hex strings
+pub(crate) mod hex_bytes {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer, T: AsRef<[u8]>>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("0x{}", hex::encode(value)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: TryFrom<Vec<u8>>>(deserializer: D) -> Result<T, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        let bytes = hex::decode(s.strip_prefix("0x").unwrap_or(&s)).map_err(de::Error::custom)?;
+        let len = bytes.len();
+        T::try_from(bytes).map_err(|_| de::Error::custom(format!("unexpected length {}", len)))
+    }
+}
+
+/// Serde helpers for `u128` amounts as JSON-RPC quantities, which survive
+/// JSON parsers limited to 53-bit integers
+pub(crate) mod quantity {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &u128, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::to_quantity(*value))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u128, D::Error> {
+        super::parse_quantity(&String::deserialize(deserializer)?).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum_address() {
+        for s in [
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+        ] {
+            let address: Address = s.parse().unwrap();
+            assert_eq!(address.to_string(), s);
+            assert_eq!(s.to_lowercase().parse::<Address>().unwrap(), address);
+        }
+        assert!("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD".parse::<Address>().is_err());
+        assert!("0x1234".parse::<Address>().is_err());
+    }
+
+    #[test]
+    fn test_quantities() {
+        assert_eq!(to_quantity(0), "0x0");
+        assert_eq!(to_quantity(1024), "0x400");
+        assert_eq!(parse_quantity("0x400").unwrap(), 1024);
+        assert_eq!(parse_quantity("0x").unwrap(), 0);
+        assert!(parse_quantity("400").is_err());
+        assert_eq!(keccak256(b"")[..4], [0xc5, 0xd2, 0x46, 0x01]);
+    }
+}
@@ -0,0 +1,304 @@
+//! Human-readable agent names
+//!
+//! Names live in an ENS-compatible registry: the registry maps the name's
+//! [`namehash`] to a resolver, and the resolver's `opacus.agent` text record
+//! holds a [`NameRecord`]. Only the name's owner can set the record, and the
+//! record is signed by the agent it points to, so a name resolves only to
+//! keys whose holder claimed it. ENS itself works as the registry on chains
+//! where it is deployed.
+
+use std::time::{Duration, Instant};
+use std::sync::Arc;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use crate::crypto::{KeyManager, SecurityManager};
+use crate::types::AgentIdentity;
+use super::abi::{ParamType, Token};
+use super::{hex_bytes, keccak256, Address, ChainClient, ChainError, TransactionReceipt};
+
+/// ENS registry on Ethereum mainnet and testnets
+pub const ENS_REGISTRY: &str = "0x00000000000C2E074eC69A0dFb2997BA6C7d2e1e";
+
+/// Text record holding the [`NameRecord`]
+pub const NAME_RECORD_KEY: &str = "opacus.agent";
+
+/// How long resolved names are used before being looked up again
+pub const NAME_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// Domain separator of name record signatures
+const NAME_CONTEXT: &str = "opacus-name-v1";
+
+/// Normalize a name (ASCII lowercase, no empty labels)
+pub fn normalize_name(name: &str) -> Result<String, ChainError> {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    if name.is_empty() || name.split('.').any(str::is_empty) {
+        return Err(ChainError::Identity(format!("Invalid name: {:?}", name)));
+    }
+    Ok(name)
+}
+
+/// ENS namehash of a normalized name
+pub fn namehash(name: &str) -> [u8; 32] {
+    let mut node = [0u8; 32];
+    if name.is_empty() {
+        return node;
+    }
+    for label in name.rsplit('.') {
+        let mut data = [0u8; 64];
+        data[..32].copy_from_slice(&node);
+        data[32..].copy_from_slice(&keccak256(label.as_bytes()));
+        node = keccak256(&data);
+    }
+    node
+}
+
+/// Whether a recipient is a name rather than an agent ID
+pub fn is_agent_name(to: &str) -> bool {
+    to.contains('.')
+}
+
+/// Agent a name points to, signed by that agent
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NameRecord {
+    /// Normalized name
+    pub name: String,
+    /// Agent ID
+    pub agent_id: String,
+    /// Agent's Ed25519 public key
+    #[serde(with = "hex_bytes")]
+    pub ed_pub: [u8; 32],
+    /// Agent's X25519 public key
+    #[serde(with = "hex_bytes")]
+    pub x_pub: [u8; 32],
+    /// Issue time (milliseconds)
+    pub issued_at: u64,
+    /// Ed25519 signature by the agent
+    pub signature: Vec<u8>,
+}
+
+impl NameRecord {
+    /// Claim a name for an agent
+    pub fn sign(identity: &AgentIdentity, name: &str, issued_at: u64) -> Result<Self, ChainError> {
+        let mut record = Self {
+            name: normalize_name(name)?,
+            agent_id: identity.id.clone(),
+            ed_pub: identity.ed_pub,
+            x_pub: identity.x_pub,
+            issued_at,
+            signature: Vec::new(),
+        };
+        record.signature = SecurityManager::sign(&identity.ed_priv, &record.signing_data());
+        Ok(record)
+    }
+
+    fn signing_data(&self) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!([
+            NAME_CONTEXT,
+            self.name,
+            self.agent_id,
+            hex::encode(self.ed_pub),
+            hex::encode(self.x_pub),
+            self.issued_at,
+        ]))
+        .expect("JSON array")
+    }
+
+    /// Check that the record is for `name` and signed by the agent it names
+    pub fn verify(&self, name: &str) -> Result<(), ChainError> {
+        if self.name != normalize_name(name)? {
+            return Err(ChainError::Identity(format!("Record is for {}, not {}", self.name, name)));
+        }
+        if KeyManager::agent_id(&self.ed_pub) != self.agent_id {
+            return Err(ChainError::Identity("Agent ID does not match signing key".into()));
+        }
+        if !SecurityManager::verify(&self.ed_pub, &self.signing_data(), &self.signature) {
+            return Err(ChainError::Identity(format!("Invalid signature on record of {}", self.name)));
+        }
+        Ok(())
+    }
+}
+
+/// Name lookups against an ENS-compatible registry, with caching
+#[derive(Debug)]
+pub struct NameService {
+    chain: Arc<ChainClient>,
+    registry: Address,
+    ttl: Duration,
+    cache: DashMap<String, (NameRecord, Instant)>,
+}
+
+impl NameService {
+    /// Create name service
+    ///
+    /// # Arguments
+    /// * `chain` - Chain client; publishing needs a signer
+    /// * `registry` - ENS-compatible registry
+    pub fn new(chain: Arc<ChainClient>, registry: Address) -> Self {
+        Self { chain, registry, ttl: NAME_CACHE_TTL, cache: DashMap::new() }
+    }
+
+    /// Set how long resolved names are cached
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Resolve a name to a verified record
+    ///
+    /// # Returns
+    /// `None` if the name has no resolver or no Opacus record
+    pub async fn resolve(&self, name: &str) -> Result<Option<NameRecord>, ChainError> {
+        let name = normalize_name(name)?;
+        if let Some(entry) = self.cache.get(&name) {
+            if entry.1.elapsed() < self.ttl {
+                return Ok(Some(entry.0.clone()));
+            }
+        }
+        let node = namehash(&name);
+        let Some(resolver) = self.resolver(node).await? else {
+            return Ok(None);
+        };
+        let args = [Token::FixedBytes(node), Token::String(NAME_RECORD_KEY.into())];
+        let text = self
+            .chain
+            .call(resolver, "text(bytes32,string)", &args, &[ParamType::String])
+            .await?
+            .into_iter()
+            .next()
+            .and_then(Token::into_string)
+            .unwrap_or_default();
+        if text.is_empty() {
+            self.cache.remove(&name);
+            return Ok(None);
+        }
+        let record: NameRecord = serde_json::from_str(&text)
+            .map_err(|e| ChainError::InvalidResponse(format!("Invalid name record of {}: {}", name, e)))?;
+        record.verify(&name)?;
+        self.cache.insert(name, (record.clone(), Instant::now()));
+        Ok(Some(record))
+    }
+
+    /// Point a name owned by the signer's account at an agent
+    ///
+    /// # Arguments
+    /// * `record` - Record signed by the agent ([`NameRecord::sign`])
+    pub async fn publish(&self, record: &NameRecord) -> Result<TransactionReceipt, ChainError> {
+        record.verify(&record.name)?;
+        let node = namehash(&record.name);
+        let resolver = self
+            .resolver(node)
+            .await?
+            .ok_or_else(|| ChainError::Identity(format!("{} has no resolver", record.name)))?;
+        let text = serde_json::to_string(record).map_err(|e| ChainError::InvalidResponse(e.to_string()))?;
+        let args = [Token::FixedBytes(node), Token::String(NAME_RECORD_KEY.into()), Token::String(text)];
+        let receipt = self.chain.transact(resolver, "setText(bytes32,string,string)", &args, 0).await?;
+        self.cache.insert(record.name.clone(), (record.clone(), Instant::now()));
+        Ok(receipt)
+    }
+
+    /// Forget a cached name
+    pub fn invalidate(&self, name: &str) {
+        if let Ok(name) = normalize_name(name) {
+            self.cache.remove(&name);
+        }
+    }
+
+    async fn resolver(&self, node: [u8; 32]) -> Result<Option<Address>, ChainError> {
+        let values = self
+            .chain
+            .call(self.registry, "resolver(bytes32)", &[Token::FixedBytes(node)], &[ParamType::Address])
+            .await?;
+        Ok(values.into_iter().next().and_then(Token::into_address).filter(|a| *a != Address::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use serde_json::{json, Value};
+    use crate::chain::abi::{self, encode, selector};
+    use crate::chain::{mock, parse_data, ChainSigner};
+
+    const KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    #[test]
+    fn test_namehash() {
+        // Vectors from EIP-137
+        assert_eq!(namehash(""), [0; 32]);
+        assert_eq!(
+            hex::encode(namehash("eth")),
+            "93cdeb708b7545dc668eb9280176169d1c33cfd8ed6f04690a0bcc88a93fc4ae"
+        );
+        assert_eq!(
+            hex::encode(namehash("foo.eth")),
+            "de9b09fd7c5f901e23a3f19fecc54828e9c848539801e86591bd9801b019f84f"
+        );
+        assert_eq!(normalize_name("Analytics.Agent0G.").unwrap(), "analytics.agent0g");
+        assert!(normalize_name("a..b").is_err());
+        assert!(is_agent_name("analytics.agent0g") && !is_agent_name("3f2a"));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_name() {
+        let resolver: Address = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".parse().unwrap();
+        let text = Arc::new(Mutex::new(String::new()));
+        let state = text.clone();
+        let url = mock::serve(move |method, params| {
+            let hex_data = |data: &[u8]| json!(format!("0x{}", hex::encode(data)));
+            match method {
+                "eth_getTransactionCount" => Ok(json!("0x0")),
+                "eth_getBlockByNumber" => Ok(json!({ "number": "0x10", "baseFeePerGas": "0x1" })),
+                "eth_maxPriorityFeePerGas" => Ok(json!("0x1")),
+                "eth_estimateGas" => Ok(json!("0x186a0")),
+                "eth_call" => {
+                    let data = parse_data(params[0]["data"].as_str().unwrap()).unwrap();
+                    if data[..4] == selector("resolver(bytes32)") {
+                        let known = data[4..] == namehash("analytics.agent0g");
+                        let address = if known { resolver } else { Address::default() };
+                        return Ok(hex_data(&encode(&[Token::Address(address)])));
+                    }
+                    Ok(hex_data(&encode(&[Token::String(state.lock().unwrap().clone())])))
+                }
+                "eth_sendRawTransaction" => {
+                    let raw = parse_data(params[0].as_str().unwrap()).unwrap();
+                    let at = raw.windows(4).position(|w| w == selector("setText(bytes32,string,string)")).unwrap();
+                    let types = [ParamType::FixedBytes, ParamType::String, ParamType::String];
+                    let args = abi::decode(&types, &raw[at + 4..]).unwrap();
+                    *state.lock().unwrap() = args[2].clone().into_string().unwrap();
+                    Ok(hex_data(&keccak256(&raw)))
+                }
+                "eth_getTransactionReceipt" => Ok(json!({
+                    "transactionHash": params[0],
+                    "blockNumber": "0x11",
+                    "gasUsed": "0x186a0",
+                    "status": "0x1",
+                    "contractAddress": Value::Null,
+                })),
+                _ => Err((-32601, format!("method {} not found", method))),
+            }
+        })
+        .await;
+        let chain = Arc::new(ChainClient::new(&url, 16661).unwrap().with_signer(ChainSigner::from_hex(KEY).unwrap()));
+        let names = NameService::new(chain, ENS_REGISTRY.parse().unwrap()).with_ttl(Duration::ZERO);
+
+        assert_eq!(names.resolve("analytics.agent0g").await.unwrap(), None);
+        assert_eq!(names.resolve("unknown.agent0g").await.unwrap(), None);
+
+        let agent = KeyManager::generate_identity(16661);
+        let record = NameRecord::sign(&agent, "Analytics.agent0g", 1_000).unwrap();
+        names.publish(&record).await.unwrap();
+        let resolved = names.resolve("analytics.agent0g").await.unwrap().unwrap();
+        assert_eq!((resolved.agent_id, resolved.x_pub), (agent.id.clone(), agent.x_pub));
+
+        // Records for another name or with a forged signature are rejected
+        let other = NameRecord::sign(&agent, "billing.agent0g", 1_000).unwrap();
+        *text.lock().unwrap() = serde_json::to_string(&other).unwrap();
+        assert!(matches!(names.resolve("analytics.agent0g").await, Err(ChainError::Identity(_))));
+        let mallory = KeyManager::generate_identity(16661);
+        let forged = NameRecord { x_pub: mallory.x_pub, ..record };
+        *text.lock().unwrap() = serde_json::to_string(&forged).unwrap();
+        assert!(names.resolve("analytics.agent0g").await.is_err());
+    }
+}
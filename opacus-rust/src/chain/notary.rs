@@ -0,0 +1,337 @@
+//! Relay-notarized delivery receipts
+//!
+//! A recipient attaches its [`SignedDeliveryReceipt`] to a frame for the
+//! sender in the `deliveryReceipt` extension. A relay running a
+//! [`ReceiptNotary`] checks the receipt against the frames it routed: the
+//! message with that ID and hash must have been relayed to the agent sending
+//! the receipt. It then countersigns the receipt and replaces the extension
+//! with a `notarizedReceipt` one carrying the [`NotarizedReceipt`].
+//!
+//! Both signatures are EIP-712 typed data in the notary domain, whose
+//! verifying contract is an anchor contract (see [`MessageAnchor`]). Posting
+//! [`NotarizedReceipt::evidence_hash`] to it with
+//! [`ChainClient::anchor_receipt`] timestamps the evidence on chain.
+//!
+//! [`MessageAnchor`]: super::MessageAnchor
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "relay")]
+use tracing::{debug, warn};
+#[cfg(feature = "relay")]
+use crate::relay::FrameNotary;
+use crate::types::{FrameType, OpacusFrame, Ulid};
+use super::abi::Token;
+use super::{
+    hex_bytes, keccak256, message_hash, recover_typed_data, typed_data_hash, Address, ChainClient, ChainError, ChainSigner,
+    DeliveryReceipt, Eip712Domain, SignedDeliveryReceipt, TransactionReceipt, TypedData,
+};
+
+/// EIP-712 domain name of notarized receipts
+pub const NOTARY_DOMAIN_NAME: &str = "Opacus Receipt Notary";
+
+/// EIP-712 domain version of notarized receipts
+pub const NOTARY_DOMAIN_VERSION: &str = "1";
+
+/// Frame extension carrying a recipient's [`SignedDeliveryReceipt`]
+pub const RECEIPT_EXTENSION: &str = "deliveryReceipt";
+
+/// Frame extension carrying a relay's [`NotarizedReceipt`]
+pub const NOTARIZED_RECEIPT_EXTENSION: &str = "notarizedReceipt";
+
+/// Routed messages a notary remembers, oldest forgotten first
+pub const NOTARY_LOG_CAPACITY: usize = 100_000;
+
+/// Signing domain of notarized receipts anchored in `contract`
+pub fn notary_domain(chain_id: u64, contract: Address) -> Eip712Domain {
+    Eip712Domain::new(NOTARY_DOMAIN_NAME, NOTARY_DOMAIN_VERSION, chain_id, contract)
+}
+
+/// Delivery receipt countersigned by the relay that routed the message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotarizedReceipt {
+    /// Recipient's signed receipt
+    pub receipt: SignedDeliveryReceipt,
+    /// When the relay routed the message (milliseconds)
+    pub relayed_at: u64,
+    /// When the relay countersigned (milliseconds)
+    pub notarized_at: u64,
+    /// Relay's EIP-712 signature (`r || s || v`)
+    #[serde(with = "hex_bytes")]
+    pub relay_signature: [u8; 65],
+}
+
+/// Signed part of a [`NotarizedReceipt`]
+struct Notarization<'a> {
+    receipt: &'a DeliveryReceipt,
+    recipient_signature: &'a [u8; 65],
+    relayed_at: u64,
+    notarized_at: u64,
+}
+
+impl TypedData for Notarization<'_> {
+    const ENCODED_TYPE: &'static str = "Notarization(DeliveryReceipt receipt,bytes recipientSignature,\
+        uint256 relayedAt,uint256 notarizedAt)\
+        DeliveryReceipt(bytes32 messageHash,string messageId,string from,string to,uint256 receivedAt)";
+
+    fn members(&self) -> Vec<Token> {
+        vec![
+            Token::FixedBytes(self.receipt.struct_hash()),
+            Token::FixedBytes(keccak256(self.recipient_signature)),
+            Token::Uint(self.relayed_at.into()),
+            Token::Uint(self.notarized_at.into()),
+        ]
+    }
+}
+
+impl NotarizedReceipt {
+    /// Countersign a receipt
+    ///
+    /// # Arguments
+    /// * `receipt` - Recipient's signed receipt
+    /// * `relayed_at` - When the message was routed (milliseconds)
+    /// * `notarized_at` - Current time (milliseconds)
+    /// * `domain` - Notary domain ([`notary_domain`])
+    /// * `signer` - Relay's key
+    pub fn sign(
+        receipt: SignedDeliveryReceipt,
+        relayed_at: u64,
+        notarized_at: u64,
+        domain: &Eip712Domain,
+        signer: &ChainSigner,
+    ) -> Result<Self, ChainError> {
+        let relay_signature = signer.sign_typed_data(domain, &Self::notarization(&receipt, relayed_at, notarized_at))?;
+        Ok(Self { receipt, relayed_at, notarized_at, relay_signature })
+    }
+
+    fn notarization(receipt: &SignedDeliveryReceipt, relayed_at: u64, notarized_at: u64) -> Notarization<'_> {
+        Notarization { receipt: &receipt.receipt, recipient_signature: &receipt.signature, relayed_at, notarized_at }
+    }
+
+    /// Account of the relay that countersigned
+    pub fn relay(&self, domain: &Eip712Domain) -> Result<Address, ChainError> {
+        recover_typed_data(domain, &Self::notarization(&self.receipt, self.relayed_at, self.notarized_at), &self.relay_signature)
+    }
+
+    /// Check the relay's countersignature
+    ///
+    /// # Returns
+    /// Account of the recipient that signed the receipt; `Err` if `relay`
+    /// did not countersign it
+    pub fn verify(&self, domain: &Eip712Domain, relay: Address) -> Result<Address, ChainError> {
+        let signer = self.relay(domain)?;
+        if signer != relay {
+            return Err(ChainError::Signer(format!("Receipt was notarized by {}, not {}", signer, relay)));
+        }
+        self.receipt.signer(domain)
+    }
+
+    /// Hash anchored as evidence (the relay's signed hash)
+    pub fn evidence_hash(&self, domain: &Eip712Domain) -> [u8; 32] {
+        typed_data_hash(domain, &Self::notarization(&self.receipt, self.relayed_at, self.notarized_at))
+    }
+}
+
+/// Message routed by the relay
+#[derive(Debug, Clone)]
+struct Relayed {
+    hash: [u8; 32],
+    to: String,
+    at: u64,
+}
+
+/// Routed messages, bounded to `NOTARY_LOG_CAPACITY`
+#[derive(Debug, Default)]
+struct RelayLog {
+    messages: HashMap<Ulid, Relayed>,
+    order: VecDeque<Ulid>,
+}
+
+/// Relay-side notary: remembers routed messages and countersigns their receipts
+///
+/// Install on a relay with `OpacusRelayServer::with_notary`.
+#[derive(Debug)]
+pub struct ReceiptNotary {
+    signer: ChainSigner,
+    domain: Eip712Domain,
+    log: Mutex<RelayLog>,
+}
+
+impl ReceiptNotary {
+    /// Notary signing with the relay's key in `domain` ([`notary_domain`])
+    pub fn new(signer: ChainSigner, domain: Eip712Domain) -> Self {
+        Self { signer, domain, log: Mutex::new(RelayLog::default()) }
+    }
+
+    /// Relay's account, which recipients of notarized receipts check against
+    pub fn address(&self) -> Address {
+        self.signer.address()
+    }
+
+    /// Remember a routed frame
+    ///
+    /// Entries of `Batch` frames are remembered as well. Frames without a
+    /// message ID are skipped.
+    pub fn record(&self, frame: &OpacusFrame, now: u64) {
+        if frame.frame_type == FrameType::Batch {
+            if let Ok(entries) = frame.batch_entries() {
+                entries.iter().for_each(|entry| self.record(entry, now));
+            }
+        }
+        let Some(id) = frame.id else {
+            return;
+        };
+        let Ok(hash) = message_hash(frame) else {
+            return;
+        };
+        let mut log = self.log.lock().unwrap();
+        if log.messages.insert(id, Relayed { hash, to: frame.to.clone(), at: now }).is_none() {
+            log.order.push_back(id);
+        }
+        while log.order.len() > NOTARY_LOG_CAPACITY {
+            if let Some(oldest) = log.order.pop_front() {
+                log.messages.remove(&oldest);
+            }
+        }
+    }
+
+    /// Countersign a receipt sent by `from`
+    ///
+    /// # Returns
+    /// `Err` if the receipt is not from its recipient, its signature is
+    /// malformed, or the relay did not route that message to the recipient
+    pub fn countersign(&self, receipt: SignedDeliveryReceipt, from: &str, now: u64) -> Result<NotarizedReceipt, ChainError> {
+        if receipt.receipt.to != from {
+            return Err(ChainError::Signer(format!("Receipt for {} sent by {}", receipt.receipt.to, from)));
+        }
+        receipt.signer(&self.domain)?;
+        let relayed_at = {
+            let log = self.log.lock().unwrap();
+            let relayed = log
+                .messages
+                .get(&receipt.receipt.message_id)
+                .filter(|r| r.hash == receipt.receipt.message_hash && r.to == from)
+                .ok_or_else(|| ChainError::Signer(format!("Message {} was not relayed to {}", receipt.receipt.message_id, from)))?;
+            relayed.at
+        };
+        NotarizedReceipt::sign(receipt, relayed_at, now, &self.domain, &self.signer)
+    }
+
+    #[cfg(feature = "relay")]
+    fn countersign_frame(&self, frame: &mut OpacusFrame, now: u64) -> bool {
+        let Some(value) = frame.extensions.get(RECEIPT_EXTENSION) else {
+            return false;
+        };
+        let notarized = value
+            .deserialized()
+            .map_err(|e| ChainError::InvalidResponse(e.to_string()))
+            .and_then(|receipt| self.countersign(receipt, &frame.from, now))
+            .and_then(|notarized| ciborium::Value::serialized(&notarized).map_err(|e| ChainError::InvalidResponse(e.to_string())));
+        match notarized {
+            Ok(value) => {
+                frame.extensions.remove(RECEIPT_EXTENSION);
+                frame.extensions.insert(NOTARIZED_RECEIPT_EXTENSION.to_string(), value);
+                debug!("Notarized receipt from {}", frame.from);
+                true
+            }
+            Err(e) => {
+                warn!("Not notarizing receipt from {}: {}", frame.from, e);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(feature = "relay")]
+impl FrameNotary for ReceiptNotary {
+    fn notarize(&self, frame: &mut OpacusFrame) -> bool {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        self.record(frame, now);
+        self.countersign_frame(frame, now)
+    }
+}
+
+impl ChainClient {
+    /// Anchor a notarized receipt in the domain's contract and wait until it is mined
+    ///
+    /// Posts the evidence hash as a batch root of one message.
+    pub async fn anchor_receipt(
+        &self,
+        domain: &Eip712Domain,
+        notarized: &NotarizedReceipt,
+    ) -> Result<TransactionReceipt, ChainError> {
+        self.anchor_root(domain.verifying_contract, notarized.evidence_hash(domain), 1).await
+    }
+
+    /// When a notarized receipt was anchored
+    ///
+    /// # Returns
+    /// Block timestamp of the anchoring, or `None` if it was never anchored
+    pub async fn receipt_anchored_at(&self, domain: &Eip712Domain, notarized: &NotarizedReceipt) -> Result<Option<u64>, ChainError> {
+        self.anchored_at(domain.verifying_contract, notarized.evidence_hash(domain)).await
+    }
+}
+
+#[cfg(all(test, feature = "relay"))]
+mod tests {
+    use super::*;
+    use crate::crypto::{KeyManager, SecurityManager};
+    use crate::types::FrameOptions;
+
+    const KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    fn domain() -> Eip712Domain {
+        notary_domain(16602, "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".parse().unwrap())
+    }
+
+    #[test]
+    fn test_notarize_receipt() {
+        let alice = KeyManager::generate_identity(16602);
+        let bob = KeyManager::generate_identity(16602);
+        let mut security = SecurityManager::new();
+        let message = security.create_auth_frame_with(&alice, &[0; 32], FrameType::Msg, &bob.id, b"report".to_vec(), FrameOptions::default());
+        let unrelayed = security.create_auth_frame_with(&alice, &[0; 32], FrameType::Msg, &bob.id, b"other".to_vec(), FrameOptions::default());
+
+        let relay = ReceiptNotary::new(ChainSigner::from_hex(KEY).unwrap(), domain());
+        relay.record(&message, 1_000);
+
+        let recipient = ChainSigner::random();
+        let receipt = |frame: &OpacusFrame| {
+            SignedDeliveryReceipt::sign(DeliveryReceipt::for_frame(frame, 1_050).unwrap(), &domain(), &recipient).unwrap()
+        };
+        let mut ack = security.create_auth_frame_with(&bob, &[0; 32], FrameType::Msg, &alice.id, Vec::new(), FrameOptions::default());
+        ack.extensions.insert(RECEIPT_EXTENSION.into(), ciborium::Value::serialized(&receipt(&message)).unwrap());
+        assert!(relay.notarize(&mut ack));
+        assert!(!ack.extensions.contains_key(RECEIPT_EXTENSION));
+
+        let notarized: NotarizedReceipt = ack.extensions[NOTARIZED_RECEIPT_EXTENSION].deserialized().unwrap();
+        assert_eq!(notarized.relayed_at, 1_000);
+        assert_eq!(notarized.verify(&domain(), relay.address()).unwrap(), recipient.address());
+        assert!(notarized.verify(&domain(), ChainSigner::random().address()).is_err());
+        assert!(notarized.receipt.receipt.matches(&message));
+        let json = serde_json::to_string(&notarized).unwrap();
+        assert_eq!(serde_json::from_str::<NotarizedReceipt>(&json).unwrap(), notarized);
+
+        let mut backdated = notarized.clone();
+        backdated.receipt.receipt.received_at = 900;
+        assert_ne!(backdated.relay(&domain()).unwrap(), relay.address());
+        assert_ne!(backdated.evidence_hash(&domain()), notarized.evidence_hash(&domain()));
+
+        // Messages the relay did not route, and receipts sent by someone else
+        assert!(relay.countersign(receipt(&unrelayed), &bob.id, 2_000).is_err());
+        assert!(relay.countersign(receipt(&message), &alice.id, 2_000).is_err());
+        let mut altered = receipt(&message);
+        altered.receipt.message_hash = [1; 32];
+        assert!(relay.countersign(altered, &bob.id, 2_000).is_err());
+
+        // Frames without a receipt pass unchanged
+        let mut plain = unrelayed.clone();
+        assert!(!relay.notarize(&mut plain));
+        assert!(plain.extensions.is_empty());
+    }
+}
@@ -0,0 +1,300 @@
+//! Payment intents and on-chain settlement
+//!
+//! The payer signs a [`PaymentIntent`] as EIP-712 typed data and sends it in a
+//! `Payment` frame. The payee checks it with [`SignedPayment::verify`], and
+//! either party submits it to the payments contract, which exposes
+//!
+//! ```solidity
+//! function settle(PaymentIntent calldata intent, bytes calldata signature) external;
+//! ```
+//!
+//! and moves `amount` from the payer's deposit (native token) or allowance
+//! (ERC-20) to the payee, at most once per `paymentId`.
+
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::random::{random_array, OsRandom};
+use super::abi::{self, Token};
+use super::{
+    hex_bytes, quantity, recover_typed_data, Address, ChainClient, ChainError, ChainSigner, Eip712Domain,
+    TransactionRequest, TxHash, TypedData,
+};
+
+/// EIP-712 domain name of the payments contract
+pub const PAYMENT_DOMAIN_NAME: &str = "Opacus Payments";
+
+/// EIP-712 domain version of the payments contract
+pub const PAYMENT_DOMAIN_VERSION: &str = "1";
+
+/// Settlement function of the payments contract
+pub const SETTLE_SIGNATURE: &str = "settle((address,address,address,uint256,bytes32,uint256),bytes)";
+
+/// Time to wait for a settlement to be mined
+const SETTLEMENT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Signing domain of a payments contract
+pub fn payment_domain(chain_id: u64, contract: Address) -> Eip712Domain {
+    Eip712Domain::new(PAYMENT_DOMAIN_NAME, PAYMENT_DOMAIN_VERSION, chain_id, contract)
+}
+
+/// Authorization to pay `amount` of `token` from `payer` to `payee`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentIntent {
+    /// Paying account
+    pub payer: Address,
+    /// Receiving account
+    pub payee: Address,
+    /// ERC-20 token, or the zero address for the native token
+    pub token: Address,
+    /// Amount in the token's smallest unit
+    #[serde(with = "quantity")]
+    pub amount: u128,
+    /// Unique ID; the contract settles each ID once
+    #[serde(with = "hex_bytes")]
+    pub payment_id: [u8; 32],
+    /// Expiry (Unix seconds)
+    pub deadline: u64,
+}
+
+impl PaymentIntent {
+    /// Create intent with a random payment ID
+    pub fn new(payer: Address, payee: Address, token: Address, amount: u128, deadline: u64) -> Self {
+        Self {
+            payer,
+            payee,
+            token,
+            amount,
+            payment_id: random_array(&OsRandom),
+            deadline,
+        }
+    }
+
+    /// Whether the payment is in the native token
+    pub fn is_native(&self) -> bool {
+        self.token == Address::default()
+    }
+}
+
+impl TypedData for PaymentIntent {
+    const ENCODED_TYPE: &'static str =
+        "PaymentIntent(address payer,address payee,address token,uint256 amount,bytes32 paymentId,uint256 deadline)";
+
+    fn members(&self) -> Vec<Token> {
+        vec![
+            Token::Address(self.payer),
+            Token::Address(self.payee),
+            Token::Address(self.token),
+            Token::Uint(self.amount),
+            Token::FixedBytes(self.payment_id),
+            Token::Uint(self.deadline.into()),
+        ]
+    }
+}
+
+/// Payment intent signed by the payer (payload of `Payment` frames)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedPayment {
+    /// Signed intent
+    pub intent: PaymentIntent,
+    /// Payer's EIP-712 signature (`r || s || v`)
+    #[serde(with = "hex_bytes")]
+    pub signature: [u8; 65],
+}
+
+impl SignedPayment {
+    /// Sign an intent
+    ///
+    /// # Arguments
+    /// * `intent` - Intent whose `payer` is the signer's address
+    /// * `domain` - Domain of the payments contract
+    /// * `signer` - Payer's key
+    pub fn sign(intent: PaymentIntent, domain: &Eip712Domain, signer: &ChainSigner) -> Result<Self, ChainError> {
+        if signer.address() != intent.payer {
+            return Err(ChainError::Signer(format!("{} cannot sign for payer {}", signer.address(), intent.payer)));
+        }
+        let signature = signer.sign_typed_data(domain, &intent)?;
+        Ok(Self { intent, signature })
+    }
+
+    /// Check the payer's signature and the deadline
+    ///
+    /// # Arguments
+    /// * `domain` - Domain of the payments contract the payment will be settled with
+    /// * `now_secs` - Current time (Unix seconds)
+    pub fn verify(&self, domain: &Eip712Domain, now_secs: u64) -> Result<(), ChainError> {
+        if self.intent.amount == 0 {
+            return Err(ChainError::Payment("Zero amount".into()));
+        }
+        if self.intent.deadline <= now_secs {
+            return Err(ChainError::Payment(format!("Expired at {}", self.intent.deadline)));
+        }
+        let signer = recover_typed_data(domain, &self.intent, &self.signature)
+            .map_err(|e| ChainError::Payment(format!("Invalid signature: {}", e)))?;
+        if signer != self.intent.payer {
+            return Err(ChainError::Payment(format!("Signed by {}, not by payer {}", signer, self.intent.payer)));
+        }
+        Ok(())
+    }
+
+    /// Call data of the settlement transaction
+    pub fn settle_call(&self) -> Vec<u8> {
+        abi::encode_call(
+            SETTLE_SIGNATURE,
+            &[Token::Tuple(self.intent.members()), Token::Bytes(self.signature.to_vec())],
+        )
+    }
+}
+
+/// Outcome of a settlement transaction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentReceipt {
+    /// Settled payment
+    #[serde(with = "hex_bytes")]
+    pub payment_id: [u8; 32],
+    /// Settlement transaction
+    #[serde(with = "hex_bytes")]
+    pub tx_hash: TxHash,
+    /// Block containing the settlement
+    pub block_number: u64,
+    /// Whether the contract accepted the settlement
+    pub success: bool,
+}
+
+impl ChainClient {
+    /// Sign a payment from this account
+    ///
+    /// # Arguments
+    /// * `contract` - Payments contract
+    /// * `payee` - Receiving account
+    /// * `token` - ERC-20 token, or `Address::default()` for the native token
+    /// * `amount` - Amount in the token's smallest unit
+    /// * `deadline` - Expiry (Unix seconds)
+    pub fn sign_payment(
+        &self,
+        contract: Address,
+        payee: Address,
+        token: Address,
+        amount: u128,
+        deadline: u64,
+    ) -> Result<SignedPayment, ChainError> {
+        let signer = self.signer()?;
+        let intent = PaymentIntent::new(signer.address(), payee, token, amount, deadline);
+        SignedPayment::sign(intent, &payment_domain(self.chain_id(), contract), signer)
+    }
+
+    /// Submit a payment to the payments contract and wait until it is mined
+    ///
+    /// Either party may settle; the submitting account pays the gas.
+    pub async fn settle_payment(&self, contract: Address, payment: &SignedPayment) -> Result<PaymentReceipt, ChainError> {
+        let hash = self.send_transaction(TransactionRequest::call(contract, payment.settle_call())).await?;
+        let receipt = self.wait_for_receipt(&hash, SETTLEMENT_TIMEOUT).await?;
+        Ok(PaymentReceipt {
+            payment_id: payment.intent.payment_id,
+            tx_hash: receipt.transaction_hash,
+            block_number: receipt.block_number,
+            success: receipt.success,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use crate::chain::abi::ParamType;
+    use crate::chain::{mock, parse_data, Eip1559Transaction};
+
+    const KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    fn contract() -> Address {
+        "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".parse().unwrap()
+    }
+
+    #[test]
+    fn test_verify_payment() {
+        let signer = ChainSigner::from_hex(KEY).unwrap();
+        let payee = ChainSigner::random().address();
+        let domain = payment_domain(16602, contract());
+        let intent = PaymentIntent::new(signer.address(), payee, Address::default(), 1_000, 2_000);
+        assert!(intent.is_native());
+        let payment = SignedPayment::sign(intent.clone(), &domain, &signer).unwrap();
+        payment.verify(&domain, 1_999).unwrap();
+
+        // Round trip through the frame payload
+        let json = serde_json::to_value(&payment).unwrap();
+        assert_eq!(json["intent"]["amount"], "0x3e8");
+        assert_eq!(serde_json::from_value::<SignedPayment>(json).unwrap(), payment);
+
+        assert!(matches!(payment.verify(&domain, 2_000), Err(ChainError::Payment(_))));
+        assert!(payment.verify(&payment_domain(16661, contract()), 0).is_err());
+        let mut tampered = payment.clone();
+        tampered.intent.amount = 2_000;
+        assert!(tampered.verify(&domain, 0).is_err());
+        let mut zero = payment.clone();
+        zero.intent.amount = 0;
+        assert!(zero.verify(&domain, 0).is_err());
+
+        // Only the payer can sign
+        let other = PaymentIntent { payer: payee, ..intent };
+        assert!(matches!(SignedPayment::sign(other, &domain, &signer), Err(ChainError::Signer(_))));
+    }
+
+    #[tokio::test]
+    async fn test_settle_payment() {
+        let url = mock::serve(|method, params| match method {
+            "eth_getTransactionCount" => Ok(json!("0x0")),
+            "eth_getBlockByNumber" => Ok(json!({ "number": "0x10", "baseFeePerGas": "0x1" })),
+            "eth_maxPriorityFeePerGas" => Ok(json!("0x1")),
+            "eth_estimateGas" => Ok(json!("0x186a0")),
+            "eth_sendRawTransaction" => {
+                let raw = parse_data(params[0].as_str().unwrap()).unwrap();
+                Ok(json!(format!("0x{}", hex::encode(crate::chain::keccak256(&raw)))))
+            }
+            "eth_getTransactionReceipt" => Ok(json!({
+                "transactionHash": params[0],
+                "blockNumber": "0x11",
+                "gasUsed": "0x186a0",
+                "status": "0x1",
+                "contractAddress": Value::Null,
+            })),
+            _ => Err((-32601, format!("method {} not found", method))),
+        })
+        .await;
+        let client = ChainClient::new(&url, 16602).unwrap().with_signer(ChainSigner::from_hex(KEY).unwrap());
+        let payee = ChainSigner::random().address();
+        let payment = client.sign_payment(contract(), payee, Address::default(), 5, u64::MAX).unwrap();
+        payment.verify(&payment_domain(16602, contract()), 0).unwrap();
+
+        let receipt = client.settle_payment(contract(), &payment).await.unwrap();
+        assert_eq!((receipt.payment_id, receipt.block_number, receipt.success), (payment.intent.payment_id, 17, true));
+
+        // The settlement calls the contract with the intent and signature
+        let expected = Eip1559Transaction {
+            chain_id: 16602,
+            nonce: 0,
+            max_priority_fee_per_gas: 1,
+            max_fee_per_gas: 3,
+            gas_limit: 120_000,
+            to: Some(contract()),
+            value: 0,
+            data: payment.settle_call(),
+        };
+        let raw = ChainSigner::from_hex(KEY).unwrap().sign_transaction(&expected).unwrap();
+        assert_eq!(receipt.tx_hash, crate::chain::keccak256(&raw));
+
+        let call = payment.settle_call();
+        assert_eq!(call[..4], abi::selector(SETTLE_SIGNATURE));
+        let intent_type = ParamType::Tuple(vec![
+            ParamType::Address,
+            ParamType::Address,
+            ParamType::Address,
+            ParamType::Uint,
+            ParamType::FixedBytes,
+            ParamType::Uint,
+        ]);
+        let args = abi::decode(&[intent_type, ParamType::Bytes], &call[4..]).unwrap();
+        assert_eq!(args[1], Token::Bytes(payment.signature.to_vec()));
+    }
+}
@@ -0,0 +1,342 @@
+//! Unidirectional payment channels
+//!
+//! The payer locks a deposit in the channel contract and pays the payee
+//! off-chain with signed, cumulative balance updates, usually attached to the
+//! frames being paid for. The payee closes the channel with the latest update
+//! and receives its amount; the rest returns to the payer, who can also
+//! reclaim the whole deposit once an unclosed channel expires. The channel
+//! contract exposes
+//!
+//! ```solidity
+//! function open(bytes32 channelId, address payee, uint256 expiry) external payable;
+//! function close(bytes32 channelId, uint256 amount, bytes calldata signature) external;
+//! function reclaim(bytes32 channelId) external;
+//! function channels(bytes32 channelId) external view
+//!     returns (address payer, address payee, uint256 deposit, uint256 expiry);
+//! ```
+
+use serde::{Deserialize, Serialize};
+use crate::random::{random_array, OsRandom};
+use super::abi::{ParamType, Token};
+use super::{
+    hex_bytes, quantity, recover_typed_data, Address, ChainClient, ChainError, ChainSigner, Eip712Domain,
+    TransactionReceipt, TypedData,
+};
+
+/// EIP-712 domain name of the channel contract
+pub const CHANNEL_DOMAIN_NAME: &str = "Opacus Payment Channels";
+
+/// EIP-712 domain version of the channel contract
+pub const CHANNEL_DOMAIN_VERSION: &str = "1";
+
+/// Frame extension carrying a [`BalanceUpdate`]
+pub const BALANCE_EXTENSION: &str = "balance";
+
+/// Payer's signature over the total amount paid through a channel
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceUpdate {
+    /// Channel
+    #[serde(with = "hex_bytes")]
+    pub channel_id: [u8; 32],
+    /// Total paid since the channel was opened
+    #[serde(with = "quantity")]
+    pub amount: u128,
+    /// Payer's EIP-712 signature (`r || s || v`)
+    #[serde(with = "hex_bytes")]
+    pub signature: [u8; 65],
+}
+
+impl TypedData for BalanceUpdate {
+    const ENCODED_TYPE: &'static str = "ChannelBalance(bytes32 channelId,uint256 amount)";
+
+    fn members(&self) -> Vec<Token> {
+        vec![Token::FixedBytes(self.channel_id), Token::Uint(self.amount)]
+    }
+}
+
+/// Payment channel, as tracked by either party
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentChannel {
+    /// Channel ID, chosen by the payer
+    #[serde(with = "hex_bytes")]
+    pub id: [u8; 32],
+    /// Chain the contract lives on
+    pub chain_id: u64,
+    /// Channel contract
+    pub contract: Address,
+    /// Paying account
+    pub payer: Address,
+    /// Receiving account
+    pub payee: Address,
+    /// Locked deposit in wei
+    #[serde(with = "quantity")]
+    pub deposit: u128,
+    /// Time after which the payer can reclaim the deposit (Unix seconds)
+    pub expiry: u64,
+    /// Total paid so far
+    #[serde(with = "quantity")]
+    pub paid: u128,
+    /// Latest update received (payee side)
+    pub latest: Option<BalanceUpdate>,
+}
+
+impl PaymentChannel {
+    /// Signing domain of the channel contract
+    pub fn domain(&self) -> Eip712Domain {
+        Eip712Domain::new(CHANNEL_DOMAIN_NAME, CHANNEL_DOMAIN_VERSION, self.chain_id, self.contract)
+    }
+
+    /// Deposit not yet paid out
+    pub fn remaining(&self) -> u128 {
+        self.deposit - self.paid
+    }
+
+    /// Pay `amount` more (payer side)
+    ///
+    /// # Returns
+    /// Update to send to the payee
+    pub fn pay(&mut self, amount: u128, signer: &ChainSigner) -> Result<BalanceUpdate, ChainError> {
+        if signer.address() != self.payer {
+            return Err(ChainError::Signer(format!("{} is not the channel payer {}", signer.address(), self.payer)));
+        }
+        if amount > self.remaining() {
+            return Err(ChainError::Payment(format!("Channel has {} left, {} requested", self.remaining(), amount)));
+        }
+        let mut update = BalanceUpdate { channel_id: self.id, amount: self.paid + amount, signature: [0; 65] };
+        update.signature = signer.sign_typed_data(&self.domain(), &update)?;
+        self.paid = update.amount;
+        Ok(update)
+    }
+
+    /// Accept an update from the payer (payee side)
+    ///
+    /// # Arguments
+    /// * `update` - Received update
+    /// * `now_secs` - Current time (Unix seconds)
+    ///
+    /// # Returns
+    /// Amount newly paid by this update
+    pub fn accept(&mut self, update: BalanceUpdate, now_secs: u64) -> Result<u128, ChainError> {
+        if update.channel_id != self.id {
+            return Err(ChainError::Payment("Update for another channel".into()));
+        }
+        if self.expiry <= now_secs {
+            return Err(ChainError::Payment(format!("Channel expired at {}", self.expiry)));
+        }
+        if update.amount <= self.paid {
+            return Err(ChainError::Payment(format!("Stale update: {} after {}", update.amount, self.paid)));
+        }
+        if update.amount > self.deposit {
+            return Err(ChainError::Payment(format!("Update of {} exceeds deposit {}", update.amount, self.deposit)));
+        }
+        let signer = recover_typed_data(&self.domain(), &update, &update.signature)
+            .map_err(|e| ChainError::Payment(format!("Invalid signature: {}", e)))?;
+        if signer != self.payer {
+            return Err(ChainError::Payment(format!("Signed by {}, not by payer {}", signer, self.payer)));
+        }
+        let increment = update.amount - self.paid;
+        self.paid = update.amount;
+        self.latest = Some(update);
+        Ok(increment)
+    }
+}
+
+impl ChainClient {
+    /// Open a channel from this account and lock the deposit
+    ///
+    /// # Arguments
+    /// * `contract` - Channel contract
+    /// * `payee` - Receiving account
+    /// * `deposit` - Deposit in wei
+    /// * `expiry` - Time after which an unclosed channel can be reclaimed (Unix seconds)
+    pub async fn open_payment_channel(
+        &self,
+        contract: Address,
+        payee: Address,
+        deposit: u128,
+        expiry: u64,
+    ) -> Result<PaymentChannel, ChainError> {
+        let payer = self.signer()?.address();
+        let id: [u8; 32] = random_array(&OsRandom);
+        let args = [Token::FixedBytes(id), Token::Address(payee), Token::Uint(expiry.into())];
+        self.transact(contract, "open(bytes32,address,uint256)", &args, deposit).await?;
+        Ok(PaymentChannel {
+            id,
+            chain_id: self.chain_id(),
+            contract,
+            payer,
+            payee,
+            deposit,
+            expiry,
+            paid: 0,
+            latest: None,
+        })
+    }
+
+    /// Read an open channel from the contract
+    ///
+    /// # Returns
+    /// The channel with nothing paid yet, or `None` if it is closed or unknown
+    pub async fn payment_channel(&self, contract: Address, id: [u8; 32]) -> Result<Option<PaymentChannel>, ChainError> {
+        let outputs = [ParamType::Address, ParamType::Address, ParamType::Uint, ParamType::Uint];
+        let mut values = self.call(contract, "channels(bytes32)", &[Token::FixedBytes(id)], &outputs).await?.into_iter();
+        let mut next = || values.next().ok_or_else(|| ChainError::InvalidResponse("Short channel record".into()));
+        let payer = next()?.into_address().unwrap_or_default();
+        let payee = next()?.into_address().unwrap_or_default();
+        let deposit = next()?.into_uint().unwrap_or_default();
+        let expiry = next()?.into_uint().unwrap_or_default();
+        if payer == Address::default() {
+            return Ok(None);
+        }
+        Ok(Some(PaymentChannel {
+            id,
+            chain_id: self.chain_id(),
+            contract,
+            payer,
+            payee,
+            deposit,
+            expiry: expiry.try_into().unwrap_or(u64::MAX),
+            paid: 0,
+            latest: None,
+        }))
+    }
+
+    /// Close a channel with its latest update (payee side)
+    pub async fn close_payment_channel(&self, channel: &PaymentChannel) -> Result<TransactionReceipt, ChainError> {
+        let update = channel.latest.as_ref().ok_or_else(|| ChainError::Payment("Nothing to claim".into()))?;
+        let args = [
+            Token::FixedBytes(channel.id),
+            Token::Uint(update.amount),
+            Token::Bytes(update.signature.to_vec()),
+        ];
+        self.transact(channel.contract, "close(bytes32,uint256,bytes)", &args, 0).await
+    }
+
+    /// Reclaim the deposit of an expired channel (payer side)
+    pub async fn reclaim_payment_channel(&self, channel: &PaymentChannel) -> Result<TransactionReceipt, ChainError> {
+        self.transact(channel.contract, "reclaim(bytes32)", &[Token::FixedBytes(channel.id)], 0).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use serde_json::{json, Value};
+    use crate::chain::abi::{self, encode};
+    use crate::chain::{keccak256, mock, parse_data};
+
+    const KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    fn channel(payer: Address, payee: Address) -> PaymentChannel {
+        PaymentChannel {
+            id: [1; 32],
+            chain_id: 16602,
+            contract: "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".parse().unwrap(),
+            payer,
+            payee,
+            deposit: 100,
+            expiry: 1_000,
+            paid: 0,
+            latest: None,
+        }
+    }
+
+    #[test]
+    fn test_balance_updates() {
+        let payer = ChainSigner::from_hex(KEY).unwrap();
+        let payee = ChainSigner::random();
+        let mut sender = channel(payer.address(), payee.address());
+        let mut receiver = sender.clone();
+
+        let first = sender.pay(30, &payer).unwrap();
+        let second = sender.pay(50, &payer).unwrap();
+        assert_eq!((second.amount, sender.remaining()), (80, 20));
+        assert!(matches!(sender.pay(21, &payer), Err(ChainError::Payment(_))));
+        assert!(matches!(sender.pay(1, &payee), Err(ChainError::Signer(_))));
+
+        assert_eq!(receiver.accept(first.clone(), 999).unwrap(), 30);
+        assert_eq!(receiver.accept(second.clone(), 999).unwrap(), 50);
+        assert_eq!(receiver.latest.as_ref(), Some(&second));
+
+        // Replayed, forged and late updates are rejected
+        assert!(receiver.accept(first, 999).is_err());
+        let mut forged = second.clone();
+        forged.amount = 90;
+        assert!(receiver.accept(forged.clone(), 999).is_err());
+        forged.signature = payee.sign_typed_data(&receiver.domain(), &forged).unwrap();
+        assert!(receiver.accept(forged, 999).is_err());
+        let third = sender.pay(10, &payer).unwrap();
+        assert!(receiver.clone().accept(third.clone(), 1_000).is_err());
+        assert_eq!(receiver.accept(third, 999).unwrap(), 10);
+
+        let json = serde_json::to_value(&second).unwrap();
+        assert_eq!(serde_json::from_value::<BalanceUpdate>(json).unwrap(), second);
+    }
+
+    #[tokio::test]
+    async fn test_channel_lifecycle() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let record = sent.clone();
+        let url = mock::serve(move |method, params| match method {
+            "eth_getTransactionCount" => Ok(json!("0x0")),
+            "eth_getBlockByNumber" => Ok(json!({ "number": "0x10", "baseFeePerGas": "0x1" })),
+            "eth_maxPriorityFeePerGas" => Ok(json!("0x1")),
+            "eth_estimateGas" => Ok(json!("0x186a0")),
+            "eth_call" => {
+                let data = parse_data(params[0]["data"].as_str().unwrap()).unwrap();
+                let payer = ChainSigner::from_hex(KEY).unwrap().address();
+                let record = if data[4..] == [1; 32] { payer } else { Address::default() };
+                Ok(json!(format!(
+                    "0x{}",
+                    hex::encode(encode(&[
+                        Token::Address(record),
+                        Token::Address(payer),
+                        Token::Uint(100),
+                        Token::Uint(1_000)
+                    ]))
+                )))
+            }
+            "eth_sendRawTransaction" => {
+                let raw = parse_data(params[0].as_str().unwrap()).unwrap();
+                record.lock().unwrap().push(raw.clone());
+                Ok(json!(format!("0x{}", hex::encode(keccak256(&raw)))))
+            }
+            "eth_getTransactionReceipt" => Ok(json!({
+                "transactionHash": params[0],
+                "blockNumber": "0x11",
+                "gasUsed": "0x186a0",
+                "status": "0x1",
+                "contractAddress": Value::Null,
+            })),
+            _ => Err((-32601, format!("method {} not found", method))),
+        })
+        .await;
+        let signer = ChainSigner::from_hex(KEY).unwrap();
+        let client = ChainClient::new(&url, 16602).unwrap().with_signer(signer.clone());
+        let contract: Address = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".parse().unwrap();
+
+        let opened = client.open_payment_channel(contract, signer.address(), 100, 1_000).await.unwrap();
+        assert_eq!((opened.payer, opened.deposit, opened.paid), (signer.address(), 100, 0));
+
+        let mut known = client.payment_channel(contract, [1; 32]).await.unwrap().unwrap();
+        assert_eq!(known, channel(signer.address(), signer.address()));
+        assert!(client.payment_channel(contract, [2; 32]).await.unwrap().is_none());
+
+        assert!(matches!(client.close_payment_channel(&known).await, Err(ChainError::Payment(_))));
+        let update = known.clone().pay(40, &signer).unwrap();
+        known.accept(update.clone(), 0).unwrap();
+        client.close_payment_channel(&known).await.unwrap();
+
+        // The deposit travels as the value of `open`, the update as `close` arguments
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), 2);
+        let close = abi::encode_call(
+            "close(bytes32,uint256,bytes)",
+            &[Token::FixedBytes([1; 32]), Token::Uint(40), Token::Bytes(update.signature.to_vec())],
+        );
+        assert!(sent[1].windows(close.len()).any(|w| w == close));
+    }
+}
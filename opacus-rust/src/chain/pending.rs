@@ -0,0 +1,102 @@
+//! Pending transactions and fee bumping
+//!
+//! `ChainClient` remembers every transaction it submitted until one version
+//! of it is mined. Waiting on a transaction follows its replacements, and a
+//! transaction that stays unmined for `replace_after` is re-signed with the
+//! same nonce and higher fees.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use super::{Eip1559Transaction, TxHash};
+
+/// When and how stuck transactions are replaced
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeBumpPolicy {
+    /// Time a transaction may stay unmined before it is replaced
+    pub replace_after: Duration,
+    /// Fee increase per replacement in percent (nodes require at least 10)
+    pub bump_percent: u64,
+    /// Replacements of one transaction before bumping stops
+    pub max_replacements: usize,
+}
+
+impl Default for FeeBumpPolicy {
+    fn default() -> Self {
+        Self {
+            replace_after: Duration::from_secs(60),
+            bump_percent: 15,
+            max_replacements: 3,
+        }
+    }
+}
+
+impl FeeBumpPolicy {
+    /// Fee after one bump (at least 1 wei more)
+    pub fn bump(&self, fee: u128) -> u128 {
+        fee.saturating_add((fee * self.bump_percent as u128 / 100).max(1))
+    }
+}
+
+/// Transaction submitted by this account and not yet seen mined
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingTransaction {
+    /// Latest version of the transaction
+    pub tx: Eip1559Transaction,
+    /// Hashes of every submitted version, latest last
+    pub hashes: Vec<TxHash>,
+    /// When the latest version was submitted
+    pub submitted_at: Instant,
+}
+
+impl PendingTransaction {
+    /// Account nonce the transaction uses
+    pub fn nonce(&self) -> u64 {
+        self.tx.nonce
+    }
+
+    /// Hash of the latest version
+    pub fn hash(&self) -> TxHash {
+        *self.hashes.last().expect("submitted at least once")
+    }
+
+    /// Number of times the transaction was replaced
+    pub fn replacements(&self) -> usize {
+        self.hashes.len() - 1
+    }
+}
+
+/// Nonce counter and pending transactions of the account
+#[derive(Debug, Default)]
+pub(crate) struct AccountState {
+    /// Nonce of the next new transaction (`None` until read from the node)
+    pub next_nonce: Option<u64>,
+    /// Pending transactions by nonce
+    pub pending: BTreeMap<u64, PendingTransaction>,
+}
+
+impl AccountState {
+    /// Record a submitted transaction, or a new version of a pending one
+    pub fn submitted(&mut self, tx: Eip1559Transaction, hash: TxHash) {
+        let now = Instant::now();
+        match self.pending.get_mut(&tx.nonce) {
+            Some(pending) => {
+                pending.tx = tx;
+                pending.hashes.push(hash);
+                pending.submitted_at = now;
+            }
+            None => {
+                self.pending.insert(tx.nonce, PendingTransaction { tx, hashes: vec![hash], submitted_at: now });
+            }
+        }
+    }
+
+    /// Pending transaction with a version hashed `hash`
+    pub fn find(&self, hash: &TxHash) -> Option<&PendingTransaction> {
+        self.pending.values().find(|p| p.hashes.contains(hash))
+    }
+
+    /// Forget the transaction with `nonce` and all earlier ones, which are mined once it is
+    pub fn mined(&mut self, nonce: u64) {
+        self.pending = self.pending.split_off(&(nonce + 1));
+    }
+}
@@ -0,0 +1,272 @@
+//! Relay registry: staked relay operators
+//!
+//! Operators list their relay endpoints in a registry contract exposing
+//!
+//! ```solidity
+//! function registerRelay(string endpoint) external payable;
+//! function relayCount() external view returns (uint256);
+//! function relayAt(uint256 index) external view
+//!     returns (address operator, string endpoint, uint256 stake, bool active);
+//! function minStake() external view returns (uint256);
+//! function reportMisbehavior(address operator, bytes first, bytes second) external;
+//! ```
+//!
+//! where the sent value is the stake. The operator account is also the key
+//! of the relay's [`ReceiptNotary`](super::ReceiptNotary), so two
+//! [`NotarizedReceipt`]s it signed that contradict each other prove
+//! misbehavior and get its stake slashed.
+
+use std::sync::Arc;
+use super::abi::{self, ParamType, Token};
+use super::{Address, ChainClient, ChainError, Eip712Domain, NotarizedReceipt, TransactionReceipt};
+
+/// Registry entry of a relay
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayRecord {
+    /// Operator account
+    pub operator: Address,
+    /// Relay URL, e.g. `quic://relay.example.com:4242`
+    pub endpoint: String,
+    /// Stake in wei
+    pub stake: u128,
+    /// Cleared when the relay leaves or is slashed
+    pub active: bool,
+}
+
+/// Two receipts notarized by one relay that cannot both be true
+///
+/// The relay countersigned receipts for the same message ID but a
+/// different message hash, sender or recipient.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MisbehaviorEvidence {
+    /// First receipt
+    pub first: NotarizedReceipt,
+    /// Receipt contradicting the first
+    pub second: NotarizedReceipt,
+}
+
+impl MisbehaviorEvidence {
+    /// Check that the receipts contradict each other
+    ///
+    /// # Arguments
+    /// * `domain` - Notary domain the relay signed in
+    ///
+    /// # Returns
+    /// Account of the relay that signed both; `Err(ChainError::Signer)` if
+    /// they were signed by different relays or are consistent
+    pub fn check(&self, domain: &Eip712Domain) -> Result<Address, ChainError> {
+        let relay = self.first.relay(domain)?;
+        if self.second.relay(domain)? != relay {
+            return Err(ChainError::Signer("Receipts were notarized by different relays".into()));
+        }
+        let (a, b) = (&self.first.receipt.receipt, &self.second.receipt.receipt);
+        if a.message_id != b.message_id {
+            return Err(ChainError::Signer("Receipts are for different messages".into()));
+        }
+        if (a.message_hash, &a.from, &a.to) == (b.message_hash, &b.from, &b.to) {
+            return Err(ChainError::Signer(format!("Receipts for {} agree", a.message_id)));
+        }
+        Ok(relay)
+    }
+}
+
+/// ABI encoding of a notarized receipt, as `reportMisbehavior` takes it
+///
+/// `(bytes32 messageHash, string messageId, string from, string to,
+/// uint256 receivedAt, bytes recipientSignature, uint256 relayedAt,
+/// uint256 notarizedAt, bytes relaySignature)`
+fn encode_receipt(notarized: &NotarizedReceipt) -> Vec<u8> {
+    let receipt = &notarized.receipt.receipt;
+    abi::encode(&[
+        Token::FixedBytes(receipt.message_hash),
+        Token::String(receipt.message_id.to_string()),
+        Token::String(receipt.from.clone()),
+        Token::String(receipt.to.clone()),
+        Token::Uint(receipt.received_at.into()),
+        Token::Bytes(notarized.receipt.signature.to_vec()),
+        Token::Uint(notarized.relayed_at.into()),
+        Token::Uint(notarized.notarized_at.into()),
+        Token::Bytes(notarized.relay_signature.to_vec()),
+    ])
+}
+
+/// Client for the relay registry contract
+#[derive(Debug)]
+pub struct RelayRegistry {
+    chain: Arc<ChainClient>,
+    contract: Address,
+}
+
+impl RelayRegistry {
+    /// Create registry client
+    ///
+    /// # Arguments
+    /// * `chain` - Chain client; registering and reporting need a signer
+    /// * `contract` - Registry address
+    pub fn new(chain: Arc<ChainClient>, contract: Address) -> Self {
+        Self { chain, contract }
+    }
+
+    /// List a relay operated by the signer's account
+    ///
+    /// # Arguments
+    /// * `endpoint` - Relay URL
+    /// * `stake` - Stake in wei (at least `min_stake`)
+    pub async fn register(&self, endpoint: &str, stake: u128) -> Result<TransactionReceipt, ChainError> {
+        self.chain.transact(self.contract, "registerRelay(string)", &[Token::String(endpoint.to_string())], stake).await
+    }
+
+    /// Minimum stake of a listed relay
+    pub async fn min_stake(&self) -> Result<u128, ChainError> {
+        let values = self.chain.call(self.contract, "minStake()", &[], &[ParamType::Uint]).await?;
+        values.into_iter().next().and_then(Token::into_uint).ok_or_else(|| ChainError::InvalidResponse("minStake result".into()))
+    }
+
+    /// Every listed relay, in registration order
+    pub async fn relays(&self) -> Result<Vec<RelayRecord>, ChainError> {
+        let count = self
+            .chain
+            .call(self.contract, "relayCount()", &[], &[ParamType::Uint])
+            .await?
+            .into_iter()
+            .next()
+            .and_then(Token::into_uint)
+            .ok_or_else(|| ChainError::InvalidResponse("relayCount result".into()))?;
+        let outputs = [ParamType::Address, ParamType::String, ParamType::Uint, ParamType::Bool];
+        let mut relays = Vec::with_capacity(count as usize);
+        for index in 0..count {
+            let values = self.chain.call(self.contract, "relayAt(uint256)", &[Token::Uint(index)], &outputs).await?;
+            let mut fields = values.into_iter();
+            let mut next = || fields.next().ok_or_else(|| ChainError::InvalidResponse("Short relay record".into()));
+            relays.push(RelayRecord {
+                operator: next()?.into_address().unwrap_or_default(),
+                endpoint: next()?.into_string().unwrap_or_default(),
+                stake: next()?.into_uint().unwrap_or_default(),
+                active: next()?.into_bool().unwrap_or_default(),
+            });
+        }
+        Ok(relays)
+    }
+
+    /// Active relays staking at least `min_stake`, highest stake first
+    pub async fn staked_relays(&self, min_stake: u128) -> Result<Vec<RelayRecord>, ChainError> {
+        let mut relays: Vec<RelayRecord> =
+            self.relays().await?.into_iter().filter(|relay| relay.active && relay.stake >= min_stake).collect();
+        relays.sort_by_key(|relay| std::cmp::Reverse(relay.stake));
+        Ok(relays)
+    }
+
+    /// Report a relay's contradictory receipts so its stake is slashed
+    ///
+    /// # Arguments
+    /// * `domain` - Notary domain the relay signed in
+    /// * `evidence` - Contradictory receipts
+    pub async fn report(&self, domain: &Eip712Domain, evidence: &MisbehaviorEvidence) -> Result<TransactionReceipt, ChainError> {
+        let operator = evidence.check(domain)?;
+        let args = [
+            Token::Address(operator),
+            Token::Bytes(encode_receipt(&evidence.first)),
+            Token::Bytes(encode_receipt(&evidence.second)),
+        ];
+        self.chain.transact(self.contract, "reportMisbehavior(address,bytes,bytes)", &args, 0).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use crate::chain::abi::{decode, selector};
+    use crate::chain::{keccak256, mock, notary_domain, parse_data, ChainSigner, DeliveryReceipt, SignedDeliveryReceipt};
+    use crate::types::OpacusFrame;
+
+    const KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    fn operator(i: u8) -> Address {
+        Address([i; 20])
+    }
+
+    /// Registry with three relays, the second inactive
+    fn handle(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        let result = |tokens: &[Token]| Ok(json!(format!("0x{}", hex::encode(abi::encode(tokens)))));
+        match method {
+            "eth_call" => {
+                let data = parse_data(params[0]["data"].as_str().unwrap()).unwrap();
+                if data[..4] == selector("relayCount()") {
+                    return result(&[Token::Uint(3)]);
+                }
+                assert_eq!(data[..4], selector("relayAt(uint256)"));
+                let index = decode(&[ParamType::Uint], &data[4..]).unwrap()[0].clone().into_uint().unwrap() as u8;
+                result(&[
+                    Token::Address(operator(index)),
+                    Token::String(format!("quic://relay{}.example.com:4242", index)),
+                    Token::Uint([5, 20, 10][index as usize]),
+                    Token::Bool(index != 1),
+                ])
+            }
+            "eth_getTransactionCount" => Ok(json!("0x0")),
+            "eth_getBlockByNumber" => Ok(json!({ "number": "0x10", "baseFeePerGas": "0x1" })),
+            "eth_maxPriorityFeePerGas" => Ok(json!("0x1")),
+            "eth_estimateGas" => {
+                let data = parse_data(params[0]["data"].as_str().unwrap()).unwrap();
+                assert_eq!(data[..4], selector("reportMisbehavior(address,bytes,bytes)"));
+                let args = decode(&[ParamType::Address, ParamType::Bytes, ParamType::Bytes], &data[4..]).unwrap();
+                assert_eq!(args[0], Token::Address(ChainSigner::from_hex(KEY).unwrap().address()));
+                Ok(json!("0x30000"))
+            }
+            "eth_sendRawTransaction" => {
+                let raw = parse_data(params[0].as_str().unwrap()).unwrap();
+                Ok(json!(format!("0x{}", hex::encode(keccak256(&raw)))))
+            }
+            "eth_getTransactionReceipt" => Ok(json!({
+                "transactionHash": params[0],
+                "blockNumber": "0x11",
+                "gasUsed": "0x30000",
+                "status": "0x1",
+                "contractAddress": Value::Null,
+            })),
+            _ => Err((-32601, format!("method {} not found", method))),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_relay_registry() {
+        let url = mock::serve(handle).await;
+        let chain = Arc::new(ChainClient::new(&url, 16602).unwrap().with_signer(ChainSigner::random()));
+        let registry = RelayRegistry::new(chain, operator(9));
+
+        let relays = registry.relays().await.unwrap();
+        assert_eq!(relays.len(), 3);
+        assert_eq!(relays[2].endpoint, "quic://relay2.example.com:4242");
+        let staked: Vec<Address> = registry.staked_relays(0).await.unwrap().iter().map(|r| r.operator).collect();
+        assert_eq!(staked, vec![operator(2), operator(0)]);
+        assert_eq!(registry.staked_relays(6).await.unwrap().len(), 1);
+
+        // A relay notarizing two different messages under one ID
+        let domain = notary_domain(16602, operator(9));
+        let relay = ChainSigner::from_hex(KEY).unwrap();
+        let recipient = ChainSigner::random();
+        let message_id = OpacusFrame::new_id(1_000);
+        let notarize = |hash: [u8; 32], relay: &ChainSigner| {
+            let receipt = DeliveryReceipt {
+                message_hash: hash,
+                message_id,
+                from: "alice".into(),
+                to: "bob".into(),
+                received_at: 1_050,
+            };
+            let signed = SignedDeliveryReceipt::sign(receipt, &domain, &recipient).unwrap();
+            NotarizedReceipt::sign(signed, 1_000, 1_100, &domain, relay).unwrap()
+        };
+        let first = notarize([1; 32], &relay);
+        let evidence = MisbehaviorEvidence { first: first.clone(), second: notarize([2; 32], &relay) };
+        assert_eq!(evidence.check(&domain).unwrap(), relay.address());
+        assert_eq!(registry.report(&domain, &evidence).await.unwrap().block_number, 17);
+
+        // Consistent receipts and receipts from different relays are no evidence
+        let renotarized = NotarizedReceipt::sign(first.receipt.clone(), 1_000, 1_300, &domain, &relay).unwrap();
+        assert!(MisbehaviorEvidence { first: first.clone(), second: renotarized }.check(&domain).is_err());
+        let other = MisbehaviorEvidence { first, second: notarize([2; 32], &ChainSigner::random()) };
+        assert!(other.check(&domain).is_err());
+    }
+}
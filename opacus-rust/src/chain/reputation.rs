@@ -0,0 +1,189 @@
+//! Reputation registry: published attestations
+//!
+//! [`ReputationAttestation`]s are signed with the issuer's Ed25519 key, which
+//! contracts cannot check, so the registry only stores them for anyone to
+//! fetch and verify:
+//!
+//! ```solidity
+//! function attest(string issuer, bytes32 issuerKey, string subject, uint8 claim,
+//!     bool positive, string reference, uint256 ts, bytes signature) external;
+//! function attestationCount(string subject) external view returns (uint256);
+//! function attestationAt(string subject, uint256 index) external view
+//!     returns (string issuer, bytes32 issuerKey, uint8 claim, bool positive,
+//!         string reference, uint256 ts, bytes signature);
+//! ```
+
+use std::sync::Arc;
+use crate::reputation::{ReputationAttestation, ReputationClaim};
+use super::abi::{ParamType, Token};
+use super::{Address, ChainClient, ChainError, TransactionReceipt};
+
+/// Publishing function of the reputation registry
+pub const ATTEST_SIGNATURE: &str = "attest(string,bytes32,string,uint8,bool,string,uint256,bytes)";
+
+/// Client for the reputation registry contract
+#[derive(Debug)]
+pub struct ReputationRegistry {
+    chain: Arc<ChainClient>,
+    contract: Address,
+}
+
+impl ReputationRegistry {
+    /// Create registry client
+    ///
+    /// # Arguments
+    /// * `chain` - Chain client; publishing needs a signer
+    /// * `contract` - Registry address
+    pub fn new(chain: Arc<ChainClient>, contract: Address) -> Self {
+        Self { chain, contract }
+    }
+
+    /// Publish an attestation (checked first) and wait until it is mined
+    pub async fn publish(&self, attestation: &ReputationAttestation) -> Result<TransactionReceipt, ChainError> {
+        attestation.verify().map_err(ChainError::Signer)?;
+        let args = [
+            Token::String(attestation.issuer.clone()),
+            Token::FixedBytes(attestation.issuer_key),
+            Token::String(attestation.subject.clone()),
+            Token::Uint(attestation.claim.code().into()),
+            Token::Bool(attestation.positive),
+            Token::String(attestation.reference.clone()),
+            Token::Uint(attestation.ts.into()),
+            Token::Bytes(attestation.signature.clone()),
+        ];
+        self.chain.transact(self.contract, ATTEST_SIGNATURE, &args, 0).await
+    }
+
+    /// Attestations published about an agent, in publishing order
+    ///
+    /// They are returned as stored; verify them (e.g. by adding them to a
+    /// [`ReputationBook`](crate::reputation::ReputationBook)) before use.
+    pub async fn attestations(&self, subject: &str) -> Result<Vec<ReputationAttestation>, ChainError> {
+        let subject_arg = Token::String(subject.to_string());
+        let count = self
+            .chain
+            .call(self.contract, "attestationCount(string)", std::slice::from_ref(&subject_arg), &[ParamType::Uint])
+            .await?
+            .into_iter()
+            .next()
+            .and_then(Token::into_uint)
+            .ok_or_else(|| ChainError::InvalidResponse("attestationCount result".into()))?;
+        let outputs = [
+            ParamType::String,
+            ParamType::FixedBytes,
+            ParamType::Uint,
+            ParamType::Bool,
+            ParamType::String,
+            ParamType::Uint,
+            ParamType::Bytes,
+        ];
+        let mut attestations = Vec::with_capacity(count as usize);
+        for index in 0..count {
+            let args = [subject_arg.clone(), Token::Uint(index)];
+            let values = self.chain.call(self.contract, "attestationAt(string,uint256)", &args, &outputs).await?;
+            let mut fields = values.into_iter();
+            let mut next = || fields.next().ok_or_else(|| ChainError::InvalidResponse("Short attestation record".into()));
+            let issuer = next()?.into_string().unwrap_or_default();
+            let issuer_key = next()?.into_fixed_bytes().unwrap_or_default();
+            let code = next()?.into_uint().unwrap_or_default();
+            let claim = u8::try_from(code)
+                .ok()
+                .and_then(ReputationClaim::from_code)
+                .ok_or_else(|| ChainError::InvalidResponse(format!("Unknown reputation claim {}", code)))?;
+            attestations.push(ReputationAttestation {
+                issuer,
+                issuer_key,
+                subject: subject.to_string(),
+                claim,
+                positive: next()?.into_bool().unwrap_or_default(),
+                reference: next()?.into_string().unwrap_or_default(),
+                ts: next()?.into_uint().unwrap_or_default() as u64,
+                signature: next()?.into_bytes().unwrap_or_default(),
+            });
+        }
+        Ok(attestations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use serde_json::{json, Value};
+    use crate::chain::abi::{self, decode, selector};
+    use crate::chain::{keccak256, mock, parse_data, ChainSigner};
+    use crate::crypto::KeyManager;
+
+    #[tokio::test]
+    async fn test_reputation_registry() {
+        let alice = KeyManager::generate_identity(16602);
+        let bob = KeyManager::generate_identity(16602);
+        let attestation = ReputationAttestation::sign(&alice, &bob.id, ReputationClaim::PaymentHonored, true, "pay-1", 1_000);
+
+        // Registry storing what is published
+        let stored: Arc<Mutex<Vec<Vec<Token>>>> = Arc::default();
+        let registry_state = stored.clone();
+        let url = mock::serve(move |method, params| {
+            let result = |tokens: &[Token]| Ok(json!(format!("0x{}", hex::encode(abi::encode(tokens)))));
+            match method {
+                "eth_call" => {
+                    let data = parse_data(params[0]["data"].as_str().unwrap()).unwrap();
+                    let stored = registry_state.lock().unwrap();
+                    if data[..4] == selector("attestationCount(string)") {
+                        return result(&[Token::Uint(stored.len() as u128)]);
+                    }
+                    assert_eq!(data[..4], selector("attestationAt(string,uint256)"));
+                    let index = decode(&[ParamType::String, ParamType::Uint], &data[4..]).unwrap()[1].clone().into_uint().unwrap();
+                    let mut record = stored[index as usize].clone();
+                    record.remove(2);
+                    result(&record)
+                }
+                "eth_getTransactionCount" => Ok(json!("0x0")),
+                "eth_getBlockByNumber" => Ok(json!({ "number": "0x10", "baseFeePerGas": "0x1" })),
+                "eth_maxPriorityFeePerGas" => Ok(json!("0x1")),
+                "eth_estimateGas" => {
+                    let data = parse_data(params[0]["data"].as_str().unwrap()).unwrap();
+                    assert_eq!(data[..4], selector(ATTEST_SIGNATURE));
+                    let types = [
+                        ParamType::String,
+                        ParamType::FixedBytes,
+                        ParamType::String,
+                        ParamType::Uint,
+                        ParamType::Bool,
+                        ParamType::String,
+                        ParamType::Uint,
+                        ParamType::Bytes,
+                    ];
+                    registry_state.lock().unwrap().push(decode(&types, &data[4..]).unwrap());
+                    Ok(json!("0x20000"))
+                }
+                "eth_sendRawTransaction" => {
+                    let raw = parse_data(params[0].as_str().unwrap()).unwrap();
+                    Ok(json!(format!("0x{}", hex::encode(keccak256(&raw)))))
+                }
+                "eth_getTransactionReceipt" => Ok(json!({
+                    "transactionHash": params[0],
+                    "blockNumber": "0x11",
+                    "gasUsed": "0x20000",
+                    "status": "0x1",
+                    "contractAddress": Value::Null,
+                })),
+                _ => Err((-32601, format!("method {} not found", method))),
+            }
+        })
+        .await;
+        let chain = Arc::new(ChainClient::new(&url, 16602).unwrap().with_signer(ChainSigner::random()));
+        let registry = ReputationRegistry::new(chain, Address([9; 20]));
+
+        assert!(registry.attestations(&bob.id).await.unwrap().is_empty());
+        assert_eq!(registry.publish(&attestation).await.unwrap().block_number, 17);
+        let published = registry.attestations(&bob.id).await.unwrap();
+        assert_eq!(published, vec![attestation.clone()]);
+        published[0].verify().unwrap();
+
+        // Tampered attestations are not published
+        let tampered = ReputationAttestation { positive: false, ..attestation };
+        assert!(matches!(registry.publish(&tampered).await, Err(ChainError::Signer(_))));
+        assert_eq!(stored.lock().unwrap().len(), 1);
+    }
+}
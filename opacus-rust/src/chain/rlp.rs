@@ -0,0 +1,88 @@
+//! Recursive Length Prefix encoding (transaction serialization)
+
+/// Builds an RLP list item by item
+#[derive(Default)]
+pub(crate) struct RlpList {
+    body: Vec<u8>,
+}
+
+impl RlpList {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a byte string
+    pub(crate) fn bytes(&mut self, data: &[u8]) -> &mut Self {
+        match data {
+            [b] if *b < 0x80 => self.body.push(*b),
+            _ => {
+                write_len(&mut self.body, 0x80, data.len());
+                self.body.extend_from_slice(data);
+            }
+        }
+        self
+    }
+
+    /// Append an unsigned integer (big-endian, no leading zeros)
+    pub(crate) fn uint(&mut self, value: u128) -> &mut Self {
+        let be = value.to_be_bytes();
+        let start = be.iter().position(|&b| b != 0).unwrap_or(be.len());
+        self.bytes(&be[start..])
+    }
+
+    /// Append an already encoded list
+    pub(crate) fn list(&mut self, list: &RlpList) -> &mut Self {
+        self.body.extend_from_slice(&list.finish());
+        self
+    }
+
+    /// Encoded list
+    pub(crate) fn finish(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.body.len() + 9);
+        write_len(&mut out, 0xc0, self.body.len());
+        out.extend_from_slice(&self.body);
+        out
+    }
+}
+
+fn write_len(out: &mut Vec<u8>, offset: u8, len: usize) {
+    if len < 56 {
+        out.push(offset + len as u8);
+        return;
+    }
+    let be = (len as u64).to_be_bytes();
+    let start = be.iter().position(|&b| b != 0).unwrap_or(be.len());
+    out.push(offset + 55 + (be.len() - start) as u8);
+    out.extend_from_slice(&be[start..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rlp_vectors() {
+        let item = |f: fn(&mut RlpList)| {
+            let mut list = RlpList::new();
+            f(&mut list);
+            // Strip the list header of a single-item list
+            list.finish()[1..].to_vec()
+        };
+        assert_eq!(item(|l| { l.bytes(b"dog"); }), hex::decode("83646f67").unwrap());
+        assert_eq!(item(|l| { l.bytes(b""); }), [0x80]);
+        assert_eq!(item(|l| { l.uint(0); }), [0x80]);
+        assert_eq!(item(|l| { l.uint(15); }), [0x0f]);
+        assert_eq!(item(|l| { l.uint(1024); }), [0x82, 0x04, 0x00]);
+
+        let mut list = RlpList::new();
+        list.bytes(b"cat").bytes(b"dog");
+        assert_eq!(list.finish(), hex::decode("c88363617483646f67").unwrap());
+        assert_eq!(RlpList::new().finish(), [0xc0]);
+
+        let lorem = b"Lorem ipsum dolor sit amet, consectetur adipisicing elit";
+        let mut list = RlpList::new();
+        list.bytes(lorem);
+        assert_eq!(list.finish()[..3], [0xf8, 0x3a, 0xb8]);
+        assert_eq!(list.finish()[3], 0x38);
+    }
+}
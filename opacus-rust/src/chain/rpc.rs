@@ -0,0 +1,248 @@
+//! Ethereum JSON-RPC over HTTP
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use super::{
+    parse_data, parse_quantity, to_quantity, Address, ChainError, Log, LogFilter, TransactionReceipt, TransactionRequest,
+    TxHash,
+};
+
+/// HTTP timeout for a single RPC call
+const RPC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// JSON-RPC client for an EVM node
+#[derive(Debug)]
+pub struct ChainRpc {
+    http: reqwest::Client,
+    url: String,
+    next_id: AtomicU64,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    #[serde(default)]
+    result: Option<Value>,
+    #[serde(default)]
+    error: Option<RpcError>,
+}
+
+#[derive(Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl ChainRpc {
+    /// Create client for an RPC endpoint
+    ///
+    /// # Arguments
+    /// * `url` - HTTP(S) endpoint (e.g., "https://evmrpc-testnet.0g.ai")
+    pub fn new(url: &str) -> Result<Self, ChainError> {
+        let http = reqwest::Client::builder().timeout(RPC_TIMEOUT).build()?;
+        Ok(Self {
+            http,
+            url: url.to_string(),
+            next_id: AtomicU64::new(1),
+        })
+    }
+
+    /// Endpoint URL
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Call a JSON-RPC method
+    ///
+    /// # Arguments
+    /// * `method` - Method name (e.g., "eth_blockNumber")
+    /// * `params` - Positional parameters (a JSON array)
+    pub async fn request<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T, ChainError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let body = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        let response: RpcResponse = self
+            .http
+            .post(&self.url)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if let Some(error) = response.error {
+            return Err(ChainError::Rpc { code: error.code, message: error.message });
+        }
+        serde_json::from_value(response.result.unwrap_or(Value::Null))
+            .map_err(|e| ChainError::InvalidResponse(format!("{}: {}", method, e)))
+    }
+
+    async fn quantity(&self, method: &str, params: Value) -> Result<u128, ChainError> {
+        let result: String = self.request(method, params).await?;
+        parse_quantity(&result)
+    }
+
+    /// Chain ID reported by the node
+    pub async fn chain_id(&self) -> Result<u64, ChainError> {
+        to_u64(self.quantity("eth_chainId", json!([])).await?)
+    }
+
+    /// Latest block number
+    pub async fn block_number(&self) -> Result<u64, ChainError> {
+        to_u64(self.quantity("eth_blockNumber", json!([])).await?)
+    }
+
+    /// Balance of an account in wei
+    pub async fn balance(&self, address: &Address) -> Result<u128, ChainError> {
+        self.quantity("eth_getBalance", json!([address, "latest"])).await
+    }
+
+    /// Next nonce of an account, counting pending transactions
+    pub async fn transaction_count(&self, address: &Address) -> Result<u64, ChainError> {
+        to_u64(self.quantity("eth_getTransactionCount", json!([address, "pending"])).await?)
+    }
+
+    /// Legacy gas price in wei
+    pub async fn gas_price(&self) -> Result<u128, ChainError> {
+        self.quantity("eth_gasPrice", json!([])).await
+    }
+
+    /// Suggested priority fee per gas in wei
+    pub async fn max_priority_fee(&self) -> Result<u128, ChainError> {
+        self.quantity("eth_maxPriorityFeePerGas", json!([])).await
+    }
+
+    /// Base fee of the latest block (`None` before London)
+    pub async fn base_fee(&self) -> Result<Option<u128>, ChainError> {
+        let block: Value = self.request("eth_getBlockByNumber", json!(["latest", false])).await?;
+        block
+            .get("baseFeePerGas")
+            .and_then(Value::as_str)
+            .map(parse_quantity)
+            .transpose()
+    }
+
+    /// Estimate the gas a transaction uses
+    ///
+    /// # Arguments
+    /// * `from` - Sender, if known
+    /// * `tx` - Transaction to estimate
+    pub async fn estimate_gas(&self, from: Option<Address>, tx: &TransactionRequest) -> Result<u64, ChainError> {
+        to_u64(self.quantity("eth_estimateGas", json!([call_object(from, tx)])).await?)
+    }
+
+    /// Execute a call against the latest state without submitting it
+    ///
+    /// # Returns
+    /// Return data of the call
+    pub async fn call(&self, from: Option<Address>, tx: &TransactionRequest) -> Result<Vec<u8>, ChainError> {
+        let result: String = self.request("eth_call", json!([call_object(from, tx), "latest"])).await?;
+        parse_data(&result)
+    }
+
+    /// Submit a signed transaction
+    pub async fn send_raw_transaction(&self, raw: &[u8]) -> Result<TxHash, ChainError> {
+        let result: String = self.request("eth_sendRawTransaction", json!([format!("0x{}", hex::encode(raw))])).await?;
+        parse_hash(&result)
+    }
+
+    /// Receipt of a transaction (`None` while pending)
+    pub async fn transaction_receipt(&self, hash: &TxHash) -> Result<Option<TransactionReceipt>, ChainError> {
+        let receipt: Option<Value> = self
+            .request("eth_getTransactionReceipt", json!([format!("0x{}", hex::encode(hash))]))
+            .await?;
+        receipt.map(|r| parse_receipt(&r)).transpose()
+    }
+
+    /// Events matching a filter
+    pub async fn logs(&self, filter: &LogFilter) -> Result<Vec<Log>, ChainError> {
+        let topics: Vec<Value> = filter
+            .topics
+            .iter()
+            .map(|t| t.map_or(Value::Null, |t| json!(format!("0x{}", hex::encode(t)))))
+            .collect();
+        let mut params = json!({
+            "fromBlock": to_quantity(filter.from_block.into()),
+            "toBlock": filter.to_block.map_or("latest".to_string(), |b| to_quantity(b.into())),
+            "topics": topics,
+        });
+        if let Some(address) = filter.address {
+            params["address"] = json!(address);
+        }
+        let logs: Vec<Value> = self.request("eth_getLogs", json!([params])).await?;
+        logs.iter().map(parse_log).collect()
+    }
+}
+
+fn call_object(from: Option<Address>, tx: &TransactionRequest) -> Value {
+    let mut call = json!({
+        "value": to_quantity(tx.value),
+        "data": format!("0x{}", hex::encode(&tx.data)),
+    });
+    if let Some(from) = from {
+        call["from"] = json!(from);
+    }
+    if let Some(to) = tx.to {
+        call["to"] = json!(to);
+    }
+    if let Some(gas) = tx.gas_limit {
+        call["gas"] = json!(to_quantity(gas.into()));
+    }
+    call
+}
+
+fn to_u64(value: u128) -> Result<u64, ChainError> {
+    u64::try_from(value).map_err(|_| ChainError::InvalidResponse(format!("Quantity out of range: {}", value)))
+}
+
+fn parse_hash(s: &str) -> Result<TxHash, ChainError> {
+    parse_data(s)?
+        .try_into()
+        .map_err(|_| ChainError::InvalidResponse(format!("Invalid hash: {}", s)))
+}
+
+fn parse_receipt(receipt: &Value) -> Result<TransactionReceipt, ChainError> {
+    let field = |name: &str| {
+        receipt
+            .get(name)
+            .and_then(Value::as_str)
+            .ok_or_else(|| ChainError::InvalidResponse(format!("Receipt without {}", name)))
+    };
+    Ok(TransactionReceipt {
+        transaction_hash: parse_hash(field("transactionHash")?)?,
+        block_number: to_u64(parse_quantity(field("blockNumber")?)?)?,
+        gas_used: to_u64(parse_quantity(field("gasUsed")?)?)?,
+        success: parse_quantity(field("status")?)? == 1,
+        contract_address: match receipt.get("contractAddress").and_then(Value::as_str) {
+            Some(address) => Some(address.parse().map_err(ChainError::InvalidResponse)?),
+            None => None,
+        },
+        logs: match receipt.get("logs").and_then(Value::as_array) {
+            Some(logs) => logs.iter().map(parse_log).collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        },
+    })
+}
+
+fn parse_log(log: &Value) -> Result<Log, ChainError> {
+    let field = |name: &str| {
+        log.get(name)
+            .and_then(Value::as_str)
+            .ok_or_else(|| ChainError::InvalidResponse(format!("Log without {}", name)))
+    };
+    let topics = log
+        .get("topics")
+        .and_then(Value::as_array)
+        .ok_or_else(|| ChainError::InvalidResponse("Log without topics".into()))?
+        .iter()
+        .map(|t| parse_hash(t.as_str().unwrap_or_default()))
+        .collect::<Result<_, _>>()?;
+    Ok(Log {
+        address: field("address")?.parse().map_err(ChainError::InvalidResponse)?,
+        topics,
+        data: parse_data(field("data")?)?,
+        block_number: to_u64(parse_quantity(field("blockNumber")?)?)?,
+        transaction_hash: parse_hash(field("transactionHash")?)?,
+    })
+}
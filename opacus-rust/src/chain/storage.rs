@@ -0,0 +1,163 @@
+//! Off-chain content referenced from contracts
+//!
+//! Registries keep only a URI on chain; the document it points to lives in a
+//! [`ContentStore`].
+
+use std::future::Future;
+use std::time::Duration;
+use serde::Deserialize;
+use super::ChainError;
+
+/// HTTP timeout for uploads and downloads
+const STORAGE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Content-addressed blob storage
+pub trait ContentStore: Send + Sync {
+    /// Store a blob
+    ///
+    /// # Returns
+    /// URI to put on chain
+    fn put(&self, data: Vec<u8>) -> impl Future<Output = Result<String, ChainError>> + Send;
+
+    /// Fetch a blob by URI
+    fn get(&self, uri: &str) -> impl Future<Output = Result<Vec<u8>, ChainError>> + Send;
+}
+
+/// IPFS node (HTTP API) and gateway
+///
+/// Uploads are pinned on the node and addressed as `ipfs://<cid>`. Plain
+/// `http(s)://` URIs are fetched as they are.
+#[derive(Debug, Clone)]
+pub struct IpfsStore {
+    http: reqwest::Client,
+    api_url: String,
+    gateway_url: String,
+}
+
+#[derive(Deserialize)]
+struct AddResponse {
+    #[serde(rename = "Hash")]
+    hash: String,
+}
+
+impl IpfsStore {
+    /// Create store
+    ///
+    /// # Arguments
+    /// * `api_url` - Node RPC API (e.g., "http://127.0.0.1:5001")
+    /// * `gateway_url` - Gateway for reads (e.g., "https://ipfs.io")
+    pub fn new(api_url: &str, gateway_url: &str) -> Result<Self, ChainError> {
+        Ok(Self {
+            http: reqwest::Client::builder().timeout(STORAGE_TIMEOUT).build()?,
+            api_url: api_url.trim_end_matches('/').to_string(),
+            gateway_url: gateway_url.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+impl ContentStore for IpfsStore {
+    async fn put(&self, data: Vec<u8>) -> Result<String, ChainError> {
+        let form = reqwest::multipart::Form::new().part("file", reqwest::multipart::Part::bytes(data));
+        let added: AddResponse = self
+            .http
+            .post(format!("{}/api/v0/add?pin=true", self.api_url))
+            .multipart(form)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(format!("ipfs://{}", added.hash))
+    }
+
+    async fn get(&self, uri: &str) -> Result<Vec<u8>, ChainError> {
+        let url = match uri.strip_prefix("ipfs://") {
+            Some(cid) => format!("{}/ipfs/{}", self.gateway_url, cid),
+            None if uri.starts_with("https://") || uri.starts_with("http://") => uri.to_string(),
+            None => return Err(ChainError::Storage(format!("Unsupported URI: {}", uri))),
+        };
+        let data = self.http.get(url).send().await?.error_for_status()?.bytes().await?;
+        Ok(data.to_vec())
+    }
+}
+
+/// 0G storage, through an indexer
+///
+/// Files are addressed as `0g://<root>`, where the root is the file's 0G
+/// Merkle root, and downloaded from the indexer's `/file?root=` endpoint.
+/// Uploads are posted as multipart to `/file/upload` on the indexer URL,
+/// which must be a gateway that submits the file to the storage network
+/// and answers with its root (e.g. a 0G storage client in gateway mode).
+#[derive(Debug, Clone)]
+pub struct ZeroGStore {
+    http: reqwest::Client,
+    indexer_url: String,
+}
+
+#[derive(Deserialize)]
+struct UploadResponse {
+    root: String,
+}
+
+impl ZeroGStore {
+    /// Create store
+    ///
+    /// # Arguments
+    /// * `indexer_url` - Indexer or upload gateway (see `Network::storage_indexer`)
+    pub fn new(indexer_url: &str) -> Result<Self, ChainError> {
+        Ok(Self {
+            http: reqwest::Client::builder().timeout(STORAGE_TIMEOUT).build()?,
+            indexer_url: indexer_url.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+impl ContentStore for ZeroGStore {
+    async fn put(&self, data: Vec<u8>) -> Result<String, ChainError> {
+        let form = reqwest::multipart::Form::new().part("file", reqwest::multipart::Part::bytes(data));
+        let uploaded: UploadResponse = self
+            .http
+            .post(format!("{}/file/upload", self.indexer_url))
+            .multipart(form)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(format!("0g://{}", uploaded.root))
+    }
+
+    async fn get(&self, uri: &str) -> Result<Vec<u8>, ChainError> {
+        let root = uri
+            .strip_prefix("0g://")
+            .ok_or_else(|| ChainError::Storage(format!("Unsupported URI: {}", uri)))?;
+        let data = self
+            .http
+            .get(format!("{}/file", self.indexer_url))
+            .query(&[("root", root)])
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        Ok(data.to_vec())
+    }
+}
+
+/// In-memory store for tests
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub(crate) struct MemoryStore(std::sync::Mutex<std::collections::HashMap<String, Vec<u8>>>);
+
+#[cfg(test)]
+impl ContentStore for MemoryStore {
+    async fn put(&self, data: Vec<u8>) -> Result<String, ChainError> {
+        let uri = format!("mem://{}", hex::encode(super::keccak256(&data)));
+        self.0.lock().unwrap().insert(uri.clone(), data);
+        Ok(uri)
+    }
+
+    async fn get(&self, uri: &str) -> Result<Vec<u8>, ChainError> {
+        self.0.lock().unwrap().get(uri).cloned().ok_or_else(|| ChainError::Storage(format!("Not found: {}", uri)))
+    }
+}
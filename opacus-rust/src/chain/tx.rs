@@ -0,0 +1,185 @@
+//! EIP-1559 transactions
+
+use super::rlp::RlpList;
+use super::{keccak256, Address};
+
+/// EIP-2718 type of dynamic-fee transactions
+const EIP1559_TX_TYPE: u8 = 0x02;
+
+/// Transaction to submit; unset fields are filled in by `ChainClient`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransactionRequest {
+    /// Recipient (`None` deploys a contract)
+    pub to: Option<Address>,
+    /// Value in wei
+    pub value: u128,
+    /// Call data
+    pub data: Vec<u8>,
+    /// Gas limit (estimated when unset)
+    pub gas_limit: Option<u64>,
+    /// Fee cap per gas in wei
+    pub max_fee_per_gas: Option<u128>,
+    /// Priority fee per gas in wei
+    pub max_priority_fee_per_gas: Option<u128>,
+    /// Account nonce (taken from the nonce manager when unset)
+    pub nonce: Option<u64>,
+}
+
+impl TransactionRequest {
+    /// Plain value transfer
+    pub fn transfer(to: Address, value: u128) -> Self {
+        Self { to: Some(to), value, ..Default::default() }
+    }
+
+    /// Contract call
+    pub fn call(to: Address, data: Vec<u8>) -> Self {
+        Self { to: Some(to), data, ..Default::default() }
+    }
+}
+
+/// Fully specified EIP-1559 transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Eip1559Transaction {
+    /// Chain ID (replay protection)
+    pub chain_id: u64,
+    /// Sender account nonce
+    pub nonce: u64,
+    /// Priority fee per gas in wei
+    pub max_priority_fee_per_gas: u128,
+    /// Fee cap per gas in wei
+    pub max_fee_per_gas: u128,
+    /// Gas limit
+    pub gas_limit: u64,
+    /// Recipient (`None` deploys a contract)
+    pub to: Option<Address>,
+    /// Value in wei
+    pub value: u128,
+    /// Call data
+    pub data: Vec<u8>,
+}
+
+impl Eip1559Transaction {
+    fn fields(&self) -> RlpList {
+        let mut list = RlpList::new();
+        list.uint(self.chain_id.into())
+            .uint(self.nonce.into())
+            .uint(self.max_priority_fee_per_gas)
+            .uint(self.max_fee_per_gas)
+            .uint(self.gas_limit.into())
+            .bytes(self.to.as_ref().map_or(&[][..], |to| &to.0[..]))
+            .uint(self.value)
+            .bytes(&self.data)
+            // Empty access list
+            .list(&RlpList::new());
+        list
+    }
+
+    /// Hash signed by the sender
+    pub fn signing_hash(&self) -> [u8; 32] {
+        let mut payload = vec![EIP1559_TX_TYPE];
+        payload.extend_from_slice(&self.fields().finish());
+        keccak256(&payload)
+    }
+
+    /// Raw signed transaction for `eth_sendRawTransaction`
+    ///
+    /// # Arguments
+    /// * `signature` - Signature over `signing_hash` (r || s || recovery id)
+    pub fn encode_signed(&self, signature: &[u8; 65]) -> Vec<u8> {
+        let mut list = self.fields();
+        list.uint(signature[64].into())
+            .bytes(trim(&signature[..32]))
+            .bytes(trim(&signature[32..64]));
+        let mut out = vec![EIP1559_TX_TYPE];
+        out.extend_from_slice(&list.finish());
+        out
+    }
+}
+
+/// Strip leading zero bytes (RLP integers are minimal)
+fn trim(data: &[u8]) -> &[u8] {
+    let start = data.iter().position(|&b| b != 0).unwrap_or(data.len());
+    &data[start..]
+}
+
+/// Transaction hash
+pub type TxHash = [u8; 32];
+
+/// Outcome of a mined transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionReceipt {
+    /// Transaction hash
+    pub transaction_hash: TxHash,
+    /// Block number
+    pub block_number: u64,
+    /// Gas used
+    pub gas_used: u64,
+    /// Whether execution succeeded
+    pub success: bool,
+    /// Created contract, for deployments
+    pub contract_address: Option<Address>,
+    /// Events emitted by the transaction
+    pub logs: Vec<Log>,
+}
+
+/// Event emitted by a contract
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Log {
+    /// Emitting contract
+    pub address: Address,
+    /// Event signature hash followed by the indexed arguments
+    pub topics: Vec<[u8; 32]>,
+    /// ABI-encoded non-indexed arguments
+    pub data: Vec<u8>,
+    /// Block containing the event
+    pub block_number: u64,
+    /// Transaction that emitted the event
+    pub transaction_hash: TxHash,
+}
+
+/// Filter for `eth_getLogs`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogFilter {
+    /// Emitting contract
+    pub address: Option<Address>,
+    /// Topics by position; `None` matches any value
+    pub topics: Vec<Option<[u8; 32]>>,
+    /// First block searched
+    pub from_block: u64,
+    /// Last block searched (latest when unset)
+    pub to_block: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transaction_encoding() {
+        let tx = Eip1559Transaction {
+            chain_id: 1,
+            nonce: 0,
+            max_priority_fee_per_gas: 1,
+            max_fee_per_gas: 2,
+            gas_limit: 21_000,
+            to: Some("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".parse().unwrap()),
+            value: 0,
+            data: vec![],
+        };
+        let fields = "0180010282520894 5aaeb6053f3e94c9b9a09f33669435e7ef1beaed 8080c0".replace(' ', "");
+        let unsigned = hex::decode(format!("02df{}", fields)).unwrap();
+        assert_eq!(tx.signing_hash(), keccak256(&unsigned));
+
+        let mut signature = [0x11u8; 65];
+        signature[32..64].fill(0x22);
+        signature[64] = 1;
+        let expected = format!("02f862{}01a0{}a0{}", fields, "11".repeat(32), "22".repeat(32));
+        assert_eq!(hex::encode(tx.encode_signed(&signature)), expected);
+
+        // Leading zeros of r and s are dropped
+        signature[..31].fill(0);
+        let encoded = tx.encode_signed(&signature);
+        assert_eq!(encoded.len(), 3 + 31 + 1 + 1 + 33);
+        assert_eq!(encoded[3 + 31 + 1], 0x11);
+    }
+}
@@ -0,0 +1,145 @@
+//! secp256k1 signing key for chain transactions
+
+use k256::ecdsa::{RecoveryId, Signature, SigningKey, VerifyingKey};
+use super::{Address, ChainError, Eip1559Transaction};
+use crate::random::{random_array, OsRandom, Random};
+
+/// Account key for signing transactions and typed data
+///
+/// Separate from the agent's Ed25519 protocol key: EVM contracts can only
+/// verify secp256k1 signatures.
+#[derive(Clone)]
+pub struct ChainSigner {
+    key: SigningKey,
+    address: Address,
+}
+
+impl ChainSigner {
+    /// Create signer from a 32-byte private key
+    pub fn from_bytes(private_key: &[u8]) -> Result<Self, ChainError> {
+        let key = SigningKey::from_slice(private_key).map_err(|e| ChainError::Signer(format!("Invalid private key: {}", e)))?;
+        let address = public_address(key.verifying_key());
+        Ok(Self { key, address })
+    }
+
+    /// Create signer from a hex private key (`0x` prefix optional)
+    pub fn from_hex(private_key: &str) -> Result<Self, ChainError> {
+        let digits = private_key.trim().trim_start_matches("0x");
+        let bytes = hex::decode(digits).map_err(|e| ChainError::Signer(format!("Invalid private key hex: {}", e)))?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Generate a random signer
+    pub fn random() -> Self {
+        Self::random_with(&OsRandom)
+    }
+
+    /// Generate a signer from a custom source of randomness
+    pub fn random_with(random: &dyn Random) -> Self {
+        // Draw again in the negligible case of zero or a value above the curve order
+        loop {
+            if let Ok(signer) = Self::from_bytes(&random_array::<32>(random)) {
+                return signer;
+            }
+        }
+    }
+
+    /// Account address
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    /// Sign a 32-byte hash
+    ///
+    /// # Returns
+    /// `r || s || recovery id` (recovery id 0 or 1)
+    pub fn sign_hash(&self, hash: &[u8; 32]) -> Result<[u8; 65], ChainError> {
+        let (sig, recid) = self
+            .key
+            .sign_prehash_recoverable(hash)
+            .map_err(|e| ChainError::Signer(format!("Signing failed: {}", e)))?;
+        let mut out = [0u8; 65];
+        out[..64].copy_from_slice(&sig.to_bytes());
+        out[64] = recid.to_byte();
+        Ok(out)
+    }
+
+    /// Sign a transaction
+    ///
+    /// # Returns
+    /// Raw transaction bytes for `eth_sendRawTransaction`
+    pub fn sign_transaction(&self, tx: &Eip1559Transaction) -> Result<Vec<u8>, ChainError> {
+        let signature = self.sign_hash(&tx.signing_hash())?;
+        Ok(tx.encode_signed(&signature))
+    }
+}
+
+impl std::fmt::Debug for ChainSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ChainSigner").field("address", &self.address).finish_non_exhaustive()
+    }
+}
+
+/// Recover the address that signed a hash
+///
+/// # Arguments
+/// * `hash` - Signed hash
+/// * `signature` - `r || s || v`, with `v` either 0/1 or 27/28
+pub fn recover_address(hash: &[u8; 32], signature: &[u8; 65]) -> Result<Address, ChainError> {
+    let v = match signature[64] {
+        v @ (0 | 1) => v,
+        v @ (27 | 28) => v - 27,
+        v => return Err(ChainError::Signer(format!("Invalid recovery id: {}", v))),
+    };
+    let sig = Signature::from_slice(&signature[..64]).map_err(|e| ChainError::Signer(format!("Invalid signature: {}", e)))?;
+    let recid = RecoveryId::from_byte(v).expect("recovery id 0 or 1");
+    let key = VerifyingKey::recover_from_prehash(hash, &sig, recid)
+        .map_err(|e| ChainError::Signer(format!("Signature recovery failed: {}", e)))?;
+    Ok(public_address(&key))
+}
+
+fn public_address(key: &VerifyingKey) -> Address {
+    let point = key.to_encoded_point(false);
+    let public: &[u8; 64] = point.as_bytes()[1..].try_into().expect("uncompressed point");
+    Address::from_public_key(public)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::keccak256;
+
+    #[test]
+    fn test_signer_address() {
+        let signer = ChainSigner::from_hex("0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318").unwrap();
+        assert_eq!(signer.address().to_string(), "0x2c7536E3605D9C16a7a3D7b1898e529396a65c23");
+        assert!(ChainSigner::from_hex("0x00").is_err());
+
+        let hash = keccak256(b"opacus");
+        let signature = signer.sign_hash(&hash).unwrap();
+        assert_eq!(recover_address(&hash, &signature).unwrap(), signer.address());
+        let mut eth_v = signature;
+        eth_v[64] += 27;
+        assert_eq!(recover_address(&hash, &eth_v).unwrap(), signer.address());
+        assert_ne!(recover_address(&keccak256(b"other"), &signature).ok(), Some(signer.address()));
+    }
+
+    #[test]
+    fn test_sign_transaction() {
+        let signer = ChainSigner::random();
+        let tx = Eip1559Transaction {
+            chain_id: 16602,
+            nonce: 7,
+            max_priority_fee_per_gas: 2_000_000_000,
+            max_fee_per_gas: 40_000_000_000,
+            gas_limit: 21_000,
+            to: Some("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".parse().unwrap()),
+            value: 10u128.pow(18),
+            data: vec![],
+        };
+        let signature = signer.sign_hash(&tx.signing_hash()).unwrap();
+        assert_eq!(recover_address(&tx.signing_hash(), &signature).unwrap(), signer.address());
+        // k256 signing is deterministic (RFC 6979)
+        assert_eq!(signer.sign_transaction(&tx).unwrap(), tx.encode_signed(&signature));
+    }
+}
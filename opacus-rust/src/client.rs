@@ -1,32 +1,297 @@
 //! Opacus client implementation
 
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use serde::Serialize;
 use tokio::sync::RwLock;
-use tracing::{info, debug};
+use tracing::{field, info, debug, debug_span, warn, Instrument};
 use crate::types::*;
-use crate::crypto::{KeyManager, SecurityManager};
-use crate::transport::QUICTransport;
+use crate::batch::{FrameBatch, MAX_BATCH_FRAMES};
+use crate::capabilities::{Capabilities, CapabilityQuery};
+use crate::profile::{Profile, ProfileQuery, SignedProfile};
+use crate::dht::{DhtAgentRecord, DhtKey, DhtMessage, DhtRpc};
+use crate::capture::{CaptureDirection, FrameCapture};
+use crate::error::{ErrorCode, ErrorPayload, OpacusError};
+use crate::clock::{Clock, SystemClock};
+use crate::random::{OsRandom, Random};
+use crate::compression::{Compression, DEFAULT_COMPRESSION_THRESHOLD};
+use crate::content::ContentType;
+use crate::crypto::{KeyManager, SecurityManager, PreKeyBundle, PreKeyStore, X3DH, X3DHHeader, Fingerprint, PeerTrustStore, RekeyPolicy};
+use crate::proto::{WireFormat, FRAME_VERSION};
+use crate::metering::{Usage, UsageMeter, UsageStatement};
+use crate::latency::{LatencyTracker, PingPayload};
+use crate::lifecycle::{FrameStage, LifecycleEvent, LifecycleLog};
+use crate::reputation::{Reputation, ReputationAttestation, ReputationBook, ReputationClaim, REPUTATION_EXTENSION};
+use crate::token::{Caveat, CapabilityPresentation, CapabilityToken, CAPABILITY_EXTENSION};
+use crate::credential::{
+    PresentationRequest, VerifiableCredential, VerifiablePresentation, CREDENTIAL_EXTENSION, PRESENTATION_EXTENSION,
+    PRESENTATION_REQUEST_EXTENSION, did_names_key,
+};
+#[cfg(feature = "chain")]
+use crate::offload::{PayloadRef, OFFLOADED_EXTENSION};
+use crate::qos::{CongestionError, Priority, SendQueue, SendStatus};
+use crate::rpc::REPLY_TO_EXTENSION;
+use crate::subscription::SubscribeRequest;
+use crate::history::{ChannelHistory, HistoryEntry, HistoryPage, HistoryQuery};
+use crate::crdt::{CrdtMessage, CrdtUpdate, SharedDocument, DOCUMENT_CHANNEL_PREFIX, SYNC_CHUNK_UPDATES};
+use crate::topic::TopicFilter;
+use crate::onion::OnionHop;
+use crate::padding::PaddingPolicy;
+use crate::task::{TaskBook, TaskMessage, TaskOptions, TaskOutcome, TaskRecord, TaskRequest};
+#[cfg(feature = "chain")]
+use crate::subscription::{HoldingProof, StreamingAuthorization};
+use crate::trace::{self, TraceContext, TRACE_EXTENSION};
+use crate::transport::{MemoryRelay, QUICTransport, Transport, LOCAL_RELAY_SCHEME};
+#[cfg(feature = "chain")]
+use crate::chain::{
+    anchor_pending, escrow_domain, spawn_event_bridge, EventBridge, escrow_lock_id, is_agent_name, notary_domain, payment_domain, spawn_anchoring, Address, AgentKeys, AgentRegistry, BalanceUpdate,
+    ChainClient, ChainEvent, ContentStore, DeliveryReceipt, Eip712Domain, EscrowLock, EscrowRelease, IpfsStore, KeyResolver, MessageAnchor, MessageProof,
+    MisbehaviorEvidence, NameRecord, NameService, NotarizedReceipt, PaymentChannel, PaymentReceipt, RelayRecord, RelayRegistry, ReputationRegistry, SignedDeliveryReceipt, SignedPayment,
+    SignedUsageStatement, TransactionReceipt, ZeroGStore, BALANCE_EXTENSION, ESCROW_RELEASE_EXTENSION, KEY_CACHE_TTL,
+    NOTARIZED_RECEIPT_EXTENSION, RECEIPT_EXTENSION,
+};
+
+/// Number of recent message IDs remembered for deduplication
+const DEDUP_CAPACITY: usize = 4096;
+
+/// How long `ping` waits for a reply
+pub const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Chain events buffered between flushes before the event bridge waits
+#[cfg(feature = "chain")]
+const CHAIN_EVENT_BUFFER: usize = 1024;
+
+/// What became of a frame handed to `dispatch_tracked`
+enum Delivery {
+    Status(SendStatus),
+    Dropped,
+}
 
 /// Main Opacus client
 pub struct OpacusClient {
     config: OpacusConfig,
     identity: Option<AgentIdentity>,
-    transport: Option<QUICTransport>,
+    transport: Option<Box<dyn Transport>>,
     security: Arc<RwLock<SecurityManager>>,
+    clock: Arc<dyn Clock>,
+    random: Arc<dyn Random>,
     relay_x_pub: Option<[u8; 32]>,
+    relay_onion_key: Option<[u8; 32]>,
+    /// ID of the `Connect` frame awaiting the relay's `Ack`
+    pending_connect: Option<Ulid>,
+    compression: Option<Compression>,
+    wire_format: WireFormat,
+    auth_token: Option<String>,
+    trace_propagation: bool,
+    capture: Option<Arc<FrameCapture>>,
+    lifecycle: Option<Arc<LifecycleLog>>,
+    prekeys: Option<PreKeyStore>,
+    trust: PeerTrustStore,
+    outbox: SendQueue,
+    inbox: VecDeque<OpacusFrame>,
+    /// Frames received while waiting for a ping reply, returned by `recv` first
+    held: VecDeque<OpacusFrame>,
+    seen_ids: HashSet<Ulid>,
+    seen_order: VecDeque<Ulid>,
     seq: u64,
+    meter: UsageMeter,
+    latency: LatencyTracker,
+    /// Channels offered to subscribers, by channel ID
+    channels: HashMap<String, DataChannel>,
+    /// Accepted subscribers, by channel ID
+    subscribers: HashMap<String, HashSet<String>>,
+    /// Accepted wildcard subscribers, by filter
+    topic_subscribers: HashMap<TopicFilter, HashSet<String>>,
+    /// Retained history of offered channels
+    histories: HashMap<String, ChannelHistory>,
+    /// Open shared documents, by document ID
+    documents: HashMap<String, SharedDocument>,
+    /// Agents each open document is replicated with
+    document_peers: HashMap<String, Vec<String>>,
+    /// Tasks delegated or assigned
+    tasks: TaskBook,
+    /// Whether frames to other agents are sealed
+    sealed_sender: bool,
+    /// X25519 keys of other agents, for sealing frames to them
+    sealing_keys: HashMap<String, [u8; 32]>,
+    /// Bucket sizes outgoing frames are padded to
+    padding: Option<PaddingPolicy>,
+    /// Mean idle time before a cover frame is sent
+    cover_interval: Option<Duration>,
+    /// When the next cover frame is due (milliseconds)
+    next_cover: Option<u64>,
+    /// Verified attestations about other agents
+    reputation: ReputationBook,
+    /// Verifiable Credentials issued to this agent
+    credentials: Vec<VerifiableCredential>,
+    /// Challenges of presentations requested from other agents
+    presentation_requests: HashMap<String, String>,
+    /// Credentials other agents presented, by agent
+    peer_credentials: HashMap<String, Vec<VerifiableCredential>>,
+    /// Advertised application services and their schema versions
+    services: BTreeMap<String, String>,
+    /// Capabilities received from or looked up for other agents
+    peer_capabilities: HashMap<String, Capabilities>,
+    /// Profile last published by this agent
+    profile: Option<SignedProfile>,
+    /// Newest verified profiles of other agents
+    peer_profiles: HashMap<String, SignedProfile>,
+    /// DHT record last published by this agent
+    record: Option<DhtAgentRecord>,
+    #[cfg(feature = "chain")]
+    chain: Option<Arc<ChainClient>>,
+    #[cfg(feature = "chain")]
+    payments_contract: Option<Address>,
+    #[cfg(feature = "chain")]
+    channel_contract: Option<Address>,
+    /// Channels this agent pays through, by recipient agent ID
+    #[cfg(feature = "chain")]
+    outgoing_channels: HashMap<String, PaymentChannel>,
+    /// Channels paying this agent, by channel ID
+    #[cfg(feature = "chain")]
+    incoming_channels: HashMap<[u8; 32], PaymentChannel>,
+    /// Amount paid for usage statements, by data channel and provider
+    #[cfg(feature = "chain")]
+    usage_paid: HashMap<(String, String), u128>,
+    /// Authorizations of paying subscribers, by data channel and agent ID
+    #[cfg(feature = "chain")]
+    paid_subscriptions: HashMap<(String, String), StreamingAuthorization>,
+    #[cfg(feature = "chain")]
+    escrow_contract: Option<Address>,
+    /// Anchor contract and relay account of notarized receipts
+    #[cfg(feature = "chain")]
+    receipt_notary: Option<(Address, Address)>,
+    #[cfg(feature = "chain")]
+    relay_registry: Option<RelayRegistry>,
+    #[cfg(feature = "chain")]
+    reputation_registry: Option<ReputationRegistry>,
+    /// Hashes of exchanged messages, once anchoring is enabled
+    #[cfg(feature = "chain")]
+    anchor: Option<Arc<std::sync::Mutex<MessageAnchor>>>,
+    #[cfg(feature = "chain")]
+    anchor_contract: Option<Address>,
+    #[cfg(feature = "chain")]
+    anchor_task: Option<tokio::task::JoinHandle<()>>,
+    #[cfg(feature = "chain")]
+    event_task: Option<tokio::task::JoinHandle<()>>,
+    /// Events polled by the event bridge, by data channel
+    #[cfg(feature = "chain")]
+    chain_events: Option<tokio::sync::mpsc::Receiver<(String, ChainEvent)>>,
+    /// Chain-rooted peer keys, once an agent registry is set
+    #[cfg(feature = "chain")]
+    key_resolver: Option<KeyResolver<IpfsStore>>,
+    #[cfg(feature = "chain")]
+    names: Option<NameService>,
+    /// Store for payloads above the threshold
+    #[cfg(feature = "chain")]
+    offload: Option<(ZeroGStore, usize)>,
 }
 
 impl OpacusClient {
     /// Create new client with configuration
     pub fn new(config: OpacusConfig) -> Self {
+        Self::with_clock(config, Arc::new(SystemClock))
+    }
+    
+    /// Create new client with a custom time source
+    /// 
+    /// The clock stamps outgoing frames and drives nonce freshness checks.
+    pub fn with_clock(config: OpacusConfig, clock: Arc<dyn Clock>) -> Self {
+        Self::with_sources(config, clock, Arc::new(OsRandom))
+    }
+    
+    /// Create new client with custom time and randomness sources
+    /// 
+    /// `random` generates the identity in `init`, nonces, message IDs and
+    /// ping nonces; with a `SeededRandom` and a `ManualClock`, a client's
+    /// frames are reproducible.
+    pub fn with_sources(config: OpacusConfig, clock: Arc<dyn Clock>, random: Arc<dyn Random>) -> Self {
         Self {
             config,
             identity: None,
             transport: None,
-            security: Arc::new(RwLock::new(SecurityManager::new())),
+            security: Arc::new(RwLock::new(SecurityManager::with_sources(clock.clone(), random.clone()))),
+            clock,
+            random,
             relay_x_pub: None,
+            relay_onion_key: None,
+            pending_connect: None,
+            compression: None,
+            wire_format: WireFormat::default(),
+            auth_token: None,
+            trace_propagation: false,
+            capture: None,
+            lifecycle: None,
+            prekeys: None,
+            trust: PeerTrustStore::new(),
+            outbox: SendQueue::default(),
+            inbox: VecDeque::new(),
+            held: VecDeque::new(),
+            seen_ids: HashSet::new(),
+            seen_order: VecDeque::new(),
             seq: 0,
+            meter: UsageMeter::new(),
+            latency: LatencyTracker::new(),
+            channels: HashMap::new(),
+            subscribers: HashMap::new(),
+            topic_subscribers: HashMap::new(),
+            histories: HashMap::new(),
+            documents: HashMap::new(),
+            document_peers: HashMap::new(),
+            tasks: TaskBook::new(),
+            sealed_sender: false,
+            sealing_keys: HashMap::new(),
+            padding: None,
+            cover_interval: None,
+            next_cover: None,
+            reputation: ReputationBook::new(),
+            credentials: Vec::new(),
+            presentation_requests: HashMap::new(),
+            peer_credentials: HashMap::new(),
+            services: BTreeMap::new(),
+            peer_capabilities: HashMap::new(),
+            profile: None,
+            record: None,
+            peer_profiles: HashMap::new(),
+            #[cfg(feature = "chain")]
+            chain: None,
+            #[cfg(feature = "chain")]
+            payments_contract: None,
+            #[cfg(feature = "chain")]
+            channel_contract: None,
+            #[cfg(feature = "chain")]
+            outgoing_channels: HashMap::new(),
+            #[cfg(feature = "chain")]
+            incoming_channels: HashMap::new(),
+            #[cfg(feature = "chain")]
+            usage_paid: HashMap::new(),
+            #[cfg(feature = "chain")]
+            paid_subscriptions: HashMap::new(),
+            #[cfg(feature = "chain")]
+            escrow_contract: None,
+            #[cfg(feature = "chain")]
+            receipt_notary: None,
+            #[cfg(feature = "chain")]
+            relay_registry: None,
+            #[cfg(feature = "chain")]
+            reputation_registry: None,
+            #[cfg(feature = "chain")]
+            anchor: None,
+            #[cfg(feature = "chain")]
+            anchor_contract: None,
+            #[cfg(feature = "chain")]
+            anchor_task: None,
+            #[cfg(feature = "chain")]
+            event_task: None,
+            #[cfg(feature = "chain")]
+            chain_events: None,
+            #[cfg(feature = "chain")]
+            key_resolver: None,
+            #[cfg(feature = "chain")]
+            names: None,
+            #[cfg(feature = "chain")]
+            offload: None,
         }
     }
     
@@ -36,7 +301,7 @@ impl OpacusClient {
     /// Reference to generated `AgentIdentity`
     pub async fn init(&mut self) -> &AgentIdentity {
         let chain_id = self.config.network.chain_id();
-        self.identity = Some(KeyManager::generate_identity(chain_id));
+        self.identity = Some(KeyManager::generate_identity_with(chain_id, &*self.random));
         let identity = self.identity.as_ref().unwrap();
         
         info!("Agent initialized: {}", identity.id);
@@ -51,177 +316,3124 @@ impl OpacusClient {
         ed_priv: [u8; 32],
         x_priv: [u8; 32],
     ) -> anyhow::Result<&AgentIdentity> {
-        use ed25519_dalek::SigningKey;
-        use x25519_dalek::{StaticSecret, PublicKey as X25519Public};
-        use sha2::{Sha256, Digest};
-        
-        let signing_key = SigningKey::from_bytes(&ed_priv);
-        let ed_pub = *signing_key.verifying_key().as_bytes();
-        
-        let x_secret = StaticSecret::from(x_priv);
-        let x_pub = X25519Public::from(&x_secret).to_bytes();
-        
-        let mut hasher = Sha256::new();
-        hasher.update(&ed_pub);
-        let hash = hasher.finalize();
-        let id = hex::encode(&hash[..20]);
-        let address = format!("0x{}", hex::encode(&hash[..20]));
-        
-        self.identity = Some(AgentIdentity {
-            id: id.clone(),
-            ed_pub,
-            ed_priv,
-            x_pub,
-            x_priv,
-            address: address.clone(),
-            chain_id: self.config.network.chain_id(),
-        });
+        let identity = KeyManager::identity_from_keys(ed_priv, x_priv, self.config.network.chain_id());
         
-        info!("Agent restored: {}", id);
-        info!("Address: {}", address);
+        info!("Agent restored: {}", identity.id);
+        info!("Address: {}", identity.address);
         
-        Ok(self.identity.as_ref().unwrap())
+        Ok(self.identity.insert(identity))
     }
     
-    /// Connect to relay server
-    pub async fn connect(&mut self) -> anyhow::Result<()> {
-        let identity = self.identity.as_ref().expect("Not initialized. Call init() first");
-        
-        // Parse relay URL
-        let url = self.config.relay_url
-            .replace("quic://", "")
-            .replace("https://", "")
-            .replace("http://", "");
-        
-        let mut transport = QUICTransport::new("0.0.0.0:0", &url).await?;
-        transport.connect().await?;
-        
-        info!("Connected to relay: {}", self.config.relay_url);
-        
-        // Send connect frame
-        let connect_payload = serde_json::json!({
-            "edPub": KeyManager::to_hex(&identity.ed_pub),
-            "xPub": KeyManager::to_hex(&identity.x_pub)
-        });
-        
-        let frame = OpacusFrame {
-            version: 1,
-            frame_type: FrameType::Connect,
-            from: identity.id.clone(),
-            to: "relay".to_string(),
-            seq: self.seq,
-            ts: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_millis() as u64,
-            nonce: SecurityManager::generate_nonce(),
-            payload: serde_json::to_vec(&connect_payload)?,
-            hmac: None,
-            sig: None,
-        };
-        self.seq += 1;
-        
-        transport.send(&frame).await?;
-        debug!("Sent connect frame");
-        
-        self.transport = Some(transport);
-        
-        Ok(())
+    /// Initialize from a 32-byte seed
+    /// 
+    /// The same seed always restores the same identity (see
+    /// [`KeyManager::identity_from_seed`]).
+    pub async fn init_from_seed(&mut self, seed: &[u8; 32]) -> &AgentIdentity {
+        let identity = KeyManager::identity_from_seed(seed, self.config.network.chain_id());
+        info!("Agent restored from seed: {}", identity.id);
+        info!("Address: {}", identity.address);
+        self.identity.insert(identity)
     }
     
-    /// Send message to another agent
+    /// Chain client for the configured network (`chain` feature)
+    /// 
+    /// Connects to `chain_rpc` and signs with `private_key` when set. Created
+    /// on first use and shared afterwards.
+    #[cfg(feature = "chain")]
+    pub fn chain(&mut self) -> anyhow::Result<Arc<ChainClient>> {
+        if let Some(chain) = &self.chain {
+            return Ok(chain.clone());
+        }
+        let chain = Arc::new(ChainClient::from_config(&self.config)?);
+        Ok(self.chain.insert(chain).clone())
+    }
+    
+    /// Set the payments contract that payment intents are signed for and settled with
+    #[cfg(feature = "chain")]
+    pub fn set_payments_contract(&mut self, contract: Address) {
+        self.payments_contract = Some(contract);
+    }
+    
+    #[cfg(feature = "chain")]
+    fn payments_contract(&self) -> anyhow::Result<Address> {
+        self.payments_contract.ok_or_else(|| anyhow::anyhow!("No payments contract set"))
+    }
+    
+    /// Pay another agent
+    /// 
+    /// Signs a payment intent with the chain key and sends it in a `Payment`
+    /// frame; the payee validates it with `on_payment` and settles it.
     /// 
     /// # Arguments
     /// * `to` - Recipient agent ID
-    /// * `payload` - Message payload bytes
-    pub async fn send_message(&mut self, to: &str, payload: Vec<u8>) -> anyhow::Result<()> {
+    /// * `payee` - Recipient's account
+    /// * `token` - ERC-20 token, or `Address::default()` for the native token
+    /// * `amount` - Amount in the token's smallest unit
+    /// * `valid_for` - How long the payee may settle the payment
+    /// 
+    /// # Returns
+    /// The signed payment that was sent
+    #[cfg(feature = "chain")]
+    pub async fn send_payment(
+        &mut self,
+        to: &str,
+        payee: Address,
+        token: Address,
+        amount: u128,
+        valid_for: std::time::Duration,
+    ) -> anyhow::Result<SignedPayment> {
+        let contract = self.payments_contract()?;
+        let deadline = self.clock.now_ms() / 1000 + valid_for.as_secs();
+        let payment = self.chain()?.sign_payment(contract, payee, token, amount, deadline)?;
+        
         let identity = self.identity.as_ref().expect("Not initialized");
-        let transport = self.transport.as_ref().expect("Not connected");
         let relay_x_pub = self.relay_x_pub.unwrap_or([0u8; 32]);
-        
-        let frame = self.security.write().await.create_auth_frame(
+        let frame = self.security.write().await.create_auth_frame_with(
             identity,
             &relay_x_pub,
-            FrameType::Msg,
+            FrameType::Payment,
             to,
-            payload,
+            serde_json::to_vec(&payment)?,
+            FrameOptions { content_type: ContentType::Json, ..Default::default() },
         );
         
-        transport.send(&frame).await?;
-        debug!("Sent message to {}", to);
+        debug!("Sending payment 0x{} to {}", hex::encode(payment.intent.payment_id), to);
+        self.dispatch(frame).await?;
+        self.rekey_if_due(to).await?;
         
-        Ok(())
+        Ok(payment)
     }
     
-    /// Send stream data
-    pub async fn send_stream(&mut self, channel_id: &str, data: Vec<u8>) -> anyhow::Result<()> {
-        let identity = self.identity.as_ref().expect("Not initialized");
-        let transport = self.transport.as_ref().expect("Not connected");
-        let relay_x_pub = self.relay_x_pub.unwrap_or([0u8; 32]);
-        
-        let payload = serde_json::json!({
-            "channelId": channel_id,
-            "data": data
-        });
+    /// Validate a received `Payment` frame
+    /// 
+    /// Checks the payer's signature for the configured payments contract, the
+    /// deadline, and that this account (if a chain key is set) is the payee.
+    #[cfg(feature = "chain")]
+    pub fn on_payment(&mut self, frame: &OpacusFrame) -> anyhow::Result<SignedPayment> {
+        if frame.frame_type != FrameType::Payment {
+            anyhow::bail!("Expected payment frame, got {:?}", frame.frame_type);
+        }
+        let payment: SignedPayment = serde_json::from_slice(&frame.payload)?;
+        let chain = self.chain()?;
+        payment.verify(&payment_domain(chain.chain_id(), self.payments_contract()?), self.clock.now_ms() / 1000)?;
+        if let Some(address) = chain.address() {
+            if payment.intent.payee != address {
+                anyhow::bail!("Payment to {} is not for this account", payment.intent.payee);
+            }
+        }
+        debug!("Accepted payment 0x{} from {}", hex::encode(payment.intent.payment_id), frame.from);
+        Ok(payment)
+    }
+    
+    /// Settle a payment with the payments contract and wait until it is mined
+    #[cfg(feature = "chain")]
+    pub async fn settle_payment(&mut self, payment: &SignedPayment) -> anyhow::Result<PaymentReceipt> {
+        let contract = self.payments_contract()?;
+        let receipt = self.chain()?.settle_payment(contract, payment).await?;
+        info!("Settled payment 0x{} in block {}", hex::encode(receipt.payment_id), receipt.block_number);
+        Ok(receipt)
+    }
+    
+    /// Set the contract payment channels are opened with
+    #[cfg(feature = "chain")]
+    pub fn set_channel_contract(&mut self, contract: Address) {
+        self.channel_contract = Some(contract);
+    }
+    
+    /// Open a payment channel to another agent
+    /// 
+    /// Locks `deposit` in the channel contract; `send_paid_message` then pays
+    /// from it without a transaction per message.
+    /// 
+    /// # Arguments
+    /// * `to` - Recipient agent ID
+    /// * `payee` - Recipient's account
+    /// * `deposit` - Deposit in wei
+    /// * `valid_for` - Time before the deposit can be reclaimed; the payee must close earlier
+    #[cfg(feature = "chain")]
+    pub async fn open_payment_channel(
+        &mut self,
+        to: &str,
+        payee: Address,
+        deposit: u128,
+        valid_for: std::time::Duration,
+    ) -> anyhow::Result<PaymentChannel> {
+        let contract = self.channel_contract.ok_or_else(|| anyhow::anyhow!("No channel contract set"))?;
+        let expiry = self.clock.now_ms() / 1000 + valid_for.as_secs();
+        let channel = self.chain()?.open_payment_channel(contract, payee, deposit, expiry).await?;
+        info!("Opened payment channel 0x{} to {} with {} wei", hex::encode(channel.id), to, deposit);
+        self.outgoing_channels.insert(to.to_string(), channel.clone());
+        Ok(channel)
+    }
+    
+    /// Send a message and pay for it through the channel to `to`
+    /// 
+    /// The balance update travels in the frame's `balance` extension. Price
+    /// messages with [`DataChannel::price`].
+    /// 
+    /// # Arguments
+    /// * `to` - Recipient agent ID
+    /// * `payload` - Message payload bytes
+    /// * `amount` - Price of this message in wei
+    #[cfg(feature = "chain")]
+    pub async fn send_paid_message(&mut self, to: &str, payload: Vec<u8>, amount: u128) -> anyhow::Result<BalanceUpdate> {
+        let chain = self.chain()?;
+        let channel = self.outgoing_channels
+            .get_mut(to)
+            .ok_or_else(|| anyhow::anyhow!("No payment channel to {}", to))?;
+        let update = channel.pay(amount, chain.signer()?)?;
         
-        let frame = self.security.write().await.create_auth_frame(
-            identity,
-            &relay_x_pub,
-            FrameType::Stream,
-            "broadcast",
-            serde_json::to_vec(&payload)?,
-        );
+        let mut frame = self.message_frame(to, payload, true, FrameOptions::default()).await;
+        frame.extensions.insert(BALANCE_EXTENSION.to_string(), ciborium::Value::serialized(&update)?);
+        debug!("Sending paid message {:?} to {} ({} wei)", frame.id, to, amount);
+        self.dispatch(frame).await?;
+        self.rekey_if_due(to).await?;
         
-        transport.send(&frame).await?;
-        debug!("Sent stream to channel {}", channel_id);
+        Ok(update)
+    }
+    
+    /// Accept the channel payment attached to a received frame
+    /// 
+    /// Channels seen for the first time are read from the channel contract
+    /// and must pay this account.
+    /// 
+    /// # Returns
+    /// Amount newly paid, or `None` if the frame carries no payment
+    #[cfg(feature = "chain")]
+    pub async fn on_channel_payment(&mut self, frame: &OpacusFrame) -> anyhow::Result<Option<u128>> {
+        let Some(value) = frame.extensions.get(BALANCE_EXTENSION) else {
+            return Ok(None);
+        };
+        let update: BalanceUpdate = value.deserialized()?;
+        let chain = self.chain()?;
+        if !self.incoming_channels.contains_key(&update.channel_id) {
+            let contract = self.channel_contract.ok_or_else(|| anyhow::anyhow!("No channel contract set"))?;
+            let channel = chain
+                .payment_channel(contract, update.channel_id)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Unknown payment channel 0x{}", hex::encode(update.channel_id)))?;
+            if Some(channel.payee) != chain.address() {
+                anyhow::bail!("Payment channel 0x{} pays {}", hex::encode(channel.id), channel.payee);
+            }
+            self.incoming_channels.insert(channel.id, channel);
+        }
+        let channel = self.incoming_channels.get_mut(&update.channel_id).expect("channel inserted above");
+        let amount = channel.accept(update, self.clock.now_ms() / 1000)?;
+        debug!("Received {} wei from {} (channel total {})", amount, frame.from, channel.paid);
+        Ok(Some(amount))
+    }
+    
+    /// Close an incoming payment channel, claiming the latest balance
+    #[cfg(feature = "chain")]
+    pub async fn close_payment_channel(&mut self, channel_id: &[u8; 32]) -> anyhow::Result<TransactionReceipt> {
+        let chain = self.chain()?;
+        let channel = self.incoming_channels
+            .get(channel_id)
+            .ok_or_else(|| anyhow::anyhow!("Unknown payment channel 0x{}", hex::encode(channel_id)))?;
+        let receipt = chain.close_payment_channel(channel).await?;
+        info!("Closed payment channel 0x{} for {} wei", hex::encode(channel_id), channel.paid);
+        self.incoming_channels.remove(channel_id);
+        Ok(receipt)
+    }
+    
+    /// Pay what a provider's usage statement says is due, through the payment channel to it
+    /// 
+    /// Pays at most what this client metered itself for the provider; the
+    /// statement goes back to the provider as the paid message.
+    /// 
+    /// # Returns
+    /// Amount paid (0 if nothing was due)
+    #[cfg(feature = "chain")]
+    pub async fn pay_usage(&mut self, statement: &UsageStatement) -> anyhow::Result<u128> {
+        statement.verify().map_err(|e| anyhow::anyhow!(e))?;
+        let identity = self.identity.as_ref().expect("Not initialized");
+        if statement.consumer != identity.id {
+            anyhow::bail!("Usage statement is for {}", statement.consumer);
+        }
+        let key = (statement.channel_id.clone(), statement.provider.clone());
+        let metered = self.meter.peer_usage(&statement.channel_id, &statement.provider).cost;
+        let paid = self.usage_paid.get(&key).copied().unwrap_or(0);
+        let due = statement.amount_due(paid).min(metered.saturating_sub(paid));
+        if due == 0 {
+            return Ok(0);
+        }
+        self.send_paid_message(&statement.provider, serde_json::to_vec(statement)?, due).await?;
+        self.usage_paid.insert(key, paid + due);
+        Ok(due)
+    }
+    
+    /// Set the `MsgEscrow` contract escrowed messages are paid through
+    /// 
+    /// Defaults to the one in the network's profile.
+    #[cfg(feature = "chain")]
+    pub fn set_escrow_contract(&mut self, contract: Address) {
+        self.escrow_contract = Some(contract);
+    }
+    
+    #[cfg(feature = "chain")]
+    fn escrow_contract(&self) -> anyhow::Result<Address> {
+        if let Some(contract) = self.escrow_contract {
+            return Ok(contract);
+        }
+        match &self.config.network.profile().contracts.msg_escrow {
+            Some(contract) => contract.parse().map_err(anyhow::Error::msg),
+            None => anyhow::bail!("No escrow contract set"),
+        }
+    }
+    
+    /// Send a request with its payment locked in escrow
+    /// 
+    /// Locks `amount` of the escrow's payment token for the responder under
+    /// the request's lock ID, then sends the request. Once the response
+    /// arrives, `confirm_delivery` lets the responder be paid; without it the
+    /// lock can be cancelled after it expires.
+    /// 
+    /// # Arguments
+    /// * `to` - Responder agent ID
+    /// * `payee` - Responder's account
+    /// * `payload` - Request payload bytes
+    /// * `amount` - Payment in the token's smallest unit
+    #[cfg(feature = "chain")]
+    pub async fn send_escrowed_message(
+        &mut self,
+        to: &str,
+        payee: Address,
+        payload: Vec<u8>,
+        amount: u128,
+    ) -> anyhow::Result<EscrowLock> {
+        let contract = self.escrow_contract()?;
+        let to = &self.recipient(to).await?;
+        let frame = self.message_frame(to, payload, true, FrameOptions::default()).await;
+        let lock = self.chain()?.lock_escrow(contract, &frame, payee, amount).await?;
+        info!("Locked {} for message {:?} to {} in escrow 0x{}", amount, frame.id, to, hex::encode(lock.id));
+        self.dispatch(frame).await?;
+        self.rekey_if_due(to).await?;
         
-        Ok(())
+        Ok(lock)
     }
     
-    /// Receive next frame (blocking)
-    pub async fn recv(&mut self) -> Option<OpacusFrame> {
-        let frame = self.transport.as_mut()?.recv().await?;
+    /// Escrow paying this account for a received request
+    /// 
+    /// # Returns
+    /// `None` if the request is not escrowed; `Err` if its lock is settled,
+    /// pays someone else or was made for a different request
+    #[cfg(feature = "chain")]
+    pub async fn on_escrowed_request(&mut self, frame: &OpacusFrame) -> anyhow::Result<Option<EscrowLock>> {
+        let Some(id) = frame.id else {
+            return Ok(None);
+        };
+        let chain = self.chain()?;
+        let Some(lock) = chain.escrow_lock(self.escrow_contract()?, escrow_lock_id(&id)).await? else {
+            return Ok(None);
+        };
+        lock.check_request(frame, chain.signer()?.address())?;
+        debug!("Request {:?} from {} is escrowed with {}", frame.id, frame.from, lock.amount);
+        Ok(Some(lock))
+    }
+    
+    /// Confirm that a response to an escrowed request was delivered
+    /// 
+    /// Signs a release of the lock over a receipt for `response` and sends it
+    /// to the responder in the `escrowRelease` extension.
+    #[cfg(feature = "chain")]
+    pub async fn confirm_delivery(&mut self, lock_id: [u8; 32], response: &OpacusFrame) -> anyhow::Result<EscrowRelease> {
+        let chain = self.chain()?;
+        let domain = escrow_domain(chain.chain_id(), self.escrow_contract()?);
+        let receipt = DeliveryReceipt::for_frame(response, self.clock.now_ms())?;
+        let release = EscrowRelease::sign(lock_id, receipt, &domain, chain.signer()?)?;
         
-        // Handle ACK to get relay public key
-        if frame.frame_type == FrameType::Ack && frame.from != self.identity.as_ref()?.id {
-            if let Ok(payload) = serde_json::from_slice::<serde_json::Value>(&frame.payload) {
-                if let Some(relay_x_pub_hex) = payload["relayXPub"].as_str() {
-                    if let Ok(bytes) = KeyManager::from_hex(relay_x_pub_hex) {
-                        if let Ok(arr) = bytes.try_into() {
-                            self.relay_x_pub = Some(arr);
-                            debug!("Stored relay public key");
-                        }
-                    }
-                }
+        let mut frame = self.message_frame(&response.from, Vec::new(), false, FrameOptions::default()).await;
+        frame.extensions.insert(ESCROW_RELEASE_EXTENSION.to_string(), ciborium::Value::serialized(&release)?);
+        debug!("Releasing escrow 0x{} to {}", hex::encode(lock_id), response.from);
+        self.dispatch(frame).await?;
+        
+        Ok(release)
+    }
+    
+    /// Accept the escrow release attached to a received frame
+    /// 
+    /// Checks that the lock pays this account and that its payer signed the
+    /// release. Submit it with `release_escrow`, through an authorized relayer.
+    /// 
+    /// # Returns
+    /// The release, or `None` if the frame carries none
+    #[cfg(feature = "chain")]
+    pub async fn on_escrow_release(&mut self, frame: &OpacusFrame) -> anyhow::Result<Option<EscrowRelease>> {
+        let Some(value) = frame.extensions.get(ESCROW_RELEASE_EXTENSION) else {
+            return Ok(None);
+        };
+        let release: EscrowRelease = value.deserialized()?;
+        let chain = self.chain()?;
+        let contract = self.escrow_contract()?;
+        let lock = chain
+            .escrow_lock(contract, release.lock_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Unknown escrow lock 0x{}", hex::encode(release.lock_id)))?;
+        if Some(lock.payee) != chain.address() {
+            anyhow::bail!("Escrow 0x{} pays {}", hex::encode(lock.id), lock.payee);
+        }
+        release.verify(&escrow_domain(chain.chain_id(), contract), &lock)?;
+        debug!("Received release of escrow 0x{} from {}", hex::encode(lock.id), frame.from);
+        Ok(Some(release))
+    }
+    
+    /// Pay out an escrow with a release (the chain key must be an authorized relayer)
+    #[cfg(feature = "chain")]
+    pub async fn release_escrow(&mut self, release: &EscrowRelease) -> anyhow::Result<TransactionReceipt> {
+        let receipt = self.chain()?.release_escrow(self.escrow_contract()?, release).await?;
+        info!("Released escrow 0x{} in block {}", hex::encode(release.lock_id), receipt.block_number);
+        Ok(receipt)
+    }
+    
+    /// Refund an expired escrow that was never released
+    #[cfg(feature = "chain")]
+    pub async fn cancel_escrow(&mut self, lock_id: [u8; 32]) -> anyhow::Result<TransactionReceipt> {
+        let receipt = self.chain()?.cancel_escrow(self.escrow_contract()?, lock_id).await?;
+        info!("Cancelled escrow 0x{}", hex::encode(lock_id));
+        Ok(receipt)
+    }
+    
+    /// Republish contract events as `Stream` frames
+    /// 
+    /// Polls `bridge` every `interval` in the background. Each event goes
+    /// out as a JSON `ChainEvent` on its watch's data channel with the next
+    /// send or flush.
+    #[cfg(feature = "chain")]
+    pub fn bridge_chain_events(&mut self, bridge: EventBridge, interval: std::time::Duration) {
+        let (tx, rx) = tokio::sync::mpsc::channel(CHAIN_EVENT_BUFFER);
+        self.chain_events = Some(rx);
+        if let Some(task) = self.event_task.replace(spawn_event_bridge(bridge, interval, tx)) {
+            task.abort();
+        }
+    }
+    
+    /// Queue `Stream` frames for the events polled by the event bridge
+    #[cfg(feature = "chain")]
+    async fn queue_chain_events(&mut self) -> anyhow::Result<()> {
+        let mut events = Vec::new();
+        if let Some(rx) = self.chain_events.as_mut() {
+            while let Ok(event) = rx.try_recv() {
+                events.push(event);
             }
         }
-        
-        Some(frame)
+        for (channel_id, event) in events {
+            let frame = self.stream_frame(&channel_id, "broadcast", serde_json::to_vec(&event)?).await?;
+            self.enqueue(frame);
+        }
+        Ok(())
     }
     
-    /// Get agent identity
-    pub fn get_identity(&self) -> Option<&AgentIdentity> {
-        self.identity.as_ref()
+    /// Anchor exchanged messages on chain
+    /// 
+    /// From now on the hashes of sent and received frames are collected, and
+    /// every `interval` they are sealed into a Merkle tree whose root is posted
+    /// to the anchor contract. `prove_message` then proves that a message was
+    /// exchanged.
+    /// 
+    /// # Arguments
+    /// * `contract` - Anchor contract
+    /// * `interval` - Time between anchoring transactions
+    #[cfg(feature = "chain")]
+    pub fn enable_anchoring(&mut self, contract: Address, interval: std::time::Duration) -> anyhow::Result<()> {
+        let chain = self.chain()?;
+        let anchor = self.anchor.get_or_insert_with(Default::default).clone();
+        if let Some(task) = self.anchor_task.replace(spawn_anchoring(anchor, chain, contract, interval)) {
+            task.abort();
+        }
+        self.anchor_contract = Some(contract);
+        info!("Anchoring messages to {} every {:?}", contract, interval);
+        Ok(())
     }
     
-    /// Export identity to hex strings
-    pub fn export_identity(&self) -> Option<(String, String)> {
-        let identity = self.identity.as_ref()?;
-        Some((
-            KeyManager::to_hex(&identity.ed_priv),
-            KeyManager::to_hex(&identity.x_priv),
-        ))
+    /// Anchor the messages collected so far without waiting for the next interval
+    /// 
+    /// # Returns
+    /// Number of batches anchored
+    #[cfg(feature = "chain")]
+    pub async fn anchor_messages(&mut self) -> anyhow::Result<usize> {
+        let (Some(anchor), Some(contract)) = (self.anchor.clone(), self.anchor_contract) else {
+            anyhow::bail!("Anchoring not enabled");
+        };
+        Ok(anchor_pending(&anchor, &*self.chain()?, contract).await?)
     }
     
-    /// Check connection status
-    pub fn is_connected(&self) -> bool {
-        self.transport.as_ref().map(|t| t.is_connected()).unwrap_or(false)
+    /// Merkle proof that a sent or received message is in an anchored batch
+    /// 
+    /// # Returns
+    /// `None` for unknown messages and messages not yet sealed into a batch;
+    /// the proof's `anchor` is unset until the batch is mined
+    #[cfg(feature = "chain")]
+    pub fn prove_message(&self, message_id: &Ulid) -> Option<MessageProof> {
+        self.anchor.as_ref()?.lock().unwrap().prove(message_id)
     }
     
-    /// Disconnect from relay
-    pub async fn disconnect(&mut self) {
+    /// Collect a frame's hash for anchoring
+    #[cfg(feature = "chain")]
+    fn record_anchored(&self, frame: &OpacusFrame) {
+        if let Some(anchor) = &self.anchor {
+            anchor.lock().unwrap().record(frame);
+        }
+    }
+    
+    /// Use an on-chain agent registry as the source of peer keys
+    /// 
+    /// Keys resolved or verified through the registry mark the peer as
+    /// verified in the trust store, and `establish_verified_session` only
+    /// accepts registered keys.
+    /// 
+    /// # Arguments
+    /// * `contract` - `AgentRegistry` address
+    /// * `store` - Store holding agent profiles
+    #[cfg(feature = "chain")]
+    pub fn set_agent_registry(&mut self, contract: Address, store: IpfsStore) -> anyhow::Result<()> {
+        let registry = AgentRegistry::new(self.chain()?, contract, store);
+        self.key_resolver = Some(KeyResolver::new(registry, KEY_CACHE_TTL));
+        Ok(())
+    }
+    
+    #[cfg(feature = "chain")]
+    fn key_resolver(&self) -> anyhow::Result<&KeyResolver<IpfsStore>> {
+        self.key_resolver.as_ref().ok_or_else(|| anyhow::anyhow!("No agent registry set"))
+    }
+    
+    /// Register this agent's keys in the agent registry
+    /// 
+    /// The profile last published with `publish_profile`, if any, is stored
+    /// with the registration.
+    /// 
+    /// # Arguments
+    /// * `stake` - Stake in wei
+    /// 
+    /// # Returns
+    /// Registry ID of the agent
+    #[cfg(feature = "chain")]
+    pub async fn register_agent(&mut self, stake: u128) -> anyhow::Result<[u8; 32]> {
+        let identity = self.identity.as_ref().expect("Not initialized");
+        let id = self.key_resolver()?.registry().register_with_profile(identity, self.profile.as_ref(), stake).await?;
+        info!("Registered {} as 0x{}", identity.id, hex::encode(id));
+        Ok(id)
+    }
+    
+    /// Resolve a peer's keys from the agent registry
+    /// 
+    /// # Arguments
+    /// * `registry_id` - Registry ID of the peer
+    #[cfg(feature = "chain")]
+    pub async fn resolve_agent(&mut self, registry_id: [u8; 32]) -> anyhow::Result<AgentKeys> {
+        let keys = self.key_resolver()?.resolve(registry_id).await?;
+        self.trust_registered_keys(&keys);
+        Ok(keys)
+    }
+    
+    /// Read the metadata profile a peer registered with from the agent registry
+    /// 
+    /// A profile is also kept for `peer_profile` unless a newer one was received.
+    /// 
+    /// # Arguments
+    /// * `registry_id` - Registry ID of the peer
+    #[cfg(feature = "chain")]
+    pub async fn resolve_profile(&mut self, registry_id: [u8; 32]) -> anyhow::Result<Option<SignedProfile>> {
+        let profile = self.key_resolver()?.registry().profile(registry_id).await?;
+        if let Some(profile) = &profile {
+            self.remember_profile(profile.clone());
+        }
+        Ok(profile)
+    }
+    
+    /// Check keys a peer presented against the agent registry
+    /// 
+    /// Cached results are reused for a while; rotated or deactivated keys
+    /// are rejected once the cache entry is rechecked.
+    #[cfg(feature = "chain")]
+    pub async fn verify_peer_keys(&mut self, ed_pub: &[u8; 32], x_pub: &[u8; 32]) -> anyhow::Result<AgentKeys> {
+        let keys = match self.key_resolver()?.verify(ed_pub, x_pub).await {
+            Ok(keys) => keys,
+            Err(e) => {
+                warn!("Unregistered keys for {}: {}", KeyManager::agent_id(ed_pub), e);
+                return Err(e.into());
+            }
+        };
+        self.trust_registered_keys(&keys);
+        Ok(keys)
+    }
+    
+    /// Establish a session from a prekey bundle whose keys are in the agent registry
+    #[cfg(feature = "chain")]
+    pub async fn establish_verified_session(&mut self, bundle: &PreKeyBundle) -> anyhow::Result<([u8; 32], X3DHHeader)> {
+        bundle.verify().map_err(anyhow::Error::msg)?;
+        self.verify_peer_keys(&bundle.ed_pub, &bundle.x_pub).await?;
+        self.establish_session(bundle)
+    }
+    
+    /// Record registry-backed keys as verified
+    #[cfg(feature = "chain")]
+    fn trust_registered_keys(&mut self, keys: &AgentKeys) {
+        self.trust.observe(&keys.agent_id, &keys.ed_pub, &keys.x_pub);
+        let fingerprint = Fingerprint::of(&keys.ed_pub, &keys.x_pub);
+        self.trust.mark_verified(&keys.agent_id, &fingerprint).expect("Keys just observed");
+        self.sealing_keys.insert(keys.agent_id.clone(), keys.x_pub);
+    }
+    
+    /// Resolve agent names (e.g. "analytics.agent0g") through an ENS-compatible registry
+    /// 
+    /// Messages to a name are then sent to the agent its record points to.
+    #[cfg(feature = "chain")]
+    pub fn set_name_registry(&mut self, registry: Address) -> anyhow::Result<()> {
+        self.names = Some(NameService::new(self.chain()?, registry));
+        Ok(())
+    }
+    
+    /// Resolve an agent name to its signed record
+    /// 
+    /// The record's keys are checked against the agent registry when one is
+    /// set, and recorded in the trust store otherwise.
+    #[cfg(feature = "chain")]
+    pub async fn resolve_name(&mut self, name: &str) -> anyhow::Result<NameRecord> {
+        let names = self.names.as_ref().ok_or_else(|| anyhow::anyhow!("No name registry set"))?;
+        let record = names.resolve(name).await?.ok_or_else(|| anyhow::anyhow!("Unknown name {}", name))?;
+        if self.key_resolver.is_some() {
+            self.verify_peer_keys(&record.ed_pub, &record.x_pub).await?;
+        } else {
+            self.trust.observe(&record.agent_id, &record.ed_pub, &record.x_pub);
+        }
+        debug!("Resolved {} to {}", record.name, record.agent_id);
+        Ok(record)
+    }
+    
+    /// Point a name owned by the chain account at this agent
+    #[cfg(feature = "chain")]
+    pub async fn register_name(&mut self, name: &str) -> anyhow::Result<NameRecord> {
+        let identity = self.identity.as_ref().expect("Not initialized");
+        let record = NameRecord::sign(identity, name, self.clock.now_ms())?;
+        let names = self.names.as_ref().ok_or_else(|| anyhow::anyhow!("No name registry set"))?;
+        names.publish(&record).await?;
+        info!("Registered name {} for {}", record.name, record.agent_id);
+        Ok(record)
+    }
+    
+    /// Agent ID of a recipient given by ID or, with a name registry set, by name
+    async fn recipient(&mut self, to: &str) -> anyhow::Result<String> {
+        #[cfg(feature = "chain")]
+        if self.names.is_some() && is_agent_name(to) {
+            return Ok(self.resolve_name(to).await?.agent_id);
+        }
+        Ok(to.to_string())
+    }
+    
+    /// Offload large message payloads to 0G storage
+    /// 
+    /// Messages whose payload exceeds `threshold` bytes are uploaded and sent
+    /// as a `Reference` to the stored payload. Received references are
+    /// fetched from the same store and checked against their hash.
+    /// 
+    /// # Arguments
+    /// * `store` - 0G storage indexer
+    /// * `threshold` - Payload size above which payloads are offloaded
+    ///   (e.g. `DEFAULT_OFFLOAD_THRESHOLD`)
+    #[cfg(feature = "chain")]
+    pub fn set_offload_store(&mut self, store: ZeroGStore, threshold: usize) {
+        self.offload = Some((store, threshold));
+    }
+    
+    /// Fetch and verify the payload of a `Reference` frame
+    #[cfg(feature = "chain")]
+    pub async fn fetch_offloaded(&self, frame: &OpacusFrame) -> anyhow::Result<Vec<u8>> {
+        let reference = frame.payload_ref().ok_or_else(|| anyhow::anyhow!("Frame carries no payload reference"))?;
+        let (store, _) = self.offload.as_ref().ok_or_else(|| anyhow::anyhow!("No offload store set"))?;
+        let data = store.get(&reference.uri).await?;
+        reference.verify(&data).map_err(anyhow::Error::msg)?;
+        Ok(data)
+    }
+    
+    /// Upload a payload above the offload threshold
+    /// 
+    /// # Returns
+    /// The reference payload and options to send instead, or the inputs unchanged
+    #[cfg(feature = "chain")]
+    async fn offload_payload(&self, payload: Vec<u8>, options: FrameOptions) -> anyhow::Result<(Vec<u8>, FrameOptions)> {
+        let Some((store, threshold)) = &self.offload else {
+            return Ok((payload, options));
+        };
+        if payload.len() <= *threshold {
+            return Ok((payload, options));
+        }
+        let uri = store.put(payload.clone()).await?;
+        let reference = PayloadRef::new(uri, &payload, options.content_type);
+        debug!("Offloaded {} byte payload to {}", reference.size, reference.uri);
+        Ok((serde_json::to_vec(&reference)?, FrameOptions { content_type: ContentType::Reference, ..options }))
+    }
+    
+    /// Replace a received reference with the payload it points to
+    /// 
+    /// The reference moves to the `offloaded` extension. Frames whose payload
+    /// cannot be fetched are delivered unchanged.
+    #[cfg(feature = "chain")]
+    async fn resolve_offloaded(&self, mut frame: OpacusFrame) -> OpacusFrame {
+        if self.offload.is_none() || frame.content_type != ContentType::Reference {
+            return frame;
+        }
+        let Some(reference) = frame.payload_ref() else {
+            return frame;
+        };
+        match self.fetch_offloaded(&frame).await {
+            Ok(data) => {
+                frame.payload = data.into();
+                frame.compressed = None;
+                frame.content_type = reference.content_type;
+                if let Ok(value) = ciborium::Value::serialized(&reference) {
+                    frame.extensions.insert(OFFLOADED_EXTENSION.to_string(), value);
+                }
+            }
+            Err(e) => warn!("Cannot fetch offloaded payload {} from {}: {}", reference.uri, frame.from, e),
+        }
+        frame
+    }
+    
+    /// Select the frame encoding used with the relay (CBOR by default)
+    /// 
+    /// Takes effect on the next `connect`.
+    pub fn set_wire_format(&mut self, format: WireFormat) {
+        self.wire_format = format;
+    }
+    
+    /// Present a JWT to relays that authenticate agents (`None` stops, the default)
+    /// 
+    /// Sent in the `Connect` frame, so it takes effect on the next `connect`
+    /// and replaces an expired token on reconnect. Relays check it with
+    /// `with_jwt_auth`.
+    pub fn set_auth_token(&mut self, token: Option<String>) {
+        self.auth_token = token;
+    }
+    
+    /// Attach a trace context to outgoing frames (off by default)
+    /// 
+    /// Frames continue the current OpenTelemetry trace with the `otel`
+    /// feature; otherwise each frame starts a trace. See [`crate::trace`].
+    pub fn set_trace_propagation(&mut self, enabled: bool) {
+        self.trace_propagation = enabled;
+    }
+    
+    /// Seal frames to other agents, hiding this agent from the relay (off by default)
+    /// 
+    /// Every frame to another agent then needs its X25519 key (see
+    /// `set_sealing_key`); frames to the relay and batches stay unsealed.
+    /// See [`crate::sealed`].
+    pub fn set_sealed_sender(&mut self, enabled: bool) {
+        self.sealed_sender = enabled;
+    }
+    
+    /// Pad outgoing frames to fixed bucket sizes (`None` stops, the default)
+    /// 
+    /// Sizes are measured in the wire format set with `set_wire_format`.
+    /// See [`crate::padding`].
+    pub fn set_padding(&mut self, policy: Option<PaddingPolicy>) {
+        self.padding = policy;
+    }
+    
+    /// Send cover frames while the connection is idle (`None` stops, the default)
+    /// 
+    /// A cover frame is due when nothing was sent for a random time between
+    /// half and one and a half `interval`; `send_cover_if_idle` sends it.
+    /// [`OpacusNode`](crate::OpacusNode) does so by itself.
+    pub fn set_cover_traffic(&mut self, interval: Option<Duration>) {
+        self.cover_interval = interval.filter(|interval| !interval.is_zero());
+        self.schedule_cover();
+    }
+    
+    /// Time until the next cover frame is due (`None` without cover traffic)
+    pub fn cover_due_in(&self) -> Option<Duration> {
+        let due = self.next_cover?;
+        Some(Duration::from_millis(due.saturating_sub(self.clock.now_ms())))
+    }
+    
+    /// Send a cover frame if one is due
+    /// 
+    /// Cover frames are addressed to the relay, which discards them, and
+    /// padded to a random bucket of the padding policy (the default buckets
+    /// without one).
+    /// 
+    /// # Returns
+    /// Whether a cover frame was sent
+    pub async fn send_cover_if_idle(&mut self) -> anyhow::Result<bool> {
+        if self.cover_due_in().is_none_or(|wait| !wait.is_zero()) {
+            return Ok(false);
+        }
+        let identity = self.identity.as_ref().expect("Not initialized");
+        let codec = self.wire_format.codec()
+            .ok_or_else(|| anyhow::anyhow!("{:?} support not compiled in", self.wire_format))?;
+        let buckets = self.padding.clone().unwrap_or_default().buckets().to_vec();
+        let Some(size) = buckets.get(self.random.next_u64() as usize % buckets.len().max(1)) else {
+            return Ok(false);
+        };
+        let id = OpacusFrame::new_id_with(self.clock.now_ms(), self.random.as_ref());
+        let cover = OpacusFrame::cover(&identity.id, id, *size, codec)?;
+        self.enqueue(cover);
+        self.flush().await?;
+        Ok(true)
+    }
+    
+    /// Set when the next cover frame is due, counting from now
+    fn schedule_cover(&mut self) {
+        self.next_cover = self.cover_interval.map(|interval| {
+            let interval = interval.as_millis() as u64;
+            self.clock.now_ms() + interval / 2 + self.random.next_u64() % interval.max(1)
+        });
+    }
+    
+    /// Record frames sent to and received from the relay (`None` stops)
+    /// 
+    /// Received batches are recorded as they arrived, not unpacked.
+    pub fn set_capture(&mut self, capture: Option<Arc<FrameCapture>>) {
+        self.capture = capture;
+    }
+    
+    /// Record the lifecycle of sent and received frames (`None` stops)
+    /// 
+    /// See [`crate::lifecycle`] for the recorded stages.
+    pub fn set_lifecycle_log(&mut self, log: Option<Arc<LifecycleLog>>) {
+        self.lifecycle = log;
+    }
+    
+    /// Record a lifecycle event, built only if a lifecycle log is set
+    fn log_lifecycle(&self, event: impl FnOnce() -> LifecycleEvent) {
+        if let Some(log) = &self.lifecycle {
+            log.record(event());
+        }
+    }
+    
+    /// Connect to relay server
+    /// 
+    /// A relay URL of the form `local://<name>` joins the in-process relay of
+    /// that name ([`MemoryRelay::local`]), for single-process multi-agent
+    /// applications and CI.
+    pub async fn connect(&mut self) -> anyhow::Result<()> {
+        assert!(self.identity.is_some(), "Not initialized. Call init() first");
+        
+        // In-process relay, without any network
+        if let Some(name) = self.config.relay_url.strip_prefix(LOCAL_RELAY_SCHEME) {
+            info!("Connected to local relay: {}", name);
+            return self.connect_with(MemoryRelay::local(name).transport()).await;
+        }
+        
+        // Parse relay URL
+        let url = self.config.relay_url
+            .replace("quic://", "")
+            .replace("https://", "")
+            .replace("http://", "");
+        
+        let mut transport = QUICTransport::new("0.0.0.0:0", &url).await?;
+        transport.set_wire_format(self.wire_format)?;
+        transport.connect().await?;
+        
+        info!("Connected to relay: {}", self.config.relay_url);
+        
+        self.connect_with(transport).await
+    }
+    
+    /// Connect to a relay over an established transport
+    /// 
+    /// Sends the `Connect` frame followed by this agent's capabilities; the
+    /// relay's `Ack` arrives through `recv`. With a [`MemoryTransport`](crate::MemoryTransport), agents can be
+    /// tested without sockets.
+    pub async fn connect_with(&mut self, transport: impl Transport + 'static) -> anyhow::Result<()> {
+        let identity = self.identity.as_ref().expect("Not initialized. Call init() first");
+        
+        // Send connect frame
+        let mut connect_payload = serde_json::json!({
+            "edPub": KeyManager::to_hex(&identity.ed_pub),
+            "xPub": KeyManager::to_hex(&identity.x_pub),
+            "compression": Compression::supported()
+        });
+        if let Some(token) = &self.auth_token {
+            connect_payload[CONNECT_TOKEN_FIELD] = token.clone().into();
+        }
+        
+        let ts = self.clock.now_ms();
+        let mut frame = OpacusFrame {
+            version: FRAME_VERSION,
+            frame_type: FrameType::Connect,
+            from: identity.id.clone(),
+            to: "relay".to_string(),
+            seq: self.seq,
+            ts,
+            nonce: self.security.read().await.next_nonce(),
+            payload: serde_json::to_vec(&connect_payload)?.into(),
+            hmac: None,
+            sig: None,
+            key_epoch: 0,
+            compressed: None,
+            id: Some(OpacusFrame::new_id_with(ts, &*self.random)),
+            priority: Priority::Control,
+            content_type: ContentType::Json,
+            extensions: Default::default(),
+        };
+        // Proves the agent holds its key, e.g. to relays checking tokens
+        frame.sig = Some(SecurityManager::sign(&identity.ed_priv, &SecurityManager::connect_sign_data(&frame)));
+        self.seq += 1;
+        
+        if let Err(e) = transport.send(&frame) {
+            self.log_lifecycle(|| LifecycleEvent::failed(&frame, e.to_string()));
+            return Err(e);
+        }
+        if let Some(capture) = &self.capture {
+            capture.record(CaptureDirection::Out, &frame);
+        }
+        self.log_lifecycle(|| LifecycleEvent::new(FrameStage::Sent, &frame));
+        self.pending_connect = frame.id;
+        debug!("Sent connect frame");
+        
+        self.transport = Some(Box::new(transport));
+        self.publish_capabilities().await
+    }
+    
+    /// Send message to another agent
+    /// 
+    /// Payloads are compressed when compression was negotiated with the relay.
+    /// 
+    /// # Arguments
+    /// * `to` - Recipient agent ID, or agent name when a name registry is set
+    /// * `payload` - Message payload bytes
+    /// 
+    /// A message held back by congestion stays queued and goes out on the
+    /// next send or flush; use [`send_message_tracked`](Self::send_message_tracked)
+    /// to tell it apart from one already sent.
+    pub async fn send_message(&mut self, to: &str, payload: Vec<u8>) -> anyhow::Result<()> {
+        self.send_message_inner(to, payload, true, FrameOptions::default(), None).await?;
+        Ok(())
+    }
+    
+    /// Send message, reporting whether it went out or was queued
+    /// 
+    /// Fails with [`CongestionError::Dropped`] only when the message was
+    /// dropped from the send queue.
+    pub async fn send_message_tracked(&mut self, to: &str, payload: Vec<u8>) -> anyhow::Result<SendStatus> {
+        Ok(self.send_message_inner(to, payload, true, FrameOptions::default(), None).await?.1)
+    }
+    
+    /// Send message without compressing it (for already-compressed data)
+    pub async fn send_message_uncompressed(&mut self, to: &str, payload: Vec<u8>) -> anyhow::Result<()> {
+        self.send_message_inner(to, payload, false, FrameOptions::default(), None).await?;
+        Ok(())
+    }
+    
+    /// Send a value as a JSON message
+    /// 
+    /// Receivers decode it with [`OpacusFrame::payload_as`].
+    pub async fn send_json<T: Serialize>(&mut self, to: &str, value: &T) -> anyhow::Result<()> {
+        let options = FrameOptions { content_type: ContentType::Json, ..Default::default() };
+        self.send_message_inner(to, serde_json::to_vec(value)?, true, options, None).await?;
+        Ok(())
+    }
+    
+    /// Send a UTF-8 text message
+    pub async fn send_text(&mut self, to: &str, text: &str) -> anyhow::Result<()> {
+        let options = FrameOptions { content_type: ContentType::Text, ..Default::default() };
+        self.send_message_inner(to, text.as_bytes().to_vec(), true, options, None).await?;
+        Ok(())
+    }
+    
+    /// Send message with an explicit priority
+    /// 
+    /// `Low` messages may be dropped under congestion, failing with
+    /// `CongestionError::Dropped`.
+    pub async fn send_message_with_priority(
+        &mut self,
+        to: &str,
+        payload: Vec<u8>,
+        priority: Priority,
+    ) -> anyhow::Result<()> {
+        let options = FrameOptions { priority: Some(priority), ..Default::default() };
+        self.send_message_inner(to, payload, true, options, None).await?;
+        Ok(())
+    }
+    
+    /// Send a message expecting a reply
+    /// 
+    /// The recipient answers with `send_reply`; the reply carries the
+    /// returned ID in its [`REPLY_TO_EXTENSION`](crate::REPLY_TO_EXTENSION).
+    /// 
+    /// # Returns
+    /// Message ID of the request
+    pub async fn send_request(&mut self, to: &str, payload: Vec<u8>, options: FrameOptions) -> anyhow::Result<Ulid> {
+        let (id, _) = self.send_message_inner(to, payload, true, options, None).await?;
+        id.ok_or_else(|| anyhow::anyhow!("Request to {} has no message ID", to))
+    }
+    
+    /// Answer a request received from another agent
+    pub async fn send_reply(&mut self, request: &OpacusFrame, payload: Vec<u8>, options: FrameOptions) -> anyhow::Result<()> {
+        let request_id = request.id.ok_or_else(|| anyhow::anyhow!("Request from {} has no message ID", request.from))?;
+        self.send_message_inner(&request.from, payload, true, options, Some(request_id)).await?;
+        Ok(())
+    }
+    
+    async fn send_message_inner(
+        &mut self,
+        to: &str,
+        payload: Vec<u8>,
+        compress: bool,
+        options: FrameOptions,
+        reply_to: Option<Ulid>,
+    ) -> anyhow::Result<(Option<Ulid>, SendStatus)> {
+        let to = &self.recipient(to).await?;
+        #[cfg(feature = "chain")]
+        let (payload, options) = self.offload_payload(payload, options).await?;
+        let mut frame = self.message_frame(to, payload, compress, options).await;
+        if let Some(request_id) = reply_to {
+            frame.set_reply_to(request_id);
+        }
+        let id = frame.id;
+        debug!("Sending message {:?} to {}", id, to);
+        let status = match self.dispatch_tracked(frame).await? {
+            Delivery::Status(status) => status,
+            Delivery::Dropped => return Err(CongestionError::Dropped(id).into()),
+        };
+        if status == SendStatus::Sent {
+            self.rekey_if_due(to).await?;
+        }
+        
+        Ok((id, status))
+    }
+    
+    async fn message_frame(
+        &mut self,
+        to: &str,
+        payload: Vec<u8>,
+        compress: bool,
+        options: FrameOptions,
+    ) -> OpacusFrame {
+        let identity = self.identity.as_ref().expect("Not initialized");
+        let relay_x_pub = self.relay_x_pub.unwrap_or([0u8; 32]);
+        
+        let (payload, compressed) = if compress {
+            Self::compress_payload(self.compression, payload)
+        } else {
+            (payload, None)
+        };
+        
+        self.security.write().await.create_auth_frame_with(
+            identity,
+            &relay_x_pub,
+            FrameType::Msg,
+            to,
+            payload,
+            FrameOptions { compressed, ..options },
+        )
+    }
+    
+    /// Send stream data
+    /// 
+    /// Stream frames are `Low` priority and may be dropped under congestion.
+    pub async fn send_stream(&mut self, channel_id: &str, data: Vec<u8>) -> anyhow::Result<()> {
+        let frame = self.stream_frame(channel_id, "broadcast", data).await?;
+        self.dispatch(frame).await?;
+        debug!("Sent stream to channel {}", channel_id);
+        self.rekey_if_due("broadcast").await?;
+        
+        Ok(())
+    }
+    
+    async fn stream_frame(&mut self, channel_id: &str, to: &str, data: Vec<u8>) -> anyhow::Result<OpacusFrame> {
+        let frame = self.unmetered_stream_frame(channel_id, to, data).await?;
+        self.meter.record_frame(&frame, to);
+        Ok(frame)
+    }
+    
+    async fn unmetered_stream_frame(&mut self, channel_id: &str, to: &str, data: Vec<u8>) -> anyhow::Result<OpacusFrame> {
+        let identity = self.identity.as_ref().expect("Not initialized");
+        let relay_x_pub = self.relay_x_pub.unwrap_or([0u8; 32]);
+        
+        let payload = serde_json::json!({
+            "channelId": channel_id,
+            "data": data
+        });
+        
+        Ok(self.security.write().await.create_auth_frame_with(
+            identity,
+            &relay_x_pub,
+            FrameType::Stream,
+            to,
+            serde_json::to_vec(&payload)?,
+            FrameOptions { content_type: ContentType::Json, ..Default::default() },
+        ))
+    }
+    
+    /// Send several messages in one `Batch` frame
+    /// 
+    /// The batch and each message are signed. Messages to different
+    /// recipients are split up by the relay, which forwards each one as sent.
+    /// 
+    /// # Arguments
+    /// * `messages` - `(recipient, payload)` pairs, at least one and at most `MAX_BATCH_FRAMES`
+    pub async fn send_batch(&mut self, messages: Vec<(String, Vec<u8>)>) -> anyhow::Result<()> {
+        if messages.is_empty() {
+            anyhow::bail!("Batch has no messages");
+        }
+        if messages.len() > MAX_BATCH_FRAMES {
+            anyhow::bail!("Batch of {} messages exceeds {}", messages.len(), MAX_BATCH_FRAMES);
+        }
+        let identity = self.identity.as_ref().expect("Not initialized");
+        let relay_x_pub = self.relay_x_pub.unwrap_or([0u8; 32]);
+        
+        let mut recipients: Vec<String> = messages.iter().map(|(to, _)| to.clone()).collect();
+        recipients.dedup();
+        let to = match recipients.as_slice() {
+            [single] => single.clone(),
+            _ => "relay".to_string(),
+        };
+        
+        let frame = {
+            let mut security = self.security.write().await;
+            let entries = messages
+                .into_iter()
+                .map(|(to, payload)| security.create_auth_frame(identity, &relay_x_pub, FrameType::Msg, &to, payload))
+                .collect();
+            let batch = FrameBatch::new(entries);
+            let options = FrameOptions { priority: Some(batch.priority()), ..Default::default() };
+            security.create_auth_frame_with(identity, &relay_x_pub, FrameType::Batch, &to, batch.encode()?, options)
+        };
+        
+        debug!("Sending batch {:?} to {}", frame.id, to);
+        self.dispatch(frame).await?;
+        recipients.sort();
+        recipients.dedup();
+        for to in recipients {
+            self.rekey_if_due(&to).await?;
+        }
+        
+        Ok(())
+    }
+    
+    /// Report a rejection to a peer with a signed `Error` frame
+    /// 
+    /// Set `related_id` on the payload to the ID of the rejected frame.
+    pub async fn send_error(&mut self, to: &str, error: &ErrorPayload) -> anyhow::Result<()> {
+        let identity = self.identity.as_ref().expect("Not initialized");
+        let relay_x_pub = self.relay_x_pub.unwrap_or([0u8; 32]);
+        
+        let frame = self.security.write().await.create_auth_frame_with(
+            identity,
+            &relay_x_pub,
+            FrameType::Error,
+            to,
+            serde_json::to_vec(error)?,
+            FrameOptions { content_type: ContentType::Json, ..Default::default() },
+        );
+        
+        self.dispatch(frame).await
+    }
+    
+    /// Compress a payload if it is large enough and actually shrinks
+    fn compress_payload(alg: Option<Compression>, payload: Vec<u8>) -> (Vec<u8>, Option<Compression>) {
+        let Some(alg) = alg else {
+            return (payload, None);
+        };
+        if payload.len() < DEFAULT_COMPRESSION_THRESHOLD {
+            return (payload, None);
+        }
+        match alg.compress(&payload) {
+            Ok(packed) if packed.len() < payload.len() => (packed, Some(alg)),
+            _ => (payload, None),
+        }
+    }
+    
+    /// Announce a new session key to a peer once the rekey policy says so
+    ///
+    /// The new key is used once the peer acknowledges; unacknowledged
+    /// announcements are repeated.
+    async fn rekey_if_due(&mut self, to: &str) -> anyhow::Result<()> {
+        let frame = {
+            let mut security = self.security.write().await;
+            if !security.rekey_due(to) {
+                return Ok(());
+            }
+            let identity = self.identity.as_ref().expect("Not initialized");
+            let relay_x_pub = self.relay_x_pub.unwrap_or([0u8; 32]);
+            security.create_rekey_frame(identity, &relay_x_pub, to)
+        };
+        debug!("Announced session key epoch {} to {}", frame.key_epoch, to);
+        self.dispatch(frame).await
+    }
+    
+    /// Queue a frame and send as much of the queue as the connection allows
+    async fn dispatch(&mut self, frame: OpacusFrame) -> anyhow::Result<()> {
+        self.dispatch_tracked(frame).await?;
+        Ok(())
+    }
+    
+    /// Queue a frame and flush, reporting whether it went out
+    async fn dispatch_tracked(&mut self, mut frame: OpacusFrame) -> anyhow::Result<Delivery> {
+        let span = debug_span!(
+            "opacus.send",
+            frame_id = ?frame.id,
+            frame_type = ?frame.frame_type,
+            to = %frame.to,
+            trace_id = field::Empty,
+        );
+        if self.trace_propagation && frame.trace_context().is_none() {
+            frame.set_trace_context(&span.in_scope(TraceContext::current));
+        }
+        if let Some(context) = frame.trace_context() {
+            span.record("trace_id", context.trace_id_hex());
+        }
+        if self.seals(&frame) {
+            frame = self.seal_frame(&frame).await?;
+        }
+        async {
+            let nonce = frame.nonce.clone();
+            self.enqueue(frame);
+            let sent = self.flush_watching(Some(&nonce)).await?.1;
+            Ok(if sent {
+                Delivery::Status(SendStatus::Sent)
+            } else if self.outbox.contains(&nonce) {
+                Delivery::Status(SendStatus::Queued)
+            } else {
+                Delivery::Dropped
+            })
+        }
+        .instrument(span)
+        .await
+    }
+    
+    /// Add a frame to the send queue
+    fn enqueue(&mut self, frame: OpacusFrame) {
+        self.log_lifecycle(|| LifecycleEvent::new(FrameStage::Enqueued, &frame));
+        if let Some(dropped) = self.outbox.push(frame) {
+            debug!("Send queue full, dropped {:?} frame to {}", dropped.priority, dropped.to);
+            self.log_lifecycle(|| LifecycleEvent::failed(&dropped, "send queue full"));
+        }
+    }
+    
+    /// Send queued frames, highest priority first
+    /// 
+    /// Stops while the connection is congested, dropping queued `Low` frames;
+    /// the remaining frames go out on the next send or flush.
+    /// 
+    /// # Returns
+    /// Number of frames sent
+    pub async fn flush(&mut self) -> anyhow::Result<usize> {
+        Ok(self.flush_watching(None).await?.0)
+    }
+    
+    /// Flush, also reporting whether the frame with nonce `watch` was sent
+    async fn flush_watching(&mut self, watch: Option<&str>) -> anyhow::Result<(usize, bool)> {
+        #[cfg(feature = "chain")]
+        self.queue_chain_events().await?;
+        let transport = self.transport.as_ref().expect("Not connected");
+        let (mut sent, mut watched_sent) = (0, false);
+        while !self.outbox.is_empty() {
+            if transport.is_congested() {
+                let shed = self.outbox.shed_droppable();
+                if !shed.is_empty() {
+                    debug!("Congested, dropped {} low-priority frames", shed.len());
+                }
+                for frame in &shed {
+                    self.log_lifecycle(|| LifecycleEvent::failed(frame, "connection congested"));
+                }
+                break;
+            }
+            let Some(mut frame) = self.outbox.pop() else { break };
+            if let (Some(policy), Some(codec)) = (&self.padding, self.wire_format.codec()) {
+                frame.pad(codec, policy)?;
+            }
+            if let Err(e) = transport.send(&frame) {
+                self.log_lifecycle(|| LifecycleEvent::failed(&frame, e.to_string()));
+                return Err(e);
+            }
+            if let Some(capture) = &self.capture {
+                capture.record(CaptureDirection::Out, &frame);
+            }
+            self.log_lifecycle(|| LifecycleEvent::new(FrameStage::Sent, &frame));
+            #[cfg(feature = "chain")]
+            self.record_anchored(&frame);
+            watched_sent |= watch == Some(frame.nonce.as_str());
+            sent += 1;
+        }
+        if sent > 0 {
+            self.schedule_cover();
+        }
+        Ok((sent, watched_sent))
+    }
+    
+    /// Number of frames waiting in the send queue
+    pub fn queued(&self) -> usize {
+        self.outbox.len()
+    }
+    
+    /// Publish prekeys so other agents can open sessions while this agent is offline
+    /// 
+    /// The first call generates a signed prekey; later calls only add
+    /// `one_time_count` fresh one-time prekeys.
+    pub async fn publish_prekeys(&mut self, one_time_count: u32) -> anyhow::Result<()> {
+        let identity = self.identity.as_ref().expect("Not initialized");
+        let relay_x_pub = self.relay_x_pub.unwrap_or([0u8; 32]);
+        
+        let bundle = match self.prekeys.as_mut() {
+            Some(store) => store.replenish_with(identity, one_time_count, &*self.random),
+            None => {
+                let (store, bundle) = PreKeyStore::generate_with(identity, one_time_count, &*self.random);
+                self.prekeys = Some(store);
+                bundle
+            }
+        };
+        
+        let frame = self.security.write().await.create_auth_frame(
+            identity,
+            &relay_x_pub,
+            FrameType::PreKeyPublish,
+            "relay",
+            serde_json::to_vec(&bundle)?,
+        );
+        
+        self.dispatch(frame).await?;
+        debug!("Published {} one-time prekeys", one_time_count);
+        
+        Ok(())
+    }
+    
+    /// Request another agent's prekey bundle from the relay
+    /// 
+    /// The bundle arrives as a `PreKeyFetch` frame; decode it with
+    /// [`OpacusClient::parse_prekey_bundle`].
+    pub async fn request_prekeys(&mut self, agent_id: &str) -> anyhow::Result<()> {
+        let identity = self.identity.as_ref().expect("Not initialized");
+        let relay_x_pub = self.relay_x_pub.unwrap_or([0u8; 32]);
+        
+        let payload = serde_json::json!({ "agentId": agent_id });
+        let frame = self.security.write().await.create_auth_frame(
+            identity,
+            &relay_x_pub,
+            FrameType::PreKeyFetch,
+            "relay",
+            serde_json::to_vec(&payload)?,
+        );
+        
+        self.dispatch(frame).await?;
+        debug!("Requested prekeys for {}", agent_id);
+        
+        Ok(())
+    }
+    
+    /// Decode a prekey bundle delivered by the relay
+    /// 
+    /// # Returns
+    /// `None` if the frame is not a bundle or the agent has none published
+    pub fn parse_prekey_bundle(frame: &OpacusFrame) -> Option<PreKeyBundle> {
+        if frame.frame_type != FrameType::PreKeyFetch || frame.payload.is_empty() {
+            return None;
+        }
+        serde_json::from_slice(&frame.payload).ok()
+    }
+    
+    /// Establish a session key with an agent from its prekey bundle
+    /// 
+    /// # Returns
+    /// Session key and the header the recipient needs to derive it
+    pub fn establish_session(&self, bundle: &PreKeyBundle) -> anyhow::Result<([u8; 32], X3DHHeader)> {
+        let identity = self.identity.as_ref().expect("Not initialized");
+        X3DH::initiate_with(identity, bundle, &*self.random).map_err(|e| anyhow::anyhow!(e))
+    }
+    
+    /// Derive the session key for a session opened against our prekeys
+    pub fn accept_session(&mut self, header: &X3DHHeader) -> anyhow::Result<[u8; 32]> {
+        let identity = self.identity.as_ref().expect("Not initialized");
+        let store = self.prekeys.as_mut()
+            .ok_or_else(|| anyhow::anyhow!("No prekeys published"))?;
+        X3DH::respond(identity, store, header).map_err(|e| anyhow::anyhow!(e))
+    }
+    
+    /// Set the X25519 key frames to an agent are sealed with
+    /// 
+    /// Keys are also learned from verified prekey bundles and from sealed
+    /// frames the agent sends. A relay could substitute the key in a bundle,
+    /// so compare safety numbers with agents whose sender must stay hidden.
+    pub fn set_sealing_key(&mut self, agent_id: &str, x_pub: [u8; 32]) {
+        self.sealing_keys.insert(agent_id.to_string(), x_pub);
+    }
+    
+    /// X25519 key frames to an agent are sealed with
+    pub fn sealing_key(&self, agent_id: &str) -> Option<[u8; 32]> {
+        self.sealing_keys.get(agent_id).copied()
+    }
+    
+    /// Send a message whose sender only the recipient learns
+    /// 
+    /// Seals it whether or not `set_sealed_sender` is on.
+    pub async fn send_sealed(&mut self, to: &str, payload: Vec<u8>) -> anyhow::Result<()> {
+        let to = &self.recipient(to).await?;
+        let frame = self.message_frame(to, payload, true, FrameOptions::default()).await;
+        let sealed = self.seal_frame(&frame).await?;
+        debug!("Sending sealed message {:?} to {}", frame.id, to);
+        self.dispatch(sealed).await?;
+        self.rekey_if_due(to).await?;
+        Ok(())
+    }
+    
+    /// Onion key of the connected relay, advertised in its connect ACK
+    pub fn relay_onion_key(&self) -> Option<[u8; 32]> {
+        self.relay_onion_key
+    }
+    
+    /// Send a sealed message through an onion route of relays
+    /// 
+    /// The connected relay is the first hop; `route` lists the one or two
+    /// relays after it, the last being the recipient's. The connected relay
+    /// learns that the agent sent something but not to whom, and the
+    /// recipient's relay neither the sender nor where the message entered
+    /// the route. Session rekeys are not sent, as they would bypass the route.
+    /// 
+    /// # Arguments
+    /// * `to` - Recipient agent ID, whose sealing key must be known
+    /// * `payload` - Message payload
+    /// * `route` - Relays after the connected one (see [`crate::onion`])
+    pub async fn send_onion(&mut self, to: &str, payload: Vec<u8>, route: &[OnionHop]) -> anyhow::Result<()> {
+        let x_pub = self.relay_onion_key
+            .ok_or_else(|| anyhow::anyhow!("Connected relay does not route onion frames"))?;
+        let to = &self.recipient(to).await?;
+        let frame = self.message_frame(to, payload, true, FrameOptions::default()).await;
+        let mut sealed = self.seal_frame(&frame).await?;
+        // Padded before wrapping, so every layer's size follows from the bucket
+        if let (Some(policy), Some(codec)) = (&self.padding, self.wire_format.codec()) {
+            sealed.pad(codec, policy)?;
+        }
+        let mut hops = vec![OnionHop::new(&self.config.relay_url, x_pub)];
+        hops.extend_from_slice(route);
+        let onion = sealed.wrap_onion(&hops, self.random.as_ref()).map_err(anyhow::Error::msg)?;
+        debug!("Sending message {:?} through {} relays", frame.id, hops.len());
+        // Not dispatched: the trace context dispatch adds would be readable by the first relay
+        self.enqueue(onion);
+        self.flush().await?;
+        Ok(())
+    }
+    
+    /// Wrap a signed frame so the relay does not learn its sender
+    async fn seal_frame(&mut self, frame: &OpacusFrame) -> anyhow::Result<OpacusFrame> {
+        let x_pub = self.peer_sealing_key(&frame.to).await?;
+        let identity = self.identity.as_ref().expect("Not initialized");
+        frame.seal(identity, &x_pub, self.random.as_ref()).map_err(anyhow::Error::msg)
+    }
+    
+    /// X25519 key to seal frames to an agent with
+    /// 
+    /// With an agent registry set, only keys resolved or verified through it
+    /// are used, so keys learned from bundles or sealed frames cannot
+    /// substitute them.
+    async fn peer_sealing_key(&mut self, agent_id: &str) -> anyhow::Result<[u8; 32]> {
+        #[cfg(feature = "chain")]
+        if let Some(resolver) = &self.key_resolver {
+            let keys = resolver.keys(agent_id).await?.ok_or_else(|| {
+                anyhow::anyhow!("Keys of {} are not in the agent registry; resolve them with resolve_agent or verify_peer_keys", agent_id)
+            })?;
+            self.sealing_keys.insert(keys.agent_id, keys.x_pub);
+            return Ok(keys.x_pub);
+        }
+        self.sealing_key(agent_id).ok_or_else(|| {
+            anyhow::anyhow!("No sealing key for {}; fetch its prekeys or set one with set_sealing_key", agent_id)
+        })
+    }
+    
+    /// Check a frame against the keys the agent registry holds for its sender
+    /// 
+    /// Frames from agents resolved or verified through the registry must be
+    /// signed with the registered key, and frames from agents whose keys were
+    /// rotated or deactivated since are refused. Other senders are not checked.
+    #[cfg(feature = "chain")]
+    async fn check_registered_sender(&self, frame: &OpacusFrame) -> anyhow::Result<()> {
+        let Some(resolver) = &self.key_resolver else { return Ok(()) };
+        let Some(keys) = resolver.keys(&frame.from).await? else { return Ok(()) };
+        let signed = match (&frame.hmac, &frame.sig) {
+            (Some(hmac), Some(sig)) => {
+                SecurityManager::verify(&keys.ed_pub, &SecurityManager::frame_sign_data(frame, hmac), sig)
+            }
+            _ => false,
+        };
+        anyhow::ensure!(signed, "not signed with the registered key of {}", frame.from);
+        Ok(())
+    }
+    
+    /// Whether `set_sealed_sender` applies to an outgoing frame
+    fn seals(&self, frame: &OpacusFrame) -> bool {
+        self.sealed_sender
+            && !frame.is_sealed()
+            && !matches!(frame.frame_type, FrameType::Connect | FrameType::Batch)
+            && !matches!(frame.to.as_str(), "" | "relay" | "broadcast")
+    }
+    
+    /// Features this agent supports
+    /// 
+    /// Built from this build's content types, compression and extensions, the
+    /// offered data channels, advertised services and published prekeys.
+    pub fn local_capabilities(&self) -> Capabilities {
+        let identity = self.identity.as_ref().expect("Not initialized");
+        let mut capabilities = Capabilities::new(&identity.id, FRAME_VERSION);
+        capabilities.content_types = vec![ContentType::Raw, ContentType::Json, ContentType::Cbor, ContentType::Text];
+        capabilities.compression = Compression::supported();
+        capabilities.extensions = [
+            REPLY_TO_EXTENSION,
+            TRACE_EXTENSION,
+            REPUTATION_EXTENSION,
+            CREDENTIAL_EXTENSION,
+            PRESENTATION_REQUEST_EXTENSION,
+            PRESENTATION_EXTENSION,
+            CAPABILITY_EXTENSION,
+        ]
+        .map(String::from)
+        .to_vec();
+        #[cfg(feature = "chain")]
+        {
+            capabilities.content_types.push(ContentType::Reference);
+            capabilities.extensions.extend(
+                [OFFLOADED_EXTENSION, BALANCE_EXTENSION, ESCROW_RELEASE_EXTENSION, RECEIPT_EXTENSION, NOTARIZED_RECEIPT_EXTENSION].map(String::from),
+            );
+        }
+        capabilities.channels = self.channels.keys().cloned().collect();
+        capabilities.channels.sort();
+        capabilities.services = self.services.clone();
+        capabilities.prekeys = self.prekeys.is_some();
+        capabilities
+    }
+    
+    /// Advertise an application service and the schema version it speaks
+    /// 
+    /// Takes effect with the next `publish_capabilities` or connect.
+    pub fn advertise_service(&mut self, name: &str, version: &str) {
+        self.services.insert(name.to_string(), version.to_string());
+    }
+    
+    /// Publish this agent's capabilities to the relay
+    /// 
+    /// Done on connect; call again after offering channels, advertising
+    /// services or publishing prekeys.
+    pub async fn publish_capabilities(&mut self) -> anyhow::Result<()> {
+        self.send_capabilities("relay").await
+    }
+    
+    /// Send this agent's capabilities straight to a peer
+    pub async fn send_capabilities(&mut self, to: &str) -> anyhow::Result<()> {
+        let capabilities = self.local_capabilities();
+        self.send_json_frame(FrameType::Capabilities, to, &capabilities).await?;
+        debug!("Sent capabilities to {}", to);
+        Ok(())
+    }
+    
+    /// Capabilities last received from an agent or looked up for it
+    pub fn peer_capabilities(&self, agent_id: &str) -> Option<&Capabilities> {
+        self.peer_capabilities.get(agent_id)
+    }
+    
+    /// Look up an agent's capabilities at the relay
+    /// 
+    /// Waits up to `PING_TIMEOUT` for the answer, keeping frames received
+    /// meanwhile for `recv`. The result is also returned by
+    /// `peer_capabilities` afterwards.
+    /// 
+    /// # Returns
+    /// `None` if the agent published no capabilities
+    pub async fn fetch_capabilities(&mut self, agent_id: &str) -> anyhow::Result<Option<Capabilities>> {
+        let query = CapabilityQuery { fetch: agent_id.to_string() };
+        let answer = self.ask("relay", FrameType::Capabilities, &query).await
+            .map_err(|e| anyhow::anyhow!("Capability lookup for {} failed: {}", agent_id, e))?;
+        Ok(answer.capabilities())
+    }
+    
+    /// Sign and publish this agent's profile to the relay
+    /// 
+    /// Agents watching this one receive it as a `Profile` frame. It is also
+    /// stored with the next `register_agent`.
+    pub async fn publish_profile(&mut self, profile: Profile) -> anyhow::Result<SignedProfile> {
+        let identity = self.identity.as_ref().expect("Not initialized");
+        // Later profiles must be strictly newer to replace this one
+        let updated_at = match &self.profile {
+            Some(previous) => self.clock.now_ms().max(previous.updated_at + 1),
+            None => self.clock.now_ms(),
+        };
+        let signed = SignedProfile::sign(identity, profile, updated_at);
+        self.send_json_frame(FrameType::Profile, "relay", &signed).await?;
+        debug!("Published profile at {}", updated_at);
+        self.profile = Some(signed.clone());
+        Ok(signed)
+    }
+    
+    /// Profile last published by this agent
+    pub fn local_profile(&self) -> Option<&SignedProfile> {
+        self.profile.as_ref()
+    }
+    
+    /// Look up an agent's profile at the relay
+    /// 
+    /// Waits up to `PING_TIMEOUT` for the answer, keeping frames received
+    /// meanwhile for `recv`. Only profiles signed by the agent are returned.
+    /// 
+    /// # Returns
+    /// `None` if the agent published no profile
+    pub async fn get_profile(&mut self, agent_id: &str) -> anyhow::Result<Option<SignedProfile>> {
+        let query = ProfileQuery { fetch: agent_id.to_string(), watch: None };
+        let answer = self.ask("relay", FrameType::Profile, &query).await
+            .map_err(|e| anyhow::anyhow!("Profile lookup for {} failed: {}", agent_id, e))?;
+        Ok(answer.profile().filter(|p| p.agent_id == agent_id))
+    }
+    
+    /// Profile last received for an agent
+    pub fn peer_profile(&self, agent_id: &str) -> Option<&SignedProfile> {
+        self.peer_profiles.get(agent_id)
+    }
+    
+    /// Get notified when an agent publishes a new profile
+    /// 
+    /// The relay answers with the current profile, then forwards each newer
+    /// one; both arrive through `recv` as `Profile` frames (see
+    /// [`OpacusFrame::profile`]) and update `peer_profile`.
+    pub async fn watch_profile(&mut self, agent_id: &str) -> anyhow::Result<()> {
+        let query = ProfileQuery { fetch: agent_id.to_string(), watch: Some(true) };
+        self.send_json_frame(FrameType::Profile, "relay", &query).await?;
+        Ok(())
+    }
+    
+    /// Stop notifications of an agent's new profiles
+    /// 
+    /// The relay does not answer.
+    pub async fn unwatch_profile(&mut self, agent_id: &str) -> anyhow::Result<()> {
+        let query = ProfileQuery { fetch: agent_id.to_string(), watch: Some(false) };
+        self.send_json_frame(FrameType::Profile, "relay", &query).await?;
+        Ok(())
+    }
+    
+    /// Sign a record of this agent's keys and relay, and publish it to the DHT
+    /// 
+    /// Other agents find this agent through `lookup_agent` until the record
+    /// expires after `ttl`; publish again before then to stay reachable.
+    pub async fn publish_record(&mut self, ttl: Duration) -> anyhow::Result<DhtAgentRecord> {
+        let identity = self.identity.as_ref().expect("Not initialized");
+        let now = self.clock.now_ms();
+        // Later records must be strictly newer to replace this one
+        let seq = match &self.record {
+            Some(previous) => now.max(previous.seq + 1),
+            None => now,
+        };
+        let record = DhtAgentRecord::sign(identity, &self.config.relay_url, seq, now + ttl.as_millis() as u64);
+        let rpc = DhtRpc::from_agent(DhtMessage::Store { record: record.clone() });
+        let answer = self.ask("relay", FrameType::Dht, &rpc).await
+            .map_err(|e| anyhow::anyhow!("Publishing record failed: {}", e))?;
+        match answer.dht_rpc().map(|rpc| rpc.message) {
+            Some(DhtMessage::Stored) => {}
+            Some(DhtMessage::Refused { reason }) => anyhow::bail!("Relay refused record: {}", reason),
+            _ => anyhow::bail!("Invalid answer to record"),
+        }
+        debug!("Published record {}", seq);
+        self.record = Some(record.clone());
+        Ok(record)
+    }
+    
+    /// Look up an agent's record in the DHT
+    /// 
+    /// Waits up to `PING_TIMEOUT` for the answer, keeping frames received
+    /// meanwhile for `recv`. Only unexpired records signed by the agent are
+    /// returned.
+    /// 
+    /// # Returns
+    /// `None` if no record of the agent was found
+    pub async fn lookup_agent(&mut self, agent_id: &str) -> anyhow::Result<Option<DhtAgentRecord>> {
+        let rpc = DhtRpc::from_agent(DhtMessage::FindValue { key: DhtKey::of_agent(agent_id) });
+        let answer = self.ask("relay", FrameType::Dht, &rpc).await
+            .map_err(|e| anyhow::anyhow!("Record lookup for {} failed: {}", agent_id, e))?;
+        let Some(DhtMessage::Value { record }) = answer.dht_rpc().map(|rpc| rpc.message) else {
+            return Ok(None);
+        };
+        if record.agent_id != agent_id {
+            anyhow::bail!("Relay answered with the record of {}", record.agent_id);
+        }
+        record.verify(self.clock.now_ms()).map_err(|e| anyhow::anyhow!(e))?;
+        Ok(Some(record))
+    }
+    
+    /// Keep a verified profile unless a newer one is known
+    fn remember_profile(&mut self, profile: SignedProfile) {
+        if self.peer_profiles.get(&profile.agent_id).is_none_or(|p| p.updated_at < profile.updated_at) {
+            debug!("Stored profile of {}", profile.agent_id);
+            self.peer_profiles.insert(profile.agent_id.clone(), profile);
+        }
+    }
+    
+    /// Send a frame with a JSON payload
+    /// 
+    /// # Returns
+    /// ID of the frame
+    async fn send_json_frame<T: Serialize>(&mut self, frame_type: FrameType, to: &str, value: &T) -> anyhow::Result<Option<Ulid>> {
+        let frame = self.json_frame(frame_type, to, value).await?;
+        let id = frame.id;
+        self.dispatch(frame).await?;
+        Ok(id)
+    }
+    
+    async fn json_frame<T: Serialize>(&mut self, frame_type: FrameType, to: &str, value: &T) -> anyhow::Result<OpacusFrame> {
+        let identity = self.identity.as_ref().expect("Not initialized");
+        let relay_x_pub = self.relay_x_pub.unwrap_or([0u8; 32]);
+        
+        Ok(self.security.write().await.create_auth_frame_with(
+            identity,
+            &relay_x_pub,
+            frame_type,
+            to,
+            serde_json::to_vec(value)?,
+            FrameOptions { content_type: ContentType::Json, ..Default::default() },
+        ))
+    }
+    
+    /// Send a query to the relay (or a peer) and wait up to `PING_TIMEOUT`
+    /// for its answer
+    /// 
+    /// Frames received meanwhile are kept for `recv`.
+    async fn ask<T: Serialize>(&mut self, to: &str, frame_type: FrameType, query: &T) -> anyhow::Result<OpacusFrame> {
+        let Some(query_id) = self.send_json_frame(frame_type, to, query).await? else {
+            anyhow::bail!("Query has no message ID");
+        };
+        let answer = tokio::time::timeout(PING_TIMEOUT, async {
+            loop {
+                let Some(frame) = self.next_frame().await else {
+                    anyhow::bail!("Connection closed");
+                };
+                if self.answer_ping(&frame).await.is_some() {
+                    continue;
+                }
+                if frame.frame_type == frame_type && frame.from == to && frame.reply_to() == Some(query_id) {
+                    return Ok(frame);
+                }
+                if let Some(error) = frame.error_payload().filter(|e| e.related_id == Some(query_id)) {
+                    return Err(OpacusError::from(error).into());
+                }
+                self.held.push_back(frame);
+            }
+        })
+        .await;
+        answer.map_err(|_| anyhow::anyhow!("{} did not answer in time", to))?
+    }
+    
+    /// Receive next frame (blocking)
+    /// 
+    /// Frames whose message ID was already delivered are dropped, `Batch`
+    /// frames are unpacked into their entries, and pings from peers are
+    /// answered instead of returned.
+    pub async fn recv(&mut self) -> Option<OpacusFrame> {
+        let frame = match self.held.pop_front() {
+            Some(frame) => frame,
+            None => loop {
+                let frame = self.next_frame().await?;
+                if self.answer_ping(&frame).await.is_none() {
+                    break frame;
+                }
+            },
+        };
+        self.log_lifecycle(|| LifecycleEvent::new(FrameStage::Delivered, &frame));
+        Some(frame)
+    }
+    
+    /// Measure the round trip to an agent through the relay
+    /// 
+    /// Sends a `Ping` frame, which the agent's client answers from `recv`
+    /// (use `"relay"` to ping the relay itself), and waits up to
+    /// `PING_TIMEOUT` for the reply. Frames received meanwhile are kept for
+    /// `recv`. The round trip is added to the agent's histogram in `latency`.
+    pub async fn ping(&mut self, agent_id: &str) -> anyhow::Result<Duration> {
+        self.ping_with_timeout(agent_id, PING_TIMEOUT).await
+    }
+    
+    /// Measure the round trip to an agent, waiting up to `timeout` for the reply
+    pub async fn ping_with_timeout(&mut self, agent_id: &str, timeout: Duration) -> anyhow::Result<Duration> {
+        let nonce = self.random.next_u64();
+        let sent = Instant::now();
+        let ping_id = self.send_ping(agent_id, PingPayload { nonce, reply: false }).await?;
+        let reply = tokio::time::timeout(timeout, async {
+            loop {
+                let Some(frame) = self.next_frame().await else {
+                    anyhow::bail!("Connection closed");
+                };
+                match self.answer_ping(&frame).await {
+                    Some(pong) if pong.reply && pong.nonce == nonce && frame.from == agent_id => return Ok(sent.elapsed()),
+                    Some(_) => continue,
+                    None => {}
+                }
+                if let Some(error) = frame.error_payload().filter(|e| ping_id.is_some() && e.related_id == ping_id) {
+                    return Err(OpacusError::from(error).into());
+                }
+                self.held.push_back(frame);
+            }
+        })
+        .await;
+        let rtt = reply.map_err(|_| anyhow::anyhow!("Ping to {} timed out", agent_id))??;
+        if let Some(id) = ping_id {
+            self.log_lifecycle(|| LifecycleEvent::acked(id, agent_id));
+        }
+        self.latency.record_rtt(agent_id, rtt);
+        Ok(rtt)
+    }
+    
+    /// Round-trip and one-way latency measured so far, per peer
+    pub fn latency(&self) -> &LatencyTracker {
+        &self.latency
+    }
+    
+    /// Send a `Ping` frame
+    /// 
+    /// # Returns
+    /// ID of the frame
+    async fn send_ping(&mut self, to: &str, ping: PingPayload) -> anyhow::Result<Option<Ulid>> {
+        let identity = self.identity.as_ref().expect("Not initialized");
+        let relay_x_pub = self.relay_x_pub.unwrap_or([0u8; 32]);
+        
+        let frame = self.security.write().await.create_auth_frame_with(
+            identity,
+            &relay_x_pub,
+            FrameType::Ping,
+            to,
+            serde_json::to_vec(&ping)?,
+            FrameOptions { content_type: ContentType::Json, ..Default::default() },
+        );
+        let id = frame.id;
+        self.dispatch(frame).await?;
+        Ok(id)
+    }
+    
+    /// Answer a ping from a peer
+    /// 
+    /// # Returns
+    /// The ping, or `None` if the frame is not one
+    async fn answer_ping(&mut self, frame: &OpacusFrame) -> Option<PingPayload> {
+        if frame.frame_type != FrameType::Ping {
+            return None;
+        }
+        let ping: PingPayload = frame.payload_as().ok()?;
+        if !ping.reply {
+            if let Err(e) = self.send_ping(&frame.from, PingPayload { reply: true, ..ping }).await {
+                warn!("Failed to answer ping from {}: {}", frame.from, e);
+            }
+        }
+        Some(ping)
+    }
+    
+    /// Receive the next frame from the relay and apply it to the client state
+    async fn next_frame(&mut self) -> Option<OpacusFrame> {
+        let frame = loop {
+            let mut frame = match self.inbox.pop_front() {
+                Some(frame) => frame,
+                None => {
+                    let frame = self.transport.as_mut()?.recv().await?;
+                    if let Some(capture) = &self.capture {
+                        capture.record(CaptureDirection::In, &frame);
+                    }
+                    frame
+                }
+            };
+            if frame.frame_type == FrameType::Cover {
+                continue;
+            }
+            frame.strip_padding();
+            if frame.frame_type == FrameType::Batch {
+                match frame.batch_entries() {
+                    Ok(entries) => self.inbox.extend(entries),
+                    Err(e) => warn!("Invalid batch from {}: {}", frame.from, e),
+                }
+                continue;
+            }
+            if frame.is_sealed() {
+                let identity = self.identity.as_ref()?;
+                match frame.unseal(identity) {
+                    Ok((inner, x_pub)) => {
+                        self.sealing_keys.insert(inner.from.clone(), x_pub);
+                        self.inbox.push_front(inner);
+                    }
+                    Err(e) => warn!("Dropped sealed frame {:?}: {}", frame.id, e),
+                }
+                continue;
+            }
+            #[cfg(feature = "chain")]
+            if let Err(e) = self.check_registered_sender(&frame).await {
+                warn!("Dropped frame {:?} from {}: {}", frame.id, frame.from, e);
+                continue;
+            }
+            match frame.id {
+                Some(id) if !self.remember_id(id) => debug!("Dropped duplicate message {}", id),
+                _ => break frame,
+            }
+        };
+        let span = debug_span!(
+            "opacus.recv",
+            frame_id = ?frame.id,
+            frame_type = ?frame.frame_type,
+            from = %frame.from,
+            trace_id = field::Empty,
+        );
+        trace::link_span(&span, &frame);
+        self.receive(frame).instrument(span).await
+    }
+    
+    /// Apply a received frame to the client state
+    async fn receive(&mut self, frame: OpacusFrame) -> Option<OpacusFrame> {
+        // Handle the relay's ACK of our Connect to get its keys; peers cannot forge one
+        if frame.frame_type == FrameType::Ack && frame.from == "relay" && self.pending_connect.is_some() {
+            let payload = serde_json::from_slice::<serde_json::Value>(&frame.payload).ok()
+                .filter(|payload| serde_json::from_value::<Ulid>(payload["ackFor"].clone()).ok() == self.pending_connect);
+            if let Some(payload) = payload {
+                self.pending_connect = None;
+                if let Ok(offered) = serde_json::from_value::<Vec<Compression>>(payload["compression"].clone()) {
+                    self.compression = Compression::negotiate(&offered);
+                    debug!("Negotiated compression: {:?}", self.compression);
+                }
+                if let Some(key) = payload["onionKey"].as_str().and_then(|hex| KeyManager::from_hex(hex).ok()) {
+                    self.relay_onion_key = key.try_into().ok();
+                    debug!("Stored relay onion key");
+                }
+                if let Some(relay_x_pub_hex) = payload["relayXPub"].as_str() {
+                    if let Ok(bytes) = KeyManager::from_hex(relay_x_pub_hex) {
+                        if let Ok(arr) = bytes.try_into() {
+                            self.relay_x_pub = Some(arr);
+                            debug!("Stored relay public key");
+                        }
+                    }
+                }
+            }
+        }
+        
+        if frame.frame_type == FrameType::Ack {
+            if let Some(id) = frame.payload_as::<serde_json::Value>().ok().and_then(|p| serde_json::from_value::<Ulid>(p["ackFor"].clone()).ok()) {
+                self.log_lifecycle(|| LifecycleEvent::acked(id, &frame.from));
+            }
+        }
+        // Peers switch to a new session key once this agent has it
+        if let Some(epoch) = SecurityManager::rekey_ack(&frame) {
+            if self.security.write().await.confirm_rekey(&frame.from, epoch) {
+                debug!("Rekeyed session with {} (epoch {})", frame.from, epoch);
+            }
+        }
+        if frame.frame_type == FrameType::Rekey && frame.payload[..] == frame.key_epoch.to_be_bytes() {
+            let ack = {
+                let identity = self.identity.as_ref()?;
+                let relay_x_pub = self.relay_x_pub.unwrap_or([0u8; 32]);
+                self.security.write().await.create_rekey_ack(identity, &relay_x_pub, &frame.from, frame.key_epoch)
+            };
+            if let Err(e) = self.dispatch(ack).await {
+                warn!("Failed to acknowledge rekey from {}: {}", frame.from, e);
+            }
+        }
+        if let Some(capabilities) = frame.capabilities() {
+            // Peers only speak for themselves; the relay answers lookups
+            if capabilities.agent_id == frame.from || frame.from == "relay" {
+                debug!("Stored capabilities of {}", capabilities.agent_id);
+                self.peer_capabilities.insert(capabilities.agent_id.clone(), capabilities);
+            }
+        }
+        // Signed by the agent, so trusted whoever relayed it
+        if let Some(profile) = frame.profile() {
+            self.remember_profile(profile);
+        }
+        if let Some(bundle) = Self::parse_prekey_bundle(&frame).filter(|b| b.verify().is_ok()) {
+            self.sealing_keys.insert(bundle.agent_id, bundle.x_pub);
+        }
+        if let Some(error) = frame.error_payload() {
+            warn!("{} rejected {:?}: {:?} {}", frame.from, error.related_id, error.code, error.message);
+            if let Some(id) = error.related_id {
+                self.log_lifecycle(|| LifecycleEvent::rejected(id, &frame.from, &error));
+            }
+        }
+        #[cfg(feature = "chain")]
+        if let Some(id) = Self::receipt_message_id(&frame) {
+            self.log_lifecycle(|| LifecycleEvent::acked(id, &frame.from));
+        }
+        self.meter.record_frame(&frame, &frame.from);
+        if frame.ts > 0 {
+            self.latency.record_one_way(&frame.from, frame.ts, self.clock.now_ms());
+        }
+        #[cfg(feature = "chain")]
+        self.record_anchored(&frame);
+        #[cfg(feature = "chain")]
+        let frame = self.resolve_offloaded(frame).await;
+        
+        Some(frame)
+    }
+    
+    /// Receive next frame, mapping `Error` frames to `OpacusError`
+    pub async fn recv_checked(&mut self) -> Option<Result<OpacusFrame, OpacusError>> {
+        let frame = self.recv().await?;
+        Some(match frame.as_error() {
+            Some(error) => Err(error),
+            None => Ok(frame),
+        })
+    }
+    
+    /// Record a delivered message ID
+    /// 
+    /// # Returns
+    /// `false` if the ID was seen recently
+    fn remember_id(&mut self, id: Ulid) -> bool {
+        if !self.seen_ids.insert(id) {
+            return false;
+        }
+        self.seen_order.push_back(id);
+        if self.seen_order.len() > DEDUP_CAPACITY {
+            if let Some(oldest) = self.seen_order.pop_front() {
+                self.seen_ids.remove(&oldest);
+            }
+        }
+        true
+    }
+    
+    /// Meter `Stream` frames on a data channel at its prices
+    pub fn set_channel_pricing(&self, channel: &DataChannel) {
+        self.meter.set_pricing(channel);
+    }
+    
+    /// Offer a data channel to subscribers
+    /// 
+    /// The channel is metered at its prices, and `on_subscribe` accepts
+    /// agents that meet its access rule.
+    pub fn offer_channel(&mut self, channel: DataChannel) {
+        self.meter.set_pricing(&channel);
+        self.channels.insert(channel.id.clone(), channel);
+    }
+    
+    /// Ask a publisher to subscribe this agent to one of its channels
+    /// 
+    /// For token-gated channels the chain key signs a holding proof, so it
+    /// must control the tokens or allowlist entry the channel requires. Paid
+    /// channels are authorized for one period; see `subscribe_for`.
+    pub async fn subscribe(&mut self, publisher: &str, channel: &DataChannel) -> anyhow::Result<()> {
+        self.subscribe_for(publisher, channel, 1).await
+    }
+    
+    /// Subscribe to a channel, authorizing payment for several periods
+    /// 
+    /// The chain key signs a streaming authorization for the channel's plan
+    /// in the payments contract's domain, starting now; the publisher
+    /// collects each period and unsubscribes this agent once it runs out.
+    /// Free channels ignore `periods`.
+    /// 
+    /// # Arguments
+    /// * `publisher` - Publisher agent ID
+    /// * `channel` - Channel to subscribe to
+    /// * `periods` - Number of periods to pay for
+    pub async fn subscribe_for(&mut self, publisher: &str, channel: &DataChannel, periods: u64) -> anyhow::Result<()> {
+        self.request_subscription(publisher, channel, periods, None).await
+    }
+    
+    /// Subscribe to a channel and catch up on its retained history
+    /// 
+    /// The publisher replays the data it retained that was published after
+    /// `since` (milliseconds; `0` for all) before streaming new data.
+    pub async fn subscribe_since(&mut self, publisher: &str, channel: &DataChannel, since: u64) -> anyhow::Result<()> {
+        self.request_subscription(publisher, channel, 1, Some(since)).await
+    }
+    
+    async fn request_subscription(&mut self, publisher: &str, channel: &DataChannel, periods: u64, since: Option<u64>) -> anyhow::Result<()> {
+        let proof = match &channel.access {
+            None => None,
+            #[cfg(feature = "chain")]
+            Some(rule) => {
+                let chain = self.chain()?;
+                let agent_id = &self.identity.as_ref().expect("Not initialized").id;
+                let now = self.clock.now_ms() / 1000;
+                Some(HoldingProof::sign(&rule.domain(chain.chain_id())?, &channel.id, agent_id, now, chain.signer()?)?)
+            }
+            #[cfg(not(feature = "chain"))]
+            Some(_) => anyhow::bail!("Channel {} is token-gated; subscribing needs the `chain` feature", channel.id),
+        };
+        let authorization = match &channel.plan {
+            None => None,
+            #[cfg(feature = "chain")]
+            Some(plan) => {
+                let chain = self.chain()?;
+                let domain = payment_domain(chain.chain_id(), self.payments_contract()?);
+                let now = self.clock.now_ms() / 1000;
+                Some(StreamingAuthorization::sign(&domain, &channel.id, plan, now, periods, chain.signer()?)?)
+            }
+            #[cfg(not(feature = "chain"))]
+            Some(_) => anyhow::bail!("Channel {} is paid; subscribing for {} periods needs the `chain` feature", channel.id, periods),
+        };
+        let request = SubscribeRequest { channel_id: channel.id.clone(), proof, authorization, since };
+        self.send_json_frame(FrameType::Subscribe, publisher, &request).await?;
+        debug!("Subscribing to {} of {}", channel.id, publisher);
+        Ok(())
+    }
+    
+    /// Subscribe to every open channel of a publisher that matches a filter
+    /// 
+    /// Covers channels the publisher offers later too. Gated and paid
+    /// channels need a subscription of their own.
+    pub async fn subscribe_topics(&mut self, publisher: &str, filter: &TopicFilter) -> anyhow::Result<()> {
+        self.request_topics(publisher, filter, None).await
+    }
+    
+    /// Subscribe to the open channels matching a filter and catch up on their
+    /// retained history
+    pub async fn subscribe_topics_since(&mut self, publisher: &str, filter: &TopicFilter, since: u64) -> anyhow::Result<()> {
+        self.request_topics(publisher, filter, Some(since)).await
+    }
+    
+    async fn request_topics(&mut self, publisher: &str, filter: &TopicFilter, since: Option<u64>) -> anyhow::Result<()> {
+        let request = SubscribeRequest { channel_id: filter.to_string(), proof: None, authorization: None, since };
+        self.send_json_frame(FrameType::Subscribe, publisher, &request).await?;
+        debug!("Subscribing to {} of {}", filter, publisher);
+        Ok(())
+    }
+    
+    /// Accept or reject a received `Subscribe` frame
+    /// 
+    /// Gated channels need a fresh holding proof whose account meets the
+    /// channel's rule on chain; paid channels need a streaming authorization
+    /// covering the plan that is active now. A [`TopicFilter`] with wildcards
+    /// in place of the channel ID subscribes to the matching open channels.
+    /// Accepted subscribers asking to catch up get the retained history as
+    /// `Stream` frames. Rejected subscribers get an `Unauthorized` error frame.
+    /// 
+    /// # Returns
+    /// ID of the channel (or filter) subscribed to
+    pub async fn on_subscribe(&mut self, frame: &OpacusFrame) -> anyhow::Result<String> {
+        let request = frame
+            .subscribe_request()
+            .ok_or_else(|| anyhow::anyhow!("Expected subscribe frame, got {:?}", frame.frame_type))?;
+        let wildcard = TopicFilter::new(&request.channel_id).ok().filter(TopicFilter::is_wildcard);
+        let checked = match self.channels.get(&request.channel_id).cloned() {
+            None if wildcard.is_some() => Ok(()),
+            None => Err(anyhow::anyhow!("Unknown channel {}", request.channel_id)),
+            Some(DataChannel { access: None, plan: None, .. }) => Ok(()),
+            #[cfg(feature = "chain")]
+            Some(channel) => self.check_subscriber(&channel, &request, &frame.from).await,
+            #[cfg(not(feature = "chain"))]
+            Some(channel) => Err(anyhow::anyhow!("Channel {} is gated or paid; checking subscribers needs the `chain` feature", channel.id)),
+        };
+        if let Err(e) = checked {
+            warn!("Rejected subscription of {} to {}: {}", frame.from, request.channel_id, e);
+            let error = ErrorPayload::new(ErrorCode::Unauthorized, e.to_string()).related_to(frame.id);
+            self.send_error(&frame.from, &error).await?;
+            return Err(e);
+        }
+        
+        info!("{} subscribed to {}", frame.from, request.channel_id);
+        match &wildcard {
+            Some(filter) => self.topic_subscribers.entry(filter.clone()).or_default().insert(frame.from.clone()),
+            None => self.subscribers.entry(request.channel_id.clone()).or_default().insert(frame.from.clone()),
+        };
+        if let Some(since) = request.since {
+            let channels: Vec<String> = match &wildcard {
+                Some(filter) => self.histories.keys().filter(|id| filter.matches(id)).cloned().collect(),
+                None => vec![request.channel_id.clone()],
+            };
+            for channel_id in channels {
+                if self.check_history_access(&channel_id, &frame.from).is_ok() {
+                    self.replay_history(&channel_id, &frame.from, since).await?;
+                }
+            }
+        }
+        Ok(request.channel_id)
+    }
+    
+    /// Retain the last `limit` data published on an offered channel
+    /// 
+    /// Subscribers catch up on it with `subscribe_since` or
+    /// `fetch_history`. A limit of `0` stops retaining and drops the history.
+    pub fn retain_history(&mut self, channel_id: &str, limit: usize) {
+        if limit == 0 {
+            self.histories.remove(channel_id);
+            return;
+        }
+        let mut history = ChannelHistory::new(limit);
+        if let Some(previous) = self.histories.remove(channel_id) {
+            for entry in previous.since(0) {
+                history.push(entry.published_at, entry.data);
+            }
+        }
+        self.histories.insert(channel_id.to_string(), history);
+    }
+    
+    /// Retained history of an offered channel
+    pub fn channel_history(&self, channel_id: &str) -> Option<&ChannelHistory> {
+        self.histories.get(channel_id)
+    }
+    
+    /// Fetch the data a publisher retained for one of its channels
+    /// 
+    /// Waits up to `PING_TIMEOUT` for the answer, keeping frames received
+    /// meanwhile for `recv`.
+    /// 
+    /// # Arguments
+    /// * `publisher` - Publisher agent ID
+    /// * `channel_id` - Channel offered by the publisher
+    /// * `since` - Only data published after this time (milliseconds; `0` for all)
+    pub async fn fetch_history(&mut self, publisher: &str, channel_id: &str, since: u64) -> anyhow::Result<Vec<HistoryEntry>> {
+        let query = HistoryQuery { channel_id: channel_id.to_string(), since };
+        let answer = self.ask(publisher, FrameType::History, &query).await
+            .map_err(|e| anyhow::anyhow!("History of {} from {} failed: {}", channel_id, publisher, e))?;
+        let page = answer.history_page().ok_or_else(|| anyhow::anyhow!("Invalid history from {}", publisher))?;
+        Ok(page.entries)
+    }
+    
+    /// Answer a received `History` frame
+    /// 
+    /// History of gated and paid channels is only sent to their subscribers;
+    /// other agents get an `Unauthorized` error frame.
+    /// 
+    /// # Returns
+    /// Number of entries sent
+    pub async fn on_history_query(&mut self, frame: &OpacusFrame) -> anyhow::Result<usize> {
+        let query = frame
+            .history_query()
+            .ok_or_else(|| anyhow::anyhow!("Expected history query, got {:?}", frame.frame_type))?;
+        let query_id = frame.id.ok_or_else(|| anyhow::anyhow!("History query from {} has no message ID", frame.from))?;
+        if let Err(e) = self.check_history_access(&query.channel_id, &frame.from) {
+            warn!("Refused history of {} to {}: {}", query.channel_id, frame.from, e);
+            let error = ErrorPayload::new(ErrorCode::Unauthorized, e.to_string()).related_to(frame.id);
+            self.send_error(&frame.from, &error).await?;
+            return Err(e);
+        }
+        
+        let entries = self.histories.get(&query.channel_id).map(|h| h.since(query.since)).unwrap_or_default();
+        let count = entries.len();
+        let page = HistoryPage { channel_id: query.channel_id, entries };
+        let mut reply = self.json_frame(FrameType::History, &frame.from, &page).await?;
+        reply.set_reply_to(query_id);
+        self.dispatch(reply).await?;
+        debug!("Sent {} history entries of {} to {}", count, page.channel_id, frame.from);
+        Ok(count)
+    }
+    
+    /// Whether an agent may read the history of an offered channel
+    fn check_history_access(&self, channel_id: &str, agent_id: &str) -> anyhow::Result<()> {
+        match self.channels.get(channel_id) {
+            None => Err(anyhow::anyhow!("Unknown channel {}", channel_id)),
+            Some(DataChannel { access: None, plan: None, .. }) => Ok(()),
+            Some(_) if self.subscribers.get(channel_id).is_some_and(|s| s.contains(agent_id)) => Ok(()),
+            Some(_) => Err(anyhow::anyhow!("{} is not subscribed to {}", agent_id, channel_id)),
+        }
+    }
+    
+    /// Stream the retained data published after `since` to one subscriber
+    async fn replay_history(&mut self, channel_id: &str, to: &str, since: u64) -> anyhow::Result<()> {
+        let entries = self.histories.get(channel_id).map(|h| h.since(since)).unwrap_or_default();
+        for entry in &entries {
+            let frame = self.stream_frame(channel_id, to, entry.data.clone()).await?;
+            self.dispatch(frame).await?;
+        }
+        debug!("Replayed {} entries of {} to {}", entries.len(), channel_id, to);
+        Ok(())
+    }
+    
+    #[cfg(feature = "chain")]
+    async fn check_subscriber(&mut self, channel: &DataChannel, request: &SubscribeRequest, agent_id: &str) -> anyhow::Result<()> {
+        let now = self.clock.now_ms() / 1000;
+        let account = self.chain()?.check_subscription(channel, request, agent_id, now).await?;
+        debug!("{} subscribes to {} with holdings of {:?}", agent_id, channel.id, account);
+        if channel.plan.is_some() {
+            let authorization = request.authorization.clone().ok_or_else(|| anyhow::anyhow!("Missing payment authorization"))?;
+            let domain = payment_domain(self.chain()?.chain_id(), self.payments_contract()?);
+            let payer = authorization.verify(&domain, channel, now)?;
+            debug!("{} pays for {} from {} until {}", agent_id, channel.id, payer, authorization.expires_at());
+            self.paid_subscriptions.insert((channel.id.clone(), agent_id.to_string()), authorization);
+        }
+        Ok(())
+    }
+    
+    /// Send data to every subscriber of an offered channel
+    /// 
+    /// Subscribers whose payment authorization has run out are dropped
+    /// first and get an `Unauthorized` error frame. The data is added to the
+    /// channel's history if it is retained.
+    /// 
+    /// # Returns
+    /// Number of subscribers sent to
+    pub async fn publish(&mut self, channel_id: &str, data: Vec<u8>) -> anyhow::Result<usize> {
+        #[cfg(feature = "chain")]
+        self.expire_subscriptions(channel_id).await?;
+        let subscribers = self.subscribers(channel_id);
+        for subscriber in &subscribers {
+            let frame = self.stream_frame(channel_id, subscriber, data.clone()).await?;
+            self.dispatch(frame).await?;
+        }
+        debug!("Published to {} subscribers of {}", subscribers.len(), channel_id);
+        let now = self.clock.now_ms();
+        if let Some(history) = self.histories.get_mut(channel_id) {
+            history.push(now, data);
+        }
+        Ok(subscribers.len())
+    }
+    
+    /// Publish data and retain it at the relay as the channel's last value
+    /// 
+    /// The relay hands the value to each later subscriber as soon as it
+    /// subscribes. Only open channels can retain values, since the relay
+    /// serves them to anyone subscribing.
+    /// 
+    /// # Returns
+    /// Number of subscribers sent to
+    pub async fn publish_retained(&mut self, channel_id: &str, data: Vec<u8>) -> anyhow::Result<usize> {
+        self.send_retained(channel_id, data.clone()).await?;
+        self.publish(channel_id, data).await
+    }
+    
+    /// Clear the value retained at the relay for a channel
+    pub async fn clear_retained(&mut self, channel_id: &str) -> anyhow::Result<()> {
+        self.send_retained(channel_id, Vec::new()).await
+    }
+    
+    async fn send_retained(&mut self, channel_id: &str, data: Vec<u8>) -> anyhow::Result<()> {
+        match self.channels.get(channel_id) {
+            None => anyhow::bail!("Unknown channel {}", channel_id),
+            Some(DataChannel { access: None, plan: None, .. }) => {}
+            Some(_) => anyhow::bail!("Channel {} is gated or paid; only open channels retain values", channel_id),
+        }
+        let mut frame = self.unmetered_stream_frame(channel_id, "relay", data).await?;
+        frame.set_retained();
+        self.dispatch(frame).await
+    }
+    
+    /// Open a shared document replicated with other agents
+    /// 
+    /// Asks the peers for the updates they have; pass received `Stream`
+    /// frames to `on_document_frame` to apply them. Reopening a document
+    /// keeps its state and replaces its peers.
+    pub async fn open_document(&mut self, doc_id: &str, peers: &[&str]) -> anyhow::Result<()> {
+        let agent_id = self.identity.as_ref().expect("Not initialized").id.clone();
+        self.documents.entry(doc_id.to_string()).or_insert_with(|| SharedDocument::new(doc_id, &agent_id));
+        self.document_peers.insert(doc_id.to_string(), peers.iter().map(ToString::to_string).collect());
+        self.sync_document(doc_id).await
+    }
+    
+    /// Open shared document
+    pub fn document(&self, doc_id: &str) -> Option<&SharedDocument> {
+        self.documents.get(doc_id)
+    }
+    
+    /// Edit a shared document and send the update to its peers
+    /// 
+    /// ```rust,no_run
+    /// # async fn vote(client: &mut opacus_sdk::OpacusClient) -> anyhow::Result<()> {
+    /// client.edit_document("plan", |doc| doc.increment("votes", 1)).await?;
+    /// # Ok(()) }
+    /// ```
+    pub async fn edit_document<F>(&mut self, doc_id: &str, edit: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(&mut SharedDocument) -> CrdtUpdate,
+    {
+        let document = self.documents.get_mut(doc_id).ok_or_else(|| anyhow::anyhow!("Document {} is not open", doc_id))?;
+        let update = edit(document);
+        let peers = self.document_peers.get(doc_id).cloned().unwrap_or_default();
+        self.send_document_message(doc_id, &peers, &CrdtMessage::Updates { updates: vec![update] }).await
+    }
+    
+    /// Ask a document's peers for the updates this agent is missing
+    pub async fn sync_document(&mut self, doc_id: &str) -> anyhow::Result<()> {
+        let document = self.documents.get(doc_id).ok_or_else(|| anyhow::anyhow!("Document {} is not open", doc_id))?;
+        let version = document.version().clone();
+        let peers = self.document_peers.get(doc_id).cloned().unwrap_or_default();
+        self.send_document_message(doc_id, &peers, &CrdtMessage::Sync { version }).await
+    }
+    
+    /// Apply a received `Stream` frame replicating a shared document
+    /// 
+    /// Updates wait until the updates they depend on are applied; on a gap
+    /// the sender is asked for what is missing. Sync requests are answered
+    /// with the updates the requester lacks. Peers are trusted to relay each
+    /// other's updates; frames from other agents are ignored.
+    /// 
+    /// # Returns
+    /// ID of the document, if the frame changed it
+    pub async fn on_document_frame(&mut self, frame: &OpacusFrame) -> anyhow::Result<Option<String>> {
+        let Some((channel_id, data)) = frame.stream_data() else {
+            return Ok(None);
+        };
+        let Some(doc_id) = channel_id.strip_prefix(DOCUMENT_CHANNEL_PREFIX) else {
+            return Ok(None);
+        };
+        let (Some(document), Some(peers)) = (self.documents.get_mut(doc_id), self.document_peers.get(doc_id)) else {
+            return Ok(None);
+        };
+        if !peers.contains(&frame.from) {
+            debug!("Ignored document {} frame from {}", doc_id, frame.from);
+            return Ok(None);
+        }
+        let sender = [frame.from.clone()];
+        match serde_json::from_slice(&data)? {
+            CrdtMessage::Updates { updates } => {
+                let applied: usize = updates.into_iter().map(|u| document.apply(u)).sum();
+                if document.has_gaps() {
+                    let version = document.version().clone();
+                    debug!("Document {} has gaps, syncing with {}", doc_id, frame.from);
+                    self.send_document_message(doc_id, &sender, &CrdtMessage::Sync { version }).await?;
+                }
+                Ok((applied > 0).then(|| doc_id.to_string()))
+            }
+            CrdtMessage::Sync { version } => {
+                let updates = document.updates_since(&version);
+                for chunk in updates.chunks(SYNC_CHUNK_UPDATES) {
+                    let message = CrdtMessage::Updates { updates: chunk.to_vec() };
+                    self.send_document_message(doc_id, &sender, &message).await?;
+                }
+                Ok(None)
+            }
+        }
+    }
+    
+    async fn send_document_message(&mut self, doc_id: &str, to: &[String], message: &CrdtMessage) -> anyhow::Result<()> {
+        let channel_id = format!("{}{}", DOCUMENT_CHANNEL_PREFIX, doc_id);
+        let data = serde_json::to_vec(message)?;
+        for peer in to {
+            let frame = self.stream_frame(&channel_id, peer, data.clone()).await?;
+            self.dispatch(frame).await?;
+        }
+        Ok(())
+    }
+    
+    /// Delegate a task to another agent
+    /// 
+    /// Sends a `Task` frame with a [`TaskRequest`]; the worker's answers
+    /// arrive as `Task` frames for `on_task`.
+    /// 
+    /// # Arguments
+    /// * `to` - Worker agent ID
+    /// * `kind` - What to do, e.g. a tool or service name
+    /// * `input` - Task input, sent as JSON
+    /// * `options` - Deadline and budget
+    /// 
+    /// # Returns
+    /// ID of the task
+    pub async fn delegate_task<T: Serialize>(
+        &mut self,
+        to: &str,
+        kind: &str,
+        input: &T,
+        options: TaskOptions,
+    ) -> anyhow::Result<Ulid> {
+        let now = self.clock.now_ms();
+        let request = TaskRequest {
+            task_id: OpacusFrame::new_id_with(now, self.random.as_ref()),
+            kind: kind.to_string(),
+            input: serde_json::to_value(input)?,
+            deadline: options.deadline.map(|deadline| now + deadline.as_millis() as u64),
+            budget: options.budget,
+        };
+        let task_id = request.task_id;
+        self.send_task_message(to, TaskMessage::Request(request)).await?;
+        debug!("Delegated task {} ({}) to {}", task_id, kind, to);
+        Ok(task_id)
+    }
+    
+    /// Handle a received `Task` frame
+    /// 
+    /// Tracks the task the frame is about. Requests already past their
+    /// deadline are refused with a failed result, and acceptances quoting more
+    /// than the budget are cancelled; both return `Err`, as do messages that
+    /// do not fit their task.
+    /// 
+    /// # Returns
+    /// The message, for the application to act on: a request is to be taken
+    /// on with `accept_task` or refused with `fail_task`
+    pub async fn on_task(&mut self, frame: &OpacusFrame) -> anyhow::Result<TaskMessage> {
+        let message = frame
+            .task_message()
+            .ok_or_else(|| anyhow::anyhow!("Expected task message, got {:?}", frame.frame_type))?;
+        let task = self.tasks.record(&frame.from, &message, true).map_err(anyhow::Error::msg)?;
+        let task_id = message.task_id();
+        let (deadline, budget) = (task.request.deadline, task.request.budget);
+        match &message {
+            TaskMessage::Request(_) if deadline.is_some_and(|deadline| self.clock.now_ms() >= deadline) => {
+                warn!("Refused task {} from {}: deadline passed", task_id, frame.from);
+                self.fail_task(task_id, "Deadline passed").await?;
+                anyhow::bail!("Task {} from {} is past its deadline", task_id, frame.from);
+            }
+            TaskMessage::Accept { price: Some(price), .. } if budget.is_some_and(|budget| *price > budget) => {
+                warn!("Cancelled task {}: {} asked {} over the budget", task_id, frame.from, price);
+                self.cancel_task(task_id, "Price above budget").await?;
+                anyhow::bail!("{} asked {} for task {}, above its budget", frame.from, price, task_id);
+            }
+            _ => {}
+        }
+        debug!("Task {} from {}: {:?}", task_id, frame.from, self.tasks.get(&task_id).map(|t| &t.status));
+        Ok(message)
+    }
+    
+    /// Take on an assigned task
+    /// 
+    /// # Arguments
+    /// * `task_id` - Task received with `on_task`
+    /// * `price` - What the delegator is charged, if anything
+    pub async fn accept_task(&mut self, task_id: Ulid, price: Option<u128>) -> anyhow::Result<()> {
+        self.update_task(TaskMessage::Accept { task_id, price }).await
+    }
+    
+    /// Report how far an accepted task got
+    pub async fn report_task_progress(&mut self, task_id: Ulid, percent: u8, note: &str) -> anyhow::Result<()> {
+        self.update_task(TaskMessage::Progress { task_id, percent, note: note.to_string() }).await
+    }
+    
+    /// Send the output of an assigned task
+    pub async fn complete_task<T: Serialize>(&mut self, task_id: Ulid, output: &T) -> anyhow::Result<()> {
+        let outcome = TaskOutcome::Completed(serde_json::to_value(output)?);
+        self.update_task(TaskMessage::Result { task_id, outcome }).await
+    }
+    
+    /// Report that an assigned task failed, or refuse it
+    pub async fn fail_task(&mut self, task_id: Ulid, error: &str) -> anyhow::Result<()> {
+        let outcome = TaskOutcome::Failed(error.to_string());
+        self.update_task(TaskMessage::Result { task_id, outcome }).await
+    }
+    
+    /// Withdraw a delegated task that has not finished
+    pub async fn cancel_task(&mut self, task_id: Ulid, reason: &str) -> anyhow::Result<()> {
+        self.update_task(TaskMessage::Cancel { task_id, reason: reason.to_string() }).await
+    }
+    
+    /// Cancel delegated tasks still unfinished at their deadline
+    /// 
+    /// # Returns
+    /// IDs of the cancelled tasks
+    pub async fn expire_tasks(&mut self) -> anyhow::Result<Vec<Ulid>> {
+        let overdue = self.tasks.overdue(self.clock.now_ms());
+        for task_id in &overdue {
+            info!("Task {} passed its deadline", task_id);
+            self.cancel_task(*task_id, "Deadline passed").await?;
+        }
+        Ok(overdue)
+    }
+    
+    /// Task delegated by or assigned to this agent
+    pub fn task(&self, task_id: &Ulid) -> Option<&TaskRecord> {
+        self.tasks.get(task_id)
+    }
+    
+    /// Tasks delegated by or assigned to this agent
+    pub fn tasks(&self) -> &TaskBook {
+        &self.tasks
+    }
+    
+    /// Pay the worker of a completed delegated task the price it quoted
+    /// 
+    /// Sends a payment with `send_payment`; each task is paid once.
+    #[cfg(feature = "chain")]
+    pub async fn pay_task(
+        &mut self,
+        task_id: Ulid,
+        payee: Address,
+        token: Address,
+        valid_for: std::time::Duration,
+    ) -> anyhow::Result<SignedPayment> {
+        let price = self.tasks.mark_paid(&task_id).map_err(anyhow::Error::msg)?;
+        let worker = self.tasks.get(&task_id).expect("Paid task").peer.clone();
+        self.send_payment(&worker, payee, token, price, valid_for).await
+    }
+    
+    /// Send a message about a known task to its peer
+    async fn update_task(&mut self, message: TaskMessage) -> anyhow::Result<()> {
+        let task_id = message.task_id();
+        let peer = self.tasks.get(&task_id).map(|t| t.peer.clone()).ok_or_else(|| anyhow::anyhow!("Unknown task {}", task_id))?;
+        self.send_task_message(&peer, message).await
+    }
+    
+    async fn send_task_message(&mut self, to: &str, message: TaskMessage) -> anyhow::Result<()> {
+        self.tasks.record(to, &message, false).map_err(anyhow::Error::msg)?;
+        self.send_json_frame(FrameType::Task, to, &message).await?;
+        Ok(())
+    }
+    
+    /// Unsubscribe agents whose authorization for a paid channel expired
+    #[cfg(feature = "chain")]
+    async fn expire_subscriptions(&mut self, channel_id: &str) -> anyhow::Result<()> {
+        let now = self.clock.now_ms() / 1000;
+        let expired: Vec<String> = self
+            .paid_subscriptions
+            .iter()
+            .filter(|((channel, _), authorization)| channel == channel_id && !authorization.is_active(now))
+            .map(|((_, agent_id), _)| agent_id.clone())
+            .collect();
+        for agent_id in expired {
+            info!("Subscription of {} to {} expired", agent_id, channel_id);
+            self.paid_subscriptions.remove(&(channel_id.to_string(), agent_id.clone()));
+            if let Some(subscribers) = self.subscribers.get_mut(channel_id) {
+                subscribers.remove(&agent_id);
+            }
+            let error = ErrorPayload::new(ErrorCode::Unauthorized, format!("Subscription to {} expired", channel_id));
+            self.send_error(&agent_id, &error).await?;
+        }
+        Ok(())
+    }
+    
+    /// Collect the current period of a paid subscription
+    /// 
+    /// # Arguments
+    /// * `channel_id` - Paid channel offered by this agent
+    /// * `subscriber` - Subscriber agent ID
+    #[cfg(feature = "chain")]
+    pub async fn collect_subscription(&mut self, channel_id: &str, subscriber: &str) -> anyhow::Result<TransactionReceipt> {
+        let chain = self.chain()?;
+        let contract = self.payments_contract()?;
+        let authorization = self
+            .paid_subscriptions
+            .get(&(channel_id.to_string(), subscriber.to_string()))
+            .ok_or_else(|| anyhow::anyhow!("{} has no paid subscription to {}", subscriber, channel_id))?;
+        let index = authorization
+            .period_at(self.clock.now_ms() / 1000)
+            .ok_or_else(|| anyhow::anyhow!("Subscription of {} to {} is not active", subscriber, channel_id))?;
+        Ok(chain.collect_subscription(contract, channel_id, authorization, index).await?)
+    }
+    
+    /// Agents subscribed to an offered channel
+    /// 
+    /// Includes agents with a matching wildcard subscription if the channel
+    /// is open.
+    pub fn subscribers(&self, channel_id: &str) -> Vec<String> {
+        let mut subscribers = self.subscribers.get(channel_id).cloned().unwrap_or_default();
+        if self.channels.get(channel_id).is_some_and(|c| c.access.is_none() && c.plan.is_none()) {
+            for (filter, agents) in &self.topic_subscribers {
+                if filter.matches(channel_id) {
+                    subscribers.extend(agents.iter().cloned());
+                }
+            }
+        }
+        subscribers.into_iter().collect()
+    }
+    
+    /// Metered usage of a data channel, sent and received
+    pub fn get_usage(&self, channel_id: &str) -> Usage {
+        self.meter.usage(channel_id)
+    }
+    
+    /// Issue a signed statement of what a consumer has received on a data channel
+    pub fn usage_statement(&self, channel_id: &str, consumer: &str) -> Option<UsageStatement> {
+        let identity = self.identity.as_ref()?;
+        Some(self.meter.statement(identity, channel_id, consumer, self.clock.now_ms()))
+    }
+    
+    /// Issue a usage statement also signed as EIP-712 typed data with the chain key
+    /// 
+    /// # Arguments
+    /// * `channel_id` - Metered data channel
+    /// * `consumer` - Agent the statement is for
+    /// * `domain` - Domain of the contract that will check the statement
+    #[cfg(feature = "chain")]
+    pub fn signed_usage_statement(
+        &mut self,
+        channel_id: &str,
+        consumer: &str,
+        domain: &Eip712Domain,
+    ) -> anyhow::Result<SignedUsageStatement> {
+        let statement = self.usage_statement(channel_id, consumer).ok_or_else(|| anyhow::anyhow!("Not initialized"))?;
+        Ok(SignedUsageStatement::sign(statement, domain, self.chain()?.signer()?)?)
+    }
+    
+    /// Sign a receipt for a received frame with the chain key
+    /// 
+    /// # Arguments
+    /// * `frame` - Delivered frame
+    /// * `domain` - Domain of the contract that will check the receipt
+    #[cfg(feature = "chain")]
+    pub fn delivery_receipt(&mut self, frame: &OpacusFrame, domain: &Eip712Domain) -> anyhow::Result<SignedDeliveryReceipt> {
+        let receipt = DeliveryReceipt::for_frame(frame, self.clock.now_ms())?;
+        Ok(SignedDeliveryReceipt::sign(receipt, domain, self.chain()?.signer()?)?)
+    }
+    
+    /// Use notarized delivery receipts
+    /// 
+    /// # Arguments
+    /// * `contract` - Anchor contract receipts are signed for and anchored in
+    /// * `relay` - Chain account of the relay's `ReceiptNotary`
+    #[cfg(feature = "chain")]
+    pub fn set_receipt_notary(&mut self, contract: Address, relay: Address) {
+        self.receipt_notary = Some((contract, relay));
+    }
+    
+    #[cfg(feature = "chain")]
+    fn receipt_notary(&mut self) -> anyhow::Result<(Eip712Domain, Address)> {
+        let (contract, relay) = self.receipt_notary.ok_or_else(|| anyhow::anyhow!("No receipt notary set"))?;
+        Ok((notary_domain(self.chain()?.chain_id(), contract), relay))
+    }
+    
+    /// Acknowledge a received frame with a receipt for the relay to notarize
+    /// 
+    /// Signs a receipt in the notary domain and sends it to the frame's
+    /// sender in the `deliveryReceipt` extension. A relay with a notary
+    /// countersigns it on the way.
+    #[cfg(feature = "chain")]
+    pub async fn send_delivery_receipt(&mut self, frame: &OpacusFrame) -> anyhow::Result<SignedDeliveryReceipt> {
+        let (domain, _) = self.receipt_notary()?;
+        let receipt = self.delivery_receipt(frame, &domain)?;
+        
+        let mut ack = self.message_frame(&frame.from, Vec::new(), false, FrameOptions::default()).await;
+        ack.extensions.insert(RECEIPT_EXTENSION.to_string(), ciborium::Value::serialized(&receipt)?);
+        debug!("Sending receipt for {:?} to {}", frame.id, frame.from);
+        self.dispatch(ack).await?;
+        
+        Ok(receipt)
+    }
+    
+    /// Accept the notarized receipt attached to a received frame
+    /// 
+    /// Checks that the configured relay countersigned it and that it is the
+    /// sender's receipt. Keep it as evidence, or anchor it with `anchor_receipt`.
+    /// 
+    /// # Returns
+    /// The receipt, or `None` if the frame carries none
+    #[cfg(feature = "chain")]
+    pub fn on_notarized_receipt(&mut self, frame: &OpacusFrame) -> anyhow::Result<Option<NotarizedReceipt>> {
+        let Some(value) = frame.extensions.get(NOTARIZED_RECEIPT_EXTENSION) else {
+            return Ok(None);
+        };
+        let notarized: NotarizedReceipt = value.deserialized()?;
+        let (domain, relay) = self.receipt_notary()?;
+        let recipient = notarized.verify(&domain, relay)?;
+        if notarized.receipt.receipt.to != frame.from {
+            anyhow::bail!("Receipt for {} sent by {}", notarized.receipt.receipt.to, frame.from);
+        }
+        debug!(
+            "Received notarized receipt for {} from {} ({})",
+            notarized.receipt.receipt.message_id, frame.from, recipient
+        );
+        Ok(Some(notarized))
+    }
+    
+    /// ID of the message a received delivery receipt is for (unverified)
+    #[cfg(feature = "chain")]
+    fn receipt_message_id(frame: &OpacusFrame) -> Option<Ulid> {
+        if let Some(value) = frame.extensions.get(NOTARIZED_RECEIPT_EXTENSION) {
+            return value.deserialized::<NotarizedReceipt>().ok().map(|n| n.receipt.receipt.message_id);
+        }
+        let value = frame.extensions.get(RECEIPT_EXTENSION)?;
+        value.deserialized::<SignedDeliveryReceipt>().ok().map(|r| r.receipt.message_id)
+    }
+    
+    /// Anchor a notarized receipt on chain and wait until it is mined
+    #[cfg(feature = "chain")]
+    pub async fn anchor_receipt(&mut self, notarized: &NotarizedReceipt) -> anyhow::Result<TransactionReceipt> {
+        let (domain, _) = self.receipt_notary()?;
+        let receipt = self.chain()?.anchor_receipt(&domain, notarized).await?;
+        info!("Anchored receipt for {} in block {}", notarized.receipt.receipt.message_id, receipt.block_number);
+        Ok(receipt)
+    }
+    
+    /// Set the registry relays are discovered from and reported to
+    #[cfg(feature = "chain")]
+    pub fn set_relay_registry(&mut self, contract: Address) -> anyhow::Result<()> {
+        self.relay_registry = Some(RelayRegistry::new(self.chain()?, contract));
+        Ok(())
+    }
+    
+    #[cfg(feature = "chain")]
+    fn relay_registry(&self) -> anyhow::Result<&RelayRegistry> {
+        self.relay_registry.as_ref().ok_or_else(|| anyhow::anyhow!("No relay registry set"))
+    }
+    
+    /// Pick the relay with the highest stake from the registry
+    /// 
+    /// Sets `relay_url` to its endpoint; takes effect on the next `connect`.
+    /// 
+    /// # Arguments
+    /// * `min_stake` - Ignore relays staking less (in wei)
+    #[cfg(feature = "chain")]
+    pub async fn discover_relay(&mut self, min_stake: u128) -> anyhow::Result<RelayRecord> {
+        let relay = self
+            .relay_registry()?
+            .staked_relays(min_stake)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("No active relay stakes {} wei", min_stake))?;
+        info!("Using relay {} of {} (stake {})", relay.endpoint, relay.operator, relay.stake);
+        self.config.relay_url = relay.endpoint.clone();
+        Ok(relay)
+    }
+    
+    /// Report contradictory receipts notarized by a relay, to slash its stake
+    /// 
+    /// The receipts are checked in the receipt notary's domain first.
+    #[cfg(feature = "chain")]
+    pub async fn report_relay(&mut self, evidence: &MisbehaviorEvidence) -> anyhow::Result<TransactionReceipt> {
+        let (domain, _) = self.receipt_notary()?;
+        let receipt = self.relay_registry()?.report(&domain, evidence).await?;
+        info!("Reported relay {} in block {}", evidence.check(&domain)?, receipt.block_number);
+        Ok(receipt)
+    }
+    
+    /// Attest to how another agent handled a task or payment
+    /// 
+    /// The attestation is kept in this agent's reputation book; share it
+    /// with `send_attestation` or `publish_attestation`.
+    /// 
+    /// # Arguments
+    /// * `subject` - Agent attested
+    /// * `claim` - Kind of interaction
+    /// * `positive` - Whether the agent lived up to it
+    /// * `reference` - Interaction attested, e.g. a message or payment ID
+    pub fn attest(
+        &mut self,
+        subject: &str,
+        claim: ReputationClaim,
+        positive: bool,
+        reference: &str,
+    ) -> anyhow::Result<ReputationAttestation> {
+        let identity = self.identity.as_ref().ok_or_else(|| anyhow::anyhow!("Not initialized"))?;
+        let attestation = ReputationAttestation::sign(identity, subject, claim, positive, reference, self.clock.now_ms());
+        self.reputation.add(attestation.clone()).map_err(anyhow::Error::msg)?;
+        Ok(attestation)
+    }
+    
+    /// Hand an attestation to another agent
+    pub async fn send_attestation(&mut self, to: &str, attestation: &ReputationAttestation) -> anyhow::Result<()> {
+        let mut frame = self.message_frame(to, Vec::new(), false, FrameOptions::default()).await;
+        frame.extensions.insert(REPUTATION_EXTENSION.to_string(), ciborium::Value::serialized(attestation)?);
+        debug!("Sending attestation about {} to {}", attestation.subject, to);
+        self.dispatch(frame).await
+    }
+    
+    /// Verify and keep the attestation attached to a received frame
+    /// 
+    /// # Returns
+    /// The attestation, or `None` if the frame carries none or it is
+    /// already known
+    pub fn on_attestation(&mut self, frame: &OpacusFrame) -> anyhow::Result<Option<ReputationAttestation>> {
+        let Some(value) = frame.extensions.get(REPUTATION_EXTENSION) else {
+            return Ok(None);
+        };
+        let attestation: ReputationAttestation = value.deserialized()?;
+        if !self.reputation.add(attestation.clone()).map_err(anyhow::Error::msg)? {
+            return Ok(None);
+        }
+        debug!("Received attestation about {} by {} from {}", attestation.subject, attestation.issuer, frame.from);
+        Ok(Some(attestation))
+    }
+    
+    /// Reputation of an agent from the attestations collected so far
+    pub fn reputation(&self, agent_id: &str) -> Reputation {
+        self.reputation.reputation(agent_id)
+    }
+    
+    /// Attestations collected about an agent, oldest first
+    pub fn attestations(&self, agent_id: &str) -> Vec<ReputationAttestation> {
+        self.reputation.attestations(agent_id)
+    }
+    
+    /// Set the registry attestations are published to and queried from
+    #[cfg(feature = "chain")]
+    pub fn set_reputation_registry(&mut self, contract: Address) -> anyhow::Result<()> {
+        self.reputation_registry = Some(ReputationRegistry::new(self.chain()?, contract));
+        Ok(())
+    }
+    
+    #[cfg(feature = "chain")]
+    fn reputation_registry(&self) -> anyhow::Result<&ReputationRegistry> {
+        self.reputation_registry.as_ref().ok_or_else(|| anyhow::anyhow!("No reputation registry set"))
+    }
+    
+    /// Publish an attestation to the reputation registry and wait until it is mined
+    #[cfg(feature = "chain")]
+    pub async fn publish_attestation(&mut self, attestation: &ReputationAttestation) -> anyhow::Result<TransactionReceipt> {
+        let receipt = self.reputation_registry()?.publish(attestation).await?;
+        info!("Published attestation about {} in block {}", attestation.subject, receipt.block_number);
+        Ok(receipt)
+    }
+    
+    /// Reputation of an agent including the attestations published about it
+    /// 
+    /// Published attestations are verified and added to the reputation
+    /// book; invalid ones are skipped.
+    #[cfg(feature = "chain")]
+    pub async fn query_reputation(&mut self, agent_id: &str) -> anyhow::Result<Reputation> {
+        for attestation in self.reputation_registry()?.attestations(agent_id).await? {
+            if let Err(e) = self.reputation.add(attestation) {
+                warn!("Skipping published attestation about {}: {}", agent_id, e);
+            }
+        }
+        Ok(self.reputation.reputation(agent_id))
+    }
+    
+    /// Mint a capability token delegating this agent's access
+    /// 
+    /// Hand it to the agent acting for this one, which attaches it with
+    /// `send_with_capability` or `send_stream_with_capability`. See
+    /// [`crate::token`].
+    pub fn mint_capability(&self, caveats: Vec<Caveat>) -> anyhow::Result<CapabilityToken> {
+        let identity = self.identity.as_ref().ok_or_else(|| anyhow::anyhow!("Not initialized"))?;
+        Ok(CapabilityToken::mint(identity, caveats, self.random.as_ref()))
+    }
+    
+    /// Restrict a capability token before handing it on
+    pub fn attenuate_capability(&self, token: &CapabilityToken, caveats: Vec<Caveat>) -> CapabilityToken {
+        token.attenuate(caveats, self.random.as_ref())
+    }
+    
+    /// Send a message carrying a capability token
+    /// 
+    /// Fails without sending if the token does not permit the message.
+    pub async fn send_with_capability(&mut self, to: &str, payload: Vec<u8>, token: &CapabilityToken) -> anyhow::Result<()> {
+        let to = &self.recipient(to).await?;
+        let frame = self.message_frame(to, payload, true, FrameOptions::default()).await;
+        let frame = self.attach_capability(frame, token)?;
+        debug!("Sending message {:?} to {} under a capability from {}", frame.id, to, token.issuer);
+        self.dispatch(frame).await
+    }
+    
+    /// Send stream data carrying a capability token
+    /// 
+    /// Fails without sending if the token does not permit the frame.
+    pub async fn send_stream_with_capability(&mut self, channel_id: &str, data: Vec<u8>, token: &CapabilityToken) -> anyhow::Result<()> {
+        let frame = self.stream_frame(channel_id, "broadcast", data).await?;
+        let frame = self.attach_capability(frame, token)?;
+        debug!("Sending stream to channel {} under a capability from {}", channel_id, token.issuer);
+        self.dispatch(frame).await
+    }
+    
+    /// Present a token on a signed frame and sign the frame again to cover it
+    fn attach_capability(&self, mut frame: OpacusFrame, token: &CapabilityToken) -> anyhow::Result<OpacusFrame> {
+        token.permits(&frame, self.clock.now_ms()).map_err(anyhow::Error::msg)?;
+        let identity = self.identity.as_ref().ok_or_else(|| anyhow::anyhow!("Not initialized"))?;
+        let hmac = frame.hmac.clone().ok_or_else(|| anyhow::anyhow!("Frame has no HMAC"))?;
+        frame.extensions.insert(CAPABILITY_EXTENSION.to_string(), ciborium::Value::serialized(&token.present(&frame))?);
+        let sign_data = SecurityManager::frame_sign_data(&frame, &hmac);
+        frame.sig = Some(SecurityManager::sign(&identity.ed_priv, &sign_data));
+        Ok(frame)
+    }
+    
+    /// Check the capability token presented on a received frame
+    /// 
+    /// The token must be valid, presented by its holder and permit the
+    /// frame; whether its issuer may grant what the frame asks for is up to
+    /// the application. Check the frame's signature first: it covers the
+    /// presentation.
+    /// 
+    /// # Returns
+    /// The presentation, or `None` if the frame carries none
+    pub fn on_capability(&self, frame: &OpacusFrame) -> anyhow::Result<Option<CapabilityPresentation>> {
+        frame.verify_capability(self.clock.now_ms()).map_err(anyhow::Error::msg)
+    }
+    
+    /// Issue a Verifiable Credential about another agent
+    /// 
+    /// Hand it to its subject with `send_credential`.
+    /// 
+    /// # Arguments
+    /// * `subject` - Subject's DID (`did:key` or `did:pkh`)
+    /// * `credential_type` - Application type, e.g. `KycCredential`
+    /// * `claims` - Attributes of the subject
+    /// * `valid_for` - Validity period (`None` if it does not expire)
+    pub fn issue_credential(
+        &self,
+        subject: &str,
+        credential_type: &str,
+        claims: serde_json::Map<String, serde_json::Value>,
+        valid_for: Option<Duration>,
+    ) -> anyhow::Result<VerifiableCredential> {
+        let identity = self.identity.as_ref().ok_or_else(|| anyhow::anyhow!("Not initialized"))?;
+        let now = self.clock.now_ms();
+        let valid_until = valid_for.map(|d| now + d.as_millis() as u64);
+        Ok(VerifiableCredential::issue(identity, subject, credential_type, claims, now, valid_until))
+    }
+    
+    /// Hand a credential to its subject
+    pub async fn send_credential(&mut self, to: &str, credential: &VerifiableCredential) -> anyhow::Result<()> {
+        let mut frame = self.message_frame(to, Vec::new(), false, FrameOptions::default()).await;
+        frame.extensions.insert(CREDENTIAL_EXTENSION.to_string(), ciborium::Value::serialized(credential)?);
+        debug!("Sending {} from {} to {}", credential.types.join(","), credential.issuer, to);
+        self.dispatch(frame).await
+    }
+    
+    /// Verify and keep a credential issued to this agent in a received frame
+    /// 
+    /// # Returns
+    /// The credential, or `None` if the frame carries none
+    pub fn on_credential(&mut self, frame: &OpacusFrame) -> anyhow::Result<Option<VerifiableCredential>> {
+        let Some(value) = frame.extensions.get(CREDENTIAL_EXTENSION) else {
+            return Ok(None);
+        };
+        let document: serde_json::Value = value.deserialized()?;
+        let credential = VerifiableCredential::verify_json(&document, self.clock.now_ms()).map_err(anyhow::Error::msg)?;
+        self.add_credential(credential.clone())?;
+        debug!("Received credential from {} via {}", credential.issuer, frame.from);
+        Ok(Some(credential))
+    }
+    
+    /// Verify and keep a credential issued to this agent
+    /// 
+    /// Its subject must be this agent's `did:key` or `did:pkh`.
+    pub fn add_credential(&mut self, credential: VerifiableCredential) -> anyhow::Result<()> {
+        let identity = self.identity.as_ref().ok_or_else(|| anyhow::anyhow!("Not initialized"))?;
+        credential.verify(self.clock.now_ms()).map_err(anyhow::Error::msg)?;
+        if !credential.subject().is_some_and(|subject| did_names_key(subject, &identity.ed_pub)) {
+            anyhow::bail!("Credential from {} is about {:?}, not this agent", credential.issuer, credential.subject());
+        }
+        if !self.credentials.contains(&credential) {
+            self.credentials.push(credential);
+        }
+        Ok(())
+    }
+    
+    /// Credentials issued to this agent
+    pub fn credentials(&self) -> &[VerifiableCredential] {
+        &self.credentials
+    }
+    
+    /// Ask another agent to present credentials of some types
+    /// 
+    /// Its answer is checked by `on_presentation`.
+    /// 
+    /// # Returns
+    /// The challenge the presentation must sign
+    pub async fn request_presentation(&mut self, to: &str, types: &[&str]) -> anyhow::Result<String> {
+        let mut challenge = [0u8; 16];
+        self.random.fill_bytes(&mut challenge);
+        let request = PresentationRequest {
+            types: types.iter().map(|t| t.to_string()).collect(),
+            challenge: hex::encode(challenge),
+        };
+        let mut frame = self.message_frame(to, Vec::new(), false, FrameOptions::default()).await;
+        frame.extensions.insert(PRESENTATION_REQUEST_EXTENSION.to_string(), ciborium::Value::serialized(&request)?);
+        debug!("Requesting {} credentials from {}", request.types.join(","), to);
+        self.dispatch(frame).await?;
+        self.presentation_requests.insert(request.challenge.clone(), to.to_string());
+        Ok(request.challenge)
+    }
+    
+    /// Presentation request in a received frame
+    pub fn presentation_request(frame: &OpacusFrame) -> anyhow::Result<Option<PresentationRequest>> {
+        frame
+            .extensions
+            .get(PRESENTATION_REQUEST_EXTENSION)
+            .map(|value| value.deserialized().map_err(anyhow::Error::from))
+            .transpose()
+    }
+    
+    /// Present the credentials an agent requested
+    /// 
+    /// Presents every valid credential held of a requested type, possibly none.
+    /// 
+    /// # Returns
+    /// The number of credentials presented
+    pub async fn present_credentials(&mut self, to: &str, request: &PresentationRequest) -> anyhow::Result<usize> {
+        let identity = self.identity.as_ref().ok_or_else(|| anyhow::anyhow!("Not initialized"))?;
+        let now = self.clock.now_ms();
+        let credentials: Vec<VerifiableCredential> = self
+            .credentials
+            .iter()
+            .filter(|c| request.types.iter().any(|t| c.has_type(t)) && c.verify(now).is_ok())
+            .cloned()
+            .collect();
+        let count = credentials.len();
+        let presentation = VerifiablePresentation::present(identity, credentials, &request.challenge, to, now);
+        let mut frame = self.message_frame(to, Vec::new(), false, FrameOptions::default()).await;
+        frame.extensions.insert(PRESENTATION_EXTENSION.to_string(), ciborium::Value::serialized(&presentation)?);
+        debug!("Presenting {} credentials to {}", count, to);
+        self.dispatch(frame).await?;
+        Ok(count)
+    }
+    
+    /// Verify a presentation this agent requested
+    /// 
+    /// The holder must be the sender, and the proof must sign the challenge
+    /// of an outstanding request to it. The credentials are kept for
+    /// `peer_credentials`; which issuers to trust is up to the application.
+    /// 
+    /// # Returns
+    /// The credentials presented, or `None` if the frame carries no presentation
+    pub fn on_presentation(&mut self, frame: &OpacusFrame) -> anyhow::Result<Option<Vec<VerifiableCredential>>> {
+        let Some(value) = frame.extensions.get(PRESENTATION_EXTENSION) else {
+            return Ok(None);
+        };
+        let identity = self.identity.as_ref().ok_or_else(|| anyhow::anyhow!("Not initialized"))?;
+        let document: serde_json::Value = value.deserialized()?;
+        let challenge = document["proof"]["challenge"].as_str().unwrap_or_default().to_string();
+        if self.presentation_requests.get(&challenge) != Some(&frame.from) {
+            anyhow::bail!("Unrequested presentation from {}", frame.from);
+        }
+        let (presentation, holder_key) =
+            VerifiablePresentation::verify_json(&document, &challenge, &identity.id, self.clock.now_ms())
+                .map_err(anyhow::Error::msg)?;
+        if KeyManager::agent_id(&holder_key) != frame.from {
+            anyhow::bail!("Presentation from {} is held by {}", frame.from, presentation.holder);
+        }
+        self.presentation_requests.remove(&challenge);
+        debug!("{} presented {} credentials", frame.from, presentation.verifiable_credential.len());
+        self.peer_credentials.insert(frame.from.clone(), presentation.verifiable_credential.clone());
+        Ok(Some(presentation.verifiable_credential))
+    }
+    
+    /// Credentials an agent last presented to this agent
+    pub fn peer_credentials(&self, agent_id: &str) -> &[VerifiableCredential] {
+        self.peer_credentials.get(agent_id).map(Vec::as_slice).unwrap_or_default()
+    }
+    
+    /// Compression algorithm negotiated with the relay
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+    
+    /// Get agent identity
+    pub fn get_identity(&self) -> Option<&AgentIdentity> {
+        self.identity.as_ref()
+    }
+    
+    /// Get this agent's key fingerprint
+    pub fn fingerprint(&self) -> Option<String> {
+        let identity = self.identity.as_ref()?;
+        Some(Fingerprint::of(&identity.ed_pub, &identity.x_pub))
+    }
+    
+    /// Get the safety number shared with a peer
+    pub fn safety_number(&self, peer_ed_pub: &[u8; 32], peer_x_pub: &[u8; 32]) -> Option<String> {
+        let identity = self.identity.as_ref()?;
+        Some(Fingerprint::safety_number(
+            (&identity.ed_pub, &identity.x_pub),
+            (peer_ed_pub, peer_x_pub),
+        ))
+    }
+    
+    /// Set tolerated clock skew (milliseconds) for nonce freshness checks
+    pub async fn set_max_clock_skew(&self, max_skew_ms: u64) {
+        self.security.write().await.set_max_skew(max_skew_ms);
+    }
+    
+    /// Set thresholds after which session keys are rotated via a `Rekey` frame
+    pub async fn set_rekey_policy(&self, policy: RekeyPolicy) {
+        self.security.write().await.set_rekey_policy(policy);
+    }
+    
+    /// Set the maximum number of nonces remembered for replay protection
+    pub async fn set_nonce_capacity(&self, capacity: usize) {
+        self.security.write().await.set_nonce_capacity(capacity);
+    }
+    
+    /// Get peer fingerprint and verification records
+    pub fn peer_trust(&self) -> &PeerTrustStore {
+        &self.trust
+    }
+    
+    /// Get mutable peer fingerprint and verification records
+    pub fn peer_trust_mut(&mut self) -> &mut PeerTrustStore {
+        &mut self.trust
+    }
+    
+    /// Get identity (`None` before `init`)
+    pub fn identity(&self) -> Option<&AgentIdentity> {
+        self.identity.as_ref()
+    }
+    
+    /// Export identity to hex strings
+    pub fn export_identity(&self) -> Option<(String, String)> {
+        let identity = self.identity.as_ref()?;
+        Some((
+            KeyManager::to_hex(&identity.ed_priv),
+            KeyManager::to_hex(&identity.x_priv),
+        ))
+    }
+    
+    /// Check connection status
+    pub fn is_connected(&self) -> bool {
+        self.transport.as_ref().map(|t| t.is_connected()).unwrap_or(false)
+    }
+    
+    /// Disconnect from relay
+    pub async fn disconnect(&mut self) {
+        #[cfg(feature = "chain")]
+        if let Some(task) = self.anchor_task.take() {
+            task.abort();
+        }
+        #[cfg(feature = "chain")]
+        if let Some(task) = self.event_task.take() {
+            task.abort();
+        }
         if let Some(mut t) = self.transport.take() {
-            t.close().await;
+            t.close();
             info!("Disconnected from relay");
         }
     }
@@ -0,0 +1,60 @@
+//! Time sources for freshness and expiry checks
+
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Source of the current time
+pub trait Clock: Send + Sync {
+    /// Milliseconds since the Unix epoch
+    fn now_ms(&self) -> u64;
+}
+
+/// Wall-clock time from the operating system
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+    fn now_ms(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+    }
+
+    // Browsers have no system clock for `SystemTime`
+    #[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+    fn now_ms(&self) -> u64 {
+        js_sys::Date::now() as u64
+    }
+}
+
+/// Manually driven clock for tests and simulations
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: AtomicU64,
+}
+
+impl ManualClock {
+    /// Create clock starting at `now_ms`
+    pub fn new(now_ms: u64) -> Self {
+        Self { now: AtomicU64::new(now_ms) }
+    }
+
+    /// Set current time
+    pub fn set(&self, now_ms: u64) {
+        self.now.store(now_ms, Ordering::SeqCst);
+    }
+
+    /// Move time forward
+    pub fn advance(&self, ms: u64) {
+        self.now.fetch_add(ms, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_ms(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,169 @@
+//! Frame payload compression
+//!
+//! Compression is negotiated with the relay: the Connect payload lists the
+//! algorithms a client can decode, and the relay answers with the ones it
+//! accepts in the ACK. Compressed frames carry a `compressed` marker naming
+//! the algorithm; the payload HMAC covers the compressed bytes.
+
+use std::borrow::Cow;
+use serde::{Deserialize, Serialize};
+use crate::types::OpacusFrame;
+
+/// Largest payload accepted after decompression (16 MiB)
+pub const MAX_DECOMPRESSED_SIZE: usize = 16 * 1024 * 1024;
+
+/// Payloads smaller than this are sent uncompressed
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 256;
+
+/// Payload compression algorithm
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// Zstandard (`zstd` feature)
+    Zstd,
+    /// LZ4 block format with prepended size (`lz4` feature)
+    Lz4,
+}
+
+impl Compression {
+    /// Algorithms compiled into this build, in order of preference
+    pub fn supported() -> Vec<Compression> {
+        let mut algs = Vec::new();
+        if cfg!(feature = "zstd") {
+            algs.push(Compression::Zstd);
+        }
+        if cfg!(feature = "lz4") {
+            algs.push(Compression::Lz4);
+        }
+        algs
+    }
+
+    /// Pick the preferred algorithm also offered by the peer
+    pub fn negotiate(offered: &[Compression]) -> Option<Compression> {
+        Self::supported().into_iter().find(|alg| offered.contains(alg))
+    }
+
+    /// Algorithm name as used on the wire
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Compression::Zstd => "zstd",
+            Compression::Lz4 => "lz4",
+        }
+    }
+
+    /// Compress data
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::bulk::compress(data, 3).map_err(|e| format!("zstd compression failed: {}", e)),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            #[allow(unreachable_patterns)]
+            _ => {
+                let _ = data;
+                Err(format!("{} support not compiled in", self.as_str()))
+            }
+        }
+    }
+
+    /// Decompress data, refusing output larger than `max_size`
+    pub fn decompress(&self, data: &[u8], max_size: usize) -> Result<Vec<u8>, String> {
+        match self {
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                use std::io::Read;
+                // Streamed, so the output grows with the data instead of
+                // reserving `max_size` up front
+                let mut out = Vec::new();
+                zstd::stream::Decoder::with_buffer(data)
+                    .and_then(|decoder| decoder.take(max_size as u64 + 1).read_to_end(&mut out))
+                    .map_err(|e| format!("zstd decompression failed: {}", e))?;
+                if out.len() > max_size {
+                    return Err(format!("Decompressed payload too large: over {} bytes", max_size));
+                }
+                Ok(out)
+            }
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => {
+                let (size, block) = lz4_flex::block::uncompressed_size(data)
+                    .map_err(|e| format!("lz4 decompression failed: {}", e))?;
+                if size > max_size {
+                    return Err(format!("Decompressed payload too large: {} bytes", size));
+                }
+                // The output is allocated from the declared size; LZ4 cannot
+                // expand a block more than 255 times
+                if size > block.len().saturating_mul(255).saturating_add(16) {
+                    return Err(format!("lz4 decompression failed: declared size {} exceeds the block", size));
+                }
+                lz4_flex::decompress_size_prepended(data).map_err(|e| format!("lz4 decompression failed: {}", e))
+            }
+            #[allow(unreachable_patterns)]
+            _ => {
+                let _ = (data, max_size);
+                Err(format!("{} support not compiled in", self.as_str()))
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zstd" => Ok(Compression::Zstd),
+            "lz4" => Ok(Compression::Lz4),
+            _ => Err(format!("Unknown compression: {}", s)),
+        }
+    }
+}
+
+impl OpacusFrame {
+    /// Get the application payload, decompressing it if needed
+    pub fn decompressed_payload(&self) -> Result<Cow<'_, [u8]>, String> {
+        match self.compressed {
+            Some(alg) => alg.decompress(&self.payload, MAX_DECOMPRESSED_SIZE).map(Cow::Owned),
+            None => Ok(Cow::Borrowed(&self.payload)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json_payload() -> Vec<u8> {
+        let items: Vec<_> = (0..200)
+            .map(|i| serde_json::json!({ "agent": "0x1234", "task": "inference", "step": i }))
+            .collect();
+        serde_json::to_vec(&items).unwrap()
+    }
+
+    #[test]
+    fn test_roundtrip_supported() {
+        let data = json_payload();
+        for alg in Compression::supported() {
+            let packed = alg.compress(&data).unwrap();
+            assert!(packed.len() * 5 < data.len(), "{} ratio", alg.as_str());
+            assert_eq!(alg.decompress(&packed, MAX_DECOMPRESSED_SIZE).unwrap(), data);
+            assert!(alg.decompress(&packed, data.len() - 1).is_err());
+        }
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn test_lz4_declared_size() {
+        // Claims 16 MiB of output from a one-byte block
+        let mut data = (MAX_DECOMPRESSED_SIZE as u32).to_le_bytes().to_vec();
+        data.push(0);
+        let err = Compression::Lz4.decompress(&data, MAX_DECOMPRESSED_SIZE).unwrap_err();
+        assert!(err.contains("exceeds the block"), "{}", err);
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(Compression::negotiate(&[]), None);
+        let supported = Compression::supported();
+        assert_eq!(Compression::negotiate(&[Compression::Lz4, Compression::Zstd]), supported.first().copied());
+    }
+}
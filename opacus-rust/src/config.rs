@@ -0,0 +1,646 @@
+//! Configuration loading and validation
+//!
+//! Deployments describe clients and relays in TOML files (`config` feature)
+//! or `OPACUS_*` environment variables instead of building the structs in
+//! code. Loaded configurations are validated before they are returned, and
+//! errors name the setting and the expected format. A client file:
+//!
+//! ```toml
+//! network = "testnet"                       # mainnet, testnet, devnet or a [network.Custom] profile
+//! relay_url = "quic://203.0.113.7:4242"     # or local://<name> for an in-process relay
+//! chain_rpc = "https://evmrpc-testnet.0g.ai"  # optional, defaults to the network's RPC
+//! private_key = "0x…"                       # optional, secp256k1 key for chain transactions
+//! ```
+//!
+//! Private keys are never included in error messages.
+
+use crate::types::{Network, OpacusConfig};
+#[cfg(feature = "config")]
+use std::path::Path;
+#[cfg(feature = "relay")]
+use std::net::SocketAddr;
+#[cfg(feature = "relay")]
+use std::time::Duration;
+#[cfg(feature = "relay")]
+use serde::Deserialize;
+#[cfg(feature = "relay")]
+use crate::relay::BatchVerifyConfig;
+
+/// Network name or built-in chain ID
+pub const ENV_NETWORK: &str = "OPACUS_NETWORK";
+/// Relay URL
+pub const ENV_RELAY: &str = "OPACUS_RELAY";
+/// Chain RPC endpoint
+pub const ENV_CHAIN_RPC: &str = "OPACUS_CHAIN_RPC";
+/// Hex secp256k1 private key
+pub const ENV_PRIVATE_KEY: &str = "OPACUS_PRIVATE_KEY";
+
+/// Schemes accepted in `relay_url` (besides `local://`)
+const RELAY_SCHEMES: [&str; 3] = ["quic", "https", "http"];
+
+/// Schemes accepted in `chain_rpc`
+const RPC_SCHEMES: [&str; 4] = ["https", "http", "wss", "ws"];
+
+/// Configuration that could not be loaded
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    /// File could not be read
+    #[error("cannot read {path}: {source}")]
+    Io {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    /// File is not valid TOML or does not match the schema
+    #[error("{0}")]
+    Parse(String),
+    /// Required setting is absent
+    #[error("missing `{0}`")]
+    Missing(String),
+    /// Setting has an unusable value
+    #[error("invalid `{setting}`: {message}")]
+    Invalid { setting: String, message: String },
+}
+
+fn invalid(setting: &str, message: impl Into<String>) -> ConfigError {
+    ConfigError::Invalid { setting: setting.to_string(), message: message.into() }
+}
+
+#[cfg(feature = "config")]
+fn read_file(path: &Path) -> Result<String, ConfigError> {
+    std::fs::read_to_string(path).map_err(|source| ConfigError::Io { path: path.display().to_string(), source })
+}
+
+/// Parse `mainnet`, `testnet` or `devnet` (any case) or a built-in chain ID
+fn parse_network(setting: &str, value: &str) -> Result<Network, ConfigError> {
+    let value = value.trim();
+    let network = match value.to_ascii_lowercase().as_str() {
+        "mainnet" => Some(Network::Mainnet),
+        "testnet" => Some(Network::Testnet),
+        "devnet" => Some(Network::Devnet),
+        other => other.parse().ok().and_then(Network::from_chain_id),
+    };
+    network.ok_or_else(|| {
+        let ids = [Network::Mainnet, Network::Testnet, Network::Devnet].map(|n| n.chain_id().to_string());
+        invalid(setting, format!(
+            "expected mainnet, testnet, devnet or one of their chain IDs ({}), got `{}`",
+            ids.join(", "), value
+        ))
+    })
+}
+
+/// Split `scheme://authority[/path]` into scheme, host, port and path
+fn split_url(url: &str) -> Option<(&str, &str, Option<&str>, &str)> {
+    let (scheme, rest) = url.split_once("://")?;
+    let (authority, path) = rest.find('/').map_or((rest, ""), |i| rest.split_at(i));
+    let (host, port) = match authority.strip_prefix('[') {
+        // Bracketed IPv6 literal
+        Some(v6) => {
+            let (host, after) = v6.split_once(']')?;
+            match after {
+                "" => (host, None),
+                _ => (host, Some(after.strip_prefix(':')?)),
+            }
+        }
+        None => match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    Some((scheme, host, port, path))
+}
+
+fn check_port(setting: &str, port: &str) -> Result<(), ConfigError> {
+    match port.parse::<u16>() {
+        Ok(p) if p != 0 => Ok(()),
+        _ => Err(invalid(setting, format!("port must be 1-65535, got `{}`", port))),
+    }
+}
+
+/// `quic://host:port` (also `https`, `http` or no scheme) or `local://name`
+fn check_relay_url(url: &str) -> Result<(), ConfigError> {
+    const SETTING: &str = "relay_url";
+    if url.is_empty() {
+        return Err(ConfigError::Missing(SETTING.into()));
+    }
+    if let Some(name) = url.strip_prefix("local://") {
+        return match name.is_empty() {
+            true => Err(invalid(SETTING, "local:// needs a relay name, e.g. local://test")),
+            false => Ok(()),
+        };
+    }
+    let with_scheme = if url.contains("://") { url.to_string() } else { format!("quic://{}", url) };
+    let expected = "expected quic://host:port, e.g. quic://203.0.113.7:4242";
+    let Some((scheme, host, port, path)) = split_url(&with_scheme) else {
+        return Err(invalid(SETTING, format!("{}, got `{}`", expected, url)));
+    };
+    if !RELAY_SCHEMES.contains(&scheme) {
+        return Err(invalid(SETTING, format!(
+            "unsupported scheme `{}://`; use quic://, https:// or http:// (or local:// for an in-process relay)", scheme
+        )));
+    }
+    if host.is_empty() {
+        return Err(invalid(SETTING, format!("{}, but the host is empty", expected)));
+    }
+    let Some(port) = port else {
+        return Err(invalid(SETTING, format!("{}, but the port is missing", expected)));
+    };
+    check_port(SETTING, port)?;
+    if !path.is_empty() {
+        return Err(invalid(SETTING, format!("relays are addressed by host and port only, remove `{}`", path)));
+    }
+    Ok(())
+}
+
+/// `https://host[:port][/path]` (also `http`, `wss`, `ws`)
+fn check_rpc_url(setting: &str, url: &str) -> Result<(), ConfigError> {
+    let expected = "expected an http(s):// or ws(s):// URL, e.g. https://evmrpc-testnet.0g.ai";
+    let Some((scheme, host, port, _)) = split_url(url) else {
+        return Err(invalid(setting, format!("{}, got `{}`", expected, url)));
+    };
+    if !RPC_SCHEMES.contains(&scheme) {
+        return Err(invalid(setting, format!("{}, got scheme `{}://`", expected, scheme)));
+    }
+    if host.is_empty() {
+        return Err(invalid(setting, format!("{}, but the host is empty", expected)));
+    }
+    match port {
+        Some(port) => check_port(setting, port),
+        None => Ok(()),
+    }
+}
+
+/// 32 bytes as 64 hex digits, optionally `0x`-prefixed; never echoes the key
+fn check_private_key(key: &str) -> Result<(), ConfigError> {
+    const SETTING: &str = "private_key";
+    let digits = key.trim();
+    let digits = digits.strip_prefix("0x").unwrap_or(digits);
+    if digits.len() != 64 {
+        return Err(invalid(SETTING, format!(
+            "expected 64 hex digits (32 bytes, optionally 0x-prefixed), got {} characters", digits.len()
+        )));
+    }
+    match hex::decode(digits) {
+        Err(_) => Err(invalid(SETTING, "expected hex digits (0-9, a-f)")),
+        Ok(bytes) if bytes.iter().all(|&b| b == 0) => Err(invalid(SETTING, "key is zero")),
+        Ok(_) => Ok(()),
+    }
+}
+
+/// Compare endpoints ignoring case and a trailing slash
+fn same_endpoint(a: &str, b: &str) -> bool {
+    a.trim_end_matches('/').eq_ignore_ascii_case(b.trim_end_matches('/'))
+}
+
+/// Client configuration file; `network` is a name, chain ID or profile table
+#[cfg(feature = "config")]
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClientFile {
+    network: toml::Value,
+    relay_url: String,
+    #[serde(default)]
+    chain_rpc: String,
+    #[serde(default)]
+    private_key: Option<String>,
+}
+
+impl OpacusConfig {
+    /// Check URL formats, network/RPC consistency and the key format
+    ///
+    /// `relay_url` is `quic://host:port` (`https`, `http` or no scheme also
+    /// work) or `local://name`. An empty `chain_rpc` uses the network's RPC,
+    /// which must then exist; a non-empty one must not be the default RPC of
+    /// another built-in network.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        check_relay_url(&self.relay_url)?;
+
+        if let Network::Custom(profile) = &self.network {
+            if profile.chain_id == 0 {
+                return Err(invalid("network.chainId", "chain ID must not be 0"));
+            }
+            if !profile.rpc.is_empty() {
+                check_rpc_url("network.rpc", &profile.rpc)?;
+            }
+        }
+        if self.chain_rpc.is_empty() {
+            if self.network.rpc().is_empty() {
+                return Err(invalid("chain_rpc", format!(
+                    "network {} has no default RPC, set chain_rpc", self.network.profile().name
+                )));
+            }
+        } else {
+            check_rpc_url("chain_rpc", &self.chain_rpc)?;
+            let other = [Network::Mainnet, Network::Testnet, Network::Devnet]
+                .into_iter()
+                .find(|n| n.chain_id() != self.network.chain_id() && same_endpoint(n.rpc(), &self.chain_rpc));
+            if let Some(other) = other {
+                return Err(invalid("chain_rpc", format!(
+                    "{} is the RPC of {} (chain {}), but network is {} (chain {})",
+                    self.chain_rpc, other.profile().name, other.chain_id(),
+                    self.network.profile().name, self.network.chain_id()
+                )));
+            }
+        }
+
+        if let Some(key) = &self.private_key {
+            check_private_key(key)?;
+        }
+        Ok(())
+    }
+
+    /// Load and validate a TOML configuration file
+    ///
+    /// `network` is a name (`"testnet"`), a built-in chain ID or a
+    /// `[network.Custom]` table with a [`NetworkProfile`](crate::NetworkProfile).
+    /// Unknown keys are rejected.
+    #[cfg(feature = "config")]
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        Self::from_toml(&read_file(path)?)
+            .map_err(|e| match e {
+                ConfigError::Parse(message) => ConfigError::Parse(format!("{}: {}", path.display(), message)),
+                e => e,
+            })
+    }
+
+    /// Parse and validate a TOML configuration (see `from_file`)
+    #[cfg(feature = "config")]
+    pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+        let file: ClientFile = toml::from_str(text).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        let network = match file.network {
+            toml::Value::String(name) => parse_network("network", &name)?,
+            toml::Value::Integer(id) => parse_network("network", &id.to_string())?,
+            table => table.try_into().map_err(|e: toml::de::Error| invalid("network", e.message()))?,
+        };
+        let config = Self {
+            network,
+            relay_url: file.relay_url,
+            chain_rpc: file.chain_rpc,
+            private_key: file.private_key,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Load and validate the configuration from `OPACUS_*` variables
+    ///
+    /// `OPACUS_NETWORK` (name or built-in chain ID) and `OPACUS_RELAY` are
+    /// required; `OPACUS_CHAIN_RPC` and `OPACUS_PRIVATE_KEY` are optional.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let required = |name: &str| var(name).filter(|v| !v.is_empty()).ok_or_else(|| ConfigError::Missing(name.into()));
+        let config = Self {
+            network: parse_network(ENV_NETWORK, &required(ENV_NETWORK)?)?,
+            relay_url: required(ENV_RELAY)?,
+            chain_rpc: var(ENV_CHAIN_RPC).unwrap_or_default(),
+            private_key: var(ENV_PRIVATE_KEY).filter(|v| !v.is_empty()),
+        };
+        // Report problems under the variable names
+        config.validate().map_err(|e| match e {
+            ConfigError::Invalid { setting, message } => {
+                let name = match setting.as_str() {
+                    "relay_url" => ENV_RELAY,
+                    "chain_rpc" => ENV_CHAIN_RPC,
+                    "private_key" => ENV_PRIVATE_KEY,
+                    _ => ENV_NETWORK,
+                };
+                invalid(name, message)
+            }
+            ConfigError::Missing(_) => ConfigError::Missing(ENV_RELAY.into()),
+            e => e,
+        })?;
+        Ok(config)
+    }
+}
+
+/// Port to listen on
+#[cfg(feature = "relay")]
+pub const ENV_RELAY_PORT: &str = "OPACUS_RELAY_PORT";
+/// Seconds between stats lines
+#[cfg(feature = "relay")]
+pub const ENV_RELAY_STATS_INTERVAL_SECS: &str = "OPACUS_RELAY_STATS_INTERVAL_SECS";
+/// Admin endpoint address
+#[cfg(feature = "relay")]
+pub const ENV_RELAY_ADMIN_ADDR: &str = "OPACUS_RELAY_ADMIN_ADDR";
+/// Signature verification batch size (enables verification)
+#[cfg(feature = "relay")]
+pub const ENV_RELAY_VERIFY_BATCH_SIZE: &str = "OPACUS_RELAY_VERIFY_BATCH_SIZE";
+/// Signature verification batch delay (enables verification)
+#[cfg(feature = "relay")]
+pub const ENV_RELAY_VERIFY_MAX_DELAY_MS: &str = "OPACUS_RELAY_VERIFY_MAX_DELAY_MS";
+
+/// Relay server configuration
+///
+/// ```toml
+/// port = 4242
+/// stats_interval_secs = 60
+/// admin_addr = "127.0.0.1:8080"
+///
+/// [signature_verification]
+/// batch_size = 64
+/// max_delay_ms = 2
+/// ```
+///
+/// Every setting has a default; unknown keys are rejected.
+#[cfg(feature = "relay")]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelayConfig {
+    /// Port to listen on
+    pub port: u16,
+    /// Seconds between stats lines (0 disables them)
+    pub stats_interval_secs: u64,
+    /// Address for the HTTP health checks and live events
+    pub admin_addr: Option<SocketAddr>,
+    /// Verify signatures on routed frames
+    pub signature_verification: Option<RelayVerifyConfig>,
+}
+
+#[cfg(feature = "relay")]
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            port: 4242,
+            stats_interval_secs: 60,
+            admin_addr: None,
+            signature_verification: None,
+        }
+    }
+}
+
+/// Batched signature verification settings of a [`RelayConfig`]
+#[cfg(feature = "relay")]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelayVerifyConfig {
+    /// Maximum frames per verification batch
+    pub batch_size: usize,
+    /// Maximum time a frame waits for its batch to fill (milliseconds)
+    pub max_delay_ms: u64,
+}
+
+#[cfg(feature = "relay")]
+impl Default for RelayVerifyConfig {
+    fn default() -> Self {
+        let config = BatchVerifyConfig::default();
+        Self {
+            batch_size: config.batch_size,
+            max_delay_ms: config.max_delay.as_millis() as u64,
+        }
+    }
+}
+
+#[cfg(feature = "relay")]
+impl From<&RelayVerifyConfig> for BatchVerifyConfig {
+    fn from(config: &RelayVerifyConfig) -> Self {
+        Self {
+            batch_size: config.batch_size,
+            max_delay: Duration::from_millis(config.max_delay_ms),
+        }
+    }
+}
+
+#[cfg(feature = "relay")]
+fn parse_var<T: std::str::FromStr>(name: &str, value: Option<String>) -> Result<Option<T>, ConfigError>
+where
+    T::Err: std::fmt::Display,
+{
+    match value.filter(|v| !v.is_empty()) {
+        Some(v) => v.trim().parse().map(Some).map_err(|e| invalid(name, format!("`{}`: {}", v, e))),
+        None => Ok(None),
+    }
+}
+
+#[cfg(feature = "relay")]
+impl RelayConfig {
+    /// Check the verification settings
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if let Some(verify) = &self.signature_verification {
+            if verify.batch_size == 0 {
+                return Err(invalid("signature_verification.batch_size", "must be at least 1"));
+            }
+            if verify.max_delay_ms > 1000 {
+                return Err(invalid("signature_verification.max_delay_ms", format!(
+                    "{} ms would hold every routed frame that long; use at most 1000", verify.max_delay_ms
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Load and validate a TOML configuration file
+    #[cfg(feature = "config")]
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        Self::from_toml(&read_file(path)?)
+            .map_err(|e| match e {
+                ConfigError::Parse(message) => ConfigError::Parse(format!("{}: {}", path.display(), message)),
+                e => e,
+            })
+    }
+
+    /// Parse and validate a TOML configuration (see `from_file`)
+    #[cfg(feature = "config")]
+    pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+        let config: Self = toml::from_str(text).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Load and validate the configuration from `OPACUS_RELAY_*` variables
+    ///
+    /// Unset variables keep their defaults. Setting either verification
+    /// variable enables signature verification.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let mut config = Self::default();
+        if let Some(port) = parse_var(ENV_RELAY_PORT, var(ENV_RELAY_PORT))? {
+            config.port = port;
+        }
+        if let Some(secs) = parse_var(ENV_RELAY_STATS_INTERVAL_SECS, var(ENV_RELAY_STATS_INTERVAL_SECS))? {
+            config.stats_interval_secs = secs;
+        }
+        config.admin_addr = parse_var(ENV_RELAY_ADMIN_ADDR, var(ENV_RELAY_ADMIN_ADDR))?;
+
+        let batch_size = parse_var(ENV_RELAY_VERIFY_BATCH_SIZE, var(ENV_RELAY_VERIFY_BATCH_SIZE))?;
+        let max_delay_ms = parse_var(ENV_RELAY_VERIFY_MAX_DELAY_MS, var(ENV_RELAY_VERIFY_MAX_DELAY_MS))?;
+        if batch_size.is_some() || max_delay_ms.is_some() {
+            let defaults = RelayVerifyConfig::default();
+            config.signature_verification = Some(RelayVerifyConfig {
+                batch_size: batch_size.unwrap_or(defaults.batch_size),
+                max_delay_ms: max_delay_ms.unwrap_or(defaults.max_delay_ms),
+            });
+        }
+        config.validate().map_err(|e| match e {
+            ConfigError::Invalid { setting, message } if setting.ends_with("batch_size") => {
+                invalid(ENV_RELAY_VERIFY_BATCH_SIZE, message)
+            }
+            ConfigError::Invalid { message, .. } => invalid(ENV_RELAY_VERIFY_MAX_DELAY_MS, message),
+            e => e,
+        })?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+
+    const KEY: &str = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    fn config(relay_url: &str, chain_rpc: &str) -> OpacusConfig {
+        OpacusConfig {
+            network: Network::Testnet,
+            relay_url: relay_url.to_string(),
+            chain_rpc: chain_rpc.to_string(),
+            private_key: Some(KEY.to_string()),
+        }
+    }
+
+    fn setting(result: Result<(), ConfigError>) -> String {
+        match result {
+            Err(ConfigError::Invalid { setting, .. }) => setting,
+            Err(ConfigError::Missing(name)) => name,
+            other => panic!("expected invalid setting, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(config("quic://203.0.113.7:4242", "").validate().is_ok());
+        assert!(config("203.0.113.7:4242", "https://evmrpc-testnet.0g.ai/").validate().is_ok());
+        assert!(config("quic://[::1]:4242", "ws://127.0.0.1:8546").validate().is_ok());
+        assert!(config("local://test", "").validate().is_ok());
+
+        for url in ["", "local://", "udp://relay:4242", "quic://relay", "quic://:4242", "quic://relay:0", "quic://relay:4242/x"] {
+            assert_eq!(setting(config(url, "").validate()), "relay_url", "{}", url);
+        }
+        for rpc in ["evmrpc-testnet.0g.ai", "ftp://rpc", "https://:8545", "https://rpc:port"] {
+            assert_eq!(setting(config("quic://relay:4242", rpc).validate()), "chain_rpc", "{}", rpc);
+        }
+
+        // Mainnet RPC with the testnet network
+        let err = config("quic://relay:4242", Network::Mainnet.rpc()).validate().unwrap_err();
+        assert!(err.to_string().contains("but network is"), "{}", err);
+
+        let mut bad_key = config("quic://relay:4242", "");
+        for key in ["0x1234", &KEY.replace('c', "g"), &format!("0x{}", "0".repeat(64))] {
+            bad_key.private_key = Some(key.to_string());
+            let err = bad_key.validate().unwrap_err();
+            assert!(matches!(&err, ConfigError::Invalid { setting, .. } if setting == "private_key"));
+            assert!(!err.to_string().contains(key.trim_start_matches("0x")), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_custom_network() {
+        let profile = crate::types::NetworkProfile::new("Base", 8453, "", crate::types::NativeToken::new("ETH"));
+        let mut config = config("quic://relay:4242", "");
+        config.network = Network::Custom(Box::new(profile));
+        assert_eq!(setting(config.validate()), "chain_rpc");
+        config.chain_rpc = "https://mainnet.base.org".into();
+        assert!(config.validate().is_ok());
+
+        let Network::Custom(profile) = &mut config.network else { unreachable!() };
+        profile.chain_id = 0;
+        assert_eq!(setting(config.validate()), "network.chainId");
+    }
+
+    #[test]
+    fn test_from_vars() {
+        let vars = |pairs: &[(&str, &str)]| {
+            let map: HashMap<String, String> = pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            move |name: &str| map.get(name).cloned()
+        };
+
+        let config = OpacusConfig::from_vars(vars(&[
+            (ENV_NETWORK, "TestNet"),
+            (ENV_RELAY, "quic://127.0.0.1:4242"),
+            (ENV_PRIVATE_KEY, KEY),
+        ]))
+        .unwrap();
+        assert_eq!(config.network, Network::Testnet);
+        assert_eq!((config.chain_rpc.as_str(), config.private_key.as_deref()), ("", Some(KEY)));
+
+        let config = OpacusConfig::from_vars(vars(&[(ENV_NETWORK, "16661"), (ENV_RELAY, "local://a")])).unwrap();
+        assert_eq!(config.network, Network::Mainnet);
+
+        assert!(matches!(
+            OpacusConfig::from_vars(vars(&[(ENV_NETWORK, "testnet")])),
+            Err(ConfigError::Missing(name)) if name == ENV_RELAY
+        ));
+        let err = OpacusConfig::from_vars(vars(&[(ENV_NETWORK, "goerli"), (ENV_RELAY, "local://a")])).unwrap_err();
+        assert!(err.to_string().contains("got `goerli`"), "{}", err);
+        let err = OpacusConfig::from_vars(vars(&[(ENV_NETWORK, "devnet"), (ENV_RELAY, "relay")])).unwrap_err();
+        assert!(err.to_string().starts_with("invalid `OPACUS_RELAY`"), "{}", err);
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn test_from_toml() {
+        let config = OpacusConfig::from_toml("network = \"devnet\"\nrelay_url = \"quic://127.0.0.1:4242\"\n").unwrap();
+        assert_eq!((config.network, config.private_key), (Network::Devnet, None));
+
+        let text = "relay_url = \"quic://127.0.0.1:4242\"\n\
+            [network.Custom]\nname = \"Base\"\nchainId = 8453\nrpc = \"https://mainnet.base.org\"\nnativeToken = { symbol = \"ETH\", decimals = 18 }\n";
+        let config = OpacusConfig::from_toml(text).unwrap();
+        assert_eq!(config.network.chain_id(), 8453);
+
+        let err = OpacusConfig::from_toml("network = \"testnet\"\nrelay = \"quic://127.0.0.1:4242\"\n").unwrap_err();
+        assert!(matches!(&err, ConfigError::Parse(m) if m.contains("unknown field `relay`")), "{}", err);
+        let err = OpacusConfig::from_toml("network = \"testnet\"\nrelay_url = \"quic://relay\"\n").unwrap_err();
+        assert!(err.to_string().contains("port is missing"), "{}", err);
+
+        let err = OpacusConfig::from_file("/nonexistent/opacus.toml").unwrap_err();
+        assert!(matches!(err, ConfigError::Io { .. }));
+    }
+
+    #[cfg(feature = "relay")]
+    #[test]
+    fn test_relay_from_vars() {
+        let vars = |pairs: &'static [(&str, &str)]| move |name: &str| {
+            pairs.iter().find(|(k, _)| *k == name).map(|(_, v)| v.to_string())
+        };
+        assert_eq!(RelayConfig::from_vars(vars(&[])).unwrap(), RelayConfig::default());
+
+        let config = RelayConfig::from_vars(vars(&[
+            (ENV_RELAY_PORT, "5000"),
+            (ENV_RELAY_ADMIN_ADDR, "127.0.0.1:8080"),
+            (ENV_RELAY_VERIFY_BATCH_SIZE, "128"),
+        ]))
+        .unwrap();
+        assert_eq!(config.port, 5000);
+        assert_eq!(config.admin_addr, Some("127.0.0.1:8080".parse().unwrap()));
+        let verify = BatchVerifyConfig::from(config.signature_verification.as_ref().unwrap());
+        assert_eq!((verify.batch_size, verify.max_delay), (128, BatchVerifyConfig::default().max_delay));
+
+        let err = RelayConfig::from_vars(vars(&[(ENV_RELAY_PORT, "70000")])).unwrap_err();
+        assert!(err.to_string().starts_with("invalid `OPACUS_RELAY_PORT`: `70000`"), "{}", err);
+        let err = RelayConfig::from_vars(vars(&[(ENV_RELAY_VERIFY_BATCH_SIZE, "0")])).unwrap_err();
+        assert!(err.to_string().contains(ENV_RELAY_VERIFY_BATCH_SIZE), "{}", err);
+    }
+
+    #[cfg(all(feature = "relay", feature = "config"))]
+    #[test]
+    fn test_relay_from_toml() {
+        let config = RelayConfig::from_toml("port = 5000\n[signature_verification]\nmax_delay_ms = 5\n").unwrap();
+        assert_eq!(config.port, 5000);
+        assert_eq!(config.stats_interval_secs, 60);
+        assert_eq!(config.signature_verification.unwrap().batch_size, BatchVerifyConfig::default().batch_size);
+
+        assert_eq!(RelayConfig::from_toml("").unwrap(), RelayConfig::default());
+        assert!(matches!(RelayConfig::from_toml("prot = 5000"), Err(ConfigError::Parse(_))));
+        assert_eq!(
+            setting(RelayConfig::from_toml("[signature_verification]\nbatch_size = 0\n").map(drop)),
+            "signature_verification.batch_size"
+        );
+    }
+}
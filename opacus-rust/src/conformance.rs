@@ -0,0 +1,266 @@
+//! Interoperability test vectors
+//!
+//! [`generate_vectors`] builds a fixed [`VectorSet`] from seeded identities,
+//! a manual clock and a seeded generator: identities, canonical CBOR frame
+//! bytes and every intermediate value of frame authentication (session key
+//! info and key, HMAC input and HMAC, signing input and signature). The
+//! committed copy in `vectors/interop.json` is checked by the `verify_vectors`
+//! test target here and by the JS SDK's test suite, so both implementations
+//! stay wire-compatible.
+//!
+//! All binary values are lowercase hex. Regenerate the file after an
+//! intentional wire change with
+//! `OPACUS_UPDATE_VECTORS=1 cargo test --test verify_vectors`.
+
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use crate::clock::ManualClock;
+use crate::content::ContentType;
+use crate::crypto::{KeyManager, SecurityManager};
+use crate::proto::CBORCodec;
+use crate::qos::Priority;
+use crate::random::SeededRandom;
+use crate::types::{AgentIdentity, FrameOptions, FrameType, OpacusFrame};
+
+/// Format version of the vector file
+pub const VECTORS_VERSION: u32 = 2;
+
+/// Chain ID of the vector identities (0G testnet)
+const VECTOR_CHAIN_ID: u64 = 16602;
+
+/// Clock of the first vector frame (2023-11-14T22:13:20Z)
+const VECTOR_TIME_MS: u64 = 1_700_000_000_000;
+
+/// Seed of the nonces and message IDs
+const VECTOR_RANDOM_SEED: u64 = 42;
+
+/// Complete set of interoperability vectors
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VectorSet {
+    /// Format version (`VECTORS_VERSION`)
+    pub version: u32,
+    /// Sender and recipient
+    pub identities: Vec<IdentityVector>,
+    /// X25519 shared secret of the two identities
+    pub shared_secret: String,
+    /// Frames from the first identity to the second, in sending order
+    pub frames: Vec<FrameVector>,
+}
+
+/// Identity derived from a seed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdentityVector {
+    /// Label (`alice`, `bob`)
+    pub name: String,
+    /// 32-byte seed (see [`KeyManager::identity_from_seed`])
+    pub seed: String,
+    /// Chain ID
+    pub chain_id: u64,
+    /// Ed25519 private key
+    pub ed_priv: String,
+    /// Ed25519 public key
+    pub ed_pub: String,
+    /// X25519 private key
+    pub x_priv: String,
+    /// X25519 public key
+    pub x_pub: String,
+    /// First 20 bytes of SHA-256 of `ed_pub`
+    pub id: String,
+    /// `0x` followed by the ID
+    pub address: String,
+}
+
+/// Authenticated frame with its intermediate values
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FrameVector {
+    /// Label of the case
+    pub name: String,
+    /// Canonical CBOR encoding of the frame
+    pub cbor: String,
+    /// HKDF-SHA256 info of the session key (no salt)
+    pub session_info: String,
+    /// Session key keying the HMAC
+    pub session_key: String,
+    /// UTF-8 string covered by the HMAC
+    pub hmac_input: String,
+    /// HMAC-SHA256 of `hmac_input`
+    pub hmac: String,
+    /// Canonical CBOR covered by the Ed25519 signature
+    pub signing_input: String,
+    /// Ed25519 signature of `signing_input`
+    pub sig: String,
+}
+
+impl VectorSet {
+    /// Serialize as pretty-printed JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Vectors serialize")
+    }
+}
+
+fn identity_vector(name: &str, seed: [u8; 32]) -> (AgentIdentity, IdentityVector) {
+    let identity = KeyManager::identity_from_seed(&seed, VECTOR_CHAIN_ID);
+    let vector = IdentityVector {
+        name: name.to_string(),
+        seed: hex::encode(seed),
+        chain_id: identity.chain_id,
+        ed_priv: hex::encode(identity.ed_priv),
+        ed_pub: hex::encode(identity.ed_pub),
+        x_priv: hex::encode(identity.x_priv),
+        x_pub: hex::encode(identity.x_pub),
+        id: identity.id.clone(),
+        address: identity.address.clone(),
+    };
+    (identity, vector)
+}
+
+fn frame_vector(name: &str, frame: &OpacusFrame, shared: &[u8; 32]) -> FrameVector {
+    let info = SecurityManager::frame_session_info(frame.version, &frame.from, &frame.to, frame.key_epoch);
+    let session_key = SecurityManager::derive_session_key(shared, &info);
+    let hmac = frame.hmac.clone().expect("Vector frames are authenticated");
+    FrameVector {
+        name: name.to_string(),
+        cbor: hex::encode(CBORCodec::encode_canonical(frame).expect("Vector frames encode")),
+        session_info: hex::encode(&info),
+        session_key: hex::encode(session_key),
+        hmac_input: SecurityManager::hmac_data(
+            frame.frame_type, &frame.from, &frame.to, frame.seq, frame.ts,
+            &frame.nonce, &frame.payload, frame.compressed,
+        ),
+        hmac: hmac.clone(),
+        signing_input: hex::encode(SecurityManager::frame_sign_data(frame, &hmac)),
+        sig: hex::encode(frame.sig.as_deref().expect("Vector frames are signed")),
+    }
+}
+
+/// Build the interoperability vectors
+///
+/// The result only depends on the implementation, never on the environment.
+pub fn generate_vectors() -> VectorSet {
+    let (alice, alice_vector) = identity_vector("alice", [1; 32]);
+    let (bob, bob_vector) = identity_vector("bob", [2; 32]);
+    let shared = SecurityManager::derive_shared_secret(&alice.x_priv, &bob.x_pub);
+
+    let clock = Arc::new(ManualClock::new(VECTOR_TIME_MS));
+    let mut security = SecurityManager::with_sources(clock.clone(), Arc::new(SeededRandom::new(VECTOR_RANDOM_SEED)));
+    let mut frames = Vec::new();
+
+    let frame = security.create_auth_frame(&alice, &bob.x_pub, FrameType::Msg, &bob.id, b"hello bob".to_vec());
+    frames.push(frame_vector("msg", &frame, &shared));
+
+    clock.advance(250);
+    let options = FrameOptions {
+        priority: Some(Priority::High),
+        content_type: ContentType::Json,
+        ..Default::default()
+    };
+    let payload = br#"{"task":"summarize","maxTokens":256}"#.to_vec();
+    let frame = security.create_auth_frame_with(&alice, &bob.x_pub, FrameType::Msg, &bob.id, payload, options);
+    frames.push(frame_vector("json-high-priority", &frame, &shared));
+
+    clock.advance(250);
+    let frame = security.create_rekey_frame(&alice, &bob.x_pub, &bob.id);
+    frames.push(frame_vector("rekey", &frame, &shared));
+    security.confirm_rekey(&bob.id, frame.key_epoch);
+
+    clock.advance(250);
+    let frame = security.create_auth_frame(&alice, &bob.x_pub, FrameType::Msg, &bob.id, b"after rekey".to_vec());
+    frames.push(frame_vector("msg-epoch-1", &frame, &shared));
+
+    VectorSet {
+        version: VECTORS_VERSION,
+        identities: vec![alice_vector, bob_vector],
+        shared_secret: hex::encode(shared),
+        frames,
+    }
+}
+
+fn check(what: &str, expected: &str, actual: &str) -> Result<(), String> {
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(format!("{} mismatch: expected {}, computed {}", what, expected, actual))
+    }
+}
+
+fn decode_key(hex_key: &str, what: &str) -> Result<[u8; 32], String> {
+    hex::decode(hex_key)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| format!("Invalid {}", what))
+}
+
+/// Check vectors, e.g. produced by another SDK, against this implementation
+///
+/// Every identity, intermediate value and frame must be reproduced exactly,
+/// and the recipient must accept the frames in order.
+///
+/// # Returns
+/// `Err` naming the first value that differs
+pub fn verify_vectors(vectors: &VectorSet) -> Result<(), String> {
+    if vectors.version != VECTORS_VERSION {
+        return Err(format!("Unsupported vectors version: {}", vectors.version));
+    }
+    let [sender, recipient] = &vectors.identities[..] else {
+        return Err("Expected two identities".into());
+    };
+    let mut identities = Vec::new();
+    for vector in [sender, recipient] {
+        let seed = decode_key(&vector.seed, "seed")?;
+        let (identity, expected) = identity_vector(&vector.name, seed);
+        if &expected != vector {
+            return Err(format!("Identity {} mismatch: expected {:?}, computed {:?}", vector.name, vector, expected));
+        }
+        identities.push(identity);
+    }
+    let (alice, bob) = (&identities[0], &identities[1]);
+    let shared = SecurityManager::derive_shared_secret(&alice.x_priv, &bob.x_pub);
+    check("Shared secret", &vectors.shared_secret, &hex::encode(shared))?;
+    check("Recipient shared secret", &vectors.shared_secret, &hex::encode(SecurityManager::derive_shared_secret(&bob.x_priv, &alice.x_pub)))?;
+
+    let clock = Arc::new(ManualClock::new(0));
+    let mut receiver = SecurityManager::with_clock(clock.clone());
+    for vector in &vectors.frames {
+        let bytes = hex::decode(&vector.cbor).map_err(|e| format!("Frame {}: {}", vector.name, e))?;
+        let frame = CBORCodec::decode(&bytes).map_err(|e| format!("Frame {}: {}", vector.name, e))?;
+        let computed = frame_vector(&vector.name, &frame, &shared);
+        check(&format!("Frame {} CBOR", vector.name), &vector.cbor, &computed.cbor)?;
+        check(&format!("Frame {} session info", vector.name), &vector.session_info, &computed.session_info)?;
+        check(&format!("Frame {} session key", vector.name), &vector.session_key, &computed.session_key)?;
+        check(&format!("Frame {} HMAC input", vector.name), &vector.hmac_input, &computed.hmac_input)?;
+        let hmac = SecurityManager::generate_hmac(&decode_key(&vector.session_key, "session key")?, &vector.hmac_input);
+        check(&format!("Frame {} HMAC", vector.name), &vector.hmac, &hmac)?;
+        check(&format!("Frame {} signing input", vector.name), &vector.signing_input, &computed.signing_input)?;
+        check(&format!("Frame {} signature", vector.name), &vector.sig, &computed.sig)?;
+
+        clock.set(frame.ts);
+        receiver
+            .verify_auth_frame(&frame, &alice.ed_pub, &bob.x_priv, &alice.x_pub)
+            .map_err(|e| format!("Frame {} rejected: {}", vector.name, e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_vectors() {
+        let vectors = generate_vectors();
+        assert_eq!(vectors, generate_vectors());
+        assert!(verify_vectors(&vectors).is_ok());
+
+        let mut tampered = vectors.clone();
+        tampered.frames[1].hmac_input.push('x');
+        assert!(verify_vectors(&tampered).unwrap_err().contains("json-high-priority HMAC input"));
+
+        // Frames depend on each other through the key epoch
+        let mut reordered = vectors;
+        reordered.frames.swap(2, 3);
+        assert!(verify_vectors(&reordered).unwrap_err().contains("rejected"));
+    }
+}
@@ -0,0 +1,175 @@
+//! Payload content types
+//!
+//! Frames declare how their payload is encoded so receivers can decode it
+//! without guessing. The content type is covered by the sender's signature
+//! and describes the payload after decompression.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use crate::types::OpacusFrame;
+
+/// Payload encoding
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentType {
+    /// Opaque bytes
+    #[default]
+    Raw,
+    /// UTF-8 JSON document
+    Json,
+    /// CBOR item
+    Cbor,
+    /// Protobuf message (schema agreed out of band)
+    Protobuf,
+    /// UTF-8 text
+    Text,
+    /// JSON `PayloadRef` to a payload stored off the relay path
+    Reference,
+}
+
+impl ContentType {
+    /// Content type name as used on the wire
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentType::Raw => "raw",
+            ContentType::Json => "json",
+            ContentType::Cbor => "cbor",
+            ContentType::Protobuf => "protobuf",
+            ContentType::Text => "text",
+            ContentType::Reference => "reference",
+        }
+    }
+
+    /// Numeric code (`Raw` is 0 so it can be omitted)
+    pub fn code(&self) -> u8 {
+        match self {
+            ContentType::Raw => 0,
+            ContentType::Json => 1,
+            ContentType::Cbor => 2,
+            ContentType::Protobuf => 3,
+            ContentType::Text => 4,
+            ContentType::Reference => 5,
+        }
+    }
+
+    /// Look up a content type by code
+    pub fn from_code(code: u8) -> Option<ContentType> {
+        match code {
+            0 => Some(ContentType::Raw),
+            1 => Some(ContentType::Json),
+            2 => Some(ContentType::Cbor),
+            3 => Some(ContentType::Protobuf),
+            4 => Some(ContentType::Text),
+            5 => Some(ContentType::Reference),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn is_raw(&self) -> bool {
+        *self == ContentType::Raw
+    }
+
+    /// Render a payload of this type for display
+    ///
+    /// JSON is pretty-printed, CBOR shown in diagnostic form, text as-is, and
+    /// raw or protobuf bytes as hex. Payloads that fail to decode fall back to hex.
+    pub fn render(&self, payload: &[u8]) -> String {
+        let rendered = match self {
+            ContentType::Json | ContentType::Reference => serde_json::from_slice::<serde_json::Value>(payload)
+                .ok()
+                .and_then(|v| serde_json::to_string_pretty(&v).ok()),
+            ContentType::Cbor => ciborium::de::from_reader::<ciborium::Value, _>(payload)
+                .ok()
+                .map(|v| format!("{:?}", v)),
+            ContentType::Text => std::str::from_utf8(payload).ok().map(String::from),
+            ContentType::Raw | ContentType::Protobuf => None,
+        };
+        rendered.unwrap_or_else(|| hex::encode(payload))
+    }
+}
+
+impl std::str::FromStr for ContentType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(ContentType::Raw),
+            "json" => Ok(ContentType::Json),
+            "cbor" => Ok(ContentType::Cbor),
+            "protobuf" => Ok(ContentType::Protobuf),
+            "text" => Ok(ContentType::Text),
+            "reference" => Ok(ContentType::Reference),
+            _ => Err(format!("Unknown content type: {}", s)),
+        }
+    }
+}
+
+impl OpacusFrame {
+    /// Decode a JSON or CBOR payload into `T`
+    pub fn payload_as<T: DeserializeOwned>(&self) -> Result<T, String> {
+        let payload = self.decompressed_payload()?;
+        match self.content_type {
+            ContentType::Json => serde_json::from_slice(&payload).map_err(|e| format!("Invalid JSON payload: {}", e)),
+            ContentType::Cbor => ciborium::de::from_reader(payload.as_ref())
+                .map_err(|e| format!("Invalid CBOR payload: {}", e)),
+            other => Err(format!("Cannot decode {} payload", other.as_str())),
+        }
+    }
+
+    /// Get a text payload
+    pub fn payload_text(&self) -> Result<String, String> {
+        if self.content_type != ContentType::Text {
+            return Err(format!("Expected text payload, got {}", self.content_type.as_str()));
+        }
+        let payload = self.decompressed_payload()?;
+        String::from_utf8(payload.into_owned()).map_err(|e| format!("Invalid UTF-8 payload: {}", e))
+    }
+
+    /// Render the payload for display according to its content type
+    pub fn render_payload(&self) -> String {
+        match self.decompressed_payload() {
+            Ok(payload) => self.content_type.render(&payload),
+            Err(e) => format!("<{}>", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(content_type: ContentType, payload: Vec<u8>) -> OpacusFrame {
+        OpacusFrame { payload: payload.into(), content_type, ..OpacusFrame::test(1) }
+    }
+
+    #[test]
+    fn test_typed_payloads() {
+        let value = serde_json::json!({ "task": "inference", "step": 3 });
+
+        let json = frame(ContentType::Json, serde_json::to_vec(&value).unwrap());
+        assert_eq!(json.payload_as::<serde_json::Value>().unwrap(), value);
+        assert!(json.payload_text().is_err());
+
+        let mut cbor = Vec::new();
+        ciborium::ser::into_writer(&value, &mut cbor).unwrap();
+        let cbor = frame(ContentType::Cbor, cbor);
+        assert_eq!(cbor.payload_as::<serde_json::Value>().unwrap(), value);
+
+        let text = frame(ContentType::Text, b"hello".to_vec());
+        assert_eq!(text.payload_text().unwrap(), "hello");
+        assert!(frame(ContentType::Raw, vec![1]).payload_as::<u8>().is_err());
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(ContentType::Json.render(br#"{"a":1}"#), "{\n  \"a\": 1\n}");
+        assert_eq!(ContentType::Text.render(b"hi"), "hi");
+        assert_eq!(ContentType::Raw.render(&[0xde, 0xad]), "dead");
+        // Undecodable payloads fall back to hex
+        assert_eq!(ContentType::Json.render(&[0xff]), "ff");
+        for code in 0..6 {
+            let ct = ContentType::from_code(code).unwrap();
+            assert_eq!(ct.as_str().parse::<ContentType>(), Ok(ct));
+        }
+    }
+}
@@ -0,0 +1,426 @@
+//! Shared state documents (CRDTs)
+//!
+//! A [`SharedDocument`] holds named counters, maps and texts that several
+//! agents edit concurrently without a coordinator. Every local edit returns a
+//! [`CrdtUpdate`] that is sent to the other replicas, which apply it in any
+//! order: updates carry causal metadata (the sender's sequence number and the
+//! version vector it had seen) and wait until what they depend on has been
+//! applied, so every replica that applied the same updates holds the same
+//! state.
+//!
+//! - Counters add up increments and decrements.
+//! - Map entries are last-writer-wins, ordered by Lamport timestamp and
+//!   replica ID; removals win over earlier writes only.
+//! - Texts are replicated growable arrays (RGA): characters are inserted after
+//!   a character ID, concurrent insertions at the same place are ordered by
+//!   ID, and deleted characters are kept as tombstones.
+//!
+//! Clients replicate documents in `Stream` frames on the channel
+//! `crdt/<document ID>`, whose data is a JSON [`CrdtMessage`]. A replica that
+//! notices missing updates sends its version vector in a
+//! [`CrdtMessage::Sync`] and gets back what it lacks.
+
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+
+/// Prefix of the stream channels documents are replicated on
+pub const DOCUMENT_CHANNEL_PREFIX: &str = "crdt/";
+
+/// Maximum updates waiting for their dependencies; later ones are dropped
+/// until a sync fills the gap
+pub const MAX_PENDING_UPDATES: usize = 1024;
+
+/// Maximum updates per `Stream` frame when answering a sync request
+pub const SYNC_CHUNK_UPDATES: usize = 64;
+
+/// Number of updates applied from each replica
+pub type VersionVector = BTreeMap<String, u64>;
+
+/// Unique, totally ordered ID of an operation (Lamport timestamp, then replica)
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct OpId {
+    pub lamport: u64,
+    pub replica: String,
+}
+
+/// Operation on one field of a document
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "camelCase")]
+pub enum CrdtOp {
+    /// Add `delta` to a counter
+    Increment { counter: String, delta: i64 },
+    /// Set a map entry
+    Set { map: String, key: String, value: serde_json::Value, id: OpId },
+    /// Remove a map entry
+    Remove { map: String, key: String, id: OpId },
+    /// Insert characters after `after` (`None` for the start); character
+    /// `i` gets the ID `id` with `lamport + i`
+    Insert { text: String, after: Option<OpId>, id: OpId, chars: String },
+    /// Delete characters
+    Delete { text: String, targets: Vec<OpId> },
+}
+
+impl CrdtOp {
+    /// Highest Lamport timestamp used by the operation
+    fn lamport(&self) -> u64 {
+        match self {
+            CrdtOp::Increment { .. } | CrdtOp::Delete { .. } => 0,
+            CrdtOp::Set { id, .. } | CrdtOp::Remove { id, .. } => id.lamport,
+            CrdtOp::Insert { id, chars, .. } => id.lamport + (chars.chars().count() as u64).saturating_sub(1),
+        }
+    }
+
+    /// Replica the operation's IDs belong to
+    fn replica(&self) -> Option<&str> {
+        match self {
+            CrdtOp::Increment { .. } | CrdtOp::Delete { .. } => None,
+            CrdtOp::Set { id, .. } | CrdtOp::Remove { id, .. } | CrdtOp::Insert { id, .. } => Some(&id.replica),
+        }
+    }
+}
+
+/// Operations of one local edit, with causal metadata
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CrdtUpdate {
+    pub doc_id: String,
+    /// Replica that made the edit
+    pub replica: String,
+    /// Number of the update among the replica's updates (from 1)
+    pub seq: u64,
+    /// Updates of other replicas the edit was made on
+    pub deps: VersionVector,
+    pub ops: Vec<CrdtOp>,
+}
+
+/// Document replication message, carried in `Stream` frames
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum CrdtMessage {
+    /// Updates to apply, in causal order
+    Updates { updates: Vec<CrdtUpdate> },
+    /// Request for the updates missing from `version`
+    Sync { version: VersionVector },
+}
+
+#[derive(Debug, Clone)]
+struct TextElement {
+    id: OpId,
+    ch: char,
+    deleted: bool,
+}
+
+/// Replica of a shared document
+#[derive(Debug, Clone)]
+pub struct SharedDocument {
+    id: String,
+    replica: String,
+    lamport: u64,
+    version: VersionVector,
+    counters: BTreeMap<String, i64>,
+    /// Entries with the ID of their last write (`None` once removed)
+    maps: BTreeMap<String, BTreeMap<String, (OpId, Option<serde_json::Value>)>>,
+    texts: BTreeMap<String, Vec<TextElement>>,
+    /// Updates waiting for their dependencies
+    pending: Vec<CrdtUpdate>,
+    /// Applied updates, in causal order, for replicas that missed them
+    log: Vec<CrdtUpdate>,
+}
+
+impl SharedDocument {
+    /// Empty document edited as `replica` (usually the agent ID)
+    pub fn new(id: &str, replica: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            replica: replica.to_string(),
+            lamport: 0,
+            version: VersionVector::new(),
+            counters: BTreeMap::new(),
+            maps: BTreeMap::new(),
+            texts: BTreeMap::new(),
+            pending: Vec::new(),
+            log: Vec::new(),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn replica(&self) -> &str {
+        &self.replica
+    }
+
+    /// Stream channel the document is replicated on
+    pub fn channel_id(&self) -> String {
+        format!("{}{}", DOCUMENT_CHANNEL_PREFIX, self.id)
+    }
+
+    /// Number of updates applied from each replica
+    pub fn version(&self) -> &VersionVector {
+        &self.version
+    }
+
+    /// Whether received updates are waiting for updates not received yet
+    pub fn has_gaps(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Value of a counter (`0` if never changed)
+    pub fn counter(&self, name: &str) -> i64 {
+        self.counters.get(name).copied().unwrap_or_default()
+    }
+
+    /// Value of a map entry
+    pub fn get(&self, map: &str, key: &str) -> Option<&serde_json::Value> {
+        self.maps.get(map)?.get(key)?.1.as_ref()
+    }
+
+    /// Entries of a map
+    pub fn map(&self, name: &str) -> BTreeMap<String, serde_json::Value> {
+        self.maps
+            .get(name)
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter_map(|(key, (_, value))| Some((key.clone(), value.clone()?)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Content of a text
+    pub fn text(&self, name: &str) -> String {
+        self.texts
+            .get(name)
+            .map(|elements| elements.iter().filter(|e| !e.deleted).map(|e| e.ch).collect())
+            .unwrap_or_default()
+    }
+
+    /// Add `delta` to a counter
+    pub fn increment(&mut self, counter: &str, delta: i64) -> CrdtUpdate {
+        self.commit(vec![CrdtOp::Increment { counter: counter.to_string(), delta }])
+    }
+
+    /// Set a map entry
+    pub fn set(&mut self, map: &str, key: &str, value: serde_json::Value) -> CrdtUpdate {
+        let id = self.next_id(1);
+        self.commit(vec![CrdtOp::Set { map: map.to_string(), key: key.to_string(), value, id }])
+    }
+
+    /// Remove a map entry
+    pub fn remove(&mut self, map: &str, key: &str) -> CrdtUpdate {
+        let id = self.next_id(1);
+        self.commit(vec![CrdtOp::Remove { map: map.to_string(), key: key.to_string(), id }])
+    }
+
+    /// Insert a string at a character index of a text (clamped to its length)
+    pub fn insert_text(&mut self, text: &str, index: usize, chars: &str) -> CrdtUpdate {
+        let after = index.checked_sub(1).and_then(|i| self.visible_id(text, i));
+        let id = self.next_id(chars.chars().count() as u64);
+        self.commit(vec![CrdtOp::Insert { text: text.to_string(), after, id, chars: chars.to_string() }])
+    }
+
+    /// Delete `len` characters of a text from a character index
+    pub fn delete_text(&mut self, text: &str, index: usize, len: usize) -> CrdtUpdate {
+        let targets = self
+            .texts
+            .get(text)
+            .map(|elements| elements.iter().filter(|e| !e.deleted).skip(index).take(len).map(|e| e.id.clone()).collect())
+            .unwrap_or_default();
+        self.commit(vec![CrdtOp::Delete { text: text.to_string(), targets }])
+    }
+
+    /// Apply a received update, and any waiting updates it makes applicable
+    ///
+    /// Updates of other documents, already applied updates, and updates
+    /// whose IDs belong to another replica are ignored.
+    ///
+    /// # Returns
+    /// Number of updates applied
+    pub fn apply(&mut self, update: CrdtUpdate) -> usize {
+        if update.doc_id != self.id
+            || update.seq <= self.seen(&update.replica)
+            || update.ops.iter().any(|op| op.replica().is_some_and(|r| r != update.replica))
+        {
+            return 0;
+        }
+        if self.pending.len() >= MAX_PENDING_UPDATES {
+            return 0;
+        }
+        self.pending.push(update);
+
+        let mut applied = 0;
+        while let Some(index) = self.pending.iter().position(|u| self.is_ready(u)) {
+            let update = self.pending.swap_remove(index);
+            self.integrate(update);
+            applied += 1;
+        }
+        let version = &self.version;
+        self.pending.retain(|u| u.seq > version.get(&u.replica).copied().unwrap_or_default());
+        applied
+    }
+
+    /// Applied updates that a replica at `version` is missing, in causal order
+    pub fn updates_since(&self, version: &VersionVector) -> Vec<CrdtUpdate> {
+        self.log
+            .iter()
+            .filter(|u| u.seq > version.get(&u.replica).copied().unwrap_or_default())
+            .cloned()
+            .collect()
+    }
+
+    fn seen(&self, replica: &str) -> u64 {
+        self.version.get(replica).copied().unwrap_or_default()
+    }
+
+    fn is_ready(&self, update: &CrdtUpdate) -> bool {
+        update.seq == self.seen(&update.replica) + 1
+            && update.deps.iter().all(|(replica, seq)| replica == &update.replica || self.seen(replica) >= *seq)
+    }
+
+    /// Reserve `count` consecutive Lamport timestamps
+    fn next_id(&mut self, count: u64) -> OpId {
+        let id = OpId { lamport: self.lamport + 1, replica: self.replica.clone() };
+        self.lamport += count.max(1);
+        id
+    }
+
+    fn commit(&mut self, ops: Vec<CrdtOp>) -> CrdtUpdate {
+        let mut deps = self.version.clone();
+        deps.remove(&self.replica);
+        let update = CrdtUpdate { doc_id: self.id.clone(), replica: self.replica.clone(), seq: self.seen(&self.replica) + 1, deps, ops };
+        self.integrate(update.clone());
+        update
+    }
+
+    fn integrate(&mut self, update: CrdtUpdate) {
+        for op in &update.ops {
+            self.lamport = self.lamport.max(op.lamport());
+            self.apply_op(op);
+        }
+        self.version.insert(update.replica.clone(), update.seq);
+        self.log.push(update);
+    }
+
+    fn apply_op(&mut self, op: &CrdtOp) {
+        match op {
+            CrdtOp::Increment { counter, delta } => {
+                let value = self.counters.entry(counter.clone()).or_default();
+                *value = value.saturating_add(*delta);
+            }
+            CrdtOp::Set { map, key, value, id } => self.write_entry(map, key, id, Some(value.clone())),
+            CrdtOp::Remove { map, key, id } => self.write_entry(map, key, id, None),
+            CrdtOp::Insert { text, after, id, chars } => {
+                let elements = self.texts.entry(text.clone()).or_default();
+                let mut origin = after.clone();
+                for (i, ch) in chars.chars().enumerate() {
+                    let id = OpId { lamport: id.lamport + i as u64, replica: id.replica.clone() };
+                    let mut position = match &origin {
+                        None => 0,
+                        Some(origin) => match elements.iter().position(|e| e.id == *origin) {
+                            Some(index) => index + 1,
+                            // Unknown origin: the update was not made on this document
+                            None => return,
+                        },
+                    };
+                    // Concurrent insertions after the same character go in ID order, newest first
+                    while position < elements.len() && elements[position].id > id {
+                        position += 1;
+                    }
+                    elements.insert(position, TextElement { id: id.clone(), ch, deleted: false });
+                    origin = Some(id);
+                }
+            }
+            CrdtOp::Delete { text, targets } => {
+                if let Some(elements) = self.texts.get_mut(text) {
+                    for element in elements.iter_mut().filter(|e| targets.contains(&e.id)) {
+                        element.deleted = true;
+                    }
+                }
+            }
+        }
+    }
+
+    fn write_entry(&mut self, map: &str, key: &str, id: &OpId, value: Option<serde_json::Value>) {
+        let entries = self.maps.entry(map.to_string()).or_default();
+        if entries.get(key).is_none_or(|(current, _)| current < id) {
+            entries.insert(key.to_string(), (id.clone(), value));
+        }
+    }
+
+    /// ID of the visible character at an index (the last one if past the end)
+    fn visible_id(&self, text: &str, index: usize) -> Option<OpId> {
+        let visible = self.texts.get(text)?.iter().filter(|e| !e.deleted);
+        visible.take(index + 1).last().map(|e| e.id.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_document() {
+        let mut alice = SharedDocument::new("plan", "alice");
+        let mut bob = SharedDocument::new("plan", "bob");
+
+        // Concurrent edits on both replicas
+        let a1 = alice.insert_text("notes", 0, "hello");
+        let a2 = alice.increment("votes", 2);
+        let a3 = alice.set("config", "mode", serde_json::json!("fast"));
+        let b1 = bob.insert_text("notes", 0, "world");
+        let b2 = bob.increment("votes", -1);
+        let b3 = bob.set("config", "mode", serde_json::json!("safe"));
+
+        // Out of order delivery waits for dependencies
+        assert_eq!(bob.apply(a3.clone()), 0);
+        assert!(bob.has_gaps());
+        assert_eq!(bob.apply(a2.clone()), 0);
+        assert_eq!(bob.apply(a1.clone()), 3);
+        assert!(!bob.has_gaps());
+        assert_eq!(bob.apply(a1.clone()), 0);
+        for update in [b1, b2, b3] {
+            assert_eq!(alice.apply(update), 1);
+        }
+        for doc in [&alice, &bob] {
+            assert_eq!(doc.counter("votes"), 1);
+            assert_eq!(doc.text("notes"), "worldhello");
+            // The later write wins (same Lamport time, higher replica ID)
+            assert_eq!(doc.get("config", "mode"), Some(&serde_json::json!("safe")));
+        }
+
+        // Edits made on merged state, and deletions
+        let a4 = alice.insert_text("notes", 5, ", ");
+        let b4 = bob.delete_text("notes", 0, 5);
+        let b5 = bob.remove("config", "mode");
+        alice.apply(b4);
+        alice.apply(b5);
+        bob.apply(a4);
+        assert_eq!(alice.text("notes"), ", hello");
+        assert_eq!(bob.text("notes"), alice.text("notes"));
+        assert!(alice.map("config").is_empty() && bob.get("config", "mode").is_none());
+        assert_eq!(alice.version(), bob.version());
+
+        // A new replica catches up from any other
+        let mut carol = SharedDocument::new("plan", "carol");
+        for update in bob.updates_since(carol.version()) {
+            carol.apply(update);
+        }
+        assert_eq!((carol.text("notes"), carol.counter("votes")), (alice.text("notes"), 1));
+        assert!(alice.updates_since(bob.version()).is_empty());
+
+        // Updates of another document, or claiming another replica's IDs
+        let mut other = SharedDocument::new("other", "alice");
+        assert_eq!(carol.apply(other.increment("votes", 1)), 0);
+        let mut forged = alice.set("config", "mode", serde_json::json!("x"));
+        forged.replica = "mallory".into();
+        forged.seq = 1;
+        assert_eq!(carol.apply(forged), 0);
+
+        let message = CrdtMessage::Sync { version: carol.version().clone() };
+        let json = serde_json::to_value(&message).unwrap();
+        assert_eq!(json["type"], "sync");
+        assert_eq!(serde_json::from_value::<CrdtMessage>(json).unwrap(), message);
+    }
+}
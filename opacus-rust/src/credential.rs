@@ -0,0 +1,664 @@
+//! Decentralized identifiers and Verifiable Credentials
+//!
+//! Agents are named by two DIDs: `did:key` of their Ed25519 key (multicodec
+//! `ed25519-pub`, base58btc) and `did:pkh` of their account on their chain
+//! (`did:pkh:eip155:<chain>:<address>`). Either can be the subject of a W3C
+//! [`VerifiableCredential`], so agents can prove attributes such as a KYC'd
+//! operator or a certified model to each other.
+//!
+//! Credentials and presentations are secured with a `DataIntegrityProof` of
+//! the `eddsa-jcs-2022` cryptosuite: the Ed25519 signature covers the
+//! SHA-256 of the JSON Canonicalization Scheme (RFC 8785) form of the proof
+//! options followed by that of the document. Proofs are checked over the
+//! JSON as received, so properties this module does not model are covered
+//! too. Issuers and holders must be `did:key`s, whose public key is in the
+//! identifier itself, so no DID resolution is needed.
+//!
+//! Agents exchange them in frame extensions: an issuer hands a credential to
+//! its subject in [`CREDENTIAL_EXTENSION`], a verifier asks for credentials
+//! of some types with a [`PresentationRequest`] and a fresh challenge, and
+//! the holder answers with a [`VerifiablePresentation`] that signs the
+//! challenge and the verifier's agent ID.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::crypto::{KeyManager, SecurityManager};
+use crate::types::AgentIdentity;
+
+/// Frame extension carrying a [`VerifiableCredential`] issued to the recipient
+pub const CREDENTIAL_EXTENSION: &str = "verifiableCredential";
+
+/// Frame extension carrying a [`PresentationRequest`]
+pub const PRESENTATION_REQUEST_EXTENSION: &str = "presentationRequest";
+
+/// Frame extension carrying a [`VerifiablePresentation`]
+pub const PRESENTATION_EXTENSION: &str = "verifiablePresentation";
+
+/// JSON-LD context of W3C Verifiable Credentials 2.0
+pub const CREDENTIALS_CONTEXT: &str = "https://www.w3.org/ns/credentials/v2";
+
+/// Multicodec prefix of Ed25519 public keys (`0xed` as varint)
+const ED25519_MULTICODEC: [u8; 2] = [0xed, 0x01];
+
+const DID_KEY_PREFIX: &str = "did:key:z";
+const DID_PKH_PREFIX: &str = "did:pkh:eip155:";
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+fn base58_encode(bytes: &[u8]) -> String {
+    let mut digits: Vec<u8> = Vec::with_capacity(bytes.len() * 138 / 100 + 1);
+    for &byte in bytes {
+        let mut carry = byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let zeros = bytes.iter().take_while(|&&b| b == 0).count();
+    let mut encoded = "1".repeat(zeros);
+    encoded.extend(digits.iter().rev().map(|&d| char::from(BASE58_ALPHABET[d as usize])));
+    encoded
+}
+
+fn base58_decode(s: &str) -> Result<Vec<u8>, String> {
+    let mut bytes: Vec<u8> = Vec::with_capacity(s.len());
+    for c in s.bytes() {
+        let mut carry = BASE58_ALPHABET
+            .iter()
+            .position(|&a| a == c)
+            .ok_or_else(|| format!("Invalid base58 character: {}", c as char))? as u32;
+        for byte in bytes.iter_mut() {
+            carry += (*byte as u32) * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+    let mut decoded = vec![0; s.bytes().take_while(|&c| c == b'1').count()];
+    decoded.extend(bytes.into_iter().rev());
+    Ok(decoded)
+}
+
+/// `did:key` of an Ed25519 public key
+pub fn did_key(ed_pub: &[u8; 32]) -> String {
+    let mut key = ED25519_MULTICODEC.to_vec();
+    key.extend_from_slice(ed_pub);
+    format!("{}{}", DID_KEY_PREFIX, base58_encode(&key))
+}
+
+/// Ed25519 public key of a `did:key` (a DID URL's fragment is ignored)
+pub fn did_key_public_key(did: &str) -> Result<[u8; 32], String> {
+    let did = did.split('#').next().unwrap_or_default();
+    let encoded = did.strip_prefix(DID_KEY_PREFIX).ok_or_else(|| format!("Not a base58 did:key: {}", did))?;
+    let key = base58_decode(encoded)?;
+    match key.strip_prefix(&ED25519_MULTICODEC[..]) {
+        Some(ed_pub) => ed_pub.try_into().map_err(|_| "Invalid Ed25519 key length in did:key".to_string()),
+        None => Err(format!("Not an Ed25519 did:key: {}", did)),
+    }
+}
+
+/// `did:pkh` of an account on an EVM chain
+pub fn did_pkh(chain_id: u64, address: &str) -> String {
+    format!("{}{}:{}", DID_PKH_PREFIX, chain_id, address)
+}
+
+impl AgentIdentity {
+    /// `did:key` of the agent's Ed25519 key
+    pub fn did_key(&self) -> String {
+        did_key(&self.ed_pub)
+    }
+
+    /// `did:pkh` of the agent's account on its chain
+    pub fn did_pkh(&self) -> String {
+        did_pkh(self.chain_id, &self.address)
+    }
+}
+
+/// Whether `did` names the agent whose Ed25519 key is `ed_pub`, by
+/// `did:key` or by the `did:pkh` of its agent address on any chain
+pub fn did_names_key(did: &str, ed_pub: &[u8; 32]) -> bool {
+    if did_key_public_key(did).is_ok_and(|key| key == *ed_pub) {
+        return true;
+    }
+    let address = format!("0x{}", KeyManager::agent_id(ed_pub));
+    did.strip_prefix(DID_PKH_PREFIX)
+        .and_then(|rest| rest.split_once(':'))
+        .is_some_and(|(chain, account)| chain.parse::<u64>().is_ok() && account.eq_ignore_ascii_case(&address))
+}
+
+/// XML Schema date-time of a Unix time in milliseconds
+fn date_time(ms: u64) -> String {
+    DateTime::<Utc>::from_timestamp_millis(ms as i64)
+        .unwrap_or_default()
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Unix time in milliseconds of an XML Schema date-time
+fn parse_date_time(s: &str) -> Result<u64, String> {
+    DateTime::parse_from_rfc3339(s)
+        .map(|t| t.timestamp_millis().max(0) as u64)
+        .map_err(|e| format!("Invalid date-time {}: {}", s, e))
+}
+
+/// JSON Canonicalization Scheme (RFC 8785)
+///
+/// Object keys are sorted by UTF-16 code units, numbers are written as
+/// ECMAScript does, and there is no whitespace.
+fn canonical_json(value: &serde_json::Value) -> Vec<u8> {
+    fn write(value: &serde_json::Value, out: &mut String) {
+        match value {
+            serde_json::Value::Object(map) => {
+                let mut entries: Vec<_> = map.iter().collect();
+                entries.sort_by(|a, b| a.0.encode_utf16().cmp(b.0.encode_utf16()));
+                out.push('{');
+                for (i, (key, value)) in entries.into_iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    out.push_str(&serde_json::to_string(key).expect("JSON string"));
+                    out.push(':');
+                    write(value, out);
+                }
+                out.push('}');
+            }
+            serde_json::Value::Array(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write(item, out);
+                }
+                out.push(']');
+            }
+            serde_json::Value::Number(n) => out.push_str(&es_number(n.as_f64().unwrap_or_default())),
+            other => out.push_str(&serde_json::to_string(other).expect("JSON value")),
+        }
+    }
+    let mut out = String::new();
+    write(value, &mut out);
+    out.into_bytes()
+}
+
+/// ECMAScript `Number.prototype.toString` of a finite number
+fn es_number(x: f64) -> String {
+    if x == 0.0 {
+        return "0".into();
+    }
+    if x < 0.0 {
+        return format!("-{}", es_number(-x));
+    }
+    // Shortest round-trip digits d.ddd and exponent, as x = 0.ddd × 10^n
+    let scientific = format!("{:e}", x);
+    let (mantissa, exponent) = scientific.split_once('e').expect("LowerExp exponent");
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let (k, n) = (digits.len() as i32, exponent.parse::<i32>().expect("LowerExp exponent") + 1);
+    if k <= n && n <= 21 {
+        format!("{}{}", digits, "0".repeat((n - k) as usize))
+    } else if 0 < n && n <= 21 {
+        format!("{}.{}", &digits[..n as usize], &digits[n as usize..])
+    } else if -6 < n && n <= 0 {
+        format!("0.{}{}", "0".repeat(-n as usize), digits)
+    } else {
+        let sign = if n - 1 < 0 { '-' } else { '+' };
+        let fraction = if k > 1 { format!(".{}", &digits[1..]) } else { String::new() };
+        format!("{}{}e{}{}", &digits[..1], fraction, sign, (n - 1).abs())
+    }
+}
+
+/// `eddsa-jcs-2022` Data Integrity proof
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DataIntegrityProof {
+    /// Always `DataIntegrityProof`
+    #[serde(rename = "type")]
+    pub proof_type: String,
+    /// Always `eddsa-jcs-2022`
+    pub cryptosuite: String,
+    /// Signing time
+    pub created: String,
+    /// Signer's `did:key`, with its key as fragment
+    pub verification_method: String,
+    /// `assertionMethod` for credentials, `authentication` for presentations
+    pub proof_purpose: String,
+    /// Verifier's challenge (presentations)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub challenge: Option<String>,
+    /// Agent the presentation is for (presentations)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    /// Multibase (base58btc) Ed25519 signature
+    pub proof_value: String,
+    /// Other proof options, covered by the signature
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl DataIntegrityProof {
+    const TYPE: &'static str = "DataIntegrityProof";
+    const CRYPTOSUITE: &'static str = "eddsa-jcs-2022";
+
+    /// Hash of the proof options and the unsecured document
+    fn signing_data(options: &serde_json::Value, document: &serde_json::Value) -> Vec<u8> {
+        let mut data = Sha256::digest(canonical_json(options)).to_vec();
+        data.extend_from_slice(&Sha256::digest(canonical_json(document)));
+        data
+    }
+
+    fn sign(
+        signer: &AgentIdentity,
+        document: &serde_json::Value,
+        purpose: &str,
+        challenge: Option<String>,
+        domain: Option<String>,
+        ts: u64,
+    ) -> Self {
+        let did = signer.did_key();
+        let mut proof = Self {
+            proof_type: Self::TYPE.to_string(),
+            cryptosuite: Self::CRYPTOSUITE.to_string(),
+            created: date_time(ts),
+            verification_method: format!("{}#{}", did, &did["did:key:".len()..]),
+            proof_purpose: purpose.to_string(),
+            challenge,
+            domain,
+            proof_value: String::new(),
+            extra: serde_json::Map::new(),
+        };
+        let mut options = serde_json::to_value(&proof).expect("JSON proof");
+        options.as_object_mut().expect("JSON object").remove("proofValue");
+        let signature = SecurityManager::sign(&signer.ed_priv, &Self::signing_data(&options, document));
+        proof.proof_value = format!("z{}", base58_encode(&signature));
+        proof
+    }
+
+    /// Check the proof of a secured JSON document was made by `signer` for `purpose`
+    ///
+    /// The signature is checked over the document and proof exactly as
+    /// given, not over their re-serialization.
+    ///
+    /// # Returns
+    /// The proof
+    fn verify(secured: &serde_json::Value, signer: &str, purpose: &str) -> Result<Self, String> {
+        let mut document = secured.as_object().ok_or("Document is not a JSON object")?.clone();
+        let mut options = document.remove("proof").ok_or("Document is not signed")?;
+        let proof: Self = serde_json::from_value(options.clone()).map_err(|e| format!("Invalid proof: {}", e))?;
+        if proof.proof_type != Self::TYPE || proof.cryptosuite != Self::CRYPTOSUITE {
+            return Err(format!("Unsupported proof {} {}", proof.proof_type, proof.cryptosuite));
+        }
+        if proof.proof_purpose != purpose {
+            return Err(format!("Expected {} proof, got {}", purpose, proof.proof_purpose));
+        }
+        if proof.verification_method.split('#').next() != Some(signer) {
+            return Err(format!("Proof by {} instead of {}", proof.verification_method, signer));
+        }
+        let ed_pub = did_key_public_key(signer)?;
+        let signature = proof
+            .proof_value
+            .strip_prefix('z')
+            .ok_or("Proof value is not base58btc")
+            .and_then(|v| base58_decode(v).map_err(|_| "Proof value is not base58btc"))?;
+        options.as_object_mut().expect("JSON object").remove("proofValue");
+        let data = Self::signing_data(&options, &serde_json::Value::Object(document));
+        if !SecurityManager::verify(&ed_pub, &data, &signature) {
+            return Err(format!("Invalid proof signature by {}", signer));
+        }
+        Ok(proof)
+    }
+}
+
+/// W3C Verifiable Credential (data model 2.0)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifiableCredential {
+    #[serde(rename = "@context")]
+    pub context: Vec<String>,
+    /// Credential identifier (`urn:uuid:` or similar)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// `VerifiableCredential` followed by the application's types
+    #[serde(rename = "type")]
+    pub types: Vec<String>,
+    /// Issuer's `did:key`
+    pub issuer: String,
+    /// Start of validity
+    pub valid_from: String,
+    /// End of validity (`None` if it does not expire)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_until: Option<String>,
+    /// Claims about the subject, including its DID as `id`
+    pub credential_subject: serde_json::Map<String, serde_json::Value>,
+    /// Issuer's proof (`None` while unsigned)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proof: Option<DataIntegrityProof>,
+    /// Other properties (`credentialStatus`, `name`, …), covered by the proof
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl VerifiableCredential {
+    /// Issue a credential
+    ///
+    /// # Arguments
+    /// * `issuer` - Issuer identity
+    /// * `subject` - Subject's DID
+    /// * `credential_type` - Application type, e.g. `KycCredential`
+    /// * `claims` - Attributes of the subject
+    /// * `valid_from` - Start of validity (milliseconds)
+    /// * `valid_until` - End of validity (milliseconds)
+    pub fn issue(
+        issuer: &AgentIdentity,
+        subject: &str,
+        credential_type: &str,
+        claims: serde_json::Map<String, serde_json::Value>,
+        valid_from: u64,
+        valid_until: Option<u64>,
+    ) -> Self {
+        let mut credential_subject = claims;
+        credential_subject.insert("id".into(), subject.into());
+        let mut credential = Self {
+            context: vec![CREDENTIALS_CONTEXT.to_string()],
+            id: None,
+            types: vec!["VerifiableCredential".to_string(), credential_type.to_string()],
+            issuer: issuer.did_key(),
+            valid_from: date_time(valid_from),
+            valid_until: valid_until.map(date_time),
+            credential_subject,
+            proof: None,
+            extra: serde_json::Map::new(),
+        };
+        credential.proof = Some(DataIntegrityProof::sign(
+            issuer,
+            &credential.unsecured(),
+            "assertionMethod",
+            None,
+            None,
+            valid_from,
+        ));
+        credential
+    }
+
+    /// DID of the subject
+    pub fn subject(&self) -> Option<&str> {
+        self.credential_subject.get("id")?.as_str()
+    }
+
+    /// Whether the credential has a type
+    pub fn has_type(&self, credential_type: &str) -> bool {
+        self.types.iter().any(|t| t == credential_type)
+    }
+
+    fn unsecured(&self) -> serde_json::Value {
+        let mut document = serde_json::to_value(self).expect("JSON credential");
+        document.as_object_mut().expect("JSON object").remove("proof");
+        document
+    }
+
+    /// Verify the issuer's proof and that the credential is valid at `now` (milliseconds)
+    pub fn verify(&self, now: u64) -> Result<(), String> {
+        Self::verify_json(&serde_json::to_value(self).expect("JSON credential"), now).map(drop)
+    }
+
+    /// Verify a credential as received, before parsing it
+    ///
+    /// # Returns
+    /// The credential
+    pub fn verify_json(document: &serde_json::Value, now: u64) -> Result<Self, String> {
+        let credential: Self =
+            serde_json::from_value(document.clone()).map_err(|e| format!("Invalid credential: {}", e))?;
+        if credential.context.first().is_none_or(|c| c != CREDENTIALS_CONTEXT)
+            || !credential.has_type("VerifiableCredential")
+        {
+            return Err("Not a Verifiable Credential".into());
+        }
+        if credential.subject().is_none() {
+            return Err("Credential names no subject".into());
+        }
+        DataIntegrityProof::verify(document, &credential.issuer, "assertionMethod")?;
+        if parse_date_time(&credential.valid_from)? > now {
+            return Err("Credential is not valid yet".into());
+        }
+        if let Some(until) = &credential.valid_until {
+            if parse_date_time(until)? <= now {
+                return Err("Credential has expired".into());
+            }
+        }
+        Ok(credential)
+    }
+}
+
+/// Verifier's request for credentials
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PresentationRequest {
+    /// Credential types wanted (any of them)
+    pub types: Vec<String>,
+    /// Fresh value the presentation must sign
+    pub challenge: String,
+}
+
+/// W3C Verifiable Presentation of credentials held by an agent
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifiablePresentation {
+    #[serde(rename = "@context")]
+    pub context: Vec<String>,
+    /// Always `VerifiablePresentation`
+    #[serde(rename = "type")]
+    pub types: Vec<String>,
+    /// Holder's `did:key`
+    pub holder: String,
+    /// Credentials presented
+    pub verifiable_credential: Vec<VerifiableCredential>,
+    /// Holder's proof (`None` while unsigned)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proof: Option<DataIntegrityProof>,
+    /// Other properties, covered by the proof
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl VerifiablePresentation {
+    /// Present credentials to a verifier
+    ///
+    /// # Arguments
+    /// * `holder` - Holder identity, the subject of the credentials
+    /// * `credentials` - Credentials presented
+    /// * `challenge` - Verifier's challenge
+    /// * `domain` - Verifier's agent ID
+    /// * `ts` - Presentation time (milliseconds)
+    pub fn present(
+        holder: &AgentIdentity,
+        credentials: Vec<VerifiableCredential>,
+        challenge: &str,
+        domain: &str,
+        ts: u64,
+    ) -> Self {
+        let mut presentation = Self {
+            context: vec![CREDENTIALS_CONTEXT.to_string()],
+            types: vec!["VerifiablePresentation".to_string()],
+            holder: holder.did_key(),
+            verifiable_credential: credentials,
+            proof: None,
+            extra: serde_json::Map::new(),
+        };
+        let document = presentation.unsecured();
+        presentation.proof = Some(DataIntegrityProof::sign(
+            holder,
+            &document,
+            "authentication",
+            Some(challenge.to_string()),
+            Some(domain.to_string()),
+            ts,
+        ));
+        presentation
+    }
+
+    fn unsecured(&self) -> serde_json::Value {
+        let mut document = serde_json::to_value(self).expect("JSON presentation");
+        document.as_object_mut().expect("JSON object").remove("proof");
+        document
+    }
+
+    /// Verify the holder's proof and every credential presented
+    ///
+    /// The proof must sign `challenge` for `domain`, and the holder must be
+    /// the subject of every credential.
+    ///
+    /// # Returns
+    /// The holder's Ed25519 public key
+    pub fn verify(&self, challenge: &str, domain: &str, now: u64) -> Result<[u8; 32], String> {
+        let document = serde_json::to_value(self).expect("JSON presentation");
+        Self::verify_json(&document, challenge, domain, now).map(|(_, holder_key)| holder_key)
+    }
+
+    /// Verify a presentation as received, before parsing it
+    ///
+    /// Checks the same as [`verify`](Self::verify), with every credential
+    /// verified as received too.
+    ///
+    /// # Returns
+    /// The presentation and the holder's Ed25519 public key
+    pub fn verify_json(
+        document: &serde_json::Value,
+        challenge: &str,
+        domain: &str,
+        now: u64,
+    ) -> Result<(Self, [u8; 32]), String> {
+        let presentation: Self =
+            serde_json::from_value(document.clone()).map_err(|e| format!("Invalid presentation: {}", e))?;
+        let proof = DataIntegrityProof::verify(document, &presentation.holder, "authentication")?;
+        if proof.challenge.as_deref() != Some(challenge) || proof.domain.as_deref() != Some(domain) {
+            return Err("Presentation is for another challenge or verifier".into());
+        }
+        let holder_key = did_key_public_key(&presentation.holder)?;
+        let credentials = document["verifiableCredential"].as_array().map(Vec::as_slice).unwrap_or_default();
+        for credential in credentials {
+            let credential = VerifiableCredential::verify_json(credential, now)?;
+            if !credential.subject().is_some_and(|subject| did_names_key(subject, &holder_key)) {
+                return Err(format!("Holder is not the subject of a credential from {}", credential.issuer));
+            }
+        }
+        Ok((presentation, holder_key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verifiable_credentials() {
+        assert_eq!(base58_encode(b"Hello World!"), "2NEpo7TZRRrLZSi2U");
+        assert_eq!(base58_encode(&[0, 0, 1]), "112");
+        assert_eq!(base58_decode("112"), Ok(vec![0, 0, 1]));
+        let ed_pub = [7u8; 32];
+        let did = did_key(&ed_pub);
+        assert!(did.starts_with("did:key:z6Mk"));
+        assert_eq!(did_key_public_key(&format!("{}#key", did)), Ok(ed_pub));
+        assert!(did_key_public_key("did:key:zQ3shokFTS3brHcDQrn82RUDfCZESWL1ZdCEJwekUDPQiYBme").is_err());
+
+        let (operator, agent, verifier) = (
+            KeyManager::generate_identity(16602),
+            KeyManager::generate_identity(16602),
+            KeyManager::generate_identity(16602),
+        );
+        assert!(did_names_key(&agent.did_pkh(), &agent.ed_pub) && did_names_key(&agent.did_key(), &agent.ed_pub));
+        assert!(!did_names_key(&operator.did_pkh(), &agent.ed_pub));
+
+        let claims = serde_json::json!({ "level": "full" }).as_object().unwrap().clone();
+        let credential = VerifiableCredential::issue(&operator, &agent.did_pkh(), "KycCredential", claims, 1_000, Some(10_000));
+        credential.verify(5_000).unwrap();
+        assert!(credential.verify(500).is_err() && credential.verify(10_000).is_err());
+        let json = serde_json::to_value(&credential).unwrap();
+        assert_eq!((json["type"][1].as_str(), json["proof"]["cryptosuite"].as_str()), (Some("KycCredential"), Some("eddsa-jcs-2022")));
+        assert_eq!(serde_json::from_value::<VerifiableCredential>(json).unwrap(), credential);
+        let mut forged = credential.clone();
+        forged.credential_subject.insert("level".into(), "none".into());
+        assert_eq!(forged.verify(5_000).unwrap_err(), format!("Invalid proof signature by {}", operator.did_key()));
+
+        let presentation = VerifiablePresentation::present(&agent, vec![credential.clone()], "c1", &verifier.id, 5_000);
+        assert_eq!(presentation.verify("c1", &verifier.id, 5_000), Ok(agent.ed_pub));
+        assert!(presentation.verify("c2", &verifier.id, 5_000).is_err());
+        assert!(presentation.verify("c1", &operator.id, 5_000).is_err());
+        // Only the subject can present a credential
+        let stolen = VerifiablePresentation::present(&verifier, vec![credential], "c1", &operator.id, 5_000);
+        assert!(stolen.verify("c1", &operator.id, 5_000).unwrap_err().starts_with("Holder is not the subject"));
+    }
+
+    #[test]
+    fn test_proof_covers_received_json() {
+        let (operator, agent) = (KeyManager::generate_identity(16602), KeyManager::generate_identity(16602));
+        let claims = serde_json::json!({ "level": "full" }).as_object().unwrap().clone();
+        let mut credential = VerifiableCredential::issue(&operator, &agent.did_key(), "KycCredential", claims, 1_000, None);
+        credential.extra.insert("name".into(), "KYC".into());
+        credential.proof = Some(DataIntegrityProof::sign(&operator, &credential.unsecured(), "assertionMethod", None, None, 1_000));
+
+        // Properties the struct does not model are signed and kept
+        let mut document = serde_json::to_value(&credential).unwrap();
+        assert_eq!(VerifiableCredential::verify_json(&document, 5_000), Ok(credential.clone()));
+        document["name"] = "Forged".into();
+        assert!(VerifiableCredential::verify_json(&document, 5_000).unwrap_err().starts_with("Invalid proof signature"));
+        document["name"] = "KYC".into();
+        document["proof"]["expires"] = "2100-01-01T00:00:00Z".into();
+        assert!(VerifiableCredential::verify_json(&document, 5_000).is_err());
+
+        let presentation = VerifiablePresentation::present(&agent, vec![credential], "c1", "verifier", 5_000);
+        let mut document = serde_json::to_value(&presentation).unwrap();
+        assert!(VerifiablePresentation::verify_json(&document, "c1", "verifier", 5_000).is_ok());
+        document["verifiableCredential"][0]["name"] = "Forged".into();
+        assert!(VerifiablePresentation::verify_json(&document, "c1", "verifier", 5_000).is_err());
+    }
+
+    #[test]
+    fn test_canonical_json() {
+        // RFC 8785 section 3.2.2 and 3.2.3
+        let input = r#"{
+            "numbers": [333333333.33333329, 1E30, 4.50, 2e-3, 0.000000000000000000000000001],
+            "string": "\u20ac$\u000F\u000aA'\u0042\u0022\u005c\\\"\/",
+            "literals": [null, true, false]
+        }"#;
+        assert_eq!(
+            String::from_utf8(canonical_json(&serde_json::from_str(input).unwrap())).unwrap(),
+            r#"{"literals":[null,true,false],"numbers":[333333333.3333333,1e+30,4.5,0.002,1e-27],"string":"€$\u000f\nA'B\"\\\\\"/"}"#
+        );
+        let input = r#"{
+            "\u20ac": "Euro Sign",
+            "\r": "Carriage Return",
+            "\ufb33": "Hebrew Letter Dalet With Dagesh",
+            "1": "One",
+            "\ud83d\ude00": "Emoji: Grinning Face",
+            "\u0080": "Control",
+            "\u00f6": "Latin Small Letter O With Diaeresis"
+        }"#;
+        assert_eq!(
+            String::from_utf8(canonical_json(&serde_json::from_str(input).unwrap())).unwrap(),
+            "{\"\\r\":\"Carriage Return\",\"1\":\"One\",\"\u{80}\":\"Control\",\
+             \"\u{f6}\":\"Latin Small Letter O With Diaeresis\",\"\u{20ac}\":\"Euro Sign\",\
+             \"\u{1f600}\":\"Emoji: Grinning Face\",\"\u{fb33}\":\"Hebrew Letter Dalet With Dagesh\"}"
+        );
+
+        // ECMAScript number serialization (RFC 8785 appendix B)
+        for (number, expected) in [
+            (-0.0, "0"),
+            (1e21, "1e+21"),
+            (1e20, "100000000000000000000"),
+            (1e-7, "1e-7"),
+            (0.000001, "0.000001"),
+            (5e-324, "5e-324"),
+            (1.7976931348623157e308, "1.7976931348623157e+308"),
+            (9007199254740992.0, "9007199254740992"),
+            (-1.5, "-1.5"),
+        ] {
+            assert_eq!(es_number(number), expected);
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! Pluggable implementations of the Ed25519, X25519 and AEAD primitives
+//!
+//! The backend is chosen at compile time:
+//! - default: `ed25519-dalek`, `x25519-dalek`, RustCrypto `chacha20poly1305`
+//! - `ring-backend`: ring for Ed25519 and AEAD (ring cannot import static
+//!   X25519 keys, so X25519 stays on dalek)
+//! - `aws-lc-backend`: aws-lc-rs for everything (FIPS-capable builds)
+//!
+//! The AEAD is ChaCha20-Poly1305 with a 32-byte key and 12-byte nonce in
+//! every backend, so ciphertexts are interchangeable.
+
+/// Primitive operations provided by a crypto backend
+pub trait CryptoBackend {
+    /// Backend name
+    const NAME: &'static str;
+
+    /// Sign message with an Ed25519 private key (32-byte seed)
+    fn ed25519_sign(priv_key: &[u8; 32], message: &[u8]) -> [u8; 64];
+
+    /// Verify an Ed25519 signature
+    fn ed25519_verify(pub_key: &[u8; 32], message: &[u8], sig: &[u8]) -> bool;
+
+    /// X25519 Diffie-Hellman (all zeros for low-order peer keys)
+    fn x25519(my_priv: &[u8; 32], peer_pub: &[u8; 32]) -> [u8; 32];
+
+    /// Encrypt and authenticate with ChaCha20-Poly1305
+    ///
+    /// # Returns
+    /// Ciphertext with the 16-byte tag appended
+    fn aead_seal(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], plaintext: &[u8]) -> Vec<u8>;
+
+    /// Decrypt and verify with ChaCha20-Poly1305
+    fn aead_open(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, String>;
+}
+
+/// Backend selected by cargo features
+#[cfg(feature = "aws-lc-backend")]
+pub type DefaultBackend = AwsLcBackend;
+/// Backend selected by cargo features
+#[cfg(all(feature = "ring-backend", not(feature = "aws-lc-backend")))]
+pub type DefaultBackend = RingBackend;
+/// Backend selected by cargo features
+#[cfg(not(any(feature = "ring-backend", feature = "aws-lc-backend")))]
+pub type DefaultBackend = DalekBackend;
+
+/// dalek + RustCrypto backend
+pub struct DalekBackend;
+
+impl CryptoBackend for DalekBackend {
+    const NAME: &'static str = "dalek";
+
+    fn ed25519_sign(priv_key: &[u8; 32], message: &[u8]) -> [u8; 64] {
+        use ed25519_dalek::{Signer, SigningKey};
+        SigningKey::from_bytes(priv_key).sign(message).to_bytes()
+    }
+
+    fn ed25519_verify(pub_key: &[u8; 32], message: &[u8], sig: &[u8]) -> bool {
+        use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+        let Ok(key) = VerifyingKey::from_bytes(pub_key) else {
+            return false;
+        };
+        let Ok(sig) = Signature::from_slice(sig) else {
+            return false;
+        };
+        key.verify(message, &sig).is_ok()
+    }
+
+    fn x25519(my_priv: &[u8; 32], peer_pub: &[u8; 32]) -> [u8; 32] {
+        use x25519_dalek::{PublicKey, StaticSecret};
+        *StaticSecret::from(*my_priv).diffie_hellman(&PublicKey::from(*peer_pub)).as_bytes()
+    }
+
+    fn aead_seal(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+        chacha20poly1305::ChaCha20Poly1305::new(key.into())
+            .encrypt(nonce.into(), Payload { msg: plaintext, aad })
+            .expect("AEAD encryption failed")
+    }
+
+    fn aead_open(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, String> {
+        use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+        chacha20poly1305::ChaCha20Poly1305::new(key.into())
+            .decrypt(nonce.into(), Payload { msg: ciphertext, aad })
+            .map_err(|_| "AEAD decryption failed".to_string())
+    }
+}
+
+/// Generates a backend from a ring-compatible API (ring and aws-lc-rs share it)
+#[cfg(any(feature = "ring-backend", feature = "aws-lc-backend"))]
+macro_rules! ring_like_backend {
+    ($name:ident, $label:literal, $krate:ident) => {
+        /// Backend built on
+        #[doc = $label]
+        pub struct $name;
+
+        impl $name {
+            fn aead_key(key: &[u8; 32]) -> $krate::aead::LessSafeKey {
+                let unbound = $krate::aead::UnboundKey::new(&$krate::aead::CHACHA20_POLY1305, key)
+                    .expect("32-byte key");
+                $krate::aead::LessSafeKey::new(unbound)
+            }
+
+            fn ed25519_sign_impl(priv_key: &[u8; 32], message: &[u8]) -> [u8; 64] {
+                let pair = $krate::signature::Ed25519KeyPair::from_seed_unchecked(priv_key)
+                    .expect("32-byte seed");
+                pair.sign(message).as_ref().try_into().expect("64-byte signature")
+            }
+
+            fn ed25519_verify_impl(pub_key: &[u8; 32], message: &[u8], sig: &[u8]) -> bool {
+                $krate::signature::UnparsedPublicKey::new(&$krate::signature::ED25519, pub_key)
+                    .verify(message, sig)
+                    .is_ok()
+            }
+
+            fn aead_seal_impl(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+                let mut buf = plaintext.to_vec();
+                Self::aead_key(key)
+                    .seal_in_place_append_tag(
+                        $krate::aead::Nonce::assume_unique_for_key(*nonce),
+                        $krate::aead::Aad::from(aad),
+                        &mut buf,
+                    )
+                    .expect("AEAD encryption failed");
+                buf
+            }
+
+            fn aead_open_impl(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, String> {
+                let mut buf = ciphertext.to_vec();
+                let len = Self::aead_key(key)
+                    .open_in_place(
+                        $krate::aead::Nonce::assume_unique_for_key(*nonce),
+                        $krate::aead::Aad::from(aad),
+                        &mut buf,
+                    )
+                    .map_err(|_| "AEAD decryption failed".to_string())?
+                    .len();
+                buf.truncate(len);
+                Ok(buf)
+            }
+        }
+    };
+}
+
+#[cfg(feature = "ring-backend")]
+ring_like_backend!(RingBackend, "ring", ring);
+
+#[cfg(feature = "ring-backend")]
+impl CryptoBackend for RingBackend {
+    const NAME: &'static str = "ring";
+
+    fn ed25519_sign(priv_key: &[u8; 32], message: &[u8]) -> [u8; 64] {
+        Self::ed25519_sign_impl(priv_key, message)
+    }
+
+    fn ed25519_verify(pub_key: &[u8; 32], message: &[u8], sig: &[u8]) -> bool {
+        Self::ed25519_verify_impl(pub_key, message, sig)
+    }
+
+    fn x25519(my_priv: &[u8; 32], peer_pub: &[u8; 32]) -> [u8; 32] {
+        DalekBackend::x25519(my_priv, peer_pub)
+    }
+
+    fn aead_seal(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        Self::aead_seal_impl(key, nonce, aad, plaintext)
+    }
+
+    fn aead_open(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, String> {
+        Self::aead_open_impl(key, nonce, aad, ciphertext)
+    }
+}
+
+#[cfg(feature = "aws-lc-backend")]
+ring_like_backend!(AwsLcBackend, "aws-lc-rs", aws_lc_rs);
+
+#[cfg(feature = "aws-lc-backend")]
+impl CryptoBackend for AwsLcBackend {
+    const NAME: &'static str = "aws-lc";
+
+    fn ed25519_sign(priv_key: &[u8; 32], message: &[u8]) -> [u8; 64] {
+        Self::ed25519_sign_impl(priv_key, message)
+    }
+
+    fn ed25519_verify(pub_key: &[u8; 32], message: &[u8], sig: &[u8]) -> bool {
+        Self::ed25519_verify_impl(pub_key, message, sig)
+    }
+
+    fn x25519(my_priv: &[u8; 32], peer_pub: &[u8; 32]) -> [u8; 32] {
+        use aws_lc_rs::agreement::{agree, PrivateKey, UnparsedPublicKey, X25519};
+        let Ok(secret) = PrivateKey::from_private_key(&X25519, my_priv) else {
+            return [0u8; 32];
+        };
+        agree(&secret, UnparsedPublicKey::new(&X25519, peer_pub), (), |k| {
+            Ok(k.try_into().expect("32-byte shared secret"))
+        })
+        .unwrap_or([0u8; 32])
+    }
+
+    fn aead_seal(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        Self::aead_seal_impl(key, nonce, aad, plaintext)
+    }
+
+    fn aead_open(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, String> {
+        Self::aead_open_impl(key, nonce, aad, ciphertext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyManager;
+
+    fn check_backend<B: CryptoBackend>() {
+        let (signing, verifying) = KeyManager::generate_ed25519();
+        let sig = B::ed25519_sign(&signing.to_bytes(), b"msg");
+        assert_eq!(sig, DalekBackend::ed25519_sign(&signing.to_bytes(), b"msg"), "{}", B::NAME);
+        assert!(B::ed25519_verify(verifying.as_bytes(), b"msg", &sig));
+        assert!(!B::ed25519_verify(verifying.as_bytes(), b"other", &sig));
+
+        let (a_priv, a_pub) = KeyManager::generate_x25519();
+        let (b_priv, b_pub) = KeyManager::generate_x25519();
+        let shared = B::x25519(&a_priv.to_bytes(), &b_pub.to_bytes());
+        assert_eq!(shared, DalekBackend::x25519(&b_priv.to_bytes(), &a_pub.to_bytes()));
+
+        let key = [9u8; 32];
+        let nonce = [1u8; 12];
+        let ct = B::aead_seal(&key, &nonce, b"aad", b"secret");
+        assert_eq!(ct, DalekBackend::aead_seal(&key, &nonce, b"aad", b"secret"));
+        assert_eq!(B::aead_open(&key, &nonce, b"aad", &ct).unwrap(), b"secret");
+        assert!(B::aead_open(&key, &nonce, b"bad", &ct).is_err());
+    }
+
+    #[test]
+    fn test_default_backend() {
+        check_backend::<DefaultBackend>();
+    }
+
+    #[cfg(feature = "ring-backend")]
+    #[test]
+    fn test_ring_backend() {
+        check_backend::<RingBackend>();
+    }
+
+    #[cfg(feature = "aws-lc-backend")]
+    #[test]
+    fn test_aws_lc_backend() {
+        check_backend::<AwsLcBackend>();
+    }
+}
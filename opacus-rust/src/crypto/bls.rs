@@ -0,0 +1,263 @@
+//! BLS12-381 aggregate signatures for frame attestation batches
+//!
+//! Uses the min-pk variant (48-byte public keys, 96-byte signatures) with
+//! message augmentation: every signed message is prefixed with the signer's
+//! public key, so aggregates over arbitrary messages and signers are safe
+//! against rogue-key attacks.
+
+use blst::min_pk::{AggregateSignature, PublicKey, SecretKey, Signature};
+use blst::BLST_ERROR;
+use serde::{Deserialize, Serialize};
+use sha2::{Sha256, Digest};
+use crate::proto::CBORCodec;
+use crate::random::{random_array, OsRandom, Random};
+use crate::types::OpacusFrame;
+
+/// Domain separation tag (augmented scheme)
+const BLS_DST: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_AUG_";
+
+/// BLS public key size in bytes
+pub const BLS_PUBLIC_KEY_LEN: usize = 48;
+/// BLS signature size in bytes
+pub const BLS_SIGNATURE_LEN: usize = 96;
+
+/// BLS signing key pair
+pub struct BlsKeyPair {
+    secret: SecretKey,
+    public: PublicKey,
+}
+
+impl BlsKeyPair {
+    /// Generate random key pair
+    pub fn generate() -> Self {
+        Self::generate_with(&OsRandom)
+    }
+
+    /// Generate key pair from a custom source of randomness
+    pub fn generate_with(random: &dyn Random) -> Self {
+        let ikm: [u8; 32] = random_array(random);
+        Self::from_seed(&ikm).expect("32-byte seed")
+    }
+
+    /// Derive key pair from seed material (at least 32 bytes)
+    pub fn from_seed(seed: &[u8]) -> Result<Self, String> {
+        let secret = SecretKey::key_gen(seed, &[])
+            .map_err(|e| format!("BLS key generation failed: {:?}", e))?;
+        let public = secret.sk_to_pk();
+        Ok(Self { secret, public })
+    }
+
+    /// Compressed public key
+    pub fn public_key(&self) -> [u8; BLS_PUBLIC_KEY_LEN] {
+        self.public.to_bytes()
+    }
+
+    /// Sign message
+    pub fn sign(&self, message: &[u8]) -> [u8; BLS_SIGNATURE_LEN] {
+        self.sign_raw(message).to_bytes()
+    }
+
+    fn sign_raw(&self, message: &[u8]) -> Signature {
+        self.secret.sign(&augment(&self.public_key(), message), BLS_DST, &[])
+    }
+}
+
+/// Stateless BLS signature operations
+pub struct BlsSignatures;
+
+impl BlsSignatures {
+    /// Verify a single signature
+    pub fn verify(pub_key: &[u8], message: &[u8], sig: &[u8]) -> bool {
+        let (Ok(pk), Ok(sig)) = (PublicKey::from_bytes(pub_key), Signature::from_bytes(sig)) else {
+            return false;
+        };
+        sig.verify(true, &augment(pub_key, message), BLS_DST, &[], &pk, true) == BLST_ERROR::BLST_SUCCESS
+    }
+
+    /// Aggregate signatures into one
+    pub fn aggregate(sigs: &[&[u8]]) -> Result<[u8; BLS_SIGNATURE_LEN], String> {
+        let sigs = sigs
+            .iter()
+            .map(|s| Signature::from_bytes(s).map_err(|e| format!("Invalid BLS signature: {:?}", e)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::aggregate_signatures(&sigs.iter().collect::<Vec<_>>())
+    }
+
+    /// Verify an aggregate signature over `(public key, message)` pairs
+    pub fn verify_aggregate(items: &[(&[u8], &[u8])], aggregate: &[u8]) -> bool {
+        if items.is_empty() {
+            return false;
+        }
+        let Ok(sig) = Signature::from_bytes(aggregate) else {
+            return false;
+        };
+        let Ok(pks) = items
+            .iter()
+            .map(|(pk, _)| PublicKey::from_bytes(pk))
+            .collect::<Result<Vec<_>, _>>()
+        else {
+            return false;
+        };
+        let msgs: Vec<Vec<u8>> = items.iter().map(|(pk, msg)| augment(pk, msg)).collect();
+        let msg_refs: Vec<&[u8]> = msgs.iter().map(|m| m.as_slice()).collect();
+        let pk_refs: Vec<&PublicKey> = pks.iter().collect();
+        sig.aggregate_verify(true, &msg_refs, BLS_DST, &pk_refs, true) == BLST_ERROR::BLST_SUCCESS
+    }
+
+    fn aggregate_signatures(sigs: &[&Signature]) -> Result<[u8; BLS_SIGNATURE_LEN], String> {
+        let agg = AggregateSignature::aggregate(sigs, true)
+            .map_err(|e| format!("BLS aggregation failed: {:?}", e))?;
+        Ok(agg.to_signature().to_bytes())
+    }
+}
+
+fn augment(pub_key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(pub_key.len() + message.len());
+    out.extend_from_slice(pub_key);
+    out.extend_from_slice(message);
+    out
+}
+
+/// Aggregate attestation over a batch of frames from a single attestor
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationBatch {
+    /// Attestor BLS public key (48 bytes)
+    pub attestor: Vec<u8>,
+    /// SHA-256 hashes of the attested frames
+    pub frame_hashes: Vec<[u8; 32]>,
+    /// Aggregate signature over all hashes (96 bytes)
+    pub signature: Vec<u8>,
+}
+
+impl AttestationBatch {
+    /// Verify the aggregate signature covers every frame hash
+    pub fn verify(&self) -> bool {
+        let items: Vec<(&[u8], &[u8])> = self
+            .frame_hashes
+            .iter()
+            .map(|h| (self.attestor.as_slice(), h.as_slice()))
+            .collect();
+        BlsSignatures::verify_aggregate(&items, &self.signature)
+    }
+
+    /// Check whether a frame is part of this batch
+    pub fn contains(&self, frame: &OpacusFrame) -> bool {
+        FrameAttestor::frame_hash(frame)
+            .map(|h| self.frame_hashes.contains(&h))
+            .unwrap_or(false)
+    }
+}
+
+/// Collects per-frame BLS attestations and aggregates them into batches
+pub struct FrameAttestor {
+    keys: BlsKeyPair,
+    hashes: Vec<[u8; 32]>,
+    sigs: Vec<Signature>,
+}
+
+impl FrameAttestor {
+    /// Create attestor with key pair
+    pub fn new(keys: BlsKeyPair) -> Self {
+        Self {
+            keys,
+            hashes: Vec::new(),
+            sigs: Vec::new(),
+        }
+    }
+
+    /// Attestor public key
+    pub fn public_key(&self) -> [u8; BLS_PUBLIC_KEY_LEN] {
+        self.keys.public_key()
+    }
+
+    /// Hash identifying a frame (SHA-256 of its canonical CBOR encoding)
+    pub fn frame_hash(frame: &OpacusFrame) -> Result<[u8; 32], String> {
+        let encoded = CBORCodec::encode_canonical(frame).map_err(|e| e.to_string())?;
+        Ok(Sha256::digest(&encoded).into())
+    }
+
+    /// Attest a frame
+    ///
+    /// # Returns
+    /// The frame hash that was signed
+    pub fn attest(&mut self, frame: &OpacusFrame) -> Result<[u8; 32], String> {
+        let hash = Self::frame_hash(frame)?;
+        self.sigs.push(self.keys.sign_raw(&hash));
+        self.hashes.push(hash);
+        Ok(hash)
+    }
+
+    /// Number of attestations waiting to be aggregated
+    pub fn pending(&self) -> usize {
+        self.hashes.len()
+    }
+
+    /// Aggregate pending attestations into a batch and reset
+    ///
+    /// # Returns
+    /// `None` if nothing was attested
+    pub fn finish(&mut self) -> Option<AttestationBatch> {
+        if self.hashes.is_empty() {
+            return None;
+        }
+        let sigs: Vec<&Signature> = self.sigs.iter().collect();
+        let signature = BlsSignatures::aggregate_signatures(&sigs).ok()?;
+        self.sigs.clear();
+        Some(AttestationBatch {
+            attestor: self.keys.public_key().to_vec(),
+            frame_hashes: std::mem::take(&mut self.hashes),
+            signature: signature.to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(seq: u64) -> OpacusFrame {
+        OpacusFrame { payload: vec![1, 2, 3].into(), ..OpacusFrame::test(seq) }
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let keys = BlsKeyPair::generate();
+        let sig = keys.sign(b"hello");
+        assert!(BlsSignatures::verify(&keys.public_key(), b"hello", &sig));
+        assert!(!BlsSignatures::verify(&keys.public_key(), b"other", &sig));
+    }
+
+    #[test]
+    fn test_aggregate_multiple_signers() {
+        let a = BlsKeyPair::generate();
+        let b = BlsKeyPair::generate();
+        let (sig_a, sig_b) = (a.sign(b"same"), b.sign(b"same"));
+        let agg = BlsSignatures::aggregate(&[&sig_a, &sig_b]).unwrap();
+
+        let (pk_a, pk_b) = (a.public_key(), b.public_key());
+        assert!(BlsSignatures::verify_aggregate(&[(&pk_a, b"same"), (&pk_b, b"same")], &agg));
+        assert!(!BlsSignatures::verify_aggregate(&[(&pk_a, b"same"), (&pk_b, b"diff")], &agg));
+    }
+
+    #[test]
+    fn test_attestation_batch() {
+        let mut attestor = FrameAttestor::new(BlsKeyPair::generate());
+        assert!(attestor.finish().is_none());
+
+        for seq in 0..50 {
+            attestor.attest(&frame(seq)).unwrap();
+        }
+        assert_eq!(attestor.pending(), 50);
+
+        let batch = attestor.finish().unwrap();
+        assert_eq!(attestor.pending(), 0);
+        assert!(batch.verify());
+        assert!(batch.contains(&frame(7)));
+        assert!(!batch.contains(&frame(99)));
+
+        let mut tampered = batch.clone();
+        tampered.frame_hashes[0] = [0u8; 32];
+        assert!(!tampered.verify());
+    }
+}
@@ -0,0 +1,224 @@
+//! Key fingerprints, safety numbers and peer key tracking
+
+use sha2::{Sha256, Sha512, Digest};
+use std::collections::HashMap;
+use tracing::warn;
+
+/// RFC 4648 Base32 alphabet
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Number of hash bytes kept in a fingerprint (160 bits)
+const FINGERPRINT_BYTES: usize = 20;
+
+/// Short human-comparable identity fingerprints
+pub struct Fingerprint;
+
+impl Fingerprint {
+    /// Compute the fingerprint of an agent's public keys
+    ///
+    /// Base32 of the first 160 bits of SHA-256(ed_pub || x_pub), in groups
+    /// of four characters, e.g. `ABCD EFGH ...`.
+    pub fn of(ed_pub: &[u8; 32], x_pub: &[u8; 32]) -> String {
+        let digest = Self::digest(ed_pub, x_pub);
+        let encoded = base32(&digest[..FINGERPRINT_BYTES]);
+        encoded
+            .as_bytes()
+            .chunks(4)
+            .map(|c| std::str::from_utf8(c).unwrap())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Compute the safety number for a pair of agents
+    ///
+    /// Both sides get the same 60-digit number regardless of argument order;
+    /// operators compare it out-of-band to confirm neither key was swapped.
+    pub fn safety_number(
+        (a_ed, a_x): (&[u8; 32], &[u8; 32]),
+        (b_ed, b_x): (&[u8; 32], &[u8; 32]),
+    ) -> String {
+        let a = Self::digest(a_ed, a_x);
+        let b = Self::digest(b_ed, b_x);
+        let (first, second) = if a <= b { (a, b) } else { (b, a) };
+
+        let mut hasher = Sha512::new();
+        hasher.update(b"opacus-safety-number");
+        hasher.update(first);
+        hasher.update(second);
+        let hash = hasher.finalize();
+
+        hash[..60]
+            .chunks(5)
+            .map(|c| {
+                let n = c.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+                format!("{:05}", n % 100_000)
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn digest(ed_pub: &[u8; 32], x_pub: &[u8; 32]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(ed_pub);
+        hasher.update(x_pub);
+        hasher.finalize().into()
+    }
+}
+
+fn base32(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(5) * 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+/// Result of observing a peer's keys
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerKeyStatus {
+    /// First time this peer is seen
+    New,
+    /// Keys match the recorded fingerprint
+    Unchanged {
+        /// Whether the operator verified this fingerprint
+        verified: bool,
+    },
+    /// Keys differ from the recorded fingerprint
+    Changed {
+        /// Previously recorded fingerprint
+        previous: String,
+        /// Whether the previous fingerprint had been verified
+        was_verified: bool,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct PeerRecord {
+    fingerprint: String,
+    verified: bool,
+}
+
+/// Tracks peer fingerprints, verification state and key changes
+#[derive(Debug, Default)]
+pub struct PeerTrustStore {
+    peers: HashMap<String, PeerRecord>,
+}
+
+impl PeerTrustStore {
+    /// Create empty trust store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the keys seen for a peer
+    ///
+    /// A key change resets the peer to unverified and logs a warning.
+    pub fn observe(&mut self, agent_id: &str, ed_pub: &[u8; 32], x_pub: &[u8; 32]) -> PeerKeyStatus {
+        let fingerprint = Fingerprint::of(ed_pub, x_pub);
+        match self.peers.get_mut(agent_id) {
+            None => {
+                self.peers.insert(agent_id.to_string(), PeerRecord { fingerprint, verified: false });
+                PeerKeyStatus::New
+            }
+            Some(record) if record.fingerprint == fingerprint => {
+                PeerKeyStatus::Unchanged { verified: record.verified }
+            }
+            Some(record) => {
+                warn!("⚠️ Keys changed for {}: {} -> {}", agent_id, record.fingerprint, fingerprint);
+                let previous = std::mem::replace(record, PeerRecord { fingerprint, verified: false });
+                PeerKeyStatus::Changed {
+                    previous: previous.fingerprint,
+                    was_verified: previous.verified,
+                }
+            }
+        }
+    }
+
+    /// Mark a peer as verified after comparing its fingerprint out-of-band
+    ///
+    /// # Arguments
+    /// * `agent_id` - Peer agent ID
+    /// * `fingerprint` - Fingerprint the operator confirmed
+    ///
+    /// # Returns
+    /// `Err` if the peer is unknown or the fingerprint does not match
+    pub fn mark_verified(&mut self, agent_id: &str, fingerprint: &str) -> Result<(), String> {
+        let record = self.peers.get_mut(agent_id).ok_or("Unknown peer")?;
+        if record.fingerprint != fingerprint {
+            return Err("Fingerprint mismatch".into());
+        }
+        record.verified = true;
+        Ok(())
+    }
+
+    /// Check whether a peer is verified
+    pub fn is_verified(&self, agent_id: &str) -> bool {
+        self.peers.get(agent_id).map(|r| r.verified).unwrap_or(false)
+    }
+
+    /// Get recorded fingerprint for a peer
+    pub fn fingerprint(&self, agent_id: &str) -> Option<&str> {
+        self.peers.get(agent_id).map(|r| r.fingerprint.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyManager;
+
+    #[test]
+    fn test_base32() {
+        assert_eq!(base32(b""), "");
+        assert_eq!(base32(b"f"), "MY");
+        assert_eq!(base32(b"foobar"), "MZXW6YTBOI");
+    }
+
+    #[test]
+    fn test_fingerprint_and_safety_number() {
+        let alice = KeyManager::generate_identity(16602);
+        let bob = KeyManager::generate_identity(16602);
+
+        let fp = Fingerprint::of(&alice.ed_pub, &alice.x_pub);
+        assert_eq!(fp.len(), 32 + 7);
+        assert_ne!(fp, Fingerprint::of(&bob.ed_pub, &bob.x_pub));
+
+        let ab = Fingerprint::safety_number((&alice.ed_pub, &alice.x_pub), (&bob.ed_pub, &bob.x_pub));
+        let ba = Fingerprint::safety_number((&bob.ed_pub, &bob.x_pub), (&alice.ed_pub, &alice.x_pub));
+        assert_eq!(ab, ba);
+        assert_eq!(ab.replace(' ', "").len(), 60);
+    }
+
+    #[test]
+    fn test_trust_store() {
+        let bob = KeyManager::generate_identity(16602);
+        let imposter = KeyManager::generate_identity(16602);
+        let mut store = PeerTrustStore::new();
+
+        assert_eq!(store.observe("bob", &bob.ed_pub, &bob.x_pub), PeerKeyStatus::New);
+        assert!(store.mark_verified("bob", "WRONG").is_err());
+
+        let fp = Fingerprint::of(&bob.ed_pub, &bob.x_pub);
+        store.mark_verified("bob", &fp).unwrap();
+        assert_eq!(
+            store.observe("bob", &bob.ed_pub, &bob.x_pub),
+            PeerKeyStatus::Unchanged { verified: true }
+        );
+
+        assert_eq!(
+            store.observe("bob", &imposter.ed_pub, &imposter.x_pub),
+            PeerKeyStatus::Changed { previous: fp, was_verified: true }
+        );
+        assert!(!store.is_verified("bob"));
+    }
+}
@@ -3,23 +3,37 @@
 use ed25519_dalek::{SigningKey, VerifyingKey};
 use x25519_dalek::{StaticSecret, PublicKey as X25519Public};
 use sha2::{Sha256, Digest};
-use rand::rngs::OsRng;
+use hkdf::Hkdf;
+use crate::random::{random_array, OsRandom, Random};
 use crate::types::AgentIdentity;
 
+/// HKDF salt for seed-based identity derivation
+const SEED_SALT: &[u8] = b"opacus-identity-seed-v1";
+
 /// Key manager for Ed25519 and X25519 operations
 pub struct KeyManager;
 
 impl KeyManager {
     /// Generate Ed25519 key pair for signing
     pub fn generate_ed25519() -> (SigningKey, VerifyingKey) {
-        let signing_key = SigningKey::generate(&mut OsRng);
+        Self::generate_ed25519_with(&OsRandom)
+    }
+    
+    /// Generate Ed25519 key pair from a custom source of randomness
+    pub fn generate_ed25519_with(random: &dyn Random) -> (SigningKey, VerifyingKey) {
+        let signing_key = SigningKey::from_bytes(&random_array(random));
         let verifying_key = signing_key.verifying_key();
         (signing_key, verifying_key)
     }
     
     /// Generate X25519 key pair for encryption
     pub fn generate_x25519() -> (StaticSecret, X25519Public) {
-        let secret = StaticSecret::random_from_rng(OsRng);
+        Self::generate_x25519_with(&OsRandom)
+    }
+    
+    /// Generate X25519 key pair from a custom source of randomness
+    pub fn generate_x25519_with(random: &dyn Random) -> (StaticSecret, X25519Public) {
+        let secret = StaticSecret::from(random_array::<32>(random));
         let public = X25519Public::from(&secret);
         (secret, public)
     }
@@ -32,27 +46,78 @@ impl KeyManager {
     /// # Returns
     /// Complete `AgentIdentity` with Ed25519 and X25519 keys
     pub fn generate_identity(chain_id: u64) -> AgentIdentity {
-        let (ed_signing, ed_verifying) = Self::generate_ed25519();
-        let (x_secret, x_public) = Self::generate_x25519();
+        Self::generate_identity_with(chain_id, &OsRandom)
+    }
+    
+    /// Generate agent identity from a custom source of randomness
+    /// 
+    /// With a `SeededRandom`, the same seed yields the same identity.
+    pub fn generate_identity_with(chain_id: u64, random: &dyn Random) -> AgentIdentity {
+        let (ed_signing, _) = Self::generate_ed25519_with(random);
+        let (x_secret, _) = Self::generate_x25519_with(random);
+        Self::identity_from_keys(ed_signing.to_bytes(), x_secret.to_bytes(), chain_id)
+    }
+    
+    /// Deterministically derive a full agent identity from a single seed
+    /// 
+    /// Both private keys are expanded from the seed with HKDF under separate
+    /// info strings, so backing up the seed is enough to restore the identity.
+    /// 
+    /// # Arguments
+    /// * `seed` - 32-byte secret seed
+    /// * `chain_id` - Blockchain chain ID
+    pub fn identity_from_seed(seed: &[u8; 32], chain_id: u64) -> AgentIdentity {
+        let hk = Hkdf::<Sha256>::new(Some(SEED_SALT), seed);
+        let mut ed_priv = [0u8; 32];
+        let mut x_priv = [0u8; 32];
+        hk.expand(b"opacus-ed25519", &mut ed_priv).expect("HKDF expand failed");
+        hk.expand(b"opacus-x25519", &mut x_priv).expect("HKDF expand failed");
+        Self::identity_from_keys(ed_priv, x_priv, chain_id)
+    }
+    
+    /// Derive the secp256k1 private key of a seed
+    /// 
+    /// Expanded like the keys of [`identity_from_seed`](Self::identity_from_seed),
+    /// under its own info string. The H3DAC SDK derives the same key from the
+    /// same seed.
+    pub fn secp256k1_from_seed(seed: &[u8; 32]) -> [u8; 32] {
+        let hk = Hkdf::<Sha256>::new(Some(SEED_SALT), seed);
+        let mut secp_priv = [0u8; 32];
+        hk.expand(b"opacus-secp256k1", &mut secp_priv).expect("HKDF expand failed");
+        secp_priv
+    }
+    
+    /// Build an agent identity from existing private keys
+    /// 
+    /// # Arguments
+    /// * `ed_priv` - Ed25519 private key
+    /// * `x_priv` - X25519 private key
+    /// * `chain_id` - Blockchain chain ID
+    pub fn identity_from_keys(ed_priv: [u8; 32], x_priv: [u8; 32], chain_id: u64) -> AgentIdentity {
+        let ed_pub = *SigningKey::from_bytes(&ed_priv).verifying_key().as_bytes();
+        let x_pub = X25519Public::from(&StaticSecret::from(x_priv)).to_bytes();
         
-        // Generate ID from public key hash
-        let mut hasher = Sha256::new();
-        hasher.update(ed_verifying.as_bytes());
-        let hash = hasher.finalize();
-        let id = hex::encode(&hash[..20]);
-        let address = format!("0x{}", hex::encode(&hash[..20]));
+        let id = Self::agent_id(&ed_pub);
+        let address = format!("0x{}", id);
         
         AgentIdentity {
             id,
-            ed_pub: *ed_verifying.as_bytes(),
-            ed_priv: ed_signing.to_bytes(),
-            x_pub: x_public.to_bytes(),
-            x_priv: x_secret.to_bytes(),
+            ed_pub,
+            ed_priv,
+            x_pub,
+            x_priv,
             address,
             chain_id,
         }
     }
     
+    /// Agent ID of an Ed25519 public key (first 20 bytes of its SHA-256, hex)
+    pub fn agent_id(ed_pub: &[u8; 32]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(ed_pub);
+        hex::encode(&hasher.finalize()[..20])
+    }
+    
     /// Convert bytes to hex string
     pub fn to_hex(bytes: &[u8]) -> String {
         hex::encode(bytes)
@@ -93,6 +158,24 @@ mod tests {
         assert!(identity.address.starts_with("0x"));
     }
     
+    #[test]
+    fn test_identity_from_seed() {
+        let seed = [42u8; 32];
+        let a = KeyManager::identity_from_seed(&seed, 16602);
+        let b = KeyManager::identity_from_seed(&seed, 16602);
+        assert_eq!(a.id, b.id);
+        assert_eq!(a.ed_priv, b.ed_priv);
+        assert_eq!(a.x_priv, b.x_priv);
+        assert_ne!(a.ed_priv, a.x_priv);
+        
+        let restored = KeyManager::identity_from_keys(a.ed_priv, a.x_priv, 16602);
+        assert_eq!(restored.ed_pub, a.ed_pub);
+        assert_eq!(restored.x_pub, a.x_pub);
+        
+        let other = KeyManager::identity_from_seed(&[43u8; 32], 16602);
+        assert_ne!(a.id, other.id);
+    }
+    
     #[test]
     fn test_hex_conversion() {
         let bytes = [1, 2, 3, 4, 5];
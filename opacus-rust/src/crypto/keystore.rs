@@ -0,0 +1,272 @@
+//! Password-encrypted identity export format (shared with the JS SDK)
+//!
+//! Envelope version 1:
+//! - KDF: scrypt over the UTF-8 password, 64-byte output
+//! - Cipher: AES-256-CTR keyed with the first 32 bytes, encrypting `ed_priv || x_priv`
+//! - MAC: HMAC-SHA256 keyed with the last 32 bytes, over `iv || ciphertext`
+//!
+//! Envelopes asking for more than [`MAX_SCRYPT_LOG_N`], [`MAX_SCRYPT_R`] or
+//! [`MAX_SCRYPT_P`] are refused before deriving, so an untrusted file cannot
+//! make the importer spend unbounded memory or time.
+
+use aes::cipher::{KeyIvInit, StreamCipher};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use crate::crypto::KeyManager;
+use crate::random::{OsRandom, Random};
+use crate::types::AgentIdentity;
+
+type Aes256Ctr = ctr::Ctr128BE<aes::Aes256>;
+type HmacSha256 = Hmac<Sha256>;
+
+/// Current envelope version
+pub const KEYSTORE_VERSION: u32 = 1;
+
+/// Largest log2 of the scrypt cost `N` accepted (256 MiB with `r = 8`)
+pub const MAX_SCRYPT_LOG_N: u8 = 18;
+
+/// Largest scrypt block size accepted
+pub const MAX_SCRYPT_R: u32 = 8;
+
+/// Largest scrypt parallelization accepted
+pub const MAX_SCRYPT_P: u32 = 4;
+
+/// scrypt cost parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeystoreParams {
+    /// log2 of the scrypt CPU/memory cost `N`
+    pub log_n: u8,
+    /// Block size
+    pub r: u32,
+    /// Parallelization
+    pub p: u32,
+}
+
+impl Default for KeystoreParams {
+    fn default() -> Self {
+        Self { log_n: 17, r: 8, p: 1 }
+    }
+}
+
+/// Encrypted identity envelope
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EncryptedIdentity {
+    /// Envelope version
+    pub version: u32,
+    /// Agent ID (informational; checked against the decrypted keys)
+    pub id: String,
+    /// Agent address
+    pub address: String,
+    /// Chain ID
+    pub chain_id: u64,
+    /// Encryption parameters and ciphertext
+    pub crypto: KeystoreCrypto,
+}
+
+/// Crypto section of the envelope
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeystoreCrypto {
+    /// Key derivation function (`scrypt`)
+    pub kdf: String,
+    /// KDF parameters
+    pub kdf_params: ScryptParams,
+    /// Cipher (`aes-256-ctr`)
+    pub cipher: String,
+    /// Hex-encoded 16-byte IV
+    pub iv: String,
+    /// Hex-encoded ciphertext of `ed_priv || x_priv`
+    pub ciphertext: String,
+    /// Hex-encoded HMAC-SHA256 over `iv || ciphertext`
+    pub mac: String,
+}
+
+/// scrypt parameters as stored in the envelope
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScryptParams {
+    /// CPU/memory cost (power of two)
+    pub n: u64,
+    /// Block size
+    pub r: u32,
+    /// Parallelization
+    pub p: u32,
+    /// Derived key length (64)
+    pub dk_len: usize,
+    /// Hex-encoded salt
+    pub salt: String,
+}
+
+impl EncryptedIdentity {
+    /// Encrypt an identity with a password
+    pub fn encrypt(identity: &AgentIdentity, password: &str, params: KeystoreParams) -> Result<Self, String> {
+        let mut salt = [0u8; 32];
+        let mut iv = [0u8; 16];
+        OsRandom.fill_bytes(&mut salt);
+        OsRandom.fill_bytes(&mut iv);
+
+        let kdf_params = ScryptParams {
+            n: 1u64 << params.log_n,
+            r: params.r,
+            p: params.p,
+            dk_len: 64,
+            salt: hex::encode(salt),
+        };
+        let dk = derive(password, &kdf_params)?;
+
+        let mut ciphertext = [0u8; 64];
+        ciphertext[..32].copy_from_slice(&identity.ed_priv);
+        ciphertext[32..].copy_from_slice(&identity.x_priv);
+        Aes256Ctr::new(dk[..32].into(), &iv.into()).apply_keystream(&mut ciphertext);
+
+        let mac = compute_mac(&dk[32..], &iv, &ciphertext);
+
+        Ok(Self {
+            version: KEYSTORE_VERSION,
+            id: identity.id.clone(),
+            address: identity.address.clone(),
+            chain_id: identity.chain_id,
+            crypto: KeystoreCrypto {
+                kdf: "scrypt".to_string(),
+                kdf_params,
+                cipher: "aes-256-ctr".to_string(),
+                iv: hex::encode(iv),
+                ciphertext: hex::encode(ciphertext),
+                mac: hex::encode(mac),
+            },
+        })
+    }
+
+    /// Decrypt the identity with a password
+    ///
+    /// Fails on unsupported versions or algorithms, wrong passwords
+    /// (MAC mismatch), and envelopes whose ID does not match the keys.
+    pub fn decrypt(&self, password: &str) -> Result<AgentIdentity, String> {
+        if self.version != KEYSTORE_VERSION {
+            return Err(format!("Unsupported keystore version: {}", self.version));
+        }
+        if self.crypto.kdf != "scrypt" || self.crypto.cipher != "aes-256-ctr" {
+            return Err("Unsupported keystore algorithms".into());
+        }
+
+        let iv: [u8; 16] = decode_fixed(&self.crypto.iv, "iv")?;
+        let mut plaintext: [u8; 64] = decode_fixed(&self.crypto.ciphertext, "ciphertext")?;
+        let mac = hex::decode(&self.crypto.mac).map_err(|e| format!("Invalid mac: {}", e))?;
+
+        let dk = derive(password, &self.crypto.kdf_params)?;
+        let mut hmac = HmacSha256::new_from_slice(&dk[32..]).expect("HMAC key error");
+        hmac.update(&iv);
+        hmac.update(&plaintext);
+        hmac.verify_slice(&mac).map_err(|_| "Wrong password or corrupted keystore".to_string())?;
+
+        Aes256Ctr::new(dk[..32].into(), &iv.into()).apply_keystream(&mut plaintext);
+        let ed_priv: [u8; 32] = plaintext[..32].try_into().unwrap();
+        let x_priv: [u8; 32] = plaintext[32..].try_into().unwrap();
+
+        let identity = KeyManager::identity_from_keys(ed_priv, x_priv, self.chain_id);
+        if identity.id != self.id {
+            return Err("Keystore ID does not match decrypted keys".into());
+        }
+        Ok(identity)
+    }
+}
+
+fn derive(password: &str, params: &ScryptParams) -> Result<[u8; 64], String> {
+    if params.dk_len != 64 {
+        return Err(format!("Unsupported dkLen: {}", params.dk_len));
+    }
+    if !params.n.is_power_of_two() || params.n < 2 {
+        return Err(format!("Invalid scrypt N: {}", params.n));
+    }
+    if params.n > 1 << MAX_SCRYPT_LOG_N || params.r > MAX_SCRYPT_R || params.p > MAX_SCRYPT_P {
+        return Err(format!("scrypt parameters exceed the limits: N={} r={} p={}", params.n, params.r, params.p));
+    }
+    let salt = hex::decode(&params.salt).map_err(|e| format!("Invalid salt: {}", e))?;
+    let scrypt_params = scrypt::Params::new(params.n.trailing_zeros() as u8, params.r, params.p, 64)
+        .map_err(|e| format!("Invalid scrypt params: {}", e))?;
+    let mut dk = [0u8; 64];
+    scrypt::scrypt(password.as_bytes(), &salt, &scrypt_params, &mut dk)
+        .map_err(|e| format!("scrypt failed: {}", e))?;
+    Ok(dk)
+}
+
+fn compute_mac(key: &[u8], iv: &[u8], ciphertext: &[u8]) -> [u8; 32] {
+    let mut hmac = HmacSha256::new_from_slice(key).expect("HMAC key error");
+    hmac.update(iv);
+    hmac.update(ciphertext);
+    hmac.finalize().into_bytes().into()
+}
+
+fn decode_fixed<const N: usize>(s: &str, field: &str) -> Result<[u8; N], String> {
+    hex::decode(s)
+        .map_err(|e| format!("Invalid {}: {}", field, e))?
+        .try_into()
+        .map_err(|_| format!("Invalid {} length", field))
+}
+
+impl KeyManager {
+    /// Export identity as a password-encrypted JSON envelope
+    pub fn export_encrypted(identity: &AgentIdentity, password: &str) -> Result<String, String> {
+        Self::export_encrypted_with(identity, password, KeystoreParams::default())
+    }
+
+    /// Export identity as a password-encrypted JSON envelope with custom scrypt cost
+    pub fn export_encrypted_with(
+        identity: &AgentIdentity,
+        password: &str,
+        params: KeystoreParams,
+    ) -> Result<String, String> {
+        let envelope = EncryptedIdentity::encrypt(identity, password, params)?;
+        serde_json::to_string_pretty(&envelope).map_err(|e| e.to_string())
+    }
+
+    /// Import identity from a password-encrypted JSON envelope
+    pub fn import_encrypted(json: &str, password: &str) -> Result<AgentIdentity, String> {
+        let envelope: EncryptedIdentity = serde_json::from_str(json)
+            .map_err(|e| format!("Invalid keystore: {}", e))?;
+        envelope.decrypt(password)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAST: KeystoreParams = KeystoreParams { log_n: 4, r: 8, p: 1 };
+
+    #[test]
+    fn test_export_import_roundtrip() {
+        let identity = KeyManager::generate_identity(16602);
+        let json = KeyManager::export_encrypted_with(&identity, "hunter2", FAST).unwrap();
+        assert!(!json.contains(&hex::encode(identity.ed_priv)));
+
+        let restored = KeyManager::import_encrypted(&json, "hunter2").unwrap();
+        assert_eq!(restored.id, identity.id);
+        assert_eq!(restored.ed_priv, identity.ed_priv);
+        assert_eq!(restored.x_priv, identity.x_priv);
+        assert_eq!(restored.chain_id, 16602);
+    }
+
+    #[test]
+    fn test_wrong_password_and_tampering() {
+        let identity = KeyManager::generate_identity(16602);
+        let envelope = EncryptedIdentity::encrypt(&identity, "right", FAST).unwrap();
+        assert!(envelope.decrypt("wrong").is_err());
+
+        let mut tampered = envelope.clone();
+        tampered.id = "someone-else".to_string();
+        assert!(tampered.decrypt("right").is_err());
+
+        let mut unsupported = envelope.clone();
+        unsupported.version = 2;
+        assert!(unsupported.decrypt("right").is_err());
+
+        // Costs beyond the limits are refused before deriving
+        let mut costly = envelope;
+        costly.crypto.kdf_params.n = 1 << 40;
+        assert!(costly.decrypt("right").unwrap_err().contains("exceed the limits"));
+        assert!(EncryptedIdentity::encrypt(&identity, "right", KeystoreParams { log_n: 4, r: 8, p: 64 }).is_err());
+    }
+}
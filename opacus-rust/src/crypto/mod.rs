@@ -1,7 +1,25 @@
 //! Cryptography modules
 
+pub mod backend;
 pub mod keys;
 pub mod security;
+pub mod prekeys;
+pub mod fingerprint;
+pub mod keystore;
+pub mod nonce;
+pub mod rekey;
+pub mod unified;
+#[cfg(feature = "bls")]
+pub mod bls;
 
+pub use backend::*;
 pub use keys::*;
 pub use security::*;
+pub use prekeys::*;
+pub use fingerprint::*;
+pub use keystore::*;
+pub use nonce::*;
+pub use rekey::*;
+pub use unified::*;
+#[cfg(feature = "bls")]
+pub use bls::*;
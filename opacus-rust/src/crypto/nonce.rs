@@ -0,0 +1,169 @@
+//! Bounded replay window for anti-replay nonces
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Default maximum number of remembered nonces
+pub const DEFAULT_NONCE_CAPACITY: usize = 100_000;
+
+/// Size-bounded set of recently seen nonces, kept per peer
+///
+/// Nonces expire once their timestamp leaves the freshness window. When the
+/// window is full the least recently used nonce is evicted (a replay counts
+/// as a use) and its timestamp becomes a floor for the peer that sent it:
+/// that peer's nonces stamped at or before the floor are rejected, since the
+/// window can no longer prove they were not seen. A peer flooding unique
+/// nonces only raises its own floor, and memory stays bounded.
+#[derive(Debug)]
+pub struct NonceWindow {
+    /// Last use and timestamp of each remembered nonce, by peer and nonce
+    seen: HashMap<(String, String), (u64, u64)>,
+    /// Remembered nonces by last use, least recent first
+    lru: BTreeMap<u64, (String, String)>,
+    /// Remembered nonces by timestamp, for expiry
+    by_ts: BTreeSet<(u64, String, String)>,
+    /// Eviction floor of each peer
+    floors: HashMap<String, u64>,
+    capacity: usize,
+    /// Use counter ordering `lru`
+    tick: u64,
+}
+
+impl NonceWindow {
+    /// Create window holding at most `capacity` nonces
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            seen: HashMap::new(),
+            lru: BTreeMap::new(),
+            by_ts: BTreeSet::new(),
+            floors: HashMap::new(),
+            capacity: capacity.max(1),
+            tick: 0,
+        }
+    }
+
+    /// Record a nonce
+    ///
+    /// # Arguments
+    /// * `peer` - Agent that sent the nonce
+    /// * `nonce` - Nonce string
+    /// * `nonce_ts` - Timestamp embedded in the nonce
+    ///
+    /// # Returns
+    /// `false` if the peer's nonce was already seen or is older than its
+    /// eviction floor
+    pub fn insert(&mut self, peer: &str, nonce: &str, nonce_ts: u64) -> bool {
+        if self.floors.get(peer).is_some_and(|floor| nonce_ts <= *floor) {
+            return false;
+        }
+        let key = (peer.to_string(), nonce.to_string());
+        self.tick += 1;
+        if let Some((used, _)) = self.seen.get_mut(&key) {
+            // Replayed nonces stay remembered longest
+            self.lru.remove(used);
+            *used = self.tick;
+            self.lru.insert(self.tick, key);
+            return false;
+        }
+
+        while self.seen.len() >= self.capacity {
+            self.evict();
+        }
+        self.lru.insert(self.tick, key.clone());
+        self.by_ts.insert((nonce_ts, key.0.clone(), key.1.clone()));
+        self.seen.insert(key, (self.tick, nonce_ts));
+        true
+    }
+
+    /// Drop nonces stamped more than `max_age` before `now`, and floors below that
+    ///
+    /// Only use an age beyond which nonces fail the freshness check anyway.
+    pub fn expire(&mut self, now: u64, max_age: u64) {
+        let cutoff = now.saturating_sub(max_age);
+        while self.by_ts.first().is_some_and(|(ts, _, _)| *ts < cutoff) {
+            let (_, peer, nonce) = self.by_ts.pop_first().expect("checked above");
+            if let Some((used, _)) = self.seen.remove(&(peer, nonce)) {
+                self.lru.remove(&used);
+            }
+        }
+        self.floors.retain(|_, floor| *floor >= cutoff);
+    }
+
+    /// Change capacity, evicting the least recently used entries if needed
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+        while self.seen.len() > self.capacity {
+            self.evict();
+        }
+    }
+
+    /// Evict the least recently used nonce, raising its peer's floor
+    fn evict(&mut self) {
+        let Some((_, key)) = self.lru.pop_first() else { return };
+        if let Some((_, ts)) = self.seen.remove(&key) {
+            let (peer, nonce) = key;
+            self.by_ts.remove(&(ts, peer.clone(), nonce));
+            let floor = self.floors.entry(peer).or_insert(ts);
+            *floor = (*floor).max(ts);
+        }
+    }
+
+    /// Eviction floor of a peer, if any of its nonces were evicted
+    pub fn floor(&self, peer: &str) -> Option<u64> {
+        self.floors.get(peer).copied()
+    }
+
+    /// Maximum number of remembered nonces
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of remembered nonces
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    /// Check if window is empty
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+}
+
+impl Default for NonceWindow {
+    fn default() -> Self {
+        Self::new(DEFAULT_NONCE_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capacity_bound_and_floor() {
+        let mut window = NonceWindow::new(3);
+        assert!(window.insert("bob", "5-b", 5));
+        for i in 0..2u64 {
+            assert!(window.insert("mallory", &format!("{}-a", i), i));
+        }
+        assert!(!window.insert("bob", "5-b", 5)); // Replay, now most recently used
+
+        // Mallory's flood evicts her own older nonces, not Bob's replayed one
+        assert!(window.insert("mallory", "10-a", 10));
+        assert!(window.insert("mallory", "11-a", 11));
+        assert_eq!(window.len(), 3);
+        assert_eq!((window.floor("mallory"), window.floor("bob")), (Some(1), None));
+        assert!(!window.insert("mallory", "1-b", 1));
+        assert!(!window.insert("bob", "5-b", 5));
+        assert!(window.insert("bob", "1-c", 1));
+    }
+
+    #[test]
+    fn test_expire_by_age() {
+        let mut window = NonceWindow::new(10);
+        window.insert("bob", "a", 0);
+        window.insert("bob", "b", 50);
+        window.expire(100, 60);
+        assert_eq!(window.len(), 1);
+        assert!(window.insert("bob", "a", 100)); // Expired, not evicted: no floor
+    }
+}
@@ -0,0 +1,295 @@
+//! X3DH-style prekey bundles for establishing sessions with offline agents
+
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use hkdf::Hkdf;
+use std::collections::HashMap;
+use crate::types::AgentIdentity;
+use crate::crypto::{KeyManager, SecurityManager};
+use crate::random::{OsRandom, Random};
+
+/// HKDF info string for X3DH session keys
+const X3DH_INFO: &[u8] = b"opacus-x3dh";
+
+/// Medium-term prekey signed by the agent's Ed25519 identity key
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedPreKey {
+    /// Prekey identifier
+    pub id: u32,
+    /// X25519 public key
+    pub public: [u8; 32],
+    /// Ed25519 signature over the public key
+    pub signature: Vec<u8>,
+}
+
+/// Single-use prekey
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct OneTimePreKey {
+    /// Prekey identifier
+    pub id: u32,
+    /// X25519 public key
+    pub public: [u8; 32],
+}
+
+/// Prekey bundle published to the relay
+///
+/// When published, `one_time_prekeys` holds every available one-time prekey.
+/// When handed out by the relay it holds at most one.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PreKeyBundle {
+    /// Owner agent ID
+    pub agent_id: String,
+    /// Owner Ed25519 public key
+    pub ed_pub: [u8; 32],
+    /// Owner X25519 identity key
+    pub x_pub: [u8; 32],
+    /// Signed prekey
+    pub signed_prekey: SignedPreKey,
+    /// One-time prekeys
+    pub one_time_prekeys: Vec<OneTimePreKey>,
+}
+
+impl PreKeyBundle {
+    /// Verify that the bundle belongs to `agent_id` and the signed prekey is authentic
+    pub fn verify(&self) -> Result<(), String> {
+        if KeyManager::agent_id(&self.ed_pub) != self.agent_id {
+            return Err("Agent ID does not match identity key".into());
+        }
+        if !SecurityManager::verify(
+            &self.ed_pub,
+            &self.signed_prekey.public,
+            &self.signed_prekey.signature,
+        ) {
+            return Err("Invalid signed prekey signature".into());
+        }
+        Ok(())
+    }
+
+    /// Take one one-time prekey out of the bundle, returning the bundle to hand out
+    pub fn take_one(&mut self) -> PreKeyBundle {
+        let one_time = if self.one_time_prekeys.is_empty() {
+            vec![]
+        } else {
+            vec![self.one_time_prekeys.remove(0)]
+        };
+        PreKeyBundle {
+            one_time_prekeys: one_time,
+            ..self.clone()
+        }
+    }
+}
+
+/// Header sent by the initiator so the recipient can derive the same session key
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct X3DHHeader {
+    /// Initiator X25519 identity key
+    pub identity_key: [u8; 32],
+    /// Initiator ephemeral key
+    pub ephemeral_key: [u8; 32],
+    /// Signed prekey used
+    pub signed_prekey_id: u32,
+    /// One-time prekey used, if any
+    pub one_time_prekey_id: Option<u32>,
+}
+
+/// Private half of published prekeys, kept by the owning agent
+pub struct PreKeyStore {
+    signed_prekey_public: SignedPreKey,
+    signed_prekey: [u8; 32],
+    one_time: HashMap<u32, [u8; 32]>,
+    next_id: u32,
+}
+
+impl PreKeyStore {
+    /// Generate a signed prekey and `one_time_count` one-time prekeys
+    ///
+    /// # Returns
+    /// The store and the bundle to publish
+    pub fn generate(identity: &AgentIdentity, one_time_count: u32) -> (Self, PreKeyBundle) {
+        Self::generate_with(identity, one_time_count, &OsRandom)
+    }
+
+    /// Generate prekeys from a custom source of randomness
+    pub fn generate_with(identity: &AgentIdentity, one_time_count: u32, random: &dyn Random) -> (Self, PreKeyBundle) {
+        let (spk_secret, spk_public) = KeyManager::generate_x25519_with(random);
+        let signature = SecurityManager::sign(&identity.ed_priv, spk_public.as_bytes());
+
+        let mut store = Self {
+            signed_prekey_public: SignedPreKey {
+                id: 1,
+                public: spk_public.to_bytes(),
+                signature,
+            },
+            signed_prekey: spk_secret.to_bytes(),
+            one_time: HashMap::new(),
+            next_id: 1,
+        };
+        let bundle = store.replenish_with(identity, one_time_count, random);
+        (store, bundle)
+    }
+
+    /// Generate `count` additional one-time prekeys
+    ///
+    /// # Returns
+    /// Bundle carrying only the new one-time prekeys, to publish to the relay
+    pub fn replenish(&mut self, identity: &AgentIdentity, count: u32) -> PreKeyBundle {
+        self.replenish_with(identity, count, &OsRandom)
+    }
+
+    /// Generate additional one-time prekeys from a custom source of randomness
+    pub fn replenish_with(&mut self, identity: &AgentIdentity, count: u32, random: &dyn Random) -> PreKeyBundle {
+        let one_time_prekeys = (0..count)
+            .map(|_| {
+                let (secret, public) = KeyManager::generate_x25519_with(random);
+                let id = self.next_id;
+                self.next_id += 1;
+                self.one_time.insert(id, secret.to_bytes());
+                OneTimePreKey { id, public: public.to_bytes() }
+            })
+            .collect();
+
+        PreKeyBundle {
+            agent_id: identity.id.clone(),
+            ed_pub: identity.ed_pub,
+            x_pub: identity.x_pub,
+            signed_prekey: self.signed_prekey_public.clone(),
+            one_time_prekeys,
+        }
+    }
+
+    /// Number of unused one-time prekeys
+    pub fn one_time_count(&self) -> usize {
+        self.one_time.len()
+    }
+}
+
+/// X3DH key agreement
+pub struct X3DH;
+
+impl X3DH {
+    /// Derive a session key for an (possibly offline) recipient from their bundle
+    ///
+    /// # Returns
+    /// 32-byte session key and the header to send with the first message
+    pub fn initiate(
+        identity: &AgentIdentity,
+        bundle: &PreKeyBundle,
+    ) -> Result<([u8; 32], X3DHHeader), String> {
+        Self::initiate_with(identity, bundle, &OsRandom)
+    }
+
+    /// Derive a session key with an ephemeral key from a custom source of randomness
+    pub fn initiate_with(
+        identity: &AgentIdentity,
+        bundle: &PreKeyBundle,
+        random: &dyn Random,
+    ) -> Result<([u8; 32], X3DHHeader), String> {
+        bundle.verify()?;
+
+        let (ephemeral, ephemeral_pub) = KeyManager::generate_x25519_with(random);
+        let ephemeral_pub = ephemeral_pub.to_bytes();
+        let ek = ephemeral.to_bytes();
+        let spk = &bundle.signed_prekey.public;
+        let opk = bundle.one_time_prekeys.first();
+
+        let mut dh = Vec::with_capacity(128);
+        dh.extend_from_slice(&SecurityManager::derive_shared_secret(&identity.x_priv, spk));
+        dh.extend_from_slice(&SecurityManager::derive_shared_secret(&ek, &bundle.x_pub));
+        dh.extend_from_slice(&SecurityManager::derive_shared_secret(&ek, spk));
+        if let Some(opk) = opk {
+            dh.extend_from_slice(&SecurityManager::derive_shared_secret(&ek, &opk.public));
+        }
+
+        let header = X3DHHeader {
+            identity_key: identity.x_pub,
+            ephemeral_key: ephemeral_pub,
+            signed_prekey_id: bundle.signed_prekey.id,
+            one_time_prekey_id: opk.map(|k| k.id),
+        };
+        Ok((Self::kdf(&dh), header))
+    }
+
+    /// Derive the initiator's session key on the recipient side
+    ///
+    /// Consumes the referenced one-time prekey so it cannot be reused.
+    pub fn respond(
+        identity: &AgentIdentity,
+        store: &mut PreKeyStore,
+        header: &X3DHHeader,
+    ) -> Result<[u8; 32], String> {
+        if header.signed_prekey_id != store.signed_prekey_public.id {
+            return Err("Unknown signed prekey".into());
+        }
+        let spk = &store.signed_prekey;
+
+        let mut dh = Vec::with_capacity(128);
+        dh.extend_from_slice(&SecurityManager::derive_shared_secret(spk, &header.identity_key));
+        dh.extend_from_slice(&SecurityManager::derive_shared_secret(&identity.x_priv, &header.ephemeral_key));
+        dh.extend_from_slice(&SecurityManager::derive_shared_secret(spk, &header.ephemeral_key));
+        if let Some(id) = header.one_time_prekey_id {
+            let opk = store.one_time.remove(&id).ok_or("Unknown or used one-time prekey")?;
+            dh.extend_from_slice(&SecurityManager::derive_shared_secret(&opk, &header.ephemeral_key));
+        }
+
+        Ok(Self::kdf(&dh))
+    }
+
+    fn kdf(dh: &[u8]) -> [u8; 32] {
+        let mut ikm = vec![0xFFu8; 32];
+        ikm.extend_from_slice(dh);
+        let hk = Hkdf::<Sha256>::new(Some(&[0u8; 32]), &ikm);
+        let mut okm = [0u8; 32];
+        hk.expand(X3DH_INFO, &mut okm).expect("HKDF expand failed");
+        okm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_x3dh_agreement() {
+        let alice = KeyManager::generate_identity(16602);
+        let bob = KeyManager::generate_identity(16602);
+        let (mut store, mut published) = PreKeyStore::generate(&bob, 2);
+
+        let bundle = published.take_one();
+        let (alice_key, header) = X3DH::initiate(&alice, &bundle).unwrap();
+        let bob_key = X3DH::respond(&bob, &mut store, &header).unwrap();
+
+        assert_eq!(alice_key, bob_key);
+        assert_eq!(store.one_time_count(), 1);
+        assert!(X3DH::respond(&bob, &mut store, &header).is_err()); // One-time key reused
+    }
+
+    #[test]
+    fn test_x3dh_without_one_time_prekey() {
+        let alice = KeyManager::generate_identity(16602);
+        let bob = KeyManager::generate_identity(16602);
+        let (mut store, mut published) = PreKeyStore::generate(&bob, 0);
+
+        let (alice_key, header) = X3DH::initiate(&alice, &published.take_one()).unwrap();
+        assert_eq!(header.one_time_prekey_id, None);
+        assert_eq!(alice_key, X3DH::respond(&bob, &mut store, &header).unwrap());
+
+        let more = store.replenish(&bob, 3);
+        assert_eq!(more.signed_prekey, published.signed_prekey);
+        assert_eq!(store.one_time_count(), 3);
+        assert_eq!(more.one_time_prekeys.len(), 3);
+    }
+
+    #[test]
+    fn test_tampered_bundle_rejected() {
+        let alice = KeyManager::generate_identity(16602);
+        let bob = KeyManager::generate_identity(16602);
+        let (_, mut bundle) = PreKeyStore::generate(&bob, 1);
+
+        bundle.signed_prekey.public = KeyManager::generate_x25519().1.to_bytes();
+        assert!(X3DH::initiate(&alice, &bundle).is_err());
+    }
+}
@@ -0,0 +1,239 @@
+//! Session key epochs and rekey thresholds
+//!
+//! Frame session keys are bound to a key epoch. The sender counts frames and
+//! payload bytes per peer and, once a threshold in the `RekeyPolicy` is hit,
+//! announces the next epoch in a `Rekey` frame. It keeps keying frames with
+//! the current epoch until the peer acknowledges the rekey, and announces
+//! again if no acknowledgement arrives within [`REKEY_RETRY_MS`], so a lost
+//! `Rekey` frame never leaves the peer unable to verify later frames.
+//! Receivers only advance on an authenticated `Rekey` frame, and keep
+//! accepting the previous epoch so frames still in flight are not dropped.
+//!
+//! Per peer only the current epoch is kept, with the announced one on the
+//! sending side; older epochs are implied and never stored.
+
+use std::collections::HashMap;
+
+/// Time after which an unacknowledged rekey is announced again (milliseconds)
+pub const REKEY_RETRY_MS: u64 = 30_000;
+
+/// Thresholds after which a session key is replaced
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RekeyPolicy {
+    /// Maximum frames sent under one key
+    pub max_frames: u64,
+    /// Maximum payload bytes sent under one key
+    pub max_bytes: u64,
+    /// Maximum key lifetime (milliseconds)
+    pub max_age_ms: u64,
+}
+
+impl Default for RekeyPolicy {
+    fn default() -> Self {
+        Self {
+            max_frames: 1 << 20,
+            max_bytes: 1 << 30,
+            max_age_ms: 60 * 60 * 1000,
+        }
+    }
+}
+
+/// Usage of the current outbound key for one peer
+#[derive(Debug, Clone, Copy)]
+struct SendEpoch {
+    epoch: u32,
+    frames: u64,
+    bytes: u64,
+    started_at: u64,
+    /// Epoch announced but not acknowledged yet, and when it was announced
+    next: Option<(u32, u64)>,
+}
+
+/// Per-peer key epoch bookkeeping
+#[derive(Debug, Default)]
+pub struct KeyEpochs {
+    policy: RekeyPolicy,
+    send: HashMap<String, SendEpoch>,
+    recv: HashMap<String, u32>,
+}
+
+impl KeyEpochs {
+    /// Create tracker with a rekey policy
+    pub fn new(policy: RekeyPolicy) -> Self {
+        Self {
+            policy,
+            ..Self::default()
+        }
+    }
+
+    /// Get the rekey policy
+    pub fn policy(&self) -> RekeyPolicy {
+        self.policy
+    }
+
+    /// Set the rekey policy (applies to keys already in use)
+    pub fn set_policy(&mut self, policy: RekeyPolicy) {
+        self.policy = policy;
+    }
+
+    /// Current outbound epoch for a peer
+    pub fn send_epoch(&self, peer: &str) -> u32 {
+        self.send.get(peer).map_or(0, |s| s.epoch)
+    }
+
+    /// Outbound epoch announced to a peer and not acknowledged yet
+    pub fn pending_epoch(&self, peer: &str) -> Option<u32> {
+        self.send.get(peer).and_then(|s| s.next).map(|(epoch, _)| epoch)
+    }
+
+    /// Current inbound epoch for a peer
+    pub fn recv_epoch(&self, peer: &str) -> u32 {
+        self.recv.get(peer).copied().unwrap_or(0)
+    }
+
+    /// Account for a frame sent to `peer`
+    ///
+    /// # Returns
+    /// Epoch the frame must be keyed with
+    pub fn record_send(&mut self, peer: &str, payload_len: usize, now: u64) -> u32 {
+        let state = self.send.entry(peer.to_string()).or_insert(SendEpoch {
+            epoch: 0,
+            frames: 0,
+            bytes: 0,
+            started_at: now,
+            next: None,
+        });
+        state.frames += 1;
+        state.bytes = state.bytes.saturating_add(payload_len as u64);
+        state.epoch
+    }
+
+    /// Check whether the outbound key for `peer` has reached a threshold,
+    /// or an announced rekey went unacknowledged for [`REKEY_RETRY_MS`]
+    pub fn rekey_due(&self, peer: &str, now: u64) -> bool {
+        let Some(state) = self.send.get(peer) else {
+            return false;
+        };
+        if let Some((_, announced_at)) = state.next {
+            return now.saturating_sub(announced_at) >= REKEY_RETRY_MS;
+        }
+        state.frames >= self.policy.max_frames
+            || state.bytes >= self.policy.max_bytes
+            || now.saturating_sub(state.started_at) >= self.policy.max_age_ms
+    }
+
+    /// Announce the next outbound epoch for `peer`
+    ///
+    /// Frames keep the current epoch until [`KeyEpochs::confirm_send`];
+    /// announcing again before then repeats the same epoch.
+    ///
+    /// # Returns
+    /// The announced epoch
+    pub fn announce_send(&mut self, peer: &str, now: u64) -> u32 {
+        let state = self.send.entry(peer.to_string()).or_insert(SendEpoch {
+            epoch: 0,
+            frames: 0,
+            bytes: 0,
+            started_at: now,
+            next: None,
+        });
+        let epoch = match state.next {
+            Some((epoch, _)) => epoch,
+            None => state.epoch.checked_add(1).expect("key epoch overflow"),
+        };
+        state.next = Some((epoch, now));
+        epoch
+    }
+
+    /// Move the outbound key for `peer` to an announced epoch the peer acknowledged
+    ///
+    /// # Returns
+    /// Whether `epoch` was the announced one
+    pub fn confirm_send(&mut self, peer: &str, epoch: u32, now: u64) -> bool {
+        let Some(state) = self.send.get_mut(peer).filter(|s| s.next.is_some_and(|(next, _)| next == epoch)) else {
+            return false;
+        };
+        *state = SendEpoch {
+            epoch,
+            frames: 0,
+            bytes: 0,
+            started_at: now,
+            next: None,
+        };
+        true
+    }
+
+    /// Check an inbound frame's epoch against the current one
+    ///
+    /// The previous epoch stays valid for in-flight frames; only a rekey
+    /// frame may announce the next epoch.
+    pub fn check_recv(&self, peer: &str, epoch: u32, is_rekey: bool) -> Result<(), String> {
+        let current = self.recv_epoch(peer);
+        if epoch > current {
+            if is_rekey && epoch == current + 1 {
+                return Ok(());
+            }
+            return Err(format!("Unexpected key epoch {} (current {})", epoch, current));
+        }
+        if epoch.saturating_add(1) < current {
+            return Err(format!("Stale key epoch {} (current {})", epoch, current));
+        }
+        Ok(())
+    }
+
+    /// Record that `peer` moved to `epoch`
+    pub fn advance_recv(&mut self, peer: &str, epoch: u32) {
+        let current = self.recv.entry(peer.to_string()).or_insert(0);
+        *current = (*current).max(epoch);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thresholds() {
+        let mut epochs = KeyEpochs::new(RekeyPolicy { max_frames: 3, max_bytes: 100, max_age_ms: 1_000 });
+        assert!(!epochs.rekey_due("bob", 0));
+
+        for _ in 0..2 {
+            assert_eq!(epochs.record_send("bob", 10, 0), 0);
+        }
+        assert!(!epochs.rekey_due("bob", 0));
+        epochs.record_send("bob", 10, 0);
+        assert!(epochs.rekey_due("bob", 0));
+
+        // The current epoch stays in use until the peer acknowledges
+        assert_eq!(epochs.announce_send("bob", 0), 1);
+        assert!(!epochs.rekey_due("bob", 0));
+        assert_eq!((epochs.record_send("bob", 10, 0), epochs.pending_epoch("bob")), (0, Some(1)));
+        assert!(epochs.rekey_due("bob", REKEY_RETRY_MS)); // Announce again
+        assert_eq!(epochs.announce_send("bob", REKEY_RETRY_MS), 1);
+        assert!(!epochs.confirm_send("bob", 2, 0));
+        assert!(epochs.confirm_send("bob", 1, 0));
+        assert_eq!((epochs.send_epoch("bob"), epochs.pending_epoch("bob")), (1, None));
+        epochs.record_send("bob", 100, 0);
+        assert!(epochs.rekey_due("bob", 0)); // Bytes
+
+        epochs.announce_send("bob", 0);
+        epochs.confirm_send("bob", 2, 0);
+        epochs.record_send("bob", 1, 0);
+        assert!(!epochs.rekey_due("bob", 999));
+        assert!(epochs.rekey_due("bob", 1_000)); // Age
+    }
+
+    #[test]
+    fn test_recv_window() {
+        let mut epochs = KeyEpochs::default();
+        assert!(epochs.check_recv("alice", 0, false).is_ok());
+        assert!(epochs.check_recv("alice", 1, false).is_err());
+        assert!(epochs.check_recv("alice", 2, true).is_err());
+        assert!(epochs.check_recv("alice", 1, true).is_ok());
+
+        epochs.advance_recv("alice", 1);
+        epochs.advance_recv("alice", 2);
+        assert!(epochs.check_recv("alice", 1, false).is_ok()); // In flight
+        assert!(epochs.check_recv("alice", 0, false).is_err());
+    }
+}
@@ -1,32 +1,141 @@
 //! Security operations: ECDH, HKDF, HMAC, signatures, nonces
 
-use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
-use x25519_dalek::{StaticSecret, PublicKey as X25519Public};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
 use sha2::Sha256;
 use hmac::{Hmac, Mac};
 use hkdf::Hkdf;
-use rand::Rng;
-use std::collections::HashMap;
-use std::time::{SystemTime, UNIX_EPOCH};
-use crate::types::{AgentIdentity, OpacusFrame, FrameType};
+use std::sync::Arc;
+use bytes::Bytes;
+use crate::clock::{Clock, SystemClock};
+use crate::random::{OsRandom, Random};
+use crate::crypto::backend::{CryptoBackend, DefaultBackend};
+use crate::crypto::nonce::NonceWindow;
+use crate::crypto::rekey::{KeyEpochs, RekeyPolicy};
+use crate::compression::Compression;
+use crate::proto::{CBORCodec, FRAME_VERSION};
+use crate::types::{AgentIdentity, FrameOptions, OpacusFrame, FrameType};
 
 type HmacSha256 = Hmac<Sha256>;
 
+/// Default tolerated clock skew between peers (milliseconds)
+pub const DEFAULT_MAX_SKEW_MS: u64 = 5_000;
+
+/// Prefix of the HKDF info string for frame session keys
+const SESSION_INFO_PREFIX: &[u8] = b"opacus-session";
+
+/// Payload field of `Ack` frames acknowledging a rekey
+pub const REKEY_ACK_FIELD: &str = "rekeyAck";
+
+/// HKDF parameters for key derivation
+#[derive(Debug, Clone, Copy)]
+pub struct HkdfParams<'a> {
+    /// Optional salt (HKDF uses a zero-filled salt when `None`)
+    pub salt: Option<&'a [u8]>,
+    /// Context information binding the key to its use
+    pub info: &'a [u8],
+    /// Output length in bytes (at most 8160 for SHA-256)
+    pub length: usize,
+}
+
 /// Security manager for authentication and encryption
 pub struct SecurityManager {
-    nonce_window: HashMap<String, u64>,
+    nonce_window: NonceWindow,
     last_nonce: u64,
+    session_salt: Option<Vec<u8>>,
+    clock: Arc<dyn Clock>,
+    random: Arc<dyn Random>,
+    max_skew_ms: u64,
+    epochs: KeyEpochs,
 }
 
 impl SecurityManager {
     /// Create new security manager
     pub fn new() -> Self {
         Self {
-            nonce_window: HashMap::new(),
+            nonce_window: NonceWindow::default(),
             last_nonce: 0,
+            session_salt: None,
+            clock: Arc::new(SystemClock),
+            random: Arc::new(OsRandom),
+            max_skew_ms: DEFAULT_MAX_SKEW_MS,
+            epochs: KeyEpochs::default(),
         }
     }
     
+    /// Create security manager with a custom time source
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            clock,
+            ..Self::new()
+        }
+    }
+    
+    /// Create security manager with custom time and randomness sources
+    /// 
+    /// Nonces and message IDs are drawn from `random`.
+    pub fn with_sources(clock: Arc<dyn Clock>, random: Arc<dyn Random>) -> Self {
+        Self {
+            clock,
+            random,
+            ..Self::new()
+        }
+    }
+    
+    /// Set tolerated clock skew for timestamp checks
+    /// 
+    /// Nonces may be up to `max_skew_ms` in the future, and up to
+    /// `max_age_ms + max_skew_ms` in the past.
+    pub fn set_max_skew(&mut self, max_skew_ms: u64) {
+        self.max_skew_ms = max_skew_ms;
+    }
+    
+    /// Set the maximum number of nonces remembered for replay protection
+    /// 
+    /// When full, the least recently used nonce is evicted, and nonces its
+    /// sender stamps at or before it are rejected from then on.
+    pub fn set_nonce_capacity(&mut self, capacity: usize) {
+        self.nonce_window.set_capacity(capacity);
+    }
+    
+    /// Number of nonces currently remembered
+    pub fn nonce_count(&self) -> usize {
+        self.nonce_window.len()
+    }
+    
+    /// Get the time source
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+    
+    /// Get the source of randomness
+    pub fn random(&self) -> &Arc<dyn Random> {
+        &self.random
+    }
+    
+    /// Set thresholds after which outbound session keys are rekeyed
+    pub fn set_rekey_policy(&mut self, policy: RekeyPolicy) {
+        self.epochs.set_policy(policy);
+    }
+    
+    /// Current outbound session key epoch for a peer
+    pub fn session_epoch(&self, peer: &str) -> u32 {
+        self.epochs.send_epoch(peer)
+    }
+    
+    /// Check whether the session key for a peer should be rotated
+    /// 
+    /// When `true`, send the frame from `create_rekey_frame` next.
+    pub fn rekey_due(&self, peer: &str) -> bool {
+        self.epochs.rekey_due(peer, self.clock.now_ms())
+    }
+    
+    /// Set the HKDF salt used for frame session keys
+    /// 
+    /// Both peers must use the same salt.
+    pub fn set_session_salt(&mut self, salt: Option<Vec<u8>>) {
+        self.session_salt = salt;
+    }
+    
     /// Derive shared secret using ECDH
     /// 
     /// # Arguments
@@ -36,9 +145,7 @@ impl SecurityManager {
     /// # Returns
     /// 32-byte shared secret
     pub fn derive_shared_secret(my_priv: &[u8; 32], peer_pub: &[u8; 32]) -> [u8; 32] {
-        let secret = StaticSecret::from(*my_priv);
-        let public = X25519Public::from(*peer_pub);
-        *secret.diffie_hellman(&public).as_bytes()
+        DefaultBackend::x25519(my_priv, peer_pub)
     }
     
     /// Derive session key using HKDF
@@ -56,6 +163,62 @@ impl SecurityManager {
         okm
     }
     
+    /// Derive key material using HKDF-SHA256 with explicit parameters
+    /// 
+    /// # Arguments
+    /// * `shared` - Input key material
+    /// * `params` - Salt, info and output length
+    /// 
+    /// # Returns
+    /// `params.length` bytes of key material, or an error if the length is too large
+    pub fn derive_key(shared: &[u8], params: &HkdfParams) -> Result<Vec<u8>, String> {
+        let hk = Hkdf::<Sha256>::new(params.salt, shared);
+        let mut okm = vec![0u8; params.length];
+        hk.expand(params.info, &mut okm)
+            .map_err(|_| format!("Invalid HKDF output length: {}", params.length))?;
+        Ok(okm)
+    }
+    
+    /// Build the HKDF info for a session between two agents
+    /// 
+    /// Binds the key to the protocol version and both party IDs. IDs are
+    /// ordered so both sides compute the same value.
+    pub fn session_info(version: u8, party_a: &str, party_b: &str) -> Vec<u8> {
+        let (first, second) = if party_a <= party_b {
+            (party_a, party_b)
+        } else {
+            (party_b, party_a)
+        };
+        let mut info = SESSION_INFO_PREFIX.to_vec();
+        info.push(version);
+        for id in [first, second] {
+            info.extend_from_slice(&(id.len() as u32).to_be_bytes());
+            info.extend_from_slice(id.as_bytes());
+        }
+        info
+    }
+    
+    /// HKDF info of a frame's session key at a key epoch
+    pub(crate) fn frame_session_info(version: u8, from: &str, to: &str, epoch: u32) -> Vec<u8> {
+        let mut info = Self::session_info(version, from, to);
+        // Epoch 0 keeps the original derivation
+        if epoch > 0 {
+            info.extend_from_slice(&epoch.to_be_bytes());
+        }
+        info
+    }
+    
+    fn frame_session_key(&self, shared: &[u8], version: u8, from: &str, to: &str, epoch: u32) -> [u8; 32] {
+        let info = Self::frame_session_info(version, from, to, epoch);
+        let params = HkdfParams {
+            salt: self.session_salt.as_deref(),
+            info: &info,
+            length: 32,
+        };
+        let okm = Self::derive_key(shared, &params).expect("HKDF expand failed");
+        okm.try_into().expect("HKDF output length")
+    }
+    
     /// Generate HMAC-SHA256
     pub fn generate_hmac(key: &[u8], data: &str) -> String {
         let mut mac = HmacSha256::new_from_slice(key).expect("HMAC key error");
@@ -73,16 +236,23 @@ impl SecurityManager {
     /// 
     /// Format: `{timestamp_ms}-{random_hex}`
     pub fn generate_nonce() -> String {
-        let ts = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis();
-        let rand: u64 = rand::thread_rng().gen();
-        format!("{}-{:016x}", ts, rand)
+        Self::nonce_at(SystemClock.now_ms(), &OsRandom)
+    }
+    
+    /// Generate anti-replay nonce stamped with this manager's clock
+    pub fn next_nonce(&self) -> String {
+        Self::nonce_at(self.clock.now_ms(), &*self.random)
+    }
+    
+    fn nonce_at(ts: u64, random: &dyn Random) -> String {
+        format!("{}-{:016x}", ts, random.next_u64())
     }
     
     /// Validate nonce (freshness + replay protection)
     /// 
+    /// Checked as one sent by an unnamed peer; frames are checked with
+    /// `validate_nonce_from`.
+    /// 
     /// # Arguments
     /// * `nonce` - Nonce string to validate
     /// * `max_age_ms` - Maximum age in milliseconds
@@ -90,58 +260,173 @@ impl SecurityManager {
     /// # Returns
     /// `true` if nonce is valid and not replayed
     pub fn validate_nonce(&mut self, nonce: &str, max_age_ms: u64) -> bool {
-        let parts: Vec<&str> = nonce.split('-').collect();
-        if parts.len() != 2 { return false; }
-        
-        let ts: u128 = match parts[0].parse() {
-            Ok(t) => t,
-            Err(_) => return false,
-        };
-        
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis();
-        
-        // Check freshness
-        if now.saturating_sub(ts) > max_age_ms as u128 { return false; }
-        
-        // Check replay
-        if self.nonce_window.contains_key(nonce) { return false; }
-        
-        // Store
-        self.nonce_window.insert(nonce.to_string(), now as u64);
-        self.cleanup_nonces(max_age_ms * 2);
+        self.validate_nonce_from("", nonce, max_age_ms)
+    }
+    
+    /// Validate a nonce sent by `peer` (freshness + replay protection)
+    /// 
+    /// Replays are tracked per peer, so one peer's traffic cannot cause
+    /// another's nonces to be rejected.
+    pub fn validate_nonce_from(&mut self, peer: &str, nonce: &str, max_age_ms: u64) -> bool {
+        let Some(ts) = self.fresh_nonce_ts(nonce, max_age_ms) else { return false };
         
-        true
+        // Check replay and store
+        let now = self.clock.now_ms();
+        self.nonce_window.expire(now, (max_age_ms + self.max_skew_ms) * 2);
+        self.nonce_window.insert(peer, nonce, ts)
     }
     
-    fn cleanup_nonces(&mut self, max_age: u64) {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64;
-        self.nonce_window.retain(|_, ts| now - *ts < max_age);
+    /// Timestamp of a well-formed nonce that is fresh, tolerating clock skew
+    /// in both directions
+    fn fresh_nonce_ts(&self, nonce: &str, max_age_ms: u64) -> Option<u64> {
+        let (ts, _) = nonce.split_once('-').filter(|(_, rest)| !rest.contains('-'))?;
+        let ts: u64 = ts.parse().ok()?;
+        let now = self.clock.now_ms();
+        if ts > now.saturating_add(self.max_skew_ms) || now.saturating_sub(ts) > max_age_ms.saturating_add(self.max_skew_ms) {
+            return None;
+        }
+        Some(ts)
     }
     
     /// Sign message with Ed25519
     pub fn sign(priv_key: &[u8; 32], message: &[u8]) -> Vec<u8> {
-        let signing_key = SigningKey::from_bytes(priv_key);
-        let signature: Signature = signing_key.sign(message);
-        signature.to_bytes().to_vec()
+        DefaultBackend::ed25519_sign(priv_key, message).to_vec()
     }
     
     /// Verify Ed25519 signature
     pub fn verify(pub_key: &[u8; 32], message: &[u8], sig: &[u8]) -> bool {
-        let verifying_key = match VerifyingKey::from_bytes(pub_key) {
-            Ok(k) => k,
-            Err(_) => return false,
-        };
-        let signature = match Signature::from_slice(sig) {
-            Ok(s) => s,
-            Err(_) => return false,
+        DefaultBackend::ed25519_verify(pub_key, message, sig)
+    }
+    
+    /// Encrypt with ChaCha20-Poly1305
+    /// 
+    /// # Arguments
+    /// * `key` - 32-byte key
+    /// * `nonce` - 12-byte nonce, never reused with the same key
+    /// * `aad` - Additional authenticated data
+    /// * `plaintext` - Data to encrypt
+    /// 
+    /// # Returns
+    /// Ciphertext with 16-byte tag appended
+    pub fn seal(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        DefaultBackend::aead_seal(key, nonce, aad, plaintext)
+    }
+    
+    /// Decrypt ChaCha20-Poly1305 ciphertext
+    pub fn open(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, String> {
+        DefaultBackend::aead_open(key, nonce, aad, ciphertext)
+    }
+    
+    /// Verify many Ed25519 signatures at once
+    /// 
+    /// Uses batch verification; if the batch fails, falls back to per-item
+    /// verification to find the offending entries.
+    /// 
+    /// # Arguments
+    /// * `items` - `(public key, message, signature)` triples
+    /// 
+    /// # Returns
+    /// Validity of each item, in input order
+    pub fn verify_batch(items: &[(&[u8; 32], &[u8], &[u8])]) -> Vec<bool> {
+        let mut results = vec![false; items.len()];
+        let mut indices = Vec::with_capacity(items.len());
+        let mut messages = Vec::with_capacity(items.len());
+        let mut signatures = Vec::with_capacity(items.len());
+        let mut keys = Vec::with_capacity(items.len());
+        
+        for (i, (pub_key, message, sig)) in items.iter().enumerate() {
+            let (Ok(key), Ok(sig)) = (VerifyingKey::from_bytes(pub_key), Signature::from_slice(sig)) else {
+                continue;
+            };
+            indices.push(i);
+            messages.push(*message);
+            signatures.push(sig);
+            keys.push(key);
+        }
+        
+        if ed25519_dalek::verify_batch(&messages, &signatures, &keys).is_ok() {
+            for i in indices {
+                results[i] = true;
+            }
+        } else {
+            for (j, i) in indices.into_iter().enumerate() {
+                results[i] = keys[j].verify(messages[j], &signatures[j]).is_ok();
+            }
+        }
+        
+        results
+    }
+    
+    /// Data covered by a frame's Ed25519 signature
+    /// 
+    /// A canonical CBOR map ([`CBORCodec::encode_value_canonical`]) of the
+    /// header fields and `hmac`, keyed and typed as in the frame encoding,
+    /// with `id`, `priority` and `content_type` when the frame sets them and
+    /// the digest of a presented capability under `cap`. The payload is
+    /// covered through the HMAC.
+    pub fn frame_sign_data(frame: &OpacusFrame, hmac: &str) -> Vec<u8> {
+        use ciborium::Value;
+        let frame_type = match frame.frame_type.name() {
+            Some(name) => Value::from(name),
+            None => Value::from(frame.frame_type.code()),
         };
-        verifying_key.verify(message, &signature).is_ok()
+        let mut fields = vec![
+            ("version", Value::from(frame.version)),
+            ("type", frame_type),
+            ("from", Value::from(frame.from.as_str())),
+            ("to", Value::from(frame.to.as_str())),
+            ("seq", Value::from(frame.seq)),
+            ("ts", Value::from(frame.ts)),
+            ("nonce", Value::from(frame.nonce.as_str())),
+            ("hmac", Value::from(hmac)),
+        ];
+        if let Some(id) = frame.id {
+            fields.push(("id", Value::from(id.to_string())));
+        }
+        if !frame.priority.is_normal() {
+            fields.push(("priority", Value::from(frame.priority.as_str())));
+        }
+        if !frame.content_type.is_raw() {
+            fields.push(("content_type", Value::from(frame.content_type.as_str())));
+        }
+        // Capability presentations cannot be stripped or swapped
+        if let Some(digest) = frame.capability_digest() {
+            fields.push(("cap", Value::from(digest)));
+        }
+        let map = Value::Map(fields.into_iter().map(|(key, value)| (Value::from(key), value)).collect());
+        CBORCodec::encode_value_canonical(&map).expect("Signed fields encode")
+    }
+    
+    /// Signed data of a `Connect` frame
+    /// 
+    /// Connect frames have no HMAC, as no session exists yet; the SHA-256 of
+    /// the payload takes its place, so the signature covers the keys and
+    /// token the frame carries.
+    pub fn connect_sign_data(frame: &OpacusFrame) -> Vec<u8> {
+        Self::frame_sign_data(frame, &hex::encode(<Sha256 as sha2::Digest>::digest(&frame.payload)))
+    }
+    
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn hmac_data(
+        frame_type: FrameType,
+        from: &str,
+        to: &str,
+        seq: u64,
+        ts: u64,
+        nonce: &str,
+        payload: &[u8],
+        compressed: Option<Compression>,
+    ) -> String {
+        let mut data = format!(
+            "{:?}|{}|{}|{}|{}|{}|{}",
+            frame_type, from, to, seq, ts, nonce, hex::encode(payload)
+        );
+        // Uncompressed frames keep the original layout
+        if let Some(alg) = compressed {
+            data.push('|');
+            data.push_str(alg.as_str());
+        }
+        data
     }
     
     /// Create authenticated frame with signature + HMAC + nonce
@@ -161,31 +446,49 @@ impl SecurityManager {
         peer_x_pub: &[u8; 32],
         frame_type: FrameType,
         to: &str,
-        payload: Vec<u8>,
+        payload: impl Into<Bytes>,
     ) -> OpacusFrame {
-        let nonce = Self::generate_nonce();
-        let ts = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64;
+        self.create_auth_frame_with(identity, peer_x_pub, frame_type, to, payload, FrameOptions::default())
+    }
+    
+    /// Create authenticated frame with explicit options
+    /// 
+    /// The `compressed` marker is covered by the HMAC, the priority and
+    /// content type by the signature.
+    pub fn create_auth_frame_with(
+        &mut self,
+        identity: &AgentIdentity,
+        peer_x_pub: &[u8; 32],
+        frame_type: FrameType,
+        to: &str,
+        payload: impl Into<Bytes>,
+        options: FrameOptions,
+    ) -> OpacusFrame {
+        let payload = payload.into();
+        let compressed = options.compressed;
+        let ts = self.clock.now_ms();
+        let nonce = Self::nonce_at(ts, &*self.random);
         self.last_nonce += 1;
         let seq = self.last_nonce;
+        // Rekey frames are keyed with the epoch they announce
+        let key_epoch = match self.epochs.pending_epoch(to) {
+            Some(next) if frame_type == FrameType::Rekey => next,
+            _ => self.epochs.record_send(to, payload.len(), ts),
+        };
         
         // Derive session key
         let shared = Self::derive_shared_secret(&identity.x_priv, peer_x_pub);
-        let session_key = Self::derive_session_key(&shared, b"opacus-session");
+        let session_key = self.frame_session_key(&shared, FRAME_VERSION, &identity.id, to, key_epoch);
         
         // Create HMAC
-        let hmac_data = format!(
-            "{:?}|{}|{}|{}|{}|{}|{}",
-            frame_type, identity.id, to, seq, ts, nonce, 
-            hex::encode(&payload)
+        let hmac_data = Self::hmac_data(
+            frame_type, &identity.id, to, seq, ts, &nonce, &payload, compressed
         );
         let hmac = Self::generate_hmac(&session_key, &hmac_data);
         
         // Create frame
         let mut frame = OpacusFrame {
-            version: 1,
+            version: FRAME_VERSION,
             frame_type,
             from: identity.id.clone(),
             to: to.to_string(),
@@ -195,19 +498,80 @@ impl SecurityManager {
             payload,
             hmac: Some(hmac.clone()),
             sig: None,
+            key_epoch,
+            compressed,
+            id: Some(OpacusFrame::new_id_with(ts, &*self.random)),
+            priority: options.priority.unwrap_or(frame_type.default_priority()),
+            content_type: options.content_type,
+            extensions: Default::default(),
         };
         
         // Sign
-        let sign_data = format!(
-            "{}|{:?}|{}|{}|{}|{}|{}|{}",
-            frame.version, frame.frame_type, frame.from, frame.to,
-            frame.seq, frame.ts, frame.nonce, hmac
-        );
-        frame.sig = Some(Self::sign(&identity.ed_priv, sign_data.as_bytes()));
+        if !options.unsigned {
+            let sign_data = Self::frame_sign_data(&frame, &hmac);
+            frame.sig = Some(Self::sign(&identity.ed_priv, &sign_data));
+        }
         
         frame
     }
     
+    /// Announce the next session key epoch for a peer in a `Rekey` frame
+    /// 
+    /// The frame is keyed with the new epoch and carries it as a 4-byte
+    /// big-endian payload. Later frames to `to` keep the current key until
+    /// the peer's acknowledgement (`create_rekey_ack`) is verified or passed
+    /// to `confirm_rekey`; until then `rekey_due` asks for the frame again
+    /// after [`crate::crypto::REKEY_RETRY_MS`].
+    /// 
+    /// # Arguments
+    /// * `identity` - Sender identity
+    /// * `peer_x_pub` - Recipient's X25519 public key
+    /// * `to` - Recipient agent ID
+    pub fn create_rekey_frame(
+        &mut self,
+        identity: &AgentIdentity,
+        peer_x_pub: &[u8; 32],
+        to: &str,
+    ) -> OpacusFrame {
+        let epoch = self.epochs.announce_send(to, self.clock.now_ms());
+        self.create_auth_frame(identity, peer_x_pub, FrameType::Rekey, to, epoch.to_be_bytes().to_vec())
+    }
+    
+    /// Create the `Ack` frame acknowledging a verified `Rekey` frame
+    /// 
+    /// # Arguments
+    /// * `identity` - Own identity
+    /// * `peer_x_pub` - Rekeying peer's X25519 public key
+    /// * `to` - Rekeying peer's agent ID
+    /// * `epoch` - Epoch of the `Rekey` frame
+    pub fn create_rekey_ack(
+        &mut self,
+        identity: &AgentIdentity,
+        peer_x_pub: &[u8; 32],
+        to: &str,
+        epoch: u32,
+    ) -> OpacusFrame {
+        let payload = serde_json::json!({ REKEY_ACK_FIELD: epoch }).to_string();
+        self.create_auth_frame(identity, peer_x_pub, FrameType::Ack, to, payload.into_bytes())
+    }
+    
+    /// Switch the outbound key for `peer` to an epoch it acknowledged
+    /// 
+    /// # Returns
+    /// Whether `epoch` was the announced one
+    pub fn confirm_rekey(&mut self, peer: &str, epoch: u32) -> bool {
+        self.epochs.confirm_send(peer, epoch, self.clock.now_ms())
+    }
+    
+    /// Epoch acknowledged by a frame from `create_rekey_ack`
+    pub fn rekey_ack(frame: &OpacusFrame) -> Option<u32> {
+        if frame.frame_type != FrameType::Ack {
+            return None;
+        }
+        let payload: serde_json::Value = serde_json::from_slice(&frame.payload).ok()?;
+        payload[REKEY_ACK_FIELD].as_u64().and_then(|epoch| u32::try_from(epoch).ok())
+    }
+    
     /// Verify authenticated frame (signature + HMAC + nonce)
     /// 
     /// # Arguments
@@ -225,35 +589,75 @@ impl SecurityManager {
         my_x_priv: &[u8; 32],
         sender_x_pub: &[u8; 32],
     ) -> Result<(), String> {
-        // 1. Validate nonce
-        if !self.validate_nonce(&frame.nonce, 60000) {
+        self.verify_frame(frame, Some(sender_ed_pub), my_x_priv, sender_x_pub)
+    }
+    
+    /// Verify an unsigned frame unpacked from a `Batch` (HMAC + nonce)
+    /// 
+    /// The batch itself carries the sender's signature; each entry is
+    /// authenticated by its HMAC, which only sender and recipient can compute.
+    pub fn verify_batch_entry(
+        &mut self,
+        frame: &OpacusFrame,
+        my_x_priv: &[u8; 32],
+        sender_x_pub: &[u8; 32],
+    ) -> Result<(), String> {
+        self.verify_frame(frame, None, my_x_priv, sender_x_pub)
+    }
+    
+    fn verify_frame(
+        &mut self,
+        frame: &OpacusFrame,
+        sender_ed_pub: Option<&[u8; 32]>,
+        my_x_priv: &[u8; 32],
+        sender_x_pub: &[u8; 32],
+    ) -> Result<(), String> {
+        // 1. Check nonce freshness; it is only remembered once the frame is
+        // authenticated, so forged copies cannot burn a genuine frame's nonce
+        if self.fresh_nonce_ts(&frame.nonce, 60000).is_none() {
             return Err("Invalid or replayed nonce".into());
         }
         
         // 2. Verify signature
         let hmac = frame.hmac.as_ref().ok_or("Missing HMAC")?;
-        let sign_data = format!(
-            "{}|{:?}|{}|{}|{}|{}|{}|{}",
-            frame.version, frame.frame_type, frame.from, frame.to,
-            frame.seq, frame.ts, frame.nonce, hmac
-        );
-        let sig = frame.sig.as_ref().ok_or("Missing signature")?;
-        if !Self::verify(sender_ed_pub, sign_data.as_bytes(), sig) {
-            return Err("Invalid signature".into());
+        if let Some(sender_ed_pub) = sender_ed_pub {
+            let sign_data = Self::frame_sign_data(frame, hmac);
+            let sig = frame.sig.as_ref().ok_or("Missing signature")?;
+            if !Self::verify(sender_ed_pub, &sign_data, sig) {
+                return Err("Invalid signature".into());
+            }
+        }
+        
+        // 3. Check key epoch
+        let is_rekey = frame.frame_type == FrameType::Rekey;
+        if is_rekey && frame.payload[..] != frame.key_epoch.to_be_bytes() {
+            return Err("Malformed rekey frame".into());
         }
+        self.epochs.check_recv(&frame.from, frame.key_epoch, is_rekey)?;
         
-        // 3. Verify HMAC
+        // 4. Verify HMAC
         let shared = Self::derive_shared_secret(my_x_priv, sender_x_pub);
-        let session_key = Self::derive_session_key(&shared, b"opacus-session");
-        let hmac_data = format!(
-            "{:?}|{}|{}|{}|{}|{}|{}",
-            frame.frame_type, frame.from, frame.to, frame.seq, frame.ts, 
-            frame.nonce, hex::encode(&frame.payload)
+        let session_key = self.frame_session_key(&shared, frame.version, &frame.from, &frame.to, frame.key_epoch);
+        let hmac_data = Self::hmac_data(
+            frame.frame_type, &frame.from, &frame.to, frame.seq, frame.ts,
+            &frame.nonce, &frame.payload, frame.compressed
         );
         if !Self::verify_hmac(&session_key, &hmac_data, hmac) {
             return Err("HMAC mismatch".into());
         }
         
+        // 5. Reject replays of authenticated frames
+        if !self.validate_nonce_from(&frame.from, &frame.nonce, 60000) {
+            return Err("Invalid or replayed nonce".into());
+        }
+        
+        if is_rekey {
+            self.epochs.advance_recv(&frame.from, frame.key_epoch);
+        }
+        if let Some(epoch) = Self::rekey_ack(frame) {
+            self.epochs.confirm_send(&frame.from, epoch, self.clock.now_ms());
+        }
+        
         Ok(())
     }
 }
@@ -267,6 +671,8 @@ impl Default for SecurityManager {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crypto::KeyManager;
+    use crate::qos::Priority;
     
     #[test]
     fn test_ecdh() {
@@ -294,6 +700,56 @@ mod tests {
         assert!(!sec.validate_nonce(&nonce, 60000)); // Replay
     }
     
+    #[test]
+    fn test_nonce_clock_skew() {
+        let clock = Arc::new(crate::clock::ManualClock::new(1_000_000));
+        let mut sec = SecurityManager::with_clock(clock.clone());
+        sec.set_max_skew(1_000);
+        
+        // Slightly in the future: within skew
+        assert!(sec.validate_nonce("1000500-01", 60000));
+        // Too far in the future
+        assert!(!sec.validate_nonce("1002000-02", 60000));
+        
+        let nonce = sec.next_nonce();
+        clock.advance(60_500);
+        assert!(sec.validate_nonce(&nonce, 60000)); // Stale by < skew
+        
+        let nonce = SecurityManager::nonce_at(clock.now_ms(), &OsRandom);
+        clock.advance(62_000);
+        assert!(!sec.validate_nonce(&nonce, 60000));
+    }
+    
+    #[test]
+    fn test_seeded_sources() {
+        let frame = |seed| {
+            let random = Arc::new(crate::random::SeededRandom::new(seed));
+            let alice = KeyManager::generate_identity_with(16602, &*random);
+            let clock = Arc::new(crate::clock::ManualClock::new(1_000_000));
+            let mut sec = SecurityManager::with_sources(clock, random);
+            sec.create_auth_frame(&alice, &[9u8; 32], FrameType::Msg, "bob", b"hi".to_vec())
+        };
+        let (a, b) = (frame(1), frame(1));
+        assert_eq!((&a.from, &a.nonce, a.id, &a.hmac, &a.sig), (&b.from, &b.nonce, b.id, &b.hmac, &b.sig));
+        assert_ne!(frame(2).from, a.from);
+    }
+    
+    #[test]
+    fn test_nonce_capacity() {
+        let clock = Arc::new(crate::clock::ManualClock::new(1_000_000));
+        let mut sec = SecurityManager::with_clock(clock.clone());
+        sec.set_nonce_capacity(16);
+        
+        let first = sec.next_nonce();
+        assert!(sec.validate_nonce(&first, 60000));
+        for _ in 0..100 {
+            clock.advance(1);
+            assert!(sec.validate_nonce(&sec.next_nonce(), 60000));
+        }
+        assert_eq!(sec.nonce_count(), 16);
+        assert!(!sec.validate_nonce(&first, 60000)); // Evicted, still rejected
+    }
+    
     #[test]
     fn test_signatures() {
         let (signing, verifying) = KeyManager::generate_ed25519();
@@ -302,4 +758,198 @@ mod tests {
         let sig = SecurityManager::sign(&signing.to_bytes(), message);
         assert!(SecurityManager::verify(verifying.as_bytes(), message, &sig));
     }
+    
+    #[test]
+    fn test_derive_key_params() {
+        let shared = [7u8; 32];
+        let info = SecurityManager::session_info(1, "alice", "bob");
+        assert_eq!(info, SecurityManager::session_info(1, "bob", "alice"));
+        assert_ne!(info, SecurityManager::session_info(2, "alice", "bob"));
+        
+        let unsalted = SecurityManager::derive_key(&shared, &HkdfParams { salt: None, info: &info, length: 32 }).unwrap();
+        let salted = SecurityManager::derive_key(&shared, &HkdfParams { salt: Some(b"salt"), info: &info, length: 32 }).unwrap();
+        let long = SecurityManager::derive_key(&shared, &HkdfParams { salt: None, info: &info, length: 64 }).unwrap();
+        assert_ne!(unsalted, salted);
+        assert_eq!(long.len(), 64);
+        assert_eq!(&long[..32], unsalted.as_slice());
+        assert_eq!(unsalted, SecurityManager::derive_session_key(&shared, &info));
+        assert!(SecurityManager::derive_key(&shared, &HkdfParams { salt: None, info: &info, length: 255 * 32 + 1 }).is_err());
+    }
+    
+    #[test]
+    fn test_auth_frame_roundtrip() {
+        let alice = KeyManager::generate_identity(16602);
+        let bob = KeyManager::generate_identity(16602);
+        let mut alice_sec = SecurityManager::new();
+        let mut bob_sec = SecurityManager::new();
+        
+        let frame = alice_sec.create_auth_frame(&alice, &bob.x_pub, FrameType::Msg, &bob.id, b"hi".to_vec());
+        assert!(bob_sec.verify_auth_frame(&frame, &alice.ed_pub, &bob.x_priv, &alice.x_pub).is_ok());
+        
+        // Mismatched salt yields a different session key
+        let frame = alice_sec.create_auth_frame(&alice, &bob.x_pub, FrameType::Msg, &bob.id, b"hi".to_vec());
+        bob_sec.set_session_salt(Some(b"other".to_vec()));
+        assert_eq!(
+            bob_sec.verify_auth_frame(&frame, &alice.ed_pub, &bob.x_priv, &alice.x_pub),
+            Err("HMAC mismatch".to_string())
+        );
+    }
+    
+    #[test]
+    fn test_compressed_marker_authenticated() {
+        let alice = KeyManager::generate_identity(16602);
+        let bob = KeyManager::generate_identity(16602);
+        let mut alice_sec = SecurityManager::new();
+        let mut bob_sec = SecurityManager::new();
+        
+        let options = FrameOptions { compressed: Some(Compression::Zstd), ..Default::default() };
+        let frame = alice_sec.create_auth_frame_with(
+            &alice, &bob.x_pub, FrameType::Msg, &bob.id, b"packed".to_vec(), options
+        );
+        assert_eq!(frame.compressed, Some(Compression::Zstd));
+        
+        let mut stripped = frame.clone();
+        stripped.compressed = None;
+        assert_eq!(
+            bob_sec.verify_auth_frame(&stripped, &alice.ed_pub, &bob.x_priv, &alice.x_pub),
+            Err("HMAC mismatch".to_string())
+        );
+        assert!(bob_sec.verify_auth_frame(&frame, &alice.ed_pub, &bob.x_priv, &alice.x_pub).is_ok());
+    }
+    
+    #[test]
+    fn test_message_id_signed() {
+        let alice = KeyManager::generate_identity(16602);
+        let bob = KeyManager::generate_identity(16602);
+        let mut alice_sec = SecurityManager::new();
+        
+        let first = alice_sec.create_auth_frame(&alice, &bob.x_pub, FrameType::Msg, &bob.id, b"1".to_vec());
+        let second = alice_sec.create_auth_frame(&alice, &bob.x_pub, FrameType::Msg, &bob.id, b"2".to_vec());
+        let (first_id, second_id) = (first.id.unwrap(), second.id.unwrap());
+        assert_ne!(first_id, second_id);
+        assert_eq!(first_id.timestamp_ms(), first.ts);
+        
+        let mut tampered = second.clone();
+        tampered.id = Some(first_id);
+        let mut bob_sec = SecurityManager::new();
+        assert_eq!(
+            bob_sec.verify_auth_frame(&tampered, &alice.ed_pub, &bob.x_priv, &alice.x_pub),
+            Err("Invalid signature".to_string())
+        );
+        // The forged copy did not use up the genuine frame's nonce
+        assert!(bob_sec.verify_auth_frame(&second, &alice.ed_pub, &bob.x_priv, &alice.x_pub).is_ok());
+        assert_eq!(
+            bob_sec.verify_auth_frame(&second, &alice.ed_pub, &bob.x_priv, &alice.x_pub),
+            Err("Invalid or replayed nonce".to_string())
+        );
+    }
+    
+    #[test]
+    fn test_priority_signed() {
+        let alice = KeyManager::generate_identity(16602);
+        let bob = KeyManager::generate_identity(16602);
+        let mut alice_sec = SecurityManager::new();
+        
+        let stream = alice_sec.create_auth_frame(&alice, &bob.x_pub, FrameType::Stream, &bob.id, vec![]);
+        assert_eq!(stream.priority, Priority::Low);
+        
+        let options = FrameOptions { priority: Some(Priority::High), ..Default::default() };
+        let frame = alice_sec.create_auth_frame_with(&alice, &bob.x_pub, FrameType::Msg, &bob.id, vec![], options);
+        assert_eq!(frame.priority, Priority::High);
+        
+        let mut promoted = stream.clone();
+        promoted.priority = Priority::Control;
+        let mut bob_sec = SecurityManager::new();
+        assert_eq!(
+            bob_sec.verify_auth_frame(&promoted, &alice.ed_pub, &bob.x_priv, &alice.x_pub),
+            Err("Invalid signature".to_string())
+        );
+    }
+    
+    #[test]
+    fn test_unsigned_batch_entry() {
+        let alice = KeyManager::generate_identity(16602);
+        let bob = KeyManager::generate_identity(16602);
+        let mallory = KeyManager::generate_identity(16602);
+        let mut alice_sec = SecurityManager::new();
+        let mut bob_sec = SecurityManager::new();
+        
+        let options = FrameOptions { unsigned: true, ..Default::default() };
+        let entry = alice_sec.create_auth_frame_with(&alice, &bob.x_pub, FrameType::Msg, &bob.id, b"hi".to_vec(), options);
+        assert!(entry.sig.is_none());
+        assert_eq!(
+            bob_sec.verify_auth_frame(&entry, &alice.ed_pub, &bob.x_priv, &alice.x_pub),
+            Err("Missing signature".to_string())
+        );
+        
+        // Only the holder of the shared secret can produce the HMAC
+        let mut mallory_sec = SecurityManager::new();
+        let mut forged = mallory_sec.create_auth_frame_with(&mallory, &bob.x_pub, FrameType::Msg, &bob.id, b"hi".to_vec(), options);
+        forged.from = alice.id.clone();
+        assert_eq!(
+            bob_sec.verify_batch_entry(&forged, &bob.x_priv, &alice.x_pub),
+            Err("HMAC mismatch".to_string())
+        );
+        let mut bob_sec = SecurityManager::new();
+        assert!(bob_sec.verify_batch_entry(&entry, &bob.x_priv, &alice.x_pub).is_ok());
+    }
+    
+    #[test]
+    fn test_rekey() {
+        let alice = KeyManager::generate_identity(16602);
+        let bob = KeyManager::generate_identity(16602);
+        let mut alice_sec = SecurityManager::new();
+        let mut bob_sec = SecurityManager::new();
+        alice_sec.set_rekey_policy(RekeyPolicy { max_frames: 2, ..RekeyPolicy::default() });
+        
+        let old = alice_sec.create_auth_frame(&alice, &bob.x_pub, FrameType::Msg, &bob.id, b"1".to_vec());
+        let in_flight = alice_sec.create_auth_frame(&alice, &bob.x_pub, FrameType::Msg, &bob.id, b"2".to_vec());
+        assert!(alice_sec.rekey_due(&bob.id));
+        
+        let rekey = alice_sec.create_rekey_frame(&alice, &bob.x_pub, &bob.id);
+        assert_eq!((rekey.key_epoch, alice_sec.session_epoch(&bob.id)), (1, 0));
+        assert!(!alice_sec.rekey_due(&bob.id));
+        
+        // Alice keeps the old key until Bob acknowledges the rekey
+        let unacked = alice_sec.create_auth_frame(&alice, &bob.x_pub, FrameType::Msg, &bob.id, b"3".to_vec());
+        assert_eq!(unacked.key_epoch, 0);
+        assert!(bob_sec.verify_auth_frame(&old, &alice.ed_pub, &bob.x_priv, &alice.x_pub).is_ok());
+        assert!(bob_sec.verify_auth_frame(&unacked, &alice.ed_pub, &bob.x_priv, &alice.x_pub).is_ok());
+        assert!(bob_sec.verify_auth_frame(&rekey, &alice.ed_pub, &bob.x_priv, &alice.x_pub).is_ok());
+        assert!(bob_sec.verify_auth_frame(&in_flight, &alice.ed_pub, &bob.x_priv, &alice.x_pub).is_ok());
+        let ack = bob_sec.create_rekey_ack(&bob, &alice.x_pub, &alice.id, rekey.key_epoch);
+        assert!(alice_sec.verify_auth_frame(&ack, &bob.ed_pub, &alice.x_priv, &bob.x_pub).is_ok());
+        assert_eq!(alice_sec.session_epoch(&bob.id), 1);
+        
+        // New-epoch frames are rejected by peers that missed the rekey frame
+        let new = alice_sec.create_auth_frame(&alice, &bob.x_pub, FrameType::Msg, &bob.id, b"3".to_vec());
+        assert_eq!(new.key_epoch, 1);
+        assert!(bob_sec.verify_auth_frame(&new, &alice.ed_pub, &bob.x_priv, &alice.x_pub).is_ok());
+        assert!(SecurityManager::new().verify_auth_frame(&new, &alice.ed_pub, &bob.x_priv, &alice.x_pub).is_err());
+        
+        // A frame keyed with another epoch fails the HMAC
+        let mut forged = alice_sec.create_auth_frame(&alice, &bob.x_pub, FrameType::Msg, &bob.id, b"4".to_vec());
+        forged.key_epoch = 0;
+        assert!(bob_sec.verify_auth_frame(&forged, &alice.ed_pub, &bob.x_priv, &alice.x_pub).is_err());
+    }
+    
+    #[test]
+    fn test_verify_batch() {
+        let (signing, verifying) = KeyManager::generate_ed25519();
+        let (other, _) = KeyManager::generate_ed25519();
+        let pub_key = verifying.as_bytes();
+        
+        let good = SecurityManager::sign(&signing.to_bytes(), b"one");
+        let good2 = SecurityManager::sign(&signing.to_bytes(), b"two");
+        let forged = SecurityManager::sign(&other.to_bytes(), b"three");
+        
+        let items: Vec<(&[u8; 32], &[u8], &[u8])> = vec![
+            (pub_key, b"one", &good),
+            (pub_key, b"two", &good2),
+            (pub_key, b"three", &forged),
+            (pub_key, b"four", &[0u8; 3]),
+        ];
+        assert_eq!(SecurityManager::verify_batch(&items), vec![true, true, false, false]);
+        assert_eq!(SecurityManager::verify_batch(&items[..2]), vec![true, true]);
+    }
 }
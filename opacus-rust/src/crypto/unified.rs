@@ -0,0 +1,120 @@
+//! One identity for the QUIC relay, the H3DAC gateway and the chain
+//!
+//! A single 32-byte seed yields the agent's Ed25519 and X25519 keys and a
+//! secp256k1 key. The agent ID (from the Ed25519 key) names the agent both
+//! on the relay and, as client ID, on the H3DAC gateway; the secp256k1 key
+//! signs for the gateway and is the agent's EVM account.
+
+use std::fmt;
+use crate::crypto::KeyManager;
+use crate::redact::Secret;
+use crate::types::{AgentIdentity, OpacusConfig};
+#[cfg(feature = "chain")]
+use crate::chain::{Address, ChainError, ChainSigner};
+
+/// Agent identity plus the secp256k1 key derived from the same seed
+///
+/// `Debug` output redacts the private keys.
+#[derive(Clone)]
+pub struct UnifiedIdentity {
+    /// Relay identity (Ed25519 + X25519)
+    pub agent: AgentIdentity,
+    /// secp256k1 private key for the H3DAC gateway and chain transactions
+    pub secp_priv: [u8; 32],
+}
+
+impl fmt::Debug for UnifiedIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UnifiedIdentity")
+            .field("agent", &self.agent)
+            .field("secp_priv", &Secret(&self.secp_priv))
+            .finish()
+    }
+}
+
+impl UnifiedIdentity {
+    /// Derive all keys from one seed
+    ///
+    /// The agent part equals [`KeyManager::identity_from_seed`], so
+    /// `OpacusClient::init_from_seed` with the same seed restores it.
+    pub fn from_seed(seed: &[u8; 32], chain_id: u64) -> Self {
+        Self {
+            agent: KeyManager::identity_from_seed(seed, chain_id),
+            secp_priv: KeyManager::secp256k1_from_seed(seed),
+        }
+    }
+
+    /// Client ID to authenticate to the H3DAC gateway with: the agent ID
+    pub fn gateway_client_id(&self) -> &str {
+        &self.agent.id
+    }
+
+    /// secp256k1 private key as hex, as taken by `H3DACClient::from_hex`
+    pub fn gateway_private_key_hex(&self) -> String {
+        KeyManager::to_hex(&self.secp_priv)
+    }
+
+    /// Sign chain transactions with this identity's secp256k1 key
+    pub fn configure(&self, config: &mut OpacusConfig) {
+        config.private_key = Some(self.gateway_private_key_hex());
+    }
+
+    /// Chain signer of the secp256k1 key (`chain` feature)
+    #[cfg(feature = "chain")]
+    pub fn signer(&self) -> Result<ChainSigner, ChainError> {
+        ChainSigner::from_bytes(&self.secp_priv)
+    }
+
+    /// EVM address of the secp256k1 key (`chain` feature)
+    #[cfg(feature = "chain")]
+    pub fn evm_address(&self) -> Result<Address, ChainError> {
+        Ok(self.signer()?.address())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Network;
+
+    #[test]
+    fn test_unified_identity() {
+        let seed = [42u8; 32];
+        let identity = UnifiedIdentity::from_seed(&seed, 16602);
+        assert_eq!(identity.agent.id, KeyManager::identity_from_seed(&seed, 16602).id);
+        assert_eq!(identity.gateway_client_id(), identity.agent.id);
+        assert_ne!(identity.secp_priv, identity.agent.ed_priv);
+        assert_ne!(identity.secp_priv, identity.agent.x_priv);
+
+        // Shared with the H3DAC SDK's `Identity::from_seed` test
+        assert_eq!(identity.agent.id, "69894affe8fde65d15885b8d65c5ebd2596b1495");
+        assert_eq!(
+            identity.gateway_private_key_hex(),
+            "1a55189b9ee02dff2024db2524e1bcd46264b0ff8e1a1c723749b02426fd9471"
+        );
+
+        let mut config = OpacusConfig {
+            network: Network::Testnet,
+            relay_url: String::new(),
+            chain_rpc: String::new(),
+            private_key: None,
+        };
+        identity.configure(&mut config);
+        assert_eq!(config.private_key, Some(identity.gateway_private_key_hex()));
+    }
+
+    #[cfg(feature = "chain")]
+    #[test]
+    fn test_unified_evm_address() {
+        let identity = UnifiedIdentity::from_seed(&[42u8; 32], 16602);
+        let mut config = OpacusConfig {
+            network: Network::Testnet,
+            relay_url: String::new(),
+            chain_rpc: String::new(),
+            private_key: None,
+        };
+        identity.configure(&mut config);
+        let signer = ChainSigner::from_hex(config.private_key.as_deref().unwrap()).unwrap();
+        assert_eq!(identity.evm_address().unwrap(), signer.address());
+    }
+}
@@ -0,0 +1,582 @@
+//! Kademlia-style DHT of agent records
+//!
+//! Instead of asking one relay where an agent is, agents can publish an
+//! [`DhtAgentRecord`] (the relay they are reachable at and their public keys,
+//! signed with their own key) to a distributed hash table kept by
+//! participating relays. Each node has an ID, the SHA-256 of its URL, and
+//! stores the records whose keys (the SHA-256 of the agent ID) are closest
+//! to it by XOR distance. A node knows up to [`DHT_K`] nodes at every
+//! distance ([`DhtNode`]) and finds the nodes closest to a key, or a record,
+//! by asking ever closer nodes, [`DHT_ALPHA`] at a time ([`DhtLookup`]).
+//!
+//! Nodes exchange [`DhtRpc`] messages in `Dht` frames. Agents send theirs
+//! to their relay, which runs the lookups for them. Records are verified by
+//! every node that stores or returns them, so nodes cannot forge records,
+//! only withhold them; they expire unless the agent publishes them again.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::content::ContentType;
+use crate::crypto::{KeyManager, SecurityManager};
+use crate::proto::FRAME_VERSION;
+use crate::qos::Priority;
+use crate::types::{AgentIdentity, FrameType, OpacusFrame};
+
+/// Nodes kept per distance, and nodes a record is stored at
+pub const DHT_K: usize = 20;
+
+/// Nodes asked at once during a lookup
+pub const DHT_ALPHA: usize = 3;
+
+/// Domain separator of record signatures
+const RECORD_CONTEXT: &str = "opacus-dht-record-v1";
+
+/// Position of a node or record in the DHT
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct DhtKey(pub [u8; 32]);
+
+impl fmt::Debug for DhtKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DhtKey({})", hex::encode(&self.0[..8]))
+    }
+}
+
+impl DhtKey {
+    /// Key of an agent's record
+    pub fn of_agent(agent_id: &str) -> Self {
+        Self(Sha256::digest(agent_id.as_bytes()).into())
+    }
+
+    /// ID of the node at a URL
+    pub fn of_node(addr: &str) -> Self {
+        Self(Sha256::digest(addr.as_bytes()).into())
+    }
+
+    /// XOR distance to another key
+    pub fn distance(&self, other: &DhtKey) -> [u8; 32] {
+        std::array::from_fn(|i| self.0[i] ^ other.0[i])
+    }
+
+    /// Index of the bucket `other` falls in (`None` for the key itself)
+    fn bucket(&self, other: &DhtKey) -> Option<usize> {
+        let distance = self.distance(other);
+        let leading = distance.iter().position(|b| *b != 0)?;
+        Some(255 - (leading * 8 + distance[leading].leading_zeros() as usize))
+    }
+}
+
+/// Node of the DHT
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DhtContact {
+    /// Node ID
+    pub id: DhtKey,
+    /// URL the node is reached at (e.g. `quic://host:port`)
+    pub addr: String,
+}
+
+impl DhtContact {
+    /// Contact of the node at a URL
+    pub fn new(addr: &str) -> Self {
+        Self { id: DhtKey::of_node(addr), addr: addr.to_string() }
+    }
+
+    /// Whether the ID is the one of the URL
+    pub fn is_valid(&self) -> bool {
+        self.id == DhtKey::of_node(&self.addr)
+    }
+}
+
+/// Where an agent is reachable, signed by the agent
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DhtAgentRecord {
+    pub agent_id: String,
+    /// URL of the relay the agent is connected to
+    pub relay: String,
+    /// Ed25519 public key
+    pub ed_pub: [u8; 32],
+    /// X25519 public key
+    pub x_pub: [u8; 32],
+    /// Version, increasing with every record of the agent
+    pub seq: u64,
+    /// Expiry (milliseconds)
+    pub expires: u64,
+    /// Signature by `ed_pub`
+    pub signature: Vec<u8>,
+}
+
+impl DhtAgentRecord {
+    /// Sign a record
+    ///
+    /// # Arguments
+    /// * `identity` - Agent the record is for
+    /// * `relay` - URL of the agent's relay
+    /// * `seq` - Version, higher than the agent's previous records
+    /// * `expires` - Expiry (milliseconds)
+    pub fn sign(identity: &AgentIdentity, relay: &str, seq: u64, expires: u64) -> Self {
+        let mut record = Self {
+            agent_id: identity.id.clone(),
+            relay: relay.to_string(),
+            ed_pub: identity.ed_pub,
+            x_pub: identity.x_pub,
+            seq,
+            expires,
+            signature: Vec::new(),
+        };
+        record.signature = SecurityManager::sign(&identity.ed_priv, &record.signing_data());
+        record
+    }
+
+    fn signing_data(&self) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!([
+            RECORD_CONTEXT,
+            self.agent_id,
+            self.relay,
+            hex::encode(self.ed_pub),
+            hex::encode(self.x_pub),
+            self.seq,
+            self.expires,
+        ]))
+        .expect("JSON array")
+    }
+
+    /// Key the record is stored under
+    pub fn key(&self) -> DhtKey {
+        DhtKey::of_agent(&self.agent_id)
+    }
+
+    /// Verify the agent's signature and that the record has not expired at `now` (milliseconds)
+    pub fn verify(&self, now: u64) -> Result<(), String> {
+        if KeyManager::agent_id(&self.ed_pub) != self.agent_id {
+            return Err(format!("Record key does not match agent {}", self.agent_id));
+        }
+        if !SecurityManager::verify(&self.ed_pub, &self.signing_data(), &self.signature) {
+            return Err(format!("Invalid signature on record of {}", self.agent_id));
+        }
+        if self.expires <= now {
+            return Err(format!("Record of {} has expired", self.agent_id));
+        }
+        Ok(())
+    }
+}
+
+/// Request or answer between DHT nodes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum DhtMessage {
+    /// Ask for the nodes closest to a key
+    FindNode { target: DhtKey },
+    /// Nodes closest to the key asked for (none for agents: not found)
+    Nodes { nodes: Vec<DhtContact> },
+    /// Store a record
+    Store { record: DhtAgentRecord },
+    /// Record stored
+    Stored,
+    /// Ask for a record, or else the nodes closest to its key
+    FindValue { key: DhtKey },
+    /// Record asked for
+    Value { record: DhtAgentRecord },
+    /// Request refused
+    Refused { reason: String },
+}
+
+/// Payload of a `Dht` frame
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DhtRpc {
+    /// Node sending the message (`None` for agents)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender: Option<DhtContact>,
+    pub message: DhtMessage,
+}
+
+impl DhtRpc {
+    /// Message from an agent
+    pub fn from_agent(message: DhtMessage) -> Self {
+        Self { sender: None, message }
+    }
+
+    /// Build an unsigned `Dht` frame between relays
+    pub fn to_frame(&self, ts: u64) -> OpacusFrame {
+        OpacusFrame {
+            version: FRAME_VERSION,
+            frame_type: FrameType::Dht,
+            from: "relay".to_string(),
+            to: "relay".to_string(),
+            seq: 0,
+            ts,
+            nonce: String::new(),
+            payload: serde_json::to_vec(self).unwrap_or_default().into(),
+            hmac: None,
+            sig: None,
+            key_epoch: 0,
+            compressed: None,
+            id: Some(OpacusFrame::new_id(ts)),
+            priority: Priority::Control,
+            content_type: ContentType::Json,
+            extensions: Default::default(),
+        }
+    }
+}
+
+/// DHT settings of a relay
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhtConfig {
+    /// URL other nodes reach this relay at
+    pub addr: String,
+    /// Nodes to join the DHT through
+    pub bootstrap: Vec<String>,
+    /// Time between refreshes of the routing table
+    pub refresh_interval: Duration,
+}
+
+impl DhtConfig {
+    /// Join the DHT as the relay at `addr`, refreshing every 5 minutes
+    pub fn new(addr: &str) -> Self {
+        Self { addr: addr.to_string(), bootstrap: Vec::new(), refresh_interval: Duration::from_secs(300) }
+    }
+
+    /// Nodes to join the DHT through
+    pub fn with_bootstrap(mut self, nodes: impl IntoIterator<Item = String>) -> Self {
+        self.bootstrap = nodes.into_iter().collect();
+        self
+    }
+}
+
+impl OpacusFrame {
+    /// Decode the message of a `Dht` frame
+    pub fn dht_rpc(&self) -> Option<DhtRpc> {
+        if self.frame_type != FrameType::Dht {
+            return None;
+        }
+        serde_json::from_slice(&self.payload).ok()
+    }
+}
+
+/// Routing table and records of one DHT node
+#[derive(Debug, Clone)]
+pub struct DhtNode {
+    contact: DhtContact,
+    /// Known nodes by distance, least recently seen first
+    buckets: Vec<Vec<DhtContact>>,
+    records: HashMap<DhtKey, DhtAgentRecord>,
+}
+
+impl DhtNode {
+    /// Node reached at `addr`
+    pub fn new(addr: &str) -> Self {
+        Self { contact: DhtContact::new(addr), buckets: vec![Vec::new(); 256], records: HashMap::new() }
+    }
+
+    /// This node's contact
+    pub fn contact(&self) -> &DhtContact {
+        &self.contact
+    }
+
+    /// Note a node was seen
+    ///
+    /// Full buckets keep their nodes, which have been up longer.
+    ///
+    /// # Returns
+    /// Whether the node is in the routing table
+    pub fn add_contact(&mut self, contact: DhtContact) -> bool {
+        if !contact.is_valid() {
+            return false;
+        }
+        let Some(index) = self.contact.id.bucket(&contact.id) else { return false };
+        let bucket = &mut self.buckets[index];
+        if let Some(position) = bucket.iter().position(|c| c.id == contact.id) {
+            let seen = bucket.remove(position);
+            bucket.push(seen);
+            return true;
+        }
+        if bucket.len() >= DHT_K {
+            return false;
+        }
+        bucket.push(contact);
+        true
+    }
+
+    /// Forget a node that did not answer
+    pub fn remove_contact(&mut self, contact: &DhtContact) {
+        if let Some(index) = self.contact.id.bucket(&contact.id) {
+            self.buckets[index].retain(|c| c.id != contact.id);
+        }
+    }
+
+    /// Number of known nodes
+    pub fn contacts(&self) -> usize {
+        self.buckets.iter().map(Vec::len).sum()
+    }
+
+    /// Known nodes closest to a key, closest first
+    pub fn closest(&self, target: &DhtKey, count: usize) -> Vec<DhtContact> {
+        let mut contacts: Vec<DhtContact> = self.buckets.iter().flatten().cloned().collect();
+        contacts.sort_by_key(|c| c.id.distance(target));
+        contacts.truncate(count);
+        contacts
+    }
+
+    /// Keep a record unless a newer one of its agent is stored
+    ///
+    /// # Returns
+    /// Whether the record was new
+    pub fn store(&mut self, record: DhtAgentRecord, now: u64) -> Result<bool, String> {
+        record.verify(now)?;
+        let key = record.key();
+        if self.records.get(&key).is_some_and(|known| known.seq >= record.seq && known.expires > now) {
+            return Ok(false);
+        }
+        self.records.insert(key, record);
+        Ok(true)
+    }
+
+    /// Stored record under a key, unless it expired
+    pub fn record(&self, key: &DhtKey, now: u64) -> Option<&DhtAgentRecord> {
+        self.records.get(key).filter(|record| record.expires > now)
+    }
+
+    /// Number of stored records
+    pub fn records(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Drop expired records
+    pub fn prune(&mut self, now: u64) {
+        self.records.retain(|_, record| record.expires > now);
+    }
+
+    /// Answer a message from another node or an agent
+    pub fn handle(&mut self, rpc: DhtRpc, now: u64) -> DhtMessage {
+        let from_node = rpc.sender.is_some();
+        if let Some(sender) = rpc.sender {
+            self.add_contact(sender);
+        }
+        match rpc.message {
+            DhtMessage::FindNode { target } => DhtMessage::Nodes { nodes: self.closest(&target, DHT_K) },
+            DhtMessage::Store { record } => match self.store(record, now) {
+                Ok(_) => DhtMessage::Stored,
+                Err(reason) => DhtMessage::Refused { reason },
+            },
+            DhtMessage::FindValue { key } => match self.record(&key, now) {
+                Some(record) => DhtMessage::Value { record: record.clone() },
+                // Agents get no nodes: they cannot reach other relays' DHT
+                None if from_node => DhtMessage::Nodes { nodes: self.closest(&key, DHT_K) },
+                None => DhtMessage::Nodes { nodes: Vec::new() },
+            },
+            other => DhtMessage::Refused { reason: format!("Not a request: {:?}", other) },
+        }
+    }
+}
+
+/// State of a node during a lookup
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Probe {
+    Waiting,
+    Asked,
+    Answered,
+    Failed,
+}
+
+/// Iterative search for the nodes closest to a key, or for a record
+#[derive(Debug, Clone)]
+pub struct DhtLookup {
+    target: DhtKey,
+    find_value: bool,
+    /// Nodes heard of, closest first
+    nodes: Vec<(DhtContact, Probe)>,
+    record: Option<DhtAgentRecord>,
+}
+
+impl DhtLookup {
+    /// Look for the nodes closest to a key
+    pub fn find_node(target: DhtKey, seeds: Vec<DhtContact>) -> Self {
+        Self::new(target, false, seeds)
+    }
+
+    /// Look for the record under a key
+    pub fn find_value(key: DhtKey, seeds: Vec<DhtContact>) -> Self {
+        Self::new(key, true, seeds)
+    }
+
+    fn new(target: DhtKey, find_value: bool, seeds: Vec<DhtContact>) -> Self {
+        let mut lookup = Self { target, find_value, nodes: Vec::new(), record: None };
+        lookup.add(seeds);
+        lookup
+    }
+
+    fn add(&mut self, contacts: Vec<DhtContact>) {
+        for contact in contacts.into_iter().filter(DhtContact::is_valid) {
+            if !self.nodes.iter().any(|(c, _)| c.id == contact.id) {
+                self.nodes.push((contact, Probe::Waiting));
+            }
+        }
+        let target = self.target;
+        self.nodes.sort_by_key(|(c, _)| c.id.distance(&target));
+    }
+
+    /// Request to send to the nodes asked
+    pub fn request(&self) -> DhtMessage {
+        if self.find_value {
+            DhtMessage::FindValue { key: self.target }
+        } else {
+            DhtMessage::FindNode { target: self.target }
+        }
+    }
+
+    /// Nodes to ask next, marked as asked
+    ///
+    /// Only the [`DHT_K`] closest nodes that have not failed are asked; none
+    /// are left once the lookup is done.
+    pub fn next_queries(&mut self) -> Vec<DhtContact> {
+        if self.record.is_some() {
+            return Vec::new();
+        }
+        let mut queries = Vec::new();
+        for (contact, probe) in self.nodes.iter_mut().filter(|(_, p)| *p != Probe::Failed).take(DHT_K) {
+            if *probe == Probe::Waiting && queries.len() < DHT_ALPHA {
+                *probe = Probe::Asked;
+                queries.push(contact.clone());
+            }
+        }
+        queries
+    }
+
+    /// Take a node's answer (`None` if it did not answer)
+    pub fn on_reply(&mut self, from: &DhtContact, reply: Option<DhtMessage>, now: u64) {
+        let answered = match reply {
+            Some(DhtMessage::Nodes { nodes }) => {
+                self.add(nodes);
+                true
+            }
+            Some(DhtMessage::Value { record }) if self.find_value && record.key() == self.target => {
+                let newer = self.record.as_ref().is_none_or(|known| known.seq < record.seq);
+                if record.verify(now).is_ok() && newer {
+                    self.record = Some(record);
+                }
+                self.record.is_some()
+            }
+            _ => false,
+        };
+        if let Some((_, probe)) = self.nodes.iter_mut().find(|(c, _)| c.id == from.id) {
+            *probe = if answered { Probe::Answered } else { Probe::Failed };
+        }
+    }
+
+    /// Nodes that did not answer
+    pub fn failed(&self) -> impl Iterator<Item = &DhtContact> {
+        self.nodes.iter().filter(|(_, p)| *p == Probe::Failed).map(|(c, _)| c)
+    }
+
+    /// Closest nodes that answered, closest first
+    pub fn closest(&self) -> Vec<DhtContact> {
+        self.nodes.iter().filter(|(_, p)| *p == Probe::Answered).take(DHT_K).map(|(c, _)| c.clone()).collect()
+    }
+
+    /// Record found, if any
+    pub fn record(&self) -> Option<&DhtAgentRecord> {
+        self.record.as_ref()
+    }
+
+    /// Ask nodes until the lookup is done
+    ///
+    /// # Arguments
+    /// * `query` - Sends a request to a node and returns its answer (`None`
+    ///   if it did not answer)
+    /// * `now` - Current time, for checking records (milliseconds)
+    pub async fn run<F, Fut>(mut self, mut query: F, now: u64) -> Self
+    where
+        F: FnMut(DhtContact, DhtMessage) -> Fut,
+        Fut: Future<Output = Option<DhtMessage>>,
+    {
+        loop {
+            let asked = self.next_queries();
+            if asked.is_empty() {
+                return self;
+            }
+            let request = self.request();
+            let replies = futures::future::join_all(asked.iter().map(|contact| query(contact.clone(), request.clone()))).await;
+            for (contact, reply) in asked.iter().zip(replies) {
+                self.on_reply(contact, reply, now);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_dht_lookup() {
+        let now = 1_000_000;
+        let nodes: Vec<RefCell<DhtNode>> = (0..60).map(|i| RefCell::new(DhtNode::new(&format!("quic://relay-{}:4242", i)))).collect();
+        let by_addr = |addr: &str| nodes.iter().find(|n| n.borrow().contact().addr == addr);
+        // Nodes join through the first one
+        let first = nodes[0].borrow().contact().clone();
+        for node in &nodes[1..] {
+            node.borrow_mut().add_contact(first.clone());
+        }
+        let network = |from: DhtContact| {
+            move |to: DhtContact, message: DhtMessage| {
+                let reply = by_addr(&to.addr).map(|node| node.borrow_mut().handle(DhtRpc { sender: Some(from.clone()), message }, now));
+                std::future::ready(reply)
+            }
+        };
+        for node in &nodes[1..] {
+            let (contact, seeds) = { let node = node.borrow(); (node.contact().clone(), node.closest(&node.contact().id, DHT_K)) };
+            let joined = futures::executor::block_on(DhtLookup::find_node(contact.id, seeds).run(network(contact.clone()), now));
+            for found in joined.closest() {
+                node.borrow_mut().add_contact(found);
+            }
+        }
+        assert!(nodes.iter().all(|n| n.borrow().contacts() > 5));
+
+        // Alice's relay stores her record at the closest nodes
+        let alice = KeyManager::generate_identity(1);
+        let record = DhtAgentRecord::sign(&alice, "quic://relay-7:4242", 1, now + 60_000);
+        let publisher = nodes[7].borrow().contact().clone();
+        let seeds = nodes[7].borrow().closest(&record.key(), DHT_K);
+        let closest = futures::executor::block_on(DhtLookup::find_node(record.key(), seeds).run(network(publisher.clone()), now)).closest();
+        assert_eq!(closest.len(), DHT_K);
+        for contact in &closest {
+            let rpc = DhtRpc { sender: Some(publisher.clone()), message: DhtMessage::Store { record: record.clone() } };
+            assert_eq!(by_addr(&contact.addr).unwrap().borrow_mut().handle(rpc, now), DhtMessage::Stored);
+        }
+
+        // Another relay finds it
+        let seeker = nodes[42].borrow().contact().clone();
+        let seeds = nodes[42].borrow().closest(&DhtKey::of_agent(&alice.id), DHT_K);
+        let found = futures::executor::block_on(DhtLookup::find_value(DhtKey::of_agent(&alice.id), seeds).run(network(seeker), now));
+        assert_eq!(found.record(), Some(&record));
+
+        // Agents get the record or nothing
+        let mut node = nodes[closest.len() % 60].borrow().clone();
+        node.store(record.clone(), now).unwrap();
+        let ask = |key| DhtRpc::from_agent(DhtMessage::FindValue { key });
+        assert_eq!(node.handle(ask(record.key()), now), DhtMessage::Value { record: record.clone() });
+        assert_eq!(node.handle(ask(DhtKey::of_agent("nobody")), now), DhtMessage::Nodes { nodes: Vec::new() });
+        assert!(node.record(&record.key(), now + 60_000).is_none());
+
+        // Forged, stale and expired records are refused
+        let mut forged = record.clone();
+        forged.relay = "quic://mallory:4242".to_string();
+        assert!(node.store(forged, now).unwrap_err().starts_with("Invalid signature"));
+        assert!(!node.store(DhtAgentRecord::sign(&alice, "quic://relay-1:4242", 1, now + 60_000), now).unwrap());
+        assert!(node.store(DhtAgentRecord::sign(&alice, "quic://relay-1:4242", 2, now), now).is_err());
+        assert!(node.store(DhtAgentRecord::sign(&alice, "quic://relay-1:4242", 2, now + 1), now).unwrap());
+
+        // Nodes that fail are dropped from lookups
+        let mut lookup = DhtLookup::find_node(DhtKey::of_agent("x"), vec![DhtContact::new("quic://gone:1")]);
+        let asked = lookup.next_queries();
+        lookup.on_reply(&asked[0], None, now);
+        assert_eq!(lookup.failed().count(), 1);
+        assert!(lookup.next_queries().is_empty() && lookup.closest().is_empty());
+        let bad = DhtContact { id: DhtKey([0; 32]), addr: "quic://liar:1".to_string() };
+        assert!(!node.add_contact(bad));
+    }
+}
@@ -0,0 +1,213 @@
+//! Structured protocol errors
+//!
+//! Rejections are reported with `Error` frames carrying a JSON
+//! `ErrorPayload`. Relays send them unsigned; peers sign them like any other
+//! frame. Clients map them to `OpacusError`.
+
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::content::ContentType;
+use crate::proto::FRAME_VERSION;
+use crate::qos::Priority;
+use crate::types::{FrameType, OpacusFrame, Ulid};
+
+/// Machine-readable rejection reason
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Sender exceeded a rate or queue limit; retry later
+    RateLimited,
+    /// Recipient is not known to the relay
+    UnknownRecipient,
+    /// Frame or payload exceeds a size limit
+    TooLarge,
+    /// Signature, HMAC or credentials were rejected
+    Unauthorized,
+    /// Recipient cannot handle the frame (e.g. its compression)
+    Unsupported,
+    /// A service behind the recipient (e.g. a bridged gateway) failed
+    Unavailable,
+    /// Code added by a newer protocol version
+    #[serde(other)]
+    Unknown,
+}
+
+/// Payload of an `Error` frame
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorPayload {
+    /// Rejection reason
+    pub code: ErrorCode,
+    /// Human-readable detail
+    pub message: String,
+    /// Suggested wait before retrying (milliseconds)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after_ms: Option<u64>,
+    /// Message ID of the rejected frame
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub related_id: Option<Ulid>,
+}
+
+impl ErrorPayload {
+    /// Create payload
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            retry_after_ms: None,
+            related_id: None,
+        }
+    }
+
+    /// Set the suggested retry delay
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after_ms = Some(retry_after.as_millis() as u64);
+        self
+    }
+
+    /// Set the message ID of the rejected frame
+    pub fn related_to(mut self, id: Option<Ulid>) -> Self {
+        self.related_id = id;
+        self
+    }
+
+    /// Build an unsigned `Error` frame (as sent by relays)
+    pub fn to_frame(&self, from: &str, to: &str, ts: u64) -> OpacusFrame {
+        OpacusFrame {
+            version: FRAME_VERSION,
+            frame_type: FrameType::Error,
+            from: from.to_string(),
+            to: to.to_string(),
+            seq: 0,
+            ts,
+            nonce: "".to_string(),
+            payload: serde_json::to_vec(self).unwrap_or_default().into(),
+            hmac: None,
+            sig: None,
+            key_epoch: 0,
+            compressed: None,
+            id: Some(OpacusFrame::new_id(ts)),
+            priority: Priority::Control,
+            content_type: ContentType::Json,
+            extensions: Default::default(),
+        }
+    }
+}
+
+/// Error reported by the relay or a peer
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum OpacusError {
+    /// Sender is rate limited
+    #[error("rate limited: {message}")]
+    RateLimited {
+        message: String,
+        retry_after: Option<Duration>,
+        related_id: Option<Ulid>,
+    },
+    /// Recipient is unknown
+    #[error("unknown recipient: {message}")]
+    UnknownRecipient {
+        message: String,
+        related_id: Option<Ulid>,
+    },
+    /// Frame too large
+    #[error("too large: {message}")]
+    TooLarge {
+        message: String,
+        related_id: Option<Ulid>,
+    },
+    /// Frame was not authorized
+    #[error("unauthorized: {message}")]
+    Unauthorized {
+        message: String,
+        related_id: Option<Ulid>,
+    },
+    /// Any other rejection
+    #[error("{code:?}: {message}")]
+    Remote {
+        code: ErrorCode,
+        message: String,
+        related_id: Option<Ulid>,
+    },
+}
+
+impl OpacusError {
+    /// Message ID of the rejected frame
+    pub fn related_id(&self) -> Option<Ulid> {
+        match self {
+            OpacusError::RateLimited { related_id, .. }
+            | OpacusError::UnknownRecipient { related_id, .. }
+            | OpacusError::TooLarge { related_id, .. }
+            | OpacusError::Unauthorized { related_id, .. }
+            | OpacusError::Remote { related_id, .. } => *related_id,
+        }
+    }
+}
+
+impl From<ErrorPayload> for OpacusError {
+    fn from(p: ErrorPayload) -> Self {
+        let (message, related_id) = (p.message, p.related_id);
+        match p.code {
+            ErrorCode::RateLimited => OpacusError::RateLimited {
+                message,
+                retry_after: p.retry_after_ms.map(Duration::from_millis),
+                related_id,
+            },
+            ErrorCode::UnknownRecipient => OpacusError::UnknownRecipient { message, related_id },
+            ErrorCode::TooLarge => OpacusError::TooLarge { message, related_id },
+            ErrorCode::Unauthorized => OpacusError::Unauthorized { message, related_id },
+            code => OpacusError::Remote { code, message, related_id },
+        }
+    }
+}
+
+impl OpacusFrame {
+    /// Decode the payload of an `Error` frame
+    ///
+    /// # Returns
+    /// `None` if this is not an `Error` frame or its payload is malformed
+    pub fn error_payload(&self) -> Option<ErrorPayload> {
+        if self.frame_type != FrameType::Error {
+            return None;
+        }
+        serde_json::from_slice(&self.payload).ok()
+    }
+
+    /// Map an `Error` frame to an `OpacusError`
+    pub fn as_error(&self) -> Option<OpacusError> {
+        self.error_payload().map(OpacusError::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_frame_roundtrip() {
+        let related = OpacusFrame::new_id(1);
+        let frame = ErrorPayload::new(ErrorCode::RateLimited, "slow down")
+            .with_retry_after(Duration::from_secs(2))
+            .related_to(Some(related))
+            .to_frame("relay", "alice", 1000);
+        assert_eq!(frame.frame_type, FrameType::Error);
+
+        match frame.as_error().unwrap() {
+            OpacusError::RateLimited { retry_after, related_id, .. } => {
+                assert_eq!(retry_after, Some(Duration::from_secs(2)));
+                assert_eq!(related_id, Some(related));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let frame = ErrorPayload::new(ErrorCode::Unsupported, "lz4").to_frame("relay", "alice", 1000);
+        assert!(matches!(frame.as_error(), Some(OpacusError::Remote { code: ErrorCode::Unsupported, .. })));
+    }
+
+    #[test]
+    fn test_unknown_code() {
+        let payload: ErrorPayload = serde_json::from_str(r#"{"code":"quota_exceeded","message":"x"}"#).unwrap();
+        assert_eq!(payload.code, ErrorCode::Unknown);
+        assert!(ErrorPayload::new(ErrorCode::TooLarge, "").to_frame("a", "b", 0).as_error().unwrap().related_id().is_none());
+    }
+}
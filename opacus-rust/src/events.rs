@@ -0,0 +1,155 @@
+//! Live relay events for dashboards
+//!
+//! The relay publishes a [`RelayEvent`] when an agent connects or
+//! disconnects, when it routes a frame, and when an offline agent's queue
+//! grows or is flushed. Subscribe in-process with
+//! [`OpacusRelayServer::subscribe_events`](crate::OpacusRelayServer::subscribe_events),
+//! or read them as Server-Sent Events from `GET /events` on the relay's
+//! admin listener. Events carry frame metadata only, never payloads.
+//!
+//! Routing events are only built while someone is subscribed; while there
+//! are subscribers, the relay decodes every frame instead of forwarding by
+//! routing header alone.
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use crate::types::{FrameType, OpacusFrame, Ulid};
+
+/// Events buffered per subscriber before a slow one starts missing events
+pub const EVENT_BUFFER: usize = 1024;
+
+/// Something that happened on the relay
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayEvent {
+    /// When it happened (milliseconds since the Unix epoch)
+    pub ts: u64,
+    #[serde(flatten)]
+    pub kind: RelayEventKind,
+}
+
+/// What happened, tagged by `type` in JSON
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum RelayEventKind {
+    AgentConnected { agent: String },
+    AgentDisconnected { agent: String },
+    /// A frame was delivered, queued or dropped
+    FrameRouted {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<Ulid>,
+        frame_type: FrameType,
+        from: String,
+        to: String,
+        /// Payload length
+        bytes: usize,
+        outcome: RouteOutcome,
+    },
+    /// Frames queued for an offline agent
+    QueueDepth { agent: String, depth: usize },
+}
+
+impl RelayEventKind {
+    /// Name of the event type, as in its `type` tag
+    pub fn name(&self) -> &'static str {
+        match self {
+            RelayEventKind::AgentConnected { .. } => "agentConnected",
+            RelayEventKind::AgentDisconnected { .. } => "agentDisconnected",
+            RelayEventKind::FrameRouted { .. } => "frameRouted",
+            RelayEventKind::QueueDepth { .. } => "queueDepth",
+        }
+    }
+
+    /// A routed frame and what became of it
+    pub fn frame_routed(frame: &OpacusFrame, outcome: RouteOutcome) -> Self {
+        RelayEventKind::FrameRouted {
+            id: frame.id,
+            frame_type: frame.frame_type,
+            from: frame.from.clone(),
+            to: frame.to.clone(),
+            bytes: frame.payload.len(),
+            outcome,
+        }
+    }
+}
+
+/// What the relay did with a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteOutcome {
+    /// Sent to the online recipient
+    Delivered,
+    /// Held for the offline recipient
+    Queued,
+    /// Dropped because the recipient's connection is congested
+    Dropped,
+    /// Refused with an `Error` frame to the sender
+    Rejected,
+    /// Handed to the federated relay the recipient is connected to
+    Forwarded,
+}
+
+/// Publisher of relay events
+#[derive(Debug, Clone)]
+pub(crate) struct RelayEvents {
+    tx: broadcast::Sender<RelayEvent>,
+}
+
+impl Default for RelayEvents {
+    fn default() -> Self {
+        Self { tx: broadcast::channel(EVENT_BUFFER).0 }
+    }
+}
+
+impl RelayEvents {
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<RelayEvent> {
+        self.tx.subscribe()
+    }
+
+    /// Whether anyone is subscribed
+    pub(crate) fn is_watched(&self) -> bool {
+        self.tx.receiver_count() > 0
+    }
+
+    /// Publish an event, built only if anyone is subscribed
+    pub(crate) fn emit(&self, kind: impl FnOnce() -> RelayEventKind) {
+        if !self.is_watched() {
+            return;
+        }
+        let ts = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let _ = self.tx.send(RelayEvent { ts, kind: kind() });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{ErrorCode, ErrorPayload};
+
+    #[test]
+    fn test_relay_events() {
+        let events = RelayEvents::default();
+        let frame = ErrorPayload::new(ErrorCode::Unavailable, "down").to_frame("alice", "bob", 1);
+        events.emit(|| unreachable!("no subscribers"));
+
+        let mut rx = events.subscribe();
+        assert!(events.is_watched());
+        events.emit(|| RelayEventKind::frame_routed(&frame, RouteOutcome::Queued));
+        let event = rx.try_recv().unwrap();
+        assert_eq!(event.kind.name(), "frameRouted");
+
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["type"], "frameRouted");
+        assert_eq!(json["frameType"], "error");
+        assert_eq!((json["from"].as_str(), json["outcome"].as_str()), (Some("alice"), Some("queued")));
+        assert_eq!(json["bytes"], frame.payload.len());
+        assert_eq!(serde_json::from_value::<RelayEvent>(json).unwrap(), event);
+
+        let json = serde_json::json!({"ts": 5, "type": "queueDepth", "agent": "bob", "depth": 3});
+        let event: RelayEvent = serde_json::from_value(json).unwrap();
+        assert_eq!(event.kind, RelayEventKind::QueueDepth { agent: "bob".into(), depth: 3 });
+    }
+}
@@ -0,0 +1,477 @@
+//! Gossip of agent presence between federated relays
+//!
+//! Federated relays find out where agents are connected without a central
+//! directory. Every round, each relay signs a [`PresenceDigest`] (its URL, a
+//! sequence number and a Bloom filter of its connected agents) with its
+//! Ed25519 key, and sends all digests it knows, its own and those it learnt,
+//! in a `Gossip` frame to a few random relays: its configured peers and the
+//! relays named by digests it received. Each relay keeps the newest digest of
+//! every relay in a [`PresenceTable`], so digests, and with them the relays
+//! themselves, spread through the federation within a few rounds.
+//!
+//! A relay asked to route a frame for an agent it does not have hands the
+//! frame to a relay whose filter contains the agent. Bloom filters never miss
+//! an agent but match others with a small probability (1% by default), so a
+//! frame may occasionally reach a relay that queues it for an agent that
+//! never connects there. Digests expire unless renewed, so relays that leave
+//! the federation, and agents that leave a relay, drop out of the tables.
+//!
+//! Any key can sign a digest claiming any agents, so tables only accept the
+//! digests of known relays (e.g. those in the relay registry), set with
+//! `with_trusted_keys`; a table without trusted keys accepts none. Digests
+//! signed further ahead than [`MAX_CLOCK_SKEW_MS`] are refused, and tables
+//! hold at most `max_relays` relays.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::time::Duration;
+use bytes::Bytes;
+use ed25519_dalek::SigningKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::content::ContentType;
+use crate::crypto::SecurityManager;
+use crate::proto::FRAME_VERSION;
+use crate::qos::Priority;
+use crate::random::Random;
+use crate::redact::Secret;
+use crate::types::{FrameType, OpacusFrame};
+
+/// Default share of absent agents a presence filter matches
+pub const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// Default lifetime of a digest that is not renewed (milliseconds)
+pub const DEFAULT_DIGEST_TTL_MS: u64 = 60_000;
+
+/// Default number of relays a table holds
+pub const DEFAULT_MAX_RELAYS: usize = 1024;
+
+/// How far ahead of the local clock a digest may be signed (milliseconds)
+pub const MAX_CLOCK_SKEW_MS: u64 = 60_000;
+
+/// Domain separator of digest signatures
+const GOSSIP_CONTEXT: &str = "opacus-gossip-v1";
+
+/// Largest filter accepted from other relays (bytes)
+const MAX_FILTER_LEN: usize = 1 << 20;
+
+/// Most bit positions per agent
+const MAX_FILTER_HASHES: u32 = 16;
+
+/// Bloom filter of the agents connected to a relay
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PresenceFilter {
+    /// Bit positions set per agent
+    pub hashes: u32,
+    /// Filter bits
+    pub bits: Bytes,
+}
+
+impl PresenceFilter {
+    /// Build a filter of agents
+    ///
+    /// # Arguments
+    /// * `agents` - IDs of the agents
+    /// * `false_positive_rate` - Share of other agents the filter may match
+    pub fn new<'a>(agents: impl IntoIterator<Item = &'a str>, false_positive_rate: f64) -> Self {
+        let agents: Vec<&str> = agents.into_iter().collect();
+        let n = agents.len().max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let m = (-n * false_positive_rate.clamp(1e-9, 0.5).ln() / (ln2 * ln2)).ceil().max(64.0) as usize;
+        let hashes = ((m as f64 / n) * ln2).round().clamp(1.0, MAX_FILTER_HASHES as f64) as u32;
+        let mut bits = vec![0u8; m.div_ceil(8)];
+        for agent in agents {
+            for bit in Self::positions(agent, bits.len() * 8, hashes) {
+                bits[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        Self { hashes, bits: bits.into() }
+    }
+
+    /// Bit positions of an agent (double hashing of SHA-256)
+    fn positions(agent_id: &str, len: usize, hashes: u32) -> impl Iterator<Item = usize> {
+        let hash = Sha256::digest(agent_id.as_bytes());
+        let h1 = u64::from_be_bytes(hash[..8].try_into().expect("8 bytes"));
+        let h2 = u64::from_be_bytes(hash[8..16].try_into().expect("8 bytes")) | 1;
+        (0..hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len as u64) as usize)
+    }
+
+    /// Whether the filter may contain an agent
+    pub fn contains(&self, agent_id: &str) -> bool {
+        !self.bits.is_empty()
+            && Self::positions(agent_id, self.bits.len() * 8, self.hashes).all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+}
+
+/// Signed summary of the agents connected to a relay
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PresenceDigest {
+    /// URL other relays reach the relay at (e.g. `quic://host:port`)
+    pub relay: String,
+    /// Relay's Ed25519 public key
+    pub relay_key: [u8; 32],
+    /// Round number, increasing with every digest of the relay
+    pub seq: u64,
+    /// Signing time (milliseconds)
+    pub ts: u64,
+    /// Number of connected agents
+    pub agents: u64,
+    /// Connected agents
+    pub filter: PresenceFilter,
+    /// Signature by `relay_key`
+    pub signature: Vec<u8>,
+}
+
+impl fmt::Debug for PresenceDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PresenceDigest")
+            .field("relay", &self.relay)
+            .field("relay_key", &hex::encode(self.relay_key))
+            .field("seq", &self.seq)
+            .field("ts", &self.ts)
+            .field("agents", &self.agents)
+            .field("filter_len", &self.filter.bits.len())
+            .finish()
+    }
+}
+
+impl PresenceDigest {
+    /// Sign a digest of a relay's agents
+    ///
+    /// # Arguments
+    /// * `config` - Relay's gossip settings
+    /// * `seq` - Round number, higher than the relay's previous digests
+    /// * `ts` - Current time (milliseconds)
+    /// * `agents` - IDs of the connected agents
+    pub fn sign<'a>(config: &GossipConfig, seq: u64, ts: u64, agents: impl IntoIterator<Item = &'a str>) -> Self {
+        let agents: Vec<&str> = agents.into_iter().collect();
+        let mut digest = Self {
+            relay: config.relay.clone(),
+            relay_key: config.public_key(),
+            seq,
+            ts,
+            agents: agents.len() as u64,
+            filter: PresenceFilter::new(agents, config.false_positive_rate),
+            signature: Vec::new(),
+        };
+        digest.signature = SecurityManager::sign(&config.key, &digest.signing_data());
+        digest
+    }
+
+    fn signing_data(&self) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!([
+            GOSSIP_CONTEXT,
+            self.relay,
+            hex::encode(self.relay_key),
+            self.seq,
+            self.ts,
+            self.agents,
+            self.filter.hashes,
+            hex::encode(&self.filter.bits),
+        ]))
+        .expect("JSON array")
+    }
+
+    /// Verify the relay's signature
+    pub fn verify(&self) -> Result<(), String> {
+        if self.filter.bits.len() > MAX_FILTER_LEN || !(1..=MAX_FILTER_HASHES).contains(&self.filter.hashes) {
+            return Err(format!("Invalid presence filter from {}", self.relay));
+        }
+        if !SecurityManager::verify(&self.relay_key, &self.signing_data(), &self.signature) {
+            return Err(format!("Invalid signature on digest of {}", self.relay));
+        }
+        Ok(())
+    }
+}
+
+/// Gossip settings of a relay
+///
+/// `Debug` output redacts the key.
+#[derive(Clone)]
+pub struct GossipConfig {
+    /// URL other relays reach this relay at
+    pub relay: String,
+    /// Ed25519 key digests are signed with
+    pub key: [u8; 32],
+    /// Relays to gossip with before others are learnt
+    pub peers: Vec<String>,
+    /// Relays each round's digests are sent to
+    pub fanout: usize,
+    /// Time between rounds
+    pub interval: Duration,
+    /// Lifetime of digests that are not renewed (milliseconds)
+    pub ttl_ms: u64,
+    /// Share of absent agents this relay's filters may match
+    pub false_positive_rate: f64,
+    /// Keys of the relays whose digests are accepted
+    pub trusted_keys: HashSet<[u8; 32]>,
+    /// Most relays a table holds
+    pub max_relays: usize,
+}
+
+impl fmt::Debug for GossipConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GossipConfig")
+            .field("relay", &self.relay)
+            .field("key", &Secret(&self.key))
+            .field("peers", &self.peers)
+            .field("fanout", &self.fanout)
+            .field("interval", &self.interval)
+            .field("ttl_ms", &self.ttl_ms)
+            .field("false_positive_rate", &self.false_positive_rate)
+            .field("trusted_keys", &self.trusted_keys.len())
+            .field("max_relays", &self.max_relays)
+            .finish()
+    }
+}
+
+impl GossipConfig {
+    /// Gossip as the relay at `relay`, signing with an Ed25519 key
+    ///
+    /// Rounds are 10 seconds apart and reach 3 relays; digests live for
+    /// [`DEFAULT_DIGEST_TTL_MS`]. No other relay's digests are accepted
+    /// until their keys are added with `with_trusted_keys`.
+    pub fn new(relay: &str, key: [u8; 32]) -> Self {
+        Self {
+            relay: relay.to_string(),
+            key,
+            peers: Vec::new(),
+            fanout: 3,
+            interval: Duration::from_secs(10),
+            ttl_ms: DEFAULT_DIGEST_TTL_MS,
+            false_positive_rate: DEFAULT_FALSE_POSITIVE_RATE,
+            trusted_keys: HashSet::new(),
+            max_relays: DEFAULT_MAX_RELAYS,
+        }
+    }
+
+    /// Relays to start gossiping with
+    pub fn with_peers(mut self, peers: impl IntoIterator<Item = String>) -> Self {
+        self.peers = peers.into_iter().collect();
+        self
+    }
+
+    /// Time between rounds and relays reached per round
+    pub fn with_rounds(mut self, interval: Duration, fanout: usize) -> Self {
+        self.interval = interval;
+        self.fanout = fanout;
+        self
+    }
+
+    /// Accept digests signed by these keys
+    pub fn with_trusted_keys(mut self, keys: impl IntoIterator<Item = [u8; 32]>) -> Self {
+        self.trusted_keys.extend(keys);
+        self
+    }
+
+    /// Public key other relays check this relay's digests with
+    pub fn public_key(&self) -> [u8; 32] {
+        SigningKey::from_bytes(&self.key).verifying_key().to_bytes()
+    }
+
+    /// Empty table accepting the digests these settings allow
+    pub fn table(&self) -> PresenceTable {
+        PresenceTable {
+            digests: HashMap::new(),
+            ttl_ms: self.ttl_ms,
+            trusted_keys: self.trusted_keys.clone(),
+            max_relays: self.max_relays,
+        }
+    }
+}
+
+/// Newest digest of every relay in the federation
+#[derive(Debug, Clone, Default)]
+pub struct PresenceTable {
+    digests: HashMap<[u8; 32], PresenceDigest>,
+    ttl_ms: u64,
+    trusted_keys: HashSet<[u8; 32]>,
+    max_relays: usize,
+}
+
+impl PresenceTable {
+    /// Add a digest unless the table has a newer one of its relay
+    ///
+    /// # Returns
+    /// Whether the digest was new
+    pub fn merge(&mut self, digest: PresenceDigest, now: u64) -> Result<bool, String> {
+        if !self.trusted_keys.contains(&digest.relay_key) {
+            return Err(format!("Digest of {} is signed by an untrusted key", digest.relay));
+        }
+        if digest.ts.saturating_add(self.ttl_ms) <= now {
+            return Err(format!("Digest of {} has expired", digest.relay));
+        }
+        if digest.ts > now.saturating_add(MAX_CLOCK_SKEW_MS) {
+            return Err(format!("Digest of {} is signed in the future", digest.relay));
+        }
+        if self.digests.get(&digest.relay_key).is_some_and(|known| known.seq >= digest.seq) {
+            return Ok(false);
+        }
+        digest.verify()?;
+        if !self.digests.contains_key(&digest.relay_key) && self.digests.len() >= self.max_relays {
+            self.prune(now);
+            if self.digests.len() >= self.max_relays {
+                return Err(format!("Presence table is full; dropping digest of {}", digest.relay));
+            }
+        }
+        self.digests.insert(digest.relay_key, digest);
+        Ok(true)
+    }
+
+    /// Drop digests that were not renewed in time
+    pub fn prune(&mut self, now: u64) {
+        let ttl_ms = self.ttl_ms;
+        self.digests.retain(|_, digest| digest.ts.saturating_add(ttl_ms) > now);
+    }
+
+    /// Digests that have not expired, to pass on
+    pub fn digests(&self, now: u64) -> Vec<PresenceDigest> {
+        self.fresh(now).cloned().collect()
+    }
+
+    fn fresh(&self, now: u64) -> impl Iterator<Item = &PresenceDigest> {
+        self.digests.values().filter(move |digest| digest.ts.saturating_add(self.ttl_ms) > now)
+    }
+
+    /// URLs of the relays known to the table
+    pub fn relays(&self, now: u64) -> Vec<String> {
+        self.fresh(now).map(|digest| digest.relay.clone()).collect()
+    }
+
+    /// Relays an agent may be connected to, those with the fewest agents
+    /// (and so the fewest false matches) first
+    pub fn locate(&self, agent_id: &str, now: u64) -> Vec<String> {
+        let mut found: Vec<&PresenceDigest> = self.fresh(now).filter(|digest| digest.filter.contains(agent_id)).collect();
+        found.sort_by_key(|digest| digest.agents);
+        found.into_iter().map(|digest| digest.relay.clone()).collect()
+    }
+
+    /// Pick the relays to send a round's digests to
+    ///
+    /// # Arguments
+    /// * `config` - This relay's gossip settings
+    /// * `now` - Current time (milliseconds)
+    /// * `random` - Source of the choice
+    pub fn gossip_targets(&self, config: &GossipConfig, now: u64, random: &dyn Random) -> Vec<String> {
+        let mut relays: Vec<String> = config.peers.clone();
+        for relay in self.relays(now) {
+            if !relays.contains(&relay) {
+                relays.push(relay);
+            }
+        }
+        relays.retain(|relay| *relay != config.relay);
+        let count = config.fanout.min(relays.len());
+        for i in 0..count {
+            let j = i + (random.next_u64() % (relays.len() - i) as u64) as usize;
+            relays.swap(i, j);
+        }
+        relays.truncate(count);
+        relays
+    }
+}
+
+impl OpacusFrame {
+    /// Build an unsigned `Gossip` frame carrying digests from relay to relay
+    pub fn gossip(digests: &[PresenceDigest], ts: u64) -> Result<OpacusFrame, String> {
+        let mut payload = Vec::new();
+        ciborium::into_writer(digests, &mut payload).map_err(|e| e.to_string())?;
+        Ok(OpacusFrame {
+            version: FRAME_VERSION,
+            frame_type: FrameType::Gossip,
+            from: "relay".to_string(),
+            to: "relay".to_string(),
+            seq: 0,
+            ts,
+            nonce: String::new(),
+            payload: payload.into(),
+            hmac: None,
+            sig: None,
+            key_epoch: 0,
+            compressed: None,
+            id: Some(OpacusFrame::new_id(ts)),
+            priority: Priority::Low,
+            content_type: ContentType::Cbor,
+            extensions: Default::default(),
+        })
+    }
+
+    /// Digests carried by a `Gossip` frame (unverified)
+    pub fn gossip_digests(&self) -> Result<Vec<PresenceDigest>, String> {
+        if self.frame_type != FrameType::Gossip {
+            return Err("Not a gossip frame".into());
+        }
+        ciborium::from_reader(&self.payload[..]).map_err(|e| format!("Invalid gossip frame: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::{OsRandom, SeededRandom};
+
+    #[test]
+    fn test_presence_gossip() {
+        let agents: Vec<String> = (0..500).map(|i| format!("agent-{}", i)).collect();
+        let filter = PresenceFilter::new(agents.iter().map(String::as_str), DEFAULT_FALSE_POSITIVE_RATE);
+        assert!(agents.iter().all(|agent| filter.contains(agent)));
+        let false_matches = (0..10_000).filter(|i| filter.contains(&format!("other-{}", i))).count();
+        assert!(false_matches < 300, "{} false matches", false_matches);
+        assert!(!PresenceFilter::new([], DEFAULT_FALSE_POSITIVE_RATE).contains("agent-0"));
+
+        let key = |seed: u8| [seed; 32];
+        let b = GossipConfig::new("quic://b:4242", key(2));
+        let c = GossipConfig::new("quic://c:4242", key(3));
+        let a = GossipConfig::new("quic://a:4242", key(1))
+            .with_peers(["quic://b:4242".to_string()])
+            .with_trusted_keys([b.public_key(), c.public_key()]);
+        let now = 1_000_000;
+
+        // Relay A learns from B which relay has an agent, and about C through B
+        let mut table = a.table();
+        assert!(table.merge(PresenceDigest::sign(&b, 1, now, ["alice", "bob"]), now).unwrap());
+        assert!(table.merge(PresenceDigest::sign(&c, 7, now, ["carol"]), now).unwrap());
+        assert_eq!(table.locate("alice", now), vec!["quic://b:4242"]);
+        assert_eq!(table.locate("carol", now), vec!["quic://c:4242"]);
+        assert!(table.locate("dave", now).is_empty());
+
+        // Newer digests replace older ones; older ones are ignored
+        assert!(table.merge(PresenceDigest::sign(&b, 2, now + 10, ["bob"]), now).unwrap());
+        assert!(!table.merge(PresenceDigest::sign(&b, 1, now, ["alice"]), now).unwrap());
+        assert!(table.locate("alice", now).is_empty());
+
+        // Forged, expired, future and untrusted digests are refused
+        let mut forged = PresenceDigest::sign(&c, 8, now, ["carol", "alice"]);
+        forged.relay = "quic://mallory:4242".to_string();
+        assert!(table.merge(forged, now).unwrap_err().starts_with("Invalid signature"));
+        assert!(table.merge(PresenceDigest::sign(&c, 9, now, []), now + DEFAULT_DIGEST_TTL_MS).is_err());
+        assert!(table.merge(PresenceDigest::sign(&c, 9, u64::MAX, []), now).unwrap_err().contains("in the future"));
+        let mut trusting = GossipConfig::new("quic://a:4242", key(1)).with_trusted_keys([b.public_key()]).table();
+        assert!(trusting.merge(PresenceDigest::sign(&c, 1, now, ["carol"]), now).is_err());
+        assert!(GossipConfig::new("quic://a:4242", key(1)).table().merge(PresenceDigest::sign(&b, 1, now, []), now).is_err());
+
+        // Tables hold a bounded number of relays
+        let mut small = GossipConfig { max_relays: 1, ..a.clone() }.table();
+        assert!(small.merge(PresenceDigest::sign(&b, 1, now, ["alice"]), now).unwrap());
+        assert!(small.merge(PresenceDigest::sign(&c, 1, now, ["carol"]), now).unwrap_err().contains("full"));
+        assert!(small.merge(PresenceDigest::sign(&c, 2, now + 100, ["carol"]), now + DEFAULT_DIGEST_TTL_MS).unwrap());
+
+        // Gossip goes to configured and learnt relays, never to the relay itself
+        let mut targets = table.gossip_targets(&a, now, &OsRandom);
+        targets.sort();
+        assert_eq!(targets, vec!["quic://b:4242", "quic://c:4242"]);
+        let one = GossipConfig { fanout: 1, ..a.clone() };
+        assert_eq!(table.gossip_targets(&one, now, &SeededRandom::new(1)).len(), 1);
+
+        // Digests expire unless renewed
+        assert_eq!(table.relays(now + DEFAULT_DIGEST_TTL_MS).len(), 1);
+        table.prune(now + DEFAULT_DIGEST_TTL_MS + 10);
+        assert!(table.digests(now).is_empty());
+
+        // Digests travel in Gossip frames
+        let digests = vec![PresenceDigest::sign(&a, 3, now, ["erin"])];
+        let frame = OpacusFrame::gossip(&digests, now).unwrap();
+        assert_eq!(frame.gossip_digests().unwrap(), digests);
+        assert!(trusting.merge(frame.gossip_digests().unwrap().remove(0), now).is_err());
+    }
+}
@@ -0,0 +1,139 @@
+//! Relay health checks
+//!
+//! The relay's admin listener (see [`crate::admin`]) answers `GET /healthz`
+//! (liveness) and `GET /readyz` (readiness) for Kubernetes probes and load
+//! balancers. Both return a JSON [`HealthReport`], with status 200 when the
+//! check passes and 503 when it does not.
+//!
+//! The relay's accept loop records a heartbeat every [`HEARTBEAT_INTERVAL`].
+//! Liveness fails once the heartbeat is older than [`HEARTBEAT_TIMEOUT`]
+//! (the loop died or the runtime is blocked); readiness also fails once the
+//! loop has stopped accepting connections, e.g. while shutting down.
+
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+
+/// How often the accept loop records a heartbeat
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Heartbeat age after which the accept loop counts as stalled
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// State of the relay's accept loop
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AcceptLoopState {
+    /// Relay not started yet
+    Starting,
+    /// Accepting connections
+    Running,
+    /// No heartbeat within [`HEARTBEAT_TIMEOUT`]
+    Stalled,
+    /// Stopped accepting connections
+    Stopped,
+}
+
+/// Health of a relay, as served by its health endpoints
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthReport {
+    /// Whether the relay is alive (`/healthz`)
+    pub live: bool,
+    /// Whether the relay accepts agents (`/readyz`)
+    pub ready: bool,
+    pub accept_loop: AcceptLoopState,
+    /// Connected agents
+    pub agents: usize,
+    /// Frames queued for offline agents
+    pub pending: usize,
+    /// Seconds since the relay started
+    pub uptime_secs: u64,
+}
+
+// Accept-loop states (0 until the relay starts)
+const RUNNING: u8 = 1;
+const STOPPED: u8 = 2;
+
+/// Accept-loop liveness, shared between the loop and the health listener
+#[derive(Debug, Default)]
+pub(crate) struct RelayHealth {
+    started: OnceLock<Instant>,
+    state: AtomicU8,
+    /// Milliseconds from `started` to the last heartbeat
+    heartbeat_ms: AtomicU64,
+}
+
+impl RelayHealth {
+    /// Mark the accept loop as running
+    pub(crate) fn start(&self) {
+        self.started.get_or_init(Instant::now);
+        self.beat();
+        self.state.store(RUNNING, Ordering::Relaxed);
+    }
+
+    /// Record an accept-loop heartbeat
+    pub(crate) fn beat(&self) {
+        if let Some(started) = self.started.get() {
+            self.heartbeat_ms.store(started.elapsed().as_millis() as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Mark the accept loop as stopped
+    pub(crate) fn stop(&self) {
+        self.state.store(STOPPED, Ordering::Relaxed);
+    }
+
+    fn accept_loop(&self, now: Instant) -> AcceptLoopState {
+        let Some(started) = self.started.get() else { return AcceptLoopState::Starting };
+        if self.state.load(Ordering::Relaxed) == STOPPED {
+            return AcceptLoopState::Stopped;
+        }
+        let heartbeat = *started + Duration::from_millis(self.heartbeat_ms.load(Ordering::Relaxed));
+        if now.saturating_duration_since(heartbeat) > HEARTBEAT_TIMEOUT {
+            AcceptLoopState::Stalled
+        } else {
+            AcceptLoopState::Running
+        }
+    }
+
+    /// Health report with the relay's current counts
+    pub(crate) fn report(&self, agents: usize, pending: usize) -> HealthReport {
+        let now = Instant::now();
+        let accept_loop = self.accept_loop(now);
+        HealthReport {
+            // A stopped relay is draining, not broken: only restart it if stalled
+            live: accept_loop != AcceptLoopState::Stalled,
+            ready: accept_loop == AcceptLoopState::Running,
+            accept_loop,
+            agents,
+            pending,
+            uptime_secs: self.started.get().map_or(0, |started| now.duration_since(*started).as_secs()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept_loop_health() {
+        let health = RelayHealth::default();
+        let report = health.report(0, 0);
+        assert_eq!((report.accept_loop, report.live, report.ready), (AcceptLoopState::Starting, true, false));
+
+        health.start();
+        let report = health.report(3, 5);
+        assert_eq!((report.accept_loop, report.live, report.ready), (AcceptLoopState::Running, true, true));
+        assert_eq!((report.agents, report.pending), (3, 5));
+
+        let later = Instant::now() + HEARTBEAT_TIMEOUT + Duration::from_secs(1);
+        assert_eq!(health.accept_loop(later), AcceptLoopState::Stalled);
+
+        health.stop();
+        let report = health.report(0, 0);
+        assert_eq!((report.accept_loop, report.live, report.ready), (AcceptLoopState::Stopped, true, false));
+    }
+}
@@ -0,0 +1,140 @@
+//! Retained channel history
+//!
+//! A publisher can retain the latest data it published on a channel (see
+//! `OpacusClient::retain_history`), so agents that subscribe late or lost
+//! their connection recover what they missed. A subscriber catches up by
+//! setting `since` in its [`SubscribeRequest`](crate::SubscribeRequest): right
+//! after accepting, the publisher replays the data published after that time
+//! as `Stream` frames. It can also fetch entries with a `History` frame whose
+//! payload is a [`HistoryQuery`]; the publisher answers with a
+//! [`HistoryPage`] marked as the reply to the query.
+//!
+//! Times are the publisher's clock in milliseconds. To resume after a gap,
+//! subscribers pass the timestamp of the last `Stream` frame they received.
+//! History of gated and paid channels is only served to their subscribers.
+
+use std::collections::VecDeque;
+use serde::{Deserialize, Serialize};
+use crate::types::{FrameType, OpacusFrame};
+
+/// Data published on a channel
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    /// Publishing time (milliseconds)
+    pub published_at: u64,
+    pub data: Vec<u8>,
+}
+
+/// Latest entries of a channel, oldest first
+#[derive(Debug, Clone)]
+pub struct ChannelHistory {
+    limit: usize,
+    entries: VecDeque<HistoryEntry>,
+}
+
+impl ChannelHistory {
+    /// History keeping at most `limit` entries
+    pub fn new(limit: usize) -> Self {
+        Self { limit, entries: VecDeque::with_capacity(limit) }
+    }
+
+    /// Maximum number of entries kept
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Append an entry, dropping the oldest one if full
+    pub fn push(&mut self, published_at: u64, data: Vec<u8>) {
+        if self.limit == 0 {
+            return;
+        }
+        if self.entries.len() == self.limit {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(HistoryEntry { published_at, data });
+    }
+
+    /// Entries published after `since` (milliseconds)
+    pub fn since(&self, since: u64) -> Vec<HistoryEntry> {
+        self.entries.iter().filter(|e| e.published_at > since).cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Request for the retained history of a channel, sent to its publisher
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryQuery {
+    pub channel_id: String,
+    /// Only entries published after this time (milliseconds; `0` for all)
+    pub since: u64,
+}
+
+/// Retained history sent in answer to a [`HistoryQuery`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryPage {
+    pub channel_id: String,
+    /// Entries, oldest first
+    pub entries: Vec<HistoryEntry>,
+}
+
+impl OpacusFrame {
+    /// Decode the query of a `History` frame
+    pub fn history_query(&self) -> Option<HistoryQuery> {
+        if self.frame_type != FrameType::History {
+            return None;
+        }
+        serde_json::from_slice(&self.payload).ok()
+    }
+
+    /// Decode the entries carried by a `History` frame
+    pub fn history_page(&self) -> Option<HistoryPage> {
+        if self.frame_type != FrameType::History {
+            return None;
+        }
+        serde_json::from_slice(&self.payload).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{ErrorCode, ErrorPayload};
+
+    #[test]
+    fn test_channel_history() {
+        let mut history = ChannelHistory::new(3);
+        for (time, data) in [(10, "a"), (20, "b"), (20, "c"), (30, "d")] {
+            history.push(time, data.as_bytes().to_vec());
+        }
+        // The oldest entry was dropped
+        assert_eq!(history.len(), 3);
+        let data = |entries: Vec<HistoryEntry>| entries.into_iter().map(|e| e.data).collect::<Vec<_>>();
+        assert_eq!(data(history.since(0)), [b"b", b"c", b"d"]);
+        assert_eq!(data(history.since(20)), [b"d"]);
+        assert!(history.since(30).is_empty());
+
+        let mut empty = ChannelHistory::new(0);
+        empty.push(10, b"a".to_vec());
+        assert!(empty.is_empty());
+
+        // Queries and pages are told apart by their fields
+        let mut frame = ErrorPayload::new(ErrorCode::Unavailable, "").to_frame("a", "b", 1);
+        frame.frame_type = FrameType::History;
+        frame.payload = br#"{"channelId":"prices","since":20}"#.to_vec().into();
+        assert_eq!(frame.history_query(), Some(HistoryQuery { channel_id: "prices".into(), since: 20 }));
+        assert_eq!(frame.history_page(), None);
+        let page = HistoryPage { channel_id: "prices".into(), entries: history.since(20) };
+        frame.payload = serde_json::to_vec(&page).unwrap().into();
+        assert_eq!((frame.history_query(), frame.history_page()), (None, Some(page)));
+    }
+}
@@ -0,0 +1,540 @@
+//! JWT-authenticated relay connections
+//!
+//! A relay with a [`JwtAuthenticator`] only accepts agents whose `Connect`
+//! frame carries a JSON Web Token (in the payload's [`CONNECT_TOKEN_FIELD`])
+//! from one of its configured issuers, e.g. an enterprise's OpenID Connect
+//! provider. Tokens are checked against the issuer's JSON Web Key Set
+//! (`RS256`, `ES256` or `EdDSA`), its audience, and their `exp` and `nbf`
+//! times. Unsigned and HMAC tokens are refused.
+//!
+//! The `Connect` frame must also be recent and signed with the key the agent
+//! ID derives from, so a token only authenticates the agent holding that
+//! key. Once authenticated, an agent ID stays with its issuer and namespace
+//! until the token expires: tokens of other issuers or namespaces are
+//! refused for it.
+//!
+//! Claims map the agent to a namespace and a quota: agents only reach agents
+//! of their own namespace, and send at most their quota of frames a minute.
+//! Agents authenticated without a namespace claim share the unnamed
+//! namespace. The relay remembers the namespace of agents after they
+//! disconnect until their token expires, so frames for them are still
+//! queued; frames for agents that never authenticated, or whose token
+//! expired, are rejected. Agents reconnect with a fresh token before then.
+//! Entries of batches addressed to the relay count as frames to their own
+//! recipients.
+//!
+//! Key sets are given as JSON, as served on an issuer's `jwks_uri`; the
+//! application fetches them and replaces them with `set_jwks` when the
+//! issuer rotates its keys.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::signature::{RsaPublicKeyComponents, UnparsedPublicKey, ECDSA_P256_SHA256_FIXED, RSA_PKCS1_2048_8192_SHA256};
+use serde::{Deserialize, Serialize};
+use crate::crypto::{KeyManager, SecurityManager};
+use crate::error::{ErrorCode, ErrorPayload};
+use crate::types::{FrameType, OpacusFrame, CONNECT_TOKEN_FIELD};
+
+/// Allowed clock difference with issuers (seconds)
+const CLOCK_LEEWAY_SECS: u64 = 60;
+
+/// Public key of a JSON Web Key Set
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Jwk {
+    /// Key type: `RSA`, `EC` or `OKP`
+    pub kty: String,
+    /// Key ID, matched against the token's `kid`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+    /// Algorithm the key is restricted to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alg: Option<String>,
+    /// RSA modulus (base64url)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    /// RSA exponent (base64url)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    /// Curve: `P-256` or `Ed25519`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    /// EC x coordinate or Ed25519 public key (base64url)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    /// EC y coordinate (base64url)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+}
+
+impl Jwk {
+    fn param(&self, value: &Option<String>, name: &str) -> Result<Vec<u8>, String> {
+        let value = value.as_ref().ok_or_else(|| format!("JWK has no {}", name))?;
+        URL_SAFE_NO_PAD.decode(value).map_err(|e| format!("Invalid JWK {}: {}", name, e))
+    }
+
+    /// Whether the key can check signatures of `alg`
+    fn fits(&self, alg: &str) -> bool {
+        let kty = match alg {
+            "RS256" => "RSA",
+            "ES256" => "EC",
+            "EdDSA" => "OKP",
+            _ => return false,
+        };
+        self.kty == kty && self.alg.as_deref().is_none_or(|a| a == alg)
+    }
+
+    /// Verify a signature of `alg` with this key
+    fn verify(&self, alg: &str, message: &[u8], signature: &[u8]) -> Result<(), String> {
+        let valid = match alg {
+            "RS256" => RsaPublicKeyComponents { n: self.param(&self.n, "n")?, e: self.param(&self.e, "e")? }
+                .verify(&RSA_PKCS1_2048_8192_SHA256, message, signature)
+                .is_ok(),
+            "ES256" => {
+                if self.crv.as_deref() != Some("P-256") {
+                    return Err("ES256 key is not on P-256".into());
+                }
+                let mut point = vec![0x04];
+                point.extend(self.param(&self.x, "x")?);
+                point.extend(self.param(&self.y, "y")?);
+                UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, point).verify(message, signature).is_ok()
+            }
+            "EdDSA" => {
+                if self.crv.as_deref() != Some("Ed25519") {
+                    return Err("EdDSA key is not Ed25519".into());
+                }
+                let key: [u8; 32] = self.param(&self.x, "x")?.try_into().map_err(|_| "Invalid Ed25519 key length")?;
+                SecurityManager::verify(&key, message, signature)
+            }
+            other => return Err(format!("Unsupported JWT algorithm: {}", other)),
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err("Invalid JWT signature".into())
+        }
+    }
+}
+
+/// JSON Web Key Set of an issuer
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+impl Jwks {
+    /// Parse a key set as served on a `jwks_uri`
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| format!("Invalid JWKS: {}", e))
+    }
+}
+
+/// Frames an agent may send
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Quota {
+    /// Frames per minute
+    pub frames_per_minute: u64,
+}
+
+/// Issuer whose tokens a relay accepts, and how their claims map to agents
+#[derive(Debug, Clone)]
+pub struct JwtIssuer {
+    /// Expected `iss` claim
+    pub issuer: String,
+    /// Expected `aud` claim (`None` accepts any audience)
+    pub audience: Option<String>,
+    /// Issuer's signing keys
+    pub jwks: Jwks,
+    /// Claim holding the agent's namespace, e.g. `tenant`
+    pub namespace_claim: Option<String>,
+    /// Claim that must equal the agent ID, binding the token to one agent
+    pub agent_claim: Option<String>,
+    /// Claim selecting a quota from `quotas`, e.g. `tier`
+    pub quota_claim: Option<String>,
+    /// Quotas by value of the quota claim
+    pub quotas: HashMap<String, Quota>,
+    /// Quota of agents the quota claim selects none for (`None` is unlimited)
+    pub default_quota: Option<Quota>,
+}
+
+impl JwtIssuer {
+    /// Accept tokens of `issuer` signed with a key of `jwks`, without
+    /// namespaces or quotas
+    pub fn new(issuer: &str, jwks: Jwks) -> Self {
+        Self {
+            issuer: issuer.to_string(),
+            audience: None,
+            jwks,
+            namespace_claim: None,
+            agent_claim: None,
+            quota_claim: None,
+            quotas: HashMap::new(),
+            default_quota: None,
+        }
+    }
+
+    /// Require an audience
+    pub fn with_audience(mut self, audience: &str) -> Self {
+        self.audience = Some(audience.to_string());
+        self
+    }
+
+    /// Take the namespace from a claim
+    pub fn with_namespace_claim(mut self, claim: &str) -> Self {
+        self.namespace_claim = Some(claim.to_string());
+        self
+    }
+
+    /// Require a claim to equal the agent ID
+    pub fn with_agent_claim(mut self, claim: &str) -> Self {
+        self.agent_claim = Some(claim.to_string());
+        self
+    }
+
+    /// Select quotas by the value of a claim
+    pub fn with_quotas(mut self, claim: &str, quotas: HashMap<String, Quota>, default_quota: Option<Quota>) -> Self {
+        self.quota_claim = Some(claim.to_string());
+        self.quotas = quotas;
+        self.default_quota = default_quota;
+        self
+    }
+
+    /// Check a token's signature and claims
+    fn validate(&self, alg: &str, kid: Option<&str>, signed: &[u8], signature: &[u8], claims: &serde_json::Value, now_secs: u64) -> Result<(), String> {
+        let mut keys = self.jwks.keys.iter().filter(|k| k.fits(alg) && (kid.is_none() || k.kid.as_deref() == kid)).peekable();
+        if keys.peek().is_none() {
+            return Err(format!("No {} key {:?} for issuer {}", alg, kid, self.issuer));
+        }
+        if !keys.any(|key| key.verify(alg, signed, signature).is_ok()) {
+            return Err("Invalid JWT signature".into());
+        }
+        let exp = claims["exp"].as_u64().ok_or("JWT has no expiry")?;
+        if exp.saturating_add(CLOCK_LEEWAY_SECS) <= now_secs {
+            return Err("JWT has expired".into());
+        }
+        if claims["nbf"].as_u64().is_some_and(|nbf| nbf > now_secs.saturating_add(CLOCK_LEEWAY_SECS)) {
+            return Err("JWT is not valid yet".into());
+        }
+        if let Some(audience) = &self.audience {
+            let matches = match &claims["aud"] {
+                serde_json::Value::String(aud) => aud == audience,
+                serde_json::Value::Array(auds) => auds.iter().any(|a| a.as_str() == Some(audience.as_str())),
+                _ => false,
+            };
+            if !matches {
+                return Err(format!("JWT is not for audience {}", audience));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Agent authenticated by a token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthenticatedAgent {
+    /// Token issuer
+    pub issuer: String,
+    /// Token subject
+    pub subject: String,
+    /// Namespace mapped from the claims (`None` for the unnamed namespace)
+    pub namespace: Option<String>,
+    /// Quota mapped from the claims (`None` is unlimited)
+    pub quota: Option<Quota>,
+    /// Time the token expires, with the allowed clock difference (milliseconds)
+    pub expires_ms: u64,
+}
+
+/// Frames sent in the current minute
+#[derive(Debug, Clone, Copy)]
+struct QuotaWindow {
+    start_ms: u64,
+    frames: u64,
+}
+
+/// Authenticates relay connections by JWT and applies their namespaces and quotas
+#[derive(Debug, Default)]
+pub struct JwtAuthenticator {
+    issuers: Mutex<HashMap<String, JwtIssuer>>,
+    /// Agents authenticated so far, kept after they disconnect until their token expires
+    agents: Mutex<HashMap<String, AuthenticatedAgent>>,
+    windows: Mutex<HashMap<String, QuotaWindow>>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl JwtAuthenticator {
+    /// Create authenticator without issuers
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept tokens of an issuer (replacing its previous settings)
+    pub fn with_issuer(self, issuer: JwtIssuer) -> Self {
+        self.add_issuer(issuer);
+        self
+    }
+
+    /// Accept tokens of an issuer (replacing its previous settings)
+    pub fn add_issuer(&self, issuer: JwtIssuer) {
+        lock(&self.issuers).insert(issuer.issuer.clone(), issuer);
+    }
+
+    /// Replace an issuer's keys, e.g. after fetching its rotated key set
+    pub fn set_jwks(&self, issuer: &str, jwks: Jwks) -> Result<(), String> {
+        let mut issuers = lock(&self.issuers);
+        let issuer = issuers.get_mut(issuer).ok_or_else(|| format!("Unknown issuer {}", issuer))?;
+        issuer.jwks = jwks;
+        Ok(())
+    }
+
+    /// Validate a token for an agent
+    ///
+    /// # Arguments
+    /// * `token` - Compact JWS
+    /// * `agent_id` - Agent connecting with it
+    /// * `now_ms` - Current time (milliseconds)
+    pub fn authenticate(&self, token: &str, agent_id: &str, now_ms: u64) -> Result<AuthenticatedAgent, String> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return Err("Malformed JWT".into());
+        };
+        let decode = |part: &str| URL_SAFE_NO_PAD.decode(part).map_err(|e| format!("Malformed JWT: {}", e));
+        let header: serde_json::Value = serde_json::from_slice(&decode(header)?).map_err(|e| format!("Malformed JWT header: {}", e))?;
+        let claims: serde_json::Value = serde_json::from_slice(&decode(payload)?).map_err(|e| format!("Malformed JWT claims: {}", e))?;
+        let alg = header["alg"].as_str().ok_or("JWT header has no algorithm")?;
+        let iss = claims["iss"].as_str().ok_or("JWT has no issuer")?;
+
+        let issuers = lock(&self.issuers);
+        let issuer = issuers.get(iss).ok_or_else(|| format!("Untrusted JWT issuer {}", iss))?;
+        let signed = &token[..token.len() - signature.len() - 1];
+        issuer.validate(alg, header["kid"].as_str(), signed.as_bytes(), &decode(signature)?, &claims, now_ms / 1000)?;
+
+        let claim = |name: &Option<String>| name.as_ref().and_then(|n| claims[n.as_str()].as_str());
+        if issuer.agent_claim.is_some() && claim(&issuer.agent_claim) != Some(agent_id) {
+            return Err(format!("JWT is not for agent {}", agent_id));
+        }
+        let quota = match claim(&issuer.quota_claim) {
+            Some(value) => issuer.quotas.get(value).copied().or(issuer.default_quota),
+            None => issuer.default_quota,
+        };
+        let exp = claims["exp"].as_u64().unwrap_or_default();
+        let agent = AuthenticatedAgent {
+            issuer: iss.to_string(),
+            subject: claims["sub"].as_str().unwrap_or_default().to_string(),
+            namespace: claim(&issuer.namespace_claim).map(String::from),
+            quota,
+            expires_ms: exp.saturating_add(CLOCK_LEEWAY_SECS).saturating_mul(1000),
+        };
+        drop(issuers);
+
+        let mut agents = lock(&self.agents);
+        agents.retain(|_, agent| agent.expires_ms > now_ms);
+        lock(&self.windows).retain(|id, window| agents.contains_key(id) && now_ms < window.start_ms.saturating_add(60_000));
+        if let Some(known) = agents.get(agent_id) {
+            if known.issuer != agent.issuer || known.namespace != agent.namespace {
+                return Err(format!("Agent {} is authenticated in another namespace", agent_id));
+            }
+        }
+        agents.insert(agent_id.to_string(), agent.clone());
+        Ok(agent)
+    }
+
+    /// Authenticate the agent of a `Connect` frame by the token in its payload
+    ///
+    /// The frame must be signed with the key its agent ID derives from
+    /// ([`SecurityManager::connect_sign_data`]) and sent within the allowed
+    /// clock difference.
+    pub fn authenticate_connect(&self, frame: &OpacusFrame, now_ms: u64) -> Result<AuthenticatedAgent, ErrorPayload> {
+        let unauthorized = |reason: &str| ErrorPayload::new(ErrorCode::Unauthorized, reason).related_to(frame.id);
+        let payload: serde_json::Value = serde_json::from_slice(&frame.payload).unwrap_or_default();
+        let token = payload[CONNECT_TOKEN_FIELD].as_str().ok_or_else(|| unauthorized("Connect frame carries no JWT"))?;
+        let ed_pub = crate::replies::connect_ed_pub(frame).ok_or_else(|| unauthorized("Connect frame carries no key"))?;
+        if KeyManager::agent_id(&ed_pub) != frame.from {
+            return Err(unauthorized("Connect key does not match the agent ID"));
+        }
+        let signed = frame.sig.as_ref().is_some_and(|sig| {
+            SecurityManager::verify(&ed_pub, &SecurityManager::connect_sign_data(frame), sig)
+        });
+        if !signed {
+            return Err(unauthorized("Connect frame is not signed by its agent"));
+        }
+        if frame.ts.abs_diff(now_ms) > CLOCK_LEEWAY_SECS * 1000 {
+            return Err(unauthorized("Connect frame is stale"));
+        }
+        self.authenticate(token, &frame.from, now_ms).map_err(|reason| unauthorized(&reason))
+    }
+
+    /// Agent authenticated under an ID
+    pub fn agent(&self, agent_id: &str) -> Option<AuthenticatedAgent> {
+        lock(&self.agents).get(agent_id).cloned()
+    }
+
+    /// Check a frame sent by an authenticated agent may be routed
+    ///
+    /// Counts the frame against the sender's quota.
+    ///
+    /// # Arguments
+    /// * `sender` - Agent of the connection the frame arrived on
+    /// * `frame` - Frame to route
+    /// * `now_ms` - Current time (milliseconds)
+    pub fn admit(&self, sender: &str, frame: &OpacusFrame, now_ms: u64) -> Result<(), ErrorPayload> {
+        let agents = lock(&self.agents);
+        let reject = |code, reason: String| Err(ErrorPayload::new(code, reason).related_to(frame.id));
+        let Some(agent) = agents.get(sender).filter(|agent| agent.expires_ms > now_ms) else {
+            return reject(ErrorCode::Unauthorized, format!("{} has not authenticated", sender));
+        };
+        // Batches to the relay are split per recipient, so each entry's recipient counts
+        let recipients = match (frame.frame_type, frame.to.as_str()) {
+            (FrameType::Batch, "relay") => match frame.batch_entries() {
+                Ok(entries) => entries.into_iter().map(|e| e.to).collect(),
+                Err(e) => return reject(ErrorCode::Unknown, e.to_string()),
+            },
+            (_, "relay") => Vec::new(),
+            _ => vec![frame.to.clone()],
+        };
+        if let Some(to) = recipients.iter().find(|to| agents.get(*to).is_none_or(|r| r.namespace != agent.namespace || r.expires_ms <= now_ms)) {
+            return reject(ErrorCode::UnknownRecipient, format!("No agent {} in the namespace of {}", to, sender));
+        }
+        let Some(quota) = agent.quota else { return Ok(()) };
+        let mut windows = lock(&self.windows);
+        let window = windows.entry(sender.to_string()).or_insert(QuotaWindow { start_ms: now_ms, frames: 0 });
+        if now_ms >= window.start_ms.saturating_add(60_000) {
+            *window = QuotaWindow { start_ms: now_ms, frames: 0 };
+        }
+        if window.frames >= quota.frames_per_minute {
+            return Err(ErrorPayload::new(ErrorCode::RateLimited, format!("{} exceeded its quota", sender))
+                .with_retry_after(Duration::from_millis(window.start_ms + 60_000 - now_ms))
+                .related_to(frame.id));
+        }
+        window.frames += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+    use crate::crypto::KeyManager;
+
+    fn b64(bytes: &[u8]) -> String {
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    fn token(alg: &str, claims: serde_json::Value, sign: impl Fn(&[u8]) -> Vec<u8>) -> String {
+        let signed = format!("{}.{}", b64(format!(r#"{{"alg":"{}","kid":"k1"}}"#, alg).as_bytes()), b64(claims.to_string().as_bytes()));
+        let signature = sign(signed.as_bytes());
+        format!("{}.{}", signed, b64(&signature))
+    }
+
+    fn frame(from: &str, to: &str) -> OpacusFrame {
+        OpacusFrame { from: from.to_string(), to: to.to_string(), ..OpacusFrame::test(0) }
+    }
+
+    #[test]
+    fn test_jwt_authentication() {
+        // ES256 from an OIDC provider, EdDSA from another
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let ec = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng).unwrap();
+        let point = ec.public_key().as_ref();
+        let idp = Jwks::from_json(
+            &serde_json::json!({ "keys": [
+                { "kty": "EC", "kid": "k1", "crv": "P-256", "x": b64(&point[1..33]), "y": b64(&point[33..]) }
+            ] })
+            .to_string(),
+        )
+        .unwrap();
+        let ed = KeyManager::generate_identity(1);
+        let partner = Jwks { keys: vec![Jwk { kty: "OKP".into(), crv: Some("Ed25519".into()), x: Some(b64(&ed.ed_pub)), ..idp.keys[0].clone() }] };
+
+        let quotas = HashMap::from([("free".to_string(), Quota { frames_per_minute: 2 })]);
+        let auth = JwtAuthenticator::new()
+            .with_issuer(
+                JwtIssuer::new("https://idp.example", idp)
+                    .with_audience("opacus-relay")
+                    .with_namespace_claim("tenant")
+                    .with_agent_claim("agent")
+                    .with_quotas("tier", quotas, None),
+            )
+            .with_issuer(JwtIssuer::new("https://partner.example", partner).with_namespace_claim("tenant"));
+        let es256 = |claims| token("ES256", claims, |data| ec.sign(&rng, data).unwrap().as_ref().to_vec());
+        let eddsa = |claims| token("EdDSA", claims, |data| SecurityManager::sign(&ed.ed_priv, data));
+        let now = 1_700_000_000_000;
+        let claims = |agent: &str, tenant: &str, tier: &str| {
+            serde_json::json!({ "iss": "https://idp.example", "sub": agent, "aud": ["opacus-relay"], "exp": now / 1000 + 600,
+                "tenant": tenant, "agent": agent, "tier": tier })
+        };
+
+        let alice = auth.authenticate(&es256(claims("alice", "acme", "free")), "alice", now).unwrap();
+        assert_eq!((alice.namespace.as_deref(), alice.quota), (Some("acme"), Some(Quota { frames_per_minute: 2 })));
+        auth.authenticate(&es256(claims("bob", "acme", "pro")), "bob", now).unwrap();
+        let carol = serde_json::json!({ "iss": "https://partner.example", "sub": "carol", "exp": now / 1000 + 600, "tenant": "globex" });
+        assert_eq!(auth.authenticate(&eddsa(carol), "carol", now).unwrap().quota, None);
+
+        // Rejected tokens
+        assert_eq!(auth.authenticate(&es256(claims("alice", "acme", "free")), "mallory", now).unwrap_err(), "JWT is not for agent mallory");
+        assert_eq!(auth.authenticate(&es256(claims("bob", "acme", "pro")), "bob", now + 700_000).unwrap_err(), "JWT has expired");
+        let mut wrong_audience = claims("bob", "acme", "pro");
+        wrong_audience["aud"] = "other".into();
+        assert!(auth.authenticate(&es256(wrong_audience), "bob", now).is_err());
+        let forged = eddsa(claims("bob", "acme", "pro"));
+        assert!(auth.authenticate(&forged, "bob", now).unwrap_err().starts_with("No EdDSA key"));
+        let unsigned = format!("{}.{}.", b64(br#"{"alg":"none"}"#), b64(claims("bob", "acme", "pro").to_string().as_bytes()));
+        assert!(auth.authenticate(&unsigned, "bob", now).is_err());
+        let mut connect = frame("dave", "relay");
+        connect.frame_type = FrameType::Connect;
+        assert_eq!(auth.authenticate_connect(&connect, now).unwrap_err().code, ErrorCode::Unauthorized);
+
+        // Connect frames prove the agent holds its key
+        let dave = KeyManager::generate_identity(2);
+        let dave_id = KeyManager::agent_id(&dave.ed_pub);
+        connect.from = dave_id.clone();
+        connect.ts = now;
+        connect.payload = serde_json::to_vec(&serde_json::json!({
+            "edPub": KeyManager::to_hex(&dave.ed_pub), "jwt": es256(claims(&dave_id, "acme", "pro")),
+        }))
+        .unwrap()
+        .into();
+        let sign = |frame: &mut OpacusFrame, key: &[u8; 32]| {
+            frame.sig = Some(SecurityManager::sign(key, &SecurityManager::connect_sign_data(frame)));
+        };
+        sign(&mut connect, &ed.ed_priv);
+        assert_eq!(auth.authenticate_connect(&connect, now).unwrap_err().message, "Connect frame is not signed by its agent");
+        sign(&mut connect, &dave.ed_priv);
+        assert_eq!(auth.authenticate_connect(&connect, now + 120_000).unwrap_err().message, "Connect frame is stale");
+        assert_eq!(auth.authenticate_connect(&connect, now).unwrap().namespace.as_deref(), Some("acme"));
+
+        // An agent ID stays in its namespace, even with long-lived tokens
+        assert!(auth.authenticate(&es256(claims("alice", "globex", "free")), "alice", now).unwrap_err().contains("another namespace"));
+        let mut forever = claims("erin", "acme", "pro");
+        forever["exp"] = u64::MAX.into();
+        assert_eq!(auth.authenticate(&es256(forever), "erin", now).unwrap().expires_ms, u64::MAX);
+
+        // Namespaces and quotas
+        auth.admit("alice", &frame("alice", "bob"), now).unwrap();
+        auth.admit("alice", &frame("alice", "relay"), now).unwrap();
+        let limited = auth.admit("alice", &frame("alice", "bob"), now + 1_000).unwrap_err();
+        assert_eq!((limited.code, limited.retry_after_ms), (ErrorCode::RateLimited, Some(59_000)));
+        auth.admit("alice", &frame("alice", "bob"), now + 60_000).unwrap();
+        assert_eq!(auth.admit("bob", &frame("bob", "carol"), now).unwrap_err().code, ErrorCode::UnknownRecipient);
+        assert_eq!(auth.admit("bob", &frame("bob", "nobody"), now).unwrap_err().code, ErrorCode::UnknownRecipient);
+        assert_eq!(auth.admit("dave", &frame("dave", "bob"), now).unwrap_err().code, ErrorCode::Unauthorized);
+
+        // Agents are forgotten once their token expires
+        let later = now + 700_000;
+        assert_eq!(auth.admit("erin", &frame("erin", "bob"), later).unwrap_err().code, ErrorCode::UnknownRecipient);
+        auth.authenticate(&es256(claims("bob", "acme", "pro")), "bob", now).unwrap();
+        let fresh = serde_json::json!({ "iss": "https://idp.example", "sub": "alice", "aud": "opacus-relay", "exp": later / 1000 + 600,
+            "tenant": "globex", "agent": "alice", "tier": "free" });
+        auth.authenticate(&es256(fresh), "alice", later).unwrap();
+        assert!(auth.agent("bob").is_none());
+        assert!(lock(&auth.windows).keys().all(|id| id == "alice"));
+    }
+}
@@ -0,0 +1,255 @@
+//! Latency measurement
+//!
+//! [`OpacusClient::ping`](crate::OpacusClient::ping) measures the round trip
+//! to an agent through the relay with a `Ping` frame that the peer's client
+//! answers automatically (the relay answers pings addressed to `"relay"`).
+//! Every received frame also yields a passive one-way estimate: receive time
+//! minus the sender's timestamp. One-way estimates include the offset
+//! between the two clocks and any time the frame was queued for an offline
+//! recipient.
+//!
+//! Both are kept per peer in [`LatencyHistogram`]s with fixed buckets, so
+//! they can be exported as-is for SLO tracking.
+
+use std::collections::HashMap;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+/// Upper bounds of the histogram buckets in microseconds (a last,
+/// unbounded bucket holds everything slower)
+pub const LATENCY_BUCKETS_US: [u64; 16] = [
+    100, 250, 500,
+    1_000, 2_500, 5_000,
+    10_000, 25_000, 50_000,
+    100_000, 250_000, 500_000,
+    1_000_000, 2_500_000, 5_000_000,
+    10_000_000,
+];
+
+/// Payload of `Ping` frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PingPayload {
+    /// Matches a reply to its ping
+    pub nonce: u64,
+    /// Whether this answers a ping
+    #[serde(default)]
+    pub reply: bool,
+}
+
+/// Latency distribution over fixed buckets
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencyHistogram {
+    /// Samples per bucket of `LATENCY_BUCKETS_US`, then the overflow bucket
+    counts: Vec<u64>,
+    count: u64,
+    sum_us: u64,
+    min_us: u64,
+    max_us: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; LATENCY_BUCKETS_US.len() + 1],
+            count: 0,
+            sum_us: 0,
+            min_us: u64::MAX,
+            max_us: 0,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    /// Create empty histogram
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a sample
+    pub fn record(&mut self, latency: Duration) {
+        let us = latency.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = LATENCY_BUCKETS_US.partition_point(|&bound| bound < us);
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.sum_us = self.sum_us.saturating_add(us);
+        self.min_us = self.min_us.min(us);
+        self.max_us = self.max_us.max(us);
+    }
+
+    /// Add another histogram's samples
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        self.count += other.count;
+        self.sum_us = self.sum_us.saturating_add(other.sum_us);
+        self.min_us = self.min_us.min(other.min_us);
+        self.max_us = self.max_us.max(other.max_us);
+    }
+
+    /// Number of samples
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Average latency
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0).then(|| Duration::from_micros(self.sum_us / self.count))
+    }
+
+    /// Lowest latency
+    pub fn min(&self) -> Option<Duration> {
+        (self.count > 0).then(|| Duration::from_micros(self.min_us))
+    }
+
+    /// Highest latency
+    pub fn max(&self) -> Option<Duration> {
+        (self.count > 0).then(|| Duration::from_micros(self.max_us))
+    }
+
+    /// Latency below which a fraction `q` (0 to 1) of the samples fall
+    ///
+    /// Estimated as the upper bound of the bucket holding that sample,
+    /// capped at the highest latency seen.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let bound = LATENCY_BUCKETS_US.get(bucket).copied().unwrap_or(u64::MAX);
+                return Some(Duration::from_micros(bound.min(self.max_us)));
+            }
+        }
+        self.max()
+    }
+
+    /// Upper bound (`None` for the overflow bucket) and sample count of each bucket
+    pub fn buckets(&self) -> Vec<(Option<Duration>, u64)> {
+        self.counts
+            .iter()
+            .enumerate()
+            .map(|(bucket, &count)| (LATENCY_BUCKETS_US.get(bucket).map(|&us| Duration::from_micros(us)), count))
+            .collect()
+    }
+}
+
+/// Round-trip and one-way latency histograms per peer
+#[derive(Debug, Clone, Default)]
+pub struct LatencyTracker {
+    rtt: HashMap<String, LatencyHistogram>,
+    one_way: HashMap<String, LatencyHistogram>,
+}
+
+impl LatencyTracker {
+    /// Create empty tracker
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a measured round trip to a peer
+    pub fn record_rtt(&mut self, peer: &str, rtt: Duration) {
+        self.rtt.entry(peer.to_string()).or_default().record(rtt);
+    }
+
+    /// Record a one-way estimate from a frame's timestamp
+    ///
+    /// # Arguments
+    /// * `peer` - Sender of the frame
+    /// * `sent_ms` - Frame timestamp (milliseconds since the Unix epoch)
+    /// * `received_ms` - Local receive time
+    pub fn record_one_way(&mut self, peer: &str, sent_ms: u64, received_ms: u64) {
+        // A sender clock ahead of ours shows up as zero latency
+        let latency = Duration::from_millis(received_ms.saturating_sub(sent_ms));
+        self.one_way.entry(peer.to_string()).or_default().record(latency);
+    }
+
+    /// Round trips to a peer
+    pub fn rtt(&self, peer: &str) -> Option<&LatencyHistogram> {
+        self.rtt.get(peer)
+    }
+
+    /// One-way estimates for frames from a peer
+    pub fn one_way(&self, peer: &str) -> Option<&LatencyHistogram> {
+        self.one_way.get(peer)
+    }
+
+    /// Round trips to all peers
+    pub fn total_rtt(&self) -> LatencyHistogram {
+        Self::total(&self.rtt)
+    }
+
+    /// One-way estimates for frames from all peers
+    pub fn total_one_way(&self) -> LatencyHistogram {
+        Self::total(&self.one_way)
+    }
+
+    /// Peers with any recorded latency
+    pub fn peers(&self) -> Vec<String> {
+        let mut peers: Vec<String> = self.rtt.keys().chain(self.one_way.keys()).cloned().collect();
+        peers.sort();
+        peers.dedup();
+        peers
+    }
+
+    fn total(histograms: &HashMap<String, LatencyHistogram>) -> LatencyHistogram {
+        let mut total = LatencyHistogram::new();
+        for histogram in histograms.values() {
+            total.merge(histogram);
+        }
+        total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = LatencyHistogram::new();
+        assert_eq!((histogram.count(), histogram.mean(), histogram.quantile(0.5)), (0, None, None));
+
+        for ms in [1, 2, 3, 4, 40] {
+            histogram.record(Duration::from_millis(ms));
+        }
+        assert_eq!(histogram.count(), 5);
+        assert_eq!(histogram.mean(), Some(Duration::from_millis(10)));
+        assert_eq!(histogram.min(), Some(Duration::from_millis(1)));
+        assert_eq!(histogram.max(), Some(Duration::from_millis(40)));
+        assert_eq!(histogram.quantile(0.2), Some(Duration::from_millis(1)));
+        assert_eq!(histogram.quantile(0.5), Some(Duration::from_micros(5_000)));
+        assert_eq!(histogram.quantile(0.99), Some(Duration::from_millis(40)));
+
+        histogram.record(Duration::from_secs(60));
+        let buckets = histogram.buckets();
+        assert_eq!(buckets.len(), LATENCY_BUCKETS_US.len() + 1);
+        assert_eq!(buckets.last(), Some(&(None, 1)));
+        assert_eq!(buckets.iter().map(|(_, count)| count).sum::<u64>(), 6);
+        assert_eq!(histogram.quantile(1.0), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_tracker() {
+        let mut tracker = LatencyTracker::new();
+        tracker.record_rtt("bob", Duration::from_millis(8));
+        tracker.record_rtt("carol", Duration::from_millis(2));
+        tracker.record_one_way("bob", 1_000, 1_004);
+        tracker.record_one_way("bob", 1_010, 1_000);
+
+        assert_eq!(tracker.rtt("bob").unwrap().count(), 1);
+        let one_way = tracker.one_way("bob").unwrap();
+        assert_eq!((one_way.min(), one_way.max()), (Some(Duration::ZERO), Some(Duration::from_millis(4))));
+        assert_eq!(tracker.total_rtt().mean(), Some(Duration::from_millis(5)));
+        assert_eq!(tracker.total_one_way().count(), 2);
+        assert_eq!(tracker.peers(), vec!["bob", "carol"]);
+
+        let ping: PingPayload = serde_json::from_str(r#"{"nonce":7}"#).unwrap();
+        assert_eq!(ping, PingPayload { nonce: 7, reply: false });
+    }
+}
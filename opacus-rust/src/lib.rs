@@ -7,16 +7,24 @@
 //! - **QUIC Transport**: HTTP/3 ready with Quinn
 //! - **Ed25519 + X25519**: Dual-key cryptography
 //! - **CBOR Framing**: Efficient binary serialization
-//! - **Multi-Chain**: 0G Chain first, EVM compatible
+//! - **Compression**: Optional zstd/lz4 frame payloads
+//! - **QoS**: Frame priorities with congestion-aware dropping
+//! - **Multi-Chain**: 0G Chain first, EVM compatible (`chain` feature: JSON-RPC client)
+//! - **Browser**: wasm32 build over WebTransport (`wasm` feature, without `native`)
+//! - **Runtime-Agnostic Core**: protocol, crypto and codecs run on any executor;
+//!   tokio and QUIC only come with the `client` and `relay` features (both in
+//!   the default `native`)
 //! - **Type-Safe**: Full Rust type safety
 //! 
 //! ## Example
 //! 
 //! ```rust,no_run
-//! use opacus_sdk::{OpacusClient, OpacusConfig, Network};
-//! 
+//! # #[cfg(not(feature = "client"))] fn main() {}
+//! # #[cfg(feature = "client")]
 //! #[tokio::main]
 //! async fn main() -> anyhow::Result<()> {
+//!     use opacus_sdk::{OpacusClient, OpacusConfig, Network};
+//!     
 //!     let config = OpacusConfig {
 //!         network: Network::Testnet,
 //!         relay_url: "quic://relay.opacus.io:4242".to_string(),
@@ -33,15 +41,127 @@
 //! ```
 
 pub mod types;
+pub mod error;
+pub mod clock;
 pub mod crypto;
 pub mod proto;
+pub mod batch;
+pub mod capture;
+#[cfg(feature = "relay")]
+pub mod health;
+#[cfg(feature = "relay")]
+pub mod events;
+#[cfg(feature = "relay")]
+pub mod admin;
+#[cfg(feature = "relay")]
+pub mod jwt;
+pub mod compression;
+pub mod content;
+pub mod qos;
+pub mod random;
+pub mod metering;
+pub mod latency;
+pub mod lifecycle;
+#[cfg(feature = "client")]
+pub mod sim;
+pub mod reputation;
+pub mod credential;
+pub mod token;
+pub mod offload;
+pub mod subscription;
+pub mod topic;
+pub mod history;
+pub mod retain;
+pub mod crdt;
+pub mod task;
+pub mod sealed;
+pub mod onion;
+pub mod padding;
+pub mod gossip;
+pub mod dht;
+pub mod trace;
+pub mod rpc;
+pub mod capabilities;
+pub mod profile;
+pub mod redact;
+pub mod config;
+#[cfg(any(feature = "client", feature = "relay"))]
+pub mod replies;
+pub mod conformance;
 pub mod transport;
+#[cfg(feature = "client")]
 pub mod client;
+#[cfg(feature = "client")]
+pub mod node;
+#[cfg(feature = "relay")]
 pub mod relay;
+#[cfg(feature = "chain")]
+pub mod chain;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+#[cfg(feature = "h3dac-bridge")]
+pub mod bridge;
+#[cfg(all(feature = "wasm", web_sys_unstable_apis))]
+pub mod wasm;
+#[cfg(all(feature = "wasm", target_arch = "wasm32", not(web_sys_unstable_apis)))]
+compile_error!("The `wasm` feature needs `--cfg=web_sys_unstable_apis` (set in .cargo/config.toml)");
 
 pub use types::*;
+pub use error::*;
+pub use clock::*;
 pub use crypto::*;
 pub use proto::*;
+pub use batch::*;
+pub use capture::*;
+#[cfg(feature = "relay")]
+pub use health::*;
+#[cfg(feature = "relay")]
+pub use events::*;
+#[cfg(feature = "relay")]
+pub use jwt::*;
+pub use compression::*;
+pub use content::*;
+pub use qos::*;
+pub use random::*;
+pub use metering::*;
+pub use latency::*;
+pub use lifecycle::*;
+#[cfg(feature = "client")]
+pub use sim::*;
+pub use reputation::*;
+pub use credential::*;
+pub use token::*;
+pub use offload::*;
+pub use subscription::*;
+pub use topic::*;
+pub use history::*;
+pub use retain::*;
+pub use crdt::*;
+pub use task::*;
+pub use sealed::*;
+pub use onion::*;
+pub use padding::*;
+pub use gossip::*;
+pub use dht::*;
+pub use trace::*;
+pub use rpc::*;
+pub use capabilities::*;
+pub use profile::*;
+pub use redact::*;
+pub use config::*;
+#[cfg(any(feature = "client", feature = "relay"))]
+pub use replies::*;
+pub use conformance::*;
 pub use transport::*;
+#[cfg(feature = "client")]
 pub use client::*;
+#[cfg(feature = "client")]
+pub use node::*;
+#[cfg(feature = "relay")]
 pub use relay::*;
+#[cfg(feature = "chain")]
+pub use chain::*;
+#[cfg(feature = "h3dac-bridge")]
+pub use bridge::*;
+#[cfg(all(feature = "wasm", web_sys_unstable_apis))]
+pub use wasm::*;
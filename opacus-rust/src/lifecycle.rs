@@ -0,0 +1,194 @@
+//! Frame lifecycle log
+//!
+//! A [`LifecycleLog`] records what becomes of the frames a client sends and
+//! receives as JSON lines, one [`LifecycleEvent`] per stage, keyed by message
+//! ID, for audit and debugging pipelines:
+//!
+//! * `enqueued` - placed in the send queue
+//! * `sent` - written to the relay connection
+//! * `acked` - confirmed by the relay (`Ack` of a `Connect`), by a ping reply,
+//!   or, with the `chain` feature, by the recipient's delivery receipt
+//! * `delivered` - received and returned by `recv`
+//! * `failed` - dropped from the send queue, not written, or rejected with an
+//!   `Error` frame
+//!
+//! Events carry frame metadata only, never payloads.
+
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use crate::clock::{Clock, SystemClock};
+use crate::error::{ErrorCode, ErrorPayload};
+use crate::types::{FrameType, OpacusFrame, Ulid};
+
+/// Stage of a frame's lifecycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameStage {
+    Enqueued,
+    Sent,
+    Acked,
+    Delivered,
+    Failed,
+}
+
+/// One lifecycle event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LifecycleEvent {
+    /// When it happened (milliseconds since the Unix epoch)
+    pub ts: u64,
+    pub event: FrameStage,
+    /// Message ID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Ulid>,
+    /// Type of the frame, if the event was raised by the frame itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frame_type: Option<FrameType>,
+    /// Recipient of a sent frame, sender of a delivered one, or the agent
+    /// that acknowledged or rejected a frame
+    pub peer: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<ErrorCode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl LifecycleEvent {
+    /// Event for a frame at a stage (timestamped when recorded)
+    pub fn new(event: FrameStage, frame: &OpacusFrame) -> Self {
+        let peer = match event {
+            FrameStage::Delivered => &frame.from,
+            _ => &frame.to,
+        };
+        Self {
+            ts: 0,
+            event,
+            id: frame.id,
+            frame_type: Some(frame.frame_type),
+            peer: peer.clone(),
+            code: None,
+            reason: None,
+        }
+    }
+
+    /// A sent frame was dropped or could not be written
+    pub fn failed(frame: &OpacusFrame, reason: impl Into<String>) -> Self {
+        Self { reason: Some(reason.into()), ..Self::new(FrameStage::Failed, frame) }
+    }
+
+    /// A peer or the relay acknowledged the message `id`
+    pub fn acked(id: Ulid, peer: &str) -> Self {
+        Self {
+            ts: 0,
+            event: FrameStage::Acked,
+            id: Some(id),
+            frame_type: None,
+            peer: peer.to_string(),
+            code: None,
+            reason: None,
+        }
+    }
+
+    /// A peer or the relay rejected the message `id`
+    pub fn rejected(id: Ulid, peer: &str, error: &ErrorPayload) -> Self {
+        Self {
+            event: FrameStage::Failed,
+            code: Some(error.code),
+            reason: Some(error.message.clone()),
+            ..Self::acked(id, peer)
+        }
+    }
+}
+
+/// Writes lifecycle events as JSON lines
+pub struct LifecycleLog {
+    writer: Mutex<Box<dyn Write + Send>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl LifecycleLog {
+    /// Log to a file, appending to any existing one
+    ///
+    /// Each event is flushed as it is written.
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::new(std::io::LineWriter::new(file)))
+    }
+
+    /// Log to any writer
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            writer: Mutex::new(Box::new(writer)),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Use a custom time source for event times
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Record an event; write errors are logged, not returned
+    pub fn record(&self, mut event: LifecycleEvent) {
+        event.ts = self.clock.now_ms();
+        let Ok(line) = serde_json::to_string(&event) else { return };
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = writeln!(writer, "{}", line) {
+            warn!("Lifecycle log failed: {}", e);
+        }
+    }
+
+    /// Flush buffered events
+    pub fn flush(&self) -> std::io::Result<()> {
+        self.writer.lock().unwrap_or_else(|e| e.into_inner()).flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(data)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_lifecycle_log() {
+        let buffer = Buffer::default();
+        let log = LifecycleLog::new(buffer.clone());
+        let error = ErrorPayload::new(ErrorCode::Unavailable, "down");
+        let mut frame = error.to_frame("alice", "bob", 1);
+        let id = OpacusFrame::new_id(1);
+        frame.id = Some(id);
+
+        log.record(LifecycleEvent::new(FrameStage::Sent, &frame));
+        log.record(LifecycleEvent::new(FrameStage::Delivered, &frame));
+        log.record(LifecycleEvent::failed(&frame, "send queue full"));
+        log.record(LifecycleEvent::rejected(id, "relay", &error));
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let events: Vec<LifecycleEvent> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(events.len(), 4);
+        assert!(events.iter().all(|event| event.id == Some(id) && event.ts > 0));
+        assert_eq!((events[0].peer.as_str(), events[1].peer.as_str()), ("bob", "alice"));
+        assert_eq!(events[2].reason.as_deref(), Some("send queue full"));
+        assert_eq!((events[3].frame_type, events[3].code), (None, Some(ErrorCode::Unavailable)));
+
+        let json: serde_json::Value = serde_json::from_str(output.lines().next().unwrap()).unwrap();
+        assert_eq!((json["event"].as_str(), json["frameType"].as_str()), (Some("sent"), Some("error")));
+        assert!(json.get("reason").is_none() && json.get("payload").is_none());
+    }
+}
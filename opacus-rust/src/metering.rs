@@ -0,0 +1,244 @@
+//! Usage metering for priced data channels
+//!
+//! Both ends of a channel count the `Stream` frames sent on it and their
+//! payload bytes, priced with the channel's [`DataChannel`] rates. The
+//! provider issues signed [`UsageStatement`]s with the running totals; the
+//! consumer checks them against its own meter and pays what is due, e.g.
+//! through a payment channel.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use serde::{Deserialize, Serialize};
+use crate::crypto::{KeyManager, SecurityManager};
+use crate::types::{AgentIdentity, DataChannel, FrameType, OpacusFrame};
+
+/// Domain separator of usage statement signatures
+const STATEMENT_CONTEXT: &str = "opacus-usage-v1";
+
+/// Message and byte counts and what they cost
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Usage {
+    /// Messages
+    pub messages: u64,
+    /// Payload bytes
+    pub bytes: u64,
+    /// Price of the messages and bytes
+    pub cost: u128,
+}
+
+impl Usage {
+    fn add(&mut self, other: &Usage) {
+        self.messages += other.messages;
+        self.bytes += other.bytes;
+        self.cost += other.cost;
+    }
+}
+
+/// Usage counters per channel and peer
+///
+/// Only channels with pricing set are metered.
+#[derive(Debug, Default)]
+pub struct UsageMeter {
+    pricing: Mutex<HashMap<String, DataChannel>>,
+    usage: Mutex<HashMap<(String, String), Usage>>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl UsageMeter {
+    /// Create empty meter
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Meter a channel at its prices
+    ///
+    /// New prices apply to later messages; recorded usage is kept.
+    pub fn set_pricing(&self, channel: &DataChannel) {
+        lock(&self.pricing).insert(channel.id.clone(), channel.clone());
+    }
+
+    /// Whether a channel is metered
+    pub fn is_metered(&self, channel_id: &str) -> bool {
+        lock(&self.pricing).contains_key(channel_id)
+    }
+
+    /// Record one message
+    ///
+    /// # Arguments
+    /// * `channel_id` - Channel the message was sent on
+    /// * `peer` - Other party (recipient of sent, sender of received messages)
+    /// * `bytes` - Payload length
+    ///
+    /// # Returns
+    /// Price of the message, or `None` if the channel is not metered
+    pub fn record(&self, channel_id: &str, peer: &str, bytes: usize) -> Option<u128> {
+        let price = lock(&self.pricing).get(channel_id)?.price(bytes);
+        let mut usage = lock(&self.usage);
+        usage.entry((channel_id.to_string(), peer.to_string())).or_default().add(&Usage { messages: 1, bytes: bytes as u64, cost: price });
+        Some(price)
+    }
+
+    /// Record a `Stream` frame on its channel
+    ///
+    /// # Returns
+    /// Price of the frame, or `None` for other frames and unmetered channels
+    pub fn record_frame(&self, frame: &OpacusFrame, peer: &str) -> Option<u128> {
+        if frame.frame_type != FrameType::Stream {
+            return None;
+        }
+        let payload: serde_json::Value = serde_json::from_slice(&frame.payload).ok()?;
+        self.record(payload["channelId"].as_str()?, peer, frame.payload.len())
+    }
+
+    /// Total usage of a channel
+    pub fn usage(&self, channel_id: &str) -> Usage {
+        let mut total = Usage::default();
+        for (_, usage) in lock(&self.usage).iter().filter(|(key, _)| key.0 == channel_id) {
+            total.add(usage);
+        }
+        total
+    }
+
+    /// Usage of a channel by one peer
+    pub fn peer_usage(&self, channel_id: &str, peer: &str) -> Usage {
+        lock(&self.usage)
+            .get(&(channel_id.to_string(), peer.to_string()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Issue a signed statement of a peer's usage so far
+    ///
+    /// # Arguments
+    /// * `identity` - Provider identity (signs the statement)
+    /// * `channel_id` - Metered channel
+    /// * `consumer` - Peer the statement is for
+    /// * `ts` - Issue time (milliseconds)
+    pub fn statement(&self, identity: &AgentIdentity, channel_id: &str, consumer: &str, ts: u64) -> UsageStatement {
+        let mut statement = UsageStatement {
+            channel_id: channel_id.to_string(),
+            provider: identity.id.clone(),
+            provider_key: identity.ed_pub,
+            consumer: consumer.to_string(),
+            usage: self.peer_usage(channel_id, consumer),
+            ts,
+            signature: Vec::new(),
+        };
+        statement.signature = SecurityManager::sign(&identity.ed_priv, &statement.signing_data());
+        statement
+    }
+}
+
+/// Provider-signed running total of a consumer's usage of a channel
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageStatement {
+    /// Metered channel
+    pub channel_id: String,
+    /// Issuing agent
+    pub provider: String,
+    /// Issuer's Ed25519 public key
+    pub provider_key: [u8; 32],
+    /// Agent the usage is billed to
+    pub consumer: String,
+    /// Totals since metering started
+    pub usage: Usage,
+    /// Issue time (milliseconds)
+    pub ts: u64,
+    /// Ed25519 signature
+    pub signature: Vec<u8>,
+}
+
+impl UsageStatement {
+    fn signing_data(&self) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!([
+            STATEMENT_CONTEXT,
+            self.channel_id,
+            self.provider,
+            self.consumer,
+            self.usage.messages,
+            self.usage.bytes,
+            self.usage.cost.to_string(),
+            self.ts,
+        ]))
+        .expect("JSON array")
+    }
+
+    /// Verify that the statement was signed by `provider`
+    pub fn verify(&self) -> Result<(), String> {
+        if KeyManager::agent_id(&self.provider_key) != self.provider {
+            return Err("Provider ID does not match signing key".into());
+        }
+        if !SecurityManager::verify(&self.provider_key, &self.signing_data(), &self.signature) {
+            return Err("Invalid usage statement signature".into());
+        }
+        Ok(())
+    }
+
+    /// Amount still owed once `paid` has been paid
+    pub fn amount_due(&self, paid: u128) -> u128 {
+        self.usage.cost.saturating_sub(paid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ChannelType;
+
+    fn channel(id: &str) -> DataChannel {
+        DataChannel {
+            id: id.into(),
+            channel_type: ChannelType::Output,
+            price_per_byte: 2,
+            price_per_msg: 100,
+            access: None,
+            plan: None,
+        }
+    }
+
+    #[test]
+    fn test_metering() {
+        let meter = UsageMeter::new();
+        assert_eq!(meter.record("prices", "alice", 10), None);
+
+        meter.set_pricing(&channel("prices"));
+        assert_eq!(meter.record("prices", "alice", 10), Some(120));
+        assert_eq!(meter.record("prices", "alice", 0), Some(100));
+        assert_eq!(meter.record("prices", "bob", 50), Some(200));
+        assert_eq!(meter.peer_usage("prices", "alice"), Usage { messages: 2, bytes: 10, cost: 220 });
+        assert_eq!(meter.usage("prices"), Usage { messages: 3, bytes: 60, cost: 420 });
+        assert_eq!(meter.usage("weather"), Usage::default());
+
+        // Repricing keeps what was recorded
+        meter.set_pricing(&DataChannel { price_per_msg: 0, ..channel("prices") });
+        assert_eq!(meter.record("prices", "alice", 5), Some(10));
+        assert_eq!(meter.peer_usage("prices", "alice").cost, 230);
+    }
+
+    #[test]
+    fn test_usage_statement() {
+        let provider = KeyManager::generate_identity(16602);
+        let meter = UsageMeter::new();
+        meter.set_pricing(&channel("prices"));
+        meter.record("prices", "alice", 10);
+
+        let statement = meter.statement(&provider, "prices", "alice", 1_000);
+        statement.verify().unwrap();
+        assert_eq!((statement.usage.cost, statement.amount_due(100), statement.amount_due(500)), (120, 20, 0));
+
+        let json = serde_json::to_vec(&statement).unwrap();
+        assert_eq!(serde_json::from_slice::<UsageStatement>(&json).unwrap(), statement);
+
+        let mut inflated = statement.clone();
+        inflated.usage.cost = 1_000;
+        assert!(inflated.verify().is_err());
+        let other = KeyManager::generate_identity(16602);
+        let forged = UsageStatement { provider_key: other.ed_pub, ..statement };
+        assert!(forged.verify().is_err());
+    }
+}
@@ -0,0 +1,930 @@
+//! High-level agent runtime
+//!
+//! [`OpacusNode`] takes an [`OpacusClient`] and runs it on a task of its
+//! own: it connects, receives continuously, reconnects with exponential
+//! backoff when the relay connection drops, and retries sends that failed
+//! because of it. Applications talk to it through cloneable
+//! [`NodeHandle`]s:
+//!
+//! ```rust,no_run
+//! use opacus_sdk::{OpacusClient, OpacusConfig, OpacusNode};
+//!
+//! # async fn run(peer_id: &str) -> anyhow::Result<()> {
+//! let node = OpacusNode::start(OpacusClient::new(OpacusConfig::from_env()?)).await?;
+//! let handle = node.handle();
+//!
+//! // Answer requests from other agents
+//! handle.serve(|request| async move { Some(request.payload.to_ascii_uppercase()) });
+//!
+//! // Ask another agent and wait for its reply
+//! let reply = handle.request(peer_id, b"hello".to_vec()).await?;
+//!
+//! // Everything else the node receives
+//! let mut frames = handle.subscribe();
+//! while let Ok(frame) = frames.recv().await {
+//!     println!("{:?} from {}", frame.frame_type, frame.from);
+//! }
+//! let client = node.shutdown().await;
+//! # Ok(()) }
+//! ```
+//!
+//! Commands are executed one at a time, in order; while the node is
+//! reconnecting they wait for the connection. `Subscribe` frames for offered
+//! channels are accepted or rejected by the node itself, and `Task` frames
+//! are tracked by it: applications act on received tasks with the handle's
+//! task methods instead of `OpacusClient::on_task`. With cover traffic set
+//! on the client (`OpacusClient::set_cover_traffic`), the node sends cover
+//! frames whenever they are due.
+
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use futures::future::BoxFuture;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+use crate::client::OpacusClient;
+use crate::content::ContentType;
+use crate::history::HistoryEntry;
+use crate::task::{TaskOptions, TaskRecord};
+use crate::topic::TopicFilter;
+use crate::types::{DataChannel, FrameOptions, FrameType, OpacusFrame, Ulid};
+
+/// Commands queued for the node before senders wait
+const COMMAND_BUFFER: usize = 256;
+
+/// Reconnection, retry and buffering behavior of an [`OpacusNode`]
+#[derive(Debug, Clone)]
+pub struct NodeOptions {
+    /// Wait before the first reconnection attempt, doubled after each failure
+    pub reconnect_delay: Duration,
+    /// Longest wait between reconnection attempts
+    pub max_reconnect_delay: Duration,
+    /// Times a send is attempted before its error is returned
+    ///
+    /// Only failures that closed the connection are retried, after
+    /// reconnecting; other errors are returned at once.
+    pub send_attempts: u32,
+    /// Time `request` waits for a reply
+    pub request_timeout: Duration,
+    /// Frames buffered per subscriber; slower subscribers miss the oldest
+    pub subscriber_buffer: usize,
+}
+
+impl Default for NodeOptions {
+    fn default() -> Self {
+        Self {
+            reconnect_delay: Duration::from_millis(500),
+            max_reconnect_delay: Duration::from_secs(30),
+            send_attempts: 3,
+            request_timeout: Duration::from_secs(30),
+            subscriber_buffer: 256,
+        }
+    }
+}
+
+/// Message to another agent
+struct Outgoing {
+    to: String,
+    payload: Vec<u8>,
+    options: FrameOptions,
+    reply_to: Option<OpacusFrame>,
+}
+
+type ClientCall = Box<dyn for<'a> FnOnce(&'a mut OpacusClient) -> BoxFuture<'a, ()> + Send>;
+
+enum Command {
+    Send { message: Outgoing, done: oneshot::Sender<anyhow::Result<()>> },
+    Request { message: Outgoing, done: oneshot::Sender<anyhow::Result<OpacusFrame>> },
+    Call(ClientCall),
+    Shutdown,
+}
+
+/// Running agent: an [`OpacusClient`] driven by a task of its own
+pub struct OpacusNode {
+    handle: NodeHandle,
+    task: JoinHandle<OpacusClient>,
+}
+
+impl OpacusNode {
+    /// Start a node with default options
+    pub async fn start(client: OpacusClient) -> anyhow::Result<Self> {
+        Self::start_with(client, NodeOptions::default()).await
+    }
+
+    /// Start a node
+    ///
+    /// A client without an identity gets a new one, and a client that is
+    /// not connected connects to its relay URL; a failure to connect is
+    /// returned rather than retried. Later reconnections also use the relay
+    /// URL, so a client connected with `connect_with` cannot reconnect.
+    pub async fn start_with(mut client: OpacusClient, options: NodeOptions) -> anyhow::Result<Self> {
+        if client.identity().is_none() {
+            client.init().await;
+        }
+        if !client.is_connected() {
+            client.connect().await?;
+        }
+        let agent_id: Arc<str> = client.identity().expect("Initialized above").id.as_str().into();
+        let (commands, command_rx) = mpsc::channel(COMMAND_BUFFER);
+        let (frames, _) = broadcast::channel(options.subscriber_buffer.max(1));
+        let connected = Arc::new(AtomicBool::new(true));
+        let handle = NodeHandle {
+            agent_id,
+            commands,
+            frames: frames.downgrade(),
+            connected: connected.clone(),
+            request_timeout: options.request_timeout,
+        };
+        let node = NodeLoop {
+            reconnect_delay: options.reconnect_delay,
+            client,
+            options,
+            frames,
+            connected,
+            requests: HashMap::new(),
+            deferred: VecDeque::new(),
+            reconnect_at: None,
+        };
+        info!("Node {} started", handle.agent_id);
+        Ok(Self { handle, task: tokio::spawn(node.run(command_rx)) })
+    }
+
+    /// Handle for sending, subscribing and requests
+    pub fn handle(&self) -> NodeHandle {
+        self.handle.clone()
+    }
+
+    /// Stop the node and disconnect
+    ///
+    /// Commands still waiting for a connection fail. Handles stay valid
+    /// but fail from now on.
+    ///
+    /// # Returns
+    /// The client, for reuse
+    pub async fn shutdown(self) -> OpacusClient {
+        let _ = self.handle.commands.send(Command::Shutdown).await;
+        self.task.await.expect("Node task panicked")
+    }
+}
+
+/// Cloneable handle to an [`OpacusNode`]
+#[derive(Clone)]
+pub struct NodeHandle {
+    agent_id: Arc<str>,
+    commands: mpsc::Sender<Command>,
+    /// Weak, so subscriptions end when the node stops
+    frames: broadcast::WeakSender<OpacusFrame>,
+    connected: Arc<AtomicBool>,
+    request_timeout: Duration,
+}
+
+impl NodeHandle {
+    /// Agent ID of the node
+    pub fn id(&self) -> &str {
+        &self.agent_id
+    }
+
+    /// Whether the node is connected to its relay right now
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    async fn command<T>(&self, command: impl FnOnce(oneshot::Sender<T>) -> Command) -> anyhow::Result<T> {
+        let (done, result) = oneshot::channel();
+        self.commands.send(command(done)).await.map_err(|_| anyhow::anyhow!("Node stopped"))?;
+        result.await.map_err(|_| anyhow::anyhow!("Node stopped"))
+    }
+
+    async fn send_with(&self, to: &str, payload: Vec<u8>, content_type: ContentType) -> anyhow::Result<()> {
+        let options = FrameOptions { content_type, ..Default::default() };
+        let message = Outgoing { to: to.to_string(), payload, options, reply_to: None };
+        self.command(|done| Command::Send { message, done }).await?
+    }
+
+    /// Send a binary message to another agent
+    pub async fn send(&self, to: &str, payload: Vec<u8>) -> anyhow::Result<()> {
+        self.send_with(to, payload, ContentType::Raw).await
+    }
+
+    /// Send a value as a JSON message
+    pub async fn send_json<T: Serialize>(&self, to: &str, value: &T) -> anyhow::Result<()> {
+        self.send_with(to, serde_json::to_vec(value)?, ContentType::Json).await
+    }
+
+    /// Send a UTF-8 text message
+    pub async fn send_text(&self, to: &str, text: &str) -> anyhow::Result<()> {
+        self.send_with(to, text.as_bytes().to_vec(), ContentType::Text).await
+    }
+
+    /// Send a request and wait up to the node's `request_timeout` for the reply
+    ///
+    /// The reply must come from `to`; an `Error` frame about the request
+    /// fails it with the `OpacusError`.
+    pub async fn request(&self, to: &str, payload: Vec<u8>) -> anyhow::Result<OpacusFrame> {
+        self.request_with(to, payload, ContentType::Raw, self.request_timeout).await
+    }
+
+    /// Send a value as a JSON request and decode the JSON reply
+    pub async fn request_json<T: Serialize, R: DeserializeOwned>(&self, to: &str, value: &T) -> anyhow::Result<R> {
+        let reply = self.request_with(to, serde_json::to_vec(value)?, ContentType::Json, self.request_timeout).await?;
+        reply.payload_as().map_err(anyhow::Error::msg)
+    }
+
+    /// Send a request and wait up to `timeout` for the reply
+    pub async fn request_with(
+        &self,
+        to: &str,
+        payload: Vec<u8>,
+        content_type: ContentType,
+        timeout: Duration,
+    ) -> anyhow::Result<OpacusFrame> {
+        let options = FrameOptions { content_type, ..Default::default() };
+        let message = Outgoing { to: to.to_string(), payload, options, reply_to: None };
+        let reply = self.command(|done| Command::Request { message, done });
+        tokio::time::timeout(timeout, reply)
+            .await
+            .map_err(|_| anyhow::anyhow!("Request to {} timed out", to))??
+    }
+
+    /// Answer a request
+    pub async fn reply(&self, request: &OpacusFrame, payload: Vec<u8>) -> anyhow::Result<()> {
+        self.reply_with(request, payload, ContentType::Raw).await
+    }
+
+    /// Answer a request with a JSON value
+    pub async fn reply_json<T: Serialize>(&self, request: &OpacusFrame, value: &T) -> anyhow::Result<()> {
+        self.reply_with(request, serde_json::to_vec(value)?, ContentType::Json).await
+    }
+
+    async fn reply_with(&self, request: &OpacusFrame, payload: Vec<u8>, content_type: ContentType) -> anyhow::Result<()> {
+        let options = FrameOptions { content_type, ..Default::default() };
+        let message = Outgoing { to: request.from.clone(), payload, options, reply_to: Some(request.clone()) };
+        self.command(|done| Command::Send { message, done }).await?
+    }
+
+    /// Answer `Msg` frames with a handler, on a task of its own
+    ///
+    /// Replies to this node's requests are not passed to the handler. A
+    /// handler returning `None` sends no reply. Runs until the node stops.
+    pub fn serve<F, Fut>(&self, handler: F) -> JoinHandle<()>
+    where
+        F: Fn(OpacusFrame) -> Fut + Send + 'static,
+        Fut: Future<Output = Option<Vec<u8>>> + Send,
+    {
+        let handle = self.clone();
+        let mut frames = self.subscribe();
+        tokio::spawn(async move {
+            loop {
+                let request = match frames.recv().await {
+                    Ok(frame) if frame.frame_type == FrameType::Msg && frame.reply_to().is_none() => frame,
+                    Ok(_) => continue,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!("Request handler missed {} frames", missed);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+                let Some(payload) = handler(request.clone()).await else { continue };
+                if let Err(e) = handle.reply(&request, payload).await {
+                    warn!("Failed to reply to {}: {}", request.from, e);
+                }
+            }
+        })
+    }
+
+    /// Receive every frame the node receives from now on
+    ///
+    /// Except replies to this node's pending requests. Pings are answered
+    /// by the node and not delivered.
+    pub fn subscribe(&self) -> broadcast::Receiver<OpacusFrame> {
+        match self.frames.upgrade() {
+            Some(frames) => frames.subscribe(),
+            // Already closed
+            None => broadcast::channel(1).1,
+        }
+    }
+
+    /// Subscribe to a publisher's data channel
+    ///
+    /// See [`OpacusClient::subscribe`] for gated and paid channels.
+    pub async fn subscribe_channel(&self, publisher: &str, channel: &DataChannel) -> anyhow::Result<ChannelSubscription> {
+        self.subscribe_channel_with(publisher, channel, None).await
+    }
+
+    /// Subscribe to a publisher's data channel, starting with the data it
+    /// retained that was published after `since` (milliseconds)
+    ///
+    /// See [`OpacusClient::subscribe_since`].
+    pub async fn subscribe_channel_since(
+        &self,
+        publisher: &str,
+        channel: &DataChannel,
+        since: u64,
+    ) -> anyhow::Result<ChannelSubscription> {
+        self.subscribe_channel_with(publisher, channel, Some(since)).await
+    }
+
+    async fn subscribe_channel_with(
+        &self,
+        publisher: &str,
+        channel: &DataChannel,
+        since: Option<u64>,
+    ) -> anyhow::Result<ChannelSubscription> {
+        let filter = TopicFilter::new(&channel.id).map_err(anyhow::Error::msg)?;
+        let frames = self.subscribe();
+        let (publisher_id, request) = (publisher.to_string(), channel.clone());
+        self.call(move |client| {
+            Box::pin(async move {
+                match since {
+                    Some(since) => client.subscribe_since(&publisher_id, &request, since).await,
+                    None => client.subscribe(&publisher_id, &request).await,
+                }
+            })
+        })
+        .await??;
+        Ok(ChannelSubscription { filter, publisher: publisher.to_string(), frames })
+    }
+
+    /// Subscribe to every open channel of a publisher that matches a filter
+    ///
+    /// See [`OpacusClient::subscribe_topics`].
+    pub async fn subscribe_topics(&self, publisher: &str, filter: &TopicFilter) -> anyhow::Result<ChannelSubscription> {
+        let frames = self.subscribe();
+        let (publisher_id, request) = (publisher.to_string(), filter.clone());
+        self.call(move |client| Box::pin(async move { client.subscribe_topics(&publisher_id, &request).await }))
+            .await??;
+        Ok(ChannelSubscription { filter: filter.clone(), publisher: publisher.to_string(), frames })
+    }
+
+    /// Offer a data channel; the node accepts subscribers that meet its rule
+    pub async fn offer_channel(&self, channel: DataChannel) -> anyhow::Result<()> {
+        self.call(move |client| Box::pin(async move { client.offer_channel(channel) })).await
+    }
+
+    /// Retain the last `limit` data published on an offered channel
+    ///
+    /// The node answers history requests and catch-up subscriptions from it.
+    pub async fn retain_history(&self, channel_id: &str, limit: usize) -> anyhow::Result<()> {
+        let channel_id = channel_id.to_string();
+        self.call(move |client| Box::pin(async move { client.retain_history(&channel_id, limit) })).await
+    }
+
+    /// Fetch the data a publisher retained for one of its channels
+    ///
+    /// See [`OpacusClient::fetch_history`].
+    pub async fn fetch_history(&self, publisher: &str, channel_id: &str, since: u64) -> anyhow::Result<Vec<HistoryEntry>> {
+        let (publisher, channel_id) = (publisher.to_string(), channel_id.to_string());
+        self.call(move |client| Box::pin(async move { client.fetch_history(&publisher, &channel_id, since).await }))
+            .await?
+    }
+
+    /// Send data to every subscriber of an offered channel
+    ///
+    /// # Returns
+    /// Number of subscribers sent to
+    pub async fn publish(&self, channel_id: &str, data: Vec<u8>) -> anyhow::Result<usize> {
+        let channel_id = channel_id.to_string();
+        self.call(move |client| Box::pin(async move { client.publish(&channel_id, data).await })).await?
+    }
+
+    /// Delegate a task to another agent
+    ///
+    /// See [`OpacusClient::delegate_task`]. Answers about the task arrive as
+    /// `Task` frames on `subscribe`.
+    pub async fn delegate_task<T: Serialize>(
+        &self,
+        to: &str,
+        kind: &str,
+        input: &T,
+        options: TaskOptions,
+    ) -> anyhow::Result<Ulid> {
+        let (to, kind, input) = (to.to_string(), kind.to_string(), serde_json::to_value(input)?);
+        self.call(move |client| Box::pin(async move { client.delegate_task(&to, &kind, &input, options).await }))
+            .await?
+    }
+
+    /// Take on a task received in a `Task` frame, for `price` if charging
+    pub async fn accept_task(&self, task_id: Ulid, price: Option<u128>) -> anyhow::Result<()> {
+        self.call(move |client| Box::pin(async move { client.accept_task(task_id, price).await })).await?
+    }
+
+    /// Report how far an accepted task got
+    pub async fn report_task_progress(&self, task_id: Ulid, percent: u8, note: &str) -> anyhow::Result<()> {
+        let note = note.to_string();
+        self.call(move |client| Box::pin(async move { client.report_task_progress(task_id, percent, &note).await }))
+            .await?
+    }
+
+    /// Send the output of an assigned task
+    pub async fn complete_task<T: Serialize>(&self, task_id: Ulid, output: &T) -> anyhow::Result<()> {
+        let output = serde_json::to_value(output)?;
+        self.call(move |client| Box::pin(async move { client.complete_task(task_id, &output).await })).await?
+    }
+
+    /// Report that an assigned task failed, or refuse it
+    pub async fn fail_task(&self, task_id: Ulid, error: &str) -> anyhow::Result<()> {
+        let error = error.to_string();
+        self.call(move |client| Box::pin(async move { client.fail_task(task_id, &error).await })).await?
+    }
+
+    /// Withdraw a delegated task that has not finished
+    pub async fn cancel_task(&self, task_id: Ulid, reason: &str) -> anyhow::Result<()> {
+        let reason = reason.to_string();
+        self.call(move |client| Box::pin(async move { client.cancel_task(task_id, &reason).await })).await?
+    }
+
+    /// Task delegated by or assigned to the node, as tracked so far
+    pub async fn task(&self, task_id: Ulid) -> anyhow::Result<Option<TaskRecord>> {
+        self.call(move |client| Box::pin(async move { client.task(&task_id).cloned() })).await
+    }
+
+    /// Run a function on the node's client
+    ///
+    /// For client methods without a handle counterpart. Waits for the
+    /// connection and is not retried.
+    ///
+    /// ```rust,no_run
+    /// # async fn publish(handle: &opacus_sdk::NodeHandle) -> anyhow::Result<()> {
+    /// handle.call(|client| Box::pin(client.publish_prekeys(10))).await??;
+    /// # Ok(()) }
+    /// ```
+    pub async fn call<R, F>(&self, call: F) -> anyhow::Result<R>
+    where
+        F: for<'a> FnOnce(&'a mut OpacusClient) -> BoxFuture<'a, R> + Send + 'static,
+        R: Send + 'static,
+    {
+        self.command(|done| {
+            Command::Call(Box::new(move |client| {
+                Box::pin(async move {
+                    let _ = done.send(call(client).await);
+                })
+            }))
+        })
+        .await
+    }
+}
+
+/// Data of subscribed channels, from [`NodeHandle::subscribe_channel`] or
+/// [`NodeHandle::subscribe_topics`]
+pub struct ChannelSubscription {
+    filter: TopicFilter,
+    publisher: String,
+    frames: broadcast::Receiver<OpacusFrame>,
+}
+
+impl ChannelSubscription {
+    /// Channel (or filter) subscribed to
+    pub fn channel_id(&self) -> &str {
+        self.filter.as_str()
+    }
+
+    /// Next data published on the channel (`None` once the node stops)
+    pub async fn recv(&mut self) -> Option<Vec<u8>> {
+        self.recv_with_channel().await.map(|(_, data)| data)
+    }
+
+    /// Next data published on a matching channel, with the channel's ID
+    pub async fn recv_with_channel(&mut self) -> Option<(String, Vec<u8>)> {
+        loop {
+            let frame = match self.frames.recv().await {
+                Ok(frame) => frame,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!("Subscription to {} missed {} frames", self.filter, missed);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            };
+            if frame.frame_type != FrameType::Stream || frame.from != self.publisher {
+                continue;
+            }
+            match frame.stream_data() {
+                Some((channel_id, data)) if self.filter.matches(&channel_id) => return Some((channel_id, data)),
+                Some(_) => {}
+                None => debug!("Invalid stream frame from {}", frame.from),
+            }
+        }
+    }
+}
+
+/// State of the node task
+struct NodeLoop {
+    client: OpacusClient,
+    options: NodeOptions,
+    frames: broadcast::Sender<OpacusFrame>,
+    connected: Arc<AtomicBool>,
+    /// Pending requests by message ID, with their recipient
+    requests: HashMap<Ulid, (String, oneshot::Sender<anyhow::Result<OpacusFrame>>)>,
+    /// Commands waiting for the connection, with the attempts made so far
+    deferred: VecDeque<(Command, u32)>,
+    reconnect_delay: Duration,
+    reconnect_at: Option<Instant>,
+}
+
+impl NodeLoop {
+    async fn run(mut self, mut commands: mpsc::Receiver<Command>) -> OpacusClient {
+        loop {
+            let connected = self.is_connected();
+            let reconnect_at = self.reconnect_at.unwrap_or_else(Instant::now);
+            let cover_in = self.client.cover_due_in().filter(|_| connected);
+            // `recv` only waits on the transport before it has a frame, so
+            // dropping it for another branch does not lose frames
+            tokio::select! {
+                command = commands.recv() => match command {
+                    Some(Command::Shutdown) | None => break,
+                    Some(command) => self.execute(command, 0).await,
+                },
+                frame = self.client.recv(), if connected => match frame {
+                    Some(frame) => self.deliver(frame).await,
+                    None => self.connection_lost("connection closed"),
+                },
+                _ = tokio::time::sleep_until(reconnect_at), if !connected => self.reconnect().await,
+                _ = tokio::time::sleep(cover_in.unwrap_or_default()), if cover_in.is_some() => {
+                    if let Err(e) = self.client.send_cover_if_idle().await {
+                        debug!("Cover frame not sent: {}", e);
+                    }
+                }
+            }
+        }
+        self.connected.store(false, Ordering::Relaxed);
+        self.client.disconnect().await;
+        info!("Node stopped");
+        self.client
+    }
+
+    fn is_connected(&self) -> bool {
+        self.reconnect_at.is_none()
+    }
+
+    fn connection_lost(&mut self, reason: &str) {
+        if !self.is_connected() {
+            return;
+        }
+        warn!("Lost relay connection ({}), reconnecting in {:?}", reason, self.reconnect_delay);
+        self.connected.store(false, Ordering::Relaxed);
+        self.reconnect_at = Some(Instant::now() + self.reconnect_delay);
+    }
+
+    async fn reconnect(&mut self) {
+        if let Err(e) = self.client.connect().await {
+            self.reconnect_delay = (self.reconnect_delay * 2).min(self.options.max_reconnect_delay);
+            warn!("Reconnection failed ({}), retrying in {:?}", e, self.reconnect_delay);
+            self.reconnect_at = Some(Instant::now() + self.reconnect_delay);
+            return;
+        }
+        info!("Reconnected to relay");
+        self.reconnect_at = None;
+        self.reconnect_delay = self.options.reconnect_delay;
+        self.connected.store(true, Ordering::Relaxed);
+        // Commands deferred again by a new disconnection keep their order
+        for (command, attempts) in std::mem::take(&mut self.deferred) {
+            self.execute(command, attempts).await;
+        }
+    }
+
+    async fn execute(&mut self, command: Command, attempts: u32) {
+        if !self.is_connected() {
+            self.deferred.push_back((command, attempts));
+            return;
+        }
+        match command {
+            Command::Send { message, done } => {
+                if let Some(result) = self.send(&message, attempts).await {
+                    let _ = done.send(result.map(drop));
+                } else {
+                    self.deferred.push_back((Command::Send { message, done }, attempts + 1));
+                }
+            }
+            Command::Request { message, done } => match self.send(&message, attempts).await {
+                Some(Ok(id)) => {
+                    self.requests.insert(id, (message.to, done));
+                }
+                Some(Err(e)) => {
+                    let _ = done.send(Err(e));
+                }
+                None => self.deferred.push_back((Command::Request { message, done }, attempts + 1)),
+            },
+            Command::Call(call) => call(&mut self.client).await,
+            Command::Shutdown => {}
+        }
+    }
+
+    /// Send a message
+    ///
+    /// # Returns
+    /// Its message ID, or `None` if it should be retried after reconnecting
+    async fn send(&mut self, message: &Outgoing, attempts: u32) -> Option<anyhow::Result<Ulid>> {
+        let Outgoing { to, payload, options, reply_to } = message;
+        let result = match reply_to {
+            Some(request) => self.client.send_reply(request, payload.clone(), *options).await.map(|()| Ulid::nil()),
+            None => self.client.send_request(to, payload.clone(), *options).await,
+        };
+        match result {
+            Err(e) if !self.client.is_connected() && attempts + 1 < self.options.send_attempts => {
+                debug!("Send to {} failed ({}), retrying after reconnecting", to, e);
+                self.connection_lost(&e.to_string());
+                None
+            }
+            result => Some(result),
+        }
+    }
+
+    async fn deliver(&mut self, frame: OpacusFrame) {
+        // Requests whose caller gave up
+        self.requests.retain(|_, (_, done)| !done.is_closed());
+        if let Some(id) = frame.reply_to() {
+            if self.requests.get(&id).is_some_and(|(to, _)| *to == frame.from) {
+                let (_, done) = self.requests.remove(&id).expect("Checked above");
+                let _ = done.send(Ok(frame));
+                return;
+            }
+        }
+        if let Some(id) = frame.error_payload().and_then(|e| e.related_id).filter(|id| self.requests.contains_key(id)) {
+            let (_, done) = self.requests.remove(&id).expect("Checked above");
+            let error = frame.as_error().expect("Error frame");
+            let _ = done.send(Err(error.into()));
+            return;
+        }
+        if frame.frame_type == FrameType::Subscribe {
+            // Rejections are logged and answered by the client
+            let _ = self.client.on_subscribe(&frame).await;
+        }
+        if frame.history_query().is_some() {
+            // Refusals are logged and answered by the client
+            let _ = self.client.on_history_query(&frame).await;
+        }
+        if frame.frame_type == FrameType::Stream {
+            if let Err(e) = self.client.on_document_frame(&frame).await {
+                debug!("Invalid document frame from {}: {}", frame.from, e);
+            }
+        }
+        if frame.frame_type == FrameType::Task {
+            if let Err(e) = self.client.on_task(&frame).await {
+                debug!("Rejected task frame from {}: {}", frame.from, e);
+            }
+        }
+        // Without subscribers the frame is dropped
+        let _ = self.frames.send(frame);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::OpacusError;
+    use crate::task::{TaskMessage, TaskOutcome, TaskStatus};
+    use crate::transport::MemoryRelay;
+    use crate::types::{AccessRule, ChannelType, Network, OpacusConfig};
+
+    fn client(relay: &str) -> OpacusClient {
+        OpacusClient::new(OpacusConfig {
+            network: Network::Devnet,
+            relay_url: format!("local://{}", relay),
+            chain_rpc: String::new(),
+            private_key: None,
+        })
+    }
+
+    fn options() -> NodeOptions {
+        NodeOptions {
+            reconnect_delay: Duration::from_millis(10),
+            request_timeout: Duration::from_secs(5),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_reply() {
+        let alice = OpacusNode::start_with(client("test-node-rpc"), options()).await.unwrap();
+        let bob = OpacusNode::start_with(client("test-node-rpc"), options()).await.unwrap();
+        let (alice_handle, bob_handle) = (alice.handle(), bob.handle());
+        bob_handle.serve(|request| async move { Some(request.payload.to_ascii_uppercase()) });
+
+        let mut frames = alice_handle.subscribe();
+        let reply = alice_handle.request(bob_handle.id(), b"hello".to_vec()).await.unwrap();
+        assert_eq!((&reply.payload[..], reply.from.as_str()), (&b"HELLO"[..], bob_handle.id()));
+        // The reply was taken by the request (subscribers only saw the connect ACK)
+        assert!(std::iter::from_fn(|| frames.try_recv().ok()).all(|f| f.frame_type == FrameType::Ack));
+
+        // Plain messages reach subscribers
+        bob_handle.send_text(alice_handle.id(), "hi").await.unwrap();
+        let frame = loop {
+            let frame = frames.recv().await.unwrap();
+            if frame.frame_type == FrameType::Msg {
+                break frame;
+            }
+        };
+        assert_eq!(frame.payload_text().unwrap(), "hi");
+
+        // Unknown recipients are rejected by the relay
+        let err = alice_handle.request("", b"?".to_vec()).await.unwrap_err();
+        assert!(err.downcast_ref::<OpacusError>().is_some(), "{}", err);
+
+        let client = bob.shutdown().await;
+        assert!(!client.is_connected());
+        assert!(bob_handle.send(alice_handle.id(), b"late".to_vec()).await.is_err());
+        let err = alice_handle.request_with(bob_handle.id(), b"?".to_vec(), ContentType::Raw, Duration::from_millis(50)).await;
+        assert!(err.unwrap_err().to_string().contains("timed out"));
+        alice.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_reconnect() {
+        let relay = MemoryRelay::local("test-node-reconnect");
+        let alice = OpacusNode::start_with(client("test-node-reconnect"), options()).await.unwrap();
+        let bob = OpacusNode::start_with(client("test-node-reconnect"), options()).await.unwrap();
+        let (alice_handle, bob_handle) = (alice.handle(), bob.handle());
+        let mut frames = bob_handle.subscribe();
+
+        assert!(relay.disconnect(bob_handle.id()));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while relay.get_connected_agents().len() < 2 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(bob_handle.is_connected());
+
+        alice_handle.send(bob_handle.id(), b"after".to_vec()).await.unwrap();
+        let frame = loop {
+            let frame = frames.recv().await.unwrap();
+            if frame.frame_type == FrameType::Msg {
+                break frame;
+            }
+        };
+        assert_eq!(&frame.payload[..], b"after");
+        alice.shutdown().await;
+        bob.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_channel_subscription() {
+        let publisher = OpacusNode::start_with(client("test-node-channel"), options()).await.unwrap();
+        let subscriber = OpacusNode::start_with(client("test-node-channel"), options()).await.unwrap();
+        let (publisher_handle, subscriber_handle) = (publisher.handle(), subscriber.handle());
+        let channel = DataChannel {
+            id: "prices".into(),
+            channel_type: ChannelType::Output,
+            price_per_byte: 0,
+            price_per_msg: 0,
+            access: None,
+            plan: None,
+        };
+        publisher_handle.offer_channel(channel.clone()).await.unwrap();
+        let mut prices = subscriber_handle.subscribe_channel(publisher_handle.id(), &channel).await.unwrap();
+
+        let sent = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match publisher_handle.publish("prices", b"42".to_vec()).await.unwrap() {
+                    0 => tokio::time::sleep(Duration::from_millis(5)).await,
+                    sent => break sent,
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(sent, 1);
+        assert_eq!(prices.recv().await.unwrap(), b"42");
+        publisher.shutdown().await;
+        subscriber.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_channel_history() {
+        let publisher = OpacusNode::start_with(client("test-node-history"), options()).await.unwrap();
+        let subscriber = OpacusNode::start_with(client("test-node-history"), options()).await.unwrap();
+        let (publisher_handle, subscriber_handle) = (publisher.handle(), subscriber.handle());
+        let channel = DataChannel {
+            id: "prices".into(),
+            channel_type: ChannelType::Output,
+            price_per_byte: 0,
+            price_per_msg: 0,
+            access: None,
+            plan: None,
+        };
+        let gated = DataChannel {
+            id: "signals".into(),
+            access: Some(AccessRule::Erc20 { token: "0x01".into(), min_balance: 10 }),
+            ..channel.clone()
+        };
+        for offered in [channel.clone(), gated] {
+            publisher_handle.offer_channel(offered.clone()).await.unwrap();
+            publisher_handle.retain_history(&offered.id, 2).await.unwrap();
+        }
+        for data in ["1", "2", "3"] {
+            assert_eq!(publisher_handle.publish("prices", data.as_bytes().to_vec()).await.unwrap(), 0);
+        }
+        publisher_handle.publish("signals", b"buy".to_vec()).await.unwrap();
+
+        // Only the last two entries are retained
+        let entries = subscriber_handle.fetch_history(publisher_handle.id(), "prices", 0).await.unwrap();
+        assert_eq!(entries.iter().map(|e| &e.data[..]).collect::<Vec<_>>(), [b"2", b"3"]);
+        assert!(subscriber_handle.fetch_history(publisher_handle.id(), "signals", 0).await.is_err());
+
+        // Catching up on subscribe, then live data
+        let mut prices = subscriber_handle.subscribe_channel_since(publisher_handle.id(), &channel, 0).await.unwrap();
+        assert_eq!(prices.recv().await.unwrap(), b"2");
+        assert_eq!(prices.recv().await.unwrap(), b"3");
+        assert_eq!(publisher_handle.publish("prices", b"4".to_vec()).await.unwrap(), 1);
+        assert_eq!(prices.recv().await.unwrap(), b"4");
+        publisher.shutdown().await;
+        subscriber.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_task_delegation() {
+        async fn next_task(frames: &mut broadcast::Receiver<OpacusFrame>) -> TaskMessage {
+            loop {
+                if let Some(message) = frames.recv().await.unwrap().task_message() {
+                    return message;
+                }
+            }
+        }
+        let delegator = OpacusNode::start_with(client("test-node-tasks"), options()).await.unwrap();
+        let worker = OpacusNode::start_with(client("test-node-tasks"), options()).await.unwrap();
+        let (delegator_handle, worker_handle) = (delegator.handle(), worker.handle());
+        let (mut answers, mut requests) = (delegator_handle.subscribe(), worker_handle.subscribe());
+        let budget = TaskOptions { deadline: Some(Duration::from_secs(60)), budget: Some(10) };
+        let task_id = delegator_handle.delegate_task(worker_handle.id(), "upper", &"hi", budget.clone()).await.unwrap();
+
+        let TaskMessage::Request(task) = next_task(&mut requests).await else { panic!("Expected a task request") };
+        assert_eq!((task.task_id, task.kind.as_str(), task.input.as_str()), (task_id, "upper", Some("hi")));
+        worker_handle.accept_task(task_id, Some(8)).await.unwrap();
+        worker_handle.report_task_progress(task_id, 50, "halfway").await.unwrap();
+        worker_handle.complete_task(task_id, &"HI").await.unwrap();
+        for expected in ["accept", "progress", "result"] {
+            let answer = next_task(&mut answers).await;
+            assert_eq!(serde_json::to_value(&answer).unwrap()["type"], expected);
+        }
+        let done = delegator_handle.task(task_id).await.unwrap().unwrap();
+        assert_eq!((done.price, done.status), (Some(8), TaskStatus::Finished(TaskOutcome::Completed("HI".into()))));
+        // A finished task takes no more messages
+        assert!(worker_handle.fail_task(task_id, "late").await.is_err());
+
+        // Quotes above the budget get the task cancelled
+        let task_id = delegator_handle.delegate_task(worker_handle.id(), "upper", &"x", budget).await.unwrap();
+        next_task(&mut requests).await;
+        worker_handle.accept_task(task_id, Some(11)).await.unwrap();
+        next_task(&mut answers).await;
+        let cancel = next_task(&mut requests).await;
+        assert!(matches!(cancel, TaskMessage::Cancel { reason, .. } if reason == "Price above budget"));
+        let cancelled = worker_handle.task(task_id).await.unwrap().unwrap();
+        assert_eq!(cancelled.status, TaskStatus::Cancelled("Price above budget".into()));
+
+        // Requests past their deadline are refused
+        let late = TaskOptions { deadline: Some(Duration::ZERO), budget: None };
+        let task_id = delegator_handle.delegate_task(worker_handle.id(), "upper", &"y", late).await.unwrap();
+        let refusal = next_task(&mut answers).await;
+        let failed = TaskOutcome::Failed("Deadline passed".into());
+        assert_eq!(refusal, TaskMessage::Result { task_id, outcome: failed });
+        delegator.shutdown().await;
+        worker.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_topic_subscription() {
+        let publisher = OpacusNode::start_with(client("test-node-topics"), options()).await.unwrap();
+        let subscriber = OpacusNode::start_with(client("test-node-topics"), options()).await.unwrap();
+        let (publisher_handle, subscriber_handle) = (publisher.handle(), subscriber.handle());
+        let channel = |id: &str| DataChannel {
+            id: id.into(),
+            channel_type: ChannelType::Output,
+            price_per_byte: 0,
+            price_per_msg: 0,
+            access: None,
+            plan: None,
+        };
+        publisher_handle.offer_channel(channel("sensors/eu/temperature")).await.unwrap();
+        publisher_handle.offer_channel(channel("sensors/eu/humidity")).await.unwrap();
+        let mut gated = channel("sensors/us/temperature");
+        gated.access = Some(AccessRule::Erc20 { token: "0x01".into(), min_balance: 10 });
+        publisher_handle.offer_channel(gated).await.unwrap();
+
+        let filter = TopicFilter::new("sensors/+/temperature").unwrap();
+        let mut temperatures = subscriber_handle.subscribe_topics(publisher_handle.id(), &filter).await.unwrap();
+        assert_eq!(temperatures.channel_id(), "sensors/+/temperature");
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while publisher_handle.publish("sensors/eu/temperature", b"21".to_vec()).await.unwrap() == 0 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(temperatures.recv_with_channel().await.unwrap(), ("sensors/eu/temperature".into(), b"21".to_vec()));
+
+        // Gated and non-matching channels are not streamed; channels offered later are
+        assert_eq!(publisher_handle.publish("sensors/us/temperature", b"70".to_vec()).await.unwrap(), 0);
+        assert_eq!(publisher_handle.publish("sensors/eu/humidity", b"60".to_vec()).await.unwrap(), 0);
+        publisher_handle.offer_channel(channel("sensors/asia/temperature")).await.unwrap();
+        assert_eq!(publisher_handle.publish("sensors/asia/temperature", b"30".to_vec()).await.unwrap(), 1);
+        assert_eq!(temperatures.recv_with_channel().await.unwrap(), ("sensors/asia/temperature".into(), b"30".to_vec()));
+        publisher.shutdown().await;
+        subscriber.shutdown().await;
+    }
+}
@@ -0,0 +1,94 @@
+//! Offloading of large payloads
+//!
+//! Payloads above a threshold are uploaded to content storage (0G storage
+//! with the `chain` feature) instead of travelling through the relay. The
+//! frame then carries a [`PayloadRef`] with content type `Reference`, and
+//! the receiver fetches the payload and checks it against the reference's
+//! hash. The reference is covered by the frame's HMAC and signature, so the
+//! fetched payload is as authentic as an inline one.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::content::ContentType;
+use crate::types::OpacusFrame;
+
+/// Payload size above which payloads are offloaded
+pub const DEFAULT_OFFLOAD_THRESHOLD: usize = 256 * 1024;
+
+/// Extension holding the `PayloadRef` of a frame whose payload was fetched
+pub const OFFLOADED_EXTENSION: &str = "offloaded";
+
+/// Pointer to an offloaded payload
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PayloadRef {
+    /// Where the payload is stored
+    pub uri: String,
+    /// SHA-256 of the payload (hex)
+    pub sha256: String,
+    /// Payload length
+    pub size: u64,
+    /// Content type of the payload
+    pub content_type: ContentType,
+}
+
+impl PayloadRef {
+    /// Reference to a stored payload
+    ///
+    /// # Arguments
+    /// * `uri` - Where the payload was stored
+    /// * `payload` - Stored payload
+    /// * `content_type` - Its content type
+    pub fn new(uri: String, payload: &[u8], content_type: ContentType) -> Self {
+        Self {
+            uri,
+            sha256: hex::encode(Sha256::digest(payload)),
+            size: payload.len() as u64,
+            content_type,
+        }
+    }
+
+    /// Check that fetched data is the referenced payload
+    pub fn verify(&self, data: &[u8]) -> Result<(), String> {
+        if data.len() as u64 != self.size {
+            return Err(format!("Expected {} bytes from {}, got {}", self.size, self.uri, data.len()));
+        }
+        if hex::encode(Sha256::digest(data)) != self.sha256 {
+            return Err(format!("Hash mismatch for {}", self.uri));
+        }
+        Ok(())
+    }
+}
+
+impl OpacusFrame {
+    /// Reference carried by a frame whose payload was offloaded
+    ///
+    /// # Returns
+    /// `None` unless the content type is `Reference` and the payload parses
+    pub fn payload_ref(&self) -> Option<PayloadRef> {
+        if self.content_type != ContentType::Reference {
+            return None;
+        }
+        serde_json::from_slice(&self.decompressed_payload().ok()?).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_ref() {
+        let payload = vec![7u8; 1000];
+        let reference = PayloadRef::new("0g://0xabc".into(), &payload, ContentType::Cbor);
+        reference.verify(&payload).unwrap();
+        assert!(reference.verify(&payload[1..]).is_err());
+        let mut altered = payload.clone();
+        altered[0] = 8;
+        assert!(reference.verify(&altered).is_err());
+
+        let json = serde_json::to_value(&reference).unwrap();
+        assert_eq!(json["contentType"], "cbor");
+        assert_eq!(serde_json::from_value::<PayloadRef>(json).unwrap(), reference);
+    }
+}
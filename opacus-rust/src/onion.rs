@@ -0,0 +1,334 @@
+//! Onion-routed frames
+//!
+//! An onion frame carries a frame through a route of two or three relays,
+//! so that no single relay sees both its sender and its recipient. The
+//! sender wraps the frame in one layer per relay, innermost for the last: an
+//! `Onion` frame addressed to `"relay"` whose payload is encrypted to that
+//! relay's [`OnionKey`] with the same construction as sealed frames (an
+//! ephemeral key agreement, HKDF-SHA256 and ChaCha20-Poly1305). Each relay
+//! peels its layer, which names the next relay (its URL, e.g.
+//! `quic://host:port` or `local://name`) and holds the frame for it. The
+//! last relay's layer names no relay; it delivers the frame to its recipient
+//! like any other.
+//!
+//! The first relay sees the sender's connection but only an encrypted frame
+//! for the next relay; the last sees the recipient but not where the frame
+//! entered the route, and not its sender if the frame is sealed (see
+//! [`crate::sealed`]), as frames sent with `send_onion` are. Every layer has
+//! its own message ID. Each layer adds a fixed overhead, so relays can tell
+//! their position on the route from a frame's size, and relays that collude
+//! can still link a frame's hops by timing.
+//!
+//! Every layer authenticates the frame's timestamp. Relays refuse layers
+//! more than [`ONION_LAYER_TTL_MS`] away from their clock and remember the
+//! ephemeral keys of the layers they peeled meanwhile ([`OnionReplays`]), so
+//! an observer cannot replay a captured layer to trace it along the route.
+
+use std::collections::{HashSet, VecDeque};
+use crate::content::ContentType;
+use crate::crypto::{KeyManager, SecurityManager};
+use crate::proto::CBORCodec;
+use crate::random::{OsRandom, Random};
+use crate::types::{FrameType, OpacusFrame};
+
+/// Fewest relays on an onion route
+pub const MIN_ONION_HOPS: usize = 2;
+
+/// Most relays on an onion route
+pub const MAX_ONION_HOPS: usize = 3;
+
+/// `from` of onion frames, which do not name their sender
+pub const ONION_SENDER: &str = "onion";
+
+/// HKDF info prefix of layer keys
+const ONION_INFO: &[u8] = b"opacus-onion-v1";
+
+/// Age (and clock skew) after which relays refuse an onion layer (milliseconds)
+pub const ONION_LAYER_TTL_MS: u64 = 60_000;
+
+/// Most layers a relay remembers within [`ONION_LAYER_TTL_MS`]; layers beyond are refused
+pub const MAX_ONION_REPLAYS: usize = 65_536;
+
+/// Ephemeral public key, next relay length and AEAD tag
+const ONION_OVERHEAD: usize = 32 + 2 + 16;
+
+/// Associated data of a layer: its recipient and the frame's timestamp
+fn layer_aad(ts: u64) -> Vec<u8> {
+    let mut aad = b"relay".to_vec();
+    aad.extend_from_slice(&ts.to_be_bytes());
+    aad
+}
+
+/// Key of an onion layer, bound to the ephemeral and relay keys
+fn layer_key(shared: &[u8; 32], ephemeral_pub: &[u8; 32], relay_x_pub: &[u8; 32]) -> [u8; 32] {
+    let mut info = ONION_INFO.to_vec();
+    info.extend_from_slice(ephemeral_pub);
+    info.extend_from_slice(relay_x_pub);
+    SecurityManager::derive_session_key(shared, &info)
+}
+
+/// Relay on an onion route
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OnionHop {
+    /// URL the previous relay reaches the relay at
+    pub relay: String,
+    /// Relay's onion key ([`OnionKey::public_key`])
+    pub x_pub: [u8; 32],
+}
+
+impl OnionHop {
+    /// Create route entry
+    pub fn new(relay: &str, x_pub: [u8; 32]) -> Self {
+        Self { relay: relay.to_string(), x_pub }
+    }
+}
+
+/// What a relay does with a peeled onion frame
+#[derive(Debug, Clone)]
+pub enum OnionStep {
+    /// Send the frame to the next relay on the route
+    Forward { relay: String, frame: OpacusFrame },
+    /// Deliver the frame to its recipient
+    Deliver(OpacusFrame),
+}
+
+/// X25519 key pair a relay peels onion layers with
+#[derive(Clone)]
+pub struct OnionKey {
+    secret: [u8; 32],
+    public: [u8; 32],
+}
+
+impl OnionKey {
+    /// Generate a new key
+    pub fn generate() -> Self {
+        Self::generate_with(&OsRandom)
+    }
+
+    /// Generate a key from a custom source of randomness
+    pub fn generate_with(random: &dyn Random) -> Self {
+        let (secret, public) = KeyManager::generate_x25519_with(random);
+        Self { secret: secret.to_bytes(), public: public.to_bytes() }
+    }
+
+    /// Restore a key from its secret, e.g. one kept across relay restarts
+    pub fn from_secret(secret: [u8; 32]) -> Self {
+        let public = x25519_dalek::PublicKey::from(&x25519_dalek::StaticSecret::from(secret)).to_bytes();
+        Self { secret, public }
+    }
+
+    /// Secret key, to persist the key
+    pub fn secret_key(&self) -> [u8; 32] {
+        self.secret
+    }
+
+    /// Public key senders encrypt layers for this relay to
+    pub fn public_key(&self) -> [u8; 32] {
+        self.public
+    }
+
+    /// Remove this relay's layer from an onion frame
+    ///
+    /// # Returns
+    /// The frame for the next relay, or the frame to deliver here. Only
+    /// frames addressed to an agent are delivered.
+    pub fn peel(&self, frame: &OpacusFrame) -> Result<OnionStep, String> {
+        if frame.frame_type != FrameType::Onion || frame.to != "relay" {
+            return Err("Not an onion frame".into());
+        }
+        if frame.payload.len() < ONION_OVERHEAD {
+            return Err("Onion frame too short".into());
+        }
+        let (ephemeral_pub, ciphertext) = frame.payload.split_at(32);
+        let ephemeral_pub: [u8; 32] = ephemeral_pub.try_into().expect("32 bytes");
+        let shared = SecurityManager::derive_shared_secret(&self.secret, &ephemeral_pub);
+        let key = layer_key(&shared, &ephemeral_pub, &self.public);
+        let plaintext = SecurityManager::open(&key, &[0u8; 12], &layer_aad(frame.ts), ciphertext)?;
+
+        let relay_len = u16::from_be_bytes([plaintext[0], plaintext[1]]) as usize;
+        let Some(relay) = plaintext.get(2..2 + relay_len) else {
+            return Err("Truncated onion layer".into());
+        };
+        let relay = String::from_utf8(relay.to_vec()).map_err(|_| "Invalid next relay".to_string())?;
+        let inner = CBORCodec::decode(&plaintext[2 + relay_len..]).map_err(|e| e.to_string())?;
+        if !relay.is_empty() {
+            return Ok(OnionStep::Forward { relay, frame: inner });
+        }
+        if matches!(inner.frame_type, FrameType::Connect | FrameType::Onion)
+            || matches!(inner.to.as_str(), "" | "relay" | "broadcast")
+        {
+            return Err(format!("Onion route ends in a {:?} frame for {:?}", inner.frame_type, inner.to));
+        }
+        Ok(OnionStep::Deliver(inner))
+    }
+}
+
+/// Ephemeral keys of the onion layers a relay peeled recently
+#[derive(Debug, Default)]
+pub struct OnionReplays {
+    seen: HashSet<[u8; 32]>,
+    /// Keys with the time they can be forgotten, oldest first
+    expiry: VecDeque<(u64, [u8; 32])>,
+}
+
+impl OnionReplays {
+    /// Check a peeled layer is fresh at `now` (milliseconds) and not a replay, then remember it
+    pub fn check(&mut self, frame: &OpacusFrame, now: u64) -> Result<(), String> {
+        while self.expiry.front().is_some_and(|(expires, _)| *expires <= now) {
+            let (_, key) = self.expiry.pop_front().expect("front exists");
+            self.seen.remove(&key);
+        }
+        if frame.ts.saturating_add(ONION_LAYER_TTL_MS) <= now || frame.ts >= now.saturating_add(ONION_LAYER_TTL_MS) {
+            return Err("Onion layer is stale".into());
+        }
+        let ephemeral_pub: [u8; 32] = frame.payload.get(..32).and_then(|key| key.try_into().ok()).ok_or("Onion frame too short")?;
+        if self.seen.contains(&ephemeral_pub) {
+            return Err("Replayed onion layer".into());
+        }
+        if self.seen.len() >= MAX_ONION_REPLAYS {
+            return Err("Too many onion layers to remember".into());
+        }
+        self.seen.insert(ephemeral_pub);
+        // Layers are refused once stale, so keys need not outlive that
+        let expires = frame.ts.max(now).saturating_add(ONION_LAYER_TTL_MS);
+        let at = self.expiry.partition_point(|(e, _)| *e <= expires);
+        self.expiry.insert(at, (expires, ephemeral_pub));
+        Ok(())
+    }
+
+    /// Number of layers remembered
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    /// Whether no layers are remembered
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+}
+
+impl OpacusFrame {
+    /// Whether the frame is an onion layer for a relay
+    pub fn is_onion(&self) -> bool {
+        self.frame_type == FrameType::Onion
+    }
+
+    /// Wrap a frame for delivery along an onion route
+    ///
+    /// # Arguments
+    /// * `route` - Relays in order, from the one the frame is sent to
+    ///   (whose URL is not used) to the recipient's
+    /// * `random` - Source of the ephemeral keys and layer IDs
+    ///
+    /// # Returns
+    /// The outermost layer, to send to the first relay
+    pub fn wrap_onion(&self, route: &[OnionHop], random: &dyn Random) -> Result<OpacusFrame, String> {
+        if !(MIN_ONION_HOPS..=MAX_ONION_HOPS).contains(&route.len()) {
+            return Err(format!("Onion routes have {} to {} relays, not {}", MIN_ONION_HOPS, MAX_ONION_HOPS, route.len()));
+        }
+        let mut layer = self.clone();
+        let mut next: &str = "";
+        for hop in route.iter().rev() {
+            let (ephemeral, ephemeral_pub) = KeyManager::generate_x25519_with(random);
+            let ephemeral_pub = ephemeral_pub.to_bytes();
+            let shared = SecurityManager::derive_shared_secret(&ephemeral.to_bytes(), &hop.x_pub);
+            let key = layer_key(&shared, &ephemeral_pub, &hop.x_pub);
+
+            let relay_len = u16::try_from(next.len()).map_err(|_| "Relay URL too long".to_string())?;
+            let mut plaintext = relay_len.to_be_bytes().to_vec();
+            plaintext.extend_from_slice(next.as_bytes());
+            plaintext.extend_from_slice(&CBORCodec::encode(&layer).map_err(|e| e.to_string())?);
+            // Each key encrypts one layer, so a fixed nonce is never reused
+            let ciphertext = SecurityManager::seal(&key, &[0u8; 12], &layer_aad(self.ts), &plaintext);
+            let mut payload = ephemeral_pub.to_vec();
+            payload.extend_from_slice(&ciphertext);
+
+            layer = OpacusFrame {
+                version: self.version,
+                frame_type: FrameType::Onion,
+                from: ONION_SENDER.to_string(),
+                to: "relay".to_string(),
+                seq: 0,
+                ts: self.ts,
+                nonce: String::new(),
+                payload: payload.into(),
+                hmac: None,
+                sig: None,
+                key_epoch: 0,
+                compressed: None,
+                id: Some(OpacusFrame::new_id_with(self.ts, random)),
+                priority: self.priority,
+                content_type: ContentType::Raw,
+                extensions: Default::default(),
+            };
+            next = &hop.relay;
+        }
+        Ok(layer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_onion_route() {
+        let keys = [OnionKey::generate(), OnionKey::generate(), OnionKey::generate()];
+        let route: Vec<OnionHop> = ["quic://a:4242", "quic://b:4242", "local://c"]
+            .iter()
+            .zip(&keys)
+            .map(|(relay, key)| OnionHop::new(relay, key.public_key()))
+            .collect();
+        let (alice, bob) = (KeyManager::generate_identity(1), KeyManager::generate_identity(1));
+        let frame = SecurityManager::new().create_auth_frame(&alice, &[0u8; 32], FrameType::Msg, &bob.id, b"hi".to_vec());
+        let sealed = frame.seal(&alice, &bob.x_pub, &OsRandom).unwrap();
+
+        // Each relay learns only the next hop
+        let onion = sealed.wrap_onion(&route, &OsRandom).unwrap();
+        assert!(onion.is_onion() && !sealed.is_onion());
+        assert_eq!((onion.from.as_str(), onion.to.as_str()), (ONION_SENDER, "relay"));
+        assert_ne!(onion.id, sealed.id);
+        assert!(keys[1].peel(&onion).is_err());
+        let OnionStep::Forward { relay, frame: second } = keys[0].peel(&onion).unwrap() else { panic!("expected forward") };
+        assert_eq!(relay, "quic://b:4242");
+        assert!(second.is_onion() && second.id != onion.id);
+        let OnionStep::Forward { relay, frame: third } = keys[1].peel(&second).unwrap() else { panic!("expected forward") };
+        assert_eq!(relay, "local://c");
+        let OnionStep::Deliver(delivered) = keys[2].peel(&third).unwrap() else { panic!("expected delivery") };
+        assert_eq!((delivered.id, &delivered.payload), (sealed.id, &sealed.payload));
+        assert_eq!(delivered.unseal(&bob).unwrap().0.from, alice.id);
+
+        // Tampered layers are refused
+        let mut tampered = onion.clone();
+        let mut payload = tampered.payload.to_vec();
+        *payload.last_mut().unwrap() ^= 1;
+        tampered.payload = payload.into();
+        assert!(keys[0].peel(&tampered).is_err());
+
+        // Routes have two or three relays and end at an agent
+        assert!(sealed.wrap_onion(&route[..1], &OsRandom).is_err());
+        assert!(sealed.wrap_onion(&[route.clone(), route.clone()].concat(), &OsRandom).is_err());
+        let to_relay = OpacusFrame { to: "relay".to_string(), ..sealed.clone() };
+        let onion = to_relay.wrap_onion(&route[1..], &OsRandom).unwrap();
+        let OnionStep::Forward { frame, .. } = keys[1].peel(&onion).unwrap() else { panic!("expected forward") };
+        assert!(keys[2].peel(&frame).is_err());
+
+        // A restored key peels layers for the original
+        let restored = OnionKey::from_secret(keys[0].secret_key());
+        assert_eq!(restored.public_key(), keys[0].public_key());
+        assert!(restored.peel(&sealed.wrap_onion(&route, &OsRandom).unwrap()).is_ok());
+
+        // Layers are authenticated with their time, peeled once and only while fresh
+        let mut retimed = onion.clone();
+        retimed.ts += 1;
+        assert!(keys[1].peel(&onion).is_ok() && keys[1].peel(&retimed).is_err());
+        let mut replays = OnionReplays::default();
+        let now = onion.ts;
+        replays.check(&onion, now).unwrap();
+        assert_eq!(replays.check(&onion, now + 1).unwrap_err(), "Replayed onion layer");
+        replays.check(&second, now).unwrap();
+        assert_eq!(replays.len(), 2);
+        assert_eq!(replays.check(&third, now + ONION_LAYER_TTL_MS).unwrap_err(), "Onion layer is stale");
+        assert!(replays.is_empty());
+    }
+}
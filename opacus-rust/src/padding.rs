@@ -0,0 +1,182 @@
+//! Frame padding and cover traffic
+//!
+//! QUIC hides what frames contain from the network but not how large they
+//! are or when they are sent, which can be enough to tell what agents are
+//! doing. Padding rounds each outgoing frame's encoded size up to the
+//! smallest of a few fixed bucket sizes ([`PaddingPolicy`]) with zero bytes
+//! in the [`PADDING_EXTENSION`]. Like other extensions, padding is not
+//! covered by the frame's HMAC or signature, and relays forward it with the
+//! frame. Frames larger than the largest bucket are sent unpadded, and a
+//! frame can end a byte short of its bucket where the padding's length
+//! prefix grows.
+//!
+//! Cover traffic fills idle periods: `Cover` frames addressed to `"relay"`,
+//! padded to one of the buckets, which the relay discards. Cover frames hide
+//! an agent's activity from observers of its connection, not from its relay.
+
+use crate::content::ContentType;
+use crate::proto::{CodecError, FrameCodec, RoutingHeader, FRAME_VERSION};
+use crate::qos::Priority;
+use crate::types::{FrameType, OpacusFrame, Ulid};
+
+/// Extension holding a frame's padding (zero bytes)
+pub const PADDING_EXTENSION: &str = "pad";
+
+/// Bucket sizes padding rounds frames up to, in bytes
+///
+/// Signed frames with small payloads fill the first; the second still fits
+/// in a QUIC datagram.
+pub const DEFAULT_PADDING_BUCKETS: [usize; 2] = [512, 1024];
+
+/// Sizes outgoing frames are padded to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaddingPolicy {
+    buckets: Vec<usize>,
+}
+
+impl PaddingPolicy {
+    /// Create policy padding to the given sizes (in any order)
+    pub fn new(buckets: impl Into<Vec<usize>>) -> Self {
+        let mut buckets = buckets.into();
+        buckets.sort_unstable();
+        buckets.dedup();
+        Self { buckets }
+    }
+
+    /// Bucket sizes, smallest first
+    pub fn buckets(&self) -> &[usize] {
+        &self.buckets
+    }
+
+    /// Smallest bucket a frame of `size` bytes fits in
+    pub fn bucket_for(&self, size: usize) -> Option<usize> {
+        self.buckets.iter().copied().find(|bucket| *bucket >= size)
+    }
+}
+
+impl Default for PaddingPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_PADDING_BUCKETS)
+    }
+}
+
+impl OpacusFrame {
+    /// Number of padding bytes the frame carries
+    pub fn padding_len(&self) -> usize {
+        self.extensions.get(PADDING_EXTENSION).and_then(|v| v.as_bytes()).map_or(0, Vec::len)
+    }
+
+    /// Remove the frame's padding
+    pub fn strip_padding(&mut self) {
+        self.extensions.remove(PADDING_EXTENSION);
+    }
+
+    /// Pad the frame to the smallest bucket its encoding fits in
+    ///
+    /// # Arguments
+    /// * `codec` - Codec the frame will be sent with
+    /// * `policy` - Bucket sizes
+    ///
+    /// # Returns
+    /// Encoded size of the frame, with its routing header
+    pub fn pad(&mut self, codec: &dyn FrameCodec, policy: &PaddingPolicy) -> Result<usize, CodecError> {
+        self.strip_padding();
+        let unpadded = RoutingHeader::encoded_size(codec, self)?;
+        self.set_padding(0);
+        let empty = RoutingHeader::encoded_size(codec, self)?;
+        let Some(bucket) = policy.bucket_for(empty) else {
+            self.strip_padding();
+            return Ok(unpadded);
+        };
+        let mut len = bucket - empty;
+        loop {
+            self.set_padding(len);
+            let size = RoutingHeader::encoded_size(codec, self)?;
+            // Longer padding can take a longer length prefix
+            if size <= bucket {
+                return Ok(size);
+            }
+            len -= size - bucket;
+        }
+    }
+
+    fn set_padding(&mut self, len: usize) {
+        self.extensions.insert(PADDING_EXTENSION.to_string(), ciborium::Value::Bytes(vec![0; len]));
+    }
+
+    /// Unsigned `Cover` frame for the relay, padded to `size` bytes
+    ///
+    /// # Arguments
+    /// * `from` - Sending agent
+    /// * `id` - Message ID, like those of the agent's other frames
+    /// * `size` - Encoded size with `codec`, a bucket of the agent's policy
+    pub fn cover(from: &str, id: Ulid, size: usize, codec: &dyn FrameCodec) -> Result<OpacusFrame, CodecError> {
+        let mut frame = OpacusFrame {
+            version: FRAME_VERSION,
+            frame_type: FrameType::Cover,
+            from: from.to_string(),
+            to: "relay".to_string(),
+            seq: 0,
+            ts: id.timestamp_ms(),
+            nonce: String::new(),
+            payload: Default::default(),
+            hmac: None,
+            sig: None,
+            key_epoch: 0,
+            compressed: None,
+            id: Some(id),
+            priority: Priority::Low,
+            content_type: ContentType::Raw,
+            extensions: Default::default(),
+        };
+        frame.pad(codec, &PaddingPolicy::new([size]))?;
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{KeyManager, SecurityManager};
+    use crate::proto::WireFormat;
+
+    #[test]
+    fn test_padding() {
+        let codec = WireFormat::Cbor.codec().unwrap();
+        let policy = PaddingPolicy::new([1024, 512, 768, 512]);
+        assert_eq!(policy.buckets(), &[512, 768, 1024]);
+        assert_eq!((policy.bucket_for(600), policy.bucket_for(2000)), (Some(768), None));
+
+        let (alice, bob) = (KeyManager::generate_identity(1), KeyManager::generate_identity(1));
+        let mut security = SecurityManager::new();
+        let mut sizes = Vec::new();
+        for len in (0..600).step_by(7) {
+            let mut frame = security.create_auth_frame(&alice, &[0u8; 32], FrameType::Msg, &bob.id, vec![7; len]);
+            let size = frame.pad(codec, &policy).unwrap();
+            assert_eq!(size, RoutingHeader::encoded_size(codec, &frame).unwrap());
+            assert!(policy.buckets().iter().any(|bucket| (bucket - 1..=*bucket).contains(&size)), "{} bytes", size);
+            sizes.push(size);
+
+            // Padding survives encoding and leaves the signature valid
+            let decoded = codec.decode(&codec.encode(&frame).unwrap()).unwrap();
+            assert_eq!(decoded.padding_len(), frame.padding_len());
+            let sign_data = SecurityManager::frame_sign_data(&decoded, decoded.hmac.as_ref().unwrap());
+            assert!(SecurityManager::verify(&alice.ed_pub, &sign_data, decoded.sig.as_ref().unwrap()));
+
+            // Padding again replaces the padding
+            assert_eq!(frame.pad(codec, &policy).unwrap(), size);
+            frame.strip_padding();
+            assert_eq!(frame.padding_len(), 0);
+        }
+        assert!(sizes.contains(&512) && sizes.contains(&768) && sizes.contains(&1024));
+
+        // Frames beyond the largest bucket are left as they are
+        let mut large = security.create_auth_frame(&alice, &[0u8; 32], FrameType::Msg, &bob.id, vec![7; 2000]);
+        assert!(large.pad(codec, &policy).unwrap() > 1024);
+        assert_eq!(large.padding_len(), 0);
+
+        let cover = OpacusFrame::cover(&alice.id, OpacusFrame::new_id(5), 512, codec).unwrap();
+        assert_eq!((cover.frame_type, cover.to.as_str(), cover.priority), (FrameType::Cover, "relay", Priority::Low));
+        assert_eq!(RoutingHeader::encoded_size(codec, &cover).unwrap(), 512);
+    }
+}
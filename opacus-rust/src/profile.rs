@@ -0,0 +1,187 @@
+//! Agent metadata profiles
+//!
+//! An agent describes itself in a [`Profile`] (name, description, service
+//! endpoints, tags and the DAC whose pricing applies) and signs it into a
+//! [`SignedProfile`]. Profiles are published in a `Profile` frame to
+//! `"relay"`, which keeps the newest one of each agent. Agents look them up
+//! with a `Profile` frame to `"relay"` whose payload is a [`ProfileQuery`];
+//! a query can also start (or stop) watching the agent, after which the
+//! relay forwards each newer profile it publishes. With the `chain` feature,
+//! a profile can also be stored with the agent's registration in the
+//! `AgentRegistry`.
+//!
+//! The signature binds a profile to the agent's Ed25519 key, so profiles can
+//! be relayed, cached and passed between peers without trusting whoever
+//! carries them. A profile with a later `updated_at` supersedes older ones.
+
+use serde::{Deserialize, Serialize};
+use crate::crypto::{KeyManager, SecurityManager};
+use crate::types::{AgentIdentity, FrameType, OpacusFrame};
+
+/// Domain separator of profile signatures
+const PROFILE_CONTEXT: &str = "opacus-profile-v1";
+
+/// Self-description of an agent
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    /// Display name
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Where the agent's services can be reached besides the relay
+    #[serde(default)]
+    pub endpoints: Vec<ServiceEndpoint>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// DAC whose pricing applies (`0x`-prefixed registry ID)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pricing_dac: Option<String>,
+}
+
+/// Endpoint of a service an agent runs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceEndpoint {
+    /// Service name, as advertised in the agent's capabilities
+    pub service: String,
+    /// URI the service is reached at
+    pub uri: String,
+}
+
+/// Profile signed by the agent it describes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedProfile {
+    /// Agent described
+    pub agent_id: String,
+    /// Agent's Ed25519 public key
+    pub ed_pub: [u8; 32],
+    #[serde(flatten)]
+    pub profile: Profile,
+    /// Signing time (milliseconds); later profiles supersede earlier ones
+    pub updated_at: u64,
+    /// Ed25519 signature
+    pub signature: Vec<u8>,
+}
+
+/// Lookup of another agent's profile, sent to `"relay"`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileQuery {
+    /// Agent whose profile is requested
+    pub fetch: String,
+    /// Start (`true`) or stop (`false`) receiving the agent's later profiles
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watch: Option<bool>,
+}
+
+impl SignedProfile {
+    /// Sign a profile
+    ///
+    /// # Arguments
+    /// * `identity` - Agent described
+    /// * `profile` - Its description
+    /// * `updated_at` - Signing time (milliseconds)
+    pub fn sign(identity: &AgentIdentity, profile: Profile, updated_at: u64) -> Self {
+        let mut signed = Self {
+            agent_id: identity.id.clone(),
+            ed_pub: identity.ed_pub,
+            profile,
+            updated_at,
+            signature: Vec::new(),
+        };
+        signed.signature = SecurityManager::sign(&identity.ed_priv, &signed.signing_data());
+        signed
+    }
+
+    fn signing_data(&self) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!([
+            PROFILE_CONTEXT,
+            self.agent_id,
+            self.profile,
+            self.updated_at,
+        ]))
+        .expect("JSON array")
+    }
+
+    /// Verify that the profile was signed by the agent it describes
+    pub fn verify(&self) -> Result<(), String> {
+        if KeyManager::agent_id(&self.ed_pub) != self.agent_id {
+            return Err("Agent ID does not match signing key".into());
+        }
+        if !SecurityManager::verify(&self.ed_pub, &self.signing_data(), &self.signature) {
+            return Err("Invalid profile signature".into());
+        }
+        Ok(())
+    }
+}
+
+impl OpacusFrame {
+    /// Decode and verify the profile carried by a `Profile` frame
+    ///
+    /// # Returns
+    /// `None` for other frames, queries, empty relay answers and profiles
+    /// that fail verification
+    pub fn profile(&self) -> Option<SignedProfile> {
+        if self.frame_type != FrameType::Profile || self.payload.is_empty() {
+            return None;
+        }
+        let profile: SignedProfile = serde_json::from_slice(&self.payload).ok()?;
+        profile.verify().ok()?;
+        Some(profile)
+    }
+
+    /// Decode the query of a `Profile` frame
+    pub fn profile_query(&self) -> Option<ProfileQuery> {
+        if self.frame_type != FrameType::Profile {
+            return None;
+        }
+        serde_json::from_slice(&self.payload).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(payload: &[u8]) -> OpacusFrame {
+        OpacusFrame {
+            frame_type: FrameType::Profile,
+            from: "a".to_string(),
+            to: "relay".to_string(),
+            payload: payload.to_vec().into(),
+            ..OpacusFrame::test(1)
+        }
+    }
+
+    #[test]
+    fn test_signed_profile() {
+        let alice = KeyManager::generate_identity(1);
+        let profile = Profile {
+            name: "Summarizer".into(),
+            endpoints: vec![ServiceEndpoint { service: "summarize".into(), uri: "https://alice.example/v2".into() }],
+            tags: vec!["nlp".into()],
+            pricing_dac: Some(format!("0x{}", "11".repeat(32))),
+            ..Default::default()
+        };
+        let signed = SignedProfile::sign(&alice, profile, 1_700_000_000_000);
+        assert!(signed.verify().is_ok());
+        let decoded = frame(&serde_json::to_vec(&signed).unwrap()).profile().unwrap();
+        assert_eq!(decoded, signed);
+
+        // Any change, or a key of another agent, breaks the signature
+        let mut tampered = signed.clone();
+        tampered.profile.tags.push("finance".into());
+        assert!(tampered.verify().is_err());
+        assert_eq!(frame(&serde_json::to_vec(&tampered).unwrap()).profile(), None);
+        let mut foreign = signed.clone();
+        foreign.ed_pub = KeyManager::generate_identity(1).ed_pub;
+        assert_eq!(foreign.verify().unwrap_err(), "Agent ID does not match signing key");
+
+        // Queries are not profiles
+        let query = frame(br#"{"fetch":"b","watch":true}"#);
+        assert_eq!(query.profile(), None);
+        assert_eq!(query.profile_query(), Some(ProfileQuery { fetch: "b".into(), watch: Some(true) }));
+    }
+}
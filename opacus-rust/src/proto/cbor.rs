@@ -0,0 +1,542 @@
+//! CBOR frame encoding (ciborium)
+//!
+//! Frames are encoded by hand on ciborium's low-level item layer instead of
+//! through serde so decoding can borrow strings from the input. The layout
+//! matches the serde representation of the frame's version
+//! (`OpacusFrameV1`/`OpacusFrameV2`): an indefinite-length map with the fields
+//! in declaration order and optional fields omitted when empty. Byte fields
+//! are accepted in either encoding. Unknown keys are kept in `extensions`.
+//!
+//! `encode_canonical` writes the same fields deterministically for frames that
+//! are hashed or signed; the decoder accepts both forms.
+
+use std::borrow::Cow;
+use bytes::Bytes;
+use ciborium_ll::{simple, Decoder, Encoder, Header};
+use crate::types::{FrameType, OpacusFrame, Ulid};
+use super::versioned::check_version;
+use super::CodecError;
+
+/// Write an item head
+fn put(out: &mut Vec<u8>, header: Header) {
+    Encoder::from(out).push(header).expect("writing to a Vec");
+}
+
+fn put_text(out: &mut Vec<u8>, s: &str) {
+    put(out, Header::Text(Some(s.len())));
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Destination for encoded map entries
+trait Entries {
+    fn entry(
+        &mut self,
+        key: &str,
+        write: impl FnOnce(&mut Vec<u8>) -> Result<(), CodecError>,
+    ) -> Result<(), CodecError>;
+
+    /// Entry whose value cannot fail to encode
+    fn put(&mut self, key: &str, write: impl FnOnce(&mut Vec<u8>)) -> Result<(), CodecError> {
+        self.entry(key, |out| {
+            write(out);
+            Ok(())
+        })
+    }
+}
+
+/// Entries written in place, in field order
+struct Streamed<'a>(&'a mut Vec<u8>);
+
+impl Entries for Streamed<'_> {
+    fn entry(
+        &mut self,
+        key: &str,
+        write: impl FnOnce(&mut Vec<u8>) -> Result<(), CodecError>,
+    ) -> Result<(), CodecError> {
+        put_text(self.0, key);
+        write(self.0)
+    }
+}
+
+/// Entries buffered so they can be sorted by key
+struct Buffered(Vec<(String, Vec<u8>)>);
+
+impl Entries for Buffered {
+    fn entry(
+        &mut self,
+        key: &str,
+        write: impl FnOnce(&mut Vec<u8>) -> Result<(), CodecError>,
+    ) -> Result<(), CodecError> {
+        let mut value = Vec::new();
+        write(&mut value)?;
+        self.0.push((key.to_string(), value));
+        Ok(())
+    }
+}
+
+/// Encode a frame
+pub(super) fn encode(frame: &OpacusFrame, out: &mut Vec<u8>) -> Result<(), CodecError> {
+    check_version(frame).map_err(CodecError::Encode)?;
+    put(out, Header::Map(None));
+    write_fields(frame, &mut Streamed(out), false)?;
+    put(out, Header::Break);
+    Ok(())
+}
+
+/// Encode a frame deterministically (RFC 8949 §4.2.1)
+///
+/// Same fields as `encode`, but in a definite-length map with keys sorted by
+/// their encoding (shorter keys first, then bytewise), including the maps
+/// nested in extension values. Integers and lengths always use the shortest
+/// form. Duplicate keys are rejected.
+pub(super) fn encode_canonical(frame: &OpacusFrame, out: &mut Vec<u8>) -> Result<(), CodecError> {
+    check_version(frame).map_err(CodecError::Encode)?;
+    let mut entries = Buffered(Vec::with_capacity(16 + frame.extensions.len()));
+    write_fields(frame, &mut entries, true)?;
+    let mut entries = entries.0;
+    entries.sort_by(|(a, _), (b, _)| (a.len(), a).cmp(&(b.len(), b)));
+    if let Some(pair) = entries.windows(2).find(|w| w[0].0 == w[1].0) {
+        return Err(CodecError::Encode(format!("Duplicate key: {}", pair[0].0)));
+    }
+    put(out, Header::Map(Some(entries.len())));
+    for (key, value) in &entries {
+        put_text(out, key);
+        out.extend_from_slice(value);
+    }
+    Ok(())
+}
+
+/// Write the fields of a frame's version layout
+fn write_fields(frame: &OpacusFrame, entries: &mut impl Entries, canonical: bool) -> Result<(), CodecError> {
+    let v1 = frame.version == 1;
+    let mut buf = [0u8; ulid::ULID_LEN];
+    entries.put("version", |out| put(out, Header::Positive(frame.version.into())))?;
+    entries.put("type", |out| match frame.frame_type.name() {
+        Some(name) => put_text(out, name),
+        None => put(out, Header::Positive(frame.frame_type.code().into())),
+    })?;
+    entries.put("from", |out| put_text(out, &frame.from))?;
+    entries.put("to", |out| put_text(out, &frame.to))?;
+    entries.put("seq", |out| put(out, Header::Positive(frame.seq)))?;
+    entries.put("ts", |out| put(out, Header::Positive(frame.ts)))?;
+    entries.put("nonce", |out| put_text(out, &frame.nonce))?;
+    if let (false, Some(id)) = (v1, frame.id) {
+        entries.put("id", |out| put_text(out, id.array_to_str(&mut buf)))?;
+    }
+    entries.put("payload", |out| byte_field(out, &frame.payload, v1))?;
+    entries.put("hmac", |out| match &frame.hmac {
+        Some(hmac) => put_text(out, hmac),
+        None => put(out, Header::Simple(simple::NULL)),
+    })?;
+    entries.put("sig", |out| match &frame.sig {
+        Some(sig) => byte_field(out, sig, v1),
+        None => put(out, Header::Simple(simple::NULL)),
+    })?;
+    if frame.key_epoch != 0 {
+        entries.put("key_epoch", |out| put(out, Header::Positive(frame.key_epoch.into())))?;
+    }
+    if let Some(alg) = frame.compressed {
+        entries.put("compressed", |out| put_text(out, alg.as_str()))?;
+    }
+    if let (true, Some(id)) = (v1, frame.id) {
+        entries.put("id", |out| put_text(out, id.array_to_str(&mut buf)))?;
+    }
+    if !frame.priority.is_normal() {
+        entries.put("priority", |out| put_text(out, frame.priority.as_str()))?;
+    }
+    if !frame.content_type.is_raw() {
+        entries.put("content_type", |out| put_text(out, frame.content_type.as_str()))?;
+    }
+    for (key, value) in &frame.extensions {
+        let sorted;
+        let value = if canonical {
+            sorted = canonical_value(value);
+            &sorted
+        } else {
+            value
+        };
+        entries.entry(key, |out| {
+            ciborium::ser::into_writer(value, out)
+                .map_err(|err| CodecError::Encode(format!("Invalid extension {}: {}", key, err)))
+        })?;
+    }
+    Ok(())
+}
+
+/// Encode a value with its map keys sorted by their encoding
+pub(super) fn encode_value_canonical(value: &ciborium::Value) -> Result<Vec<u8>, CodecError> {
+    let mut out = Vec::new();
+    ciborium::ser::into_writer(&canonical_value(value), &mut out)
+        .map_err(|e| CodecError::Encode(e.to_string()))?;
+    Ok(out)
+}
+
+/// Sort map keys by their encoding, recursively
+///
+/// ciborium already writes definite lengths, shortest integers and the
+/// shortest lossless float, so key order is all that is left.
+fn canonical_value(value: &ciborium::Value) -> ciborium::Value {
+    use ciborium::Value;
+    match value {
+        Value::Array(items) => Value::Array(items.iter().map(canonical_value).collect()),
+        Value::Map(entries) => {
+            let mut keyed: Vec<(Vec<u8>, Value, Value)> = entries
+                .iter()
+                .map(|(k, v)| {
+                    let k = canonical_value(k);
+                    let mut encoded = Vec::new();
+                    ciborium::ser::into_writer(&k, &mut encoded).expect("CBOR value encoding");
+                    (encoded, k, canonical_value(v))
+                })
+                .collect();
+            keyed.sort_by(|a, b| a.0.cmp(&b.0));
+            Value::Map(keyed.into_iter().map(|(_, k, v)| (k, v)).collect())
+        }
+        Value::Tag(tag, inner) => Value::Tag(*tag, Box::new(canonical_value(inner))),
+        other => other.clone(),
+    }
+}
+
+/// Write bytes as an array of integers (version 1) or a byte string
+fn byte_field(out: &mut Vec<u8>, data: &[u8], int_array: bool) {
+    if !int_array {
+        put(out, Header::Bytes(Some(data.len())));
+        out.extend_from_slice(data);
+        return;
+    }
+    put(out, Header::Array(Some(data.len())));
+    let start = out.len();
+    out.resize(start + byte_field_len(data, true) - head_len(data.len() as u64), 0);
+    let mut rest = &mut out[start..];
+    for &b in data {
+        rest = if b < 24 {
+            rest[0] = b;
+            &mut rest[1..]
+        } else {
+            rest[..2].copy_from_slice(&[0x18, b]);
+            &mut rest[2..]
+        };
+    }
+}
+
+/// Size of a frame's `encode` output, computed without encoding it
+///
+/// Only extension values are serialized (into a byte counter).
+pub(super) fn encoded_size(frame: &OpacusFrame) -> Result<usize, CodecError> {
+    check_version(frame).map_err(CodecError::Encode)?;
+    let v1 = frame.version == 1;
+    // Indefinite-length map header and break
+    let mut size = 2;
+    let mut field = |key: &str, value_len: usize| size += text_len(key) + value_len;
+    field("version", head_len(frame.version.into()));
+    field("type", match frame.frame_type.name() {
+        Some(name) => text_len(name),
+        None => head_len(frame.frame_type.code().into()),
+    });
+    field("from", text_len(&frame.from));
+    field("to", text_len(&frame.to));
+    field("seq", head_len(frame.seq));
+    field("ts", head_len(frame.ts));
+    field("nonce", text_len(&frame.nonce));
+    if frame.id.is_some() {
+        field("id", head_len(ulid::ULID_LEN as u64) + ulid::ULID_LEN);
+    }
+    field("payload", byte_field_len(&frame.payload, v1));
+    field("hmac", frame.hmac.as_deref().map_or(1, text_len));
+    field("sig", frame.sig.as_deref().map_or(1, |sig| byte_field_len(sig, v1)));
+    if frame.key_epoch != 0 {
+        field("key_epoch", head_len(frame.key_epoch.into()));
+    }
+    if let Some(alg) = frame.compressed {
+        field("compressed", text_len(alg.as_str()));
+    }
+    if !frame.priority.is_normal() {
+        field("priority", text_len(frame.priority.as_str()));
+    }
+    if !frame.content_type.is_raw() {
+        field("content_type", text_len(frame.content_type.as_str()));
+    }
+    for (key, value) in &frame.extensions {
+        let mut counter = ByteCounter(0);
+        ciborium::ser::into_writer(value, &mut counter)
+            .map_err(|err| CodecError::Encode(format!("Invalid extension {}: {}", key, err)))?;
+        field(key, counter.0);
+    }
+    Ok(size)
+}
+
+/// Length of an item head carrying argument `n`
+fn head_len(n: u64) -> usize {
+    match n {
+        0..=23 => 1,
+        24..=0xff => 2,
+        0x100..=0xffff => 3,
+        0x1_0000..=0xffff_ffff => 5,
+        _ => 9,
+    }
+}
+
+fn text_len(s: &str) -> usize {
+    head_len(s.len() as u64) + s.len()
+}
+
+fn byte_field_len(data: &[u8], int_array: bool) -> usize {
+    let body = if int_array {
+        data.iter().map(|&b| if b < 24 { 1 } else { 2 }).sum()
+    } else {
+        data.len()
+    };
+    head_len(data.len() as u64) + body
+}
+
+/// Writer that only counts bytes
+struct ByteCounter(usize);
+
+impl std::io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Cursor over the input, so strings can be borrowed from it
+struct Reader<'b> {
+    data: &'b [u8],
+    pos: usize,
+}
+
+impl<'b> Reader<'b> {
+    /// Read the next item head
+    fn header(&mut self) -> Result<Header, CodecError> {
+        let mut d = Decoder::from(&self.data[self.pos..]);
+        let header = d.pull().map_err(|e| match e {
+            ciborium_ll::Error::Io(e) => CodecError::Decode(e.to_string()),
+            ciborium_ll::Error::Syntax(at) => CodecError::Decode(format!("Invalid CBOR at offset {}", self.pos + at)),
+        })?;
+        self.pos += d.offset();
+        Ok(header)
+    }
+
+    /// The next item head, without consuming it
+    fn peek(&self) -> Result<Header, CodecError> {
+        Reader { data: self.data, pos: self.pos }.header()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'b [u8], CodecError> {
+        let bytes = self
+            .data
+            .get(self.pos..)
+            .and_then(|rest| rest.get(..len))
+            .ok_or_else(|| CodecError::Decode("Unexpected end of input".into()))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, CodecError> {
+        match self.header()? {
+            Header::Positive(n) => Ok(n),
+            other => Err(unexpected("an unsigned integer", other)),
+        }
+    }
+
+    fn uint<T: TryFrom<u64>>(&mut self) -> Result<T, CodecError> {
+        let n = self.u64()?;
+        T::try_from(n).map_err(|_| CodecError::Decode(format!("Integer out of range: {}", n)))
+    }
+
+    /// Read any item as a value
+    fn value(&mut self) -> Result<ciborium::Value, CodecError> {
+        let mut rest = &self.data[self.pos..];
+        let value = ciborium::de::from_reader(&mut rest).map_err(|e| CodecError::Decode(e.to_string()))?;
+        self.pos = self.data.len() - rest.len();
+        Ok(value)
+    }
+}
+
+fn unexpected(expected: &str, found: Header) -> CodecError {
+    CodecError::Decode(format!("Expected {}, found {:?}", expected, found))
+}
+
+/// Decode a frame, rejecting trailing data
+pub(super) fn decode(data: &[u8]) -> Result<OpacusFrame, CodecError> {
+    let mut d = Reader { data, pos: 0 };
+    let len = match d.header()? {
+        Header::Map(len) => len,
+        other => return Err(unexpected("a map", other)),
+    };
+
+    let mut version = None;
+    let mut frame_type = None;
+    let mut from = None;
+    let mut to = None;
+    let mut seq = None;
+    let mut ts = None;
+    let mut nonce = None;
+    let mut payload = None;
+    let mut frame = OpacusFrame {
+        version: 0,
+        frame_type: FrameType::Unknown(FrameType::UNKNOWN_CODE),
+        from: String::new(),
+        to: String::new(),
+        seq: 0,
+        ts: 0,
+        nonce: String::new(),
+        payload: Bytes::new(),
+        hmac: None,
+        sig: None,
+        key_epoch: 0,
+        compressed: None,
+        id: None,
+        priority: Default::default(),
+        content_type: Default::default(),
+        extensions: Default::default(),
+    };
+
+    let mut remaining = len;
+    loop {
+        match remaining {
+            Some(0) => break,
+            Some(n) => remaining = Some(n - 1),
+            None if d.peek()? == Header::Break => {
+                d.header()?;
+                break;
+            }
+            None => {}
+        }
+        let key = text(&mut d)?;
+        match key.as_ref() {
+            "version" => version = Some(d.uint()?),
+            "type" => frame_type = Some(decode_frame_type(&mut d)?),
+            "from" => from = Some(text(&mut d)?.into_owned()),
+            "to" => to = Some(text(&mut d)?.into_owned()),
+            "seq" => seq = Some(d.u64()?),
+            "ts" => ts = Some(d.u64()?),
+            "nonce" => nonce = Some(text(&mut d)?.into_owned()),
+            "payload" => payload = Some(byte_string(&mut d)?),
+            "hmac" => frame.hmac = nullable(&mut d, |d| text(d).map(Cow::into_owned))?,
+            "sig" => frame.sig = nullable(&mut d, |d| byte_string(d).map(Vec::from))?,
+            "key_epoch" => frame.key_epoch = d.uint()?,
+            "compressed" => {
+                frame.compressed = nullable(&mut d, |d| text(d)?.parse().map_err(CodecError::Decode))?;
+            }
+            "id" => {
+                frame.id = nullable(&mut d, |d| {
+                    Ulid::from_string(&text(d)?).map_err(|e| CodecError::Decode(format!("Invalid message ID: {}", e)))
+                })?;
+            }
+            "priority" => frame.priority = text(&mut d)?.parse().map_err(CodecError::Decode)?,
+            "content_type" => frame.content_type = text(&mut d)?.parse().map_err(CodecError::Decode)?,
+            _ => {
+                let key = key.into_owned();
+                let value = d
+                    .value()
+                    .map_err(|e| CodecError::Decode(format!("Invalid extension {}: {}", key, e)))?;
+                frame.extensions.insert(key, value);
+            }
+        }
+    }
+    if d.pos != data.len() {
+        return Err(CodecError::Decode("Trailing data after frame".into()));
+    }
+
+    let missing = |field: &str| CodecError::Decode(format!("missing field `{}`", field));
+    frame.version = version.ok_or_else(|| missing("version"))?;
+    frame.frame_type = frame_type.ok_or_else(|| missing("type"))?;
+    frame.from = from.ok_or_else(|| missing("from"))?;
+    frame.to = to.ok_or_else(|| missing("to"))?;
+    frame.seq = seq.ok_or_else(|| missing("seq"))?;
+    frame.ts = ts.ok_or_else(|| missing("ts"))?;
+    frame.nonce = nonce.ok_or_else(|| missing("nonce"))?;
+    frame.payload = payload.ok_or_else(|| missing("payload"))?;
+    check_version(&frame).map_err(CodecError::Decode)?;
+    Ok(frame)
+}
+
+fn decode_frame_type(d: &mut Reader<'_>) -> Result<FrameType, CodecError> {
+    if let Header::Text(_) = d.peek()? {
+        return Ok(FrameType::from_name(&text(d)?));
+    }
+    let code = d.u64()?;
+    u8::try_from(code)
+        .map(FrameType::from_code)
+        .map_err(|_| CodecError::Decode(format!("frame type code out of range: {}", code)))
+}
+
+/// Read a text string, borrowed unless it is chunked
+fn text<'b>(d: &mut Reader<'b>) -> Result<Cow<'b, str>, CodecError> {
+    let utf8 = |bytes| std::str::from_utf8(bytes).map_err(|e| CodecError::Decode(e.to_string()));
+    match d.header()? {
+        Header::Text(Some(len)) => d.take(len).and_then(utf8).map(Cow::Borrowed),
+        Header::Text(None) => {
+            let mut out = String::new();
+            loop {
+                match d.header()? {
+                    Header::Break => return Ok(Cow::Owned(out)),
+                    Header::Text(Some(len)) => out.push_str(utf8(d.take(len)?)?),
+                    other => return Err(unexpected("a text chunk", other)),
+                }
+            }
+        }
+        other => Err(unexpected("a text string", other)),
+    }
+}
+
+/// Read bytes encoded as an array of integers or as a byte string
+fn byte_string(d: &mut Reader<'_>) -> Result<Bytes, CodecError> {
+    let len = match d.header()? {
+        Header::Bytes(Some(len)) => return d.take(len).map(Bytes::copy_from_slice),
+        Header::Bytes(None) => {
+            let mut out = Vec::new();
+            loop {
+                match d.header()? {
+                    Header::Break => return Ok(out.into()),
+                    Header::Bytes(Some(len)) => out.extend_from_slice(d.take(len)?),
+                    other => return Err(unexpected("a byte string chunk", other)),
+                }
+            }
+        }
+        Header::Array(len) => len,
+        other => return Err(unexpected("a byte string", other)),
+    };
+
+    let input = d.data;
+    let mut out = Vec::with_capacity(len.unwrap_or(0).min(input.len()));
+    loop {
+        match len {
+            Some(n) if out.len() == n => break,
+            None if input.get(d.pos) == Some(&0xff) => {
+                d.pos += 1;
+                break;
+            }
+            _ => {}
+        }
+        // Fast path for the shortest encodings
+        match input.get(d.pos) {
+            Some(&b) if b < 24 => {
+                out.push(b);
+                d.pos += 1;
+            }
+            Some(0x18) if d.pos + 1 < input.len() => {
+                out.push(input[d.pos + 1]);
+                d.pos += 2;
+            }
+            _ => out.push(d.uint()?),
+        }
+    }
+    Ok(out.into())
+}
+
+fn nullable<'b, T>(
+    d: &mut Reader<'b>,
+    read: impl FnOnce(&mut Reader<'b>) -> Result<T, CodecError>,
+) -> Result<Option<T>, CodecError> {
+    if d.peek()? == Header::Simple(simple::NULL) {
+        d.header()?;
+        return Ok(None);
+    }
+    read(d).map(Some)
+}
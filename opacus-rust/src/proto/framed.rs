@@ -0,0 +1,270 @@
+//! Length-prefixed frame streams
+//!
+//! Each frame is written as a 4-byte big-endian length followed by the
+//! encoded frame. Used on reliable byte streams (QUIC streams, TCP) where
+//! datagram boundaries are not available.
+//! 
+//! [`FramedRead`] and [`FramedWrite`] use the runtime-independent
+//! `futures::io` traits, so they work on any executor; tokio streams need
+//! `tokio_util::compat`.
+
+use bytes::{Buf, BufMut, BytesMut};
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use crate::types::OpacusFrame;
+use super::{CBORCodec, CodecError, FrameCodec};
+
+/// Default maximum encoded frame length on streams (1 MiB)
+pub const DEFAULT_MAX_FRAME_LEN: usize = 1024 * 1024;
+
+/// Length prefix size in bytes
+const LEN_PREFIX: usize = 4;
+
+/// Most buffer space reserved ahead of a partially received frame, so a
+/// length prefix alone cannot make the reader allocate the full limit
+const MAX_RESERVE: usize = 64 * 1024;
+
+/// Bytes requested from the reader at a time
+const READ_CHUNK: usize = 8 * 1024;
+
+/// Buffer-level length-prefixed frame codec
+pub struct LengthPrefixedCodec {
+    codec: &'static dyn FrameCodec,
+    max_len: usize,
+}
+
+impl LengthPrefixedCodec {
+    /// Create codec with the given frame encoding
+    pub fn new(codec: &'static dyn FrameCodec) -> Self {
+        Self {
+            codec,
+            max_len: DEFAULT_MAX_FRAME_LEN,
+        }
+    }
+    
+    /// Set maximum encoded frame length
+    pub fn with_max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+    
+    /// Decode the next frame from a buffer
+    /// 
+    /// # Returns
+    /// `None` if the buffer does not yet hold a complete frame; consumed bytes
+    /// are removed from `buf`
+    pub fn decode(&self, buf: &mut BytesMut) -> Result<Option<OpacusFrame>, CodecError> {
+        if buf.len() < LEN_PREFIX {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes(buf[..LEN_PREFIX].try_into().unwrap()) as usize;
+        if len > self.max_len {
+            return Err(CodecError::LimitExceeded(format!("frame of {} bytes exceeds {}", len, self.max_len)));
+        }
+        if buf.len() < LEN_PREFIX + len {
+            buf.reserve((LEN_PREFIX + len - buf.len()).min(MAX_RESERVE));
+            return Ok(None);
+        }
+        buf.advance(LEN_PREFIX);
+        let body = buf.split_to(len);
+        self.codec.decode(&body).map(Some)
+    }
+    
+    /// Encode frame into a buffer
+    pub fn encode(&self, frame: &OpacusFrame, buf: &mut BytesMut) -> Result<(), CodecError> {
+        let body = self.codec.encode(frame)?;
+        if body.len() > self.max_len {
+            return Err(CodecError::LimitExceeded(format!("frame of {} bytes exceeds {}", body.len(), self.max_len)));
+        }
+        buf.reserve(LEN_PREFIX + body.len());
+        buf.put_u32(body.len() as u32);
+        buf.put_slice(&body);
+        Ok(())
+    }
+}
+
+impl Default for LengthPrefixedCodec {
+    fn default() -> Self {
+        Self::new(&CBORCodec)
+    }
+}
+
+/// Reads length-prefixed frames from an async byte stream
+pub struct FramedRead<R> {
+    inner: R,
+    codec: LengthPrefixedCodec,
+    buf: BytesMut,
+    chunk: Box<[u8]>,
+}
+
+impl<R: AsyncRead + Unpin> FramedRead<R> {
+    /// Wrap a reader using CBOR frames
+    pub fn new(inner: R) -> Self {
+        Self::with_codec(inner, LengthPrefixedCodec::default())
+    }
+    
+    /// Wrap a reader with a custom codec
+    pub fn with_codec(inner: R, codec: LengthPrefixedCodec) -> Self {
+        Self {
+            inner,
+            codec,
+            buf: BytesMut::with_capacity(READ_CHUNK),
+            chunk: vec![0; READ_CHUNK].into_boxed_slice(),
+        }
+    }
+    
+    /// Read the next frame
+    /// 
+    /// # Returns
+    /// `None` when the stream ends cleanly between frames; an error if it
+    /// ends inside a frame
+    /// 
+    /// Cancel-safe: bytes are only buffered once a read has completed.
+    pub async fn read_frame(&mut self) -> Result<Option<OpacusFrame>, CodecError> {
+        loop {
+            if let Some(frame) = self.codec.decode(&mut self.buf)? {
+                return Ok(Some(frame));
+            }
+            let n = self.inner.read(&mut self.chunk).await?;
+            if n == 0 {
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                return Err(CodecError::Io(std::io::ErrorKind::UnexpectedEof.into()));
+            }
+            self.buf.extend_from_slice(&self.chunk[..n]);
+        }
+    }
+    
+    /// Get the underlying reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+/// Writes length-prefixed frames to an async byte stream
+pub struct FramedWrite<W> {
+    inner: W,
+    codec: LengthPrefixedCodec,
+    buf: BytesMut,
+}
+
+impl<W: AsyncWrite + Unpin> FramedWrite<W> {
+    /// Wrap a writer using CBOR frames
+    pub fn new(inner: W) -> Self {
+        Self::with_codec(inner, LengthPrefixedCodec::default())
+    }
+    
+    /// Wrap a writer with a custom codec
+    pub fn with_codec(inner: W, codec: LengthPrefixedCodec) -> Self {
+        Self {
+            inner,
+            codec,
+            buf: BytesMut::new(),
+        }
+    }
+    
+    /// Write one frame and flush
+    pub async fn write_frame(&mut self, frame: &OpacusFrame) -> Result<(), CodecError> {
+        self.buf.clear();
+        self.codec.encode(frame, &mut self.buf)?;
+        self.inner.write_all(&self.buf).await?;
+        self.inner.flush().await?;
+        Ok(())
+    }
+    
+    /// Get the underlying writer
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn frame(seq: u64) -> OpacusFrame {
+        OpacusFrame { payload: vec![seq as u8; 300].into(), ..OpacusFrame::test(seq) }
+    }
+    
+    #[test]
+    fn test_partial_buffers() {
+        let codec = LengthPrefixedCodec::default();
+        let mut encoded = BytesMut::new();
+        codec.encode(&frame(1), &mut encoded).unwrap();
+        codec.encode(&frame(2), &mut encoded).unwrap();
+        
+        // Feed one byte at a time
+        let mut buf = BytesMut::new();
+        let mut seqs = Vec::new();
+        for byte in encoded.iter() {
+            buf.put_u8(*byte);
+            if let Some(f) = codec.decode(&mut buf).unwrap() {
+                seqs.push(f.seq);
+            }
+        }
+        assert_eq!(seqs, vec![1, 2]);
+        assert!(buf.is_empty());
+    }
+    
+    #[test]
+    fn test_max_len() {
+        let codec = LengthPrefixedCodec::default().with_max_len(64);
+        let mut buf = BytesMut::new();
+        assert!(matches!(codec.encode(&frame(1), &mut buf), Err(CodecError::LimitExceeded(_))));
+        
+        // Oversized prefix is rejected before the body arrives
+        let mut buf = BytesMut::from(&u32::MAX.to_be_bytes()[..]);
+        assert!(matches!(codec.decode(&mut buf), Err(CodecError::LimitExceeded(_))));
+        
+        // A prefix within the limit reserves at most MAX_RESERVE ahead of the body
+        let mut buf = BytesMut::from(&(DEFAULT_MAX_FRAME_LEN as u32).to_be_bytes()[..]);
+        assert!(LengthPrefixedCodec::default().decode(&mut buf).unwrap().is_none());
+        assert!(buf.capacity() <= LEN_PREFIX + MAX_RESERVE);
+    }
+    
+    #[test]
+    fn test_framed_stream() {
+        // No runtime needed
+        futures::executor::block_on(async {
+            let mut writer = FramedWrite::new(Vec::new());
+            for seq in 0..5 {
+                writer.write_frame(&frame(seq)).await.unwrap();
+            }
+            let bytes = writer.into_inner();
+            
+            let mut reader = FramedRead::new(futures::io::Cursor::new(bytes.clone()));
+            for seq in 0..5 {
+                assert_eq!(reader.read_frame().await.unwrap().unwrap().seq, seq);
+            }
+            assert!(reader.read_frame().await.unwrap().is_none());
+            
+            // Stream ending inside a frame
+            let mut reader = FramedRead::new(&bytes[..bytes.len() - 1]);
+            for _ in 0..4 {
+                reader.read_frame().await.unwrap();
+            }
+            assert!(matches!(reader.read_frame().await, Err(CodecError::Io(_))));
+        });
+    }
+    
+    #[cfg(feature = "client")]
+    #[tokio::test]
+    async fn test_tokio_stream() {
+        use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
+        
+        let (client, server) = tokio::io::duplex(64);
+        let mut writer = FramedWrite::new(client.compat_write());
+        let mut reader = FramedRead::new(server.compat());
+        
+        let send = tokio::spawn(async move {
+            for seq in 0..5 {
+                writer.write_frame(&frame(seq)).await.unwrap();
+            }
+        });
+        for seq in 0..5 {
+            assert_eq!(reader.read_frame().await.unwrap().unwrap().seq, seq);
+        }
+        send.await.unwrap();
+        assert!(reader.read_frame().await.unwrap().is_none());
+    }
+}
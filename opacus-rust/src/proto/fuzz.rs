@@ -0,0 +1,166 @@
+//! Random frames for fuzzing and property tests
+//!
+//! With the `arbitrary` feature `OpacusFrame` implements
+//! [`arbitrary::Arbitrary`] (cargo-fuzz), with the `proptest` feature
+//! [`proptest::arbitrary::Arbitrary`] (`any::<OpacusFrame>()`). Generated
+//! frames are always encodable: the version is supported, version 2 frames
+//! carry an ID and extension keys never shadow a frame field. Extension
+//! values are integers, strings, byte strings, booleans or null, which every
+//! wire format round-trips.
+
+use std::collections::BTreeMap;
+use crate::compression::Compression;
+
+/// Frame field names, which extensions cannot use
+const FIELDS: [&str; 15] = [
+    "version", "type", "from", "to", "seq", "ts", "nonce", "id", "payload", "hmac", "sig",
+    "key_epoch", "compressed", "priority", "content_type",
+];
+
+fn compression(code: u8) -> Option<Compression> {
+    match code {
+        1 => Some(Compression::Zstd),
+        2 => Some(Compression::Lz4),
+        _ => None,
+    }
+}
+
+fn drop_fields(mut extensions: BTreeMap<String, ciborium::Value>) -> BTreeMap<String, ciborium::Value> {
+    extensions.retain(|key, _| !FIELDS.contains(&key.as_str()));
+    extensions
+}
+
+#[cfg(feature = "arbitrary")]
+mod fuzzing {
+    use std::collections::BTreeMap;
+    use arbitrary::{Arbitrary, Result, Unstructured};
+    use crate::content::ContentType;
+    use crate::proto::{FRAME_VERSION, MIN_FRAME_VERSION};
+    use crate::qos::Priority;
+    use crate::types::{FrameType, OpacusFrame, Ulid};
+    use super::{compression, drop_fields};
+
+    fn extension_value(u: &mut Unstructured<'_>) -> Result<ciborium::Value> {
+        use ciborium::Value;
+        Ok(match u.int_in_range(0..=4)? {
+            0 => Value::Integer(i64::arbitrary(u)?.into()),
+            1 => Value::Text(String::arbitrary(u)?),
+            2 => Value::Bytes(Vec::arbitrary(u)?),
+            3 => Value::Bool(bool::arbitrary(u)?),
+            _ => Value::Null,
+        })
+    }
+
+    impl<'a> Arbitrary<'a> for OpacusFrame {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            let version = u.int_in_range(MIN_FRAME_VERSION..=FRAME_VERSION)?;
+            let id = match version {
+                1 => Option::<u128>::arbitrary(u)?,
+                _ => Some(u128::arbitrary(u)?),
+            };
+            let mut extensions = BTreeMap::new();
+            for _ in 0..u.int_in_range(0..=4)? {
+                extensions.insert(String::arbitrary(u)?, extension_value(u)?);
+            }
+            Ok(OpacusFrame {
+                version,
+                frame_type: FrameType::from_code(u8::arbitrary(u)?),
+                from: String::arbitrary(u)?,
+                to: String::arbitrary(u)?,
+                seq: u64::arbitrary(u)?,
+                ts: u64::arbitrary(u)?,
+                nonce: String::arbitrary(u)?,
+                payload: Vec::<u8>::arbitrary(u)?.into(),
+                hmac: Option::arbitrary(u)?,
+                sig: Option::arbitrary(u)?,
+                key_epoch: u32::arbitrary(u)?,
+                compressed: compression(u.int_in_range(0..=2)?),
+                id: id.map(Ulid),
+                priority: Priority::from_code(u.int_in_range(0..=3)?).unwrap_or_default(),
+                content_type: ContentType::from_code(u.int_in_range(0..=5)?).unwrap_or_default(),
+                extensions: drop_fields(extensions),
+            })
+        }
+    }
+}
+
+#[cfg(feature = "proptest")]
+mod property {
+    use proptest::arbitrary::{any, Arbitrary};
+    use proptest::collection::{btree_map, vec};
+    use proptest::option;
+    use proptest::strategy::{BoxedStrategy, Just, Strategy};
+    use crate::content::ContentType;
+    use crate::proto::{FRAME_VERSION, MIN_FRAME_VERSION};
+    use crate::qos::Priority;
+    use crate::types::{FrameType, OpacusFrame, Ulid};
+    use super::{compression, drop_fields};
+
+    fn extension_value() -> impl Strategy<Value = ciborium::Value> {
+        use ciborium::Value;
+        proptest::prop_oneof![
+            any::<i64>().prop_map(|v| Value::Integer(v.into())),
+            any::<String>().prop_map(Value::Text),
+            vec(any::<u8>(), 0..32).prop_map(Value::Bytes),
+            any::<bool>().prop_map(Value::Bool),
+            Just(Value::Null),
+        ]
+    }
+
+    impl Arbitrary for OpacusFrame {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(_: ()) -> Self::Strategy {
+            let header = (
+                MIN_FRAME_VERSION..=FRAME_VERSION,
+                any::<u8>(),
+                any::<String>(),
+                any::<String>(),
+                any::<u64>(),
+                any::<u64>(),
+                any::<String>(),
+                option::of(any::<u128>()),
+            );
+            let body = (
+                vec(any::<u8>(), 0..512),
+                option::of(any::<String>()),
+                option::of(vec(any::<u8>(), 0..96)),
+                any::<u32>(),
+                0u8..=2,
+                0u8..=3,
+                0u8..=5,
+                btree_map(any::<String>(), extension_value(), 0..4),
+            );
+            (header, body)
+                .prop_map(|(header, body)| {
+                    let (version, frame_type, from, to, seq, ts, nonce, id) = header;
+                    let (payload, hmac, sig, key_epoch, compressed, priority, content_type, extensions) = body;
+                    // Version 2 requires an ID
+                    let id = match version {
+                        1 => id,
+                        _ => Some(id.unwrap_or(ts.into())),
+                    };
+                    OpacusFrame {
+                        version,
+                        frame_type: FrameType::from_code(frame_type),
+                        from,
+                        to,
+                        seq,
+                        ts,
+                        nonce,
+                        payload: payload.into(),
+                        hmac,
+                        sig,
+                        key_epoch,
+                        compressed: compression(compressed),
+                        id: id.map(Ulid),
+                        priority: Priority::from_code(priority).unwrap_or_default(),
+                        content_type: ContentType::from_code(content_type).unwrap_or_default(),
+                        extensions: drop_fields(extensions),
+                    }
+                })
+                .boxed()
+        }
+    }
+}
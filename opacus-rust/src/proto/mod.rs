@@ -0,0 +1,1063 @@
+//! Frame codecs
+//!
+//! Frames are CBOR-encoded by default. MessagePack (`msgpack` feature) and
+//! Protobuf (`protobuf` feature) are available for deployments integrating
+//! with other stacks; the format of a connection is chosen by its QUIC ALPN.
+
+mod cbor;
+pub mod framed;
+mod versioned;
+#[cfg(any(feature = "arbitrary", feature = "proptest"))]
+mod fuzz;
+
+pub use framed::*;
+pub use versioned::{FRAME_VERSION, MIN_FRAME_VERSION};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::compression::Compression;
+use crate::qos::Priority;
+use crate::types::{FrameType, OpacusFrame};
+
+/// Frame encoding or decoding error
+#[derive(Debug, thiserror::Error)]
+pub enum CodecError {
+    /// Frame could not be encoded
+    #[error("encode failed: {0}")]
+    Encode(String),
+    /// Bytes are not a valid frame
+    #[error("decode failed: {0}")]
+    Decode(String),
+    /// Frame exceeds a size or nesting limit
+    #[error("limit exceeded: {0}")]
+    LimitExceeded(String),
+    /// Stream I/O failed
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Serialization format for frames on the wire
+pub trait FrameCodec: Send + Sync {
+    /// Wire format implemented by this codec
+    fn format(&self) -> WireFormat;
+    
+    /// Encode frame to bytes
+    fn encode(&self, frame: &OpacusFrame) -> Result<Vec<u8>, CodecError>;
+    
+    /// Decode bytes to frame
+    fn decode(&self, data: &[u8]) -> Result<OpacusFrame, CodecError>;
+    
+    /// Exact length of `encode(frame)`
+    /// 
+    /// Encodes the frame unless the codec can compute the size directly.
+    fn encoded_size(&self, frame: &OpacusFrame) -> Result<usize, CodecError> {
+        self.encode(frame).map(|data| data.len())
+    }
+}
+
+/// Frame serialization formats
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum WireFormat {
+    /// CBOR (always available)
+    #[default]
+    Cbor,
+    /// MessagePack with named fields (`msgpack` feature)
+    MsgPack,
+    /// Protobuf, see `proto/opacus.proto` (`protobuf` feature)
+    Protobuf,
+}
+
+impl WireFormat {
+    /// Formats compiled into this build, CBOR first
+    pub fn supported() -> Vec<WireFormat> {
+        let mut formats = vec![WireFormat::Cbor];
+        if cfg!(feature = "msgpack") {
+            formats.push(WireFormat::MsgPack);
+        }
+        if cfg!(feature = "protobuf") {
+            formats.push(WireFormat::Protobuf);
+        }
+        formats
+    }
+    
+    /// QUIC ALPN protocol identifier
+    pub fn alpn(&self) -> &'static [u8] {
+        match self {
+            WireFormat::Cbor => b"opacus",
+            WireFormat::MsgPack => b"opacus-msgpack",
+            WireFormat::Protobuf => b"opacus-protobuf",
+        }
+    }
+    
+    /// Format for a negotiated ALPN (CBOR when none was negotiated)
+    pub fn from_alpn(alpn: Option<&[u8]>) -> Option<WireFormat> {
+        let Some(alpn) = alpn else {
+            return Some(WireFormat::Cbor);
+        };
+        [WireFormat::Cbor, WireFormat::MsgPack, WireFormat::Protobuf]
+            .into_iter()
+            .find(|f| f.alpn() == alpn)
+    }
+    
+    /// Get codec for this format, if compiled in
+    pub fn codec(&self) -> Option<&'static dyn FrameCodec> {
+        match self {
+            WireFormat::Cbor => Some(&CBORCodec),
+            #[cfg(feature = "msgpack")]
+            WireFormat::MsgPack => Some(&MsgPackCodec),
+            #[cfg(feature = "protobuf")]
+            WireFormat::Protobuf => Some(&ProtobufCodec),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+}
+
+/// Default limit on byte strings and arrays (payload, signature) when decoding
+pub const DEFAULT_MAX_PAYLOAD: usize = 1024 * 1024;
+
+/// Default limit on text strings (IDs, nonce, HMAC) when decoding
+pub const DEFAULT_MAX_STRING_LEN: usize = 4096;
+
+/// Maximum CBOR nesting depth accepted when decoding a frame
+const MAX_CBOR_DEPTH: usize = 8;
+
+/// Maximum number of entries in a CBOR map
+const MAX_CBOR_MAP_ENTRIES: u64 = 64;
+
+/// CBOR codec for binary frame serialization
+pub struct CBORCodec;
+
+impl CBORCodec {
+    /// Encode frame to CBOR bytes
+    /// 
+    /// # Arguments
+    /// * `frame` - Frame to encode
+    /// 
+    /// # Returns
+    /// CBOR-encoded bytes
+    pub fn encode(frame: &OpacusFrame) -> Result<Vec<u8>, CodecError> {
+        let mut out = Vec::with_capacity(Self::encoded_size(frame)?);
+        cbor::encode(frame, &mut out)?;
+        Ok(out)
+    }
+    
+    /// Encode frame to deterministic CBOR bytes
+    /// 
+    /// Use this for anything that is hashed or signed: map keys are sorted
+    /// (shorter first, then bytewise), lengths are definite and integers use
+    /// their shortest form, so every SDK produces the same bytes for the same
+    /// frame. The result decodes with `decode` like any other frame.
+    /// 
+    /// # Arguments
+    /// * `frame` - Frame to encode
+    /// 
+    /// # Returns
+    /// Canonical CBOR-encoded bytes
+    pub fn encode_canonical(frame: &OpacusFrame) -> Result<Vec<u8>, CodecError> {
+        // Same entries as `encode`, in a definite-length map (header of at most 3 bytes)
+        let mut out = Vec::with_capacity(Self::encoded_size(frame)? + 1);
+        cbor::encode_canonical(frame, &mut out)?;
+        Ok(out)
+    }
+    
+    /// Encode a CBOR value deterministically, as `encode_canonical` encodes
+    /// extension values
+    /// 
+    /// # Arguments
+    /// * `value` - Value to encode
+    /// 
+    /// # Returns
+    /// Canonical CBOR-encoded bytes
+    pub fn encode_value_canonical(value: &ciborium::Value) -> Result<Vec<u8>, CodecError> {
+        cbor::encode_value_canonical(value)
+    }
+    
+    /// Decode CBOR bytes to frame
+    /// 
+    /// # Arguments
+    /// * `data` - CBOR bytes
+    /// 
+    /// # Returns
+    /// Decoded `OpacusFrame`
+    pub fn decode(data: &[u8]) -> Result<OpacusFrame, CodecError> {
+        cbor::decode(data)
+    }
+    
+    /// Decode CBOR bytes to frame, enforcing size limits
+    /// 
+    /// The input is scanned before anything is allocated: byte strings and
+    /// arrays longer than `max_payload`, text strings longer than
+    /// `max_string_len`, declared lengths beyond the input, and nesting deeper
+    /// than 8 levels are rejected.
+    /// 
+    /// # Arguments
+    /// * `data` - CBOR bytes
+    /// * `max_payload` - Maximum byte string / array length
+    /// * `max_string_len` - Maximum text string length in bytes
+    pub fn decode_with_limits(
+        data: &[u8],
+        max_payload: usize,
+        max_string_len: usize,
+    ) -> Result<OpacusFrame, CodecError> {
+        let mut scanner = CborScanner {
+            data,
+            pos: 0,
+            max_payload: max_payload as u64,
+            max_string_len: max_string_len as u64,
+        };
+        scanner.item(0)?;
+        if scanner.pos != data.len() {
+            return Err(CodecError::Decode("Trailing data after frame".into()));
+        }
+        Self::decode(data)
+    }
+    
+    /// Exact encoded size of a frame, without encoding it
+    /// 
+    /// Lets senders choose between a datagram and a stream before encoding.
+    /// 
+    /// # Arguments
+    /// * `frame` - Frame to measure
+    /// 
+    /// # Returns
+    /// Length of `encode(frame)` in bytes
+    pub fn encoded_size(frame: &OpacusFrame) -> Result<usize, CodecError> {
+        cbor::encoded_size(frame)
+    }
+    
+    /// Estimate encoded size (approximation)
+    #[deprecated(note = "ignores header size; use `encoded_size`")]
+    pub fn estimate_size(frame: &OpacusFrame) -> usize {
+        // Rough estimate: headers ~100 bytes + payload
+        100 + frame.payload.len()
+    }
+}
+
+impl FrameCodec for CBORCodec {
+    fn format(&self) -> WireFormat {
+        WireFormat::Cbor
+    }
+    
+    fn encode(&self, frame: &OpacusFrame) -> Result<Vec<u8>, CodecError> {
+        CBORCodec::encode(frame)
+    }
+    
+    fn decode(&self, data: &[u8]) -> Result<OpacusFrame, CodecError> {
+        CBORCodec::decode_with_limits(data, DEFAULT_MAX_PAYLOAD, DEFAULT_MAX_STRING_LEN)
+    }
+    
+    fn encoded_size(&self, frame: &OpacusFrame) -> Result<usize, CodecError> {
+        CBORCodec::encoded_size(frame)
+    }
+}
+
+/// Walks CBOR item headers without allocating
+struct CborScanner<'a> {
+    data: &'a [u8],
+    pos: usize,
+    max_payload: u64,
+    max_string_len: u64,
+}
+
+impl CborScanner<'_> {
+    fn remaining(&self) -> u64 {
+        (self.data.len() - self.pos) as u64
+    }
+    
+    fn byte(&mut self) -> Result<u8, CodecError> {
+        let b = *self.data.get(self.pos).ok_or_else(|| CodecError::Decode("Unexpected end of CBOR".into()))?;
+        self.pos += 1;
+        Ok(b)
+    }
+    
+    /// Read an item head: major type and argument (`None` for indefinite length)
+    fn head(&mut self) -> Result<(u8, Option<u64>), CodecError> {
+        let initial = self.byte()?;
+        let (major, info) = (initial >> 5, initial & 0x1f);
+        let arg = match info {
+            0..=23 => Some(info as u64),
+            24..=27 => {
+                let len = 1usize << (info - 24);
+                let mut value = 0u64;
+                for _ in 0..len {
+                    value = (value << 8) | self.byte()? as u64;
+                }
+                Some(value)
+            }
+            31 => None,
+            _ => return Err(CodecError::Decode(format!("Invalid CBOR additional info: {}", info))),
+        };
+        Ok((major, arg))
+    }
+    
+    fn check_len(&self, len: u64, limit: u64, what: &str) -> Result<(), CodecError> {
+        if len > limit {
+            return Err(CodecError::LimitExceeded(format!("{} of {} exceeds {}", what, len, limit)));
+        }
+        Ok(())
+    }
+    
+    fn skip(&mut self, len: u64) -> Result<(), CodecError> {
+        if len > self.remaining() {
+            return Err(CodecError::Decode("Declared length exceeds input".into()));
+        }
+        self.pos += len as usize;
+        Ok(())
+    }
+    
+    fn at_break(&mut self) -> Result<bool, CodecError> {
+        if self.data.get(self.pos) == Some(&0xff) {
+            self.pos += 1;
+            return Ok(true);
+        }
+        if self.pos >= self.data.len() {
+            return Err(CodecError::Decode("Unterminated indefinite-length item".into()));
+        }
+        Ok(false)
+    }
+    
+    fn item(&mut self, depth: usize) -> Result<(), CodecError> {
+        if depth > MAX_CBOR_DEPTH {
+            return Err(CodecError::LimitExceeded(format!("nesting deeper than {}", MAX_CBOR_DEPTH)));
+        }
+        let (major, arg) = self.head()?;
+        match (major, arg) {
+            (0 | 1, Some(_)) => Ok(()),
+            (2 | 3, Some(len)) => {
+                let (limit, what) = if major == 2 { (self.max_payload, "byte string") } else { (self.max_string_len, "text string") };
+                self.check_len(len, limit, what)?;
+                self.skip(len)
+            }
+            (2 | 3, None) => {
+                let (limit, what) = if major == 2 { (self.max_payload, "byte string") } else { (self.max_string_len, "text string") };
+                let mut total = 0u64;
+                while !self.at_break()? {
+                    let (chunk_major, chunk_len) = self.head()?;
+                    let chunk_len = match chunk_len {
+                        Some(len) if chunk_major == major => len,
+                        _ => return Err(CodecError::Decode("Invalid indefinite-length string chunk".into())),
+                    };
+                    total = total.saturating_add(chunk_len);
+                    self.check_len(total, limit, what)?;
+                    self.skip(chunk_len)?;
+                }
+                Ok(())
+            }
+            (4, Some(len)) => {
+                self.check_len(len, self.max_payload, "array")?;
+                // Every element takes at least one byte
+                if len > self.remaining() {
+                    return Err(CodecError::Decode("Declared length exceeds input".into()));
+                }
+                (0..len).try_for_each(|_| self.item(depth + 1))
+            }
+            (5, Some(len)) => {
+                self.check_len(len, MAX_CBOR_MAP_ENTRIES, "map")?;
+                if len.saturating_mul(2) > self.remaining() {
+                    return Err(CodecError::Decode("Declared length exceeds input".into()));
+                }
+                (0..len * 2).try_for_each(|_| self.item(depth + 1))
+            }
+            (4 | 5, None) => {
+                let (limit, what) = if major == 4 { (self.max_payload, "array") } else { (MAX_CBOR_MAP_ENTRIES, "map") };
+                let mut count = 0u64;
+                while !self.at_break()? {
+                    count += 1;
+                    self.check_len(count, limit, what)?;
+                    self.item(depth + 1)?;
+                    if major == 5 {
+                        self.item(depth + 1)?;
+                    }
+                }
+                Ok(())
+            }
+            (6, Some(_)) => self.item(depth + 1),
+            (7, Some(_)) => Ok(()),
+            _ => Err(CodecError::Decode(format!("Unexpected CBOR item (major type {})", major))),
+        }
+    }
+}
+
+/// MessagePack codec (fields encoded by name, like CBOR)
+#[cfg(feature = "msgpack")]
+pub struct MsgPackCodec;
+
+#[cfg(feature = "msgpack")]
+impl FrameCodec for MsgPackCodec {
+    fn format(&self) -> WireFormat {
+        WireFormat::MsgPack
+    }
+    
+    fn encode(&self, frame: &OpacusFrame) -> Result<Vec<u8>, CodecError> {
+        rmp_serde::to_vec_named(frame).map_err(|e| CodecError::Encode(e.to_string()))
+    }
+    
+    fn decode(&self, data: &[u8]) -> Result<OpacusFrame, CodecError> {
+        rmp_serde::from_slice(data).map_err(|e| CodecError::Decode(e.to_string()))
+    }
+}
+
+/// Protobuf codec using the `Frame` message from `proto/opacus.proto`
+#[cfg(feature = "protobuf")]
+pub struct ProtobufCodec;
+
+#[cfg(feature = "protobuf")]
+mod protobuf {
+    use std::collections::BTreeMap;
+    use prost::Message;
+    use crate::compression::Compression;
+    use crate::content::ContentType;
+    use crate::qos::Priority;
+    use crate::types::{FrameType, OpacusFrame, Ulid};
+    use super::CodecError;
+    
+    #[derive(Clone, PartialEq, Message)]
+    struct Frame {
+        #[prost(uint32, tag = "1")]
+        version: u32,
+        #[prost(uint32, tag = "2")]
+        frame_type: u32,
+        #[prost(string, tag = "3")]
+        from: String,
+        #[prost(string, tag = "4")]
+        to: String,
+        #[prost(uint64, tag = "5")]
+        seq: u64,
+        #[prost(uint64, tag = "6")]
+        ts: u64,
+        #[prost(string, tag = "7")]
+        nonce: String,
+        #[prost(bytes = "bytes", tag = "8")]
+        payload: bytes::Bytes,
+        #[prost(string, optional, tag = "9")]
+        hmac: Option<String>,
+        #[prost(bytes = "vec", optional, tag = "10")]
+        sig: Option<Vec<u8>>,
+        #[prost(uint32, tag = "11")]
+        key_epoch: u32,
+        #[prost(string, optional, tag = "12")]
+        compressed: Option<String>,
+        #[prost(btree_map = "string, bytes", tag = "13")]
+        extensions: BTreeMap<String, Vec<u8>>,
+        #[prost(bytes = "vec", optional, tag = "14")]
+        id: Option<Vec<u8>>,
+        #[prost(uint32, tag = "15")]
+        priority: u32,
+        #[prost(uint32, tag = "16")]
+        content_type: u32,
+    }
+    
+    pub(super) fn encode(frame: &OpacusFrame) -> Result<Vec<u8>, CodecError> {
+        super::versioned::check_version(frame).map_err(CodecError::Encode)?;
+        Ok(Frame {
+            version: frame.version as u32,
+            frame_type: frame.frame_type.code() as u32,
+            from: frame.from.clone(),
+            to: frame.to.clone(),
+            seq: frame.seq,
+            ts: frame.ts,
+            nonce: frame.nonce.clone(),
+            payload: frame.payload.clone(),
+            hmac: frame.hmac.clone(),
+            sig: frame.sig.clone(),
+            key_epoch: frame.key_epoch,
+            compressed: frame.compressed.map(|c| c.as_str().to_string()),
+            id: frame.id.map(|id| id.to_bytes().to_vec()),
+            priority: frame.priority.code() as u32,
+            content_type: frame.content_type.code() as u32,
+            // Extension values are carried as CBOR
+            extensions: frame.extensions
+                .iter()
+                .filter_map(|(k, v)| {
+                    let mut value = Vec::new();
+                    ciborium::ser::into_writer(v, &mut value).ok()?;
+                    Some((k.clone(), value))
+                })
+                .collect(),
+        }
+        .encode_to_vec())
+    }
+    
+    pub(super) fn decode(data: &[u8]) -> Result<OpacusFrame, CodecError> {
+        let msg = Frame::decode(data).map_err(|e| CodecError::Decode(e.to_string()))?;
+        let version = u8::try_from(msg.version)
+            .map_err(|_| CodecError::Decode(format!("Invalid version: {}", msg.version)))?;
+        let frame_type = u8::try_from(msg.frame_type)
+            .map(FrameType::from_code)
+            .map_err(|_| CodecError::Decode(format!("Invalid frame type: {}", msg.frame_type)))?;
+        let compressed = msg.compressed
+            .map(|c| c.parse::<Compression>().map_err(CodecError::Decode))
+            .transpose()?;
+        let id = msg.id
+            .map(|id| {
+                <[u8; 16]>::try_from(id.as_slice())
+                    .map(Ulid::from_bytes)
+                    .map_err(|_| CodecError::Decode(format!("Invalid message ID length: {}", id.len())))
+            })
+            .transpose()?;
+        let priority = u8::try_from(msg.priority)
+            .ok()
+            .and_then(Priority::from_code)
+            .ok_or_else(|| CodecError::Decode(format!("Invalid priority: {}", msg.priority)))?;
+        let content_type = u8::try_from(msg.content_type)
+            .ok()
+            .and_then(ContentType::from_code)
+            .ok_or_else(|| CodecError::Decode(format!("Invalid content type: {}", msg.content_type)))?;
+        let extensions = msg.extensions
+            .into_iter()
+            .map(|(k, v)| {
+                ciborium::de::from_reader(v.as_slice())
+                    .map(|v| (k, v))
+                    .map_err(|e| CodecError::Decode(format!("Invalid extension: {}", e)))
+            })
+            .collect::<Result<_, _>>()?;
+        let frame = OpacusFrame {
+            version,
+            frame_type,
+            from: msg.from,
+            to: msg.to,
+            seq: msg.seq,
+            ts: msg.ts,
+            nonce: msg.nonce,
+            payload: msg.payload,
+            hmac: msg.hmac,
+            sig: msg.sig,
+            key_epoch: msg.key_epoch,
+            compressed,
+            id,
+            priority,
+            content_type,
+            extensions,
+        };
+        super::versioned::check_version(&frame).map_err(CodecError::Decode)?;
+        Ok(frame)
+    }
+}
+
+#[cfg(feature = "protobuf")]
+impl FrameCodec for ProtobufCodec {
+    fn format(&self) -> WireFormat {
+        WireFormat::Protobuf
+    }
+    
+    fn encode(&self, frame: &OpacusFrame) -> Result<Vec<u8>, CodecError> {
+        protobuf::encode(frame)
+    }
+    
+    fn decode(&self, data: &[u8]) -> Result<OpacusFrame, CodecError> {
+        protobuf::decode(data)
+    }
+}
+
+/// Magic byte opening a routing header
+const ROUTING_MAGIC: u8 = b'O';
+
+/// Encoded routing header size in bytes
+pub const ROUTING_HEADER_LEN: usize = 24;
+
+/// Fixed-layout header prepended to every encoded frame
+/// 
+/// Lets the relay route a frame without decoding its body. Layout:
+/// magic `O` (1) | protocol version (1) | frame type code (1) |
+/// flags (1: compression in the low nibble, 0 none, 1 zstd, 2 lz4; priority
+/// code in the high nibble) | first 16 bytes of SHA-256(`to`) (16) |
+/// body length, big-endian (4).
+/// The magic byte cannot start a CBOR, MessagePack or Protobuf frame, so
+/// bodies sent without a header are still accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoutingHeader {
+    /// Protocol version of the frame
+    pub version: u8,
+    /// Frame type
+    pub frame_type: FrameType,
+    /// Payload compression of the frame
+    pub compressed: Option<Compression>,
+    /// Priority of the frame
+    pub priority: Priority,
+    /// Truncated SHA-256 of the recipient ID
+    pub to_hash: [u8; 16],
+    /// Length of the encoded body following the header
+    pub body_len: u32,
+}
+
+impl RoutingHeader {
+    /// Hash identifying a recipient in routing headers
+    pub fn hash_id(id: &str) -> [u8; 16] {
+        let digest = Sha256::digest(id.as_bytes());
+        digest[..16].try_into().expect("16-byte prefix")
+    }
+    
+    /// Serialize header
+    pub fn to_bytes(&self) -> [u8; ROUTING_HEADER_LEN] {
+        let mut out = [0u8; ROUTING_HEADER_LEN];
+        out[0] = ROUTING_MAGIC;
+        out[1] = self.version;
+        out[2] = self.frame_type.code();
+        out[3] = match self.compressed {
+            None => 0,
+            Some(Compression::Zstd) => 1,
+            Some(Compression::Lz4) => 2,
+        } | (self.priority.code() << 4);
+        out[4..20].copy_from_slice(&self.to_hash);
+        out[20..].copy_from_slice(&self.body_len.to_be_bytes());
+        out
+    }
+    
+    /// Split a datagram into header and body
+    /// 
+    /// # Returns
+    /// `None` if the data has no routing header, otherwise the header and the body slice
+    pub fn parse(data: &[u8]) -> Result<Option<(RoutingHeader, &[u8])>, CodecError> {
+        if data.first() != Some(&ROUTING_MAGIC) {
+            return Ok(None);
+        }
+        if data.len() < ROUTING_HEADER_LEN {
+            return Err(CodecError::Decode("Truncated routing header".into()));
+        }
+        let frame_type = FrameType::from_code(data[2]);
+        let compressed = match data[3] & 0x0f {
+            0 => None,
+            1 => Some(Compression::Zstd),
+            2 => Some(Compression::Lz4),
+            c => return Err(CodecError::Decode(format!("Unknown compression code: {}", c))),
+        };
+        let priority = Priority::from_code(data[3] >> 4)
+            .ok_or_else(|| CodecError::Decode(format!("Unknown priority code: {}", data[3] >> 4)))?;
+        let body_len = u32::from_be_bytes(data[20..24].try_into().unwrap());
+        let body = &data[ROUTING_HEADER_LEN..];
+        if body.len() != body_len as usize {
+            return Err(CodecError::Decode(format!(
+                "Body length mismatch: header says {}, got {}", body_len, body.len()
+            )));
+        }
+        let header = RoutingHeader {
+            version: data[1],
+            frame_type,
+            compressed,
+            priority,
+            to_hash: data[4..20].try_into().unwrap(),
+            body_len,
+        };
+        Ok(Some((header, body)))
+    }
+    
+    /// Encode frame with a routing header
+    pub fn encode(codec: &dyn FrameCodec, frame: &OpacusFrame) -> Result<Vec<u8>, CodecError> {
+        let body = codec.encode(frame)?;
+        let header = RoutingHeader {
+            version: frame.version,
+            frame_type: frame.frame_type,
+            compressed: frame.compressed,
+            priority: frame.priority,
+            to_hash: Self::hash_id(&frame.to),
+            body_len: u32::try_from(body.len()).map_err(|_| CodecError::Encode("Frame too large".into()))?,
+        };
+        let mut out = Vec::with_capacity(ROUTING_HEADER_LEN + body.len());
+        out.extend_from_slice(&header.to_bytes());
+        out.extend_from_slice(&body);
+        Ok(out)
+    }
+    
+    /// Length of `encode(codec, frame)`: the routing header plus the body
+    pub fn encoded_size(codec: &dyn FrameCodec, frame: &OpacusFrame) -> Result<usize, CodecError> {
+        Ok(ROUTING_HEADER_LEN + codec.encoded_size(frame)?)
+    }
+    
+    /// Decode frame with or without a routing header
+    /// 
+    /// Fails if the header disagrees with the decoded body.
+    pub fn decode(codec: &dyn FrameCodec, data: &[u8]) -> Result<OpacusFrame, CodecError> {
+        let Some((header, body)) = Self::parse(data)? else {
+            return codec.decode(data);
+        };
+        let frame = codec.decode(body)?;
+        if header.frame_type != frame.frame_type
+            || header.compressed != frame.compressed
+            || header.priority != frame.priority
+            || header.version != frame.version
+            || header.to_hash != Self::hash_id(&frame.to)
+        {
+            return Err(CodecError::Decode("Routing header does not match frame".into()));
+        }
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use crate::batch::FrameBatch;
+    use crate::content::ContentType;
+    use crate::types::Ulid;
+    
+    fn frame() -> OpacusFrame {
+        OpacusFrame {
+            nonce: "test-nonce".to_string(),
+            payload: vec![1, 2, 3, 4, 5].into(),
+            hmac: Some("deadbeef".to_string()),
+            sig: Some(vec![9, 8, 7, 6, 5]),
+            ..OpacusFrame::test(42)
+        }
+    }
+    
+    fn to_cbor(value: &ciborium::Value) -> Vec<u8> {
+        let mut out = Vec::new();
+        ciborium::ser::into_writer(value, &mut out).unwrap();
+        out
+    }
+    
+    /// Encodings produced by the previous CBOR implementation (serde_cbor 0.11)
+    const VECTOR_MINIMAL: &str = "bf6776657273696f6e016474797065636d73676466726f6d65616c69636562746f63626f62637365711\
+        82a6274731a499602d2656e6f6e63656a746573742d6e6f6e6365677061796c6f616485010203040564686d61636864656164626565666373\
+        6967850908070605ff";
+    const VECTOR_FULL: &str = "bf6776657273696f6e0164747970656572656b65796466726f6d65616c69636562746f63626f626373657\
+        1182a6274731a499602d2656e6f6e63656a746573742d6e6f6e6365677061796c6f6164840017181818ff64686d6163f66373696785090807\
+        0605696b65795f65706f6368036a636f6d70726573736564647a737464626964781a3030303134534330504a303431303631303530523347\
+        47323841687072696f7269747964686967686c636f6e74656e745f74797065646a736f6e64686f707307ff";
+    
+    #[test]
+    fn test_wire_vectors() {
+        let mut full = frame();
+        full.frame_type = FrameType::Rekey;
+        full.key_epoch = 3;
+        full.compressed = Some(Compression::Zstd);
+        full.id = Some(Ulid::from_parts(1234567890, 0x0102030405060708090a));
+        full.priority = Priority::High;
+        full.content_type = ContentType::Json;
+        full.payload = vec![0, 23, 24, 255].into();
+        full.hmac = None;
+        full.extensions.insert("hops".into(), ciborium::Value::Integer(7.into()));
+        
+        for (frame, vector) in [(frame(), VECTOR_MINIMAL), (full, VECTOR_FULL)] {
+            let vector = hex::decode(vector).unwrap();
+            assert_eq!(CBORCodec::encode(&frame).unwrap(), vector);
+            let decoded = CBORCodec::decode(&vector).unwrap();
+            assert_eq!(CBORCodec::encode(&decoded).unwrap(), vector);
+        }
+        // Empty map: required fields are missing
+        assert!(CBORCodec::decode(&[0xa0]).is_err());
+    }
+    
+    /// Canonical encodings; other SDKs must reproduce these byte for byte
+    const CANONICAL_MINIMAL: &str = "aa62746f63626f626274731a499602d263736571182a637369678509080706056466726f6d65616c69636564686d6163\
+        6864656164626565666474797065636d7367656e6f6e63656a746573742d6e6f6e6365677061796c6f61648501020304056776657273696f6e01";
+    const CANONICAL_FULL: &str = "b0626964781a3030303134534330504a3034313036313035305233474732384162746f63626f626274731a499602d2637365\
+        71182a637369674509080706056466726f6d65616c69636564686d6163f664686f70730764747970656572656b6579656e6f6e63656a746573742d\
+        6e6f6e636565726f757465a30a81f562617a1901f4647a6f6e65f93e00677061796c6f616444001718ff6776657273696f6e02687072696f726974\
+        796468696768696b65795f65706f6368036c636f6e74656e745f74797065646a736f6e";
+    
+    #[test]
+    fn test_canonical_vectors() {
+        use ciborium::Value;
+        let mut full = frame();
+        full.version = 2;
+        full.frame_type = FrameType::Rekey;
+        full.key_epoch = 3;
+        full.id = Some(Ulid::from_parts(1234567890, 0x0102030405060708090a));
+        full.priority = Priority::High;
+        full.content_type = ContentType::Json;
+        full.payload = vec![0, 23, 24, 255].into();
+        full.hmac = None;
+        full.extensions.insert("hops".into(), Value::Integer(7.into()));
+        full.extensions.insert("route".into(), Value::Map(vec![
+            ("zone".into(), Value::Float(1.5)),
+            ("az".into(), Value::Integer(500.into())),
+            (Value::Integer(10.into()), Value::Array(vec![Value::Bool(true)])),
+        ]));
+        
+        for (frame, vector) in [(frame(), CANONICAL_MINIMAL), (full.clone(), CANONICAL_FULL)] {
+            let vector = hex::decode(vector).unwrap();
+            assert_eq!(CBORCodec::encode_canonical(&frame).unwrap(), vector);
+            let decoded = CBORCodec::decode(&vector).unwrap();
+            assert_eq!(CBORCodec::encode_canonical(&decoded).unwrap(), vector);
+        }
+        
+        // Nested key order does not matter
+        let mut reordered = full.clone();
+        reordered.extensions.insert("route".into(), Value::Map(vec![
+            (Value::Integer(10.into()), Value::Array(vec![Value::Bool(true)])),
+            ("az".into(), Value::Integer(500.into())),
+            ("zone".into(), Value::Float(1.5)),
+        ]));
+        assert_ne!(CBORCodec::encode(&reordered).unwrap(), CBORCodec::encode(&full).unwrap());
+        assert_eq!(CBORCodec::encode_canonical(&reordered).unwrap(), CBORCodec::encode_canonical(&full).unwrap());
+        
+        // An extension shadowing a field would make the encoding ambiguous
+        full.extensions.insert("seq".into(), Value::Integer(1.into()));
+        assert!(CBORCodec::encode_canonical(&full).is_err());
+    }
+    
+    #[test]
+    fn test_version_2_layout() {
+        let mut v2 = frame();
+        v2.version = FRAME_VERSION;
+        assert!(CBORCodec::encode(&v2).is_err(), "version 2 requires a message ID");
+        v2.id = Some(Ulid::from_parts(1234567890, 7));
+        v2.key_epoch = 1;
+        v2.payload = vec![0xff; 3].into();
+        v2.extensions.insert("hops".into(), ciborium::Value::Integer(7.into()));
+        
+        // The hand-written codec matches the serde layout
+        let encoded = CBORCodec::encode(&v2).unwrap();
+        let mut via_serde = Vec::new();
+        ciborium::ser::into_writer(&v2, &mut via_serde).unwrap();
+        assert_eq!(encoded, via_serde);
+        // Payload is a byte string
+        assert!(encoded.windows(4).any(|w| w == [0x43, 0xff, 0xff, 0xff]));
+        
+        let decoded = CBORCodec::decode(&encoded).unwrap();
+        assert_eq!((decoded.version, decoded.id, &decoded.sig), (2, v2.id, &v2.sig));
+        assert_eq!(decoded.payload, v2.payload);
+        
+        v2.version = 3;
+        assert!(CBORCodec::encode(&v2).is_err());
+        let mut future = ciborium::Value::serialized(&frame()).unwrap();
+        if let ciborium::Value::Map(map) = &mut future {
+            map[0].1 = 3.into();
+        }
+        assert!(CBORCodec::decode(&to_cbor(&future)).is_err());
+    }
+    
+    #[test]
+    fn test_encode_decode() {
+        let frame = frame();
+        
+        let encoded = CBORCodec::encode(&frame).unwrap();
+        let decoded = CBORCodec::decode(&encoded).unwrap();
+        
+        assert_eq!(frame.version, decoded.version);
+        assert_eq!(frame.from, decoded.from);
+        assert_eq!(frame.to, decoded.to);
+        assert_eq!(frame.payload, decoded.payload);
+    }
+    
+    #[test]
+    fn test_encoded_size() {
+        let mut frames = vec![frame()];
+        for version in [1, 2] {
+            for len in [0, 23, 24, 255, 256, 70_000] {
+                let mut f = frame();
+                f.version = version;
+                f.id = Some(Ulid::from_parts(1234567890, len as u128));
+                f.payload = (0..len).map(|i| i as u8).collect::<Vec<_>>().into();
+                f.sig = Some(vec![0xab; 64]);
+                frames.push(f);
+            }
+        }
+        // Large headers
+        let mut big = frame();
+        big.from = "a".repeat(300);
+        big.to = "b".repeat(70_000);
+        big.nonce = "n".repeat(24);
+        big.hmac = None;
+        big.seq = u64::MAX;
+        big.key_epoch = 1 << 20;
+        big.frame_type = FrameType::Unknown(200);
+        big.compressed = Some(Compression::Lz4);
+        big.priority = Priority::Control;
+        big.content_type = ContentType::Protobuf;
+        big.extensions.insert("hops".into(), ciborium::Value::Integer(7.into()));
+        big.extensions.insert("trace".into(), ciborium::Value::Bytes(vec![1; 40]));
+        frames.push(big);
+        
+        for f in &frames {
+            let encoded = CBORCodec::encode(f).unwrap();
+            assert_eq!(CBORCodec::encoded_size(f).unwrap(), encoded.len());
+            assert_eq!(RoutingHeader::encoded_size(&CBORCodec, f).unwrap(), RoutingHeader::encode(&CBORCodec, f).unwrap().len());
+            for format in WireFormat::supported() {
+                let codec = format.codec().unwrap();
+                assert_eq!(codec.encoded_size(f).unwrap(), codec.encode(f).unwrap().len());
+            }
+        }
+        
+        let mut invalid = frame();
+        invalid.version = 2;
+        assert!(CBORCodec::encoded_size(&invalid).is_err());
+    }
+    
+    #[test]
+    fn test_payload_encoding() {
+        let frame = frame();
+        // Clones share the payload buffer
+        assert_eq!(frame.clone().payload.as_ptr(), frame.payload.as_ptr());
+        
+        // Payload keeps the integer-array encoding; byte strings are accepted too
+        let mut value = ciborium::Value::serialized(&frame).unwrap();
+        let ciborium::Value::Map(map) = &mut value else { panic!("not a map") };
+        let (_, payload) = map.iter_mut().find(|(k, _)| k.as_text() == Some("payload")).unwrap();
+        assert!(payload.is_array());
+        *payload = ciborium::Value::Bytes(vec![1, 2, 3, 4, 5]);
+        let decoded = CBORCodec::decode(&to_cbor(&value)).unwrap();
+        assert_eq!(decoded.payload, frame.payload);
+    }
+    
+    #[test]
+    fn test_decode_with_limits() {
+        let mut frame = frame();
+        frame.payload = vec![7u8; 100].into();
+        let encoded = CBORCodec::encode(&frame).unwrap();
+        assert!(CBORCodec::decode_with_limits(&encoded, 100, 64).is_ok());
+        assert!(matches!(
+            CBORCodec::decode_with_limits(&encoded, 99, 64),
+            Err(CodecError::LimitExceeded(_))
+        ));
+        assert!(matches!(
+            CBORCodec::decode_with_limits(&encoded, 100, 4),
+            Err(CodecError::LimitExceeded(_))
+        ));
+        
+        // Byte string claiming 4 GiB in a 9-byte input
+        let huge = [0x5b, 0, 0, 0, 1, 0, 0, 0, 0];
+        assert!(CBORCodec::decode_with_limits(&huge, usize::MAX, 64).is_err());
+        
+        // Deep nesting
+        let nested = [[0x81u8; 64].as_slice(), &[0x00]].concat();
+        assert!(matches!(
+            CBORCodec::decode_with_limits(&nested, DEFAULT_MAX_PAYLOAD, 64),
+            Err(CodecError::LimitExceeded(_))
+        ));
+        
+        // Trailing garbage
+        let trailing = [encoded.as_slice(), &[0x00]].concat();
+        assert!(CBORCodec::decode_with_limits(&trailing, DEFAULT_MAX_PAYLOAD, 64).is_err());
+    }
+    
+    #[test]
+    fn test_all_codecs_roundtrip() {
+        let mut frame = frame();
+        frame.frame_type = FrameType::Rekey;
+        frame.key_epoch = 3;
+        frame.id = Some(OpacusFrame::new_id(frame.ts));
+        frame.content_type = ContentType::Cbor;
+        
+        for format in WireFormat::supported() {
+            let codec = format.codec().unwrap();
+            assert_eq!(codec.format(), format);
+            assert_eq!(WireFormat::from_alpn(Some(format.alpn())), Some(format));
+            
+            let decoded = codec.decode(&codec.encode(&frame).unwrap()).unwrap();
+            assert_eq!(decoded.frame_type, FrameType::Rekey);
+            assert_eq!(decoded.key_epoch, 3);
+            assert_eq!(decoded.id, frame.id);
+            assert_eq!(decoded.content_type, ContentType::Cbor);
+            assert_eq!(decoded.sig, frame.sig);
+            assert_eq!(decoded.hmac, frame.hmac);
+            assert_eq!(decoded.payload, frame.payload);
+            assert!(codec.decode(&[0xff, 0x00]).is_err());
+        }
+        assert_eq!(WireFormat::from_alpn(None), Some(WireFormat::Cbor));
+        assert_eq!(WireFormat::from_alpn(Some(b"h3")), None);
+    }
+    
+    #[test]
+    fn test_forward_compatible_decode() {
+        let mut future = ciborium::Value::serialized(&frame()).unwrap();
+        if let ciborium::Value::Map(map) = &mut future {
+            map.retain(|(k, _)| k.as_text() != Some("type"));
+            map.push(("type".into(), "teleport".into()));
+            map.push(("hops".into(), 7.into()));
+        }
+        let decoded = CBORCodec::decode(&to_cbor(&future)).unwrap();
+        assert_eq!(decoded.frame_type, FrameType::Unknown(FrameType::UNKNOWN_CODE));
+        assert_eq!(decoded.extensions.get("hops"), Some(&ciborium::Value::Integer(7.into())));
+        
+        let mut frame = frame();
+        frame.frame_type = FrameType::Unknown(42);
+        frame.extensions.insert("hops".into(), ciborium::Value::Integer(7.into()));
+        for format in WireFormat::supported() {
+            let codec = format.codec().unwrap();
+            let decoded = codec.decode(&codec.encode(&frame).unwrap()).unwrap();
+            assert_eq!(decoded.frame_type, FrameType::Unknown(42), "{:?}", format);
+            assert_eq!(decoded.extensions, frame.extensions, "{:?}", format);
+        }
+    }
+    
+    #[test]
+    fn test_frame_type_codes() {
+        for (code, frame_type) in FrameType::ALL.iter().enumerate() {
+            assert_eq!(frame_type.code() as usize, code);
+            assert_eq!(FrameType::from_code(code as u8), *frame_type);
+        }
+    }
+    
+    #[test]
+    fn test_routing_header() {
+        let frame = frame();
+        let data = RoutingHeader::encode(&CBORCodec, &frame).unwrap();
+        
+        let (header, body) = RoutingHeader::parse(&data).unwrap().unwrap();
+        assert_eq!(header.frame_type, FrameType::Msg);
+        assert_eq!(header.to_hash, RoutingHeader::hash_id("bob"));
+        assert_eq!(body, CBORCodec::encode(&frame).unwrap().as_slice());
+        
+        // Bare bodies are still accepted
+        assert!(RoutingHeader::parse(body).unwrap().is_none());
+        assert_eq!(RoutingHeader::decode(&CBORCodec, body).unwrap().seq, 42);
+        assert_eq!(RoutingHeader::decode(&CBORCodec, &data).unwrap().seq, 42);
+        
+        // Header must agree with the body
+        let mut redirected = data.clone();
+        redirected[4..20].copy_from_slice(&RoutingHeader::hash_id("mallory"));
+        assert!(RoutingHeader::decode(&CBORCodec, &redirected).is_err());
+        assert!(RoutingHeader::parse(&data[..data.len() - 1]).is_err());
+        
+        let mut frame = frame;
+        frame.priority = Priority::Low;
+        frame.compressed = Some(Compression::Lz4);
+        let data = RoutingHeader::encode(&CBORCodec, &frame).unwrap();
+        let (header, _) = RoutingHeader::parse(&data).unwrap().unwrap();
+        assert_eq!((header.priority, header.compressed), (Priority::Low, Some(Compression::Lz4)));
+        let mut promoted = data.clone();
+        promoted[3] = (promoted[3] & 0x0f) | (Priority::Control.code() << 4);
+        assert!(RoutingHeader::decode(&CBORCodec, &promoted).is_err());
+    }
+    
+    /// Feed bytes to every decoder a relay or client runs on received data
+    fn decode_everywhere(data: &[u8]) {
+        let _ = CBORCodec::decode(data);
+        for format in WireFormat::supported() {
+            let _ = RoutingHeader::decode(format.codec().unwrap(), data);
+        }
+        let _ = FrameBatch::decode(data);
+        let _ = LengthPrefixedCodec::default().decode(&mut bytes::BytesMut::from(data));
+    }
+    
+    proptest::proptest! {
+        #[test]
+        fn test_decode_arbitrary_bytes(data in proptest::collection::vec(any::<u8>(), 0..512)) {
+            decode_everywhere(&data);
+        }
+        
+        #[test]
+        fn test_decode_corrupted_frames(flips in proptest::collection::vec((any::<usize>(), any::<u8>()), 1..8), keep in any::<usize>()) {
+            let mut frame = frame();
+            frame.extensions.insert("hops".into(), ciborium::Value::Array(vec![7.into(), "x".into()]));
+            for data in [RoutingHeader::encode(&CBORCodec, &frame).unwrap(), FrameBatch::new(vec![frame]).encode().unwrap().to_vec()] {
+                let mut data = data;
+                let len = data.len();
+                for &(at, bits) in &flips {
+                    data[at % len] ^= bits;
+                }
+                data.truncate(keep % (len + 1));
+                decode_everywhere(&data);
+            }
+        }
+    }
+    
+    #[cfg(feature = "proptest")]
+    proptest::proptest! {
+        #[test]
+        fn test_any_frame_roundtrip(frame in any::<OpacusFrame>()) {
+            let canonical = CBORCodec::encode_canonical(&frame).unwrap();
+            prop_assert_eq!(CBORCodec::encoded_size(&frame).unwrap(), CBORCodec::encode(&frame).unwrap().len());
+            for format in WireFormat::supported() {
+                let codec = format.codec().unwrap();
+                let decoded = RoutingHeader::decode(codec, &RoutingHeader::encode(codec, &frame).unwrap()).unwrap();
+                prop_assert_eq!(&CBORCodec::encode_canonical(&decoded).unwrap(), &canonical, "{:?}", format);
+            }
+        }
+    }
+}
@@ -0,0 +1,299 @@
+//! Versioned frame layouts
+//!
+//! `OpacusFrame` always holds the fields of the latest version. Each wire
+//! version has its own struct: frames are encoded in the layout named by their
+//! `version` field, and decoded frames are converted up, so older frames keep
+//! decoding as the format evolves.
+//!
+//! * Version 1: `payload` and `sig` as arrays of integers, message ID optional
+//! * Version 2: `payload` and `sig` as byte strings, message ID required
+
+use std::collections::BTreeMap;
+use bytes::Bytes;
+use serde::de::Error as _;
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::compression::Compression;
+use crate::content::ContentType;
+use crate::qos::Priority;
+use crate::types::{FrameType, OpacusFrame, Ulid};
+
+/// Frame version created by this SDK
+pub const FRAME_VERSION: u8 = 2;
+
+/// Oldest frame version still decoded
+pub const MIN_FRAME_VERSION: u8 = 1;
+
+/// Check the fields required by a frame's version
+pub(crate) fn check_version(frame: &OpacusFrame) -> Result<(), String> {
+    match frame.version {
+        1 => Ok(()),
+        2 if frame.id.is_none() => Err("Version 2 frame without message ID".into()),
+        2 => Ok(()),
+        v => Err(format!("Unsupported frame version: {}", v)),
+    }
+}
+
+/// Version 1 layout
+#[derive(Serialize, Deserialize)]
+pub(crate) struct OpacusFrameV1 {
+    version: u8,
+    #[serde(rename = "type")]
+    frame_type: FrameType,
+    from: String,
+    to: String,
+    seq: u64,
+    ts: u64,
+    nonce: String,
+    #[serde(serialize_with = "serialize_int_array", deserialize_with = "deserialize_bytes")]
+    payload: Bytes,
+    hmac: Option<String>,
+    sig: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    key_epoch: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compressed: Option<Compression>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<Ulid>,
+    #[serde(default, skip_serializing_if = "Priority::is_normal")]
+    priority: Priority,
+    #[serde(default, skip_serializing_if = "ContentType::is_raw")]
+    content_type: ContentType,
+    #[serde(flatten, default, skip_serializing_if = "BTreeMap::is_empty")]
+    extensions: BTreeMap<String, ciborium::Value>,
+}
+
+/// Version 2 layout
+#[derive(Serialize, Deserialize)]
+pub(crate) struct OpacusFrameV2 {
+    version: u8,
+    #[serde(rename = "type")]
+    frame_type: FrameType,
+    from: String,
+    to: String,
+    seq: u64,
+    ts: u64,
+    nonce: String,
+    id: Ulid,
+    #[serde(deserialize_with = "deserialize_bytes")]
+    payload: Bytes,
+    hmac: Option<String>,
+    sig: Option<Bytes>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    key_epoch: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compressed: Option<Compression>,
+    #[serde(default, skip_serializing_if = "Priority::is_normal")]
+    priority: Priority,
+    #[serde(default, skip_serializing_if = "ContentType::is_raw")]
+    content_type: ContentType,
+    #[serde(flatten, default, skip_serializing_if = "BTreeMap::is_empty")]
+    extensions: BTreeMap<String, ciborium::Value>,
+}
+
+fn is_zero(v: &u32) -> bool {
+    *v == 0
+}
+
+/// Serialize bytes as a sequence of integers (version 1 layout)
+fn serialize_int_array<S: Serializer>(data: &Bytes, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(data.iter())
+}
+
+/// Deserialize bytes from a byte string or a sequence of integers
+fn deserialize_bytes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bytes, D::Error> {
+    struct BytesVisitor;
+
+    impl<'de> serde::de::Visitor<'de> for BytesVisitor {
+        type Value = Bytes;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a byte string or an array of integers")
+        }
+
+        fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Bytes, E> {
+            Ok(Bytes::copy_from_slice(v))
+        }
+
+        fn visit_byte_buf<E: serde::de::Error>(self, v: Vec<u8>) -> Result<Bytes, E> {
+            Ok(v.into())
+        }
+
+        fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Bytes, A::Error> {
+            let mut out = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(64 * 1024));
+            while let Some(b) = seq.next_element::<u8>()? {
+                out.push(b);
+            }
+            Ok(out.into())
+        }
+    }
+
+    deserializer.deserialize_any(BytesVisitor)
+}
+
+impl From<&OpacusFrame> for OpacusFrameV1 {
+    fn from(f: &OpacusFrame) -> Self {
+        Self {
+            version: f.version,
+            frame_type: f.frame_type,
+            from: f.from.clone(),
+            to: f.to.clone(),
+            seq: f.seq,
+            ts: f.ts,
+            nonce: f.nonce.clone(),
+            payload: f.payload.clone(),
+            hmac: f.hmac.clone(),
+            sig: f.sig.clone(),
+            key_epoch: f.key_epoch,
+            compressed: f.compressed,
+            id: f.id,
+            priority: f.priority,
+            content_type: f.content_type,
+            extensions: f.extensions.clone(),
+        }
+    }
+}
+
+impl From<OpacusFrameV1> for OpacusFrame {
+    fn from(f: OpacusFrameV1) -> Self {
+        Self {
+            version: f.version,
+            frame_type: f.frame_type,
+            from: f.from,
+            to: f.to,
+            seq: f.seq,
+            ts: f.ts,
+            nonce: f.nonce,
+            payload: f.payload,
+            hmac: f.hmac,
+            sig: f.sig,
+            key_epoch: f.key_epoch,
+            compressed: f.compressed,
+            id: f.id,
+            priority: f.priority,
+            content_type: f.content_type,
+            extensions: f.extensions,
+        }
+    }
+}
+
+impl TryFrom<&OpacusFrame> for OpacusFrameV2 {
+    type Error = String;
+
+    fn try_from(f: &OpacusFrame) -> Result<Self, String> {
+        check_version(f)?;
+        Ok(Self {
+            version: f.version,
+            frame_type: f.frame_type,
+            from: f.from.clone(),
+            to: f.to.clone(),
+            seq: f.seq,
+            ts: f.ts,
+            nonce: f.nonce.clone(),
+            id: f.id.ok_or("Missing message ID")?,
+            payload: f.payload.clone(),
+            hmac: f.hmac.clone(),
+            sig: f.sig.clone().map(Bytes::from),
+            key_epoch: f.key_epoch,
+            compressed: f.compressed,
+            priority: f.priority,
+            content_type: f.content_type,
+            extensions: f.extensions.clone(),
+        })
+    }
+}
+
+impl From<OpacusFrameV2> for OpacusFrame {
+    fn from(f: OpacusFrameV2) -> Self {
+        Self {
+            version: f.version,
+            frame_type: f.frame_type,
+            from: f.from,
+            to: f.to,
+            seq: f.seq,
+            ts: f.ts,
+            nonce: f.nonce,
+            payload: f.payload,
+            hmac: f.hmac,
+            sig: f.sig.map(Vec::from),
+            key_epoch: f.key_epoch,
+            compressed: f.compressed,
+            id: Some(f.id),
+            priority: f.priority,
+            content_type: f.content_type,
+            extensions: f.extensions,
+        }
+    }
+}
+
+impl Serialize for OpacusFrame {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.version {
+            1 => OpacusFrameV1::from(self).serialize(serializer),
+            _ => OpacusFrameV2::try_from(self)
+                .map_err(S::Error::custom)?
+                .serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for OpacusFrame {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Buffer the frame to pick the layout by its version
+        let value = ciborium::Value::deserialize(deserializer)?;
+        let version = value
+            .as_map()
+            .and_then(|m| m.iter().find(|(k, _)| k.as_text() == Some("version")))
+            .and_then(|(_, v)| v.as_integer())
+            .and_then(|v| u8::try_from(v).ok())
+            .ok_or_else(|| D::Error::missing_field("version"))?;
+        let frame: OpacusFrame = match version {
+            1 => value.deserialized::<OpacusFrameV1>().map(Into::into),
+            2 => value.deserialized::<OpacusFrameV2>().map(Into::into),
+            v => return Err(D::Error::custom(format!("Unsupported frame version: {}", v))),
+        }
+        .map_err(D::Error::custom)?;
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(version: u8) -> OpacusFrame {
+        OpacusFrame {
+            version,
+            nonce: "nonce".to_string(),
+            payload: vec![1, 2, 3].into(),
+            hmac: Some("mac".to_string()),
+            sig: Some(vec![9; 4]),
+            id: Some(Ulid::from_parts(1234567890, 1)),
+            priority: Priority::High,
+            ..OpacusFrame::test(1)
+        }
+    }
+
+    #[test]
+    fn test_layout_by_version() {
+        for version in [1, 2] {
+            let value = ciborium::Value::serialized(&frame(version)).unwrap();
+            let payload = value.as_map().unwrap().iter().find(|(k, _)| k.as_text() == Some("payload")).unwrap();
+            assert_eq!(payload.1.is_bytes(), version == 2);
+
+            let decoded: OpacusFrame = value.deserialized().unwrap();
+            assert_eq!(decoded.version, version);
+            assert_eq!(decoded.payload, frame(version).payload);
+            assert_eq!(decoded.sig, frame(version).sig);
+            assert_eq!(decoded.priority, Priority::High);
+        }
+
+        let mut v1 = frame(1);
+        v1.id = None;
+        assert!(ciborium::Value::serialized(&v1).is_ok());
+        v1.version = 2;
+        assert!(ciborium::Value::serialized(&v1).is_err());
+        v1.version = 3;
+        assert!(check_version(&v1).is_err());
+    }
+}
@@ -0,0 +1,285 @@
+//! Frame priorities and priority-ordered send queues
+//!
+//! Every frame carries a `Priority`. Senders drain higher priorities first,
+//! and under congestion the relay and client drop `Low` frames (streams,
+//! telemetry) before anything else. `Control` frames are never dropped.
+
+use std::collections::VecDeque;
+use serde::{Deserialize, Serialize};
+use crate::types::{FrameType, OpacusFrame, Ulid};
+
+/// Default maximum number of frames held in a `SendQueue`
+pub const DEFAULT_SEND_QUEUE_CAPACITY: usize = 1024;
+
+/// Free QUIC datagram send buffer (bytes) below which a connection counts as congested
+pub const CONGESTION_THRESHOLD: usize = 64 * 1024;
+
+/// Frame priority, lowest first
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// Best effort; dropped first under congestion (streams, telemetry)
+    Low,
+    /// Regular application messages
+    #[default]
+    Normal,
+    /// Latency-sensitive application messages
+    High,
+    /// Protocol control frames; never dropped
+    Control,
+}
+
+impl Priority {
+    /// All priorities, highest first
+    pub const DESCENDING: [Priority; 4] = [Priority::Control, Priority::High, Priority::Normal, Priority::Low];
+
+    /// Priority name as used in CBOR and JSON
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+            Priority::Control => "control",
+        }
+    }
+    
+    /// Wire code (`Normal` is 0 so it can be omitted)
+    pub fn code(&self) -> u8 {
+        match self {
+            Priority::Normal => 0,
+            Priority::Low => 1,
+            Priority::High => 2,
+            Priority::Control => 3,
+        }
+    }
+
+    /// Look up a priority by wire code
+    pub fn from_code(code: u8) -> Option<Priority> {
+        match code {
+            0 => Some(Priority::Normal),
+            1 => Some(Priority::Low),
+            2 => Some(Priority::High),
+            3 => Some(Priority::Control),
+            _ => None,
+        }
+    }
+
+    /// Whether frames of this priority may be dropped under congestion
+    pub fn is_droppable(&self) -> bool {
+        *self == Priority::Low
+    }
+
+    pub(crate) fn is_normal(&self) -> bool {
+        *self == Priority::Normal
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+impl std::str::FromStr for Priority {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(Priority::Low),
+            "normal" => Ok(Priority::Normal),
+            "high" => Ok(Priority::High),
+            "control" => Ok(Priority::Control),
+            _ => Err(format!("Unknown priority: {}", s)),
+        }
+    }
+}
+
+impl FrameType {
+    /// Priority frames of this type are sent with unless overridden
+    pub fn default_priority(&self) -> Priority {
+        match self {
+            FrameType::Connect
+            | FrameType::Ping
+            | FrameType::Ack
+            | FrameType::PreKeyPublish
+            | FrameType::PreKeyFetch
+            | FrameType::Rekey
+            | FrameType::Error
+            | FrameType::Subscribe
+            | FrameType::Capabilities
+            | FrameType::Profile
+            | FrameType::Dht => Priority::Control,
+            FrameType::Stream | FrameType::Cover | FrameType::Gossip => Priority::Low,
+            FrameType::Msg
+            | FrameType::Payment
+            | FrameType::Batch
+            | FrameType::History
+            | FrameType::Task
+            | FrameType::Onion
+            | FrameType::Unknown(_) => Priority::Normal,
+        }
+    }
+}
+
+/// What became of a message handed to the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendStatus {
+    /// Written to the transport
+    Sent,
+    /// Held back by congestion; goes out on the next send or flush
+    Queued,
+}
+
+/// Message not sent because the connection is congested
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CongestionError {
+    /// Dropped from the send queue (`Low` priority, or evicted when full)
+    #[error("connection congested, message {0:?} dropped")]
+    Dropped(Option<Ulid>),
+}
+
+/// Bounded outbound queue that yields higher priorities first
+///
+/// When full, the oldest frame of the lowest priority not above the new
+/// frame's is evicted. `Control` frames are always admitted, even past
+/// capacity.
+#[derive(Debug)]
+pub struct SendQueue {
+    queues: [VecDeque<OpacusFrame>; 4],
+    capacity: usize,
+    dropped: u64,
+}
+
+impl SendQueue {
+    /// Create queue holding at most `capacity` frames
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            queues: Default::default(),
+            capacity: capacity.max(1),
+            dropped: 0,
+        }
+    }
+
+    /// Queue a frame
+    ///
+    /// # Returns
+    /// The frame dropped to make room, if any (possibly `frame` itself)
+    pub fn push(&mut self, frame: OpacusFrame) -> Option<OpacusFrame> {
+        let priority = frame.priority;
+        let mut evicted = None;
+        if self.len() >= self.capacity && priority != Priority::Control {
+            let victim = Priority::DESCENDING
+                .iter()
+                .rev()
+                .take_while(|p| **p <= priority)
+                .find(|p| !self.queues[p.index()].is_empty());
+            match victim {
+                Some(p) => evicted = self.queues[p.index()].pop_front(),
+                None => {
+                    self.dropped += 1;
+                    return Some(frame);
+                }
+            }
+            self.dropped += 1;
+        }
+        self.queues[priority.index()].push_back(frame);
+        evicted
+    }
+
+    /// Take the oldest frame of the highest priority
+    pub fn pop(&mut self) -> Option<OpacusFrame> {
+        Priority::DESCENDING
+            .iter()
+            .find_map(|p| self.queues[p.index()].pop_front())
+    }
+
+    /// Drop all queued `Low` frames (on congestion)
+    ///
+    /// # Returns
+    /// The dropped frames
+    pub fn shed_droppable(&mut self) -> Vec<OpacusFrame> {
+        let shed: Vec<OpacusFrame> = self.queues[Priority::Low.index()].drain(..).collect();
+        self.dropped += shed.len() as u64;
+        shed
+    }
+
+    /// Whether a frame with this nonce is queued
+    pub fn contains(&self, nonce: &str) -> bool {
+        self.queues.iter().flatten().any(|f| f.nonce == nonce)
+    }
+
+    /// Number of queued frames
+    pub fn len(&self) -> usize {
+        self.queues.iter().map(|q| q.len()).sum()
+    }
+
+    /// Check whether the queue is empty
+    pub fn is_empty(&self) -> bool {
+        self.queues.iter().all(|q| q.is_empty())
+    }
+
+    /// Total frames dropped since creation
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+impl Default for SendQueue {
+    fn default() -> Self {
+        Self::new(DEFAULT_SEND_QUEUE_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(seq: u64, priority: Priority) -> OpacusFrame {
+        OpacusFrame { priority, ..OpacusFrame::test(seq) }
+    }
+
+    #[test]
+    fn test_priority_order() {
+        let mut queue = SendQueue::default();
+        queue.push(frame(1, Priority::Low));
+        queue.push(frame(2, Priority::Normal));
+        queue.push(frame(3, Priority::Control));
+        queue.push(frame(4, Priority::Normal));
+        queue.push(frame(5, Priority::High));
+
+        let order: Vec<u64> = std::iter::from_fn(|| queue.pop()).map(|f| f.seq).collect();
+        assert_eq!(order, vec![3, 5, 2, 4, 1]);
+    }
+
+    #[test]
+    fn test_overflow_drops_low_first() {
+        let mut queue = SendQueue::new(2);
+        assert!(queue.push(frame(1, Priority::Low)).is_none());
+        assert!(queue.push(frame(2, Priority::Normal)).is_none());
+
+        // Full: the Low frame makes room
+        assert_eq!(queue.push(frame(3, Priority::Normal)).unwrap().seq, 1);
+        // Nothing lower than Low to evict: the new frame is dropped
+        assert_eq!(queue.push(frame(4, Priority::Low)).unwrap().seq, 4);
+        // Control frames are admitted past capacity
+        assert!(queue.push(frame(5, Priority::Control)).is_none());
+        assert_eq!((queue.len(), queue.dropped()), (3, 2));
+        assert_eq!(queue.pop().unwrap().seq, 5);
+
+        let mut queue = SendQueue::default();
+        queue.push(frame(1, Priority::Low));
+        queue.push(frame(2, Priority::Low));
+        queue.push(frame(3, Priority::Normal));
+        assert_eq!(queue.shed_droppable().len(), 2);
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn test_codes() {
+        for p in Priority::DESCENDING {
+            assert_eq!(Priority::from_code(p.code()), Some(p));
+            assert_eq!(p.as_str().parse::<Priority>(), Ok(p));
+        }
+        assert_eq!(Priority::from_code(4), None);
+        assert_eq!(FrameType::Rekey.default_priority(), Priority::Control);
+        assert_eq!(FrameType::Stream.default_priority(), Priority::Low);
+    }
+}
@@ -0,0 +1,97 @@
+//! Sources of randomness for keys, nonces and message IDs
+
+use std::sync::Mutex;
+use rand::rngs::{OsRng, StdRng};
+use rand::{RngCore, SeedableRng};
+
+/// Source of random bytes
+pub trait Random: Send + Sync {
+    /// Fill `dest` with random bytes
+    fn fill_bytes(&self, dest: &mut [u8]);
+
+    /// Random 64-bit number
+    fn next_u64(&self) -> u64 {
+        let mut bytes = [0u8; 8];
+        self.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+}
+
+/// Randomness from the operating system
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OsRandom;
+
+impl Random for OsRandom {
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        OsRng.fill_bytes(dest);
+    }
+}
+
+/// Seeded generator for reproducible tests and simulations
+///
+/// Everything drawn from it, keys included, follows from the seed. Never use
+/// it outside tests.
+#[derive(Debug)]
+pub struct SeededRandom {
+    rng: Mutex<StdRng>,
+}
+
+impl SeededRandom {
+    /// Create generator from a seed
+    pub fn new(seed: u64) -> Self {
+        Self { rng: Mutex::new(StdRng::seed_from_u64(seed)) }
+    }
+}
+
+impl Random for SeededRandom {
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        self.rng.lock().unwrap_or_else(|e| e.into_inner()).fill_bytes(dest);
+    }
+}
+
+/// `N` random bytes from a source
+pub(crate) fn random_array<const N: usize>(random: &dyn Random) -> [u8; N] {
+    let mut bytes = [0u8; N];
+    random.fill_bytes(&mut bytes);
+    bytes
+}
+
+/// A [`Random`] as a `rand` generator, for APIs that take one
+///
+/// Not a `CryptoRng`, as the source may be seeded; keys are generated from
+/// [`Random::fill_bytes`] directly.
+pub struct RandomRng<'a>(pub &'a dyn Random);
+
+impl RngCore for RandomRng<'_> {
+    fn next_u32(&mut self) -> u32 {
+        self.0.next_u64() as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.fill_bytes(dest);
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.0.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_random() {
+        let (a, b) = (SeededRandom::new(1), SeededRandom::new(1));
+        assert_eq!(a.next_u64(), b.next_u64());
+        let mut bytes = [0u8; 16];
+        RandomRng(&a).fill_bytes(&mut bytes);
+        assert_ne!(bytes, [0u8; 16]);
+        assert_ne!(a.next_u64(), SeededRandom::new(2).next_u64());
+    }
+}
@@ -0,0 +1,198 @@
+//! Redaction of secrets in logs
+//!
+//! `Debug` output of identities, configurations and frames hides private
+//! keys and shows only the length of payloads, so `?identity` or `?frame` in
+//! a log line is safe. [`RedactingFields`] formats `tracing` fields for
+//! `tracing-subscriber`, hiding the values of fields whose names mark them
+//! as secret (`ed_priv`, `session_key`, `password`, `payload`, ...).
+//!
+//! For local debugging, [`set_log_secrets`] turns redaction off
+//! process-wide. Never enable it where logs are collected.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::field::{Field, Visit};
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::FormatFields;
+
+/// Placeholder for redacted values
+pub const REDACTED: &str = "[redacted]";
+
+/// Field name parts that mark a `tracing` field as secret
+const SECRET_FIELDS: &[&str] = &[
+    "priv",
+    "secret",
+    "password",
+    "passphrase",
+    "seed",
+    "mnemonic",
+    "session_key",
+    "shared_key",
+    "payload",
+    "plaintext",
+];
+
+static LOG_SECRETS: AtomicBool = AtomicBool::new(false);
+
+/// Show secrets and payloads in `Debug` output and redacted fields
+///
+/// Off by default. Meant for debugging on a developer machine only.
+pub fn set_log_secrets(enabled: bool) {
+    LOG_SECRETS.store(enabled, Ordering::Relaxed);
+    if enabled {
+        tracing::warn!("Secret redaction disabled: logs may contain private keys and payloads");
+    }
+}
+
+/// Whether secrets are shown in logs
+pub fn log_secrets() -> bool {
+    LOG_SECRETS.load(Ordering::Relaxed)
+}
+
+/// Whether a `tracing` field with this name holds a secret
+pub fn is_secret_field(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    SECRET_FIELDS.iter().any(|part| name.contains(part))
+}
+
+/// Secret value that is redacted in `Debug` output
+///
+/// ```
+/// use opacus_sdk::Secret;
+///
+/// let key = [7u8; 32];
+/// tracing::debug!(key = ?Secret(&key), "Derived key");
+/// ```
+#[derive(Clone, Copy)]
+pub struct Secret<T>(pub T);
+
+impl<T: fmt::Debug> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if log_secrets() {
+            self.0.fmt(f)
+        } else {
+            f.write_str(REDACTED)
+        }
+    }
+}
+
+/// Payload bytes, shown only by length in `Debug` output
+#[derive(Clone, Copy)]
+pub struct PayloadBytes<'a>(pub &'a [u8]);
+
+impl fmt::Debug for PayloadBytes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if log_secrets() {
+            write!(f, "b\"{}\"", self.0.escape_ascii())
+        } else {
+            write!(f, "<{} bytes>", self.0.len())
+        }
+    }
+}
+
+/// `tracing-subscriber` field formatter that redacts secret fields
+///
+/// ```no_run
+/// tracing_subscriber::fmt()
+///     .fmt_fields(opacus_sdk::RedactingFields)
+///     .init();
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RedactingFields;
+
+impl<'writer> FormatFields<'writer> for RedactingFields {
+    fn format_fields<R: RecordFields>(&self, mut writer: Writer<'writer>, fields: R) -> fmt::Result {
+        let mut visitor = RedactingVisitor { writer: &mut writer, empty: true, result: Ok(()) };
+        fields.record(&mut visitor);
+        visitor.result
+    }
+}
+
+struct RedactingVisitor<'a, 'writer> {
+    writer: &'a mut Writer<'writer>,
+    empty: bool,
+    result: fmt::Result,
+}
+
+impl Visit for RedactingVisitor<'_, '_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.record_debug(field, &format_args!("{}", value));
+        } else {
+            self.record_debug(field, &value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        // Fields added by `tracing-log` repeat the event's metadata
+        if self.result.is_err() || field.name().starts_with("log.") {
+            return;
+        }
+        let separator = if self.empty { "" } else { " " };
+        self.empty = false;
+        self.result = match field.name() {
+            "message" => write!(self.writer, "{}{:?}", separator, value),
+            name if is_secret_field(name) && !log_secrets() => write!(self.writer, "{}{}={}", separator, name, REDACTED),
+            name => write!(self.writer, "{}{}={:?}", separator, name, value),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use crate::crypto::KeyManager;
+    use crate::error::{ErrorCode, ErrorPayload};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(data)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // One test, since redaction is process-wide
+    #[test]
+    fn test_redaction() {
+        let identity = KeyManager::identity_from_seed(&[1u8; 32], 16602);
+        let frame = ErrorPayload::new(ErrorCode::Unavailable, "down").to_frame("relay", "alice", 1);
+        let priv_hex = format!("{:?}", identity.ed_priv);
+
+        let debug = format!("{:?}", identity);
+        assert!(debug.contains(&identity.id) && debug.contains(REDACTED));
+        assert!(!debug.contains(&priv_hex));
+        let debug = format!("{:?}", frame);
+        assert!(debug.contains(&format!("<{} bytes>", frame.payload.len())));
+        assert!(!debug.contains("down"));
+
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .fmt_fields(RedactingFields)
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(agent = %identity.id, ed_priv = ?identity.ed_priv, session_key = "k3y", "connected {}", 1);
+        });
+        let line = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(line.contains(&format!("connected 1 agent={} ed_priv={} session_key={}", identity.id, REDACTED, REDACTED)), "{}", line);
+
+        set_log_secrets(true);
+        let debug = format!("{:?} {:?}", identity, frame);
+        set_log_secrets(false);
+        assert!(debug.contains(&priv_hex) && debug.contains("down"));
+
+        assert!(is_secret_field("x_priv") && is_secret_field("Password") && !is_secret_field("ed_pub"));
+        assert_eq!(format!("{:?}", Secret("hunter2")), REDACTED);
+    }
+}
@@ -3,14 +3,49 @@
 use quinn::{ServerConfig, Endpoint, Connection};
 use rustls::pki_types::{CertificateDer, PrivateKeyDer};
 use rcgen::generate_simple_self_signed;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::net::SocketAddr;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use bytes::Bytes;
 use dashmap::DashMap;
-use tokio::sync::broadcast;
-use tracing::{info, warn, debug};
+use tokio::sync::{broadcast, mpsc};
+use tracing::{field, info, warn, debug, debug_span};
 use crate::types::{OpacusFrame, FrameType};
-use crate::proto::CBORCodec;
-use crate::crypto::KeyManager;
+use crate::capture::{CaptureDirection, FrameCapture};
+use crate::admin::{self, AdminState};
+use crate::events::{RelayEvent, RelayEventKind, RelayEvents, RouteOutcome};
+use crate::health::{HealthReport, RelayHealth, HEARTBEAT_INTERVAL};
+use crate::error::{ErrorCode, ErrorPayload};
+use crate::proto::{CodecError, FrameCodec, RoutingHeader, WireFormat, DEFAULT_MAX_FRAME_LEN, MIN_FRAME_VERSION};
+use crate::random::OsRandom;
+use crate::compression::Compression;
+use crate::qos::CONGESTION_THRESHOLD;
+use crate::crypto::{KeyManager, SecurityManager};
+use crate::metering::{Usage, UsageMeter};
+use crate::replies::{self, CapabilityDirectory, DhtRecords, PreKeyDirectory, ProfileDirectory, RetainedValues};
+use crate::onion::{OnionKey, OnionReplays, OnionStep};
+use crate::jwt::JwtAuthenticator;
+use crate::gossip::{GossipConfig, PresenceDigest, PresenceTable};
+use crate::dht::{DhtConfig, DhtContact, DhtKey, DhtLookup, DhtMessage, DhtRpc, DHT_K};
+use crate::trace;
+use crate::transport::tls;
+use crate::config::RelayConfig;
+
+pub use crate::replies::{MAX_ONE_TIME_PREKEYS, MAX_PENDING_PER_AGENT};
+
+/// Time a DHT node has to answer a request
+const DHT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Time to connect to another relay
+const LINK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Most connections kept open to other relays; the least recently used is closed first
+const MAX_RELAY_LINKS: usize = 64;
+
+/// Most frames waiting to be forwarded to other relays; more are dropped
+const MAX_FORWARD_QUEUE: usize = 1024;
 
 /// Connected agent information
 pub struct ConnectedAgent {
@@ -19,13 +54,477 @@ pub struct ConnectedAgent {
     pub ed_pub: [u8; 32],
     pub x_pub: [u8; 32],
     pub last_seen: u64,
+    /// Compression algorithms the agent can decode
+    pub compression: Vec<Compression>,
+    /// Frame encoding negotiated via ALPN
+    pub format: WireFormat,
+    /// Newest frame version the agent can decode (that of its Connect frame)
+    pub version: u8,
+}
+
+/// Frame decoded by the relay, with the datagram it arrived in
+/// 
+/// Recipients using the same wire format get the original datagram, so
+/// frames the relay does not modify are never re-encoded.
+#[derive(Clone)]
+struct RoutedFrame {
+    frame: OpacusFrame,
+    /// Original datagram and its wire format (`None` for frames built by the relay)
+    raw: Option<(Bytes, WireFormat)>,
+}
+
+impl RoutedFrame {
+    fn built(frame: OpacusFrame) -> Self {
+        Self { frame, raw: None }
+    }
+}
+
+/// Routing counters
+#[derive(Debug, Default)]
+struct RelayStats {
+    /// Datagrams forwarded without re-encoding
+    passthrough: AtomicU64,
+    /// Frames encoded for a recipient
+    reencoded: AtomicU64,
+}
+
+/// Signature verification settings for routed frames
+/// 
+/// Inbound frames are collected into micro-batches and verified together;
+/// a batch is flushed when it reaches `batch_size` frames or its first frame
+/// has waited `max_delay`.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchVerifyConfig {
+    /// Maximum frames per verification batch
+    pub batch_size: usize,
+    /// Maximum time a frame waits for its batch to fill
+    pub max_delay: Duration,
+}
+
+impl Default for BatchVerifyConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 64,
+            max_delay: Duration::from_millis(2),
+        }
+    }
+}
+
+/// Inspects frames the relay routes, e.g. to countersign delivery receipts
+/// 
+/// Implemented by `ReceiptNotary` and `RelayCheckpointer` (`chain` feature).
+pub trait FrameNotary: Send + Sync {
+    /// Inspect a frame accepted for routing
+    /// 
+    /// # Returns
+    /// Whether the frame was changed and must be re-encoded
+    fn notarize(&self, frame: &mut OpacusFrame) -> bool;
+}
+
+/// Connections this relay opens to other relays, by URL
+/// 
+/// Only relays of the federation are reached: those the relay was built
+/// with and those named by trusted presence digests. URLs from anywhere
+/// else, such as onion layers or DHT answers, are never resolved or dialled.
+struct RelayLinks {
+    /// The relay's own endpoint, so other relays see its listening address
+    endpoint: Endpoint,
+    /// URLs of the relays this relay may connect to
+    known: std::sync::RwLock<HashSet<String>>,
+    /// Open connections, with their last use
+    connections: DashMap<String, (Connection, Instant)>,
+    /// Frames waiting for `forward`'s sender task
+    queue: mpsc::Sender<(String, OpacusFrame)>,
+}
+
+impl RelayLinks {
+    /// Links from the relay's endpoint to the relays of its federation
+    /// 
+    /// Spawns the task sending forwarded frames.
+    fn new(endpoint: Endpoint, known: HashSet<String>) -> Arc<Self> {
+        let (queue, mut frames) = mpsc::channel(MAX_FORWARD_QUEUE);
+        let links = Arc::new(Self { endpoint, known: std::sync::RwLock::new(known), connections: DashMap::new(), queue });
+        let sender = Arc::downgrade(&links);
+        tokio::spawn(async move {
+            while let Some((relay, frame)) = frames.recv().await {
+                let Some(links) = sender.upgrade() else { break };
+                links.send(relay, frame).await;
+            }
+        });
+        links
+    }
+    
+    /// Let the relay connect to another relay of the federation
+    fn allow(&self, relay: &str) {
+        if !self.is_known(relay) {
+            self.known.write().unwrap_or_else(|e| e.into_inner()).insert(relay.to_string());
+        }
+    }
+    
+    fn is_known(&self, relay: &str) -> bool {
+        self.known.read().unwrap_or_else(|e| e.into_inner()).contains(relay)
+    }
+    
+    /// Connection to a relay, connecting to it first if needed
+    async fn connection(&self, relay: &str) -> anyhow::Result<Connection> {
+        if !self.is_known(relay) {
+            anyhow::bail!("{} is not a relay of the federation", relay);
+        }
+        if let Some(mut link) = self.connections.get_mut(relay).filter(|link| link.0.close_reason().is_none()) {
+            link.1 = Instant::now();
+            return Ok(link.0.clone());
+        }
+        let connect = async {
+            let addr = tokio::net::lookup_host(relay.trim_start_matches("quic://"))
+                .await?
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("No address for {}", relay))?;
+            anyhow::Ok(self.endpoint.connect(addr, "opacus")?.await?)
+        };
+        let conn = tokio::time::timeout(LINK_TIMEOUT, connect)
+            .await
+            .map_err(|_| anyhow::anyhow!("Connecting to {} timed out", relay))??;
+        self.connections.retain(|_, link| link.0.close_reason().is_none());
+        if self.connections.len() >= MAX_RELAY_LINKS {
+            let oldest = self.connections.iter().min_by_key(|link| link.1).map(|link| link.key().clone());
+            if let Some((_, (old, _))) = oldest.and_then(|relay| self.connections.remove(&relay)) {
+                old.close(0u32.into(), b"idle");
+            }
+        }
+        self.connections.insert(relay.to_string(), (conn.clone(), Instant::now()));
+        Ok(conn)
+    }
+    
+    /// Queue a frame for a relay, dropping it if the queue is full
+    fn forward(&self, relay: String, frame: OpacusFrame) {
+        if !self.is_known(relay.as_str()) {
+            warn!("Dropping {:?} frame for {}: not a relay of the federation", frame.frame_type, relay);
+            return;
+        }
+        if let Err(e) = self.queue.try_send((relay, frame)) {
+            warn!("Dropping frame for another relay: {}", e);
+        }
+    }
+    
+    /// Send a frame to a relay in a datagram
+    async fn send(&self, relay: String, frame: OpacusFrame) {
+        let conn = match self.connection(&relay).await {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("Dropping {:?} frame: cannot reach {}: {}", frame.frame_type, relay, e);
+                return;
+            }
+        };
+        let codec = WireFormat::default().codec().expect("Default wire format is always compiled in");
+        match RoutingHeader::encode(codec, &frame) {
+            Ok(data) => match conn.send_datagram(data.into()) {
+                Ok(_) => debug!("Forwarded {:?} frame to {}", frame.frame_type, relay),
+                Err(e) => warn!("Failed to forward {:?} frame to {}: {}", frame.frame_type, relay, e),
+            },
+            Err(e) => warn!("Failed to encode {:?} frame: {}", frame.frame_type, e),
+        }
+    }
+    
+    /// Send a frame to a relay on a stream of its own, for frames too large for a datagram
+    async fn send_stream(&self, relay: &str, frame: &OpacusFrame) -> anyhow::Result<()> {
+        let codec = WireFormat::default().codec().expect("Default wire format is always compiled in");
+        let data = RoutingHeader::encode(codec, frame)?;
+        let mut stream = self.connection(relay).await?.open_uni().await?;
+        stream.write_all(&data).await?;
+        stream.finish()?;
+        Ok(())
+    }
+}
+
+impl RelayLinks {
+    /// Send a frame to a relay on a stream of its own and read its answer
+    async fn request(&self, relay: &str, frame: &OpacusFrame) -> anyhow::Result<OpacusFrame> {
+        let codec = WireFormat::default().codec().expect("Default wire format is always compiled in");
+        let data = RoutingHeader::encode(codec, frame)?;
+        let (mut send, mut recv) = self.connection(relay).await?.open_bi().await?;
+        send.write_all(&data).await?;
+        send.finish()?;
+        let answer = recv.read_to_end(DEFAULT_MAX_FRAME_LEN).await?;
+        Ok(RoutingHeader::decode(codec, &answer)?)
+    }
+}
+
+/// Peels onion frames and forwards them to the relays they go to next
+struct OnionLinks {
+    key: OnionKey,
+    replays: Mutex<OnionReplays>,
+    links: Arc<RelayLinks>,
+}
+
+impl OnionLinks {
+    /// Peel an onion frame, forwarding it if it is for another relay
+    /// 
+    /// # Returns
+    /// The frame to deliver from this relay, if any
+    fn peel(&self, frame: &OpacusFrame) -> Option<OpacusFrame> {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let step = self.key.peel(frame).and_then(|step| {
+            self.replays.lock().unwrap_or_else(|e| e.into_inner()).check(frame, now)?;
+            Ok(step)
+        });
+        match step {
+            Ok(OnionStep::Deliver(frame)) => Some(frame),
+            Ok(OnionStep::Forward { relay, frame }) => {
+                self.links.forward(relay, frame);
+                None
+            }
+            Err(e) => {
+                warn!("Dropping onion frame: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Exchanges presence digests with federated relays and forwards frames by them
+struct GossipLinks {
+    config: GossipConfig,
+    table: Mutex<PresenceTable>,
+    links: Arc<RelayLinks>,
+}
+
+impl GossipLinks {
+    fn table(&self) -> std::sync::MutexGuard<'_, PresenceTable> {
+        self.table.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    
+    /// Send a digest of the connected agents, with the digests of other relays, to a few relays every round
+    async fn run(self: Arc<Self>, agents: Arc<DashMap<String, ConnectedAgent>>) {
+        let mut rounds = tokio::time::interval(self.config.interval);
+        loop {
+            rounds.tick().await;
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64;
+            let ids: Vec<String> = agents.iter().map(|a| a.key().clone()).collect();
+            // The time as round number keeps digests increasing across restarts
+            let own = PresenceDigest::sign(&self.config, now, now, ids.iter().map(String::as_str));
+            let (digests, targets) = {
+                let mut table = self.table();
+                table.prune(now);
+                let mut digests = table.digests(now);
+                digests.push(own);
+                (digests, table.gossip_targets(&self.config, now, &OsRandom))
+            };
+            let frame = match OpacusFrame::gossip(&digests, now) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    warn!("Failed to build gossip frame: {}", e);
+                    continue;
+                }
+            };
+            for relay in targets {
+                if let Err(e) = self.links.send_stream(&relay, &frame).await {
+                    debug!("Gossip to {} failed: {}", relay, e);
+                }
+            }
+        }
+    }
+    
+    /// Merge the digests of `Gossip` frames arriving on streams of another relay's connection
+    async fn receive(self: Arc<Self>, conn: Connection, codec: &'static dyn FrameCodec) {
+        while let Ok(mut stream) = conn.accept_uni().await {
+            let frame = match stream.read_to_end(DEFAULT_MAX_FRAME_LEN).await {
+                Ok(data) => RoutingHeader::decode(codec, &data),
+                Err(e) => {
+                    debug!("Gossip stream failed: {}", e);
+                    continue;
+                }
+            };
+            let digests = match frame.map_err(|e| e.to_string()).and_then(|frame| frame.gossip_digests()) {
+                Ok(digests) => digests,
+                Err(e) => {
+                    warn!("Dropping gossip frame: {}", e);
+                    continue;
+                }
+            };
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64;
+            let mut table = self.table();
+            for digest in digests.into_iter().filter(|d| d.relay_key != self.config.public_key()) {
+                let relay = digest.relay.clone();
+                match table.merge(digest, now) {
+                    Ok(_) => self.links.allow(&relay),
+                    Err(e) => debug!("Ignoring presence digest: {}", e),
+                }
+            }
+        }
+    }
+    
+    /// Relay a frame for an agent connected elsewhere goes to, if any
+    fn locate(&self, agent_id: &str) -> Option<String> {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        self.table().locate(agent_id, now).into_iter().next()
+    }
+}
+
+/// Runs DHT lookups for agents and answers other nodes
+struct DhtLinks {
+    config: DhtConfig,
+    records: Arc<DhtRecords>,
+    links: Arc<RelayLinks>,
+}
+
+impl DhtLinks {
+    fn now_ms() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+    }
+    
+    /// Send a message to a node and wait up to `DHT_TIMEOUT` for its answer
+    async fn query(&self, contact: DhtContact, message: DhtMessage) -> Option<DhtMessage> {
+        let rpc = DhtRpc { sender: Some(DhtContact::new(&self.config.addr)), message };
+        match tokio::time::timeout(DHT_TIMEOUT, self.links.request(&contact.addr, &rpc.to_frame(Self::now_ms()))).await {
+            Ok(Ok(answer)) => answer.dht_rpc().map(|rpc| rpc.message),
+            Ok(Err(e)) => {
+                debug!("DHT node {} failed: {}", contact.addr, e);
+                None
+            }
+            Err(_) => {
+                debug!("DHT node {} did not answer in time", contact.addr);
+                None
+            }
+        }
+    }
+    
+    /// Run a lookup from the closest known nodes, updating the routing table with its outcome
+    async fn lookup(&self, target: DhtKey, find_value: bool) -> DhtLookup {
+        let seeds = self.records.with_node(|node| node.closest(&target, DHT_K));
+        let lookup = if find_value { DhtLookup::find_value(target, seeds) } else { DhtLookup::find_node(target, seeds) };
+        let lookup = lookup.run(|contact, message| self.query(contact, message), Self::now_ms()).await;
+        self.records.with_node(|node| {
+            lookup.failed().for_each(|contact| node.remove_contact(contact));
+            lookup.closest().into_iter().for_each(|contact| {
+                node.add_contact(contact);
+            });
+        });
+        lookup
+    }
+    
+    /// Join the DHT through the bootstrap nodes, then refresh the routing table every `refresh_interval`
+    async fn run(self: Arc<Self>) {
+        let own = DhtKey::of_node(&self.config.addr);
+        let mut refresh = tokio::time::interval(self.config.refresh_interval);
+        loop {
+            refresh.tick().await;
+            self.records.with_node(|node| {
+                for addr in self.config.bootstrap.iter().filter(|addr| **addr != self.config.addr) {
+                    node.add_contact(DhtContact::new(addr));
+                }
+            });
+            self.lookup(own, false).await;
+            debug!("DHT routing table has {} nodes", self.records.with_node(|node| node.contacts()));
+        }
+    }
+    
+    /// Answer DHT requests arriving on streams of another node's connection
+    async fn serve(self: Arc<Self>, conn: Connection, codec: &'static dyn FrameCodec) {
+        while let Ok((mut send, mut recv)) = conn.accept_bi().await {
+            let dht = self.clone();
+            tokio::spawn(async move {
+                let request = match recv.read_to_end(DEFAULT_MAX_FRAME_LEN).await {
+                    Ok(data) => RoutingHeader::decode(codec, &data).ok().and_then(|frame| frame.dht_rpc()),
+                    Err(e) => {
+                        debug!("DHT stream failed: {}", e);
+                        return;
+                    }
+                };
+                let Some(request) = request else {
+                    warn!("Dropping invalid DHT request");
+                    return;
+                };
+                let answer = DhtRpc { sender: Some(DhtContact::new(&dht.config.addr)), message: dht.records.handle(request) };
+                if let Ok(data) = RoutingHeader::encode(codec, &answer.to_frame(Self::now_ms())) {
+                    if send.write_all(&data).await.is_ok() {
+                        let _ = send.finish();
+                    }
+                }
+            });
+        }
+    }
+    
+    /// Answer a `Dht` frame from an agent, looking records up across the DHT
+    /// 
+    /// Records are acknowledged once stored here, then stored at the nodes
+    /// closest to their key.
+    async fn serve_agent(self: Arc<Self>, frame: OpacusFrame, conn: Connection, codec: &'static dyn FrameCodec) {
+        let Some(rpc) = frame.dht_rpc() else {
+            warn!("Invalid DHT message from {}", frame.from);
+            return;
+        };
+        let answer = self.records.handle(DhtRpc::from_agent(rpc.message.clone()));
+        let answer = match (rpc.message, answer) {
+            (DhtMessage::Store { record }, DhtMessage::Stored) => {
+                Self::send_reply(&frame, DhtMessage::Stored, &conn, codec);
+                let closest = self.lookup(record.key(), false).await.closest();
+                let stored = futures::future::join_all(
+                    closest.into_iter().map(|contact| self.query(contact, DhtMessage::Store { record: record.clone() })),
+                )
+                .await
+                .into_iter()
+                .filter(|answer| *answer == Some(DhtMessage::Stored))
+                .count();
+                debug!("Stored record of {} at {} nodes", record.agent_id, stored);
+                return;
+            }
+            (DhtMessage::FindValue { key }, DhtMessage::Nodes { .. }) => match self.lookup(key, true).await.record() {
+                Some(record) => DhtMessage::Value { record: record.clone() },
+                None => DhtMessage::Nodes { nodes: Vec::new() },
+            },
+            (_, answer) => answer,
+        };
+        Self::send_reply(&frame, answer, &conn, codec);
+    }
+    
+    fn send_reply(frame: &OpacusFrame, message: DhtMessage, conn: &Connection, codec: &dyn FrameCodec) {
+        if let Ok(data) = RoutingHeader::encode(codec, &DhtRecords::reply(frame, message)) {
+            let _ = conn.send_datagram(data.into());
+        }
+    }
 }
 
 /// Opacus relay server
 pub struct OpacusRelayServer {
     port: u16,
     agents: Arc<DashMap<String, ConnectedAgent>>,
-    pending: Arc<DashMap<String, Vec<OpacusFrame>>>,
+    routes: Arc<DashMap<[u8; 16], String>>,
+    pending: Arc<DashMap<String, Vec<RoutedFrame>>>,
+    prekeys: Arc<PreKeyDirectory>,
+    capabilities: Arc<CapabilityDirectory>,
+    profiles: Arc<ProfileDirectory>,
+    retained: Arc<RetainedValues>,
+    verify_config: Option<BatchVerifyConfig>,
+    meter: Option<Arc<UsageMeter>>,
+    notaries: Vec<Arc<dyn FrameNotary>>,
+    capture: Option<Arc<FrameCapture>>,
+    onion_key: Option<OnionKey>,
+    federation: HashSet<String>,
+    verify_capabilities: bool,
+    jwt_auth: Option<Arc<JwtAuthenticator>>,
+    gossip: Option<GossipConfig>,
+    dht: Option<DhtConfig>,
+    records: Arc<DhtRecords>,
+    admin_addr: Option<SocketAddr>,
+    health: Arc<RelayHealth>,
+    events: RelayEvents,
+    rejected: Arc<AtomicU64>,
+    stats: Arc<RelayStats>,
     shutdown_tx: Option<broadcast::Sender<()>>,
 }
 
@@ -38,13 +537,176 @@ impl OpacusRelayServer {
         Self {
             port,
             agents: Arc::new(DashMap::new()),
+            routes: Arc::new(DashMap::new()),
             pending: Arc::new(DashMap::new()),
+            prekeys: Arc::new(PreKeyDirectory::default()),
+            capabilities: Arc::new(CapabilityDirectory::default()),
+            profiles: Arc::new(ProfileDirectory::default()),
+            retained: Arc::new(RetainedValues::default()),
+            verify_config: None,
+            meter: None,
+            notaries: Vec::new(),
+            capture: None,
+            onion_key: None,
+            federation: HashSet::new(),
+            verify_capabilities: false,
+            jwt_auth: None,
+            gossip: None,
+            dht: None,
+            records: Arc::new(DhtRecords::default()),
+            admin_addr: None,
+            health: Arc::new(RelayHealth::default()),
+            events: RelayEvents::default(),
+            rejected: Arc::new(AtomicU64::new(0)),
+            stats: Arc::new(RelayStats::default()),
             shutdown_tx: None,
         }
     }
     
-    /// Start relay server
-    pub async fn start(&mut self) -> anyhow::Result<()> {
+    /// Create relay server from a loaded configuration
+    /// 
+    /// Applies the port, signature verification and admin address;
+    /// `stats_interval_secs` is left to the caller.
+    pub fn from_config(config: &RelayConfig) -> Self {
+        let mut relay = Self::new(config.port);
+        if let Some(verify) = &config.signature_verification {
+            relay = relay.with_signature_verification(verify.into());
+        }
+        if let Some(addr) = config.admin_addr {
+            relay = relay.with_admin(addr);
+        }
+        relay
+    }
+    
+    /// Require valid Ed25519 signatures on routed frames, verified in batches
+    /// 
+    /// Frames that are unsigned, fail verification, or come from an agent
+    /// that has not connected are dropped. Sealed frames (see
+    /// [`crate::sealed`]) name no sender and are routed for their recipient
+    /// to authenticate.
+    pub fn with_signature_verification(mut self, config: BatchVerifyConfig) -> Self {
+        self.verify_config = Some(config);
+        self
+    }
+    
+    /// Meter `Stream` frames on priced channels, per channel and sender
+    /// 
+    /// Metered frames are decoded, so they skip header-only forwarding.
+    pub fn with_metering(mut self, meter: Arc<UsageMeter>) -> Self {
+        self.meter = Some(meter);
+        self
+    }
+    
+    /// Show routed frames to a notary, e.g. to countersign delivery receipts
+    /// 
+    /// Every routed frame is decoded and shown to each notary in the order
+    /// they were added, so header-only forwarding is disabled.
+    pub fn with_notary(mut self, notary: Arc<dyn FrameNotary>) -> Self {
+        self.notaries.push(notary);
+        self
+    }
+    
+    /// Record frames received from agents and frames delivered to them
+    /// 
+    /// Captured frames are decoded, so header-only forwarding is disabled.
+    pub fn with_capture(mut self, capture: Arc<FrameCapture>) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+    
+    /// Peel onion frames with `key` (see [`crate::onion`])
+    /// 
+    /// The public key is advertised in connect ACKs; publish it with the
+    /// relay's URL so agents can route through it. Frames for a next relay
+    /// of the federation (`with_federation`) are forwarded over a QUIC
+    /// connection from the relay's endpoint; peeled frames for local agents
+    /// are routed like sealed frames, without signature verification. Stale
+    /// and replayed layers are dropped (see [`OnionReplays`]).
+    pub fn with_onion_key(mut self, key: OnionKey) -> Self {
+        self.onion_key = Some(key);
+        self
+    }
+    
+    /// Relays this relay may forward onion layers to and query, by URL
+    /// 
+    /// Gossip peers, DHT bootstrap nodes and relays named by trusted
+    /// presence digests are added to them. Onion layers naming any other
+    /// next relay are dropped, and DHT nodes outside the federation are not
+    /// contacted.
+    pub fn with_federation(mut self, relays: impl IntoIterator<Item = String>) -> Self {
+        self.federation.extend(relays);
+        self
+    }
+    
+    /// Check capability tokens attached to routed frames (see [`crate::token`])
+    /// 
+    /// Frames whose token is invalid, expired or does not permit them are
+    /// rejected; frames without a token are routed as before. Checked frames
+    /// are decoded, so header-only forwarding is disabled. Entries of batches
+    /// are left to their recipients to check.
+    pub fn with_capability_verification(mut self) -> Self {
+        self.verify_capabilities = true;
+        self
+    }
+    
+    /// Only accept agents whose `Connect` frame carries a valid JWT (see [`crate::jwt`])
+    /// 
+    /// Connections are closed to other frames until their agent has
+    /// authenticated. Routed frames are checked against the namespace and
+    /// quota mapped from the token's claims, so header-only forwarding is
+    /// disabled. Frames peeled from onions come from other relays and are not
+    /// checked.
+    pub fn with_jwt_auth(mut self, auth: JwtAuthenticator) -> Self {
+        self.jwt_auth = Some(Arc::new(auth));
+        self
+    }
+    
+    /// Exchange presence digests with federated relays (see [`crate::gossip`])
+    /// 
+    /// Frames from this relay's agents for agents connected to another relay
+    /// of the federation are forwarded to it instead of being queued. Frames
+    /// forwarded by other relays are delivered or queued here, never passed
+    /// on again. Relays verifying signatures (`with_signature_verification`) only know
+    /// the keys of their own agents, so they refuse forwarded signed frames.
+    /// Starting fails unless the config trusts the keys of other relays.
+    pub fn with_gossip(mut self, config: GossipConfig) -> Self {
+        self.gossip = Some(config);
+        self
+    }
+    
+    /// Join a DHT of agent records with other relays (see [`crate::dht`])
+    /// 
+    /// Without one, the relay keeps the records its agents publish and
+    /// answers lookups from them alone. With one, records are stored at the
+    /// nodes closest to their key and lookups search the DHT. Only nodes of
+    /// the federation (`with_federation` and the bootstrap nodes) are
+    /// contacted.
+    pub fn with_dht(mut self, config: DhtConfig) -> Self {
+        self.records = Arc::new(DhtRecords::new(&config.addr));
+        self.dht = Some(config);
+        self
+    }
+    
+    /// Serve the admin endpoints over HTTP on `addr` once started
+    /// 
+    /// `/healthz` and `/readyz` for health checks, `/events` for live
+    /// events. Use port 0 to pick a free port, then read it with
+    /// `get_admin_addr`.
+    pub fn with_admin(mut self, addr: SocketAddr) -> Self {
+        self.admin_addr = Some(addr);
+        self
+    }
+    
+    /// Subscribe to live relay events
+    /// 
+    /// Subscribers that fall `EVENT_BUFFER` events behind miss the oldest
+    /// ones. While anyone is subscribed, header-only forwarding is disabled.
+    pub fn subscribe_events(&self) -> tokio::sync::broadcast::Receiver<RelayEvent> {
+        self.events.subscribe()
+    }
+    
+    /// QUIC server settings with a self-signed certificate, offering every wire format via ALPN
+    fn server_config() -> anyhow::Result<ServerConfig> {
         // Generate self-signed cert
         let subject_names = vec!["opacus".to_string(), "localhost".to_string()];
         let cert = generate_simple_self_signed(subject_names)?;
@@ -56,80 +718,261 @@ impl OpacusRelayServer {
         let mut server_crypto = rustls::ServerConfig::builder()
             .with_no_client_auth()
             .with_single_cert(vec![cert_der], key_der)?;
-        server_crypto.alpn_protocols = vec![b"opacus".to_vec()];
+        server_crypto.alpn_protocols = WireFormat::supported()
+            .iter()
+            .map(|f| f.alpn().to_vec())
+            .collect();
         
-        let server_config = ServerConfig::with_crypto(Arc::new(
+        Ok(ServerConfig::with_crypto(Arc::new(
             quinn::crypto::rustls::QuicServerConfig::try_from(server_crypto)?
-        ));
-        
+        )))
+    }
+    
+    /// Start relay server
+    pub async fn start(&mut self) -> anyhow::Result<()> {
+        if self.gossip.as_ref().is_some_and(|config| config.trusted_keys.is_empty()) {
+            anyhow::bail!("Gossip needs the keys of the federation's relays (GossipConfig::with_trusted_keys)");
+        }
         let addr: SocketAddr = format!("0.0.0.0:{}", self.port).parse()?;
-        let endpoint = Endpoint::server(server_config, addr)?;
+        let mut endpoint = Endpoint::server(Self::server_config()?, addr)?;
+        
+        if self.onion_key.is_some() || self.gossip.is_some() || self.dht.is_some() {
+            endpoint.set_default_client_config(tls::client_config(WireFormat::default())?);
+        }
+        let mut federation = self.federation.clone();
+        federation.extend(self.gossip.iter().flat_map(|config| config.peers.iter().cloned()));
+        federation.extend(self.dht.iter().flat_map(|config| config.bootstrap.iter().cloned()));
+        let links = RelayLinks::new(endpoint.clone(), federation);
+        let onion = self.onion_key.as_ref().map(|key| {
+            info!("🧅 Onion routing enabled");
+            Arc::new(OnionLinks { key: key.clone(), replays: Mutex::new(OnionReplays::default()), links: links.clone() })
+        });
+        let gossip = self.gossip.as_ref().map(|config| {
+            info!("🗣️ Gossiping presence as {}", config.relay);
+            Arc::new(GossipLinks { config: config.clone(), table: Mutex::new(config.table()), links: links.clone() })
+        });
+        let records = self.records.clone();
+        let dht = self.dht.as_ref().map(|config| {
+            info!("🗂️ Joining the DHT as {}", config.addr);
+            Arc::new(DhtLinks { config: config.clone(), records: records.clone(), links: links.clone() })
+        });
         
         info!("🚀 Opacus Relay Server listening on port {}", self.port);
         info!("📡 QUIC transport ready");
         
+        let admin_listener = match self.admin_addr {
+            Some(addr) => {
+                let listener = tokio::net::TcpListener::bind(addr).await?;
+                self.admin_addr = Some(listener.local_addr()?);
+                info!("🩺 Admin endpoints on http://{}", listener.local_addr()?);
+                Some(listener)
+            }
+            None => None,
+        };
+        
         let (shutdown_tx, _) = broadcast::channel(1);
         self.shutdown_tx = Some(shutdown_tx.clone());
         
         let agents = self.agents.clone();
+        let routes = self.routes.clone();
         let pending = self.pending.clone();
+        let prekeys = self.prekeys.clone();
+        let capabilities = self.capabilities.clone();
+        let profiles = self.profiles.clone();
+        let retained = self.retained.clone();
+        let stats = self.stats.clone();
+        let meter = self.meter.clone();
+        let notaries: Arc<[Arc<dyn FrameNotary>]> = self.notaries.clone().into();
+        let capture = self.capture.clone();
+        let health = self.health.clone();
+        let events = self.events.clone();
+        let verify_capabilities = self.verify_capabilities;
+        let jwt_auth = self.jwt_auth.clone();
+        
+        if let Some(listener) = admin_listener {
+            let agents = agents.clone();
+            let pending = pending.clone();
+            let health = health.clone();
+            let report = Arc::new(move || {
+                health.report(agents.len(), pending.iter().map(|r| r.value().len()).sum())
+            });
+            tokio::spawn(admin::serve(listener, AdminState { report, events: events.clone() }));
+        }
+        
+        let verify_tx = self.verify_config.map(|config| {
+            let (tx, rx) = mpsc::channel(config.batch_size.max(1) * 16);
+            tokio::spawn(Self::verify_loop(
+                rx,
+                config,
+                agents.clone(),
+                pending.clone(),
+                self.rejected.clone(),
+                stats.clone(),
+                meter.clone(),
+                notaries.clone(),
+                capture.clone(),
+                events.clone(),
+            ));
+            tx
+        });
+        
+        if let Some(gossip) = &gossip {
+            tokio::spawn(gossip.clone().run(agents.clone()));
+        }
+        if let Some(dht) = &dht {
+            tokio::spawn(dht.clone().run());
+        }
         
+        health.start();
         tokio::spawn(async move {
+            let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
             loop {
                 tokio::select! {
-                    Some(conn) = endpoint.accept() => {
+                    conn = endpoint.accept() => {
+                        let Some(conn) = conn else {
+                            warn!("Relay endpoint closed");
+                            break;
+                        };
                         let agents = agents.clone();
+                        let routes = routes.clone();
                         let pending = pending.clone();
+                        let prekeys = prekeys.clone();
+                        let capabilities = capabilities.clone();
+                        let profiles = profiles.clone();
+                        let retained = retained.clone();
+                        let verify_tx = verify_tx.clone();
+                        let stats = stats.clone();
+                        let meter = meter.clone();
+                        let notaries = notaries.clone();
+                        let capture = capture.clone();
+                        let events = events.clone();
+                        let onion = onion.clone();
+                        let jwt_auth = jwt_auth.clone();
+                        let gossip = gossip.clone();
+                        let records = records.clone();
+                        let dht = dht.clone();
                         tokio::spawn(async move {
                             match conn.await {
                                 Ok(conn) => {
-                                    debug!("New connection from {}", conn.remote_address());
-                                    Self::handle_connection(conn, agents, pending).await;
+                                    let alpn = conn.handshake_data()
+                                        .and_then(|d| d.downcast::<quinn::crypto::rustls::HandshakeData>().ok())
+                                        .and_then(|d| d.protocol);
+                                    let Some(codec) = WireFormat::from_alpn(alpn.as_deref()).and_then(|f| f.codec()) else {
+                                        warn!("Unsupported ALPN from {}", conn.remote_address());
+                                        conn.close(0u32.into(), b"unsupported protocol");
+                                        return;
+                                    };
+                                    debug!("New {:?} connection from {}", codec.format(), conn.remote_address());
+                                    Self::handle_connection(conn, codec, agents, routes, pending, prekeys, capabilities, profiles, retained, verify_tx, verify_capabilities, jwt_auth, stats, meter, notaries, capture, onion, gossip, records, dht, events).await;
                                 }
                                 Err(e) => warn!("Connection failed: {}", e),
                             }
                         });
                     }
+                    _ = heartbeat.tick() => health.beat(),
                     _ = tokio::signal::ctrl_c() => {
                         info!("Shutting down relay server...");
                         break;
                     }
                 }
             }
+            health.stop();
         });
         
         Ok(())
     }
     
+    #[allow(clippy::too_many_arguments)]
     async fn handle_connection(
         conn: Connection,
+        codec: &'static dyn FrameCodec,
         agents: Arc<DashMap<String, ConnectedAgent>>,
-        pending: Arc<DashMap<String, Vec<OpacusFrame>>>,
+        routes: Arc<DashMap<[u8; 16], String>>,
+        pending: Arc<DashMap<String, Vec<RoutedFrame>>>,
+        prekeys: Arc<PreKeyDirectory>,
+        capabilities: Arc<CapabilityDirectory>,
+        profiles: Arc<ProfileDirectory>,
+        retained: Arc<RetainedValues>,
+        verify_tx: Option<mpsc::Sender<RoutedFrame>>,
+        verify_capabilities: bool,
+        jwt_auth: Option<Arc<JwtAuthenticator>>,
+        stats: Arc<RelayStats>,
+        meter: Option<Arc<UsageMeter>>,
+        notaries: Arc<[Arc<dyn FrameNotary>]>,
+        capture: Option<Arc<FrameCapture>>,
+        onion: Option<Arc<OnionLinks>>,
+        gossip: Option<Arc<GossipLinks>>,
+        records: Arc<DhtRecords>,
+        dht: Option<Arc<DhtLinks>>,
+        events: RelayEvents,
     ) {
+        let capture = capture.as_deref();
+        if let Some(gossip) = &gossip {
+            tokio::spawn(gossip.clone().receive(conn.clone(), codec));
+        }
+        if let Some(dht) = &dht {
+            tokio::spawn(dht.clone().serve(conn.clone(), codec));
+        }
         let mut agent_id: Option<String> = None;
+        let mut agent_version = MIN_FRAME_VERSION;
         
         loop {
             match conn.read_datagram().await {
                 Ok(data) => {
-                    match CBORCodec::decode(&data) {
+                    // Fast path: forward by header without decoding the body
+                    if verify_tx.is_none() && !verify_capabilities && jwt_auth.is_none() && meter.is_none() && notaries.is_empty() && capture.is_none() && !events.is_watched() && Self::forward_raw(&data, codec.format(), &agents, &routes, &stats) {
+                        continue;
+                    }
+                    
+                    match RoutingHeader::decode(codec, &data) {
                         Ok(frame) => {
+                            if let Some(capture) = capture {
+                                capture.record(CaptureDirection::In, &frame);
+                            }
+                            if let Some(jwt_auth) = &jwt_auth {
+                                let now = std::time::SystemTime::now()
+                                    .duration_since(std::time::UNIX_EPOCH)
+                                    .unwrap()
+                                    .as_millis() as u64;
+                                // Checked against the connection's agent, as sealed frames name no sender
+                                let error = match (frame.frame_type, &agent_id) {
+                                    (FrameType::Connect, _) => jwt_auth.authenticate_connect(&frame, now).err(),
+                                    (_, None) => Some(ErrorPayload::new(ErrorCode::Unauthorized, "Not authenticated").related_to(frame.id)),
+                                    (_, Some(id)) => jwt_auth.admit(id, &frame, now).err(),
+                                };
+                                if let Some(error) = error {
+                                    warn!("Refused frame from {}: {}", frame.from, error.message);
+                                    events.emit(|| RelayEventKind::frame_routed(&frame, RouteOutcome::Rejected));
+                                    let to = agent_id.as_deref().unwrap_or(&frame.from);
+                                    Self::send_error(&conn, codec, frame.version, to, error);
+                                    continue;
+                                }
+                            }
+                            if frame.frame_type == FrameType::Subscribe {
+                                // Retained values go straight to the subscriber; the frame is routed below
+                                for value in retained.matching(&frame) {
+                                    if let Ok(data) = RoutingHeader::encode(codec, &value) {
+                                        let _ = conn.send_datagram(data.into());
+                                    }
+                                }
+                            }
                             if frame.frame_type == FrameType::Connect {
                                 agent_id = Some(frame.from.clone());
+                                agent_version = frame.version;
                                 
                                 // Parse payload for keys
                                 if let Ok(payload) = serde_json::from_slice::<serde_json::Value>(&frame.payload) {
-                                    let ed_pub_hex = payload["edPub"].as_str().unwrap_or("");
                                     let x_pub_hex = payload["xPub"].as_str().unwrap_or("");
                                     
-                                    let ed_pub = KeyManager::from_hex(ed_pub_hex)
-                                        .ok()
-                                        .and_then(|v| v.try_into().ok())
-                                        .unwrap_or([0u8; 32]);
+                                    let ed_pub = replies::connect_ed_pub(&frame).unwrap_or([0u8; 32]);
                                     let x_pub = KeyManager::from_hex(x_pub_hex)
                                         .ok()
                                         .and_then(|v| v.try_into().ok())
                                         .unwrap_or([0u8; 32]);
+                                    let compression: Vec<Compression> = serde_json::from_value(payload["compression"].clone())
+                                        .unwrap_or_default();
                                     
+                                    routes.insert(RoutingHeader::hash_id(&frame.from), frame.from.clone());
                                     agents.insert(frame.from.clone(), ConnectedAgent {
                                         id: frame.from.clone(),
                                         connection: conn.clone(),
@@ -139,44 +982,119 @@ impl OpacusRelayServer {
                                             .duration_since(std::time::UNIX_EPOCH)
                                             .unwrap()
                                             .as_secs(),
+                                        compression,
+                                        format: codec.format(),
+                                        version: frame.version,
                                     });
                                     
                                     info!("✅ Agent connected: {}", frame.from);
+                                    events.emit(|| RelayEventKind::AgentConnected { agent: frame.from.clone() });
                                     
-                                    // Send ACK
-                                    let ack = OpacusFrame {
-                                        version: 1,
-                                        frame_type: FrameType::Ack,
-                                        from: "relay".to_string(),
-                                        to: frame.from.clone(),
-                                        seq: 0,
-                                        ts: std::time::SystemTime::now()
-                                            .duration_since(std::time::UNIX_EPOCH)
-                                            .unwrap()
-                                            .as_millis() as u64,
-                                        nonce: "".to_string(),
-                                        payload: vec![],
-                                        hmac: None,
-                                        sig: None,
-                                    };
-                                    if let Ok(ack_data) = CBORCodec::encode(&ack) {
+                                    let ack = replies::connect_ack(&frame, onion.as_ref().map(|o| &o.key));
+                                    if let Ok(ack_data) = RoutingHeader::encode(codec, &ack) {
                                         let _ = conn.send_datagram(ack_data.into());
                                     }
                                     
                                     // Flush pending messages
-                                    if let Some((_, msgs)) = pending.remove(&frame.from) {
+                                    if let Some((_, mut msgs)) = pending.remove(&frame.from) {
+                                        msgs.sort_by_key(|m| std::cmp::Reverse(m.frame.priority));
                                         let count = msgs.len();
                                         for msg in msgs {
-                                            let _ = Self::route_frame(&msg, &agents, &pending).await;
+                                            Self::route_frame(msg, &agents, &pending, &stats, capture, &events).await;
                                         }
                                         debug!("Flushed {} pending messages for {}", count, frame.from);
+                                        events.emit(|| RelayEventKind::QueueDepth { agent: frame.from.clone(), depth: 0 });
+                                    }
+                                }
+                            } else if frame.frame_type == FrameType::PreKeyPublish {
+                                let sender = agent_id.as_deref().and_then(|id| Some((id, agents.get(id)?.ed_pub)));
+                                prekeys.store(&frame, sender.as_ref().map(|(id, ed_pub)| (*id, ed_pub)));
+                            } else if frame.frame_type == FrameType::PreKeyFetch {
+                                Self::serve_prekeys(&frame, &conn, codec, &prekeys);
+                            } else if frame.frame_type == FrameType::Capabilities && frame.to == "relay" {
+                                Self::serve_capabilities(&frame, &conn, codec, agent_id.as_deref(), &capabilities);
+                            } else if frame.frame_type == FrameType::Profile && frame.to == "relay" {
+                                if let Some(reply) = profiles.answer(&frame, agent_id.as_deref()) {
+                                    if let Ok(data) = RoutingHeader::encode(codec, &reply) {
+                                        let _ = conn.send_datagram(data.into());
                                     }
                                 }
+                                for notice in profiles.store(&frame, agent_id.as_deref()) {
+                                    Self::route_frame(RoutedFrame::built(notice), &agents, &pending, &stats, capture, &events).await;
+                                }
+                            } else if frame.frame_type == FrameType::Ping && frame.to == "relay" {
+                                Self::answer_ping(&frame, &conn, codec);
+                            } else if frame.frame_type == FrameType::Dht && frame.to == "relay" {
+                                match &dht {
+                                    Some(dht) => {
+                                        tokio::spawn(dht.clone().serve_agent(frame, conn.clone(), codec));
+                                    }
+                                    None => {
+                                        if let Some(reply) = records.answer(&frame) {
+                                            if let Ok(data) = RoutingHeader::encode(codec, &reply) {
+                                                let _ = conn.send_datagram(data.into());
+                                            }
+                                        }
+                                    }
+                                }
+                            } else if frame.frame_type == FrameType::Stream && frame.to == "relay" {
+                                retained.store(&frame, agent_id.as_deref());
+                            } else if frame.frame_type == FrameType::Cover && frame.to == "relay" {
+                                // Cover traffic only hides the agent's activity on the way here
+                            } else if frame.frame_type == FrameType::Onion && frame.to == "relay" {
+                                // Peeled frames come from the previous relay, not their sender, so they are not verified
+                                let Some(frame) = onion.as_ref().and_then(|o| o.peel(&frame)) else { continue };
+                                if let Some(meter) = &meter {
+                                    meter.record_frame(&frame, &frame.from);
+                                }
+                                let routed = Self::notarize(RoutedFrame::built(frame), &notaries);
+                                Self::route_frame(routed, &agents, &pending, &stats, capture, &events).await;
                             } else {
-                                Self::route_frame(&frame, &agents, &pending).await;
+                                if verify_capabilities {
+                                    let now = std::time::SystemTime::now()
+                                        .duration_since(std::time::UNIX_EPOCH)
+                                        .unwrap()
+                                        .as_millis() as u64;
+                                    if let Err(reason) = frame.verify_capability(now) {
+                                        warn!("Dropped frame from {}: {}", frame.from, reason);
+                                        events.emit(|| RelayEventKind::frame_routed(&frame, RouteOutcome::Rejected));
+                                        Self::reject(&frame, &agents, ErrorPayload::new(ErrorCode::Unauthorized, reason));
+                                        continue;
+                                    }
+                                }
+                                // Frames from other relays' connections (no agent) are not passed on again
+                                if let (Some(gossip), Some(_)) = (&gossip, &agent_id) {
+                                    if frame.to != "relay" && !agents.contains_key(&frame.to) {
+                                        if let Some(relay) = gossip.locate(&frame.to) {
+                                            events.emit(|| RelayEventKind::frame_routed(&frame, RouteOutcome::Forwarded));
+                                            gossip.links.forward(relay, frame);
+                                            continue;
+                                        }
+                                    }
+                                }
+                                // Keep the datagram so the frame can be forwarded without re-encoding
+                                let routed = RoutedFrame { frame, raw: Some((data, codec.format())) };
+                                // Sealed frames name no sender to verify; their recipient authenticates them
+                                if let Some(tx) = verify_tx.as_ref().filter(|_| !routed.frame.is_sealed()) {
+                                    if tx.send(routed).await.is_err() {
+                                        warn!("Verifier stopped, dropping frame");
+                                    }
+                                } else {
+                                    if let Some(meter) = &meter {
+                                        meter.record_frame(&routed.frame, &routed.frame.from);
+                                    }
+                                    let routed = Self::notarize(routed, &notaries);
+                                    Self::route_frame(routed, &agents, &pending, &stats, capture, &events).await;
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            warn!("Decode error: {}", e);
+                            if let CodecError::LimitExceeded(reason) = e {
+                                let to = agent_id.as_deref().unwrap_or_default();
+                                Self::send_error(&conn, codec, agent_version, to, ErrorPayload::new(ErrorCode::TooLarge, reason));
                             }
                         }
-                        Err(e) => warn!("Decode error: {}", e),
                     }
                 }
                 Err(e) => {
@@ -187,29 +1105,324 @@ impl OpacusRelayServer {
         }
         
         if let Some(id) = agent_id {
+            routes.remove(&RoutingHeader::hash_id(&id));
             agents.remove(&id);
             info!("❌ Agent disconnected: {}", id);
+            events.emit(|| RelayEventKind::AgentDisconnected { agent: id });
+        }
+    }
+    
+    /// Forward a datagram using only its routing header
+    /// 
+    /// Applies to routable frames for online recipients that use the same
+    /// wire format and can decode the payload compression. The bytes are sent
+    /// unchanged. Returns `false` if the frame must take the decoding path.
+    fn forward_raw(
+        data: &bytes::Bytes,
+        format: WireFormat,
+        agents: &DashMap<String, ConnectedAgent>,
+        routes: &DashMap<[u8; 16], String>,
+        stats: &RelayStats,
+    ) -> bool {
+        let Ok(Some((header, _))) = RoutingHeader::parse(data) else {
+            return false;
+        };
+        if matches!(
+            header.frame_type,
+            FrameType::Connect | FrameType::PreKeyPublish | FrameType::PreKeyFetch | FrameType::Subscribe
+        ) {
+            return false;
+        }
+        let Some(to) = routes.get(&header.to_hash).map(|id| id.clone()) else {
+            return false;
+        };
+        let Some(agent) = agents.get(&to) else {
+            return false;
+        };
+        if agent.format != format
+            || header.version > agent.version
+            || header.compressed.is_some_and(|alg| !agent.compression.contains(&alg))
+        {
+            return false;
+        }
+        // The routing span of a frame forwarded undecoded has what the header carries
+        let _span = debug_span!(
+            "relay.route",
+            frame_type = ?header.frame_type,
+            priority = ?header.priority,
+            to = %to,
+            passthrough = true,
+        )
+        .entered();
+        if header.priority.is_droppable() && Self::congested(&agent.connection) {
+            debug!("Dropped low-priority {:?} for congested {}", header.frame_type, to);
+            return true;
+        }
+        match agent.connection.send_datagram(data.clone()) {
+            Ok(_) => {
+                stats.passthrough.fetch_add(1, Ordering::Relaxed);
+                debug!("Forwarded {:?} to {}", header.frame_type, to)
+            }
+            Err(e) => warn!("Failed to route: {}", e),
+        }
+        true
+    }
+    
+    /// Check whether a connection's datagram send buffer is nearly full
+    fn congested(conn: &Connection) -> bool {
+        conn.datagram_send_buffer_space() < CONGESTION_THRESHOLD
+    }
+    
+    /// Send an unsigned `Error` frame of the given version over a connection
+    fn send_error(conn: &Connection, codec: &dyn FrameCodec, version: u8, to: &str, error: ErrorPayload) {
+        let ts = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let mut frame = error.to_frame("relay", to, ts);
+        frame.version = version;
+        if let Ok(data) = RoutingHeader::encode(codec, &frame) {
+            let _ = conn.send_datagram(data.into());
+        }
+    }
+    
+    /// Report a rejected frame to its sender, if connected
+    fn reject(frame: &OpacusFrame, agents: &DashMap<String, ConnectedAgent>, error: ErrorPayload) {
+        let Some(agent) = agents.get(&frame.from) else { return };
+        let Some(codec) = agent.format.codec() else { return };
+        Self::send_error(&agent.connection, codec, agent.version, &frame.from, error.related_to(frame.id));
+    }
+    
+    /// Show a frame to the notaries, dropping its datagram if one changed it
+    fn notarize(mut routed: RoutedFrame, notaries: &[Arc<dyn FrameNotary>]) -> RoutedFrame {
+        let mut changed = false;
+        for notary in notaries {
+            changed |= notary.notarize(&mut routed.frame);
+        }
+        if changed {
+            routed.raw = None;
         }
+        routed
     }
     
     async fn route_frame(
+        routed: RoutedFrame,
+        agents: &DashMap<String, ConnectedAgent>,
+        pending: &DashMap<String, Vec<RoutedFrame>>,
+        stats: &RelayStats,
+        capture: Option<&FrameCapture>,
+        events: &RelayEvents,
+    ) {
+        if routed.frame.frame_type == FrameType::Batch && routed.frame.to == "relay" {
+            Self::route_batch(&routed.frame, agents, pending, stats, capture, events);
+        } else {
+            Self::deliver(routed, agents, pending, stats, capture, events);
+        }
+    }
+    
+    /// Unpack a batch addressed to the relay and forward each entry as its sender signed it
+    fn route_batch(
         frame: &OpacusFrame,
         agents: &DashMap<String, ConnectedAgent>,
-        pending: &DashMap<String, Vec<OpacusFrame>>,
+        pending: &DashMap<String, Vec<RoutedFrame>>,
+        stats: &RelayStats,
+        capture: Option<&FrameCapture>,
+        events: &RelayEvents,
     ) {
+        let entries = match frame.batch_entries() {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Invalid batch from {}: {}", frame.from, e);
+                return;
+            }
+        };
+        if entries.iter().any(|e| e.from != frame.from) {
+            warn!("Batch from {} contains entries from other senders", frame.from);
+            Self::reject(frame, agents, ErrorPayload::new(ErrorCode::Unauthorized, "Batch entry sender mismatch"));
+            return;
+        }
+        for entry in entries {
+            Self::deliver(RoutedFrame::built(entry), agents, pending, stats, capture, events);
+        }
+    }
+    
+    fn deliver(
+        routed: RoutedFrame,
+        agents: &DashMap<String, ConnectedAgent>,
+        pending: &DashMap<String, Vec<RoutedFrame>>,
+        stats: &RelayStats,
+        capture: Option<&FrameCapture>,
+        events: &RelayEvents,
+    ) {
+        let frame = &routed.frame;
+        let span = debug_span!(
+            "relay.route",
+            frame_id = ?frame.id,
+            frame_type = ?frame.frame_type,
+            from = %frame.from,
+            to = %frame.to,
+            trace_id = field::Empty,
+        );
+        trace::link_span(&span, frame);
+        let _span = span.entered();
+        if frame.to.is_empty() {
+            Self::reject(frame, agents, ErrorPayload::new(ErrorCode::UnknownRecipient, "Frame has no recipient"));
+            events.emit(|| RelayEventKind::frame_routed(frame, RouteOutcome::Rejected));
+            return;
+        }
         if let Some(agent) = agents.get(&frame.to) {
-            if let Ok(data) = CBORCodec::encode(frame) {
-                match agent.connection.send_datagram(data.into()) {
-                    Ok(_) => debug!("Routed {} to {}", frame.frame_type as u8, frame.to),
-                    Err(e) => warn!("Failed to route: {}", e),
+            if let Some(alg) = frame.compressed {
+                if !agent.compression.contains(&alg) {
+                    warn!("Dropping {} frame for {}: recipient cannot decode it", alg.as_str(), frame.to);
+                    drop(agent);
+                    let reason = format!("Recipient {} cannot decode {}", frame.to, alg.as_str());
+                    Self::reject(frame, agents, ErrorPayload::new(ErrorCode::Unsupported, reason));
+                    events.emit(|| RelayEventKind::frame_routed(frame, RouteOutcome::Rejected));
+                    return;
+                }
+            }
+            if frame.version > agent.version {
+                drop(agent);
+                let reason = format!("Recipient {} cannot decode frame version {}", frame.to, frame.version);
+                Self::reject(frame, agents, ErrorPayload::new(ErrorCode::Unsupported, reason));
+                events.emit(|| RelayEventKind::frame_routed(frame, RouteOutcome::Rejected));
+                return;
+            }
+            if frame.priority.is_droppable() && Self::congested(&agent.connection) {
+                debug!("Dropped low-priority frame for congested {}", frame.to);
+                events.emit(|| RelayEventKind::frame_routed(frame, RouteOutcome::Dropped));
+                return;
+            }
+            let data = match &routed.raw {
+                Some((data, format)) if *format == agent.format => {
+                    stats.passthrough.fetch_add(1, Ordering::Relaxed);
+                    data.clone()
                 }
+                _ => {
+                    let Some(codec) = agent.format.codec() else { return };
+                    let Ok(data) = RoutingHeader::encode(codec, frame) else { return };
+                    stats.reencoded.fetch_add(1, Ordering::Relaxed);
+                    data.into()
+                }
+            };
+            match agent.connection.send_datagram(data) {
+                Ok(_) => {
+                    if let Some(capture) = capture {
+                        capture.record(CaptureDirection::Out, frame);
+                    }
+                    debug!("Routed {} to {}", frame.frame_type.code(), frame.to);
+                    events.emit(|| RelayEventKind::frame_routed(frame, RouteOutcome::Delivered));
+                }
+                Err(e) => warn!("Failed to route: {}", e),
             }
         } else {
             // Queue for later
+            let mut queue = pending.entry(frame.to.clone()).or_default();
+            if queue.len() >= MAX_PENDING_PER_AGENT {
+                drop(queue);
+                let reason = format!("Queue for offline agent {} is full", frame.to);
+                Self::reject(frame, agents, ErrorPayload::new(ErrorCode::RateLimited, reason));
+                events.emit(|| RelayEventKind::frame_routed(frame, RouteOutcome::Rejected));
+                return;
+            }
             debug!("Queueing message for offline agent: {}", frame.to);
-            pending.entry(frame.to.clone())
-                .or_insert_with(Vec::new)
-                .push(frame.clone());
+            let depth = queue.len() + 1;
+            events.emit(|| RelayEventKind::frame_routed(frame, RouteOutcome::Queued));
+            events.emit(|| RelayEventKind::QueueDepth { agent: frame.to.clone(), depth });
+            queue.push(routed);
+        }
+    }
+    
+    #[allow(clippy::too_many_arguments)]
+    async fn verify_loop(
+        mut rx: mpsc::Receiver<RoutedFrame>,
+        config: BatchVerifyConfig,
+        agents: Arc<DashMap<String, ConnectedAgent>>,
+        pending: Arc<DashMap<String, Vec<RoutedFrame>>>,
+        rejected: Arc<AtomicU64>,
+        stats: Arc<RelayStats>,
+        meter: Option<Arc<UsageMeter>>,
+        notaries: Arc<[Arc<dyn FrameNotary>]>,
+        capture: Option<Arc<FrameCapture>>,
+        events: RelayEvents,
+    ) {
+        let mut batch = Vec::with_capacity(config.batch_size);
+        
+        while let Some(first) = rx.recv().await {
+            batch.push(first);
+            let deadline = tokio::time::Instant::now() + config.max_delay;
+            while batch.len() < config.batch_size {
+                match tokio::time::timeout_at(deadline, rx.recv()).await {
+                    Ok(Some(frame)) => batch.push(frame),
+                    _ => break,
+                }
+            }
+            // Route higher priorities first
+            batch.sort_by_key(|r| std::cmp::Reverse(r.frame.priority));
+            
+            let sign_data: Vec<_> = batch.iter()
+                .map(|RoutedFrame { frame, .. }| {
+                    let hmac = frame.hmac.as_ref()?;
+                    let sig = frame.sig.clone()?;
+                    let ed_pub = agents.get(&frame.from)?.ed_pub;
+                    Some((SecurityManager::frame_sign_data(frame, hmac), sig, ed_pub))
+                })
+                .collect();
+            let items: Vec<(&[u8; 32], &[u8], &[u8])> = sign_data.iter()
+                .flatten()
+                .map(|(data, sig, ed_pub)| (ed_pub, data.as_slice(), sig.as_slice()))
+                .collect();
+            let mut results = SecurityManager::verify_batch(&items).into_iter();
+            
+            for (routed, data) in batch.drain(..).zip(sign_data.iter()) {
+                let valid = data.is_some() && results.next().unwrap_or(false);
+                if valid {
+                    if let Some(meter) = &meter {
+                        meter.record_frame(&routed.frame, &routed.frame.from);
+                    }
+                    let routed = Self::notarize(routed, &notaries);
+                    Self::route_frame(routed, &agents, &pending, &stats, capture.as_deref(), &events).await;
+                } else {
+                    rejected.fetch_add(1, Ordering::Relaxed);
+                    warn!("Dropped frame with invalid signature from {}", routed.frame.from);
+                    events.emit(|| RelayEventKind::frame_routed(&routed.frame, RouteOutcome::Rejected));
+                    Self::reject(&routed.frame, &agents, ErrorPayload::new(ErrorCode::Unauthorized, "Invalid signature"));
+                }
+            }
+        }
+    }
+    
+    fn serve_prekeys(
+        frame: &OpacusFrame,
+        conn: &Connection,
+        codec: &dyn FrameCodec,
+        prekeys: &PreKeyDirectory,
+    ) {
+        let reply = prekeys.reply(frame);
+        if let Ok(data) = RoutingHeader::encode(codec, &reply) {
+            let _ = conn.send_datagram(data.into());
+        }
+    }
+    
+    /// Store an agent's capabilities or answer a lookup
+    fn serve_capabilities(
+        frame: &OpacusFrame,
+        conn: &Connection,
+        codec: &dyn FrameCodec,
+        sender: Option<&str>,
+        capabilities: &CapabilityDirectory,
+    ) {
+        let Some(reply) = capabilities.handle(frame, sender) else { return };
+        if let Ok(data) = RoutingHeader::encode(codec, &reply) {
+            let _ = conn.send_datagram(data.into());
+        }
+    }
+    
+    /// Answer a ping addressed to the relay, for round trips to the relay itself
+    fn answer_ping(frame: &OpacusFrame, conn: &Connection, codec: &dyn FrameCodec) {
+        let Some(reply) = replies::ping_reply(frame) else { return };
+        if let Ok(data) = RoutingHeader::encode(codec, &reply) {
+            let _ = conn.send_datagram(data.into());
         }
     }
     
@@ -223,8 +1436,203 @@ impl OpacusRelayServer {
         self.agents.iter().map(|r| r.key().clone()).collect()
     }
     
+    /// Get number of agents with published prekey bundles
+    pub fn get_prekey_count(&self) -> usize {
+        self.prekeys.len()
+    }
+    
+    /// Get number of agents with published capabilities
+    pub fn get_capability_count(&self) -> usize {
+        self.capabilities.len()
+    }
+    
+    /// Get number of agents with a published profile
+    pub fn get_profile_count(&self) -> usize {
+        self.profiles.len()
+    }
+    
+    /// Get number of agent records kept by this relay
+    pub fn get_record_count(&self) -> usize {
+        self.records.len()
+    }
+    
+    /// Get number of channels with a retained value
+    pub fn get_retained_count(&self) -> usize {
+        self.retained.len()
+    }
+    
+    /// Get number of frames dropped by signature verification
+    pub fn get_rejected_count(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+    
+    /// Get number of datagrams forwarded without re-encoding
+    pub fn get_passthrough_count(&self) -> u64 {
+        self.stats.passthrough.load(Ordering::Relaxed)
+    }
+    
+    /// Get number of frames re-encoded for their recipient
+    pub fn get_reencoded_count(&self) -> u64 {
+        self.stats.reencoded.load(Ordering::Relaxed)
+    }
+    
+    /// Get metered usage of a channel (zero without metering)
+    pub fn get_usage(&self, channel_id: &str) -> Usage {
+        self.meter.as_ref().map(|m| m.usage(channel_id)).unwrap_or_default()
+    }
+    
     /// Get pending message count
     pub fn get_pending_count(&self) -> usize {
         self.pending.iter().map(|r| r.value().len()).sum()
     }
+    
+    /// Get the public onion key, if set
+    pub fn get_onion_key(&self) -> Option<[u8; 32]> {
+        self.onion_key.as_ref().map(OnionKey::public_key)
+    }
+    
+    /// Get the address of the admin listener (bound address once started)
+    pub fn get_admin_addr(&self) -> Option<SocketAddr> {
+        self.admin_addr
+    }
+    
+    /// Get the relay's health, as served on `/healthz` and `/readyz`
+    pub fn get_health(&self) -> HealthReport {
+        self.health.report(self.get_agent_count(), self.get_pending_count())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use crate::proto::CBORCodec;
+
+    /// System allocator counting the bytes each thread allocates
+    struct Counting;
+
+    thread_local! {
+        static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            ALLOCATED.with(|a| a.set(a.get() + layout.size()));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            ALLOCATED.with(|a| a.set(a.get() + new_size.saturating_sub(layout.size())));
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: Counting = Counting;
+
+    /// Bytes allocated on this thread while running `f`, with its result
+    fn allocated<T>(f: impl FnOnce() -> T) -> (usize, T) {
+        let before = ALLOCATED.with(Cell::get);
+        let result = f();
+        (ALLOCATED.with(Cell::get) - before, result)
+    }
+
+    /// Fits a QUIC datagram once CBOR-encoded as an array of bytes
+    const PAYLOAD_LEN: usize = 512;
+
+    fn datagram(frame: &OpacusFrame) -> Bytes {
+        RoutingHeader::encode(&CBORCodec, frame).unwrap().into()
+    }
+
+    /// Connect "bob" to the relay's tables, returning his side of the connection
+    async fn connect_bob(
+        agents: &DashMap<String, ConnectedAgent>,
+        routes: &DashMap<[u8; 16], String>,
+    ) -> (Connection, Endpoint, Endpoint) {
+        let server = Endpoint::server(OpacusRelayServer::server_config().unwrap(), "127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut client = Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        client.set_default_client_config(tls::client_config(WireFormat::Cbor).unwrap());
+        let connecting = client.connect(server.local_addr().unwrap(), "localhost").unwrap();
+        let (bob, relay_side) = tokio::join!(connecting, async { server.accept().await.unwrap().await });
+        agents.insert("bob".to_string(), ConnectedAgent {
+            id: "bob".to_string(),
+            connection: relay_side.unwrap(),
+            ed_pub: [0u8; 32],
+            x_pub: [0u8; 32],
+            last_seen: 0,
+            compression: Vec::new(),
+            format: WireFormat::Cbor,
+            version: 2,
+        });
+        routes.insert(RoutingHeader::hash_id("bob"), "bob".to_string());
+        (bob.unwrap(), client, server)
+    }
+
+    #[tokio::test]
+    async fn test_forward_raw_copies_nothing() {
+        let (agents, routes, stats) = (DashMap::new(), DashMap::new(), RelayStats::default());
+        let (bob, _client, _server) = connect_bob(&agents, &routes).await;
+        let frame = OpacusFrame { payload: vec![0x5a; PAYLOAD_LEN].into(), ..OpacusFrame::test(1) };
+        let data = datagram(&frame);
+        // The first datagram sets up the connection's send queue
+        assert!(OpacusRelayServer::forward_raw(&data, WireFormat::Cbor, &agents, &routes, &stats));
+
+        let (forwarding, forwarded) =
+            allocated(|| OpacusRelayServer::forward_raw(&data, WireFormat::Cbor, &agents, &routes, &stats));
+        assert!(forwarded);
+        assert!(forwarding <= 64, "forwarding allocated {} bytes", forwarding);
+        assert_eq!(stats.passthrough.load(Ordering::Relaxed), 2);
+        assert_eq!(bob.read_datagram().await.unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn test_deliver_reencodes_only_built_frames() {
+        let (agents, routes, stats) = (DashMap::new(), DashMap::new(), RelayStats::default());
+        let (bob, _client, _server) = connect_bob(&agents, &routes).await;
+        let (pending, events) = (DashMap::new(), RelayEvents::default());
+        let frame = OpacusFrame { payload: vec![0x5a; PAYLOAD_LEN].into(), ..OpacusFrame::test(1) };
+        let data = datagram(&frame);
+        let received = RoutedFrame { frame: frame.clone(), raw: Some((data.clone(), WireFormat::Cbor)) };
+        // As above, the first datagram sets up the send queue
+        OpacusRelayServer::deliver(received.clone(), &agents, &pending, &stats, None, &events);
+
+        let (passthrough, _) =
+            allocated(|| OpacusRelayServer::deliver(received, &agents, &pending, &stats, None, &events));
+        assert!(passthrough <= 64, "passthrough allocated {} bytes", passthrough);
+        let (reencoding, _) =
+            allocated(|| OpacusRelayServer::deliver(RoutedFrame::built(frame), &agents, &pending, &stats, None, &events));
+        assert!(reencoding >= 2 * PAYLOAD_LEN, "re-encoding allocated {} bytes", reencoding);
+
+        assert_eq!(stats.passthrough.load(Ordering::Relaxed), 2);
+        assert_eq!(stats.reencoded.load(Ordering::Relaxed), 1);
+        for _ in 0..3 {
+            assert_eq!(bob.read_datagram().await.unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_queued_frames_share_their_datagram() {
+        let (agents, pending, stats, events) = (DashMap::new(), DashMap::new(), RelayStats::default(), RelayEvents::default());
+        let data = datagram(&OpacusFrame { payload: vec![0x5a; PAYLOAD_LEN].into(), ..OpacusFrame::test(1) });
+        let frame = RoutingHeader::decode(&CBORCodec, &data).unwrap();
+        let received = RoutedFrame { frame, raw: Some((data.clone(), WireFormat::Cbor)) };
+
+        // Bob is offline, so the frames wait in his queue
+        OpacusRelayServer::deliver(received.clone(), &agents, &pending, &stats, None, &events);
+        let second = received.clone();
+        let (queueing, _) =
+            allocated(|| OpacusRelayServer::deliver(second, &agents, &pending, &stats, None, &events));
+        assert!(queueing <= 64, "queueing allocated {} bytes", queueing);
+        let queued = pending.get("bob").unwrap();
+        let (queued_data, _) = queued[0].raw.as_ref().unwrap();
+        assert_eq!(queued_data.as_ptr(), data.as_ptr());
+        let (cloning, copy) = allocated(|| queued[0].clone());
+        assert_eq!(copy.frame.payload.as_ptr(), queued[0].frame.payload.as_ptr());
+        assert!(cloning < PAYLOAD_LEN / 4, "cloning allocated {} bytes", cloning);
+    }
 }
@@ -0,0 +1,429 @@
+//! Frames a relay answers itself
+//!
+//! Connect ACKs, prekey, capability and profile lookups, agent records,
+//! retained channel values and pings to `"relay"` are answered the same way by [`OpacusRelayServer`](crate::OpacusRelayServer)
+//! and the in-process [`MemoryRelay`](crate::MemoryRelay), so they live here,
+//! independent of either (and of any async runtime).
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use tracing::{debug, warn};
+use crate::capabilities::Capabilities;
+use crate::clock::{Clock, SystemClock};
+use crate::compression::Compression;
+use crate::content::ContentType;
+use crate::crypto::{KeyManager, PreKeyBundle, SecurityManager};
+use crate::dht::{DhtMessage, DhtNode, DhtRpc};
+use crate::latency::PingPayload;
+use crate::onion::OnionKey;
+use crate::profile::SignedProfile;
+use crate::qos::Priority;
+use crate::topic::TopicFilter;
+use crate::types::{FrameType, OpacusFrame};
+
+/// Maximum frames queued for one offline agent
+pub const MAX_PENDING_PER_AGENT: usize = 1024;
+
+/// Maximum one-time prekeys stored for one agent; later ones are ignored
+pub const MAX_ONE_TIME_PREKEYS: usize = 100;
+
+/// Maximum channels with a retained value for one agent; values for further
+/// channels are ignored
+pub const MAX_RETAINED_PER_AGENT: usize = 256;
+
+/// Ed25519 key announced in a `Connect` frame
+pub(crate) fn connect_ed_pub(frame: &OpacusFrame) -> Option<[u8; 32]> {
+    let payload = serde_json::from_slice::<serde_json::Value>(&frame.payload).ok()?;
+    KeyManager::from_hex(payload["edPub"].as_str()?).ok()?.try_into().ok()
+}
+
+/// Control frame from the relay to the sender of `frame`
+fn relay_frame(frame: &OpacusFrame, frame_type: FrameType, payload: Vec<u8>) -> OpacusFrame {
+    let ts = SystemClock.now_ms();
+    OpacusFrame {
+        version: frame.version,
+        frame_type,
+        from: "relay".to_string(),
+        to: frame.from.clone(),
+        seq: 0,
+        ts,
+        nonce: "".to_string(),
+        payload: payload.into(),
+        hmac: None,
+        sig: None,
+        key_epoch: 0,
+        compressed: None,
+        id: Some(OpacusFrame::new_id(ts)),
+        priority: Priority::Control,
+        content_type: ContentType::Json,
+        extensions: Default::default(),
+    }
+}
+
+/// ACK for a `Connect` frame, correlated with its ID
+///
+/// Advertises the relay's onion key, if it has one, for the first hop of
+/// the agent's onion routes.
+pub(crate) fn connect_ack(frame: &OpacusFrame, onion_key: Option<&OnionKey>) -> OpacusFrame {
+    let mut payload = serde_json::json!({
+        "compression": Compression::supported(),
+        "ackFor": frame.id
+    });
+    if let Some(key) = onion_key {
+        payload["onionKey"] = hex::encode(key.public_key()).into();
+    }
+    let payload = serde_json::to_vec(&payload).unwrap_or_default();
+    relay_frame(frame, FrameType::Ack, payload)
+}
+
+/// Reply to a ping addressed to the relay (`None` for replies and invalid pings)
+pub(crate) fn ping_reply(frame: &OpacusFrame) -> Option<OpacusFrame> {
+    let ping = frame.payload_as::<PingPayload>().ok()?;
+    if ping.reply {
+        return None;
+    }
+    let payload = serde_json::to_vec(&PingPayload { reply: true, ..ping }).unwrap_or_default();
+    Some(relay_frame(frame, FrameType::Ping, payload))
+}
+
+/// Published prekey bundles, by agent
+#[derive(Default)]
+pub(crate) struct PreKeyDirectory {
+    bundles: Mutex<HashMap<String, PreKeyBundle>>,
+}
+
+impl PreKeyDirectory {
+    /// Store the bundle of a `PreKeyPublish` frame if it is valid and the sender's own
+    ///
+    /// `sender` is the agent connected on the frame's connection, with the
+    /// Ed25519 key it connected with; the frame must be signed by that key
+    /// and the bundle must be for it.
+    pub fn store(&self, frame: &OpacusFrame, sender: Option<(&str, &[u8; 32])>) {
+        let Some((_, ed_pub)) = sender.filter(|(id, _)| *id == frame.from) else {
+            warn!("Prekey bundle from {} on a foreign connection", frame.from);
+            return;
+        };
+        let signed = match (&frame.hmac, &frame.sig) {
+            (Some(hmac), Some(sig)) => SecurityManager::verify(ed_pub, &SecurityManager::frame_sign_data(frame, hmac), sig),
+            _ => false,
+        };
+        if !signed {
+            warn!("Unsigned prekey bundle from {}", frame.from);
+            return;
+        }
+        let mut bundle = match serde_json::from_slice::<PreKeyBundle>(&frame.payload) {
+            Ok(b) => b,
+            Err(e) => {
+                warn!("Invalid prekey bundle from {}: {}", frame.from, e);
+                return;
+            }
+        };
+        if bundle.agent_id != frame.from || bundle.ed_pub != *ed_pub {
+            warn!("Prekey bundle owner mismatch from {}", frame.from);
+            return;
+        }
+        if let Err(e) = bundle.verify() {
+            warn!("Rejected prekey bundle from {}: {}", frame.from, e);
+            return;
+        }
+
+        debug!("Stored {} one-time prekeys for {}", bundle.one_time_prekeys.len(), frame.from);
+        let mut bundles = self.bundles.lock().unwrap_or_else(|e| e.into_inner());
+        match bundles.get_mut(&frame.from) {
+            // Same signed prekey: replenish one-time prekeys
+            Some(existing) if existing.signed_prekey == bundle.signed_prekey => {
+                let room = MAX_ONE_TIME_PREKEYS.saturating_sub(existing.one_time_prekeys.len());
+                existing.one_time_prekeys.extend(bundle.one_time_prekeys.into_iter().take(room));
+            }
+            _ => {
+                bundle.one_time_prekeys.truncate(MAX_ONE_TIME_PREKEYS);
+                bundles.insert(frame.from.clone(), bundle);
+            }
+        }
+    }
+
+    /// Answer to a `PreKeyFetch` frame, taking one of the target's one-time prekeys
+    pub fn reply(&self, frame: &OpacusFrame) -> OpacusFrame {
+        let target = serde_json::from_slice::<serde_json::Value>(&frame.payload)
+            .ok()
+            .and_then(|p| p["agentId"].as_str().map(String::from))
+            .unwrap_or_default();
+
+        // Empty payload signals that no bundle is published
+        let payload = self.bundles.lock().unwrap_or_else(|e| e.into_inner())
+            .get_mut(&target)
+            .map(|b| b.take_one())
+            .and_then(|b| serde_json::to_vec(&b).ok())
+            .unwrap_or_default();
+        relay_frame(frame, FrameType::PreKeyFetch, payload)
+    }
+
+    /// Number of agents with a published bundle
+    #[cfg(feature = "relay")]
+    pub fn len(&self) -> usize {
+        self.bundles.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+}
+
+/// Published capabilities, by agent
+#[derive(Default)]
+pub(crate) struct CapabilityDirectory {
+    capabilities: Mutex<HashMap<String, Capabilities>>,
+}
+
+impl CapabilityDirectory {
+    /// Handle a `Capabilities` frame addressed to the relay
+    ///
+    /// `sender` is the agent connected on the frame's connection; only its
+    /// own capabilities are stored.
+    ///
+    /// # Returns
+    /// The answer to a lookup
+    pub fn handle(&self, frame: &OpacusFrame, sender: Option<&str>) -> Option<OpacusFrame> {
+        if let Some(query) = frame.capability_query() {
+            // Empty payload signals that no capabilities are published
+            let payload = self.capabilities.lock().unwrap_or_else(|e| e.into_inner())
+                .get(&query.fetch)
+                .and_then(|c| serde_json::to_vec(c).ok())
+                .unwrap_or_default();
+            let mut reply = relay_frame(frame, FrameType::Capabilities, payload);
+            if let Some(id) = frame.id {
+                reply.set_reply_to(id);
+            }
+            return Some(reply);
+        }
+
+        match frame.capabilities() {
+            Some(capabilities) if capabilities.agent_id == frame.from && sender == Some(frame.from.as_str()) => {
+                debug!("Stored capabilities of {}", frame.from);
+                self.capabilities.lock().unwrap_or_else(|e| e.into_inner()).insert(frame.from.clone(), capabilities);
+            }
+            Some(_) => warn!("Capabilities owner mismatch from {}", frame.from),
+            None => warn!("Invalid capabilities from {}", frame.from),
+        }
+        None
+    }
+
+    /// Number of agents with published capabilities
+    #[cfg(feature = "relay")]
+    pub fn len(&self) -> usize {
+        self.capabilities.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+}
+
+/// Published profiles and the agents watching them
+#[derive(Default)]
+pub(crate) struct ProfileDirectory {
+    state: Mutex<ProfileState>,
+}
+
+#[derive(Default)]
+struct ProfileState {
+    /// Newest profile of each agent
+    profiles: HashMap<String, SignedProfile>,
+    /// Agents notified of new profiles, by the agent they watch
+    watchers: HashMap<String, HashSet<String>>,
+}
+
+impl ProfileDirectory {
+    /// Answer a `Profile` lookup, updating the sender's watches
+    ///
+    /// `sender` is the agent connected on the frame's connection; watches
+    /// are only changed for it.
+    ///
+    /// # Returns
+    /// `None` if the frame is not a lookup or only stops a watch
+    pub fn answer(&self, frame: &OpacusFrame, sender: Option<&str>) -> Option<OpacusFrame> {
+        let query = frame.profile_query()?;
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match query.watch {
+            Some(watch) if sender == Some(frame.from.as_str()) => {
+                let watchers = state.watchers.entry(query.fetch.clone()).or_default();
+                if watch {
+                    watchers.insert(frame.from.clone());
+                } else {
+                    watchers.remove(&frame.from);
+                }
+            }
+            Some(_) => warn!("Profile watch from {} on a foreign connection", frame.from),
+            None => {}
+        }
+        if query.watch == Some(false) {
+            return None;
+        }
+
+        // Empty payload signals that no profile is published
+        let payload = state.profiles
+            .get(&query.fetch)
+            .and_then(|p| serde_json::to_vec(p).ok())
+            .unwrap_or_default();
+        let mut reply = relay_frame(frame, FrameType::Profile, payload);
+        if let Some(id) = frame.id {
+            reply.set_reply_to(id);
+        }
+        Some(reply)
+    }
+
+    /// Store a published profile if it is valid, newer and the sender's own
+    ///
+    /// # Returns
+    /// Notifications for the agents watching the sender
+    pub fn store(&self, frame: &OpacusFrame, sender: Option<&str>) -> Vec<OpacusFrame> {
+        if frame.profile_query().is_some() {
+            return Vec::new();
+        }
+        let Some(profile) = frame.profile() else {
+            warn!("Invalid profile from {}", frame.from);
+            return Vec::new();
+        };
+        if profile.agent_id != frame.from || sender != Some(frame.from.as_str()) {
+            warn!("Profile owner mismatch from {}", frame.from);
+            return Vec::new();
+        }
+
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.profiles.get(&frame.from).is_some_and(|p| p.updated_at >= profile.updated_at) {
+            debug!("Ignored outdated profile from {}", frame.from);
+            return Vec::new();
+        }
+        let payload = serde_json::to_vec(&profile).unwrap_or_default();
+        debug!("Stored profile of {}", frame.from);
+        state.profiles.insert(frame.from.clone(), profile);
+        state.watchers
+            .get(&frame.from)
+            .map(|watchers| {
+                watchers
+                    .iter()
+                    .map(|watcher| {
+                        let mut notice = relay_frame(frame, FrameType::Profile, payload.clone());
+                        notice.to = watcher.clone();
+                        notice
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Number of agents with a published profile
+    #[cfg(feature = "relay")]
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).profiles.len()
+    }
+}
+
+/// Agent records kept by the relay as a node of the DHT
+pub(crate) struct DhtRecords {
+    node: Mutex<DhtNode>,
+}
+
+impl Default for DhtRecords {
+    fn default() -> Self {
+        Self::new("relay")
+    }
+}
+
+impl DhtRecords {
+    /// Records of the node reached at `addr`
+    pub fn new(addr: &str) -> Self {
+        Self { node: Mutex::new(DhtNode::new(addr)) }
+    }
+
+    /// Use the node (routing table and records)
+    pub fn with_node<R>(&self, f: impl FnOnce(&mut DhtNode) -> R) -> R {
+        f(&mut self.node.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Answer a message from another node or an agent from this node alone
+    pub fn handle(&self, rpc: DhtRpc) -> DhtMessage {
+        let now = SystemClock.now_ms();
+        self.with_node(|node| {
+            node.prune(now);
+            node.handle(rpc, now)
+        })
+    }
+
+    /// Answer to a `Dht` frame from an agent, correlated with its ID
+    pub fn reply(frame: &OpacusFrame, message: DhtMessage) -> OpacusFrame {
+        let payload = serde_json::to_vec(&DhtRpc::from_agent(message)).unwrap_or_default();
+        let mut reply = relay_frame(frame, FrameType::Dht, payload);
+        if let Some(id) = frame.id {
+            reply.set_reply_to(id);
+        }
+        reply
+    }
+
+    /// Answer a `Dht` frame from an agent from this node alone
+    pub fn answer(&self, frame: &OpacusFrame) -> Option<OpacusFrame> {
+        let Some(rpc) = frame.dht_rpc() else {
+            warn!("Invalid DHT message from {}", frame.from);
+            return None;
+        };
+        Some(Self::reply(frame, self.handle(DhtRpc::from_agent(rpc.message))))
+    }
+
+    /// Number of records kept
+    #[cfg(feature = "relay")]
+    pub fn len(&self) -> usize {
+        self.with_node(|node| node.records())
+    }
+}
+
+/// Last retained `Stream` frame of each channel, by publisher
+#[derive(Default)]
+pub(crate) struct RetainedValues {
+    values: Mutex<HashMap<String, HashMap<String, OpacusFrame>>>,
+}
+
+impl RetainedValues {
+    /// Store a retained `Stream` frame addressed to the relay
+    ///
+    /// `sender` is the agent connected on the frame's connection; only its
+    /// own frames are stored. Empty data clears the channel's value.
+    pub fn store(&self, frame: &OpacusFrame, sender: Option<&str>) {
+        let Some((channel_id, data)) = frame.stream_data().filter(|_| frame.is_retained()) else {
+            warn!("Invalid retained value from {}", frame.from);
+            return;
+        };
+        if sender != Some(frame.from.as_str()) {
+            warn!("Retained value from {} on a foreign connection", frame.from);
+            return;
+        }
+        let mut values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        let channels = values.entry(frame.from.clone()).or_default();
+        if data.is_empty() {
+            debug!("Cleared retained value of {} on {}", frame.from, channel_id);
+            channels.remove(&channel_id);
+        } else if channels.len() >= MAX_RETAINED_PER_AGENT && !channels.contains_key(&channel_id) {
+            warn!("Too many retained channels for {}, ignoring {}", frame.from, channel_id);
+        } else {
+            debug!("Retained value of {} on {}", frame.from, channel_id);
+            channels.insert(channel_id, frame.clone());
+        }
+        if channels.is_empty() {
+            values.remove(&frame.from);
+        }
+    }
+
+    /// Retained frames of the channels a `Subscribe` frame asks for
+    pub fn matching(&self, frame: &OpacusFrame) -> Vec<OpacusFrame> {
+        let Some(request) = frame.subscribe_request() else {
+            return Vec::new();
+        };
+        let values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(channels) = values.get(&frame.to) else {
+            return Vec::new();
+        };
+        match TopicFilter::new(&request.channel_id) {
+            Ok(filter) if filter.is_wildcard() => {
+                channels.iter().filter(|(id, _)| filter.matches(id)).map(|(_, f)| f.clone()).collect()
+            }
+            _ => channels.get(&request.channel_id).cloned().into_iter().collect(),
+        }
+    }
+
+    /// Number of channels with a retained value
+    #[cfg(feature = "relay")]
+    pub fn len(&self) -> usize {
+        self.values.lock().unwrap_or_else(|e| e.into_inner()).values().map(HashMap::len).sum()
+    }
+}
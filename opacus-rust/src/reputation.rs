@@ -0,0 +1,258 @@
+//! Agent reputation attestations
+//!
+//! After working with a peer, an agent signs a [`ReputationAttestation`]
+//! saying whether the peer completed a task or honored a payment. Agents
+//! hand attestations to each other (and, with the `chain` feature, publish
+//! them to a registry contract); a [`ReputationBook`] verifies the ones it
+//! collects and aggregates them into a [`Reputation`] to check before
+//! engaging with the peer.
+
+use std::collections::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+use crate::crypto::{KeyManager, SecurityManager};
+use crate::types::AgentIdentity;
+
+/// Domain separator of attestation signatures
+const ATTESTATION_CONTEXT: &str = "opacus-reputation-v1";
+
+/// Frame extension carrying a [`ReputationAttestation`]
+pub const REPUTATION_EXTENSION: &str = "reputationAttestation";
+
+/// What an attestation vouches for (or against)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReputationClaim {
+    /// The subject delivered a requested task
+    TaskCompleted,
+    /// The subject paid what it owed
+    PaymentHonored,
+}
+
+impl ReputationClaim {
+    /// Wire code (`uint8` on chain)
+    pub fn code(self) -> u8 {
+        match self {
+            ReputationClaim::TaskCompleted => 0,
+            ReputationClaim::PaymentHonored => 1,
+        }
+    }
+
+    /// Claim for a wire code
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(ReputationClaim::TaskCompleted),
+            1 => Some(ReputationClaim::PaymentHonored),
+            _ => None,
+        }
+    }
+}
+
+/// Issuer-signed statement about another agent's conduct
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReputationAttestation {
+    /// Issuing agent
+    pub issuer: String,
+    /// Issuer's Ed25519 public key
+    pub issuer_key: [u8; 32],
+    /// Agent the attestation is about
+    pub subject: String,
+    /// Kind of interaction
+    pub claim: ReputationClaim,
+    /// Whether the subject lived up to it
+    pub positive: bool,
+    /// Interaction attested, e.g. a message or payment ID
+    pub reference: String,
+    /// Issue time (milliseconds)
+    pub ts: u64,
+    /// Ed25519 signature
+    pub signature: Vec<u8>,
+}
+
+impl ReputationAttestation {
+    /// Sign an attestation
+    ///
+    /// # Arguments
+    /// * `identity` - Issuer identity
+    /// * `subject` - Agent attested
+    /// * `claim` - Kind of interaction
+    /// * `positive` - Whether the subject lived up to it
+    /// * `reference` - Interaction attested
+    /// * `ts` - Issue time (milliseconds)
+    pub fn sign(
+        identity: &AgentIdentity,
+        subject: &str,
+        claim: ReputationClaim,
+        positive: bool,
+        reference: &str,
+        ts: u64,
+    ) -> Self {
+        let mut attestation = Self {
+            issuer: identity.id.clone(),
+            issuer_key: identity.ed_pub,
+            subject: subject.to_string(),
+            claim,
+            positive,
+            reference: reference.to_string(),
+            ts,
+            signature: Vec::new(),
+        };
+        attestation.signature = SecurityManager::sign(&identity.ed_priv, &attestation.signing_data());
+        attestation
+    }
+
+    fn signing_data(&self) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!([
+            ATTESTATION_CONTEXT,
+            self.issuer,
+            self.subject,
+            self.claim,
+            self.positive,
+            self.reference,
+            self.ts,
+        ]))
+        .expect("JSON array")
+    }
+
+    /// Verify that the attestation was signed by `issuer` about someone else
+    pub fn verify(&self) -> Result<(), String> {
+        if KeyManager::agent_id(&self.issuer_key) != self.issuer {
+            return Err("Issuer ID does not match signing key".into());
+        }
+        if self.issuer == self.subject {
+            return Err("Agents cannot attest to themselves".into());
+        }
+        if !SecurityManager::verify(&self.issuer_key, &self.signing_data(), &self.signature) {
+            return Err("Invalid attestation signature".into());
+        }
+        Ok(())
+    }
+}
+
+/// Aggregated attestations about one agent
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reputation {
+    /// Tasks attested as completed
+    pub tasks_completed: u64,
+    /// Tasks attested as not completed
+    pub tasks_failed: u64,
+    /// Payments attested as honored
+    pub payments_honored: u64,
+    /// Payments attested as not honored
+    pub payments_defaulted: u64,
+    /// Distinct agents that attested
+    pub issuers: usize,
+}
+
+impl Reputation {
+    /// Number of attestations counted
+    pub fn total(&self) -> u64 {
+        self.tasks_completed + self.tasks_failed + self.payments_honored + self.payments_defaulted
+    }
+
+    /// Share of positive attestations, or `None` without any
+    pub fn score(&self) -> Option<f64> {
+        let total = self.total();
+        (total > 0).then(|| (self.tasks_completed + self.payments_honored) as f64 / total as f64)
+    }
+}
+
+/// Verified attestations collected about other agents
+///
+/// Each issuer counts once per claim and reference; a later attestation
+/// replaces an earlier one, so issuers can revise their verdict.
+#[derive(Debug, Default)]
+pub struct ReputationBook {
+    attestations: HashMap<String, HashMap<(String, ReputationClaim, String), ReputationAttestation>>,
+}
+
+impl ReputationBook {
+    /// Create empty book
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Verify and keep an attestation
+    ///
+    /// # Returns
+    /// Whether it was new or newer than the one it replaces
+    pub fn add(&mut self, attestation: ReputationAttestation) -> Result<bool, String> {
+        attestation.verify()?;
+        let key = (attestation.issuer.clone(), attestation.claim, attestation.reference.clone());
+        let by_subject = self.attestations.entry(attestation.subject.clone()).or_default();
+        if by_subject.get(&key).is_some_and(|known| known.ts >= attestation.ts) {
+            return Ok(false);
+        }
+        by_subject.insert(key, attestation);
+        Ok(true)
+    }
+
+    /// Attestations about an agent, oldest first
+    pub fn attestations(&self, subject: &str) -> Vec<ReputationAttestation> {
+        let mut attestations: Vec<ReputationAttestation> =
+            self.attestations.get(subject).map(|a| a.values().cloned().collect()).unwrap_or_default();
+        attestations.sort_by_key(|a| a.ts);
+        attestations
+    }
+
+    /// Aggregate the attestations about an agent
+    pub fn reputation(&self, subject: &str) -> Reputation {
+        let mut reputation = Reputation::default();
+        let mut issuers = HashSet::new();
+        for attestation in self.attestations.get(subject).into_iter().flat_map(|a| a.values()) {
+            issuers.insert(attestation.issuer.as_str());
+            let count = match (attestation.claim, attestation.positive) {
+                (ReputationClaim::TaskCompleted, true) => &mut reputation.tasks_completed,
+                (ReputationClaim::TaskCompleted, false) => &mut reputation.tasks_failed,
+                (ReputationClaim::PaymentHonored, true) => &mut reputation.payments_honored,
+                (ReputationClaim::PaymentHonored, false) => &mut reputation.payments_defaulted,
+            };
+            *count += 1;
+        }
+        reputation.issuers = issuers.len();
+        reputation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reputation_book() {
+        let alice = KeyManager::generate_identity(16602);
+        let carol = KeyManager::generate_identity(16602);
+        let bob = KeyManager::generate_identity(16602);
+        let mut book = ReputationBook::new();
+        assert_eq!(book.reputation(&bob.id).score(), None);
+
+        let delivered = ReputationAttestation::sign(&alice, &bob.id, ReputationClaim::TaskCompleted, true, "task-1", 1_000);
+        assert!(book.add(delivered.clone()).unwrap());
+        assert!(!book.add(delivered.clone()).unwrap());
+        assert!(book.add(ReputationAttestation::sign(&alice, &bob.id, ReputationClaim::PaymentHonored, true, "pay-1", 1_100)).unwrap());
+        assert!(book.add(ReputationAttestation::sign(&carol, &bob.id, ReputationClaim::TaskCompleted, true, "task-2", 1_200)).unwrap());
+        assert!(book.add(ReputationAttestation::sign(&carol, &bob.id, ReputationClaim::PaymentHonored, false, "pay-2", 1_300)).unwrap());
+        let reputation = book.reputation(&bob.id);
+        assert_eq!((reputation.tasks_completed, reputation.payments_honored, reputation.payments_defaulted), (2, 1, 1));
+        assert_eq!((reputation.issuers, reputation.total()), (2, 4));
+        assert_eq!(reputation.score(), Some(0.75));
+
+        // A later verdict on the same task replaces the first
+        assert!(book.add(ReputationAttestation::sign(&alice, &bob.id, ReputationClaim::TaskCompleted, false, "task-1", 2_000)).unwrap());
+        assert_eq!(book.reputation(&bob.id).tasks_failed, 1);
+        assert_eq!(book.attestations(&bob.id).last().unwrap().reference, "task-1");
+
+        // Forged, impersonated and self attestations are rejected
+        let mut forged = delivered.clone();
+        forged.positive = false;
+        assert!(book.add(forged).is_err());
+        let impersonated = ReputationAttestation { issuer: carol.id.clone(), ..delivered };
+        assert!(book.add(impersonated).is_err());
+        assert!(book.add(ReputationAttestation::sign(&bob, &bob.id, ReputationClaim::TaskCompleted, true, "task-3", 3_000)).is_err());
+
+        let json = serde_json::to_value(book.attestations(&bob.id)[0].clone()).unwrap();
+        assert_eq!(json["claim"], "paymentHonored");
+        let parsed: ReputationAttestation = serde_json::from_value(json).unwrap();
+        parsed.verify().unwrap();
+    }
+}
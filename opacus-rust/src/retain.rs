@@ -0,0 +1,72 @@
+//! Last-value caching of stream channels
+//!
+//! For state-style channels (prices, sensor readings) a publisher can mark
+//! data as retained (`OpacusClient::publish_retained`): besides streaming it
+//! to its subscribers, it sends the `Stream` frame to `"relay"` with the
+//! [`RETAIN_EXTENSION`]. The relay keeps the latest retained frame of each of
+//! the publisher's channels and, when it routes a `Subscribe` frame to the
+//! publisher, immediately hands the subscriber the retained frames of the
+//! channels subscribed to (all matching channels for a
+//! [`TopicFilter`](crate::TopicFilter)), as MQTT brokers do. Retaining empty
+//! data clears the channel's value.
+//!
+//! Retained frames are delivered unchanged, still addressed to `"relay"`, so
+//! the publisher's signature can be checked. The relay serves them to any
+//! subscriber, so publishers only retain data of open channels.
+
+use crate::types::{FrameType, OpacusFrame};
+
+/// Extension (`true`) marking a `Stream` frame as its channel's last value
+pub const RETAIN_EXTENSION: &str = "retain";
+
+impl OpacusFrame {
+    /// Whether the frame is a retained channel value
+    pub fn is_retained(&self) -> bool {
+        self.frame_type == FrameType::Stream
+            && self.extensions.get(RETAIN_EXTENSION).and_then(|v| v.as_bool()) == Some(true)
+    }
+
+    /// Mark the frame as its channel's last value
+    pub fn set_retained(&mut self) {
+        self.extensions.insert(RETAIN_EXTENSION.to_string(), ciborium::Value::Bool(true));
+    }
+
+    /// Channel ID and data of a `Stream` frame
+    pub fn stream_data(&self) -> Option<(String, Vec<u8>)> {
+        #[derive(serde::Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct StreamPayload {
+            channel_id: String,
+            data: Vec<u8>,
+        }
+        if self.frame_type != FrameType::Stream {
+            return None;
+        }
+        let payload: StreamPayload = self.payload_as().ok()?;
+        Some((payload.channel_id, payload.data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{ErrorCode, ErrorPayload};
+    use crate::proto::CBORCodec;
+
+    #[test]
+    fn test_retained_frame() {
+        let mut frame = ErrorPayload::new(ErrorCode::Unavailable, "").to_frame("a", "relay", 1);
+        frame.frame_type = FrameType::Stream;
+        frame.payload = br#"{"channelId":"prices","data":[52,50]}"#.to_vec().into();
+        assert!(!frame.is_retained());
+        assert_eq!(frame.stream_data(), Some(("prices".into(), b"42".to_vec())));
+
+        frame.set_retained();
+        let decoded = CBORCodec::decode(&CBORCodec::encode(&frame).unwrap()).unwrap();
+        assert!(decoded.is_retained());
+
+        // Only stream frames carry channel values
+        frame.frame_type = FrameType::Msg;
+        assert!(!frame.is_retained() && frame.stream_data().is_none());
+    }
+}
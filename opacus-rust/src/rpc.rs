@@ -0,0 +1,49 @@
+//! Request/response correlation
+//!
+//! A reply carries the message ID of the request it answers in its
+//! [`REPLY_TO_EXTENSION`], so a requester can match replies to requests
+//! while other frames keep arriving. Like other extensions it is not
+//! covered by the frame's HMAC or signature; requesters should only accept a
+//! reply from the agent the request was sent to.
+
+use crate::types::{OpacusFrame, Ulid};
+
+/// Extension holding the message ID (text) of the request a frame answers
+pub const REPLY_TO_EXTENSION: &str = "replyTo";
+
+impl OpacusFrame {
+    /// Message ID of the request this frame answers
+    ///
+    /// # Returns
+    /// `None` if the frame is not a reply or the ID does not parse
+    pub fn reply_to(&self) -> Option<Ulid> {
+        Ulid::from_string(self.extensions.get(REPLY_TO_EXTENSION)?.as_text()?).ok()
+    }
+
+    /// Mark the frame as the reply to a request
+    pub fn set_reply_to(&mut self, request_id: Ulid) {
+        self.extensions.insert(REPLY_TO_EXTENSION.to_string(), ciborium::Value::Text(request_id.to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{ErrorCode, ErrorPayload};
+    use crate::proto::CBORCodec;
+    use crate::types::FrameType;
+
+    #[test]
+    fn test_reply_to() {
+        let request_id = OpacusFrame::new_id(1_700_000_000_000);
+        let mut frame = ErrorPayload::new(ErrorCode::Unavailable, "busy").to_frame("a", "b", 1);
+        assert_eq!(frame.reply_to(), None);
+
+        frame.set_reply_to(request_id);
+        let decoded = CBORCodec::decode(&CBORCodec::encode(&frame).unwrap()).unwrap();
+        assert_eq!((decoded.frame_type, decoded.reply_to()), (FrameType::Error, Some(request_id)));
+
+        frame.extensions.insert(REPLY_TO_EXTENSION.into(), ciborium::Value::Text("not-an-id".into()));
+        assert_eq!(frame.reply_to(), None);
+    }
+}
@@ -0,0 +1,181 @@
+//! Sealed-sender frames
+//!
+//! Sealing hides who sent a frame from the relay, which still learns whom it
+//! is for. The sender signs the frame as usual, then wraps it in a `Msg`
+//! frame to the same recipient whose `from` is a one-time identifier
+//! ([`SEALED_SENDER_PREFIX`] and 16 hex digits). The wrapper's payload is
+//! the signed frame and the sender's public keys, encrypted to the
+//! recipient's X25519 key: an ephemeral key agreement, HKDF-SHA256 and
+//! ChaCha20-Poly1305, prefixed with the ephemeral public key. Only the
+//! recipient can unseal it, which reveals the sender and checks its
+//! signature.
+//!
+//! The wrapper keeps the frame's message ID and priority, so deduplication,
+//! acknowledgements and scheduling work as before. It is not signed: relays
+//! that verify signatures route sealed frames unverified and leave
+//! authentication to the recipient. A relay still sees which connection a
+//! sealed frame arrives on; sealing keeps the sender out of the frames it
+//! forwards, queues, captures and reports.
+
+use crate::content::ContentType;
+use crate::crypto::{KeyManager, SecurityManager};
+use crate::proto::CBORCodec;
+use crate::random::Random;
+use crate::types::{AgentIdentity, FrameType, OpacusFrame};
+
+/// Start of the `from` of sealed frames
+pub const SEALED_SENDER_PREFIX: &str = "sealed:";
+
+/// HKDF info prefix of sealing keys
+const SEALED_SENDER_INFO: &[u8] = b"opacus-sealed-sender-v1";
+
+/// Ephemeral public key, sender keys and AEAD tag
+const SEALED_OVERHEAD: usize = 32 + 64 + 16;
+
+/// Key of a sealed frame, bound to the ephemeral and recipient keys
+fn sealing_key(shared: &[u8; 32], ephemeral_pub: &[u8; 32], recipient_x_pub: &[u8; 32]) -> [u8; 32] {
+    let mut info = SEALED_SENDER_INFO.to_vec();
+    info.extend_from_slice(ephemeral_pub);
+    info.extend_from_slice(recipient_x_pub);
+    SecurityManager::derive_session_key(shared, &info)
+}
+
+impl OpacusFrame {
+    /// Whether the frame is a sealed wrapper hiding its sender
+    pub fn is_sealed(&self) -> bool {
+        self.from.starts_with(SEALED_SENDER_PREFIX)
+    }
+
+    /// Seal a signed frame for its recipient
+    ///
+    /// # Arguments
+    /// * `sender` - Identity that signed the frame
+    /// * `recipient_x_pub` - Recipient's X25519 public key
+    /// * `random` - Source of the ephemeral key
+    ///
+    /// # Returns
+    /// The wrapper to send in place of the frame
+    pub fn seal(&self, sender: &AgentIdentity, recipient_x_pub: &[u8; 32], random: &dyn Random) -> Result<OpacusFrame, String> {
+        if self.from != sender.id || self.sig.is_none() {
+            return Err("Only frames signed by the sender can be sealed".into());
+        }
+        let (ephemeral, ephemeral_pub) = KeyManager::generate_x25519_with(random);
+        let ephemeral_pub = ephemeral_pub.to_bytes();
+        let shared = SecurityManager::derive_shared_secret(&ephemeral.to_bytes(), recipient_x_pub);
+        let key = sealing_key(&shared, &ephemeral_pub, recipient_x_pub);
+
+        let mut plaintext = Vec::with_capacity(64 + self.payload.len() + 128);
+        plaintext.extend_from_slice(&sender.ed_pub);
+        plaintext.extend_from_slice(&sender.x_pub);
+        plaintext.extend_from_slice(&CBORCodec::encode(self).map_err(|e| e.to_string())?);
+        // Each key seals one frame, so a fixed nonce is never reused
+        let ciphertext = SecurityManager::seal(&key, &[0u8; 12], self.to.as_bytes(), &plaintext);
+        let mut payload = ephemeral_pub.to_vec();
+        payload.extend_from_slice(&ciphertext);
+
+        Ok(OpacusFrame {
+            version: self.version,
+            frame_type: FrameType::Msg,
+            from: format!("{}{}", SEALED_SENDER_PREFIX, hex::encode(&ephemeral_pub[..8])),
+            to: self.to.clone(),
+            seq: 0,
+            ts: self.ts,
+            nonce: self.nonce.clone(),
+            payload: payload.into(),
+            hmac: None,
+            sig: None,
+            key_epoch: 0,
+            compressed: None,
+            id: self.id,
+            priority: self.priority,
+            content_type: ContentType::Raw,
+            extensions: Default::default(),
+        })
+    }
+
+    /// Unseal a sealed frame addressed to this agent
+    ///
+    /// Checks that the sealed frame is for `recipient` and signed by the
+    /// agent it names as sender.
+    ///
+    /// # Returns
+    /// The sender's frame and X25519 key, for sealed replies
+    pub fn unseal(&self, recipient: &AgentIdentity) -> Result<(OpacusFrame, [u8; 32]), String> {
+        if !self.is_sealed() || self.to != recipient.id {
+            return Err("Not a sealed frame for this agent".into());
+        }
+        if self.payload.len() < SEALED_OVERHEAD {
+            return Err("Sealed frame too short".into());
+        }
+        let (ephemeral_pub, ciphertext) = self.payload.split_at(32);
+        let ephemeral_pub: [u8; 32] = ephemeral_pub.try_into().expect("32 bytes");
+        let shared = SecurityManager::derive_shared_secret(&recipient.x_priv, &ephemeral_pub);
+        let key = sealing_key(&shared, &ephemeral_pub, &recipient.x_pub);
+        let plaintext = SecurityManager::open(&key, &[0u8; 12], self.to.as_bytes(), ciphertext)?;
+
+        let ed_pub: [u8; 32] = plaintext[..32].try_into().expect("32 bytes");
+        let x_pub: [u8; 32] = plaintext[32..64].try_into().expect("32 bytes");
+        let frame = CBORCodec::decode(&plaintext[64..]).map_err(|e| e.to_string())?;
+        if frame.to != recipient.id {
+            return Err(format!("Sealed frame from {} is for {}", frame.from, frame.to));
+        }
+        if KeyManager::agent_id(&ed_pub) != frame.from {
+            return Err("Sender ID does not match signing key".into());
+        }
+        let (Some(hmac), Some(sig)) = (&frame.hmac, &frame.sig) else {
+            return Err(format!("Sealed frame from {} is not signed", frame.from));
+        };
+        if !SecurityManager::verify(&ed_pub, &SecurityManager::frame_sign_data(&frame, hmac), sig) {
+            return Err(format!("Invalid signature on sealed frame from {}", frame.from));
+        }
+        Ok((frame, x_pub))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::OsRandom;
+
+    #[test]
+    fn test_sealed_sender() {
+        let (alice, bob, carol) = (
+            KeyManager::generate_identity(1),
+            KeyManager::generate_identity(1),
+            KeyManager::generate_identity(1),
+        );
+        let mut security = SecurityManager::new();
+        let frame = security.create_auth_frame(&alice, &[0u8; 32], FrameType::Task, &bob.id, b"{}".to_vec());
+        let sealed = frame.seal(&alice, &bob.x_pub, &OsRandom).unwrap();
+        assert!(sealed.is_sealed() && !frame.is_sealed());
+        assert_eq!((sealed.frame_type, sealed.to.as_str(), sealed.id), (FrameType::Msg, bob.id.as_str(), frame.id));
+        assert!(!sealed.from.contains(&alice.id) && sealed.sig.is_none());
+        // A new identifier for every frame
+        assert_ne!(frame.seal(&alice, &bob.x_pub, &OsRandom).unwrap().from, sealed.from);
+
+        let (unsealed, x_pub) = sealed.unseal(&bob).unwrap();
+        assert_eq!((unsealed.from.as_str(), unsealed.frame_type, x_pub), (alice.id.as_str(), FrameType::Task, alice.x_pub));
+        assert_eq!(unsealed.payload, frame.payload);
+
+        // Only the recipient can unseal, and tampering is detected
+        assert!(sealed.unseal(&carol).is_err());
+        let mut redirected = sealed.clone();
+        redirected.to = carol.id.clone();
+        assert!(redirected.unseal(&carol).is_err());
+        let mut tampered = sealed.clone();
+        let mut payload = tampered.payload.to_vec();
+        *payload.last_mut().unwrap() ^= 1;
+        tampered.payload = payload.into();
+        assert!(tampered.unseal(&bob).is_err());
+
+        // Nobody can seal frames in the name of another agent
+        assert!(frame.seal(&carol, &bob.x_pub, &OsRandom).is_err());
+        let mut impostor = AgentIdentity { id: alice.id.clone(), ..carol.clone() };
+        let forged = security.create_auth_frame(&impostor, &[0u8; 32], FrameType::Msg, &bob.id, b"pay".to_vec());
+        let error = forged.seal(&impostor, &bob.x_pub, &OsRandom).unwrap().unseal(&bob).unwrap_err();
+        assert_eq!(error, "Sender ID does not match signing key");
+        impostor.ed_pub = alice.ed_pub;
+        let forged = security.create_auth_frame(&impostor, &[0u8; 32], FrameType::Msg, &bob.id, b"pay".to_vec());
+        assert!(forged.seal(&impostor, &bob.x_pub, &OsRandom).unwrap().unseal(&bob).unwrap_err().starts_with("Invalid signature"));
+    }
+}
@@ -0,0 +1,454 @@
+//! Deterministic network simulation
+//!
+//! A [`Simulation`] runs virtual agents against an in-process
+//! [`MemoryRelay`] over links with configurable latency, packet loss and
+//! reordering ([`LinkConditions`]). Time is virtual: frames in flight are
+//! delivered only as [`Simulation::advance`] moves the clock forward, and the
+//! agents' clients read the same [`ManualClock`]. Network behaviour and the
+//! agents' randomness are drawn from a seeded generator, so a run with the same seed and the same traffic
+//! plays out the same way every time, which makes retransmission, ordering
+//! and timeout logic testable.
+//!
+//! Conditions apply in both directions, between each agent and the relay.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use futures::future::BoxFuture;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::sync::mpsc;
+use tracing::debug;
+use crate::client::OpacusClient;
+use crate::clock::ManualClock;
+use crate::random::SeededRandom;
+use crate::transport::{MemoryRelay, MemoryTransport, Transport};
+use crate::types::{Network, OpacusConfig, OpacusFrame};
+
+/// Virtual time at which simulations start (milliseconds since the Unix epoch)
+pub const SIM_START_MS: u64 = 1_700_000_000_000;
+
+/// Distribution of one-way link latency
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LatencyModel {
+    /// Every frame takes the same time
+    Fixed(Duration),
+    /// Uniformly distributed between `min` and `max`
+    Uniform { min: Duration, max: Duration },
+    /// Normally distributed, cut off at zero
+    Normal { mean: Duration, std_dev: Duration },
+}
+
+impl Default for LatencyModel {
+    fn default() -> Self {
+        LatencyModel::Fixed(Duration::ZERO)
+    }
+}
+
+impl LatencyModel {
+    /// Draw a latency
+    pub fn sample(&self, rng: &mut impl Rng) -> Duration {
+        match *self {
+            LatencyModel::Fixed(latency) => latency,
+            LatencyModel::Uniform { min, max } if min < max => rng.gen_range(min..=max),
+            LatencyModel::Uniform { min, .. } => min,
+            LatencyModel::Normal { mean, std_dev } => {
+                // Box-Muller transform
+                let (u1, u2): (f64, f64) = (1.0 - rng.gen::<f64>(), rng.gen());
+                let z = (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos();
+                Duration::from_secs_f64((mean.as_secs_f64() + z * std_dev.as_secs_f64()).max(0.0))
+            }
+        }
+    }
+}
+
+/// Behaviour of the link between an agent and the relay
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LinkConditions {
+    pub latency: LatencyModel,
+    /// Probability (0 to 1) that a frame is lost
+    pub loss: f64,
+    /// Probability (0 to 1) that a frame is held back by `reorder_delay`,
+    /// letting frames sent after it overtake it
+    pub reorder: f64,
+    pub reorder_delay: Duration,
+}
+
+/// Counts of simulated frames
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SimStats {
+    /// Frames put on a link, in either direction
+    pub sent: u64,
+    /// Frames that reached the other end of their link
+    pub delivered: u64,
+    /// Frames lost on a link, or sent on a closed connection
+    pub lost: u64,
+    /// Frames held back for reordering
+    pub reordered: u64,
+}
+
+/// Direction of a frame on a link
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    /// Agent to relay
+    Up,
+    /// Relay to agent
+    Down,
+}
+
+/// An agent's link to the relay
+struct Link {
+    /// The agent's connection on the relay side
+    upstream: MemoryTransport,
+    /// Frames that arrived at the agent (`None` once closed)
+    inbox: Option<mpsc::UnboundedSender<OpacusFrame>>,
+    conditions: LinkConditions,
+}
+
+struct SimState {
+    rng: StdRng,
+    conditions: LinkConditions,
+    links: Vec<Link>,
+    /// Frames in flight by arrival time (microseconds) and send order
+    in_flight: BTreeMap<(u64, u64), (usize, Direction, OpacusFrame)>,
+    now_us: u64,
+    next_seq: u64,
+    stats: SimStats,
+}
+
+impl SimState {
+    /// Put a frame on a link, unless the link loses it
+    fn schedule(&mut self, link: usize, direction: Direction, frame: OpacusFrame) {
+        let conditions = self.links[link].conditions;
+        self.stats.sent += 1;
+        if chance(&mut self.rng, conditions.loss) {
+            debug!("Lost {} frame to {}", frame.frame_type.code(), frame.to);
+            self.stats.lost += 1;
+            return;
+        }
+        let mut delay = conditions.latency.sample(&mut self.rng);
+        if chance(&mut self.rng, conditions.reorder) {
+            delay += conditions.reorder_delay;
+            self.stats.reordered += 1;
+        }
+        let at = self.now_us + delay.as_micros() as u64;
+        self.in_flight.insert((at, self.next_seq), (link, direction, frame));
+        self.next_seq += 1;
+    }
+
+    /// Carry frames the relay has routed onto the links towards their agents
+    fn pump(&mut self) {
+        for link in 0..self.links.len() {
+            while let Some(frame) = self.links[link].upstream.try_recv() {
+                self.schedule(link, Direction::Down, frame);
+            }
+            // Close the agent's inbox once the relay dropped it and nothing is left in flight
+            let closed = !self.links[link].upstream.is_connected();
+            if closed && !self.in_flight.values().any(|(l, d, _)| *l == link && *d == Direction::Down) {
+                self.links[link].inbox = None;
+            }
+        }
+    }
+
+    /// Deliver the next frame arriving by `until_us`
+    ///
+    /// # Returns
+    /// `false` if no frame arrives by then
+    fn step(&mut self, until_us: u64) -> bool {
+        let Some(entry) = self.in_flight.first_entry() else { return false };
+        if entry.key().0 > until_us {
+            return false;
+        }
+        let ((at, _), (link, direction, frame)) = entry.remove_entry();
+        self.now_us = at;
+        let delivered = match direction {
+            Direction::Up => self.links[link].upstream.send(&frame).is_ok(),
+            Direction::Down => self.links[link].inbox.as_ref().is_some_and(|inbox| inbox.send(frame).is_ok()),
+        };
+        if delivered {
+            self.stats.delivered += 1;
+        } else {
+            self.stats.lost += 1;
+        }
+        self.pump();
+        true
+    }
+}
+
+/// Whether an event with probability `p` happens
+fn chance(rng: &mut StdRng, p: f64) -> bool {
+    p > 0.0 && rng.gen::<f64>() < p
+}
+
+/// Agents, a relay and the links between them on a virtual clock
+pub struct Simulation {
+    relay: MemoryRelay,
+    clock: Arc<ManualClock>,
+    state: Arc<Mutex<SimState>>,
+}
+
+impl Simulation {
+    /// Create simulation with perfect links
+    ///
+    /// # Arguments
+    /// * `seed` - Seed for latency, loss, reordering and the agents' randomness
+    pub fn new(seed: u64) -> Self {
+        Self {
+            relay: MemoryRelay::new(),
+            clock: Arc::new(ManualClock::new(SIM_START_MS)),
+            state: Arc::new(Mutex::new(SimState {
+                rng: StdRng::seed_from_u64(seed),
+                conditions: LinkConditions::default(),
+                links: Vec::new(),
+                in_flight: BTreeMap::new(),
+                now_us: 0,
+                next_seq: 0,
+                stats: SimStats::default(),
+            })),
+        }
+    }
+
+    /// Use these conditions for links opened from now on
+    pub fn with_conditions(self, conditions: LinkConditions) -> Self {
+        self.lock().conditions = conditions;
+        self
+    }
+
+    /// Virtual clock shared by the simulated agents
+    pub fn clock(&self) -> Arc<ManualClock> {
+        self.clock.clone()
+    }
+
+    /// The simulated relay
+    pub fn relay(&self) -> &MemoryRelay {
+        &self.relay
+    }
+
+    /// Virtual time since the simulation started
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_micros(self.lock().now_us)
+    }
+
+    /// Counts of simulated frames so far
+    pub fn stats(&self) -> SimStats {
+        self.lock().stats
+    }
+
+    /// Number of frames in flight
+    pub fn in_flight(&self) -> usize {
+        self.lock().in_flight.len()
+    }
+
+    /// Open a link to the relay with the simulation's conditions
+    pub fn transport(&self) -> SimTransport {
+        let conditions = self.lock().conditions;
+        self.transport_with(conditions)
+    }
+
+    /// Open a link to the relay with its own conditions
+    pub fn transport_with(&self, conditions: LinkConditions) -> SimTransport {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut state = self.lock();
+        state.links.push(Link { upstream: self.relay.transport(), inbox: Some(tx), conditions });
+        SimTransport { state: self.state.clone(), link: state.links.len() - 1, rx }
+    }
+
+    /// Create an agent on the virtual clock and connect it to the relay
+    ///
+    /// Its identity, nonces and message IDs follow from the simulation's
+    /// seed. The relay's `Ack` arrives once the clock is advanced past the
+    /// round trip.
+    pub async fn agent(&self) -> anyhow::Result<OpacusClient> {
+        let conditions = self.lock().conditions;
+        self.agent_with(conditions).await
+    }
+
+    /// Create an agent whose link has its own conditions
+    pub async fn agent_with(&self, conditions: LinkConditions) -> anyhow::Result<OpacusClient> {
+        let config = OpacusConfig {
+            network: Network::Devnet,
+            relay_url: "sim".to_string(),
+            chain_rpc: String::new(),
+            private_key: None,
+        };
+        let random = Arc::new(SeededRandom::new(self.lock().rng.gen()));
+        let mut client = OpacusClient::with_sources(config, self.clock.clone(), random);
+        client.init().await;
+        client.connect_with(self.transport_with(conditions)).await?;
+        Ok(client)
+    }
+
+    /// Create `count` agents
+    pub async fn agents(&self, count: usize) -> anyhow::Result<Vec<OpacusClient>> {
+        let mut agents = Vec::with_capacity(count);
+        for _ in 0..count {
+            agents.push(self.agent().await?);
+        }
+        Ok(agents)
+    }
+
+    /// Move virtual time forward, delivering the frames that arrive meanwhile
+    ///
+    /// Frames the relay routes on the way are put on their links and
+    /// delivered too if they arrive in time.
+    pub fn advance(&self, duration: Duration) {
+        let until = self.lock().now_us + duration.as_micros() as u64;
+        while self.deliver_next(until) {}
+        self.finish(until);
+    }
+
+    /// Move virtual time forward like `advance`, yielding to the runtime
+    /// after each delivery so agents running as tasks can react
+    ///
+    /// Deterministic on a current-thread runtime.
+    pub async fn run_for(&self, duration: Duration) {
+        let until = self.lock().now_us + duration.as_micros() as u64;
+        loop {
+            tokio::task::yield_now().await;
+            if !self.deliver_next(until) {
+                break;
+            }
+        }
+        self.finish(until);
+        tokio::task::yield_now().await;
+    }
+
+    fn deliver_next(&self, until_us: u64) -> bool {
+        let mut state = self.lock();
+        let delivered = state.step(until_us);
+        self.clock.set(SIM_START_MS + state.now_us / 1000);
+        delivered
+    }
+
+    fn finish(&self, until_us: u64) {
+        let mut state = self.lock();
+        state.now_us = until_us;
+        self.clock.set(SIM_START_MS + until_us / 1000);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SimState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// An agent's end of a simulated link
+pub struct SimTransport {
+    state: Arc<Mutex<SimState>>,
+    link: usize,
+    rx: mpsc::UnboundedReceiver<OpacusFrame>,
+}
+
+impl SimTransport {
+    fn lock(&self) -> MutexGuard<'_, SimState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Transport for SimTransport {
+    fn send(&self, frame: &OpacusFrame) -> anyhow::Result<()> {
+        let mut state = self.lock();
+        if !state.links[self.link].upstream.is_connected() {
+            anyhow::bail!("Connection closed");
+        }
+        state.schedule(self.link, Direction::Up, frame.clone());
+        Ok(())
+    }
+
+    fn recv(&mut self) -> BoxFuture<'_, Option<OpacusFrame>> {
+        Box::pin(self.rx.recv())
+    }
+
+    fn is_congested(&self) -> bool {
+        false
+    }
+
+    fn is_connected(&self) -> bool {
+        self.lock().links[self.link].upstream.is_connected()
+    }
+
+    fn close(&mut self) {
+        let mut state = self.lock();
+        let link = &mut state.links[self.link];
+        link.upstream.close();
+        link.inbox = None;
+    }
+}
+
+impl Drop for SimTransport {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+    use crate::types::FrameType;
+
+    /// Payloads of the messages an agent has received so far
+    ///
+    /// Runs outside the task's cooperative budget, which would otherwise
+    /// report frames still in the channel as pending.
+    fn received(agent: &mut OpacusClient) -> Vec<Vec<u8>> {
+        let mut payloads = Vec::new();
+        while let Some(Some(frame)) = tokio::task::unconstrained(agent.recv()).now_or_never() {
+            if frame.frame_type == FrameType::Msg {
+                payloads.push(frame.payload.to_vec());
+            }
+        }
+        payloads
+    }
+
+    #[tokio::test]
+    async fn test_latency_and_loss() {
+        let latency = LinkConditions { latency: LatencyModel::Fixed(Duration::from_millis(20)), ..Default::default() };
+        let sim = Simulation::new(7).with_conditions(latency);
+        let mut agents = sim.agents(2).await.unwrap();
+        let bob_id = agents[1].get_identity().unwrap().id.clone();
+        sim.advance(Duration::from_millis(40));
+        assert_eq!(sim.relay().get_agent_count(), 2);
+
+        agents[0].send_message(&bob_id, b"hi".to_vec()).await.unwrap();
+        sim.advance(Duration::from_millis(39));
+        assert!(received(&mut agents[1]).is_empty());
+        sim.advance(Duration::from_millis(1));
+        assert_eq!(received(&mut agents[1]), vec![b"hi".to_vec()]);
+        assert_eq!(sim.elapsed(), Duration::from_millis(80));
+        let alice_id = agents[0].get_identity().unwrap().id.clone();
+        assert_eq!(agents[1].latency().one_way(&alice_id).unwrap().max(), Some(Duration::from_millis(40)));
+
+        let mut lossy = sim.agent_with(LinkConditions { loss: 1.0, ..latency }).await.unwrap();
+        sim.advance(Duration::from_secs(1));
+        // Both its Connect and Capabilities frames were lost
+        assert_eq!((sim.relay().get_agent_count(), sim.stats().lost), (2, 2));
+        assert!(lossy.recv().now_or_never().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_reordering_is_reproducible() {
+        async fn run(seed: u64) -> (String, Vec<Vec<u8>>, SimStats) {
+            let sim = Simulation::new(seed).with_conditions(LinkConditions {
+                latency: LatencyModel::Uniform { min: Duration::from_millis(5), max: Duration::from_millis(10) },
+                reorder: 0.3,
+                reorder_delay: Duration::from_millis(50),
+                ..Default::default()
+            });
+            let mut agents = sim.agents(2).await.unwrap();
+            let bob_id = agents[1].get_identity().unwrap().id.clone();
+            sim.advance(Duration::from_secs(1));
+            for i in 0..20u8 {
+                agents[0].send_message(&bob_id, vec![i]).await.unwrap();
+                sim.advance(Duration::from_millis(1));
+            }
+            sim.advance(Duration::from_secs(1));
+            (bob_id, received(&mut agents[1]), sim.stats())
+        }
+
+        let (bob_id, order, stats) = run(42).await;
+        assert_eq!(order.len(), 20);
+        assert!(stats.reordered > 0);
+        assert_ne!(order, (0..20u8).map(|i| vec![i]).collect::<Vec<_>>());
+        assert_eq!(run(42).await, (bob_id, order, stats));
+    }
+}
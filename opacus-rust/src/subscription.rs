@@ -0,0 +1,152 @@
+//! Data channel subscriptions
+//!
+//! Subscribers send a `Subscribe` frame carrying a [`SubscribeRequest`] to
+//! the channel's publisher. Channels with an [`AccessRule`] also need a
+//! [`HoldingProof`]: a signature by the account that holds the token or
+//! allowlist entry, binding it to the subscribing agent and the channel. The
+//! publisher recovers the account and checks the holding on chain (with the
+//! `chain` feature).
+//!
+//! Channels with a [`SubscriptionPlan`] are paid per period. The subscriber
+//! signs a [`StreamingAuthorization`] for a number of periods, which lets
+//! the publisher collect the price once per period from the payments
+//! contract. The subscription ends when the authorization expires.
+//!
+//! [`AccessRule`]: crate::types::AccessRule
+//! [`SubscriptionPlan`]: crate::types::SubscriptionPlan
+
+use serde::{Deserialize, Serialize};
+use crate::types::{decimal, FrameType, OpacusFrame};
+
+/// Maximum age of a holding proof (seconds)
+pub const HOLDING_PROOF_MAX_AGE: u64 = 300;
+
+/// Request to subscribe to a data channel
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscribeRequest {
+    /// Channel to subscribe to
+    pub channel_id: String,
+    /// Proof of holding, for gated channels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proof: Option<HoldingProof>,
+    /// Payment authorization, for paid channels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authorization: Option<StreamingAuthorization>,
+    /// Replay retained data published after this time (milliseconds)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<u64>,
+}
+
+/// Signature by a chain account that it lets an agent subscribe with its holdings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HoldingProof {
+    /// Holding account (`0x`-prefixed)
+    pub account: String,
+    /// Signing time (Unix seconds)
+    pub issued_at: u64,
+    /// EIP-712 signature over channel, agent and time (`0x`-prefixed `r || s || v`)
+    pub signature: String,
+}
+
+impl HoldingProof {
+    /// Whether the proof was issued within `HOLDING_PROOF_MAX_AGE` of `now` (Unix seconds)
+    pub fn is_fresh(&self, now: u64) -> bool {
+        self.issued_at <= now + HOLDING_PROOF_MAX_AGE && now.saturating_sub(self.issued_at) <= HOLDING_PROOF_MAX_AGE
+    }
+}
+
+/// Subscriber's signed authorization to be charged for a channel every period
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamingAuthorization {
+    /// Paying account (`0x`-prefixed)
+    pub payer: String,
+    /// Publisher's account being paid
+    pub payee: String,
+    /// ERC-20 token, or the zero address for the native token
+    pub token: String,
+    /// Amount charged per period (a decimal string in JSON)
+    #[serde(with = "decimal")]
+    pub amount_per_period: u128,
+    /// Period length (seconds)
+    pub period: u64,
+    /// Start of the first period (Unix seconds)
+    pub start: u64,
+    /// Number of periods authorized
+    pub periods: u64,
+    /// EIP-712 signature by the payer (`0x`-prefixed `r || s || v`)
+    pub signature: String,
+}
+
+impl StreamingAuthorization {
+    /// End of the last authorized period (Unix seconds)
+    pub fn expires_at(&self) -> u64 {
+        self.start.saturating_add(self.period.saturating_mul(self.periods))
+    }
+
+    /// Whether `now` (Unix seconds) falls within an authorized period
+    pub fn is_active(&self, now: u64) -> bool {
+        self.start <= now && now < self.expires_at()
+    }
+
+    /// Index of the period containing `now`, if authorized
+    pub fn period_at(&self, now: u64) -> Option<u64> {
+        self.is_active(now).then(|| (now - self.start) / self.period)
+    }
+}
+
+impl OpacusFrame {
+    /// Subscribe request carried by a `Subscribe` frame
+    ///
+    /// # Returns
+    /// `None` for other frame types or unparseable payloads
+    pub fn subscribe_request(&self) -> Option<SubscribeRequest> {
+        if self.frame_type != FrameType::Subscribe {
+            return None;
+        }
+        serde_json::from_slice(&self.decompressed_payload().ok()?).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AccessRule, ChannelType, DataChannel};
+
+    #[test]
+    fn test_subscribe_request() {
+        let request = SubscribeRequest {
+            channel_id: "signals".into(),
+            proof: Some(HoldingProof { account: "0xab".into(), issued_at: 1_000, signature: "0x01".into() }),
+            authorization: None,
+            since: None,
+        };
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["proof"]["issuedAt"], 1_000);
+        assert_eq!(serde_json::from_value::<SubscribeRequest>(json).unwrap(), request);
+        let open = SubscribeRequest { channel_id: "prices".into(), proof: None, authorization: None, since: None };
+        let open = serde_json::to_value(open).unwrap();
+        assert!(open.get("proof").is_none() && open.get("authorization").is_none() && open.get("since").is_none());
+
+        let proof = request.proof.unwrap();
+        assert!(proof.is_fresh(1_000) && proof.is_fresh(1_300) && proof.is_fresh(700));
+        assert!(!proof.is_fresh(1_301) && !proof.is_fresh(699));
+
+        // Channels without a rule keep their old JSON form
+        let channel = DataChannel {
+            id: "signals".into(),
+            channel_type: ChannelType::Output,
+            price_per_byte: 0,
+            price_per_msg: 0,
+            access: Some(AccessRule::Erc20 { token: "0x01".into(), min_balance: 10 }),
+            plan: None,
+        };
+        let json = serde_json::to_string(&channel).unwrap();
+        assert!(json.ends_with(r#""access":{"kind":"erc20","token":"0x01","minBalance":"10"}}"#));
+        assert_eq!(serde_json::from_str::<DataChannel>(&json).unwrap().access, channel.access);
+        let open = serde_json::to_string(&DataChannel { access: None, ..channel }).unwrap();
+        assert!(!open.contains("access"));
+    }
+}
@@ -0,0 +1,355 @@
+//! Task delegation
+//!
+//! An agent hands work to another (an LLM agent to a tool agent, say) with a
+//! `Task` frame whose payload is a [`TaskMessage::Request`]: the kind of
+//! task, its JSON input, an optional deadline and an optional budget. Every
+//! later message about the task carries its ID, and both sides track the
+//! task in a [`TaskBook`]:
+//!
+//! - the worker accepts it (quoting a price if it charges), reports progress
+//!   and sends the result, which is either the output or why the task
+//!   failed (refusing a request is sending a failed result);
+//! - the delegator can cancel it until the result arrives.
+//!
+//! Deadlines are in milliseconds since the epoch, by the delegator's clock.
+//! Workers refuse requests already past their deadline, and delegators
+//! cancel tasks still unfinished at it (`OpacusClient::expire_tasks`).
+//! Prices and budgets are in the smallest unit of the token the agents pay
+//! with; with the `chain` feature, `OpacusClient::pay_task` pays the worker
+//! of a completed task.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::types::{decimal, FrameType, OpacusFrame, Ulid};
+
+/// Finished tasks kept in a [`TaskBook`]; older ones are forgotten
+pub const MAX_FINISHED_TASKS: usize = 1024;
+
+/// Work handed to another agent
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskRequest {
+    /// ID correlating all messages about the task
+    pub task_id: Ulid,
+    /// What to do, e.g. a tool or service name
+    pub kind: String,
+    #[serde(default)]
+    pub input: serde_json::Value,
+    /// Time the result is needed by (milliseconds)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<u64>,
+    /// Most the delegator pays for the task (a decimal string in JSON)
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "decimal::option")]
+    pub budget: Option<u128>,
+}
+
+/// Outcome of a task
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TaskOutcome {
+    /// Output of the task
+    Completed(serde_json::Value),
+    /// Why the task failed or was refused
+    Failed(String),
+}
+
+/// Payload of a `Task` frame
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum TaskMessage {
+    /// Delegate a task (delegator)
+    Request(TaskRequest),
+    /// Take the task on, for `price` if the worker charges (worker)
+    Accept {
+        task_id: Ulid,
+        /// Decimal string in JSON
+        #[serde(default, skip_serializing_if = "Option::is_none", with = "decimal::option")]
+        price: Option<u128>,
+    },
+    /// Report how far the task got (worker)
+    Progress {
+        task_id: Ulid,
+        /// Percentage done
+        percent: u8,
+        #[serde(default)]
+        note: String,
+    },
+    /// Finish the task (worker)
+    Result { task_id: Ulid, outcome: TaskOutcome },
+    /// Withdraw the task (delegator)
+    Cancel {
+        task_id: Ulid,
+        #[serde(default)]
+        reason: String,
+    },
+}
+
+impl TaskMessage {
+    /// Task the message is about
+    pub fn task_id(&self) -> Ulid {
+        match self {
+            TaskMessage::Request(request) => request.task_id,
+            TaskMessage::Accept { task_id, .. }
+            | TaskMessage::Progress { task_id, .. }
+            | TaskMessage::Result { task_id, .. }
+            | TaskMessage::Cancel { task_id, .. } => *task_id,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            TaskMessage::Request(_) => "request",
+            TaskMessage::Accept { .. } => "accept",
+            TaskMessage::Progress { .. } => "progress",
+            TaskMessage::Result { .. } => "result",
+            TaskMessage::Cancel { .. } => "cancel",
+        }
+    }
+}
+
+/// Deadline and budget of a delegated task
+#[derive(Debug, Clone, Default)]
+pub struct TaskOptions {
+    /// Time from now the result is needed by
+    pub deadline: Option<Duration>,
+    /// Most the delegator pays for the task
+    pub budget: Option<u128>,
+}
+
+/// Side of a task this agent is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskRole {
+    /// This agent delegated the task; the peer works on it
+    Delegated,
+    /// The peer delegated the task to this agent
+    Assigned,
+}
+
+/// State of a task
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaskStatus {
+    /// Waiting for the worker to accept
+    Requested,
+    Accepted,
+    /// Percentage done, as last reported
+    Running(u8),
+    Finished(TaskOutcome),
+    /// Cancelled by the delegator, for the given reason
+    Cancelled(String),
+}
+
+impl TaskStatus {
+    /// Whether no more messages are expected about the task
+    pub fn is_finished(&self) -> bool {
+        matches!(self, TaskStatus::Finished(_) | TaskStatus::Cancelled(_))
+    }
+}
+
+/// Task tracked by a [`TaskBook`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskRecord {
+    pub role: TaskRole,
+    /// The other agent: the worker of a delegated task, the delegator of an
+    /// assigned one
+    pub peer: String,
+    pub request: TaskRequest,
+    pub status: TaskStatus,
+    /// Price the worker quoted when accepting
+    pub price: Option<u128>,
+    /// Whether the delegator paid for the task
+    pub paid: bool,
+}
+
+/// Tasks an agent delegated or was assigned, by task ID
+///
+/// Checks that each message comes from the right side of its task and fits
+/// the task's state.
+#[derive(Debug, Clone, Default)]
+pub struct TaskBook {
+    tasks: BTreeMap<Ulid, TaskRecord>,
+}
+
+impl TaskBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, task_id: &Ulid) -> Option<&TaskRecord> {
+        self.tasks.get(task_id)
+    }
+
+    /// Tasks still in progress, oldest first
+    pub fn active(&self) -> impl Iterator<Item = (&Ulid, &TaskRecord)> {
+        self.tasks.iter().filter(|(_, task)| !task.status.is_finished())
+    }
+
+    /// Record a message about a task
+    ///
+    /// # Arguments
+    /// * `peer` - Agent the message was received from or sent to
+    /// * `message` - The message
+    /// * `incoming` - Whether the message was received (rather than sent)
+    ///
+    /// # Returns
+    /// The task after the message; `Err` if the message does not fit it
+    pub fn record(&mut self, peer: &str, message: &TaskMessage, incoming: bool) -> Result<&TaskRecord, String> {
+        let task_id = message.task_id();
+        if let TaskMessage::Request(request) = message {
+            if self.tasks.contains_key(&task_id) {
+                return Err(format!("Task {} already exists", task_id));
+            }
+            let task = TaskRecord {
+                role: if incoming { TaskRole::Assigned } else { TaskRole::Delegated },
+                peer: peer.to_string(),
+                request: request.clone(),
+                status: TaskStatus::Requested,
+                price: None,
+                paid: false,
+            };
+            self.tasks.insert(task_id, task);
+            return Ok(&self.tasks[&task_id]);
+        }
+
+        let task = self
+            .tasks
+            .get_mut(&task_id)
+            .filter(|task| task.peer == peer)
+            .ok_or_else(|| format!("Unknown task {} with {}", task_id, peer))?;
+        // Workers send everything but cancellations
+        let from_worker = !matches!(message, TaskMessage::Cancel { .. });
+        if from_worker != ((task.role == TaskRole::Delegated) == incoming) {
+            let side = if from_worker { "worker" } else { "delegator" };
+            return Err(format!("Only the {} of task {} sends {} messages", side, task_id, message.name()));
+        }
+        task.status = match (message, &task.status) {
+            (_, status) if status.is_finished() => return Err(format!("Task {} is already finished", task_id)),
+            (TaskMessage::Accept { price, .. }, TaskStatus::Requested) => {
+                task.price = *price;
+                TaskStatus::Accepted
+            }
+            (TaskMessage::Progress { percent, .. }, TaskStatus::Accepted | TaskStatus::Running(_)) => {
+                TaskStatus::Running((*percent).min(100))
+            }
+            (TaskMessage::Result { outcome, .. }, _) => TaskStatus::Finished(outcome.clone()),
+            (TaskMessage::Cancel { reason, .. }, _) => TaskStatus::Cancelled(reason.clone()),
+            (_, status) => return Err(format!("Unexpected {} message for task {} in state {:?}", message.name(), task_id, status)),
+        };
+        if task.status.is_finished() {
+            self.prune(task_id);
+        }
+        Ok(&self.tasks[&task_id])
+    }
+
+    /// Delegated tasks still unfinished at their deadline
+    pub fn overdue(&self, now: u64) -> Vec<Ulid> {
+        self.active()
+            .filter(|(_, task)| task.role == TaskRole::Delegated && task.request.deadline.is_some_and(|d| now >= d))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Mark a completed delegated task as paid
+    ///
+    /// # Returns
+    /// The price quoted by its worker; `Err` if the task is not completed,
+    /// has no price or was already paid
+    pub fn mark_paid(&mut self, task_id: &Ulid) -> Result<u128, String> {
+        let task = self.tasks.get_mut(task_id).ok_or_else(|| format!("Unknown task {}", task_id))?;
+        if task.role != TaskRole::Delegated || !matches!(task.status, TaskStatus::Finished(TaskOutcome::Completed(_))) {
+            return Err(format!("Task {} is not a completed delegated task", task_id));
+        }
+        if task.paid {
+            return Err(format!("Task {} is already paid", task_id));
+        }
+        let price = task.price.ok_or_else(|| format!("Task {} has no price", task_id))?;
+        task.paid = true;
+        Ok(price)
+    }
+
+    /// Forget the oldest finished tasks beyond `MAX_FINISHED_TASKS`, except
+    /// the one that just finished
+    fn prune(&mut self, keep: Ulid) {
+        let finished: Vec<Ulid> = self
+            .tasks
+            .iter()
+            .filter(|(id, task)| **id != keep && task.status.is_finished())
+            .map(|(id, _)| *id)
+            .collect();
+        for id in finished.iter().take((finished.len() + 1).saturating_sub(MAX_FINISHED_TASKS)) {
+            self.tasks.remove(id);
+        }
+    }
+}
+
+impl OpacusFrame {
+    /// Decode the message carried by a `Task` frame
+    pub fn task_message(&self) -> Option<TaskMessage> {
+        if self.frame_type != FrameType::Task {
+            return None;
+        }
+        serde_json::from_slice(&self.payload).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{ErrorCode, ErrorPayload};
+
+    /// Send a message between two books
+    fn exchange(books: &mut [TaskBook; 2], message: TaskMessage, from_worker: bool) -> Result<TaskStatus, String> {
+        let [delegator, worker] = books;
+        let (sender, receiver, sender_id, receiver_id) = if from_worker {
+            (worker, delegator, "bob", "alice")
+        } else {
+            (delegator, worker, "alice", "bob")
+        };
+        sender.record(receiver_id, &message, false)?;
+        receiver.record(sender_id, &message, true).map(|task| task.status.clone())
+    }
+
+    #[test]
+    fn test_task_book() {
+        let task_id = OpacusFrame::new_id(1);
+        let request = TaskRequest {
+            task_id,
+            kind: "summarize".into(),
+            input: serde_json::json!({ "url": "https://example.com" }),
+            deadline: Some(1_000),
+            budget: Some(u128::MAX),
+        };
+        let mut books = [TaskBook::new(), TaskBook::new()];
+        assert_eq!(exchange(&mut books, TaskMessage::Request(request.clone()), false), Ok(TaskStatus::Requested));
+        // Progress before acceptance, and acceptance by the delegator, are refused
+        let progress = TaskMessage::Progress { task_id, percent: 150, note: String::new() };
+        assert!(exchange(&mut books, progress.clone(), true).is_err());
+        assert!(exchange(&mut books, TaskMessage::Accept { task_id, price: Some(5) }, false).is_err());
+        assert_eq!(exchange(&mut books, TaskMessage::Accept { task_id, price: Some(5) }, true), Ok(TaskStatus::Accepted));
+        assert_eq!(exchange(&mut books, progress, true), Ok(TaskStatus::Running(100)));
+        assert_eq!(books[0].overdue(999), Vec::<Ulid>::new());
+        assert_eq!((books[0].overdue(1_000), books[1].overdue(1_000)), (vec![task_id], vec![]));
+
+        let outcome = TaskOutcome::Completed(serde_json::json!("short"));
+        let result = TaskMessage::Result { task_id, outcome: outcome.clone() };
+        assert_eq!(exchange(&mut books, result, true), Ok(TaskStatus::Finished(outcome)));
+        assert!(exchange(&mut books, TaskMessage::Cancel { task_id, reason: String::new() }, false).is_err());
+        let [delegator, worker] = &mut books;
+        assert_eq!(delegator.active().count(), 0);
+        assert_eq!(delegator.mark_paid(&task_id), Ok(5));
+        assert!(delegator.mark_paid(&task_id).is_err() && worker.mark_paid(&task_id).is_err());
+
+        // Messages about a task only count from its peer
+        assert!(delegator.record("mallory", &TaskMessage::Request(request.clone()), true).is_err());
+        let cancel = TaskMessage::Cancel { task_id: OpacusFrame::new_id(2), reason: String::new() };
+        assert!(delegator.record("bob", &cancel, true).is_err());
+
+        // Messages travel as JSON in `Task` frames, amounts included
+        let mut frame = ErrorPayload::new(ErrorCode::Unavailable, "").to_frame("a", "b", 1);
+        frame.frame_type = FrameType::Task;
+        frame.payload = serde_json::to_vec(&TaskMessage::Request(request.clone())).unwrap().into();
+        assert_eq!(frame.task_message(), Some(TaskMessage::Request(request)));
+        frame.payload = br#"{"type":"cancel","taskId":"01ARZ3NDEKTSV4RRFFQ69G5FAV"}"#.to_vec().into();
+        assert_eq!(frame.task_message().map(|m| m.name()), Some("cancel"));
+    }
+}
@@ -0,0 +1,397 @@
+//! Attenuable capability tokens
+//!
+//! A [`CapabilityToken`] lets an agent delegate access without sharing its
+//! keys: it mints a token restricted by [`Caveat`]s (recipients, channels,
+//! expiry, payload size) and hands it to another agent, which presents it on
+//! the frames it sends in the [`CAPABILITY_EXTENSION`]. Like a macaroon,
+//! whoever holds a token can attenuate it with more caveats before passing it
+//! on, but never remove any.
+//!
+//! Unlike a macaroon, verifying a token needs no shared secret, so relays can
+//! check it as well as recipients. Each block of caveats names the public key
+//! of a fresh Ed25519 key pair and the next block is signed with its private
+//! key. The first block is signed by the issuer, and the token carries the
+//! private key of its last block as proof of holding it: attenuating signs a
+//! new block and replaces the proof, so the previous proof, and with it the
+//! laxer token, is not passed on.
+//!
+//! Tokens are bearer credentials, so frames never carry the proof. A
+//! [`CapabilityPresentation`] carries the blocks and a signature of the frame
+//! made with the proof, and the sender's frame signature covers the
+//! presentation: a relay or recipient that sees it can neither reuse the
+//! token nor move it to another frame.
+
+use std::fmt;
+use ed25519_dalek::SigningKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::crypto::{KeyManager, SecurityManager};
+use crate::random::Random;
+use crate::redact::Secret;
+use crate::types::{AgentIdentity, FrameType, OpacusFrame};
+
+/// Frame extension carrying a [`CapabilityToken`]
+pub const CAPABILITY_EXTENSION: &str = "capabilityToken";
+
+/// Domain separator of token block signatures
+const TOKEN_CONTEXT: &str = "opacus-capability-v1";
+
+/// Domain separator of presentation signatures
+const PRESENTATION_CONTEXT: &str = "opacus-capability-presentation-v1";
+
+/// Restriction on the frames a token permits
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "camelCase")]
+pub enum Caveat {
+    /// Frames to one of these agents
+    Recipients(Vec<String>),
+    /// `Stream` frames on one of these channels
+    Channels(Vec<String>),
+    /// Frames sent before this time (milliseconds)
+    Expires(u64),
+    /// Frames with payloads of at most this many bytes
+    MaxBytes(u64),
+}
+
+impl Caveat {
+    /// Check the caveat holds for a frame sent at `now` (milliseconds)
+    fn check(&self, frame: &OpacusFrame, now: u64) -> Result<(), String> {
+        let holds = match self {
+            Caveat::Recipients(agents) => agents.contains(&frame.to),
+            Caveat::Channels(channels) => {
+                frame.frame_type == FrameType::Stream
+                    && serde_json::from_slice::<serde_json::Value>(&frame.payload)
+                        .is_ok_and(|payload| payload["channelId"].as_str().is_some_and(|c| channels.iter().any(|ch| ch == c)))
+            }
+            Caveat::Expires(expires) => now < *expires,
+            Caveat::MaxBytes(max) => frame.payload.len() as u64 <= *max,
+        };
+        if holds {
+            Ok(())
+        } else {
+            Err(format!("Capability does not permit the frame: {:?}", self))
+        }
+    }
+}
+
+/// Caveats added by the issuer or by one attenuation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenBlock {
+    /// Restrictions added
+    pub caveats: Vec<Caveat>,
+    /// Ed25519 key the next block is signed with
+    pub next_key: [u8; 32],
+    /// Signature by the issuer (first block) or the previous block's key
+    pub signature: Vec<u8>,
+}
+
+/// Bearer token delegating an issuer's access, restricted by caveats
+///
+/// `Debug` output redacts the proof.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CapabilityToken {
+    /// Agent that minted the token
+    pub issuer: String,
+    /// Issuer's Ed25519 public key
+    pub issuer_key: [u8; 32],
+    /// Blocks of caveats, the issuer's first
+    pub blocks: Vec<TokenBlock>,
+    /// Private key of the last block's `next_key`
+    pub proof: [u8; 32],
+}
+
+impl fmt::Debug for CapabilityToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CapabilityToken")
+            .field("issuer", &self.issuer)
+            .field("issuer_key", &self.issuer_key)
+            .field("blocks", &self.blocks)
+            .field("proof", &Secret(&self.proof))
+            .finish()
+    }
+}
+
+impl CapabilityToken {
+    /// Mint a token
+    ///
+    /// # Arguments
+    /// * `issuer` - Identity whose access is delegated
+    /// * `caveats` - Restrictions on the frames the token permits
+    /// * `random` - Source of the block key
+    pub fn mint(issuer: &AgentIdentity, caveats: Vec<Caveat>, random: &dyn Random) -> Self {
+        let mut token = Self {
+            issuer: issuer.id.clone(),
+            issuer_key: issuer.ed_pub,
+            blocks: Vec::new(),
+            proof: [0u8; 32],
+        };
+        token.push_block(&issuer.ed_priv, caveats, random);
+        token
+    }
+
+    /// Restrict the token further
+    ///
+    /// Hand on the attenuated token instead of this one; its holder cannot
+    /// remove the new caveats.
+    pub fn attenuate(&self, caveats: Vec<Caveat>, random: &dyn Random) -> Self {
+        let mut token = self.clone();
+        token.push_block(&self.proof, caveats, random);
+        token
+    }
+
+    fn push_block(&mut self, signing_key: &[u8; 32], caveats: Vec<Caveat>, random: &dyn Random) {
+        let (next_priv, next_pub) = KeyManager::generate_ed25519_with(random);
+        let mut block = TokenBlock { caveats, next_key: next_pub.to_bytes(), signature: Vec::new() };
+        block.signature = SecurityManager::sign(signing_key, &block_signing_data(&self.issuer, &self.blocks, &block));
+        self.blocks.push(block);
+        self.proof = next_priv.to_bytes();
+    }
+
+    /// All caveats, from every block
+    pub fn caveats(&self) -> impl Iterator<Item = &Caveat> {
+        self.blocks.iter().flat_map(|b| b.caveats.iter())
+    }
+
+    /// Earliest expiry among the caveats (milliseconds)
+    pub fn expires(&self) -> Option<u64> {
+        expires(&self.blocks)
+    }
+
+    /// Verify the chain of blocks from the issuer to the proof
+    pub fn verify(&self) -> Result<(), String> {
+        let last_key = verify_chain(&self.issuer, &self.issuer_key, &self.blocks)?;
+        if SigningKey::from_bytes(&self.proof).verifying_key().to_bytes() != last_key {
+            return Err("Capability proof does not match its last block".into());
+        }
+        Ok(())
+    }
+
+    /// Verify the token and check it permits a frame sent at `now` (milliseconds)
+    pub fn permits(&self, frame: &OpacusFrame, now: u64) -> Result<(), String> {
+        self.verify()?;
+        self.caveats().try_for_each(|caveat| caveat.check(frame, now))
+    }
+
+    /// Present the token on a frame, signing the frame with the proof
+    ///
+    /// The frame must not change afterwards, except for its signature.
+    pub fn present(&self, frame: &OpacusFrame) -> CapabilityPresentation {
+        let mut presentation = CapabilityPresentation {
+            issuer: self.issuer.clone(),
+            issuer_key: self.issuer_key,
+            blocks: self.blocks.clone(),
+            signature: Vec::new(),
+        };
+        presentation.signature = SecurityManager::sign(&self.proof, &presentation.signing_data(frame));
+        presentation
+    }
+}
+
+/// Capability token as presented on a frame, without its proof
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CapabilityPresentation {
+    /// Agent that minted the token
+    pub issuer: String,
+    /// Issuer's Ed25519 public key
+    pub issuer_key: [u8; 32],
+    /// Blocks of caveats, the issuer's first
+    pub blocks: Vec<TokenBlock>,
+    /// Signature of the frame by the last block's key
+    pub signature: Vec<u8>,
+}
+
+impl CapabilityPresentation {
+    /// Signed data: the frame's routing fields and payload, chained to the last block
+    fn signing_data(&self, frame: &OpacusFrame) -> Vec<u8> {
+        let last = self.blocks.last().map(|b| hex::encode(&b.signature)).unwrap_or_default();
+        serde_json::to_vec(&serde_json::json!([
+            PRESENTATION_CONTEXT,
+            last,
+            frame.frame_type,
+            frame.from,
+            frame.to,
+            frame.seq,
+            frame.ts,
+            frame.nonce,
+            frame.id.map(|id| id.to_string()),
+            hex::encode(Sha256::digest(&frame.payload)),
+        ]))
+        .expect("JSON array")
+    }
+
+    /// All caveats, from every block
+    pub fn caveats(&self) -> impl Iterator<Item = &Caveat> {
+        self.blocks.iter().flat_map(|b| b.caveats.iter())
+    }
+
+    /// Earliest expiry among the caveats (milliseconds)
+    pub fn expires(&self) -> Option<u64> {
+        expires(&self.blocks)
+    }
+
+    /// Verify the chain of blocks and that the holder of the proof signed `frame`
+    pub fn verify(&self, frame: &OpacusFrame) -> Result<(), String> {
+        let last_key = verify_chain(&self.issuer, &self.issuer_key, &self.blocks)?;
+        if !SecurityManager::verify(&last_key, &self.signing_data(frame), &self.signature) {
+            return Err("Capability was not presented by its holder".into());
+        }
+        Ok(())
+    }
+
+    /// Verify the presentation and check the token permits `frame` sent at `now` (milliseconds)
+    pub fn permits(&self, frame: &OpacusFrame, now: u64) -> Result<(), String> {
+        self.verify(frame)?;
+        self.caveats().try_for_each(|caveat| caveat.check(frame, now))
+    }
+}
+
+/// Signed data of the next block, chained to the previous signature
+fn block_signing_data(issuer: &str, blocks: &[TokenBlock], block: &TokenBlock) -> Vec<u8> {
+    let previous = blocks.last().map(|b| hex::encode(&b.signature)).unwrap_or_default();
+    serde_json::to_vec(&serde_json::json!([
+        TOKEN_CONTEXT,
+        issuer,
+        blocks.len(),
+        previous,
+        block.caveats,
+        hex::encode(block.next_key),
+    ]))
+    .expect("JSON array")
+}
+
+/// Verify a chain of blocks from the issuer
+///
+/// # Returns
+/// Key of the last block
+fn verify_chain(issuer: &str, issuer_key: &[u8; 32], blocks: &[TokenBlock]) -> Result<[u8; 32], String> {
+    if KeyManager::agent_id(issuer_key) != issuer {
+        return Err("Issuer ID does not match signing key".into());
+    }
+    let mut signer = *issuer_key;
+    for (i, block) in blocks.iter().enumerate() {
+        if !SecurityManager::verify(&signer, &block_signing_data(issuer, &blocks[..i], block), &block.signature) {
+            return Err(format!("Invalid signature on capability block {}", i));
+        }
+        signer = block.next_key;
+    }
+    if blocks.is_empty() {
+        return Err("Capability has no blocks".into());
+    }
+    Ok(signer)
+}
+
+fn expires(blocks: &[TokenBlock]) -> Option<u64> {
+    blocks
+        .iter()
+        .flat_map(|b| b.caveats.iter())
+        .filter_map(|c| match c {
+            Caveat::Expires(expires) => Some(*expires),
+            _ => None,
+        })
+        .min()
+}
+
+impl OpacusFrame {
+    /// Capability token presented on the frame
+    pub fn capability(&self) -> Result<Option<CapabilityPresentation>, String> {
+        self.extensions
+            .get(CAPABILITY_EXTENSION)
+            .map(|value| value.deserialized().map_err(|e| format!("Invalid capability presentation: {}", e)))
+            .transpose()
+    }
+
+    /// Hash of the presentation, covered by the frame signature
+    pub(crate) fn capability_digest(&self) -> Option<String> {
+        let value = self.extensions.get(CAPABILITY_EXTENSION)?;
+        let mut encoded = Vec::new();
+        ciborium::into_writer(value, &mut encoded).ok()?;
+        Some(hex::encode(Sha256::digest(&encoded)))
+    }
+
+    /// Check the presented capability token, if any, permits the frame at `now` (milliseconds)
+    ///
+    /// The frame's own signature, which covers the presentation, is checked
+    /// separately.
+    ///
+    /// # Returns
+    /// The presentation, or `None` if the frame carries none
+    pub fn verify_capability(&self, now: u64) -> Result<Option<CapabilityPresentation>, String> {
+        let presentation = self.capability()?;
+        if let Some(presentation) = &presentation {
+            presentation.permits(self, now)?;
+        }
+        Ok(presentation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::OsRandom;
+
+    #[test]
+    fn test_capability_tokens() {
+        let (owner, mallory) = (KeyManager::generate_identity(1), KeyManager::generate_identity(1));
+        let mut security = SecurityManager::new();
+        let mut frame = |to: &str, payload: &[u8]| {
+            security.create_auth_frame(&mallory, &[0u8; 32], FrameType::Msg, to, payload.to_vec())
+        };
+        let (to_bob, to_carol, large) = (frame("bob", b"hi"), frame("carol", b"hi"), frame("bob", &[0u8; 64]));
+
+        let token = CapabilityToken::mint(&owner, vec![Caveat::Recipients(vec!["bob".into(), "carol".into()])], &OsRandom);
+        token.permits(&to_bob, 1_000).unwrap();
+        token.permits(&to_carol, 1_000).unwrap();
+        token.permits(&large, 1_000).unwrap();
+
+        // Attenuated tokens permit less
+        let narrow = token.attenuate(vec![Caveat::Recipients(vec!["bob".into()]), Caveat::MaxBytes(16), Caveat::Expires(2_000)], &OsRandom);
+        narrow.permits(&to_bob, 1_000).unwrap();
+        assert!(narrow.permits(&to_carol, 1_000).is_err());
+        assert!(narrow.permits(&large, 1_000).is_err());
+        assert!(narrow.permits(&to_bob, 2_000).is_err());
+        assert_eq!((narrow.expires(), token.expires()), (Some(2_000), None));
+
+        // Caveats cannot be removed or changed, nor blocks dropped
+        let mut stripped = narrow.clone();
+        stripped.blocks[1].caveats.clear();
+        assert!(stripped.verify().unwrap_err().starts_with("Invalid signature"));
+        let mut truncated = narrow.clone();
+        truncated.blocks.pop();
+        assert_eq!(truncated.verify().unwrap_err(), "Capability proof does not match its last block");
+        let forged = CapabilityToken { issuer: owner.id.clone(), ..CapabilityToken::mint(&mallory, Vec::new(), &OsRandom) };
+        assert!(forged.verify().is_err());
+
+        // Channel caveats permit only streams on those channels
+        let channels = CapabilityToken::mint(&owner, vec![Caveat::Channels(vec!["prices".into()])], &OsRandom);
+        assert!(channels.permits(&to_bob, 1_000).is_err());
+        let mut stream = security.create_auth_frame(&mallory, &[0u8; 32], FrameType::Stream, "broadcast", br#"{"channelId":"prices","data":[]}"#.to_vec());
+        channels.permits(&stream, 1_000).unwrap();
+        stream.payload = br#"{"channelId":"news","data":[]}"#.to_vec().into();
+        assert!(channels.permits(&stream, 1_000).is_err());
+
+        // Frames carry a signature by the proof, bound to that frame, never the proof
+        let presentation = narrow.present(&to_bob);
+        presentation.permits(&to_bob, 1_000).unwrap();
+        assert_eq!(presentation.verify(&large).unwrap_err(), "Capability was not presented by its holder");
+        let forged = CapabilityPresentation { signature: vec![0u8; 64], ..presentation.clone() };
+        assert!(forged.verify(&to_bob).is_err());
+        assert!(!serde_json::to_string(&presentation).unwrap().contains(&serde_json::to_string(&narrow.proof).unwrap()));
+
+        // The sender's signature covers the presentation, which is redacted in logs
+        let mut presented = to_bob.clone();
+        let unbound = SecurityManager::frame_sign_data(&presented, "mac");
+        presented.extensions.insert(CAPABILITY_EXTENSION.to_string(), ciborium::Value::serialized(&presentation).unwrap());
+        assert_eq!(presented.verify_capability(1_000).unwrap(), Some(presentation.clone()));
+        let bound = SecurityManager::frame_sign_data(&presented, "mac");
+        presented.extensions.insert(CAPABILITY_EXTENSION.to_string(), ciborium::Value::serialized(&token.present(&to_bob)).unwrap());
+        assert!(![unbound, bound].contains(&SecurityManager::frame_sign_data(&presented, "mac")));
+        assert!(format!("{:?}", presented).contains(&format!("\"{}\": {}", CAPABILITY_EXTENSION, crate::redact::REDACTED)));
+
+        let json = serde_json::to_value(&narrow).unwrap();
+        assert_eq!(json["blocks"][1]["caveats"][1], serde_json::json!({ "kind": "maxBytes", "value": 16 }));
+        assert_eq!(serde_json::from_value::<CapabilityToken>(json).unwrap(), narrow);
+    }
+}
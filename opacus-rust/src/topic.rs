@@ -0,0 +1,112 @@
+//! Hierarchical channel names and wildcard filters
+//!
+//! Data channel IDs can be hierarchical, with levels separated by `/`
+//! (`sensors/eu/temperature`). A [`TopicFilter`] selects a family of
+//! channels, as in MQTT: a `+` level matches exactly one level, and a `#`
+//! level, which must come last, matches any number of remaining levels
+//! (including none, so `sensors/#` also matches `sensors`). Wildcards only
+//! count as whole levels; channel IDs should not use `+` or `#` as a level.
+//!
+//! Subscribers send a filter in place of the channel ID of a
+//! [`SubscribeRequest`](crate::SubscribeRequest); the publisher then streams
+//! every open channel it offers that matches, including channels offered
+//! later. Gated and paid channels need a subscription of their own.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Separator of channel ID levels
+pub const TOPIC_SEPARATOR: char = '/';
+
+/// Level matching exactly one level
+pub const SINGLE_LEVEL_WILDCARD: &str = "+";
+
+/// Last level matching any number of levels
+pub const MULTI_LEVEL_WILDCARD: &str = "#";
+
+/// Pattern over hierarchical channel IDs
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TopicFilter(String);
+
+impl TopicFilter {
+    /// Parse a filter
+    ///
+    /// # Returns
+    /// `Err` if `#` is not the last level
+    pub fn new(filter: &str) -> Result<Self, String> {
+        let mut levels = filter.split(TOPIC_SEPARATOR);
+        if levels.by_ref().any(|level| level == MULTI_LEVEL_WILDCARD) && levels.next().is_some() {
+            return Err(format!("`#` must be the last level of {}", filter));
+        }
+        Ok(Self(filter.to_string()))
+    }
+
+    /// Filter as written
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether the filter has wildcard levels (otherwise it names one channel)
+    pub fn is_wildcard(&self) -> bool {
+        self.0.split(TOPIC_SEPARATOR).any(|level| level == SINGLE_LEVEL_WILDCARD || level == MULTI_LEVEL_WILDCARD)
+    }
+
+    /// Whether a channel ID matches the filter
+    pub fn matches(&self, channel_id: &str) -> bool {
+        let mut levels = channel_id.split(TOPIC_SEPARATOR);
+        for pattern in self.0.split(TOPIC_SEPARATOR) {
+            match pattern {
+                MULTI_LEVEL_WILDCARD => return true,
+                SINGLE_LEVEL_WILDCARD => {
+                    if levels.next().is_none() {
+                        return false;
+                    }
+                }
+                literal => {
+                    if levels.next() != Some(literal) {
+                        return false;
+                    }
+                }
+            }
+        }
+        levels.next().is_none()
+    }
+}
+
+impl FromStr for TopicFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl fmt::Display for TopicFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topic_filter() {
+        let matches = |filter: &str, channel: &str| TopicFilter::new(filter).unwrap().matches(channel);
+        assert!(matches("sensors/eu/temperature", "sensors/eu/temperature"));
+        assert!(!matches("sensors/eu/temperature", "sensors/eu"));
+        assert!(matches("sensors/+/temperature", "sensors/us/temperature"));
+        assert!(!matches("sensors/+/temperature", "sensors/us/west/temperature"));
+        assert!(!matches("sensors/+", "sensors"));
+        assert!(matches("sensors/#", "sensors/eu/humidity") && matches("sensors/#", "sensors"));
+        assert!(!matches("sensors/#", "prices/eu"));
+        assert!(matches("#", "prices") && matches("+/#", "prices/eu"));
+
+        // Wildcards are whole levels only
+        assert!(matches("c++", "c++") && !matches("c++", "cpp"));
+        assert!(!TopicFilter::new("c++").unwrap().is_wildcard());
+        assert!(TopicFilter::new("sensors/#/temperature").is_err());
+        assert_eq!("a/+".parse::<TopicFilter>().unwrap().to_string(), "a/+");
+    }
+}
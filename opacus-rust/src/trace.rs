@@ -0,0 +1,236 @@
+//! End-to-end trace context for frames
+//!
+//! A frame can carry a W3C `traceparent` in its [`TRACE_EXTENSION`] so one
+//! message can be followed from the sender through the relay to the
+//! recipient. Clients, the QUIC transport and the relay record `tracing`
+//! spans with the frame ID, peers and trace ID. With the `otel` feature,
+//! outgoing contexts are taken from the current OpenTelemetry span and the
+//! relay's and recipient's spans become its children.
+//!
+//! Like other extensions, the trace context is not covered by the frame's
+//! HMAC or signature; the relay forwards it unchanged.
+
+use std::fmt;
+use std::str::FromStr;
+#[cfg(any(feature = "client", feature = "relay"))]
+use tracing::Span;
+use crate::random::{random_array, OsRandom};
+use crate::types::OpacusFrame;
+
+/// Extension holding the frame's W3C `traceparent` (text)
+pub const TRACE_EXTENSION: &str = "traceparent";
+
+/// W3C trace context of a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceContext {
+    /// Trace the frame belongs to
+    pub trace_id: [u8; 16],
+    /// Span that sent the frame
+    pub parent_id: [u8; 8],
+    /// Whether the sender records this trace
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    /// Start a new sampled trace
+    pub fn new_root() -> Self {
+        Self {
+            trace_id: nonzero(|| random_array(&OsRandom)),
+            parent_id: nonzero(|| random_array(&OsRandom)),
+            sampled: true,
+        }
+    }
+
+    /// Context for a frame sent from within the current span
+    ///
+    /// With the `otel` feature this is the current OpenTelemetry span's
+    /// context if it has one; otherwise a new trace is started.
+    pub fn current() -> Self {
+        #[cfg(feature = "otel")]
+        if let Some(context) = Self::from_span(&Span::current()) {
+            return context;
+        }
+        Self::new_root()
+    }
+
+    /// Same trace, sent from a new span
+    pub fn child(&self) -> Self {
+        Self { parent_id: nonzero(|| random_array(&OsRandom)), ..*self }
+    }
+
+    /// Trace ID as lowercase hex
+    pub fn trace_id_hex(&self) -> String {
+        hex::encode(self.trace_id)
+    }
+
+    /// Encode as a `traceparent` header value
+    pub fn to_traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            hex::encode(self.trace_id),
+            hex::encode(self.parent_id),
+            self.sampled as u8,
+        )
+    }
+
+    /// Parse a `traceparent` header value
+    ///
+    /// Later versions are accepted as long as they start with the version
+    /// `00` fields.
+    pub fn parse(traceparent: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid traceparent: {}", traceparent);
+        let mut fields = traceparent.split('-');
+        let (Some(version), Some(trace_id), Some(parent_id), Some(flags)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid());
+        };
+        if version.len() != 2 || version == "ff" || (version == "00" && fields.next().is_some()) {
+            return Err(invalid());
+        }
+        let trace_id: [u8; 16] = decode_field(trace_id).ok_or_else(invalid)?;
+        let parent_id: [u8; 8] = decode_field(parent_id).ok_or_else(invalid)?;
+        let [flags] = decode_field::<1>(flags).ok_or_else(invalid)?;
+        if trace_id == [0; 16] || parent_id == [0; 8] {
+            return Err(invalid());
+        }
+        Ok(Self { trace_id, parent_id, sampled: flags & 1 == 1 })
+    }
+
+    /// Context of a span, if it is linked to a valid OpenTelemetry span
+    #[cfg(feature = "otel")]
+    pub fn from_span(span: &Span) -> Option<Self> {
+        use opentelemetry::trace::TraceContextExt;
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let context = span.context();
+        let span_context = context.span().span_context().clone();
+        span_context.is_valid().then(|| Self {
+            trace_id: span_context.trace_id().to_bytes(),
+            parent_id: span_context.span_id().to_bytes(),
+            sampled: span_context.is_sampled(),
+        })
+    }
+
+    /// OpenTelemetry context with this one as the remote parent
+    #[cfg(feature = "otel")]
+    pub fn to_otel_context(&self) -> opentelemetry::Context {
+        use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+
+        let flags = if self.sampled { TraceFlags::SAMPLED } else { TraceFlags::default() };
+        let span_context = SpanContext::new(
+            TraceId::from_bytes(self.trace_id),
+            SpanId::from_bytes(self.parent_id),
+            flags,
+            true,
+            TraceState::default(),
+        );
+        opentelemetry::Context::new().with_remote_span_context(span_context)
+    }
+}
+
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_traceparent())
+    }
+}
+
+impl FromStr for TraceContext {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl OpacusFrame {
+    /// Trace context carried by the frame
+    ///
+    /// # Returns
+    /// `None` if the frame has none or it does not parse
+    pub fn trace_context(&self) -> Option<TraceContext> {
+        TraceContext::parse(self.extensions.get(TRACE_EXTENSION)?.as_text()?).ok()
+    }
+
+    /// Attach a trace context to the frame
+    pub fn set_trace_context(&mut self, context: &TraceContext) {
+        self.extensions.insert(TRACE_EXTENSION.to_string(), ciborium::Value::Text(context.to_traceparent()));
+    }
+}
+
+/// Record a frame's trace ID on a span and, with `otel`, parent the span to the frame's sender
+#[cfg(any(feature = "client", feature = "relay"))]
+pub(crate) fn link_span(span: &Span, frame: &OpacusFrame) {
+    let Some(context) = frame.trace_context() else { return };
+    span.record("trace_id", context.trace_id_hex());
+    #[cfg(feature = "otel")]
+    {
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+        let _ = span.set_parent(context.to_otel_context());
+    }
+}
+
+/// Random ID, never all zeros (which the W3C format reserves as invalid)
+fn nonzero<const N: usize>(random: impl Fn() -> [u8; N]) -> [u8; N] {
+    loop {
+        let id = random();
+        if id != [0; N] {
+            return id;
+        }
+    }
+}
+
+/// Decode a lowercase hex field of exactly `N` bytes
+fn decode_field<const N: usize>(field: &str) -> Option<[u8; N]> {
+    if field.len() != 2 * N || field.bytes().any(|b| b.is_ascii_uppercase()) {
+        return None;
+    }
+    hex::decode(field).ok()?.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{ErrorCode, ErrorPayload};
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn test_traceparent() {
+        let context = TraceContext::parse(TRACEPARENT).unwrap();
+        assert_eq!(context.trace_id_hex(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(context.parent_id, [0x00, 0xf0, 0x67, 0xaa, 0x0b, 0xa9, 0x02, 0xb7]);
+        assert!(context.sampled);
+        assert_eq!(context.to_string(), TRACEPARENT);
+
+        // Later versions may append fields
+        assert_eq!(format!("01{}-extra", &TRACEPARENT[2..]).parse::<TraceContext>().unwrap(), context);
+        for invalid in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+        ] {
+            assert!(TraceContext::parse(invalid).is_err(), "{}", invalid);
+        }
+
+        let root = TraceContext::new_root();
+        let child = root.child();
+        assert_eq!(child.trace_id, root.trace_id);
+        assert_ne!(child.parent_id, root.parent_id);
+        assert_eq!(TraceContext::parse(&child.to_traceparent()).unwrap(), child);
+    }
+
+    #[test]
+    fn test_frame_trace_context() {
+        let mut frame = ErrorPayload::new(ErrorCode::Unavailable, "down").to_frame("relay", "alice", 1);
+        assert_eq!(frame.trace_context(), None);
+        let context = TraceContext::parse(TRACEPARENT).unwrap();
+        frame.set_trace_context(&context);
+        assert_eq!(frame.trace_context(), Some(context));
+        assert_eq!(frame.extensions[TRACE_EXTENSION], ciborium::Value::Text(TRACEPARENT.into()));
+    }
+}
@@ -0,0 +1,1024 @@
+//! In-memory transport for tests
+//!
+//! A [`MemoryRelay`] routes frames between [`MemoryTransport`]s in the same
+//! process, so agent logic (handshakes, request/response, retries) can be
+//! unit-tested deterministically, without sockets or timers. Frames are
+//! routed synchronously inside `send` and are never encoded. Connect a client
+//! with [`OpacusClient::connect_with`](crate::OpacusClient::connect_with).
+//!
+//! Like a relay with default settings, it acknowledges `Connect` frames,
+//! answers pings addressed to `"relay"`, stores and serves prekey bundles,
+//! capabilities, profiles, agent records (as a DHT of one node) and retained
+//! channel values, unpacks batches addressed to the relay, and
+//! queues frames for offline agents until they connect. Signatures are not verified.
+//! With an onion key ([`MemoryRelay::set_onion_key`]), it peels onion frames
+//! and forwards them to the next relay of their route, which must be another
+//! `local://` relay. Cover frames for the relay are discarded. With
+//! [`MemoryRelay::set_capability_verification`], frames whose capability
+//! token does not permit them are rejected.
+//!
+//! For single-process applications, a relay URL of the form `local://<name>`
+//! makes [`OpacusClient::connect`](crate::OpacusClient::connect) join the
+//! process-wide relay of that name ([`MemoryRelay::local`]) instead of the
+//! network. Agent IDs can also be served by [`LocalHandler`]s registered on
+//! the relay, without a client of their own.
+//!
+//! ```
+//! use opacus_sdk::{FrameType, MemoryRelay, Network, OpacusClient, OpacusConfig};
+//!
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! let relay = MemoryRelay::new();
+//! let config = OpacusConfig {
+//!     network: Network::Devnet,
+//!     relay_url: "memory".to_string(),
+//!     chain_rpc: String::new(),
+//!     private_key: None,
+//! };
+//! let mut alice = OpacusClient::new(config.clone());
+//! let mut bob = OpacusClient::new(config);
+//! alice.init().await;
+//! let bob_id = bob.init().await.id.clone();
+//! alice.connect_with(relay.transport()).await?;
+//! bob.connect_with(relay.transport()).await?;
+//!
+//! alice.send_message(&bob_id, b"hello".to_vec()).await?;
+//! let frame = loop {
+//!     let frame = bob.recv().await.unwrap();
+//!     if frame.frame_type == FrameType::Msg {
+//!         break frame;
+//!     }
+//! };
+//! assert_eq!(&frame.payload[..], b"hello");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard};
+use futures::future::BoxFuture;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+use crate::content::ContentType;
+use crate::error::{ErrorCode, ErrorPayload};
+use crate::clock::{Clock, SystemClock};
+use crate::onion::{OnionKey, OnionReplays, OnionStep};
+use crate::replies::{self, CapabilityDirectory, DhtRecords, PreKeyDirectory, ProfileDirectory, RetainedValues, MAX_PENDING_PER_AGENT};
+use crate::types::{FrameType, OpacusFrame};
+use super::Transport;
+
+/// Relay URL prefix selecting a process-wide [`MemoryRelay`] by name
+pub const LOCAL_RELAY_SCHEME: &str = "local://";
+
+/// Relays created by [`MemoryRelay::local`], by name
+static LOCAL_RELAYS: LazyLock<Mutex<HashMap<String, MemoryRelay>>> = LazyLock::new(Default::default);
+
+/// Agent served in-process by a [`MemoryRelay`]
+///
+/// Implemented for closures taking the frame and returning the reply payload.
+pub trait LocalHandler: Send + Sync {
+    /// Handle a frame addressed to the handler's agent ID
+    ///
+    /// Called with the relay locked, so it must not use the relay or wait on
+    /// clients connected to it. Payloads may be compressed; see
+    /// [`OpacusFrame::decompressed_payload`].
+    ///
+    /// # Returns
+    /// Payload of a `Msg` reply to the sender, if any
+    fn handle(&self, frame: &OpacusFrame) -> Option<Vec<u8>>;
+}
+
+impl<F> LocalHandler for F
+where
+    F: Fn(&OpacusFrame) -> Option<Vec<u8>> + Send + Sync,
+{
+    fn handle(&self, frame: &OpacusFrame) -> Option<Vec<u8>> {
+        self(frame)
+    }
+}
+
+/// In-process relay for tests and single-process applications
+#[derive(Clone, Default)]
+pub struct MemoryRelay {
+    state: Arc<Mutex<MemoryRelayState>>,
+    prekeys: Arc<PreKeyDirectory>,
+    capabilities: Arc<CapabilityDirectory>,
+    profiles: Arc<ProfileDirectory>,
+    records: Arc<DhtRecords>,
+    retained: Arc<RetainedValues>,
+}
+
+#[derive(Default)]
+struct MemoryRelayState {
+    /// Open connections, by connection number
+    connections: HashMap<u64, mpsc::UnboundedSender<OpacusFrame>>,
+    /// Connection of each connected agent
+    agents: HashMap<String, u64>,
+    /// Ed25519 key each connected agent announced
+    ed_pubs: HashMap<String, [u8; 32]>,
+    /// Frames for offline agents
+    pending: HashMap<String, Vec<OpacusFrame>>,
+    /// Agents served in-process
+    handlers: HashMap<String, Arc<dyn LocalHandler>>,
+    /// Key onion layers for this relay are encrypted to
+    onion_key: Option<OnionKey>,
+    /// Onion layers peeled recently
+    onion_replays: OnionReplays,
+    /// Whether capability tokens on routed frames are checked
+    verify_capabilities: bool,
+    next_connection: u64,
+}
+
+impl MemoryRelayState {
+    /// Agent that connected on a connection
+    fn agent_on(&self, connection: u64) -> Option<&str> {
+        self.agents.iter().find(|(_, c)| **c == connection).map(|(id, _)| id.as_str())
+    }
+}
+
+impl MemoryRelay {
+    /// Create relay with no connections
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the process-wide relay named `name`, creating it on first use
+    ///
+    /// Clients whose relay URL is `local://<name>` connect to it.
+    pub fn local(name: &str) -> Self {
+        let mut relays = LOCAL_RELAYS.lock().unwrap_or_else(|e| e.into_inner());
+        relays.entry(name.to_string()).or_default().clone()
+    }
+
+    /// Serve an agent ID with a handler instead of a connected client
+    ///
+    /// Frames addressed to `agent_id` are passed to the handler, and its
+    /// replies are sent back as `Msg` frames from `agent_id` (unsigned, with
+    /// the request's timestamp). Pings are answered on its behalf. Replaces
+    /// any handler already serving the ID.
+    pub fn register_handler(&self, agent_id: &str, handler: impl LocalHandler + 'static) {
+        self.lock().handlers.insert(agent_id.to_string(), Arc::new(handler));
+    }
+
+    /// Stop serving an agent ID
+    ///
+    /// # Returns
+    /// Whether a handler was registered
+    pub fn unregister_handler(&self, agent_id: &str) -> bool {
+        self.lock().handlers.remove(agent_id).is_some()
+    }
+
+    /// Peel onion frames with `key`, advertised to agents that connect later
+    pub fn set_onion_key(&self, key: OnionKey) {
+        self.lock().onion_key = Some(key);
+    }
+
+    /// Get the public onion key, if set
+    pub fn get_onion_key(&self) -> Option<[u8; 32]> {
+        self.lock().onion_key.as_ref().map(OnionKey::public_key)
+    }
+
+    /// Reject routed frames whose capability token does not permit them,
+    /// checked at the frame's timestamp
+    pub fn set_capability_verification(&self, enabled: bool) {
+        self.lock().verify_capabilities = enabled;
+    }
+
+    /// Open a connection to the relay
+    ///
+    /// The connection belongs to an agent once it sends its `Connect` frame.
+    pub fn transport(&self) -> MemoryTransport {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut state = self.lock();
+        let connection = state.next_connection;
+        state.next_connection += 1;
+        state.connections.insert(connection, tx);
+        MemoryTransport { relay: self.clone(), connection, rx }
+    }
+
+    /// Close an agent's connection, as if the network dropped it
+    ///
+    /// Frames already delivered can still be received; later sends fail.
+    ///
+    /// # Returns
+    /// Whether the agent was connected
+    pub fn disconnect(&self, agent_id: &str) -> bool {
+        let mut state = self.lock();
+        let Some(connection) = state.agents.remove(agent_id) else { return false };
+        state.ed_pubs.remove(agent_id);
+        state.connections.remove(&connection);
+        debug!("Disconnected {}", agent_id);
+        true
+    }
+
+    /// Get connected agent count
+    pub fn get_agent_count(&self) -> usize {
+        self.lock().agents.len()
+    }
+
+    /// Get list of connected agent IDs
+    pub fn get_connected_agents(&self) -> Vec<String> {
+        let mut agents: Vec<String> = self.lock().agents.keys().cloned().collect();
+        agents.sort();
+        agents
+    }
+
+    /// Get pending message count
+    pub fn get_pending_count(&self) -> usize {
+        self.lock().pending.values().map(Vec::len).sum()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MemoryRelayState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Handle a frame sent on a connection
+    fn receive(&self, connection: u64, frame: OpacusFrame) -> anyhow::Result<()> {
+        let mut state = self.lock();
+        let Some(tx) = state.connections.get(&connection).cloned() else {
+            anyhow::bail!("Connection closed");
+        };
+        match frame.frame_type {
+            FrameType::Connect => {
+                state.agents.insert(frame.from.clone(), connection);
+                match replies::connect_ed_pub(&frame) {
+                    Some(ed_pub) => state.ed_pubs.insert(frame.from.clone(), ed_pub),
+                    None => state.ed_pubs.remove(&frame.from),
+                };
+                let _ = tx.send(replies::connect_ack(&frame, state.onion_key.as_ref()));
+                if let Some(mut frames) = state.pending.remove(&frame.from) {
+                    frames.sort_by_key(|f| std::cmp::Reverse(f.priority));
+                    debug!("Flushed {} pending messages for {}", frames.len(), frame.from);
+                    for frame in frames {
+                        let _ = tx.send(frame);
+                    }
+                }
+            }
+            FrameType::PreKeyPublish => {
+                let sender = state.agent_on(connection).and_then(|id| Some((id, state.ed_pubs.get(id)?)));
+                self.prekeys.store(&frame, sender);
+            }
+            FrameType::PreKeyFetch => {
+                let _ = tx.send(self.prekeys.reply(&frame));
+            }
+            FrameType::Capabilities if frame.to == "relay" => {
+                if let Some(reply) = self.capabilities.handle(&frame, state.agent_on(connection)) {
+                    let _ = tx.send(reply);
+                }
+            }
+            FrameType::Profile if frame.to == "relay" => {
+                let sender = state.agent_on(connection).map(String::from);
+                if let Some(reply) = self.profiles.answer(&frame, sender.as_deref()) {
+                    let _ = tx.send(reply);
+                }
+                for notice in self.profiles.store(&frame, sender.as_deref()) {
+                    Self::deliver(&mut state, &tx, notice);
+                }
+            }
+            FrameType::Ping if frame.to == "relay" => {
+                if let Some(reply) = replies::ping_reply(&frame) {
+                    let _ = tx.send(reply);
+                }
+            }
+            FrameType::Dht if frame.to == "relay" => {
+                if let Some(reply) = self.records.answer(&frame) {
+                    let _ = tx.send(reply);
+                }
+            }
+            FrameType::Stream if frame.to == "relay" => self.retained.store(&frame, state.agent_on(connection)),
+            FrameType::Subscribe => {
+                for value in self.retained.matching(&frame) {
+                    let _ = tx.send(value);
+                }
+                Self::deliver(&mut state, &tx, frame);
+            }
+            FrameType::Batch if frame.to == "relay" => Self::route_batch(&mut state, &tx, frame),
+            FrameType::Cover if frame.to == "relay" => {}
+            FrameType::Onion if frame.to == "relay" => {
+                // The next relay may be this one, so peel without holding the lock
+                drop(state);
+                self.route_onion(&frame);
+            }
+            _ => {
+                if state.verify_capabilities {
+                    if let Err(reason) = frame.verify_capability(frame.ts) {
+                        let error = ErrorPayload::new(ErrorCode::Unauthorized, reason);
+                        let _ = tx.send(error.related_to(frame.id).to_frame("relay", &frame.from, frame.ts));
+                        return Ok(());
+                    }
+                }
+                Self::deliver(&mut state, &tx, frame)
+            }
+        }
+        Ok(())
+    }
+
+    /// Peel an onion frame, then forward it to the next relay or deliver it
+    fn route_onion(&self, frame: &OpacusFrame) {
+        let Some(key) = self.lock().onion_key.clone() else {
+            warn!("Dropping onion frame: relay has no onion key");
+            return;
+        };
+        let step = key.peel(frame).and_then(|step| {
+            self.lock().onion_replays.check(frame, SystemClock.now_ms())?;
+            Ok(step)
+        });
+        match step {
+            Ok(OnionStep::Forward { relay, frame }) => match relay.strip_prefix(LOCAL_RELAY_SCHEME) {
+                Some(name) => Self::local(name).route_onion(&frame),
+                None => warn!("Dropping onion frame: cannot reach {}", relay),
+            },
+            Ok(OnionStep::Deliver(frame)) => {
+                // Rejections have nobody to go to: the sender is not connected here
+                let (nobody, _) = mpsc::unbounded_channel();
+                Self::deliver(&mut self.lock(), &nobody, frame);
+            }
+            Err(e) => warn!("Dropping onion frame: {}", e),
+        }
+    }
+
+    /// Unpack a batch addressed to the relay and forward each entry as its sender signed it
+    fn route_batch(state: &mut MemoryRelayState, sender: &mpsc::UnboundedSender<OpacusFrame>, frame: OpacusFrame) {
+        let entries = match frame.batch_entries() {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Invalid batch from {}: {}", frame.from, e);
+                return;
+            }
+        };
+        if entries.iter().any(|e| e.from != frame.from) {
+            let error = ErrorPayload::new(ErrorCode::Unauthorized, "Batch entry sender mismatch");
+            let _ = sender.send(error.related_to(frame.id).to_frame("relay", &frame.from, frame.ts));
+            return;
+        }
+        for entry in entries {
+            Self::deliver(state, sender, entry);
+        }
+    }
+
+    /// Deliver a frame to its recipient, or queue it while the recipient is offline
+    fn deliver(state: &mut MemoryRelayState, sender: &mpsc::UnboundedSender<OpacusFrame>, frame: OpacusFrame) {
+        let reject = |code, reason: String| {
+            let _ = sender.send(ErrorPayload::new(code, reason).related_to(frame.id).to_frame("relay", &frame.from, frame.ts));
+        };
+        if frame.to.is_empty() {
+            reject(ErrorCode::UnknownRecipient, "Frame has no recipient".to_string());
+            return;
+        }
+        if let Some(handler) = state.handlers.get(&frame.to) {
+            let reply = match frame.frame_type {
+                FrameType::Ping => replies::ping_reply(&frame).map(|reply| OpacusFrame { from: frame.to.clone(), ..reply }),
+                _ => handler.handle(&frame).map(|payload| Self::local_reply(&frame, payload)),
+            };
+            if let Some(reply) = reply {
+                let _ = sender.send(reply);
+            }
+            return;
+        }
+        let recipient = state.agents.get(&frame.to).and_then(|connection| state.connections.get(connection));
+        if let Some(tx) = recipient {
+            debug!("Routed {} to {}", frame.frame_type.code(), frame.to);
+            let _ = tx.send(frame);
+            return;
+        }
+        let queue = state.pending.entry(frame.to.clone()).or_default();
+        if queue.len() >= MAX_PENDING_PER_AGENT {
+            reject(ErrorCode::RateLimited, format!("Queue for offline agent {} is full", frame.to));
+            return;
+        }
+        debug!("Queueing message for offline agent: {}", frame.to);
+        queue.push(frame);
+    }
+
+    /// `Msg` frame answering a frame handled by a local handler
+    fn local_reply(frame: &OpacusFrame, payload: Vec<u8>) -> OpacusFrame {
+        OpacusFrame {
+            version: frame.version,
+            frame_type: FrameType::Msg,
+            from: frame.to.clone(),
+            to: frame.from.clone(),
+            seq: 0,
+            ts: frame.ts,
+            nonce: String::new(),
+            payload: payload.into(),
+            hmac: None,
+            sig: None,
+            key_epoch: 0,
+            compressed: None,
+            id: Some(OpacusFrame::new_id(frame.ts)),
+            priority: frame.priority,
+            content_type: ContentType::Raw,
+            extensions: Default::default(),
+        }
+    }
+}
+
+/// Connection to a [`MemoryRelay`]
+pub struct MemoryTransport {
+    relay: MemoryRelay,
+    connection: u64,
+    rx: mpsc::UnboundedReceiver<OpacusFrame>,
+}
+
+impl MemoryTransport {
+    /// Take a frame the relay already delivered, without waiting
+    pub(crate) fn try_recv(&mut self) -> Option<OpacusFrame> {
+        self.rx.try_recv().ok()
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn send(&self, frame: &OpacusFrame) -> anyhow::Result<()> {
+        self.relay.receive(self.connection, frame.clone())
+    }
+
+    fn recv(&mut self) -> BoxFuture<'_, Option<OpacusFrame>> {
+        Box::pin(self.rx.recv())
+    }
+
+    fn is_congested(&self) -> bool {
+        false
+    }
+
+    fn is_connected(&self) -> bool {
+        self.relay.lock().connections.contains_key(&self.connection)
+    }
+
+    fn close(&mut self) {
+        let mut state = self.relay.lock();
+        state.connections.remove(&self.connection);
+        state.agents.retain(|_, connection| *connection != self.connection);
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+    use futures::FutureExt;
+    use crate::client::OpacusClient;
+    use crate::clock::ManualClock;
+    use crate::crypto::{PreKeyStore, RekeyPolicy, SecurityManager};
+    use crate::dht::{DhtAgentRecord, DhtMessage, DhtRpc};
+    use crate::onion::OnionHop;
+    use crate::padding::PaddingPolicy;
+    use crate::proto::{RoutingHeader, WireFormat};
+    use crate::profile::{Profile, SignedProfile};
+    use crate::token::{Caveat, CAPABILITY_EXTENSION};
+    use crate::topic::TopicFilter;
+    use crate::types::{AccessRule, ChannelType, DataChannel, Network, OpacusConfig};
+
+    async fn agent(relay: &MemoryRelay) -> (OpacusClient, String) {
+        let mut client = OpacusClient::new(OpacusConfig {
+            network: Network::Devnet,
+            relay_url: "memory".to_string(),
+            chain_rpc: String::new(),
+            private_key: None,
+        });
+        let id = client.init().await.id.clone();
+        client.connect_with(relay.transport()).await.unwrap();
+        let ack = client.recv().await.unwrap();
+        assert_eq!((ack.frame_type, ack.from.as_str()), (FrameType::Ack, "relay"));
+        (client, id)
+    }
+
+    #[tokio::test]
+    async fn test_memory_relay() {
+        let relay = MemoryRelay::new();
+        let (mut alice, alice_id) = agent(&relay).await;
+        let (mut bob, bob_id) = agent(&relay).await;
+        assert_eq!(relay.get_agent_count(), 2);
+
+        // Request and response
+        alice.send_message(&bob_id, b"ping?".to_vec()).await.unwrap();
+        let request = bob.recv().await.unwrap();
+        assert_eq!((request.from.as_str(), &request.payload[..]), (alice_id.as_str(), &b"ping?"[..]));
+        bob.send_message(&alice_id, b"pong!".to_vec()).await.unwrap();
+        assert_eq!(&alice.recv().await.unwrap().payload[..], b"pong!");
+
+        // Pings to the relay and to a peer, answered from the peer's recv
+        assert!(alice.ping("relay").await.is_ok());
+        let (rtt, _) = tokio::join!(alice.ping(&bob_id), async {
+            tokio::time::timeout(Duration::from_millis(50), bob.recv()).await
+        });
+        assert!(rtt.is_ok());
+
+        // Batches to several agents are forwarded as their signed entries
+        let (mut carol, carol_id) = agent(&relay).await;
+        alice.send_batch(vec![(bob_id.clone(), b"one".to_vec()), (carol_id.clone(), b"two".to_vec())]).await.unwrap();
+        for (client, payload) in [(&mut bob, &b"one"[..]), (&mut carol, &b"two"[..])] {
+            let entry = client.recv().await.unwrap();
+            assert_eq!((entry.frame_type, entry.from.as_str(), &entry.payload[..]), (FrameType::Msg, alice_id.as_str(), payload));
+            assert!(entry.sig.is_some());
+        }
+        assert!(alice.send_batch(Vec::new()).await.is_err());
+        carol.disconnect().await;
+
+        // Session keys change once the peer acknowledges the rekey
+        alice.set_rekey_policy(RekeyPolicy { max_frames: 1, ..RekeyPolicy::default() }).await;
+        alice.send_message(&bob_id, b"first".to_vec()).await.unwrap();
+        alice.send_message(&bob_id, b"second".to_vec()).await.unwrap();
+        let mut epochs = Vec::new();
+        for _ in 0..3 {
+            let frame = bob.recv().await.unwrap();
+            epochs.push((frame.frame_type, frame.key_epoch));
+        }
+        assert_eq!(epochs, vec![(FrameType::Msg, 0), (FrameType::Rekey, 1), (FrameType::Msg, 0)]);
+        let ack = alice.recv().await.unwrap();
+        assert_eq!(SecurityManager::rekey_ack(&ack), Some(1));
+        alice.send_message(&bob_id, b"third".to_vec()).await.unwrap();
+        assert_eq!(bob.recv().await.unwrap().key_epoch, 1);
+
+        // Frames for a dropped agent wait until it reconnects
+        assert!(relay.disconnect(&bob_id));
+        assert!(!bob.is_connected() && !relay.disconnect(&bob_id));
+        alice.send_message(&bob_id, b"later".to_vec()).await.unwrap();
+        assert_eq!(relay.get_pending_count(), 1);
+        bob.connect_with(relay.transport()).await.unwrap();
+        assert_eq!(bob.recv().await.unwrap().frame_type, FrameType::Ack);
+        assert_eq!(&bob.recv().await.unwrap().payload[..], b"later");
+        assert_eq!(relay.get_pending_count(), 0);
+
+        alice.disconnect().await;
+        assert_eq!(relay.get_connected_agents(), vec![bob_id]);
+    }
+
+    #[tokio::test]
+    async fn test_capabilities() {
+        let relay = MemoryRelay::new();
+        let (mut alice, alice_id) = agent(&relay).await;
+        let (mut bob, bob_id) = agent(&relay).await;
+
+        // Published on connect, updated on request
+        let published = bob.fetch_capabilities(&alice_id).await.unwrap().unwrap();
+        assert_eq!(published, alice.local_capabilities());
+        assert!(published.accepts(ContentType::Json) && published.services.is_empty());
+        alice.advertise_service("summarize", "2");
+        alice.publish_capabilities().await.unwrap();
+        bob.fetch_capabilities(&alice_id).await.unwrap();
+        assert_eq!(bob.peer_capabilities(&alice_id).unwrap().service_version("summarize"), Some("2"));
+        assert_eq!(bob.fetch_capabilities("nobody").await.unwrap(), None);
+
+        // Sent directly to a peer
+        assert!(alice.peer_capabilities(&bob_id).is_none());
+        bob.send_capabilities(&alice_id).await.unwrap();
+        assert_eq!(alice.recv().await.unwrap().frame_type, FrameType::Capabilities);
+        assert_eq!(alice.peer_capabilities(&bob_id), Some(&bob.local_capabilities()));
+
+        // Only an agent's own capabilities, sent on its own connection, are stored
+        let mut forged = bob.local_capabilities();
+        forged.agent_id = alice_id.clone();
+        let mut frame = ErrorPayload::new(ErrorCode::Unavailable, "").to_frame(&bob_id, "relay", 1);
+        frame.frame_type = FrameType::Capabilities;
+        frame.payload = serde_json::to_vec(&forged).unwrap().into();
+        let other = relay.transport();
+        other.send(&frame).unwrap();
+        frame.from = alice_id.clone();
+        other.send(&frame).unwrap();
+        let stored = bob.fetch_capabilities(&alice_id).await.unwrap().unwrap();
+        assert_eq!(stored.service_version("summarize"), Some("2"));
+    }
+
+    #[tokio::test]
+    async fn test_profiles() {
+        let relay = MemoryRelay::new();
+        let (mut alice, alice_id) = agent(&relay).await;
+        let (mut bob, _) = agent(&relay).await;
+        assert_eq!(bob.get_profile(&alice_id).await.unwrap(), None);
+
+        let published = alice.publish_profile(Profile { name: "Alice".into(), ..Default::default() }).await.unwrap();
+        assert_eq!(bob.get_profile(&alice_id).await.unwrap(), Some(published.clone()));
+
+        // Watchers are notified of newer profiles
+        bob.watch_profile(&alice_id).await.unwrap();
+        assert_eq!(bob.recv().await.unwrap().profile(), Some(published.clone()));
+        let updated = alice.publish_profile(Profile { name: "Alice v2".into(), ..Default::default() }).await.unwrap();
+        assert!(updated.updated_at > published.updated_at);
+        let notice = bob.recv().await.unwrap();
+        assert_eq!((notice.frame_type, notice.profile()), (FrameType::Profile, Some(updated.clone())));
+        assert_eq!(bob.peer_profile(&alice_id), Some(&updated));
+
+        // Outdated profiles, and profiles sent on another connection, are ignored
+        let profile_frame = |profile: &SignedProfile| {
+            let mut frame = ErrorPayload::new(ErrorCode::Unavailable, "").to_frame(&alice_id, "relay", 1);
+            frame.frame_type = FrameType::Profile;
+            frame.payload = serde_json::to_vec(profile).unwrap().into();
+            frame
+        };
+        let connection = relay.lock().agents[&alice_id];
+        relay.receive(connection, profile_frame(&published)).unwrap();
+        let newer = SignedProfile::sign(alice.get_identity().unwrap(), Profile::default(), updated.updated_at + 1);
+        relay.transport().send(&profile_frame(&newer)).unwrap();
+        assert_eq!(bob.get_profile(&alice_id).await.unwrap(), Some(updated));
+
+        // No notifications once unwatched
+        bob.unwatch_profile(&alice_id).await.unwrap();
+        alice.publish_profile(Profile { name: "Alice v3".into(), ..Default::default() }).await.unwrap();
+        assert_eq!(bob.get_profile(&alice_id).await.unwrap().unwrap().profile.name, "Alice v3");
+        assert!(tokio::task::unconstrained(bob.recv()).now_or_never().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_retained_values() {
+        let relay = MemoryRelay::new();
+        let (mut publisher, publisher_id) = agent(&relay).await;
+        let (mut subscriber, _) = agent(&relay).await;
+        let channel = |id: &str| DataChannel {
+            id: id.into(),
+            channel_type: ChannelType::Output,
+            price_per_byte: 0,
+            price_per_msg: 0,
+            access: None,
+            plan: None,
+        };
+        publisher.offer_channel(channel("sensors/eu/temperature"));
+        publisher.offer_channel(channel("sensors/us/temperature"));
+        publisher.publish_retained("sensors/eu/temperature", b"20".to_vec()).await.unwrap();
+        publisher.publish_retained("sensors/eu/temperature", b"21".to_vec()).await.unwrap();
+        publisher.publish_retained("sensors/us/temperature", b"70".to_vec()).await.unwrap();
+
+        // The latest value arrives on subscribing, before the publisher accepts
+        subscriber.subscribe(&publisher_id, &channel("sensors/eu/temperature")).await.unwrap();
+        let value = subscriber.recv().await.unwrap();
+        assert!(value.is_retained() && value.from == publisher_id);
+        assert_eq!(value.stream_data(), Some(("sensors/eu/temperature".into(), b"21".to_vec())));
+        assert_eq!(publisher.recv().await.unwrap().frame_type, FrameType::Subscribe);
+
+        // Wildcard subscriptions get every matching value; cleared values are gone
+        publisher.clear_retained("sensors/eu/temperature").await.unwrap();
+        subscriber.subscribe_topics(&publisher_id, &TopicFilter::new("sensors/+/temperature").unwrap()).await.unwrap();
+        let value = subscriber.recv().await.unwrap();
+        assert_eq!(value.stream_data(), Some(("sensors/us/temperature".into(), b"70".to_vec())));
+        assert!(tokio::task::unconstrained(subscriber.recv()).now_or_never().is_none());
+
+        // Gated channels do not retain values
+        let mut gated = channel("signals");
+        gated.access = Some(AccessRule::Erc20 { token: "0x01".into(), min_balance: 10 });
+        publisher.offer_channel(gated);
+        assert!(publisher.publish_retained("signals", b"buy".to_vec()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_shared_documents() {
+        async fn apply_next(client: &mut OpacusClient) -> Option<String> {
+            let frame = client.recv().await.unwrap();
+            client.on_document_frame(&frame).await.unwrap()
+        }
+        let relay = MemoryRelay::new();
+        let (mut alice, alice_id) = agent(&relay).await;
+        let (mut bob, bob_id) = agent(&relay).await;
+        alice.open_document("plan", &[&bob_id]).await.unwrap();
+        alice.edit_document("plan", |doc| doc.insert_text("notes", 0, "hi")).await.unwrap();
+
+        // Bob catches up with the edit made before he joined
+        bob.open_document("plan", &[&alice_id]).await.unwrap();
+        assert_eq!(apply_next(&mut bob).await, None);
+        assert_eq!(apply_next(&mut bob).await, Some("plan".into()));
+        // Alice answers Bob's sync with the edit he already applied
+        assert_eq!(apply_next(&mut alice).await, None);
+        assert_eq!(apply_next(&mut bob).await, None);
+        assert_eq!(bob.document("plan").unwrap().text("notes"), "hi");
+
+        // A lost update is recovered by syncing with its sender
+        bob.edit_document("plan", |doc| doc.increment("votes", 1)).await.unwrap();
+        bob.edit_document("plan", |doc| doc.increment("votes", 1)).await.unwrap();
+        alice.recv().await.unwrap();
+        assert_eq!(apply_next(&mut alice).await, None);
+        assert!(alice.document("plan").unwrap().has_gaps());
+        assert_eq!(apply_next(&mut bob).await, None);
+        assert_eq!(apply_next(&mut alice).await, Some("plan".into()));
+        assert_eq!(alice.document("plan").unwrap().counter("votes"), 2);
+
+        // Agents that are not peers of the document are ignored
+        let (mut carol, _) = agent(&relay).await;
+        carol.open_document("plan", &[&alice_id]).await.unwrap();
+        assert_eq!(apply_next(&mut alice).await, None);
+        assert!(tokio::task::unconstrained(carol.recv()).now_or_never().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_sealed_sender() {
+        let relay = MemoryRelay::new();
+        let (mut alice, alice_id) = agent(&relay).await;
+        let (mut bob, bob_id) = agent(&relay).await;
+        alice.set_sealed_sender(true);
+        assert!(alice.send_message(&bob_id, b"hi".to_vec()).await.unwrap_err().to_string().contains("No sealing key"));
+
+        // Bundles are only taken from their owner's connection
+        let identity = bob.identity().unwrap().clone();
+        let (_, bundle) = PreKeyStore::generate(&identity, 1);
+        let forged = SecurityManager::new().create_auth_frame(&identity, &[0; 32], FrameType::PreKeyPublish, "relay", serde_json::to_vec(&bundle).unwrap());
+        relay.prekeys.store(&forged, Some((alice_id.as_str(), &alice.identity().unwrap().ed_pub)));
+        alice.request_prekeys(&bob_id).await.unwrap();
+        assert!(alice.recv().await.unwrap().payload.is_empty());
+
+        // Alice learns Bob's key from his prekey bundle
+        bob.publish_prekeys(1).await.unwrap();
+        alice.request_prekeys(&bob_id).await.unwrap();
+        alice.recv().await.unwrap();
+        assert_eq!(alice.sealing_key(&bob_id), Some(bob.identity().unwrap().x_pub));
+        alice.send_message(&bob_id, b"hi".to_vec()).await.unwrap();
+        let message = bob.recv().await.unwrap();
+        assert_eq!((message.from.as_str(), &message.payload[..]), (alice_id.as_str(), &b"hi"[..]));
+
+        // Bob can answer sealed with the key Alice sealed her message with
+        assert_eq!(bob.sealing_key(&alice_id), Some(alice.identity().unwrap().x_pub));
+        bob.send_sealed(&alice_id, b"hello".to_vec()).await.unwrap();
+        let reply = alice.recv().await.unwrap();
+        assert_eq!((reply.from.as_str(), &reply.payload[..]), (bob_id.as_str(), &b"hello"[..]));
+    }
+
+    #[tokio::test]
+    async fn test_verifiable_credentials() {
+        let relay = MemoryRelay::new();
+        let (mut operator, _) = agent(&relay).await;
+        let (mut alice, alice_id) = agent(&relay).await;
+        let (mut bob, bob_id) = agent(&relay).await;
+
+        // The operator vouches for Alice, who keeps the credential
+        let claims = serde_json::json!({ "model": "llama-3-70b" }).as_object().unwrap().clone();
+        let alice_did = alice.identity().unwrap().did_pkh();
+        let credential = operator.issue_credential(&alice_did, "CertifiedModel", claims, None).unwrap();
+        assert!(bob.add_credential(credential.clone()).is_err());
+        operator.send_credential(&alice_id, &credential).await.unwrap();
+        let frame = alice.recv().await.unwrap();
+        assert_eq!(alice.on_credential(&frame).unwrap(), Some(credential.clone()));
+        assert_eq!(alice.credentials(), std::slice::from_ref(&credential));
+
+        // Bob asks for it, and Alice presents it
+        let challenge = bob.request_presentation(&alice_id, &["CertifiedModel"]).await.unwrap();
+        let frame = alice.recv().await.unwrap();
+        let request = OpacusClient::presentation_request(&frame).unwrap().unwrap();
+        assert_eq!(request.challenge, challenge);
+        assert_eq!(alice.present_credentials(&bob_id, &request).await.unwrap(), 1);
+        let frame = bob.recv().await.unwrap();
+        assert_eq!(bob.on_presentation(&frame).unwrap(), Some(vec![credential.clone()]));
+        assert_eq!(bob.peer_credentials(&alice_id)[0].issuer, operator.identity().unwrap().did_key());
+
+        // A presentation is accepted once, and only when requested
+        assert!(bob.on_presentation(&frame).is_err());
+        alice.present_credentials(&bob_id, &request).await.unwrap();
+        let replayed = bob.recv().await.unwrap();
+        assert!(bob.on_presentation(&replayed).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_capability_tokens() {
+        let relay = MemoryRelay::new();
+        relay.set_capability_verification(true);
+        let (mut alice, alice_id) = agent(&relay).await;
+        let (mut bob, bob_id) = agent(&relay).await;
+        let (mut carol, carol_id) = agent(&relay).await;
+
+        // Alice lets Bob message Carol for her, and Bob narrows it further
+        let token = alice.mint_capability(vec![Caveat::Recipients(vec![carol_id.clone()])]).unwrap();
+        let token = bob.attenuate_capability(&token, vec![Caveat::MaxBytes(8)]);
+        bob.send_with_capability(&carol_id, b"order".to_vec(), &token).await.unwrap();
+        let frame = carol.recv().await.unwrap();
+        assert_eq!((frame.from.as_str(), &frame.payload[..]), (bob_id.as_str(), &b"order"[..]));
+        assert_eq!(carol.on_capability(&frame).unwrap().unwrap().issuer, alice_id);
+        assert!(bob.send_with_capability(&carol_id, b"too large".to_vec(), &token).await.is_err());
+        assert!(bob.send_with_capability(&alice_id, b"hi".to_vec(), &token).await.is_err());
+
+        // The relay rejects frames the token does not permit
+        let mut forged = frame.clone();
+        forged.to = alice_id.clone();
+        relay.transport().send(&forged).unwrap();
+        let mut stripped = token.clone();
+        stripped.blocks[1].caveats.clear();
+        let mut tampered = frame.clone();
+        tampered.extensions.insert(CAPABILITY_EXTENSION.to_string(), ciborium::Value::serialized(&stripped.present(&frame)).unwrap());
+        relay.transport().send(&tampered).unwrap();
+        assert!(tokio::task::unconstrained(alice.recv()).now_or_never().is_none());
+        assert!(tokio::task::unconstrained(carol.recv()).now_or_never().is_none());
+        assert_eq!(relay.get_pending_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_forged_connect_ack() {
+        let relay = MemoryRelay::new();
+        let key = OnionKey::generate();
+        relay.set_onion_key(key.clone());
+        let (mut alice, alice_id) = agent(&relay).await;
+        assert_eq!(alice.relay_onion_key(), Some(key.public_key()));
+
+        // Only the relay's ACK of the pending Connect sets its keys, not a peer's or a replayed one
+        let mallory = relay.transport();
+        let forged = serde_json::json!({
+            "ackFor": OpacusFrame::new_id(1234567890),
+            "onionKey": hex::encode(OnionKey::generate().public_key()),
+            "relayXPub": hex::encode([7u8; 32]),
+        });
+        for (seq, from) in [(1, "mallory"), (2, "relay")] {
+            mallory.send(&OpacusFrame {
+                frame_type: FrameType::Ack,
+                from: from.to_string(),
+                to: alice_id.clone(),
+                payload: serde_json::to_vec(&forged).unwrap().into(),
+                ..OpacusFrame::test(seq)
+            }).unwrap();
+            assert_eq!(alice.recv().await.unwrap().from, from);
+        }
+        assert_eq!(alice.relay_onion_key(), Some(key.public_key()));
+    }
+
+    #[tokio::test]
+    async fn test_onion_routing() {
+        let entry = MemoryRelay::new();
+        let (middle, exit) = (MemoryRelay::local("test-onion-middle"), MemoryRelay::local("test-onion-exit"));
+        let (mut alice, _) = agent(&entry).await;
+        let (mut bob, bob_id) = agent(&exit).await;
+        bob.publish_prekeys(1).await.unwrap();
+        let route = [OnionHop::new("local://test-onion-middle", [0u8; 32]), OnionHop::new("local://test-onion-exit", [0u8; 32])];
+        assert!(alice.send_onion(&bob_id, b"hi".to_vec(), &route).await.unwrap_err().to_string().contains("does not route"));
+
+        // Keys are advertised to agents that connect afterwards
+        let keys = [OnionKey::generate(), OnionKey::generate(), OnionKey::generate()];
+        for (relay, key) in [&entry, &middle, &exit].into_iter().zip(&keys) {
+            relay.set_onion_key(key.clone());
+        }
+        let (mut alice, alice_id) = agent(&entry).await;
+        assert_eq!(alice.relay_onion_key(), entry.get_onion_key());
+        alice.set_sealing_key(&bob_id, bob.identity().unwrap().x_pub);
+        let route = [OnionHop::new("local://test-onion-middle", keys[1].public_key()), OnionHop::new("local://test-onion-exit", keys[2].public_key())];
+        alice.send_onion(&bob_id, b"hi".to_vec(), &route).await.unwrap();
+        let message = bob.recv().await.unwrap();
+        assert_eq!((message.frame_type, message.to.as_str(), &message.payload[..]), (FrameType::Msg, bob_id.as_str(), &b"hi"[..]));
+        assert_eq!(message.from, alice_id);
+
+        // Layers encrypted to another relay's key are dropped
+        let wrong = [OnionHop::new("local://test-onion-middle", keys[2].public_key()), route[1].clone()];
+        alice.send_onion(&bob_id, b"lost".to_vec(), &wrong).await.unwrap();
+        assert!(tokio::task::unconstrained(bob.recv()).now_or_never().is_none());
+        assert_eq!((exit.get_pending_count(), middle.get_pending_count(), entry.get_pending_count()), (0, 0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_padding_and_cover_traffic() {
+        let relay = MemoryRelay::new();
+        let sizes = Arc::new(Mutex::new(Vec::new()));
+        let seen = sizes.clone();
+        relay.register_handler("sink", move |frame: &OpacusFrame| {
+            seen.lock().unwrap().push(RoutingHeader::encoded_size(WireFormat::Cbor.codec().unwrap(), frame).unwrap());
+            None
+        });
+        let clock = Arc::new(ManualClock::new(SystemClock.now_ms()));
+        let mut alice = OpacusClient::with_clock(OpacusConfig {
+            network: Network::Devnet,
+            relay_url: "memory".to_string(),
+            chain_rpc: String::new(),
+            private_key: None,
+        }, clock.clone());
+        alice.init().await;
+        alice.connect_with(relay.transport()).await.unwrap();
+        alice.recv().await.unwrap();
+        let (mut bob, bob_id) = agent(&relay).await;
+
+        // Frames leave padded to a bucket; recipients get them without the padding
+        alice.set_padding(Some(PaddingPolicy::default()));
+        alice.send_message("sink", b"hi".to_vec()).await.unwrap();
+        // Random bytes, which compression cannot shrink
+        alice.send_message("sink", (0..400).map(|_| rand::random::<u8>()).collect()).await.unwrap();
+        let sizes = sizes.lock().unwrap().clone();
+        assert!(sizes[0] >= 511 && sizes[0] <= 512 && sizes[1] >= 1023 && sizes[1] <= 1024, "{:?}", sizes);
+        alice.send_message(&bob_id, b"hi".to_vec()).await.unwrap();
+        let message = bob.recv().await.unwrap();
+        assert_eq!((&message.payload[..], message.padding_len()), (&b"hi"[..], 0));
+
+        // Cover frames are due after a randomized idle time, and the relay discards them
+        assert_eq!(alice.cover_due_in(), None);
+        alice.set_cover_traffic(Some(Duration::from_secs(10)));
+        let due = alice.cover_due_in().unwrap();
+        assert!(due >= Duration::from_secs(5) && due < Duration::from_secs(15));
+        assert!(!alice.send_cover_if_idle().await.unwrap());
+        clock.advance(15_000);
+        assert_eq!(alice.cover_due_in(), Some(Duration::ZERO));
+        assert!(alice.send_cover_if_idle().await.unwrap());
+        assert!(alice.cover_due_in().unwrap() >= Duration::from_secs(5));
+        assert_eq!(relay.get_pending_count(), 0);
+        assert!(tokio::task::unconstrained(bob.recv()).now_or_never().is_none());
+
+        // Other frames put the next cover frame off
+        clock.advance(15_000);
+        alice.send_message(&bob_id, b"busy".to_vec()).await.unwrap();
+        assert!(alice.cover_due_in().unwrap() >= Duration::from_secs(5));
+        alice.set_cover_traffic(None);
+        assert_eq!(alice.cover_due_in(), None);
+    }
+
+    #[tokio::test]
+    async fn test_dht_records() {
+        let relay = MemoryRelay::new();
+        let (mut alice, alice_id) = agent(&relay).await;
+        let (mut bob, _) = agent(&relay).await;
+        assert_eq!(bob.lookup_agent(&alice_id).await.unwrap(), None);
+
+        let published = alice.publish_record(Duration::from_secs(60)).await.unwrap();
+        assert_eq!(published.x_pub, alice.get_identity().unwrap().x_pub);
+        assert_eq!(bob.lookup_agent(&alice_id).await.unwrap(), Some(published.clone()));
+
+        // Republished records replace older ones
+        let updated = alice.publish_record(Duration::from_secs(120)).await.unwrap();
+        assert!(updated.seq > published.seq);
+        assert_eq!(bob.lookup_agent(&alice_id).await.unwrap(), Some(updated.clone()));
+
+        // Older records are ignored and tampered ones refused
+        let store = |record: DhtAgentRecord| relay.records.handle(DhtRpc::from_agent(DhtMessage::Store { record }));
+        assert_eq!(store(published), DhtMessage::Stored);
+        let tampered = DhtAgentRecord { relay: "quic://elsewhere".into(), seq: updated.seq + 1, ..updated.clone() };
+        assert!(matches!(store(tampered), DhtMessage::Refused { .. }));
+        assert_eq!(bob.lookup_agent(&alice_id).await.unwrap(), Some(updated));
+    }
+
+    #[tokio::test]
+    async fn test_local_mode() {
+        let relay = MemoryRelay::local("test-local-mode");
+        relay.register_handler("upper", |frame: &OpacusFrame| Some(frame.payload.to_ascii_uppercase()));
+
+        // Clients join the named relay through their relay URL
+        let mut client = OpacusClient::new(OpacusConfig {
+            network: Network::Devnet,
+            relay_url: "local://test-local-mode".to_string(),
+            chain_rpc: String::new(),
+            private_key: None,
+        });
+        let id = client.init().await.id.clone();
+        client.connect().await.unwrap();
+        assert_eq!(client.recv().await.unwrap().frame_type, FrameType::Ack);
+        assert_eq!(MemoryRelay::local("test-local-mode").get_connected_agents(), vec![id.clone()]);
+
+        client.send_message("upper", b"hello".to_vec()).await.unwrap();
+        let reply = client.recv().await.unwrap();
+        assert_eq!((reply.frame_type, reply.from.as_str(), reply.to.as_str()), (FrameType::Msg, "upper", id.as_str()));
+        assert_eq!(&reply.payload[..], b"HELLO");
+        assert!(client.ping("upper").await.is_ok());
+
+        assert!(relay.unregister_handler("upper"));
+        client.send_message("upper", b"later".to_vec()).await.unwrap();
+        assert_eq!(relay.get_pending_count(), 1);
+        assert!(MemoryRelay::local("other").get_connected_agents().is_empty());
+    }
+
+    /// Memory transport whose congestion the test switches
+    struct Congestible(MemoryTransport, Arc<AtomicBool>);
+
+    impl Transport for Congestible {
+        fn send(&self, frame: &OpacusFrame) -> anyhow::Result<()> {
+            self.0.send(frame)
+        }
+
+        fn recv(&mut self) -> BoxFuture<'_, Option<OpacusFrame>> {
+            self.0.recv()
+        }
+
+        fn is_congested(&self) -> bool {
+            self.1.load(Ordering::SeqCst)
+        }
+
+        fn is_connected(&self) -> bool {
+            self.0.is_connected()
+        }
+
+        fn close(&mut self) {
+            self.0.close()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_while_congested() {
+        use crate::qos::{CongestionError, Priority, SendStatus};
+
+        let relay = MemoryRelay::new();
+        let (mut bob, bob_id) = agent(&relay).await;
+        let congested = Arc::new(AtomicBool::new(false));
+        let mut alice = OpacusClient::new(OpacusConfig {
+            network: Network::Devnet,
+            relay_url: "memory".to_string(),
+            chain_rpc: String::new(),
+            private_key: None,
+        });
+        alice.init().await;
+        alice.connect_with(Congestible(relay.transport(), congested.clone())).await.unwrap();
+        assert_eq!(alice.recv().await.unwrap().frame_type, FrameType::Ack);
+
+        // Messages held back or dropped while congested are not reported as sent
+        congested.store(true, Ordering::SeqCst);
+        assert_eq!(alice.send_message_tracked(&bob_id, b"later".to_vec()).await.unwrap(), SendStatus::Queued);
+        let error = alice.send_message_with_priority(&bob_id, b"lossy".to_vec(), Priority::Low).await.unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(CongestionError::Dropped(Some(_)))));
+        assert_eq!(alice.queued(), 1);
+
+        congested.store(false, Ordering::SeqCst);
+        assert_eq!(alice.flush().await.unwrap(), 1);
+        assert_eq!(&bob.recv().await.unwrap().payload[..], b"later");
+        alice.send_message(&bob_id, b"now".to_vec()).await.unwrap();
+        assert_eq!(&bob.recv().await.unwrap().payload[..], b"now");
+    }
+}
@@ -1,5 +1,36 @@
 //! Transport layer implementations
 
+use futures::future::BoxFuture;
+use crate::types::OpacusFrame;
+
+#[cfg(feature = "client")]
 pub mod quic;
+#[cfg(feature = "client")]
+pub mod memory;
+#[cfg(any(feature = "client", feature = "relay"))]
+pub(crate) mod tls;
 
+#[cfg(feature = "client")]
 pub use quic::*;
+#[cfg(feature = "client")]
+pub use memory::*;
+
+/// Connection from a client to a relay
+/// 
+/// Implemented by [`QUICTransport`] and, for tests, [`MemoryTransport`].
+pub trait Transport: Send + Sync {
+    /// Send a frame without waiting for delivery
+    fn send(&self, frame: &OpacusFrame) -> anyhow::Result<()>;
+    
+    /// Receive the next frame (`None` once the connection is closed)
+    fn recv(&mut self) -> BoxFuture<'_, Option<OpacusFrame>>;
+    
+    /// Check whether sends should be held back
+    fn is_congested(&self) -> bool;
+    
+    /// Check connection status
+    fn is_connected(&self) -> bool;
+    
+    /// Close connection
+    fn close(&mut self);
+}
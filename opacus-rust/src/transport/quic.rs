@@ -1,13 +1,13 @@
 //! QUIC transport using Quinn
 
-use quinn::{ClientConfig, Endpoint, Connection, SendDatagramError};
-use rustls::pki_types::CertificateDer;
-use std::sync::Arc;
+use quinn::{Endpoint, Connection, SendDatagramError};
 use std::net::SocketAddr;
 use tokio::sync::mpsc;
-use tracing::{debug, warn};
+use futures::future::BoxFuture;
+use tracing::{debug, debug_span, warn, Instrument};
 use crate::types::OpacusFrame;
-use crate::proto::CBORCodec;
+use crate::qos::CONGESTION_THRESHOLD;
+use crate::proto::{FrameCodec, FramedRead, FramedWrite, LengthPrefixedCodec, RoutingHeader, WireFormat};
 
 /// QUIC transport for Opacus protocol
 pub struct QUICTransport {
@@ -15,6 +15,7 @@ pub struct QUICTransport {
     connection: Option<Connection>,
     server_addr: SocketAddr,
     rx: Option<mpsc::Receiver<OpacusFrame>>,
+    format: WireFormat,
 }
 
 impl QUICTransport {
@@ -27,18 +28,9 @@ impl QUICTransport {
         let bind: SocketAddr = bind_addr.parse()?;
         let server: SocketAddr = server_addr.parse()?;
         
-        // Create client config (skip verification for dev)
-        let crypto = rustls::ClientConfig::builder()
-            .dangerous()
-            .with_custom_certificate_verifier(Arc::new(SkipVerification))
-            .with_no_client_auth();
-        
-        let client_config = ClientConfig::new(Arc::new(
-            quinn::crypto::rustls::QuicClientConfig::try_from(crypto)?
-        ));
-        
+        let format = WireFormat::default();
         let mut endpoint = Endpoint::client(bind)?;
-        endpoint.set_default_client_config(client_config);
+        endpoint.set_default_client_config(super::tls::client_config(format)?);
         
         debug!("QUIC endpoint created on {}", bind);
         
@@ -47,9 +39,31 @@ impl QUICTransport {
             connection: None,
             server_addr: server,
             rx: None,
+            format,
         })
     }
     
+    /// Select the frame encoding (offered to the relay via ALPN)
+    /// 
+    /// Must be called before `connect`.
+    pub fn set_wire_format(&mut self, format: WireFormat) -> anyhow::Result<()> {
+        if format.codec().is_none() {
+            anyhow::bail!("{:?} support not compiled in", format);
+        }
+        self.endpoint.set_default_client_config(super::tls::client_config(format)?);
+        self.format = format;
+        Ok(())
+    }
+    
+    /// Get the frame encoding
+    pub fn wire_format(&self) -> WireFormat {
+        self.format
+    }
+    
+    fn codec(&self) -> &'static dyn FrameCodec {
+        self.format.codec().expect("Wire format checked in set_wire_format")
+    }
+    
     /// Connect to relay server
     pub async fn connect(&mut self) -> anyhow::Result<()> {
         debug!("Connecting to {}", self.server_addr);
@@ -63,13 +77,15 @@ impl QUICTransport {
         // Start receive loop
         let (tx, rx) = mpsc::channel(256);
         let conn_clone = conn.clone();
+        let codec = self.codec();
         tokio::spawn(async move {
             loop {
                 match conn_clone.read_datagram().await {
                     Ok(data) => {
-                        match CBORCodec::decode(&data) {
+                        match RoutingHeader::decode(codec, &data) {
                             Ok(frame) => {
-                                if tx.send(frame).await.is_err() {
+                                let span = debug_span!("quic.recv", frame_id = ?frame.id, from = %frame.from, bytes = data.len());
+                                if tx.send(frame).instrument(span).await.is_err() {
                                     break;
                                 }
                             }
@@ -92,15 +108,45 @@ impl QUICTransport {
     /// Send frame
     pub async fn send(&self, frame: &OpacusFrame) -> Result<(), SendDatagramError> {
         let conn = self.connection.as_ref().expect("Not connected");
-        let data = CBORCodec::encode(frame).expect("Encode failed");
+        let data = RoutingHeader::encode(self.codec(), frame).expect("Encode failed");
+        let _span = debug_span!("quic.send", frame_id = ?frame.id, to = %frame.to, bytes = data.len()).entered();
         conn.send_datagram(data.into())
     }
     
+    /// Check whether a frame fits in a single datagram on this connection
+    /// 
+    /// Frames that do not fit should be sent on a frame stream instead.
+    pub fn fits_datagram(&self, frame: &OpacusFrame) -> bool {
+        let Some(max) = self.connection.as_ref().and_then(|conn| conn.max_datagram_size()) else {
+            return false;
+        };
+        RoutingHeader::encoded_size(self.codec(), frame).is_ok_and(|size| size <= max)
+    }
+    
+    /// Open a bidirectional stream carrying length-prefixed frames
+    /// 
+    /// Streams are reliable and ordered, for frames too large for a datagram
+    pub async fn open_frame_stream(&self) -> anyhow::Result<(FramedWrite<quinn::SendStream>, FramedRead<quinn::RecvStream>)> {
+        let conn = self.connection.as_ref().ok_or_else(|| anyhow::anyhow!("Not connected"))?;
+        let (send, recv) = conn.open_bi().await?;
+        Ok((
+            FramedWrite::with_codec(send, LengthPrefixedCodec::new(self.codec())),
+            FramedRead::with_codec(recv, LengthPrefixedCodec::new(self.codec())),
+        ))
+    }
+    
     /// Receive frame (blocking)
     pub async fn recv(&mut self) -> Option<OpacusFrame> {
         self.rx.as_mut()?.recv().await
     }
     
+    /// Check whether the datagram send buffer is nearly full
+    pub fn is_congested(&self) -> bool {
+        self.connection
+            .as_ref()
+            .is_some_and(|conn| conn.datagram_send_buffer_space() < CONGESTION_THRESHOLD)
+    }
+    
     /// Check connection status
     pub fn is_connected(&self) -> bool {
         self.connection.is_some()
@@ -108,46 +154,34 @@ impl QUICTransport {
     
     /// Close connection
     pub async fn close(&mut self) {
-        if let Some(conn) = self.connection.take() {
-            conn.close(0u32.into(), b"bye");
-            debug!("Connection closed");
-        }
+        super::Transport::close(self);
     }
 }
 
-// Skip TLS verification for development
-#[derive(Debug)]
-struct SkipVerification;
-
-impl rustls::client::danger::ServerCertVerifier for SkipVerification {
-    fn verify_server_cert(
-        &self,
-        _end_entity: &CertificateDer<'_>,
-        _intermediates: &[CertificateDer<'_>],
-        _server_name: &rustls::pki_types::ServerName<'_>,
-        _ocsp_response: &[u8],
-        _now: rustls::pki_types::UnixTime,
-    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
-        Ok(rustls::client::danger::ServerCertVerified::assertion())
+impl super::Transport for QUICTransport {
+    fn send(&self, frame: &OpacusFrame) -> anyhow::Result<()> {
+        let conn = self.connection.as_ref().ok_or_else(|| anyhow::anyhow!("Not connected"))?;
+        let data = RoutingHeader::encode(self.codec(), frame)?;
+        let _span = debug_span!("quic.send", frame_id = ?frame.id, to = %frame.to, bytes = data.len()).entered();
+        Ok(conn.send_datagram(data.into())?)
+    }
+    
+    fn recv(&mut self) -> BoxFuture<'_, Option<OpacusFrame>> {
+        Box::pin(QUICTransport::recv(self))
     }
     
-    fn verify_tls12_signature(
-        &self, _: &[u8], _: &CertificateDer<'_>, _: &rustls::DigitallySignedStruct,
-    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
-        Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+    fn is_congested(&self) -> bool {
+        QUICTransport::is_congested(self)
     }
     
-    fn verify_tls13_signature(
-        &self, _: &[u8], _: &CertificateDer<'_>, _: &rustls::DigitallySignedStruct,
-    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
-        Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+    fn is_connected(&self) -> bool {
+        QUICTransport::is_connected(self)
     }
     
-    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
-        vec![
-            rustls::SignatureScheme::RSA_PKCS1_SHA256,
-            rustls::SignatureScheme::ECDSA_NISTP256_SHA256,
-            rustls::SignatureScheme::ED25519,
-        ]
+    fn close(&mut self) {
+        if let Some(conn) = self.connection.take() {
+            conn.close(0u32.into(), b"bye");
+            debug!("Connection closed");
+        }
     }
 }
@@ -1,9 +1,22 @@
 //! Core types for Opacus protocol
 
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::LazyLock;
+use bytes::Bytes;
 use serde::{Deserialize, Serialize};
+use crate::compression::Compression;
+use crate::content::ContentType;
+use crate::qos::Priority;
+use crate::random::{OsRandom, Random};
+use crate::redact::{PayloadBytes, Secret};
+
+pub use ulid::Ulid;
 
 /// Main configuration for Opacus client
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// 
+/// `Debug` output redacts the private key.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct OpacusConfig {
     /// Network selection (mainnet, testnet, devnet)
     pub network: Network,
@@ -15,41 +28,188 @@ pub struct OpacusConfig {
     pub private_key: Option<String>,
 }
 
+impl fmt::Debug for OpacusConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpacusConfig")
+            .field("network", &self.network)
+            .field("relay_url", &self.relay_url)
+            .field("chain_rpc", &self.chain_rpc)
+            .field("private_key", &self.private_key.as_ref().map(Secret))
+            .finish()
+    }
+}
+
 /// Network variants
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum Network {
     Mainnet,
     Testnet,
     Devnet,
+    /// Any other EVM chain
+    Custom(Box<NetworkProfile>),
 }
 
 impl Network {
-    /// Get chain ID for network
-    pub fn chain_id(&self) -> u64 {
+    /// Built-in network with a chain ID
+    pub fn from_chain_id(chain_id: u64) -> Option<Network> {
+        [Network::Mainnet, Network::Testnet, Network::Devnet]
+            .into_iter()
+            .find(|n| n.chain_id() == chain_id)
+    }
+    
+    /// Chain ID, RPC, explorer, native token and contracts of the network
+    pub fn profile(&self) -> &NetworkProfile {
         match self {
-            Network::Mainnet => 16661,
-            Network::Testnet => 16602,
-            Network::Devnet => 16600,
+            Network::Mainnet => &MAINNET,
+            Network::Testnet => &TESTNET,
+            Network::Devnet => &DEVNET,
+            Network::Custom(profile) => profile,
         }
     }
     
+    /// Get chain ID for network
+    pub fn chain_id(&self) -> u64 {
+        self.profile().chain_id
+    }
+    
     /// Get default RPC URL for network
-    pub fn rpc(&self) -> &'static str {
-        match self {
-            Network::Mainnet => "https://evmrpc.0g.ai",
-            Network::Testnet => "https://evmrpc-testnet.0g.ai",
-            Network::Devnet => "http://localhost:8545",
+    pub fn rpc(&self) -> &str {
+        &self.profile().rpc
+    }
+    
+    /// Get default 0G storage indexer URL for network (`None` without 0G storage)
+    pub fn storage_indexer(&self) -> Option<&str> {
+        self.profile().storage_indexer.as_deref()
+    }
+}
+
+/// Everything the SDK needs to know about an EVM network
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkProfile {
+    /// Display name
+    pub name: String,
+    /// EIP-155 chain ID
+    pub chain_id: u64,
+    /// Default JSON-RPC endpoint
+    pub rpc: String,
+    /// Block explorer base URL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explorer: Option<String>,
+    /// Native gas token
+    pub native_token: NativeToken,
+    /// 0G storage indexer URL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage_indexer: Option<String>,
+    /// Deployed Opacus contracts
+    #[serde(default)]
+    pub contracts: NetworkContracts,
+}
+
+impl NetworkProfile {
+    /// Profile of a chain without storage or deployed contracts
+    pub fn new(name: &str, chain_id: u64, rpc: &str, native_token: NativeToken) -> Self {
+        Self {
+            name: name.to_string(),
+            chain_id,
+            rpc: rpc.to_string(),
+            explorer: None,
+            native_token,
+            storage_indexer: None,
+            contracts: NetworkContracts::default(),
         }
     }
+    
+    /// Set the block explorer
+    pub fn with_explorer(mut self, url: &str) -> Self {
+        self.explorer = Some(url.trim_end_matches('/').to_string());
+        self
+    }
+    
+    /// Set the deployed contracts
+    pub fn with_contracts(mut self, contracts: NetworkContracts) -> Self {
+        self.contracts = contracts;
+        self
+    }
+    
+    /// Explorer page of a transaction (hex hash)
+    pub fn tx_url(&self, tx_hash: &str) -> Option<String> {
+        Some(format!("{}/tx/{}", self.explorer.as_ref()?, tx_hash))
+    }
+    
+    /// Explorer page of an account or contract
+    pub fn address_url(&self, address: &str) -> Option<String> {
+        Some(format!("{}/address/{}", self.explorer.as_ref()?, address))
+    }
+}
+
+/// Native gas token of a network
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NativeToken {
+    /// Ticker symbol
+    pub symbol: String,
+    /// Decimal places of one token in wei
+    pub decimals: u8,
+}
+
+impl NativeToken {
+    /// Token with 18 decimals, like ether
+    pub fn new(symbol: &str) -> Self {
+        Self { symbol: symbol.to_string(), decimals: 18 }
+    }
+}
+
+/// Addresses of the Opacus contracts on a network (`0x`-prefixed)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkContracts {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dac_registry: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent_registry: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_stream: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub msg_escrow: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opacus_core: Option<String>,
 }
 
+static MAINNET: LazyLock<NetworkProfile> = LazyLock::new(|| NetworkProfile {
+    storage_indexer: Some("https://indexer-storage-turbo.0g.ai".into()),
+    contracts: NetworkContracts {
+        dac_registry: Some("0x12fEbDd82739D88A731b2a0f308644eA0F99d9cE".into()),
+        agent_registry: Some("0xD7f91B117918f3968C715A9440123b9B6eD83500".into()),
+        data_stream: Some("0xA641BB1d05Edd555a52fCa1B4d9E8852A118c6aE".into()),
+        msg_escrow: Some("0xE2b6bfA4b9E6BEe8DFd9c8E9b2Ce6906c27e750E".into()),
+        opacus_core: Some("0x51Daa351F855837F07270DA4F92F282F58efD84B".into()),
+    },
+    ..NetworkProfile::new("0G Mainnet", 16661, "https://evmrpc.0g.ai", NativeToken::new("0G"))
+        .with_explorer("https://chainscan.0g.ai")
+});
+
+static TESTNET: LazyLock<NetworkProfile> = LazyLock::new(|| NetworkProfile {
+    storage_indexer: Some("https://indexer-storage-testnet-turbo.0g.ai".into()),
+    ..NetworkProfile::new("0G Testnet", 16602, "https://evmrpc-testnet.0g.ai", NativeToken::new("0G"))
+        .with_explorer("https://chainscan-galileo.0g.ai")
+});
+
+static DEVNET: LazyLock<NetworkProfile> = LazyLock::new(|| NetworkProfile {
+    storage_indexer: Some("http://localhost:12345".into()),
+    ..NetworkProfile::new("0G Devnet", 16600, "http://localhost:8545", NativeToken::new("0G"))
+});
+
 /// Opacus protocol frame
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// 
+/// Serialized in the layout of its `version` (see [`FRAME_VERSION`]).
+/// `Debug` output shows only the payload length.
+/// 
+/// [`FRAME_VERSION`]: crate::proto::FRAME_VERSION
+#[derive(Clone)]
 pub struct OpacusFrame {
-    /// Protocol version
+    /// Frame layout and protocol version
     pub version: u8,
     /// Frame type
-    #[serde(rename = "type")]
     pub frame_type: FrameType,
     /// Sender agent ID
     pub from: String,
@@ -62,16 +222,135 @@ pub struct OpacusFrame {
     /// Anti-replay nonce
     pub nonce: String,
     /// Frame payload (application data)
-    pub payload: Vec<u8>,
+    /// 
+    /// Reference-counted, so cloning a frame for queues or fan-out does not
+    /// copy the payload.
+    pub payload: Bytes,
     /// HMAC for payload authentication
     pub hmac: Option<String>,
     /// Ed25519 signature
     pub sig: Option<Vec<u8>>,
+    /// Session key epoch, incremented on each rekey (omitted when 0)
+    pub key_epoch: u32,
+    /// Payload compression algorithm (omitted when uncompressed)
+    pub compressed: Option<Compression>,
+    /// Globally unique message ID (ULID, sorts by creation time)
+    /// 
+    /// Set by the sender and preserved by the relay; used for deduplication,
+    /// ACK correlation, receipts and tracing. Required from version 2.
+    pub id: Option<Ulid>,
+    /// Scheduling priority (omitted when `Normal`)
+    pub priority: Priority,
+    /// Payload encoding (omitted when `Raw`)
+    pub content_type: ContentType,
+    /// Fields this version does not know, kept so frames survive re-encoding
+    /// (not covered by the HMAC or signature, except a capability presentation)
+    pub extensions: BTreeMap<String, ciborium::Value>,
+}
+
+impl OpacusFrame {
+    /// Generate a message ID for a frame created at `ts` (milliseconds)
+    pub fn new_id(ts: u64) -> Ulid {
+        Self::new_id_with(ts, &OsRandom)
+    }
+    
+    /// Generate a message ID from a custom source of randomness
+    pub fn new_id_with(ts: u64, random: &dyn Random) -> Ulid {
+        let mut bytes = [0u8; 16];
+        random.fill_bytes(&mut bytes);
+        Ulid::from_parts(ts, u128::from_le_bytes(bytes))
+    }
+
+    /// Unsigned `Msg` frame from `alice` to `bob` for tests
+    ///
+    /// Other fields are set with struct update syntax:
+    /// `OpacusFrame { payload, ..OpacusFrame::test(seq) }`.
+    #[cfg(test)]
+    pub(crate) fn test(seq: u64) -> Self {
+        Self {
+            version: 1,
+            frame_type: FrameType::Msg,
+            from: "alice".to_string(),
+            to: "bob".to_string(),
+            seq,
+            ts: 1234567890,
+            nonce: format!("nonce-{}", seq),
+            payload: Bytes::new(),
+            hmac: None,
+            sig: None,
+            key_epoch: 0,
+            compressed: None,
+            id: None,
+            priority: Priority::Normal,
+            content_type: ContentType::Raw,
+            extensions: Default::default(),
+        }
+    }
+}
+
+impl fmt::Debug for OpacusFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpacusFrame")
+            .field("version", &self.version)
+            .field("frame_type", &self.frame_type)
+            .field("from", &self.from)
+            .field("to", &self.to)
+            .field("seq", &self.seq)
+            .field("ts", &self.ts)
+            .field("nonce", &self.nonce)
+            .field("payload", &PayloadBytes(&self.payload))
+            .field("hmac", &self.hmac)
+            .field("sig", &self.sig)
+            .field("key_epoch", &self.key_epoch)
+            .field("compressed", &self.compressed)
+            .field("id", &self.id)
+            .field("priority", &self.priority)
+            .field("content_type", &self.content_type)
+            .field("extensions", &Extensions(&self.extensions))
+            .finish()
+    }
+}
+
+/// Frame extensions, with capability presentations redacted in `Debug` output
+struct Extensions<'a>(&'a BTreeMap<String, ciborium::Value>);
+
+impl fmt::Debug for Extensions<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut map = f.debug_map();
+        for (name, value) in self.0 {
+            if name == crate::token::CAPABILITY_EXTENSION {
+                map.entry(name, &Secret(value));
+            } else {
+                map.entry(name, value);
+            }
+        }
+        map.finish()
+    }
+}
+
+/// Field of the `Connect` payload carrying a relay access token (a JWT)
+pub const CONNECT_TOKEN_FIELD: &str = "jwt";
+
+/// Optional settings for [`SecurityManager::create_auth_frame_with`]
+/// 
+/// [`SecurityManager::create_auth_frame_with`]: crate::crypto::SecurityManager::create_auth_frame_with
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameOptions {
+    /// Algorithm the payload is already compressed with
+    pub compressed: Option<Compression>,
+    /// Priority (defaults to the frame type's)
+    pub priority: Option<Priority>,
+    /// Payload encoding
+    pub content_type: ContentType,
+    /// Skip the Ed25519 signature (entries of a signed `Batch`)
+    pub unsigned: bool,
 }
 
 /// Frame type variants
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
-#[serde(rename_all = "lowercase")]
+/// 
+/// Serialized by lowercase name. Types this version does not know decode as
+/// `Unknown` instead of failing, so newer peers can introduce frame types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FrameType {
     /// Initial connection handshake
     Connect,
@@ -85,10 +364,156 @@ pub enum FrameType {
     Stream,
     /// Payment transaction
     Payment,
+    /// Publish prekey bundle to the relay
+    PreKeyPublish,
+    /// Request (or deliver) another agent's prekey bundle
+    PreKeyFetch,
+    /// Announce the sender's next session key epoch
+    Rekey,
+    /// Rejection report (`ErrorPayload`)
+    Error,
+    /// Container for several frames (`FrameBatch`)
+    Batch,
+    /// Request to subscribe to a data channel (`SubscribeRequest`)
+    Subscribe,
+    /// Advertise (or look up) supported features (`Capabilities`)
+    Capabilities,
+    /// Publish (or look up) a signed agent profile (`SignedProfile`)
+    Profile,
+    /// Fetch (or deliver) the retained history of a data channel
+    /// (`HistoryQuery`, `HistoryPage`)
+    History,
+    /// Delegate a task, or report on a delegated one (`TaskMessage`)
+    Task,
+    /// Layer of an onion-routed frame for a relay (see `crate::onion`)
+    Onion,
+    /// Cover traffic for the relay to discard (see `crate::padding`)
+    Cover,
+    /// Presence digests exchanged between relays (see `crate::gossip`)
+    Gossip,
+    /// Agent record lookups and storage in the DHT (`DhtRpc`, see `crate::dht`)
+    Dht,
+    /// Frame type added by a newer protocol version (wire code, or
+    /// `FrameType::UNKNOWN_CODE` if it arrived by an unknown name)
+    Unknown(u8),
+}
+
+impl FrameType {
+    /// All frame types, indexed by wire code (new variants are appended)
+    pub const ALL: [FrameType; 20] = [
+        FrameType::Connect,
+        FrameType::Msg,
+        FrameType::Ping,
+        FrameType::Ack,
+        FrameType::Stream,
+        FrameType::Payment,
+        FrameType::PreKeyPublish,
+        FrameType::PreKeyFetch,
+        FrameType::Rekey,
+        FrameType::Error,
+        FrameType::Batch,
+        FrameType::Subscribe,
+        FrameType::Capabilities,
+        FrameType::Profile,
+        FrameType::History,
+        FrameType::Task,
+        FrameType::Onion,
+        FrameType::Cover,
+        FrameType::Gossip,
+        FrameType::Dht,
+    ];
+    
+    /// Code for unknown frame types received by name
+    pub const UNKNOWN_CODE: u8 = u8::MAX;
+    
+    /// Numeric wire code used by binary headers and Protobuf
+    pub fn code(self) -> u8 {
+        match self {
+            FrameType::Unknown(code) => code,
+            known => Self::ALL.iter().position(|t| *t == known).expect("known frame type") as u8,
+        }
+    }
+    
+    /// Frame type for a wire code
+    pub fn from_code(code: u8) -> FrameType {
+        Self::ALL.get(code as usize).copied().unwrap_or(FrameType::Unknown(code))
+    }
+    
+    /// Wire name (`None` for unknown types)
+    pub fn name(self) -> Option<&'static str> {
+        Some(match self {
+            FrameType::Connect => "connect",
+            FrameType::Msg => "msg",
+            FrameType::Ping => "ping",
+            FrameType::Ack => "ack",
+            FrameType::Stream => "stream",
+            FrameType::Payment => "payment",
+            FrameType::PreKeyPublish => "prekeypublish",
+            FrameType::PreKeyFetch => "prekeyfetch",
+            FrameType::Rekey => "rekey",
+            FrameType::Error => "error",
+            FrameType::Batch => "batch",
+            FrameType::Subscribe => "subscribe",
+            FrameType::Capabilities => "capabilities",
+            FrameType::Profile => "profile",
+            FrameType::History => "history",
+            FrameType::Task => "task",
+            FrameType::Onion => "onion",
+            FrameType::Cover => "cover",
+            FrameType::Gossip => "gossip",
+            FrameType::Dht => "dht",
+            FrameType::Unknown(_) => return None,
+        })
+    }
+    
+    /// Frame type for a wire name
+    pub fn from_name(name: &str) -> FrameType {
+        Self::ALL
+            .into_iter()
+            .find(|t| t.name() == Some(name))
+            .unwrap_or(FrameType::Unknown(Self::UNKNOWN_CODE))
+    }
+}
+
+impl Serialize for FrameType {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.name() {
+            Some(name) => serializer.serialize_str(name),
+            None => serializer.serialize_u8(self.code()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for FrameType {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FrameTypeVisitor;
+        
+        impl serde::de::Visitor<'_> for FrameTypeVisitor {
+            type Value = FrameType;
+            
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a frame type name or code")
+            }
+            
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<FrameType, E> {
+                Ok(FrameType::from_name(v))
+            }
+            
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<FrameType, E> {
+                u8::try_from(v)
+                    .map(FrameType::from_code)
+                    .map_err(|_| E::custom(format!("frame type code out of range: {}", v)))
+            }
+        }
+        
+        deserializer.deserialize_any(FrameTypeVisitor)
+    }
 }
 
 /// Agent identity with dual keys
-#[derive(Debug, Clone)]
+/// 
+/// `Debug` output redacts the private keys.
+#[derive(Clone)]
 pub struct AgentIdentity {
     /// Unique agent identifier
     pub id: String,
@@ -106,6 +531,20 @@ pub struct AgentIdentity {
     pub chain_id: u64,
 }
 
+impl fmt::Debug for AgentIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AgentIdentity")
+            .field("id", &self.id)
+            .field("ed_pub", &self.ed_pub)
+            .field("ed_priv", &Secret(&self.ed_priv))
+            .field("x_pub", &self.x_pub)
+            .field("x_priv", &Secret(&self.x_priv))
+            .field("address", &self.address)
+            .field("chain_id", &self.chain_id)
+            .finish()
+    }
+}
+
 /// DAC (Decentralized Agent Communication) configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DACConfig {
@@ -143,6 +582,89 @@ pub struct DataChannel {
     pub price_per_byte: u64,
     /// Price per message
     pub price_per_msg: u64,
+    /// What subscribers must hold on chain (`None` for open channels)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access: Option<AccessRule>,
+    /// Recurring subscription price (`None` if subscribing is free)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plan: Option<SubscriptionPlan>,
+}
+
+impl DataChannel {
+    /// Price of one message with a payload of `len` bytes
+    pub fn price(&self, len: usize) -> u128 {
+        self.price_per_msg as u128 + self.price_per_byte as u128 * len as u128
+    }
+}
+
+/// On-chain holding required to subscribe to a data channel
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum AccessRule {
+    /// Minimum balance of an ERC-20 token
+    Erc20 {
+        /// Token contract
+        token: String,
+        /// Minimum balance in the token's smallest unit (a decimal string in JSON)
+        #[serde(rename = "minBalance", with = "decimal")]
+        min_balance: u128,
+    },
+    /// At least one token of an ERC-721 collection
+    Erc721 {
+        /// Collection contract
+        token: String,
+    },
+    /// Entry in an allowlist contract (`isAllowed(address) returns (bool)`)
+    Allowlist {
+        /// Allowlist contract
+        contract: String,
+    },
+}
+
+/// Price subscribers pay a data channel's publisher every period
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriptionPlan {
+    /// Publisher's account receiving the payments
+    pub payee: String,
+    /// ERC-20 token, or the zero address for the native token
+    pub token: String,
+    /// Price per period in the token's smallest unit (a decimal string in JSON)
+    #[serde(with = "decimal")]
+    pub price: u128,
+    /// Period length (seconds)
+    pub period: u64,
+}
+
+/// Serde for `u128` amounts as decimal strings, which JSON numbers cannot carry exactly
+pub(crate) mod decimal {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &u128, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u128, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+
+    /// Optional amounts
+    pub mod option {
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(value: &Option<u128>, serializer: S) -> Result<S::Ok, S::Error> {
+            match value {
+                Some(value) => serializer.collect_str(value),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u128>, D::Error> {
+            Option::<String>::deserialize(deserializer)?
+                .map(|value| value.parse().map_err(serde::de::Error::custom))
+                .transpose()
+        }
+    }
 }
 
 /// Channel type variants
@@ -156,3 +678,35 @@ pub enum ChannelType {
     /// Bidirectional channel
     Bidirectional,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_network_profiles() {
+        assert_eq!(Network::Mainnet.chain_id(), 16661);
+        assert_eq!(Network::Testnet.rpc(), "https://evmrpc-testnet.0g.ai");
+        assert_eq!(Network::from_chain_id(16600), Some(Network::Devnet));
+        assert_eq!(Network::from_chain_id(1), None);
+        assert!(Network::Mainnet.profile().contracts.msg_escrow.is_some());
+        assert_eq!(
+            Network::Mainnet.profile().tx_url("0xab").as_deref(),
+            Some("https://chainscan.0g.ai/tx/0xab")
+        );
+
+        let profile = NetworkProfile::new("Base", 8453, "https://mainnet.base.org", NativeToken::new("ETH"))
+            .with_explorer("https://basescan.org/")
+            .with_contracts(NetworkContracts { msg_escrow: Some("0x01".into()), ..Default::default() });
+        let network = Network::Custom(Box::new(profile));
+        assert_eq!((network.chain_id(), network.rpc(), network.storage_indexer()), (8453, "https://mainnet.base.org", None));
+        assert_eq!(network.profile().address_url("0x02").as_deref(), Some("https://basescan.org/address/0x02"));
+
+        // Built-in networks keep their JSON form
+        assert_eq!(serde_json::to_string(&Network::Testnet).unwrap(), r#""Testnet""#);
+        let json = serde_json::to_string(&network).unwrap();
+        assert_eq!(serde_json::from_str::<Network>(&json).unwrap(), network);
+        let minimal = r#"{"Custom":{"name":"Local","chainId":31337,"rpc":"http://localhost:8545","nativeToken":{"symbol":"ETH","decimals":18}}}"#;
+        assert_eq!(serde_json::from_str::<Network>(minimal).unwrap().profile().contracts, NetworkContracts::default());
+    }
+}
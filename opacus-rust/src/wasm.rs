@@ -0,0 +1,324 @@
+//! Browser client over WebTransport
+//!
+//! With the `wasm` feature and without `native`, the crate builds for
+//! `wasm32-unknown-unknown`, so web dashboards can join the agent network
+//! directly. Frames, codecs and [`SecurityManager`] are the same as on native
+//! targets; randomness comes from the Web Crypto API
+//! (`crypto.getRandomValues`) and time from `Date.now()`.
+//!
+//! [`WebTransportClient`] carries frames as WebTransport datagrams, encoded
+//! like QUIC datagrams with a [`RoutingHeader`], so the relay must be
+//! reachable over HTTP/3 WebTransport (for instance through a gateway in
+//! front of it). [`WasmAgent`] wraps it with an identity for JavaScript:
+//!
+//! ```js
+//! import init, { WasmAgent } from "./opacus_sdk.js";
+//!
+//! await init();
+//! const agent = new WasmAgent(16602n);
+//! await agent.connect("https://relay.opacus.io:4433/opacus");
+//! agent.sendText(peerId, "hello");
+//! const message = await agent.recv();
+//! ```
+//!
+//! The WebTransport bindings of `web-sys` are unstable and need
+//! `--cfg=web_sys_unstable_apis`, which `.cargo/config.toml` sets for
+//! `wasm32-unknown-unknown`.
+
+use js_sys::Uint8Array;
+use tracing::{debug, warn};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    ReadableStreamDefaultReader, ReadableStreamReadResult, WebTransport, WebTransportHash,
+    WebTransportOptions, WritableStreamDefaultWriter,
+};
+use crate::clock::{Clock, SystemClock};
+use crate::compression::Compression;
+use crate::content::ContentType;
+use crate::crypto::{KeyManager, SecurityManager};
+use crate::proto::{FrameCodec, RoutingHeader, WireFormat, FRAME_VERSION};
+use crate::qos::Priority;
+use crate::types::{AgentIdentity, FrameOptions, FrameType, OpacusFrame};
+
+fn js_error(e: JsValue) -> anyhow::Error {
+    anyhow::anyhow!("{}", e.as_string().unwrap_or_else(|| format!("{:?}", e)))
+}
+
+/// WebTransport session to a relay
+///
+/// JavaScript objects cannot cross threads, so this does not implement
+/// [`Transport`](crate::Transport); its methods mirror it instead.
+pub struct WebTransportClient {
+    session: WebTransport,
+    writer: WritableStreamDefaultWriter,
+    reader: ReadableStreamDefaultReader,
+    codec: &'static dyn FrameCodec,
+    connected: bool,
+}
+
+impl WebTransportClient {
+    /// Open a session to `url` (`https://host:port/path`)
+    ///
+    /// # Arguments
+    /// * `url` - WebTransport endpoint of the relay
+    /// * `cert_hash` - SHA-256 of the relay's certificate, for self-signed
+    ///   certificates the browser would otherwise reject
+    pub async fn connect(url: &str, cert_hash: Option<&[u8]>) -> anyhow::Result<Self> {
+        let options = WebTransportOptions::new();
+        if let Some(hash) = cert_hash {
+            let pinned = WebTransportHash::new();
+            pinned.set_algorithm("sha-256");
+            pinned.set_value_u8_array(&Uint8Array::from(hash));
+            options.set_server_certificate_hashes(&[pinned]);
+        }
+        let session = WebTransport::new_with_options(url, &options).map_err(js_error)?;
+        JsFuture::from(session.ready()).await.map_err(js_error)?;
+        debug!("WebTransport session established to {}", url);
+
+        let datagrams = session.datagrams();
+        let writer = datagrams.writable().get_writer().map_err(js_error)?;
+        let reader = ReadableStreamDefaultReader::new(&datagrams.readable()).map_err(js_error)?;
+        Ok(Self {
+            session,
+            writer,
+            reader,
+            codec: WireFormat::default().codec().expect("Default wire format is always compiled in"),
+            connected: true,
+        })
+    }
+
+    /// Send a frame without waiting for delivery
+    pub fn send(&self, frame: &OpacusFrame) -> anyhow::Result<()> {
+        if !self.connected {
+            anyhow::bail!("Not connected");
+        }
+        let data = RoutingHeader::encode(self.codec, frame)?;
+        // Datagrams are unreliable anyway; the returned promise only reports
+        // that the browser queued it
+        let _ = self.writer.write_with_chunk(&Uint8Array::from(&data[..]));
+        Ok(())
+    }
+
+    /// Receive the next frame (`None` once the session is closed)
+    pub async fn recv(&mut self) -> Option<OpacusFrame> {
+        if !self.connected {
+            return None;
+        }
+        loop {
+            let result: ReadableStreamReadResult = match JsFuture::from(self.reader.read()).await {
+                Ok(result) => result.unchecked_into(),
+                Err(e) => {
+                    debug!("Session closed: {:?}", e);
+                    break;
+                }
+            };
+            if result.get_done().unwrap_or(false) {
+                break;
+            }
+            let data = Uint8Array::new(&result.get_value()).to_vec();
+            match RoutingHeader::decode(self.codec, &data) {
+                Ok(frame) => return Some(frame),
+                Err(e) => warn!("Decode error: {}", e),
+            }
+        }
+        self.connected = false;
+        None
+    }
+
+    /// Check whether the datagram queue is full
+    pub fn is_congested(&self) -> bool {
+        matches!(self.writer.desired_size(), Ok(Some(size)) if size <= 0.0)
+    }
+
+    /// Check connection status
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Close the session
+    pub fn close(&mut self) {
+        if std::mem::take(&mut self.connected) {
+            self.session.close();
+            debug!("Session closed");
+        }
+    }
+}
+
+/// Message received by a [`WasmAgent`]
+#[wasm_bindgen]
+pub struct WasmMessage {
+    frame: OpacusFrame,
+    payload: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl WasmMessage {
+    /// Frame type (e.g. `"Msg"`, `"Ack"`)
+    #[wasm_bindgen(getter, js_name = frameType)]
+    pub fn frame_type(&self) -> String {
+        format!("{:?}", self.frame.frame_type)
+    }
+
+    /// Sender agent ID
+    #[wasm_bindgen(getter)]
+    pub fn from(&self) -> String {
+        self.frame.from.clone()
+    }
+
+    /// Recipient agent ID
+    #[wasm_bindgen(getter)]
+    pub fn to(&self) -> String {
+        self.frame.to.clone()
+    }
+
+    /// Message ID, if the frame has one
+    #[wasm_bindgen(getter)]
+    pub fn id(&self) -> Option<String> {
+        self.frame.id.map(|id| id.to_string())
+    }
+
+    /// Sender timestamp (milliseconds since the Unix epoch)
+    #[wasm_bindgen(getter)]
+    pub fn ts(&self) -> f64 {
+        self.frame.ts as f64
+    }
+
+    /// Decompressed payload
+    #[wasm_bindgen(getter)]
+    pub fn payload(&self) -> Vec<u8> {
+        self.payload.clone()
+    }
+
+    /// Payload as UTF-8 text, if it is valid
+    #[wasm_bindgen(getter)]
+    pub fn text(&self) -> Option<String> {
+        String::from_utf8(self.payload.clone()).ok()
+    }
+}
+
+/// Agent for JavaScript: an identity and a WebTransport session
+///
+/// Outgoing frames are built by [`SecurityManager`] exactly as in
+/// [`OpacusClient`](crate::OpacusClient) on native targets.
+#[wasm_bindgen]
+pub struct WasmAgent {
+    identity: AgentIdentity,
+    security: SecurityManager,
+    transport: Option<WebTransportClient>,
+    seq: u64,
+}
+
+#[wasm_bindgen]
+impl WasmAgent {
+    /// Create agent with a new identity for the chain `chain_id`
+    #[wasm_bindgen(constructor)]
+    pub fn new(chain_id: u64) -> WasmAgent {
+        Self::with_identity(KeyManager::generate_identity(chain_id))
+    }
+
+    /// Restore agent from a 32-byte seed
+    #[wasm_bindgen(js_name = fromSeed)]
+    pub fn from_seed(seed: &[u8], chain_id: u64) -> Result<WasmAgent, JsError> {
+        let seed: &[u8; 32] = seed.try_into().map_err(|_| JsError::new("Seed must be 32 bytes"))?;
+        Ok(Self::with_identity(KeyManager::identity_from_seed(seed, chain_id)))
+    }
+
+    fn with_identity(identity: AgentIdentity) -> WasmAgent {
+        WasmAgent { identity, security: SecurityManager::new(), transport: None, seq: 0 }
+    }
+
+    /// Agent ID
+    #[wasm_bindgen(getter)]
+    pub fn id(&self) -> String {
+        self.identity.id.clone()
+    }
+
+    /// EVM address
+    #[wasm_bindgen(getter)]
+    pub fn address(&self) -> String {
+        self.identity.address.clone()
+    }
+
+    /// Connect to a relay's WebTransport endpoint and send the `Connect` frame
+    ///
+    /// The relay's `Ack` arrives through `recv`.
+    pub async fn connect(&mut self, url: String, cert_hash: Option<Vec<u8>>) -> Result<(), JsError> {
+        let transport = WebTransportClient::connect(&url, cert_hash.as_deref()).await.map_err(to_js)?;
+
+        let connect_payload = serde_json::json!({
+            "edPub": KeyManager::to_hex(&self.identity.ed_pub),
+            "xPub": KeyManager::to_hex(&self.identity.x_pub),
+            "compression": Compression::supported()
+        });
+        let ts = SystemClock.now_ms();
+        let frame = OpacusFrame {
+            version: FRAME_VERSION,
+            frame_type: FrameType::Connect,
+            from: self.identity.id.clone(),
+            to: "relay".to_string(),
+            seq: self.seq,
+            ts,
+            nonce: self.security.next_nonce(),
+            payload: serde_json::to_vec(&connect_payload)?.into(),
+            hmac: None,
+            sig: None,
+            key_epoch: 0,
+            compressed: None,
+            id: Some(OpacusFrame::new_id(ts)),
+            priority: Priority::Control,
+            content_type: ContentType::Json,
+            extensions: Default::default(),
+        };
+        self.seq += 1;
+        transport.send(&frame).map_err(to_js)?;
+        self.transport = Some(transport);
+        Ok(())
+    }
+
+    /// Send a binary message to another agent
+    #[wasm_bindgen(js_name = sendMessage)]
+    pub fn send_message(&mut self, to: &str, payload: Vec<u8>) -> Result<(), JsError> {
+        self.send(to, payload, ContentType::Raw)
+    }
+
+    /// Send a UTF-8 text message to another agent
+    #[wasm_bindgen(js_name = sendText)]
+    pub fn send_text(&mut self, to: &str, text: &str) -> Result<(), JsError> {
+        self.send(to, text.as_bytes().to_vec(), ContentType::Text)
+    }
+
+    fn send(&mut self, to: &str, payload: Vec<u8>, content_type: ContentType) -> Result<(), JsError> {
+        let transport = self.transport.as_ref().ok_or_else(|| JsError::new("Not connected"))?;
+        let options = FrameOptions { content_type, ..Default::default() };
+        let frame = self.security.create_auth_frame_with(&self.identity, &[0u8; 32], FrameType::Msg, to, payload, options);
+        transport.send(&frame).map_err(to_js)
+    }
+
+    /// Receive the next frame (`undefined` once the session is closed)
+    pub async fn recv(&mut self) -> Result<Option<WasmMessage>, JsError> {
+        let Some(frame) = self.transport.as_mut().ok_or_else(|| JsError::new("Not connected"))?.recv().await else {
+            return Ok(None);
+        };
+        let payload = frame.decompressed_payload().map_err(|e| JsError::new(&e))?.into_owned();
+        Ok(Some(WasmMessage { frame, payload }))
+    }
+
+    /// Check whether sends should be held back
+    #[wasm_bindgen(js_name = isCongested)]
+    pub fn is_congested(&self) -> bool {
+        self.transport.as_ref().is_some_and(WebTransportClient::is_congested)
+    }
+
+    /// Close the session
+    pub fn disconnect(&mut self) {
+        if let Some(mut transport) = self.transport.take() {
+            transport.close();
+        }
+    }
+}
+
+fn to_js(e: anyhow::Error) -> JsError {
+    JsError::new(&e.to_string())
+}
@@ -0,0 +1,22 @@
+//! Checks the committed interoperability vectors against this implementation
+//!
+//! Regenerate them after an intentional wire change with
+//! `OPACUS_UPDATE_VECTORS=1 cargo test --test verify_vectors`.
+
+use opacus_sdk::{generate_vectors, verify_vectors, VectorSet};
+
+const VECTORS_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/vectors/interop.json");
+
+#[test]
+fn test_interop_vectors() {
+    let generated = generate_vectors();
+    if std::env::var_os("OPACUS_UPDATE_VECTORS").is_some() {
+        std::fs::write(VECTORS_PATH, generated.to_json() + "\n").unwrap();
+    }
+
+    let committed: VectorSet = serde_json::from_str(&std::fs::read_to_string(VECTORS_PATH).unwrap()).unwrap();
+    if let Err(e) = verify_vectors(&committed) {
+        panic!("{} no longer matches the wire format: {}", VECTORS_PATH, e);
+    }
+    assert_eq!(committed, generated, "Vectors changed; regenerate them if this is intended");
+}
@@ -144,9 +144,9 @@ export class OpacusClient {
       targetXPub,
       'msg',
       to,
-      payload
+      payload,
+      ++this.seq
     );
-    frame.seq = ++this.seq;
     
     await this.transport.send(frame);
   }
@@ -164,9 +164,9 @@ export class OpacusClient {
       this.relayXPub,
       'stream',
       'broadcast',
-      { channelId, data }
+      { channelId, data },
+      ++this.seq
     );
-    frame.seq = ++this.seq;
     
     await this.transport.send(frame);
   }
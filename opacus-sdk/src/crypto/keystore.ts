@@ -0,0 +1,177 @@
+/**
+ * Opacus Encrypted Keystore
+ * Password-encrypted identity export format shared with the Rust SDK
+ *
+ * Envelope version 1:
+ * - KDF: scrypt over the UTF-8 password, 64-byte output
+ * - Cipher: AES-256-CTR keyed with the first 32 bytes, encrypting edPriv || xPriv
+ * - MAC: HMAC-SHA256 keyed with the last 32 bytes, over iv || ciphertext
+ *
+ * Envelopes asking for more than the MAX_SCRYPT_* costs are refused before
+ * deriving, so an untrusted file cannot exhaust memory or time.
+ */
+
+import * as ed from '@noble/ed25519';
+import { x25519 } from '@noble/curves/ed25519';
+import { ctr } from '@noble/ciphers/aes';
+import { scryptAsync } from '@noble/hashes/scrypt';
+import { hmac } from '@noble/hashes/hmac';
+import { sha256 } from '@noble/hashes/sha256';
+import { randomBytes } from '@noble/hashes/utils';
+import { KeyManager } from './keys';
+import { AgentIdentity } from '../types';
+
+export const KEYSTORE_VERSION = 1;
+
+export interface KeystoreParams {
+  /** log2 of the scrypt cost N */
+  logN: number;
+  r: number;
+  p: number;
+}
+
+/** Largest log2 of the scrypt cost N accepted (256 MiB with r = 8) */
+export const MAX_SCRYPT_LOG_N = 18;
+/** Largest scrypt block size accepted */
+export const MAX_SCRYPT_R = 8;
+/** Largest scrypt parallelization accepted */
+export const MAX_SCRYPT_P = 4;
+
+export const DEFAULT_KEYSTORE_PARAMS: KeystoreParams = { logN: 17, r: 8, p: 1 };
+
+export interface EncryptedIdentity {
+  version: number;
+  id: string;
+  address: string;
+  chainId: number;
+  crypto: {
+    kdf: 'scrypt';
+    kdfParams: { n: number; r: number; p: number; dkLen: number; salt: string };
+    cipher: 'aes-256-ctr';
+    iv: string;
+    ciphertext: string;
+    mac: string;
+  };
+}
+
+export class Keystore {
+  /**
+   * Encrypt identity into a JSON envelope
+   */
+  static async encrypt(
+    identity: AgentIdentity,
+    password: string,
+    params: KeystoreParams = DEFAULT_KEYSTORE_PARAMS
+  ): Promise<string> {
+    const salt = randomBytes(32);
+    const iv = randomBytes(16);
+    const kdfParams = { n: 2 ** params.logN, r: params.r, p: params.p, dkLen: 64, salt: KeyManager.toHex(salt) };
+    const dk = await this.derive(password, kdfParams);
+
+    const plaintext = new Uint8Array(64);
+    plaintext.set(identity.edPriv, 0);
+    plaintext.set(identity.xPriv, 32);
+    const ciphertext = ctr(dk.slice(0, 32), iv).encrypt(plaintext);
+    const mac = hmac(sha256, dk.slice(32), concat(iv, ciphertext));
+
+    const envelope: EncryptedIdentity = {
+      version: KEYSTORE_VERSION,
+      id: identity.id,
+      address: identity.address,
+      chainId: identity.chainId,
+      crypto: {
+        kdf: 'scrypt',
+        kdfParams,
+        cipher: 'aes-256-ctr',
+        iv: KeyManager.toHex(iv),
+        ciphertext: KeyManager.toHex(ciphertext),
+        mac: KeyManager.toHex(mac)
+      }
+    };
+    return JSON.stringify(envelope, null, 2);
+  }
+
+  /**
+   * Decrypt identity from a JSON envelope
+   */
+  static async decrypt(json: string, password: string): Promise<AgentIdentity> {
+    const envelope: EncryptedIdentity = JSON.parse(json);
+    if (envelope.version !== KEYSTORE_VERSION) {
+      throw new Error(`Unsupported keystore version: ${envelope.version}`);
+    }
+    const { crypto } = envelope;
+    if (crypto.kdf !== 'scrypt' || crypto.cipher !== 'aes-256-ctr') {
+      throw new Error('Unsupported keystore algorithms');
+    }
+
+    const iv = KeyManager.fromHex(crypto.iv);
+    const ciphertext = KeyManager.fromHex(crypto.ciphertext);
+    if (iv.length !== 16 || ciphertext.length !== 64) {
+      throw new Error('Invalid keystore field length');
+    }
+
+    const dk = await this.derive(password, crypto.kdfParams);
+    const mac = hmac(sha256, dk.slice(32), concat(iv, ciphertext));
+    if (!constantTimeEqual(mac, KeyManager.fromHex(crypto.mac))) {
+      throw new Error('Wrong password or corrupted keystore');
+    }
+
+    const plaintext = ctr(dk.slice(0, 32), iv).decrypt(ciphertext);
+    const edPriv = plaintext.slice(0, 32);
+    const xPriv = plaintext.slice(32);
+    const edPub = await ed.getPublicKeyAsync(edPriv);
+    const id = KeyManager.toHex(sha256(edPub).slice(0, 20));
+    if (id !== envelope.id) {
+      throw new Error('Keystore ID does not match decrypted keys');
+    }
+
+    return {
+      id,
+      edPub,
+      edPriv,
+      xPub: x25519.getPublicKey(xPriv),
+      xPriv,
+      address: '0x' + id,
+      chainId: envelope.chainId
+    };
+  }
+
+  private static async derive(
+    password: string,
+    params: EncryptedIdentity['crypto']['kdfParams']
+  ): Promise<Uint8Array> {
+    if (params.dkLen !== 64) {
+      throw new Error(`Unsupported dkLen: ${params.dkLen}`);
+    }
+    const { n, r, p } = params;
+    if (![n, r, p].every(Number.isSafeInteger) || n < 2 || r < 1 || p < 1) {
+      throw new Error('Invalid scrypt parameters');
+    }
+    if (n > 2 ** MAX_SCRYPT_LOG_N || r > MAX_SCRYPT_R || p > MAX_SCRYPT_P) {
+      throw new Error(`scrypt parameters exceed the limits: N=${n} r=${r} p=${p}`);
+    }
+    if ((n & (n - 1)) !== 0) {
+      throw new Error(`Invalid scrypt N: ${n}`);
+    }
+    return scryptAsync(new TextEncoder().encode(password), KeyManager.fromHex(params.salt), {
+      N: params.n,
+      r: params.r,
+      p: params.p,
+      dkLen: 64
+    });
+  }
+}
+
+function concat(a: Uint8Array, b: Uint8Array): Uint8Array {
+  const out = new Uint8Array(a.length + b.length);
+  out.set(a, 0);
+  out.set(b, a.length);
+  return out;
+}
+
+function constantTimeEqual(a: Uint8Array, b: Uint8Array): boolean {
+  if (a.length !== b.length) return false;
+  let diff = 0;
+  for (let i = 0; i < a.length; i++) diff |= a[i] ^ b[i];
+  return diff === 0;
+}
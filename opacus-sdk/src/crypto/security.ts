@@ -1,6 +1,9 @@
 /**
  * Opacus Security Manager
  * Handles ECDH, HKDF, HMAC, signatures, and nonce management
+ *
+ * Frame session keys, HMAC inputs and signing inputs follow the Rust SDK,
+ * so frames authenticate across both (see tests/vectors.test.ts).
  */
 
 import { x25519 } from '@noble/curves/ed25519';
@@ -10,12 +13,33 @@ import { hkdf } from '@noble/hashes/hkdf';
 import * as ed from '@noble/ed25519';
 import { randomBytes } from '@noble/hashes/utils';
 import { KeyManager } from './keys';
-import { AgentIdentity, OpacusFrame } from '../types';
+import { AgentIdentity, FrameType, OpacusFrame } from '../types';
+
+/** HKDF info prefix of frame session keys */
+const SESSION_INFO_PREFIX = 'opacus-session';
+
+/** Frame type names as they appear in HMAC inputs */
+const TYPE_NAMES: Record<FrameType, string> = {
+  connect: 'Connect', msg: 'Msg', ping: 'Ping', ack: 'Ack', stream: 'Stream', payment: 'Payment',
+  prekeypublish: 'PreKeyPublish', prekeyfetch: 'PreKeyFetch', rekey: 'Rekey', error: 'Error',
+  batch: 'Batch', subscribe: 'Subscribe', capabilities: 'Capabilities', profile: 'Profile',
+  history: 'History', task: 'Task', onion: 'Onion', cover: 'Cover', gossip: 'Gossip', dht: 'Dht'
+};
+
+export interface SecurityManagerOptions {
+  /** Time source for nonces and timestamps (milliseconds) */
+  now?: () => number;
+}
 
 export class SecurityManager {
   private nonceWindow: Map<string, number> = new Map();
   private lastNonce: bigint = 0n;
   private sessionKeys: Map<string, Uint8Array> = new Map();
+  private now: () => number;
+
+  constructor(options: SecurityManagerOptions = {}) {
+    this.now = options.now ?? Date.now;
+  }
 
   /**
    * Derive shared secret using ECDH
@@ -27,10 +51,57 @@ export class SecurityManager {
   /**
    * Derive session key from shared secret using HKDF
    */
-  deriveSessionKey(sharedSecret: Uint8Array, info = 'opacus-session'): Uint8Array {
+  deriveSessionKey(sharedSecret: Uint8Array, info: string | Uint8Array = SESSION_INFO_PREFIX): Uint8Array {
     return hkdf(sha256, sharedSecret, undefined, info, 32);
   }
 
+  /**
+   * HKDF info of a frame's session key: the prefix, the frame version, the
+   * length-prefixed sender and recipient, and the key epoch unless 0
+   */
+  static frameSessionInfo(version: number, from: string, to: string, epoch = 0): Uint8Array {
+    const encoder = new TextEncoder();
+    const parts = [encoder.encode(SESSION_INFO_PREFIX), Uint8Array.of(version)];
+    for (const id of [from, to]) {
+      const bytes = encoder.encode(id);
+      parts.push(u32(bytes.length), bytes);
+    }
+    if (epoch > 0) parts.push(u32(epoch));
+    return concat(parts);
+  }
+
+  /**
+   * Derive the session key of a frame between two agents
+   */
+  deriveFrameSessionKey(sharedSecret: Uint8Array, version: number, from: string, to: string, epoch = 0): Uint8Array {
+    return this.deriveSessionKey(sharedSecret, SecurityManager.frameSessionInfo(version, from, to, epoch));
+  }
+
+  /**
+   * Data a frame's HMAC covers
+   */
+  static hmacInput(frame: OpacusFrame): string {
+    let data = `${TYPE_NAMES[frame.type]}|${frame.from}|${frame.to}|${frame.seq}|${frame.ts}|${frame.nonce}|` +
+      KeyManager.toHex(payloadBytes(frame.payload));
+    if (frame.compressed) data += `|${frame.compressed}`;
+    return data;
+  }
+
+  /**
+   * Data a frame's Ed25519 signature covers: a canonical CBOR map of the
+   * header fields and HMAC, with the ID, priority and content type when set
+   */
+  static signingInput(frame: OpacusFrame, hmac: string): Uint8Array {
+    const fields: [string, CborScalar][] = [
+      ['version', frame.version], ['type', frame.type], ['from', frame.from], ['to', frame.to],
+      ['seq', frame.seq], ['ts', frame.ts], ['nonce', frame.nonce], ['hmac', hmac]
+    ];
+    if (frame.id) fields.push(['id', frame.id]);
+    if (frame.priority && frame.priority !== 'normal') fields.push(['priority', frame.priority]);
+    if (frame.content_type && frame.content_type !== 'raw') fields.push(['content_type', frame.content_type]);
+    return canonicalMap(fields);
+  }
+
   /**
    * Generate HMAC for message authentication
    */
@@ -43,15 +114,15 @@ export class SecurityManager {
    * Verify HMAC
    */
   verifyHMAC(key: Uint8Array, data: string, expected: string): boolean {
-    const computed = this.generateHMAC(key, data);
-    return computed === expected;
+    const computed = KeyManager.fromHex(this.generateHMAC(key, data));
+    return /^[0-9a-f]{64}$/.test(expected) && constantTimeEqual(computed, KeyManager.fromHex(expected));
   }
 
   /**
    * Generate nonce (anti-replay protection)
    */
   generateNonce(): string {
-    const timestamp = Date.now();
+    const timestamp = this.now();
     const random = randomBytes(8);
     const nonce = `${timestamp}-${KeyManager.toHex(random)}`;
     return nonce;
@@ -63,7 +134,7 @@ export class SecurityManager {
   validateNonce(nonce: string, maxAge = 60000): boolean {
     const [tsStr] = nonce.split('-');
     const ts = parseInt(tsStr, 10);
-    const now = Date.now();
+    const now = this.now();
     
     // Check timestamp freshness
     if (now - ts > maxAge) return false;
@@ -81,7 +152,7 @@ export class SecurityManager {
   }
 
   private cleanupNonces(maxAge: number) {
-    const now = Date.now();
+    const now = this.now();
     for (const [n, ts] of this.nonceWindow) {
       if (now - ts > maxAge * 2) this.nonceWindow.delete(n);
     }
@@ -109,19 +180,11 @@ export class SecurityManager {
     peerXPub: Uint8Array,
     type: OpacusFrame['type'],
     to: string,
-    payload: any
+    payload: any,
+    seq = Number(++this.lastNonce)
   ): Promise<OpacusFrame> {
     const nonce = this.generateNonce();
-    const ts = Date.now();
-    const seq = Number(++this.lastNonce);
-    
-    // Derive session key
-    const shared = this.deriveSharedSecret(identity.xPriv, peerXPub);
-    const sessionKey = this.deriveSessionKey(shared);
-    
-    // Create HMAC
-    const hmacData = `${type}|${identity.id}|${to}|${seq}|${ts}|${nonce}|${JSON.stringify(payload)}`;
-    const hmacVal = this.generateHMAC(sessionKey, hmacData);
+    const ts = Number(nonce.split('-')[0]);
     
     // Create frame
     const frame: OpacusFrame = {
@@ -132,22 +195,16 @@ export class SecurityManager {
       seq,
       ts,
       nonce,
-      payload,
-      hmac: hmacVal
+      payload
     };
     
+    // Create HMAC
+    const shared = this.deriveSharedSecret(identity.xPriv, peerXPub);
+    const sessionKey = this.deriveFrameSessionKey(shared, frame.version, frame.from, frame.to);
+    frame.hmac = this.generateHMAC(sessionKey, SecurityManager.hmacInput(frame));
+    
     // Sign frame
-    const frameBytes = new TextEncoder().encode(JSON.stringify({
-      version: frame.version,
-      type: frame.type,
-      from: frame.from,
-      to: frame.to,
-      seq: frame.seq,
-      ts: frame.ts,
-      nonce: frame.nonce,
-      hmac: frame.hmac
-    }));
-    frame.sig = await this.signMessage(identity.edPriv, frameBytes);
+    frame.sig = await this.signMessage(identity.edPriv, SecurityManager.signingInput(frame, frame.hmac));
     
     return frame;
   }
@@ -167,27 +224,18 @@ export class SecurityManager {
     }
     
     // 2. Verify signature
-    const frameBytes = new TextEncoder().encode(JSON.stringify({
-      version: frame.version,
-      type: frame.type,
-      from: frame.from,
-      to: frame.to,
-      seq: frame.seq,
-      ts: frame.ts,
-      nonce: frame.nonce,
-      hmac: frame.hmac
-    }));
-    
-    if (!frame.sig || !(await this.verifySignature(senderEdPub, frameBytes, frame.sig))) {
+    if (!frame.hmac) {
+      return { valid: false, reason: 'Missing HMAC' };
+    }
+    const signingInput = SecurityManager.signingInput(frame, frame.hmac);
+    if (!frame.sig || !(await this.verifySignature(senderEdPub, signingInput, frame.sig))) {
       return { valid: false, reason: 'Invalid signature' };
     }
     
     // 3. Verify HMAC
     const shared = this.deriveSharedSecret(myXPriv, senderXPub);
-    const sessionKey = this.deriveSessionKey(shared);
-    const hmacData = `${frame.type}|${frame.from}|${frame.to}|${frame.seq}|${frame.ts}|${frame.nonce}|${JSON.stringify(frame.payload)}`;
-    
-    if (!this.verifyHMAC(sessionKey, hmacData, frame.hmac!)) {
+    const sessionKey = this.deriveFrameSessionKey(shared, frame.version, frame.from, frame.to, frame.key_epoch ?? 0);
+    if (!this.verifyHMAC(sessionKey, SecurityManager.hmacInput(frame), frame.hmac)) {
       return { valid: false, reason: 'HMAC mismatch' };
     }
     
@@ -215,3 +263,66 @@ export class SecurityManager {
     this.sessionKeys.clear();
   }
 }
+
+/** Payload bytes as authenticated: bytes as they are, objects as JSON */
+function payloadBytes(payload: OpacusFrame['payload']): Uint8Array {
+  return payload instanceof Uint8Array ? payload : new TextEncoder().encode(JSON.stringify(payload));
+}
+
+type CborScalar = string | number | bigint;
+
+/** Definite-length CBOR map with keys sorted bytewise by their encoding (RFC 8949 §4.2.1) */
+function canonicalMap(fields: [string, CborScalar][]): Uint8Array {
+  const entries = fields.map(([key, value]) => [cborScalar(key), cborScalar(value)]);
+  entries.sort(([a], [b]) => compareBytes(a, b));
+  return concat([cborHead(5, fields.length), ...entries.flat()]);
+}
+
+/** Text string or unsigned integer, in its shortest form */
+function cborScalar(value: CborScalar): Uint8Array {
+  if (typeof value === 'string') {
+    const bytes = new TextEncoder().encode(value);
+    return concat([cborHead(3, bytes.length), bytes]);
+  }
+  return cborHead(0, value);
+}
+
+function cborHead(major: number, value: number | bigint): Uint8Array {
+  const n = BigInt(value);
+  if (n < 24n) return Uint8Array.of((major << 5) | Number(n));
+  const [info, len] = n < 0x100n ? [24, 1] : n < 0x10000n ? [25, 2] : n < 0x100000000n ? [26, 4] : [27, 8];
+  const out = new Uint8Array(1 + len);
+  out[0] = (major << 5) | info;
+  for (let i = len; i > 0; i--) out[i] = Number((n >> BigInt(8 * (len - i))) & 0xffn);
+  return out;
+}
+
+function compareBytes(a: Uint8Array, b: Uint8Array): number {
+  for (let i = 0; i < Math.min(a.length, b.length); i++) {
+    if (a[i] !== b[i]) return a[i] - b[i];
+  }
+  return a.length - b.length;
+}
+
+function u32(value: number): Uint8Array {
+  const out = new Uint8Array(4);
+  new DataView(out.buffer).setUint32(0, value);
+  return out;
+}
+
+function concat(parts: Uint8Array[]): Uint8Array {
+  const out = new Uint8Array(parts.reduce((len, part) => len + part.length, 0));
+  let offset = 0;
+  for (const part of parts) {
+    out.set(part, offset);
+    offset += part.length;
+  }
+  return out;
+}
+
+function constantTimeEqual(a: Uint8Array, b: Uint8Array): boolean {
+  if (a.length !== b.length) return false;
+  let diff = 0;
+  for (let i = 0; i < a.length; i++) diff |= a[i] ^ b[i];
+  return diff === 0;
+}
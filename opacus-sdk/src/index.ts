@@ -11,6 +11,9 @@ export { OpacusClient } from './client.js';
 // Crypto
 export { KeyManager } from './crypto/keys.js';
 export { SecurityManager } from './crypto/security.js';
+export type { SecurityManagerOptions } from './crypto/security.js';
+export { Keystore, KEYSTORE_VERSION, DEFAULT_KEYSTORE_PARAMS } from './crypto/keystore.js';
+export type { KeystoreParams, EncryptedIdentity } from './crypto/keystore.js';
 
 // Protocol
 export { CBORCodec } from './proto/cbor.js';
@@ -13,17 +13,36 @@ export interface OpacusConfig {
   storageIndexer?: string;
 }
 
+export type FrameType =
+  | 'connect' | 'msg' | 'ping' | 'ack' | 'stream' | 'payment'
+  | 'prekeypublish' | 'prekeyfetch' | 'rekey' | 'error' | 'batch'
+  | 'subscribe' | 'capabilities' | 'profile' | 'history' | 'task'
+  | 'onion' | 'cover' | 'gossip' | 'dht';
+
+export type FramePriority = 'low' | 'normal' | 'high' | 'control';
+
 export interface OpacusFrame {
   version: number;
-  type: 'connect' | 'msg' | 'ping' | 'ack' | 'stream' | 'payment';
+  type: FrameType;
   from: string;
   to: string;
   seq: number;
   ts: number;
   nonce: string;
+  /** Bytes, or an object authenticated as its JSON encoding */
   payload: Uint8Array | object;
   hmac?: string;
   sig?: Uint8Array;
+  /** Message ID (ULID), required from version 2 */
+  id?: string;
+  /** Session key epoch (omitted when 0) */
+  key_epoch?: number;
+  /** Payload compression algorithm (omitted when uncompressed) */
+  compressed?: string;
+  /** Scheduling priority (omitted when normal) */
+  priority?: FramePriority;
+  /** Payload encoding (omitted when raw) */
+  content_type?: string;
 }
 
 export interface DACConfig {
@@ -1,6 +1,7 @@
 import { describe, test, expect, beforeEach } from 'vitest';
 import { KeyManager } from '../src/crypto/keys';
 import { SecurityManager } from '../src/crypto/security';
+import { Keystore } from '../src/crypto/keystore';
 import type { AgentIdentity } from '../src/types';
 
 describe('KeyManager', () => {
@@ -179,3 +180,33 @@ describe('SecurityManager', () => {
     expect(security.getSessionKey('peer-2')).toBeUndefined();
   });
 });
+
+describe('Keystore', () => {
+  const FAST = { logN: 4, r: 8, p: 1 };
+
+  test('encrypt and decrypt identity roundtrip', async () => {
+    const original = await KeyManager.generateFullIdentity();
+    const json = await Keystore.encrypt(original, 'hunter2', FAST);
+    expect(json).not.toContain(KeyManager.toHex(original.edPriv));
+
+    const restored = await Keystore.decrypt(json, 'hunter2');
+    expect(restored.id).toBe(original.id);
+    expect(restored.edPriv).toEqual(original.edPriv);
+    expect(restored.xPriv).toEqual(original.xPriv);
+    expect(restored.xPub).toEqual(original.xPub);
+  });
+
+  test('wrong password is rejected', async () => {
+    const original = await KeyManager.generateFullIdentity();
+    const json = await Keystore.encrypt(original, 'right', FAST);
+    await expect(Keystore.decrypt(json, 'wrong')).rejects.toThrow();
+  });
+
+  test('costs beyond the limits are refused', async () => {
+    const original = await KeyManager.generateFullIdentity();
+    const envelope = JSON.parse(await Keystore.encrypt(original, 'right', FAST));
+    envelope.crypto.kdfParams.n = 2 ** 40;
+    await expect(Keystore.decrypt(JSON.stringify(envelope), 'right')).rejects.toThrow('exceed the limits');
+    await expect(Keystore.encrypt(original, 'right', { logN: 4, r: 8, p: 64 })).rejects.toThrow();
+  });
+});
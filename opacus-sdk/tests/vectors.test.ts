@@ -0,0 +1,80 @@
+/**
+ * Cross-SDK interoperability vectors
+ * Runs the frames generated by opacus-rust (`vectors/interop.json`) through
+ * SecurityManager, so both implementations stay wire-compatible
+ */
+
+import { describe, test, expect } from 'vitest';
+import { readFileSync } from 'fs';
+import { join } from 'path';
+import * as ed from '@noble/ed25519';
+import { x25519 } from '@noble/curves/ed25519';
+import { sha256 } from '@noble/hashes/sha256';
+import { hkdf } from '@noble/hashes/hkdf';
+import { decode } from 'cbor-x';
+import { KeyManager } from '../src/crypto/keys';
+import { SecurityManager } from '../src/crypto/security';
+
+const vectors = JSON.parse(
+  readFileSync(join(__dirname, '../../opacus-rust/vectors/interop.json'), 'utf8')
+);
+const hex = KeyManager.fromHex;
+const SEED_SALT = 'opacus-identity-seed-v1';
+
+describe('Interop vectors', () => {
+  test('vector format version is supported', () => {
+    expect(vectors.version).toBe(2);
+  });
+
+  test.each(vectors.identities.map((v: any) => [v.name, v]))('identity %s derives from its seed', async (_, v: any) => {
+    const edPriv = hkdf(sha256, hex(v.seed), SEED_SALT, 'opacus-ed25519', 32);
+    const xPriv = hkdf(sha256, hex(v.seed), SEED_SALT, 'opacus-x25519', 32);
+    expect(KeyManager.toHex(edPriv)).toBe(v.edPriv);
+    expect(KeyManager.toHex(xPriv)).toBe(v.xPriv);
+
+    const edPub = await ed.getPublicKeyAsync(edPriv);
+    expect(KeyManager.toHex(edPub)).toBe(v.edPub);
+    expect(KeyManager.toHex(x25519.getPublicKey(xPriv))).toBe(v.xPub);
+    expect(KeyManager.toHex(sha256(edPub).slice(0, 20))).toBe(v.id);
+    expect(v.address).toBe('0x' + v.id);
+  });
+
+  test('shared secret matches on both sides', () => {
+    const [alice, bob] = vectors.identities;
+    const security = new SecurityManager();
+    expect(KeyManager.toHex(security.deriveSharedSecret(hex(alice.xPriv), hex(bob.xPub)))).toBe(vectors.sharedSecret);
+    expect(KeyManager.toHex(security.deriveSharedSecret(hex(bob.xPriv), hex(alice.xPub)))).toBe(vectors.sharedSecret);
+  });
+
+  test.each(vectors.frames.map((v: any) => [v.name, v]))('frame %s authenticates', async (_, v: any) => {
+    const [alice, bob] = vectors.identities;
+    const frame = decode(hex(v.cbor));
+    expect(frame.from).toBe(alice.id);
+    expect(frame.to).toBe(bob.id);
+
+    const security = new SecurityManager({ now: () => Number(frame.ts) });
+    const info = SecurityManager.frameSessionInfo(frame.version, frame.from, frame.to, frame.key_epoch ?? 0);
+    expect(KeyManager.toHex(info)).toBe(v.sessionInfo);
+    const sessionKey = security.deriveFrameSessionKey(
+      hex(vectors.sharedSecret), frame.version, frame.from, frame.to, frame.key_epoch ?? 0
+    );
+    expect(KeyManager.toHex(sessionKey)).toBe(v.sessionKey);
+    expect(SecurityManager.hmacInput(frame)).toBe(v.hmacInput);
+    expect(security.generateHMAC(sessionKey, v.hmacInput)).toBe(v.hmac);
+    expect(KeyManager.toHex(SecurityManager.signingInput(frame, frame.hmac))).toBe(v.signingInput);
+    expect(KeyManager.toHex(Uint8Array.from(frame.sig))).toBe(v.sig);
+
+    const result = await security.verifyAuthFrame(frame, hex(alice.edPub), hex(bob.xPriv), hex(alice.xPub));
+    expect(result).toEqual({ valid: true });
+  });
+
+  test('tampered frame is rejected', async () => {
+    const [alice, bob] = vectors.identities;
+    const frame = decode(hex(vectors.frames[0].cbor));
+    frame.payload = Uint8Array.from([...frame.payload, 0]);
+
+    const security = new SecurityManager({ now: () => Number(frame.ts) });
+    const result = await security.verifyAuthFrame(frame, hex(alice.edPub), hex(bob.xPriv), hex(alice.xPub));
+    expect(result).toEqual({ valid: false, reason: 'HMAC mismatch' });
+  });
+});
@@ -0,0 +1,284 @@
+//! Blocking client for code without an async runtime (`blocking` feature)
+//!
+//! Wraps the async [`H3DACClient`](crate::H3DACClient) and drives it on a
+//! runtime owned by the client. Do not call it from inside an async runtime;
+//! use the async client there.
+
+use secp256k1::SecretKey;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::Read;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::runtime::Runtime;
+
+use crate::clock::Clock;
+use crate::error::Result;
+use crate::events::{EventStream, GatewayEvent};
+use crate::http::{GatewayHealth, HttpClient, ProofStatus, RateLimit, UploadReceipt, UsageReport};
+use crate::session::ExportedSession;
+use crate::AuthSession;
+
+/// Blocking counterpart of [`crate::H3DACClient`]
+pub struct H3DACClient {
+    inner: crate::H3DACClient,
+    runtime: Runtime,
+}
+
+impl H3DACClient {
+    /// Create a new client with a private key
+    pub fn new(private_key: SecretKey, gateway_url: Option<&str>) -> Result<Self> {
+        Self::from_async(crate::H3DACClient::new(private_key, gateway_url))
+    }
+
+    /// Create a client from a hex-encoded private key
+    pub fn from_hex(private_key_hex: &str, gateway_url: Option<&str>) -> Result<Self> {
+        Self::from_async(crate::H3DACClient::from_hex(private_key_hex, gateway_url)?)
+    }
+
+    /// Wrap a configured async client
+    ///
+    /// Background work such as connection pooling runs on one worker thread
+    /// between calls.
+    pub fn from_async(inner: crate::H3DACClient) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()?;
+        Ok(Self { inner, runtime })
+    }
+
+    /// Use a custom time source for timestamps and session expiry
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.inner = self.inner.with_clock(clock);
+        self
+    }
+
+    /// Set tolerated clock skew against the gateway
+    pub fn with_clock_skew(mut self, clock_skew_ms: u64) -> Self {
+        self.inner = self.inner.with_clock_skew(clock_skew_ms);
+        self
+    }
+
+    /// Only accept sessions whose responses the gateway encrypts
+    pub fn with_required_response_encryption(mut self) -> Self {
+        self.inner = self.inner.with_required_response_encryption();
+        self
+    }
+
+    /// Talk to the gateway through a custom-built HTTP client
+    pub fn with_http_client(mut self, http_client: HttpClient) -> Self {
+        self.inner = self.inner.with_http_client(http_client);
+        self
+    }
+
+    /// Verify proofs recorded at `contract` against the chain through a JSON-RPC endpoint
+    pub fn with_rpc(mut self, rpc_url: &str, contract: &str) -> Self {
+        self.inner = self.inner.with_rpc(rpc_url, contract);
+        self
+    }
+
+    /// The wrapped async client
+    pub fn inner(&self) -> &crate::H3DACClient {
+        &self.inner
+    }
+
+    /// Get the client's public key as hex string
+    pub fn get_public_key_hex(&self) -> String {
+        self.inner.get_public_key_hex()
+    }
+
+    /// Get the client's private key as hex string (use with caution)
+    pub fn get_private_key_hex(&self) -> String {
+        self.inner.get_private_key_hex()
+    }
+
+    /// URL of the gateway requests currently go to
+    pub fn active_gateway(&self) -> &str {
+        self.inner.active_gateway()
+    }
+
+    /// Gateway rate limit as last reported
+    pub fn rate_limit(&self) -> RateLimit {
+        self.inner.rate_limit()
+    }
+
+    /// Health-check the configured gateways, failing over if needed
+    pub fn check_gateways(&self) -> Vec<GatewayHealth> {
+        self.runtime.block_on(self.inner.check_gateways())
+    }
+
+    /// Authenticate with the gateway and establish a session
+    pub fn authenticate(&mut self, client_id: &str) -> Result<AuthSession> {
+        self.runtime.block_on(self.inner.authenticate(client_id))
+    }
+
+    /// Send an encrypted request to the gateway and decode its response
+    pub fn send<Req, Resp>(&self, request: &Req) -> Result<Resp>
+    where
+        Req: Serialize + ?Sized,
+        Resp: DeserializeOwned,
+    {
+        self.runtime.block_on(self.inner.send(request))
+    }
+
+    /// Send raw bytes, encrypted, and return the gateway's JSON response
+    pub fn send_raw(&self, payload: &[u8]) -> Result<serde_json::Value> {
+        self.runtime.block_on(self.inner.send_raw(payload))
+    }
+
+    /// Upload a large payload in encrypted, individually signed chunks
+    pub fn upload<R: Read + Unpin>(
+        &self,
+        reader: R,
+        chunk_size: Option<usize>,
+    ) -> Result<UploadReceipt> {
+        self.runtime
+            .block_on(self.inner.upload(SyncReader(reader), chunk_size))
+    }
+
+    /// Replace the key this client signs with
+    pub fn rotate_key(&mut self, new_private_key: SecretKey) -> Result<()> {
+        self.runtime
+            .block_on(self.inner.rotate_key(new_private_key))
+    }
+
+    /// Verify if the session is still valid
+    pub fn verify_session(&self) -> Result<bool> {
+        self.runtime.block_on(self.inner.verify_session())
+    }
+
+    /// Get proof status from the gateway
+    pub fn get_proof_status(&self) -> Result<ProofStatus> {
+        self.runtime.block_on(self.inner.get_proof_status())
+    }
+
+    /// Today's usage and remaining quota
+    pub fn usage(&self) -> Result<UsageReport> {
+        self.runtime.block_on(self.inner.usage())
+    }
+
+    /// Wait until the session's proof is confirmed
+    pub fn wait_for_proof(&self, timeout: Duration, interval: Duration) -> Result<ProofStatus> {
+        self.runtime
+            .block_on(self.inner.wait_for_proof(timeout, interval))
+    }
+
+    /// Check a proof against the chain
+    pub fn verify_proof(&self, status: &ProofStatus) -> Result<bool> {
+        self.runtime.block_on(self.inner.verify_proof(status))
+    }
+
+    /// Iterate over events pushed by the gateway
+    pub fn subscribe_events(&self) -> Result<Events<'_>> {
+        let stream = self.runtime.block_on(self.inner.subscribe_events())?;
+        Ok(Events {
+            runtime: &self.runtime,
+            stream,
+        })
+    }
+
+    /// Export the current session for storage
+    pub fn export_session(&self) -> Result<ExportedSession> {
+        self.inner.export_session()
+    }
+
+    /// Restore a session exported by a client with the same key
+    pub fn restore_session(&mut self, exported: &ExportedSession) -> Result<AuthSession> {
+        self.inner.restore_session(exported)
+    }
+
+    /// Clear current session
+    pub fn clear_session(&mut self) {
+        self.inner.clear_session();
+    }
+
+    /// Check if currently authenticated
+    pub fn is_authenticated(&self) -> bool {
+        self.inner.is_authenticated()
+    }
+}
+
+/// Blocking iterator over gateway events; ends when the stream closes
+pub struct Events<'a> {
+    runtime: &'a Runtime,
+    stream: EventStream,
+}
+
+impl Iterator for Events<'_> {
+    type Item = Result<GatewayEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.runtime.block_on(self.stream.next())
+    }
+}
+
+/// `AsyncRead` over a blocking reader, polled only inside `block_on`
+struct SyncReader<R>(R);
+
+impl<R: Read + Unpin> AsyncRead for SyncReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let n = self.0.read(buf.initialize_unfilled())?;
+        buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl From<H3DACClient> for crate::H3DACClient {
+    fn from(client: H3DACClient) -> Self {
+        client.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{generate_private_key, get_public_key};
+    use crate::error::H3DACError;
+    use crate::tests::serve_adder;
+
+    #[test]
+    fn test_blocking_send() {
+        #[derive(Serialize)]
+        struct Add {
+            a: u64,
+            b: u64,
+        }
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Sum {
+            sum: u64,
+        }
+
+        let server = Runtime::new().unwrap();
+        let gateway_key = generate_private_key();
+        let session = AuthSession {
+            session_key: vec![3u8; 32],
+            client_id: "test".to_string(),
+            expires_at: u64::MAX,
+            gateway_pub_key: get_public_key(&gateway_key),
+            encrypted_responses: true,
+        };
+        let url = server.block_on(serve_adder(session.session_key.clone(), gateway_key));
+
+        let mut client = H3DACClient::new(generate_private_key(), Some(&url)).unwrap();
+        assert!(matches!(
+            client.send::<_, Sum>(&Add { a: 1, b: 2 }),
+            Err(H3DACError::NotAuthenticated)
+        ));
+        assert!(matches!(
+            client.upload(&b"data"[..], None),
+            Err(H3DACError::NotAuthenticated)
+        ));
+        client.inner.session = Some(session);
+        let sum: Sum = client.send(&Add { a: 1, b: 2 }).unwrap();
+        assert_eq!(sum, Sum { sum: 3 });
+        assert!(client.is_authenticated());
+    }
+}
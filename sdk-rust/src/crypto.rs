@@ -2,7 +2,7 @@ use secp256k1::{ecdh::SharedSecret, PublicKey, Secp256k1, SecretKey};
 use sha2::{Digest, Sha256};
 use hkdf::Hkdf;
 use aes_gcm::{
-    aead::{Aead, KeyInit, OsRng},
+    aead::{Aead, KeyInit},
     Aes256Gcm, Nonce,
 };
 use rand::RngCore;
@@ -11,7 +11,6 @@ use crate::error::{H3DACError, Result};
 
 /// Generate a new random private key
 pub fn generate_private_key() -> SecretKey {
-    let secp = Secp256k1::new();
     SecretKey::new(&mut rand::thread_rng())
 }
 
@@ -67,6 +66,15 @@ pub fn derive_session_key(shared_secret: &[u8]) -> Result<Vec<u8>> {
     Ok(session_key)
 }
 
+/// Derive the key encrypting data the client stores at rest
+pub fn derive_storage_key(private_key: &SecretKey) -> Result<Vec<u8>> {
+    let hk = Hkdf::<Sha256>::new(None, &private_key.secret_bytes());
+    let mut storage_key = vec![0u8; 32];
+    hk.expand(b"H3DAC-storage", &mut storage_key)
+        .map_err(|e| H3DACError::CryptoError(format!("HKDF failed: {}", e)))?;
+    Ok(storage_key)
+}
+
 /// Encrypt payload with AES-GCM
 pub fn encrypt_payload(payload: &[u8], session_key: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
     if session_key.len() != 32 {
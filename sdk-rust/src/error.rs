@@ -24,6 +24,72 @@ pub enum H3DACError {
 
     #[error("Serialization error: {0}")]
     SerializationError(String),
+
+    #[error("RPC request failed: {0}")]
+    RpcError(String),
+
+    #[error("I/O error: {0}")]
+    IoError(String),
+
+    #[error("Timed out: {0}")]
+    Timeout(String),
+
+    #[error("Invalid signature: {0}")]
+    InvalidSignature(String),
+
+    #[error("Nonce expired: {0}")]
+    NonceExpired(String),
+
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
+
+    #[error("Payload too large: {0}")]
+    PayloadTooLarge(String),
+
+    /// The gateway's rate limit is exhausted for longer than the client waits
+    #[error("Rate limited, retry after {retry_after:?}")]
+    RateLimited { retry_after: std::time::Duration },
+
+    /// Gateway error without a dedicated variant
+    #[error("Gateway error {code} ({status}): {message}")]
+    GatewayError {
+        status: u16,
+        code: String,
+        message: String,
+    },
+}
+
+impl H3DACError {
+    /// Machine-readable error code
+    ///
+    /// Gateway errors keep the gateway's code; the others are named after
+    /// their variant.
+    pub fn code(&self) -> &str {
+        match self {
+            H3DACError::CryptoError(_) => "crypto_error",
+            H3DACError::HttpError(_) => "http_error",
+            H3DACError::AuthError(_) => "auth_error",
+            H3DACError::SessionExpired => "session_expired",
+            H3DACError::InvalidResponse(_) => "invalid_response",
+            H3DACError::NotAuthenticated => "not_authenticated",
+            H3DACError::SerializationError(_) => "serialization_error",
+            H3DACError::RpcError(_) => "rpc_error",
+            H3DACError::IoError(_) => "io_error",
+            H3DACError::Timeout(_) => "timeout",
+            H3DACError::InvalidSignature(_) => "invalid_signature",
+            H3DACError::NonceExpired(_) => "nonce_expired",
+            H3DACError::QuotaExceeded(_) => "quota_exceeded",
+            H3DACError::PayloadTooLarge(_) => "payload_too_large",
+            H3DACError::RateLimited { .. } => "rate_limited",
+            H3DACError::GatewayError { code, .. } => code,
+        }
+    }
+}
+
+impl From<std::io::Error> for H3DACError {
+    fn from(err: std::io::Error) -> Self {
+        H3DACError::IoError(err.to_string())
+    }
 }
 
 impl From<reqwest::Error> for H3DACError {
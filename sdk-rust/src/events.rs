@@ -0,0 +1,178 @@
+//! Server-pushed gateway events
+
+use reqwest::Response;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use crate::error::{H3DACError, Result};
+
+/// Event pushed by the gateway
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GatewayEvent {
+    /// The session's proof was recorded
+    ProofConfirmed {
+        #[serde(rename = "blockTime")]
+        block_time: u64,
+        #[serde(rename = "txHash", default, skip_serializing_if = "Option::is_none")]
+        tx_hash: Option<String>,
+    },
+    /// The session expires soon; authenticate again to keep going
+    SessionExpiring {
+        #[serde(rename = "expiresAt")]
+        expires_at: u64,
+    },
+    /// The session expired; the stream ends after this event
+    SessionExpired,
+    /// Event type this SDK version does not know
+    #[serde(other)]
+    Unknown,
+}
+
+/// Largest event the parser buffers, counting its unfinished line
+const MAX_EVENT_SIZE: usize = 64 * 1024;
+
+/// Incremental Server-Sent Events parser yielding each event's data
+#[derive(Debug, Default)]
+struct SseParser {
+    buffer: Vec<u8>,
+    data: Vec<String>,
+    /// Bytes of `data` so far
+    data_size: usize,
+}
+
+impl SseParser {
+    /// Feed received bytes, returning the data of completed events
+    ///
+    /// Fails once an event grows past [`MAX_EVENT_SIZE`], so a gateway that
+    /// never ends its line or event cannot make the client buffer without
+    /// bound.
+    fn push(&mut self, bytes: &[u8]) -> Result<Vec<String>> {
+        self.buffer.extend_from_slice(bytes);
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    events.push(self.data.join("\n"));
+                    self.data.clear();
+                    self.data_size = 0;
+                }
+            } else if let Some(value) = line.strip_prefix("data:") {
+                let value = value.strip_prefix(' ').unwrap_or(value);
+                self.data_size += value.len() + 1;
+                self.check_size()?;
+                self.data.push(value.to_string());
+            }
+            // Comments (`:`) and other fields (`event:`, `id:`) carry nothing we need
+        }
+        self.check_size()?;
+        Ok(events)
+    }
+
+    fn check_size(&self) -> Result<()> {
+        if self.data_size + self.buffer.len() > MAX_EVENT_SIZE {
+            return Err(H3DACError::InvalidResponse(format!(
+                "Event exceeds {} bytes",
+                MAX_EVENT_SIZE
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Stream of events from the gateway
+///
+/// Ends when the gateway closes the connection, e.g. once the session
+/// expired, or after an event too large to buffer; subscribe again after
+/// re-authenticating.
+pub struct EventStream {
+    response: Response,
+    parser: SseParser,
+    pending: VecDeque<String>,
+    ended: bool,
+}
+
+impl EventStream {
+    pub(crate) fn new(response: Response) -> Self {
+        Self {
+            response,
+            parser: SseParser::default(),
+            pending: VecDeque::new(),
+            ended: false,
+        }
+    }
+
+    /// Next event, or `None` once the stream ended
+    pub async fn next(&mut self) -> Option<Result<GatewayEvent>> {
+        loop {
+            if let Some(data) = self.pending.pop_front() {
+                return Some(serde_json::from_str(&data).map_err(Into::into));
+            }
+            if self.ended {
+                return None;
+            }
+            match self.response.chunk().await {
+                Ok(Some(bytes)) => match self.parser.push(&bytes) {
+                    Ok(events) => self.pending.extend(events),
+                    Err(e) => {
+                        self.ended = true;
+                        return Some(Err(e));
+                    }
+                },
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sse_parser() {
+        let mut parser = SseParser::default();
+        assert!(parser.push(b": connected\n\nevent: proof_confirmed\nda").unwrap().is_empty());
+        assert_eq!(
+            parser.push(b"ta: {\"a\":1}\r\n\r\n: keepalive\n\ndata: x\ndata:y\n\n").unwrap(),
+            vec!["{\"a\":1}".to_string(), "x\ny".to_string()]
+        );
+
+        // Neither an endless line nor an endless event is buffered
+        let line = vec![b'a'; MAX_EVENT_SIZE + 1];
+        assert!(SseParser::default().push(&line).is_err());
+        let mut parser = SseParser::default();
+        let data = format!("data: {}\n", "a".repeat(1000));
+        assert!((0..65).all(|_| parser.push(data.as_bytes()).is_ok()));
+        assert!(parser.push(data.as_bytes()).is_err());
+        let mut parser = SseParser::default();
+        assert!((0..100).all(|_| parser.push(format!("{}\n", data).as_bytes()).is_ok()));
+
+        let events: Vec<GatewayEvent> = [
+            r#"{"type":"proof_confirmed","blockTime":5,"txHash":"0xab"}"#,
+            r#"{"type":"session_expiring","expiresAt":9}"#,
+            r#"{"type":"session_expired"}"#,
+            r#"{"type":"maintenance"}"#,
+        ]
+        .iter()
+        .map(|json| serde_json::from_str(json).unwrap())
+        .collect();
+        assert_eq!(
+            events,
+            vec![
+                GatewayEvent::ProofConfirmed {
+                    block_time: 5,
+                    tx_hash: Some("0xab".to_string())
+                },
+                GatewayEvent::SessionExpiring { expires_at: 9 },
+                GatewayEvent::SessionExpired,
+                GatewayEvent::Unknown,
+            ]
+        );
+    }
+}
@@ -1,7 +1,138 @@
-use reqwest::Client;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Certificate, Client, Identity, Proxy, RequestBuilder, Response};
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use crate::error::{H3DACError, Result};
+use crate::events::EventStream;
+use crate::retry::RetryPolicy;
+
+/// Header carrying the key under which the gateway deduplicates a submission
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Longest an event stream stays open, overriding the request timeout
+const EVENT_STREAM_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Consecutive server errors after which a gateway is failed over
+pub const DEFAULT_FAILOVER_THRESHOLD: u32 = 3;
+
+/// Timeout of a gateway health check
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest a request waits for the rate limit to reset before failing
+pub const DEFAULT_MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(30);
+
+/// Wait after a 429 without `Retry-After`
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Rate limit as last reported by the gateway
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Requests allowed per window
+    pub limit: Option<u64>,
+    /// Requests left in the current window
+    pub remaining: Option<u64>,
+    /// Time until the window resets
+    pub reset_in: Option<Duration>,
+    /// Time requests are held back for after a 429
+    pub retry_after: Option<Duration>,
+}
+
+#[derive(Debug, Default)]
+struct RateLimitState {
+    limit: Option<u64>,
+    remaining: Option<u64>,
+    reset_at: Option<Instant>,
+    blocked_until: Option<Instant>,
+}
+
+impl RateLimitState {
+    /// Take in the rate limit headers of a response
+    fn update(&mut self, response: &Response) {
+        let header = |names: &[&str]| {
+            names.iter().find_map(|name| {
+                response
+                    .headers()
+                    .get(*name)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.trim().parse::<u64>().ok())
+            })
+        };
+        let now = Instant::now();
+
+        if let Some(limit) = header(&["ratelimit-limit", "x-ratelimit-limit"]) {
+            self.limit = Some(limit);
+        }
+        if let Some(remaining) = header(&["ratelimit-remaining", "x-ratelimit-remaining"]) {
+            self.remaining = Some(remaining);
+        }
+        if let Some(reset) = header(&["ratelimit-reset", "x-ratelimit-reset"]) {
+            // Seconds until the reset, or the reset time in Unix seconds
+            let unix_now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            let seconds = if reset > unix_now / 2 {
+                reset.saturating_sub(unix_now)
+            } else {
+                reset
+            };
+            self.reset_at = Some(now + Duration::from_secs(seconds));
+        }
+        if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = header(&["retry-after"])
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_RETRY_AFTER);
+            self.blocked_until = Some(now + retry_after);
+        }
+    }
+
+    /// How long to hold back the next request
+    fn wait(&self) -> Duration {
+        let now = Instant::now();
+        let blocked = self.blocked_until.map(|until| until.saturating_duration_since(now));
+        let exhausted = match (self.remaining, self.reset_at) {
+            (Some(0), Some(reset_at)) => Some(reset_at.saturating_duration_since(now)),
+            _ => None,
+        };
+        blocked.max(exhausted).unwrap_or_default()
+    }
+
+    fn snapshot(&self) -> RateLimit {
+        let now = Instant::now();
+        RateLimit {
+            limit: self.limit,
+            remaining: self.remaining,
+            reset_in: self.reset_at.map(|at| at.saturating_duration_since(now)),
+            retry_after: self
+                .blocked_until
+                .map(|until| until.saturating_duration_since(now))
+                .filter(|wait| !wait.is_zero()),
+        }
+    }
+}
+
+/// Change of the active gateway
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GatewaySwitch {
+    /// Gateway given up
+    pub from: String,
+    /// Gateway now active
+    pub to: String,
+    /// Why the client switched
+    pub reason: String,
+}
+
+/// Result of a gateway health check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GatewayHealth {
+    pub url: String,
+    pub healthy: bool,
+}
+
+type FailoverCallback = Arc<dyn Fn(&GatewaySwitch) + Send + Sync>;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NonceResponse {
@@ -21,6 +152,9 @@ pub struct AuthRequest {
     #[serde(rename = "clientPubKey")]
     pub client_pub_key: String,
     pub timestamp: u64,
+    /// Ask for `/payload` responses encrypted with the session key
+    #[serde(rename = "encryptResponses", default)]
+    pub encrypt_responses: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -31,6 +165,9 @@ pub struct AuthResponse {
     pub message: Option<String>,
     #[serde(rename = "expiresAt")]
     pub expires_at: u64,
+    /// Whether the gateway agreed to encrypt `/payload` responses
+    #[serde(rename = "encryptedResponses", default)]
+    pub encrypted_responses: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -47,6 +184,102 @@ pub struct PayloadResponse {
     pub status: String,
     pub message: Option<String>,
     pub data: Option<serde_json::Value>,
+    /// Response encrypted with the session key (hex), replacing `data`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted: Option<String>,
+    /// AES-GCM nonce of `encrypted` (hex)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    /// Gateway's signature over the request nonce, `nonce` and `encrypted` (hex)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunkRequest {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub index: u64,
+    pub encrypted: String,
+    pub nonce: String,
+    pub signature: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompleteUploadRequest {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub chunks: u64,
+    pub size: u64,
+    #[serde(rename = "manifestHash")]
+    pub manifest_hash: String,
+    pub signature: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UploadResponse {
+    pub status: String,
+    pub message: Option<String>,
+    #[serde(rename = "uploadId", default)]
+    pub upload_id: Option<String>,
+}
+
+/// Gateway's confirmation of a completed upload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadReceipt {
+    pub status: String,
+    pub message: Option<String>,
+    #[serde(rename = "uploadId", default)]
+    pub upload_id: String,
+    #[serde(default)]
+    pub chunks: u64,
+    /// Plaintext bytes
+    #[serde(default)]
+    pub size: u64,
+    #[serde(rename = "manifestHash", default)]
+    pub manifest_hash: String,
+}
+
+/// Client's usage for the current day and what its quota leaves
+///
+/// Bytes count ciphertext, which is 16 bytes longer than the plaintext of
+/// each payload or upload chunk.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageReport {
+    pub client_id: String,
+    /// Bytes submitted through payloads and upload chunks
+    pub bytes_submitted: u64,
+    pub payloads: u64,
+    pub proofs_anchored: u64,
+    /// Daily quota; `None` if unlimited
+    pub quota_bytes: Option<u64>,
+    /// Bytes left today; `None` if unlimited
+    pub remaining_bytes: Option<u64>,
+    /// When the counters reset (milliseconds since epoch)
+    pub reset_at: u64,
+}
+
+impl UsageReport {
+    /// Whether `bytes` more ciphertext fit in today's quota
+    pub fn allows(&self, bytes: u64) -> bool {
+        self.remaining_bytes.is_none_or(|remaining| bytes <= remaining)
+    }
+}
+
+/// Request to replace the client's signing key
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RotateKeyRequest {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    #[serde(rename = "newPubKey")]
+    pub new_pub_key: String,
+    pub timestamp: u64,
+    /// Signature by the current key
+    pub signature: String,
+    /// Signature by the new key
+    #[serde(rename = "newSignature")]
+    pub new_signature: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -56,44 +289,475 @@ pub struct ProofStatus {
     pub block_time: Option<u64>,
     #[serde(rename = "txHash")]
     pub tx_hash: Option<String>,
+    /// Whether the proof was checked against the chain (never read from the gateway)
+    #[serde(skip_deserializing, default)]
+    pub verified: bool,
+}
+
+/// Error for a failed gateway response
+///
+/// Uses the `code` of the gateway's error body where there is one, and
+/// falls back to `HttpError` with `context` and the HTTP status.
+async fn gateway_error(response: Response, context: &str) -> H3DACError {
+    let status = response.status();
+    match response.bytes().await {
+        Ok(body) => error_from_body(status, &body, context),
+        Err(_) => H3DACError::HttpError(format!("{}: {}", context, status)),
+    }
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    code: Option<String>,
+    message: Option<String>,
 }
 
+fn error_from_body(status: reqwest::StatusCode, body: &[u8], context: &str) -> H3DACError {
+    let body: Option<ErrorBody> = serde_json::from_slice(body).ok();
+    let (code, message) = match body {
+        Some(ErrorBody {
+            code: Some(code),
+            message,
+        }) => (code, message.unwrap_or_else(|| context.to_string())),
+        _ if status == reqwest::StatusCode::PAYLOAD_TOO_LARGE => {
+            return H3DACError::PayloadTooLarge(context.to_string())
+        }
+        _ => return H3DACError::HttpError(format!("{}: {}", context, status)),
+    };
+
+    match code.as_str() {
+        "invalid_signature" => H3DACError::InvalidSignature(message),
+        "nonce_expired" => H3DACError::NonceExpired(message),
+        "quota_exceeded" => H3DACError::QuotaExceeded(message),
+        "payload_too_large" => H3DACError::PayloadTooLarge(message),
+        "no_session" | "invalid_session" => H3DACError::SessionExpired,
+        _ => H3DACError::GatewayError {
+            status: status.as_u16(),
+            code,
+            message,
+        },
+    }
+}
+
+/// Client for one gateway, or several replicas sharing session state
+///
+/// Requests go to the active gateway. Connection failures, timeouts, and
+/// streaks of server errors fail over to the next configured gateway.
 pub struct HttpClient {
     client: Client,
-    base_url: String,
+    gateways: Vec<String>,
+    active: AtomicUsize,
+    error_streak: AtomicU32,
+    failover_threshold: u32,
+    on_failover: Option<FailoverCallback>,
+    rate_limit: Mutex<RateLimitState>,
+    max_rate_limit_wait: Duration,
+    retry: RetryPolicy,
+}
+
+/// Builder for an [`HttpClient`] with custom transport settings
+///
+/// Settings left unset keep reqwest's defaults. Nothing is validated until
+/// [`build`](Self::build), which reports the first invalid setting.
+pub struct HttpClientBuilder {
+    gateways: Vec<String>,
+    failover_threshold: u32,
+    on_failover: Option<FailoverCallback>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    pool_idle_timeout: Option<Duration>,
+    pool_max_idle_per_host: Option<usize>,
+    proxy: Option<String>,
+    proxy_auth: Option<(String, String)>,
+    root_certificates: Vec<Vec<u8>>,
+    tls_built_in_roots: bool,
+    client_identity: Option<(Vec<u8>, Vec<u8>)>,
+    headers: Vec<(String, String)>,
+    max_rate_limit_wait: Duration,
+    retry: RetryPolicy,
+}
+
+impl HttpClientBuilder {
+    fn new(base_url: &str) -> Self {
+        Self {
+            gateways: vec![base_url.to_string()],
+            failover_threshold: DEFAULT_FAILOVER_THRESHOLD,
+            on_failover: None,
+            timeout: None,
+            connect_timeout: None,
+            pool_idle_timeout: None,
+            pool_max_idle_per_host: None,
+            proxy: None,
+            proxy_auth: None,
+            root_certificates: Vec::new(),
+            tls_built_in_roots: true,
+            client_identity: None,
+            headers: Vec::new(),
+            max_rate_limit_wait: DEFAULT_MAX_RATE_LIMIT_WAIT,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Timeout of a whole request, from connecting until the body is read
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Timeout of establishing a connection
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// How long idle pooled connections are kept open
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Maximum idle pooled connections per host (0 disables pooling)
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = Some(max);
+        self
+    }
+
+    /// Send all requests through a proxy, e.g. `http://proxy.corp:3128`
+    pub fn proxy(mut self, url: &str) -> Self {
+        self.proxy = Some(url.to_string());
+        self
+    }
+
+    /// Authenticate to the proxy with basic auth
+    pub fn proxy_auth(mut self, username: &str, password: &str) -> Self {
+        self.proxy_auth = Some((username.to_string(), password.to_string()));
+        self
+    }
+
+    /// Trust an additional root CA (PEM), e.g. a corporate TLS-inspection CA
+    pub fn add_root_certificate(mut self, pem: &[u8]) -> Self {
+        self.root_certificates.push(pem.to_vec());
+        self
+    }
+
+    /// Whether to trust the system's root CAs as well (default `true`)
+    ///
+    /// Disable to trust only the roots added with
+    /// [`add_root_certificate`](Self::add_root_certificate).
+    pub fn tls_built_in_root_certs(mut self, enabled: bool) -> Self {
+        self.tls_built_in_roots = enabled;
+        self
+    }
+
+    /// Present a client certificate for mutual TLS
+    ///
+    /// # Arguments
+    /// * `cert_pem` - Certificate chain (PEM)
+    /// * `key_pem` - PKCS#8 private key (PEM)
+    pub fn client_identity(mut self, cert_pem: &[u8], key_pem: &[u8]) -> Self {
+        self.client_identity = Some((cert_pem.to_vec(), key_pem.to_vec()));
+        self
+    }
+
+    /// Send a header with every request
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Add a gateway to fail over to, after those added before
+    ///
+    /// All gateways must share session state, e.g. replicas using the same
+    /// Redis, since a session may be used on any of them.
+    pub fn failover_gateway(mut self, url: &str) -> Self {
+        self.gateways.push(url.to_string());
+        self
+    }
+
+    /// Consecutive server errors (5xx) after which to fail over (default 3)
+    ///
+    /// Connection failures and timeouts fail over right away.
+    pub fn failover_threshold(mut self, threshold: u32) -> Self {
+        self.failover_threshold = threshold.max(1);
+        self
+    }
+
+    /// Report every change of the active gateway
+    pub fn on_failover(
+        mut self,
+        callback: impl Fn(&GatewaySwitch) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_failover = Some(Arc::new(callback));
+        self
+    }
+
+    /// Longest a request waits for the gateway's rate limit (default 30s)
+    ///
+    /// Requests are held back while the gateway's budget is exhausted; a
+    /// longer wait fails with `H3DACError::RateLimited` instead.
+    pub fn max_rate_limit_wait(mut self, wait: Duration) -> Self {
+        self.max_rate_limit_wait = wait;
+        self
+    }
+
+    /// Retry transient failures with this policy (default [`RetryPolicy::default`])
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    pub fn build(self) -> Result<HttpClient> {
+        let invalid = |setting: &str, e: &dyn std::fmt::Display| {
+            H3DACError::HttpError(format!("Invalid {}: {}", setting, e))
+        };
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| invalid("header name", &e))?;
+            let value = HeaderValue::from_str(value).map_err(|e| invalid("header value", &e))?;
+            headers.append(name, value);
+        }
+
+        let mut builder = Client::builder()
+            .default_headers(headers)
+            .tls_built_in_root_certs(self.tls_built_in_roots);
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(timeout);
+        }
+        if let Some(max) = self.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max);
+        }
+        if let Some(url) = &self.proxy {
+            let mut proxy = Proxy::all(url).map_err(|e| invalid("proxy", &e))?;
+            if let Some((username, password)) = &self.proxy_auth {
+                proxy = proxy.basic_auth(username, password);
+            }
+            builder = builder.proxy(proxy);
+        }
+        for pem in &self.root_certificates {
+            let certificate =
+                Certificate::from_pem(pem).map_err(|e| invalid("root certificate", &e))?;
+            builder = builder.add_root_certificate(certificate);
+        }
+        if let Some((cert_pem, key_pem)) = &self.client_identity {
+            let identity = Identity::from_pkcs8_pem(cert_pem, key_pem)
+                .map_err(|e| invalid("client identity", &e))?;
+            builder = builder.identity(identity);
+        }
+
+        Ok(HttpClient {
+            client: builder.build()?,
+            gateways: self.gateways,
+            active: AtomicUsize::new(0),
+            error_streak: AtomicU32::new(0),
+            failover_threshold: self.failover_threshold,
+            on_failover: self.on_failover,
+            rate_limit: Mutex::default(),
+            max_rate_limit_wait: self.max_rate_limit_wait,
+            retry: self.retry,
+        })
+    }
 }
 
 impl HttpClient {
     pub fn new(base_url: &str) -> Self {
         Self {
             client: Client::new(),
-            base_url: base_url.to_string(),
+            gateways: vec![base_url.to_string()],
+            active: AtomicUsize::new(0),
+            error_streak: AtomicU32::new(0),
+            failover_threshold: DEFAULT_FAILOVER_THRESHOLD,
+            on_failover: None,
+            rate_limit: Mutex::default(),
+            max_rate_limit_wait: DEFAULT_MAX_RATE_LIMIT_WAIT,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Configure a client for the gateway at `base_url`
+    pub fn builder(base_url: &str) -> HttpClientBuilder {
+        HttpClientBuilder::new(base_url)
+    }
+
+    /// Rate limit as last reported by the gateway
+    pub fn rate_limit(&self) -> RateLimit {
+        self.rate_limit.lock().unwrap().snapshot()
+    }
+
+    /// Hold a request back until the rate limit allows it
+    async fn wait_for_rate_limit(&self) -> Result<()> {
+        let wait = self.rate_limit.lock().unwrap().wait();
+        if wait > self.max_rate_limit_wait {
+            return Err(H3DACError::RateLimited { retry_after: wait });
+        }
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        Ok(())
+    }
+
+    /// Underlying transport, with the builder's timeouts, proxy and TLS settings
+    pub(crate) fn transport(&self) -> &Client {
+        &self.client
+    }
+
+    /// URL of the gateway requests currently go to
+    pub fn active_gateway(&self) -> &str {
+        &self.gateways[self.active.load(Ordering::SeqCst)]
+    }
+
+    /// Make `to` the active gateway unless another request already moved on from `from`
+    fn switch_gateway(&self, from: usize, to: usize, reason: &str) {
+        if from == to
+            || self
+                .active
+                .compare_exchange(from, to, Ordering::SeqCst, Ordering::SeqCst)
+                .is_err()
+        {
+            return;
+        }
+        self.error_streak.store(0, Ordering::SeqCst);
+        if let Some(callback) = &self.on_failover {
+            callback(&GatewaySwitch {
+                from: self.gateways[from].clone(),
+                to: self.gateways[to].clone(),
+                reason: reason.to_string(),
+            });
+        }
+    }
+
+    /// Check every gateway's `/health`
+    ///
+    /// Makes the first healthy gateway in configured order the active one,
+    /// which also fails back to a recovered primary.
+    pub async fn check_health(&self) -> Vec<GatewayHealth> {
+        let mut health = Vec::with_capacity(self.gateways.len());
+        for url in &self.gateways {
+            let response = self
+                .client
+                .get(format!("{}/health", url))
+                .timeout(HEALTH_CHECK_TIMEOUT)
+                .send()
+                .await;
+            health.push(GatewayHealth {
+                url: url.clone(),
+                healthy: response.is_ok_and(|r| r.status().is_success()),
+            });
+        }
+
+        if let Some(healthy) = health.iter().position(|h| h.healthy) {
+            let active = self.active.load(Ordering::SeqCst);
+            self.switch_gateway(active, healthy, "health check");
+        }
+        health
+    }
+
+    /// Send a request to the active gateway, retrying transient failures
+    ///
+    /// `request` is called with the full URL once per attempt. Failing over
+    /// to another gateway after a connection failure or timeout does not
+    /// use up a retry. Rate-limited requests wait for the limit and are
+    /// retried, failing with `RateLimited` once out of retries; a 429
+    /// carrying another error code fails with that error. A 409
+    /// `request_in_progress`, sent while an earlier attempt with the same
+    /// idempotency key is still being processed, is retried with backoff.
+    /// Any other final response is returned whatever its status.
+    async fn send(&self, path: &str, request: impl Fn(&str) -> RequestBuilder) -> Result<Response> {
+        let mut retry = 0;
+        let mut failovers = 0;
+        loop {
+            let active = self.active.load(Ordering::SeqCst);
+            let next = (active + 1) % self.gateways.len();
+            let url = format!("{}{}", self.gateways[active], path);
+
+            self.wait_for_rate_limit().await?;
+            let result = request(&url).send().await;
+            if let Ok(response) = &result {
+                self.rate_limit.lock().unwrap().update(response);
+            }
+
+            let transient = match result {
+                Ok(response) if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS => {
+                    // A 429 with another code, such as quota_exceeded, is
+                    // final and must not be waited out as a rate limit
+                    let status = response.status();
+                    let body = response.bytes().await?;
+                    let code = serde_json::from_slice::<ErrorBody>(&body).ok().and_then(|b| b.code);
+                    if code.as_deref().is_some_and(|code| code != "rate_limited") {
+                        return Err(error_from_body(status, &body, path));
+                    }
+                    if retry >= self.retry.max_retries {
+                        let retry_after = self.rate_limit.lock().unwrap().wait();
+                        return Err(H3DACError::RateLimited { retry_after });
+                    }
+                    retry += 1;
+                    continue;
+                }
+                Ok(response)
+                    if response.status() == reqwest::StatusCode::CONFLICT && retry < self.retry.max_retries =>
+                {
+                    let status = response.status();
+                    let body = response.bytes().await?;
+                    let code = serde_json::from_slice::<ErrorBody>(&body).ok().and_then(|b| b.code);
+                    if code.as_deref() != Some("request_in_progress") {
+                        return Err(error_from_body(status, &body, path));
+                    }
+                    tokio::time::sleep(self.retry.backoff(retry)).await;
+                    retry += 1;
+                    continue;
+                }
+                Ok(response) if response.status().is_server_error() => {
+                    let streak = self.error_streak.fetch_add(1, Ordering::SeqCst) + 1;
+                    if streak >= self.failover_threshold {
+                        let reason = format!("{} server errors in a row", streak);
+                        self.switch_gateway(active, next, &reason);
+                    }
+                    Ok(response)
+                }
+                Ok(response) => {
+                    self.error_streak.store(0, Ordering::SeqCst);
+                    return Ok(response);
+                }
+                Err(e) if e.is_timeout() || e.is_connect() => {
+                    if failovers + 1 < self.gateways.len() {
+                        failovers += 1;
+                        self.switch_gateway(active, next, &e.to_string());
+                        continue;
+                    }
+                    Err(e)
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if retry >= self.retry.max_retries {
+                return Ok(transient?);
+            }
+            tokio::time::sleep(self.retry.backoff(retry)).await;
+            retry += 1;
         }
     }
 
     pub async fn fetch_nonce(&self) -> Result<NonceResponse> {
-        let url = format!("{}/nonce", self.base_url);
-        let response = self.client.get(&url).send().await?;
+        let response = self.send("/nonce", |url| self.client.get(url)).await?;
 
         if !response.status().is_success() {
-            return Err(H3DACError::HttpError(format!(
-                "Failed to fetch nonce: {}",
-                response.status()
-            )));
+            return Err(gateway_error(response, "Failed to fetch nonce").await);
         }
 
         Ok(response.json().await?)
     }
 
     pub async fn authenticate(&self, auth_data: AuthRequest) -> Result<AuthResponse> {
-        let url = format!("{}/auth", self.base_url);
-        let response = self.client.post(&url).json(&auth_data).send().await?;
+        let response = self.send("/auth", |url| self.client.post(url).json(&auth_data)).await?;
 
         if !response.status().is_success() {
-            return Err(H3DACError::HttpError(format!(
-                "Authentication request failed: {}",
-                response.status()
-            )));
+            return Err(gateway_error(response, "Authentication request failed").await);
         }
 
         let auth_response: AuthResponse = response.json().await?;
@@ -109,15 +773,23 @@ impl HttpClient {
         Ok(auth_response)
     }
 
+    /// Submit a payload
+    ///
+    /// All attempts carry the same fresh idempotency key, so the gateway
+    /// applies the payload once however often it is retried.
     pub async fn send_payload(&self, payload_data: PayloadRequest) -> Result<PayloadResponse> {
-        let url = format!("{}/payload", self.base_url);
-        let response = self.client.post(&url).json(&payload_data).send().await?;
+        let idempotency_key = hex::encode(rand::random::<[u8; 16]>());
+        let response = self
+            .send("/payload", |url| {
+                self.client
+                    .post(url)
+                    .header(IDEMPOTENCY_KEY_HEADER, &idempotency_key)
+                    .json(&payload_data)
+            })
+            .await?;
 
         if !response.status().is_success() {
-            return Err(H3DACError::HttpError(format!(
-                "Payload request failed: {}",
-                response.status()
-            )));
+            return Err(gateway_error(response, "Payload request failed").await);
         }
 
         let payload_response: PayloadResponse = response.json().await?;
@@ -133,17 +805,101 @@ impl HttpClient {
         Ok(payload_response)
     }
 
+    /// Open a chunked upload, returning its ID
+    pub async fn start_upload(&self, client_id: &str) -> Result<String> {
+        let body = serde_json::json!({ "clientId": client_id });
+        let response = self.send("/upload", |url| self.client.post(url).json(&body)).await?;
+
+        if !response.status().is_success() {
+            return Err(gateway_error(response, "Failed to start upload").await);
+        }
+
+        let upload: UploadResponse = response.json().await?;
+        match upload.upload_id {
+            Some(upload_id) if upload.status == "success" => Ok(upload_id),
+            _ => Err(H3DACError::InvalidResponse(
+                upload
+                    .message
+                    .unwrap_or_else(|| "No upload ID".to_string()),
+            )),
+        }
+    }
+
+    /// Send one chunk; resending the same index is harmless
+    pub async fn upload_chunk(&self, upload_id: &str, chunk: ChunkRequest) -> Result<()> {
+        let path = format!("/upload/{}/chunk", upload_id);
+        let response = self.send(&path, |url| self.client.post(url).json(&chunk)).await?;
+
+        if !response.status().is_success() {
+            let context = format!("Chunk {} upload failed", chunk.index);
+            return Err(gateway_error(response, &context).await);
+        }
+
+        let ack: UploadResponse = response.json().await?;
+        if ack.status != "success" {
+            return Err(H3DACError::InvalidResponse(
+                ack.message.unwrap_or_else(|| "Unknown error".to_string()),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Finalize an upload with its signed manifest
+    pub async fn complete_upload(
+        &self,
+        upload_id: &str,
+        manifest: CompleteUploadRequest,
+    ) -> Result<UploadReceipt> {
+        let path = format!("/upload/{}/complete", upload_id);
+        let response = self.send(&path, |url| self.client.post(url).json(&manifest)).await?;
+
+        if !response.status().is_success() {
+            return Err(gateway_error(response, "Failed to complete upload").await);
+        }
+
+        let receipt: UploadReceipt = response.json().await?;
+        if receipt.status != "success" {
+            return Err(H3DACError::InvalidResponse(
+                receipt
+                    .message
+                    .unwrap_or_else(|| "Unknown error".to_string()),
+            ));
+        }
+
+        Ok(receipt)
+    }
+
+    /// Submit a signed key rotation
+    pub async fn rotate_key(&self, request: RotateKeyRequest) -> Result<()> {
+        let response = self.send("/rotate-key", |url| self.client.post(url).json(&request)).await?;
+
+        if !response.status().is_success() {
+            return Err(gateway_error(response, "Key rotation failed").await);
+        }
+
+        #[derive(Deserialize)]
+        struct RotateKeyResponse {
+            status: String,
+            message: Option<String>,
+        }
+
+        let ack: RotateKeyResponse = response.json().await?;
+        if ack.status != "success" {
+            return Err(H3DACError::InvalidResponse(
+                ack.message.unwrap_or_else(|| "Unknown error".to_string()),
+            ));
+        }
+
+        Ok(())
+    }
+
     pub async fn verify_session(&self, client_id: &str, session_key: &str) -> Result<bool> {
-        let url = format!("{}/verify-session", self.base_url);
-        let response = self
-            .client
-            .post(&url)
-            .json(&serde_json::json!({
-                "clientId": client_id,
-                "sessionKey": session_key
-            }))
-            .send()
-            .await?;
+        let body = serde_json::json!({
+            "clientId": client_id,
+            "sessionKey": session_key
+        });
+        let response = self.send("/verify-session", |url| self.client.post(url).json(&body)).await?;
 
         if !response.status().is_success() {
             return Ok(false);
@@ -158,17 +914,395 @@ impl HttpClient {
         Ok(verify_response.valid)
     }
 
+    pub async fn subscribe_events(
+        &self,
+        client_id: &str,
+        session_key: &str,
+    ) -> Result<EventStream> {
+        let response = self
+            .send("/events", |url| {
+                self.client
+                    .get(url)
+                    .header("X-Client-Id", client_id)
+                    .header(reqwest::header::ACCEPT, "text/event-stream")
+                    .bearer_auth(session_key)
+                    .timeout(EVENT_STREAM_TIMEOUT)
+            })
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(gateway_error(response, "Failed to subscribe to events").await);
+        }
+
+        Ok(EventStream::new(response))
+    }
+
+    /// Today's usage and remaining quota of an authenticated client
+    pub async fn get_usage(&self, client_id: &str, session_key: &str) -> Result<UsageReport> {
+        let response = self
+            .send("/usage", |url| {
+                self.client
+                    .get(url)
+                    .header("X-Client-Id", client_id)
+                    .bearer_auth(session_key)
+            })
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(gateway_error(response, "Failed to get usage").await);
+        }
+
+        Ok(response.json().await?)
+    }
+
     pub async fn get_proof_status(&self, client_id: &str) -> Result<ProofStatus> {
-        let url = format!("{}/proof/{}", self.base_url, client_id);
-        let response = self.client.get(&url).send().await?;
+        let path = format!("/proof/{}", client_id);
+        let response = self.send(&path, |url| self.client.get(url)).await?;
 
         if !response.status().is_success() {
-            return Err(H3DACError::HttpError(format!(
-                "Failed to get proof status: {}",
-                response.status()
-            )));
+            return Err(gateway_error(response, "Failed to get proof status").await);
         }
 
         Ok(response.json().await?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::GatewayEvent;
+    use crate::mock::{mock_gateway, serve_on, MockResponse};
+    use tokio::net::TcpListener;
+
+    /// Gateway answering one request per connection with the next of
+    /// `responses` (status, body) after `delay`, returning the requests
+    async fn serve(
+        responses: Vec<(u16, &'static str)>,
+        delay: Duration,
+    ) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let responses = responses.into_iter().map(|(s, b)| (s, "", b)).collect();
+        serve_with_headers(responses, delay).await
+    }
+
+    /// Like [`serve`], with extra header lines (`name: value\r\n`) per response
+    async fn serve_with_headers(
+        responses: Vec<(u16, &'static str, &'static str)>,
+        delay: Duration,
+    ) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let count = responses.len();
+        let mut responses = responses.into_iter();
+        mock_gateway(count, move |_| {
+            let (status, headers, body) = responses.next().unwrap();
+            MockResponse {
+                headers: headers.to_string(),
+                delay,
+                ..MockResponse::new(status, body)
+            }
+        })
+        .await
+    }
+
+    const NONCE: &str = r#"{"nonce":"00","serverPubKey":"02","expiresAt":1}"#;
+
+    fn fast_retry(max_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+        }
+    }
+
+    fn idempotency_key(request: &str) -> &str {
+        let start = request.find("idempotency-key: ").unwrap() + "idempotency-key: ".len();
+        request[start..].lines().next().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_builder() {
+        let (url, requests) = serve(vec![(200, NONCE)], Duration::ZERO).await;
+        let client = HttpClient::builder(&url)
+            .header("X-Api-Key", "secret")
+            .timeout(Duration::from_secs(5))
+            .connect_timeout(Duration::from_secs(1))
+            .pool_max_idle_per_host(0)
+            .build()
+            .unwrap();
+        assert_eq!(client.fetch_nonce().await.unwrap().expires_at, 1);
+        assert!(requests.await.unwrap()[0].contains("x-api-key: secret"));
+
+        // Requests outliving the timeout fail
+        let (url, _requests) = serve(vec![(200, NONCE)], Duration::from_secs(5)).await;
+        let client = HttpClient::builder(&url)
+            .timeout(Duration::from_millis(100))
+            .retry(RetryPolicy::none())
+            .build()
+            .unwrap();
+        assert!(matches!(client.fetch_nonce().await, Err(H3DACError::HttpError(_))));
+
+        // Invalid settings are reported on build
+        let invalid =
+            |builder: HttpClientBuilder| matches!(builder.build(), Err(H3DACError::HttpError(_)));
+        assert!(invalid(HttpClient::builder(&url).header("bad header", "x")));
+        assert!(invalid(HttpClient::builder(&url).header("X-Key", "line\nbreak")));
+        assert!(invalid(HttpClient::builder(&url).proxy("not a url")));
+        assert!(invalid(HttpClient::builder(&url).add_root_certificate(b"not a certificate")));
+        assert!(invalid(HttpClient::builder(&url).client_identity(b"cert", b"key")));
+        assert!(HttpClient::builder(&url)
+            .proxy("http://proxy.corp:3128")
+            .proxy_auth("user", "pass")
+            .build()
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_get_usage() {
+        let usage = r#"{"clientId":"test","bytesSubmitted":900,"payloads":3,"proofsAnchored":1,
+            "quotaBytes":1000,"remainingBytes":100,"resetAt":86400000}"#;
+        let unlimited = r#"{"clientId":"test","bytesSubmitted":900,"payloads":3,"proofsAnchored":1,
+            "quotaBytes":null,"remainingBytes":null,"resetAt":86400000}"#;
+        let (url, requests) = serve(vec![(200, usage), (200, unlimited)], Duration::ZERO).await;
+        let client = HttpClient::new(&url);
+
+        let report = client.get_usage("test", "abcd").await.unwrap();
+        assert_eq!(
+            (report.bytes_submitted, report.payloads, report.proofs_anchored),
+            (900, 3, 1)
+        );
+        assert!(report.allows(100));
+        assert!(!report.allows(101));
+        assert!(client.get_usage("test", "abcd").await.unwrap().allows(u64::MAX));
+
+        let request = &requests.await.unwrap()[0];
+        assert!(request.starts_with("get /usage"));
+        assert!(request.contains("x-client-id: test"));
+        assert!(request.contains("authorization: bearer abcd"));
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_events() {
+        let stream = ": connected\n\nevent: proof_confirmed\n\
+            data: {\"type\":\"proof_confirmed\",\"blockTime\":5}\n\n\
+            event: session_expired\ndata: {\"type\":\"session_expired\"}\n\n";
+        let (url, requests) = serve(vec![(200, stream)], Duration::ZERO).await;
+        let client = HttpClient::new(&url);
+        let mut events = client.subscribe_events("test", "abcd").await.unwrap();
+        assert_eq!(
+            events.next().await.unwrap().unwrap(),
+            GatewayEvent::ProofConfirmed {
+                block_time: 5,
+                tx_hash: None
+            }
+        );
+        assert_eq!(events.next().await.unwrap().unwrap(), GatewayEvent::SessionExpired);
+        assert!(events.next().await.is_none());
+        let request = &requests.await.unwrap()[0];
+        assert!(request.starts_with("get /events"));
+        assert!(request.contains("x-client-id: test"));
+        assert!(request.contains("authorization: bearer abcd"));
+
+        let (url, _requests) = serve(vec![(401, "{}")], Duration::ZERO).await;
+        let client = HttpClient::new(&url);
+        assert!(client.subscribe_events("test", "abcd").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_gateway_errors() {
+        let responses = vec![
+            (401, r#"{"status":"failed","code":"invalid_signature","message":"Invalid signature"}"#),
+            (401, r#"{"status":"failed","code":"nonce_expired","message":"Invalid or expired nonce"}"#),
+            (401, r#"{"status":"failed","code":"no_session","message":"No active session"}"#),
+            (429, r#"{"status":"failed","code":"quota_exceeded","message":"Daily quota used"}"#),
+            (400, r#"{"status":"failed","code":"missing_fields","message":"Missing required fields"}"#),
+            (413, "<html>too large</html>"),
+            (404, "not found"),
+        ];
+        let (url, _requests) = serve(responses, Duration::ZERO).await;
+        let client = HttpClient::builder(&url)
+            .retry(RetryPolicy::none())
+            .build()
+            .unwrap();
+
+        let mut errors = Vec::new();
+        for _ in 0..7 {
+            errors.push(client.fetch_nonce().await.unwrap_err());
+        }
+        assert!(matches!(&errors[0], H3DACError::InvalidSignature(m) if m == "Invalid signature"));
+        assert!(matches!(errors[1], H3DACError::NonceExpired(_)));
+        assert!(matches!(errors[2], H3DACError::SessionExpired));
+        assert!(matches!(errors[3], H3DACError::QuotaExceeded(_)));
+        assert!(matches!(
+            &errors[4],
+            H3DACError::GatewayError { status: 400, code, .. } if code == "missing_fields"
+        ));
+        assert!(matches!(errors[5], H3DACError::PayloadTooLarge(_)));
+        assert!(matches!(errors[6], H3DACError::HttpError(_)));
+        let codes: Vec<&str> = errors.iter().map(H3DACError::code).collect();
+        assert_eq!(
+            codes,
+            vec![
+                "invalid_signature",
+                "nonce_expired",
+                "session_expired",
+                "quota_exceeded",
+                "missing_fields",
+                "payload_too_large",
+                "http_error"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let limited = r#"{"status":"failed","code":"rate_limited","retryAfter":1}"#;
+        let responses = vec![
+            (200, "ratelimit-limit: 2\r\nratelimit-remaining: 1\r\nratelimit-reset: 60\r\n", NONCE),
+            (429, "retry-after: 1\r\nratelimit-remaining: 0\r\nratelimit-reset: 1\r\n", limited),
+            (200, "ratelimit-limit: 2\r\nratelimit-remaining: 0\r\nratelimit-reset: 60\r\n", NONCE),
+        ];
+        let (url, requests) = serve_with_headers(responses, Duration::ZERO).await;
+        let client = HttpClient::builder(&url).retry(fast_retry(1)).build().unwrap();
+        assert_eq!(client.rate_limit(), RateLimit::default());
+
+        client.fetch_nonce().await.unwrap();
+        let limit = client.rate_limit();
+        assert_eq!((limit.limit, limit.remaining), (Some(2), Some(1)));
+        assert!(limit.reset_in.unwrap() > Duration::from_secs(55));
+
+        // A 429 holds requests back for Retry-After, then retries
+        let started = Instant::now();
+        client.fetch_nonce().await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(900));
+        assert_eq!(requests.await.unwrap().len(), 3);
+
+        // With the budget used up for longer than the client waits, requests fail fast
+        assert_eq!(client.rate_limit().remaining, Some(0));
+        match client.fetch_nonce().await {
+            Err(H3DACError::RateLimited { retry_after }) => {
+                assert!(retry_after > Duration::from_secs(55))
+            }
+            other => panic!("expected RateLimited, got {:?}", other.map(|_| ())),
+        }
+
+        // Out of retries, a 429 is a RateLimited error
+        let responses = vec![(429, "retry-after: 120\r\n", limited)];
+        let (url, _requests) = serve_with_headers(responses, Duration::ZERO).await;
+        let client = HttpClient::builder(&url).retry(RetryPolicy::none()).build().unwrap();
+        let error = client.fetch_nonce().await.unwrap_err();
+        assert_eq!(error.code(), "rate_limited");
+        assert!(client.rate_limit().retry_after.unwrap() > Duration::from_secs(110));
+
+        // A 429 for a used-up quota fails at once instead of being retried
+        let quota = r#"{"status":"failed","code":"quota_exceeded","message":"Daily quota used"}"#;
+        let (url, requests) = serve_with_headers(vec![(429, "", quota)], Duration::ZERO).await;
+        let client = HttpClient::builder(&url).retry(fast_retry(3)).build().unwrap();
+        let error = client.fetch_nonce().await.unwrap_err();
+        assert!(matches!(error, H3DACError::QuotaExceeded(m) if m == "Daily quota used"));
+        assert_eq!(requests.await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failover() {
+        use std::sync::Mutex;
+
+        // A port nothing listens on
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let down = format!("http://{}", closed.local_addr().unwrap());
+        drop(closed);
+
+        let switches: Arc<Mutex<Vec<GatewaySwitch>>> = Arc::default();
+        let log = switches.clone();
+        let (backup, _requests) = serve(vec![(200, NONCE); 4], Duration::ZERO).await;
+        let client = HttpClient::builder(&down)
+            .failover_gateway(&backup)
+            .retry(RetryPolicy::none())
+            .on_failover(move |switch| log.lock().unwrap().push(switch.clone()))
+            .build()
+            .unwrap();
+
+        // Connection failures fail over right away, without using up retries
+        assert_eq!(client.fetch_nonce().await.unwrap().expires_at, 1);
+        assert_eq!(client.active_gateway(), backup);
+        assert_eq!(switches.lock().unwrap()[0].from, down);
+        assert_eq!(switches.lock().unwrap()[0].to, backup);
+
+        // Health checks fail back to the primary once it recovers
+        let health = client.check_health().await;
+        assert_eq!(health.iter().map(|h| h.healthy).collect::<Vec<_>>(), vec![false, true]);
+        assert_eq!(client.active_gateway(), backup);
+        let primary = TcpListener::bind(down.trim_start_matches("http://")).await.unwrap();
+        serve_on(primary, 1, |_| MockResponse::new(200, "{}"));
+        client.check_health().await;
+        assert_eq!(client.active_gateway(), down);
+        assert_eq!(switches.lock().unwrap()[1].reason, "health check");
+
+        // Streaks of server errors fail over too
+        let (flaky, _requests) = serve(vec![(500, "{}"), (503, "{}")], Duration::ZERO).await;
+        let (backup, _requests) = serve(vec![(200, NONCE)], Duration::ZERO).await;
+        let client = HttpClient::builder(&flaky)
+            .failover_gateway(&backup)
+            .failover_threshold(2)
+            .retry(fast_retry(2))
+            .build()
+            .unwrap();
+        assert_eq!(client.fetch_nonce().await.unwrap().expires_at, 1);
+        assert_eq!(client.active_gateway(), backup);
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let payload = || PayloadRequest {
+            client_id: "test".to_string(),
+            encrypted: "00".to_string(),
+            nonce: "00".to_string(),
+            signature: "00".to_string(),
+        };
+        let accepted = r#"{"status":"success","message":null,"data":{"processed":true}}"#;
+
+        // Server errors are retried with the same idempotency key
+        let responses = vec![(503, "{}"), (502, "{}"), (200, accepted)];
+        let (url, requests) = serve(responses, Duration::ZERO).await;
+        let client = HttpClient::builder(&url).retry(fast_retry(3)).build().unwrap();
+        let response = client.send_payload(payload()).await.unwrap();
+        assert_eq!(response.data.unwrap()["processed"], true);
+        let requests = requests.await.unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(requests.iter().all(|r| idempotency_key(r) == idempotency_key(&requests[0])));
+
+        // A retry racing the first attempt waits for it to complete
+        let in_progress = r#"{"status":"failed","code":"request_in_progress"}"#;
+        let (url, requests) = serve(vec![(409, in_progress), (200, accepted)], Duration::ZERO).await;
+        let client = HttpClient::builder(&url).retry(fast_retry(3)).build().unwrap();
+        client.send_payload(payload()).await.unwrap();
+        let requests = requests.await.unwrap();
+        assert_eq!(idempotency_key(&requests[0]), idempotency_key(&requests[1]));
+
+        // Every submission gets its own key
+        let (url, requests) = serve(vec![(200, accepted), (200, accepted)], Duration::ZERO).await;
+        let client = HttpClient::builder(&url).retry(fast_retry(3)).build().unwrap();
+        client.send_payload(payload()).await.unwrap();
+        client.send_payload(payload()).await.unwrap();
+        let requests = requests.await.unwrap();
+        assert_ne!(idempotency_key(&requests[0]), idempotency_key(&requests[1]));
+
+        // Retries give up after the policy's limit, client errors are final
+        let (url, requests) = serve(vec![(500, "{}"), (500, "{}")], Duration::ZERO).await;
+        let client = HttpClient::builder(&url).retry(fast_retry(1)).build().unwrap();
+        assert!(matches!(client.fetch_nonce().await, Err(H3DACError::HttpError(_))));
+        assert_eq!(requests.await.unwrap().len(), 2);
+        let (url, requests) = serve(vec![(400, "{}")], Duration::ZERO).await;
+        let client = HttpClient::builder(&url).retry(fast_retry(3)).build().unwrap();
+        assert!(client.fetch_nonce().await.is_err());
+        assert_eq!(requests.await.unwrap().len(), 1);
+
+        // Timeouts are retried too
+        let slow = Duration::from_millis(300);
+        let (url, requests) = serve(vec![(200, NONCE), (200, NONCE)], slow).await;
+        let client = HttpClient::builder(&url)
+            .timeout(Duration::from_millis(100))
+            .retry(fast_retry(1))
+            .build()
+            .unwrap();
+        assert!(client.fetch_nonce().await.is_err());
+        assert_eq!(requests.await.unwrap().len(), 2);
+    }
+}
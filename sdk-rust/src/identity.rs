@@ -0,0 +1,86 @@
+//! One identity shared with the Opacus SDK
+//!
+//! A single 32-byte seed yields the secp256k1 key used with the gateway and
+//! the Ed25519 and X25519 keys an Opacus agent uses on the QUIC relay,
+//! derived exactly as by the Opacus SDK's `UnifiedIdentity::from_seed`. The
+//! agent ID serves as the gateway client ID, so an agent has the same name
+//! on both.
+
+use ed25519_dalek::SigningKey;
+use hkdf::Hkdf;
+use secp256k1::SecretKey;
+use sha2::{Digest, Sha256};
+
+use crate::error::{H3DACError, Result};
+
+/// HKDF salt shared with Opacus seed-based identities
+const SEED_SALT: &[u8] = b"opacus-identity-seed-v1";
+
+/// Keys of an agent derived from one seed
+#[derive(Clone)]
+pub struct Identity {
+    secp256k1: SecretKey,
+    ed25519: [u8; 32],
+    x25519: [u8; 32],
+}
+
+impl Identity {
+    /// Derive all keys from a seed
+    pub fn from_seed(seed: &[u8; 32]) -> Result<Self> {
+        let hk = Hkdf::<Sha256>::new(Some(SEED_SALT), seed);
+        let expand = |info: &[u8]| {
+            let mut key = [0u8; 32];
+            hk.expand(info, &mut key)
+                .map_err(|e| H3DACError::CryptoError(format!("HKDF expand failed: {}", e)))?;
+            Ok::<_, H3DACError>(key)
+        };
+
+        let secp256k1 = SecretKey::from_slice(&expand(b"opacus-secp256k1")?)
+            .map_err(|e| H3DACError::CryptoError(format!("Invalid derived key: {}", e)))?;
+        Ok(Self {
+            secp256k1,
+            ed25519: expand(b"opacus-ed25519")?,
+            x25519: expand(b"opacus-x25519")?,
+        })
+    }
+
+    /// Agent ID on the Opacus relay, used as the gateway client ID
+    pub fn client_id(&self) -> String {
+        let ed_pub = SigningKey::from_bytes(&self.ed25519).verifying_key();
+        hex::encode(&Sha256::digest(ed_pub.as_bytes())[..20])
+    }
+
+    /// secp256k1 key the gateway authenticates
+    pub fn secret_key(&self) -> SecretKey {
+        self.secp256k1
+    }
+
+    /// Ed25519 and X25519 private keys, as taken by `OpacusClient::init_from_keys`
+    pub fn opacus_keys(&self) -> ([u8; 32], [u8; 32]) {
+        (self.ed25519, self.x25519)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_from_seed() {
+        let identity = Identity::from_seed(&[42u8; 32]).unwrap();
+        // Shared with the Opacus SDK's `UnifiedIdentity::from_seed` test
+        assert_eq!(
+            identity.client_id(),
+            "69894affe8fde65d15885b8d65c5ebd2596b1495"
+        );
+        assert_eq!(
+            hex::encode(identity.secret_key().secret_bytes()),
+            "1a55189b9ee02dff2024db2524e1bcd46264b0ff8e1a1c723749b02426fd9471"
+        );
+
+        let (ed25519, x25519) = identity.opacus_keys();
+        assert_ne!(ed25519, x25519);
+        let other = Identity::from_seed(&[43u8; 32]).unwrap();
+        assert_ne!(other.client_id(), identity.client_id());
+    }
+}
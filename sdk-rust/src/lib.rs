@@ -1,29 +1,66 @@
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod clock;
 pub mod crypto;
 pub mod error;
+pub mod events;
 pub mod http;
+pub mod identity;
+pub mod retry;
+pub mod rpc;
+pub mod session;
+pub mod upload;
+
+#[cfg(test)]
+mod mock;
 
 use secp256k1::{PublicKey, SecretKey};
-use std::time::{SystemTime, UNIX_EPOCH};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
 
+use crate::clock::{Clock, SystemClock};
 use crate::crypto::{
     decrypt_payload, derive_session_key, derive_shared_secret, encrypt_payload, get_public_key,
-    sign_message,
+    hash_data, sign_message, verify_signature,
 };
 use crate::error::{H3DACError, Result};
-use crate::http::{AuthRequest, HttpClient, PayloadRequest, ProofStatus};
+use crate::events::EventStream;
+use crate::http::{
+    AuthRequest, ChunkRequest, CompleteUploadRequest, GatewayHealth, HttpClient, PayloadRequest,
+    PayloadResponse, ProofStatus, RateLimit, RotateKeyRequest, UploadReceipt, UsageReport,
+};
+use crate::identity::Identity;
+use crate::rpc::{records_hash, RpcClient};
+use crate::session::ExportedSession;
+use crate::upload::{chunk_message, manifest_hash, DEFAULT_CHUNK_SIZE};
 
 #[derive(Debug, Clone)]
 pub struct AuthSession {
     pub session_key: Vec<u8>,
     pub client_id: String,
     pub expires_at: u64,
+    /// Gateway key the session was derived with, which signs encrypted responses
+    pub gateway_pub_key: PublicKey,
+    /// Whether the gateway encrypts responses in this session; plaintext
+    /// responses are then rejected
+    pub encrypted_responses: bool,
 }
 
+/// Default tolerated clock skew against the gateway (milliseconds)
+pub const DEFAULT_CLOCK_SKEW_MS: u64 = 5_000;
+
 pub struct H3DACClient {
     private_key: SecretKey,
     public_key: PublicKey,
     http_client: HttpClient,
     session: Option<AuthSession>,
+    clock: Arc<dyn Clock>,
+    clock_skew_ms: u64,
+    rpc: Option<RpcClient>,
+    require_encrypted_responses: bool,
 }
 
 impl H3DACClient {
@@ -37,9 +74,79 @@ impl H3DACClient {
             public_key,
             http_client,
             session: None,
+            clock: Arc::new(SystemClock),
+            clock_skew_ms: DEFAULT_CLOCK_SKEW_MS,
+            rpc: None,
+            require_encrypted_responses: false,
         }
     }
 
+    /// Use a custom time source for timestamps and session expiry
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Set tolerated clock skew against the gateway
+    ///
+    /// A session is treated as expired only once the local clock is more
+    /// than `clock_skew_ms` past its expiry; the gateway stays authoritative.
+    pub fn with_clock_skew(mut self, clock_skew_ms: u64) -> Self {
+        self.clock_skew_ms = clock_skew_ms;
+        self
+    }
+
+    /// Only accept sessions whose responses the gateway encrypts
+    ///
+    /// Every session asks for encrypted responses, and once the gateway
+    /// agrees plaintext responses are rejected. With this set, `authenticate`
+    /// also fails with `AuthError` against a gateway that does not agree.
+    pub fn with_required_response_encryption(mut self) -> Self {
+        self.require_encrypted_responses = true;
+        self
+    }
+
+    /// Talk to the gateway through a custom-built HTTP client
+    ///
+    /// Use [`HttpClient::builder`] for timeouts, proxies, custom CAs, mutual
+    /// TLS or extra headers; its base URL replaces the one given to `new`.
+    /// RPC requests (`with_rpc`) use its transport too.
+    pub fn with_http_client(mut self, http_client: HttpClient) -> Self {
+        self.rpc = self.rpc.map(|rpc| rpc.with_client(http_client.transport().clone()));
+        self.http_client = http_client;
+        self
+    }
+
+    /// URL of the gateway requests currently go to
+    pub fn active_gateway(&self) -> &str {
+        self.http_client.active_gateway()
+    }
+
+    /// Gateway rate limit as last reported, to throttle before being rejected
+    pub fn rate_limit(&self) -> RateLimit {
+        self.http_client.rate_limit()
+    }
+
+    /// Health-check the configured gateways, failing over if needed
+    pub async fn check_gateways(&self) -> Vec<GatewayHealth> {
+        self.http_client.check_health().await
+    }
+
+    /// Verify proofs against the chain through a JSON-RPC endpoint
+    ///
+    /// `get_proof_status` then checks the gateway's transaction itself
+    /// instead of trusting the gateway's answer: it must record the
+    /// session hash at `contract`, the gateway's proof contract. Requests
+    /// use the transport of the gateway HTTP client.
+    pub fn with_rpc(mut self, rpc_url: &str, contract: &str) -> Self {
+        self.rpc = Some(RpcClient::new(self.http_client.transport().clone(), rpc_url, contract));
+        self
+    }
+
+    fn is_expired(&self, session: &AuthSession) -> bool {
+        self.clock.now_ms() > session.expires_at.saturating_add(self.clock_skew_ms)
+    }
+
     /// Create a client from a hex-encoded private key
     pub fn from_hex(private_key_hex: &str, gateway_url: Option<&str>) -> Result<Self> {
         let private_key_bytes = hex::decode(private_key_hex)
@@ -51,6 +158,14 @@ impl H3DACClient {
         Ok(Self::new(private_key, gateway_url))
     }
 
+    /// Create a client with the secp256k1 key of a seed-derived identity
+    ///
+    /// Authenticate with [`Identity::client_id`] to use the same name as
+    /// the agent on the Opacus relay.
+    pub fn from_identity(identity: &Identity, gateway_url: Option<&str>) -> Self {
+        Self::new(identity.secret_key(), gateway_url)
+    }
+
     /// Get the client's public key as hex string
     pub fn get_public_key_hex(&self) -> String {
         hex::encode(self.public_key.serialize())
@@ -67,10 +182,7 @@ impl H3DACClient {
         let nonce_response = self.http_client.fetch_nonce().await?;
 
         // Step 2: Create signature
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64;
+        let timestamp = self.clock.now_ms();
 
         let mut message = Vec::new();
         message.extend_from_slice(&hex::decode(&nonce_response.nonce).unwrap());
@@ -98,14 +210,22 @@ impl H3DACClient {
                 nonce: nonce_response.nonce,
                 client_pub_key: self.get_public_key_hex(),
                 timestamp,
+                encrypt_responses: true,
             })
             .await?;
+        if self.require_encrypted_responses && !auth_response.encrypted_responses {
+            return Err(H3DACError::AuthError(
+                "Gateway does not encrypt responses".to_string(),
+            ));
+        }
 
         // Store session
         let session = AuthSession {
             session_key,
             client_id: client_id.to_string(),
             expires_at: auth_response.expires_at,
+            gateway_pub_key: server_pub_key,
+            encrypted_responses: auth_response.encrypted_responses,
         };
 
         self.session = Some(session.clone());
@@ -113,26 +233,63 @@ impl H3DACClient {
         Ok(session)
     }
 
+    /// Send an encrypted request to the gateway and decode its response
+    ///
+    /// The request is serialized to JSON; the response, decrypted if the
+    /// gateway encrypted it, is deserialized into `Resp`. On sessions with
+    /// encrypted responses a plaintext response fails with `InvalidResponse`.
+    ///
+    /// ```no_run
+    /// # async fn run(client: h3_dac_sdk::H3DACClient) -> h3_dac_sdk::error::Result<()> {
+    /// #[derive(serde::Serialize)]
+    /// struct Transfer { to: String, amount: u64 }
+    /// #[derive(serde::Deserialize)]
+    /// struct Receipt { processed: bool }
+    ///
+    /// let transfer = Transfer { to: "bob".into(), amount: 100 };
+    /// let receipt: Receipt = client.send(&transfer).await?;
+    /// # Ok(()) }
+    /// ```
+    pub async fn send<Req, Resp>(&self, request: &Req) -> Result<Resp>
+    where
+        Req: Serialize + ?Sized,
+        Resp: DeserializeOwned,
+    {
+        self.send_bytes(&serde_json::to_vec(request)?).await
+    }
+
+    /// Send an already serialized payload and return the untyped response
+    ///
+    /// Escape hatch for payloads built outside serde or responses whose shape
+    /// is not known in advance.
+    pub async fn send_raw(&self, payload: &[u8]) -> Result<serde_json::Value> {
+        self.send_bytes(payload).await
+    }
+
     /// Send encrypted payload to the gateway
+    #[deprecated(note = "use `send` for typed requests or `send_raw`")]
     pub async fn send_payload(&self, payload: serde_json::Value) -> Result<serde_json::Value> {
+        self.send(&payload).await
+    }
+
+    /// Encrypt, sign and submit a payload
+    ///
+    /// Encrypted responses are checked against the gateway's signature and
+    /// decrypted with the session key; plaintext responses are returned as is
+    /// unless the session encrypts responses.
+    async fn send_bytes<Resp: DeserializeOwned>(&self, payload_bytes: &[u8]) -> Result<Resp> {
         let session = self
             .session
             .as_ref()
             .ok_or(H3DACError::NotAuthenticated)?;
 
         // Check if session is expired
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64;
-
-        if now > session.expires_at {
+        if self.is_expired(session) {
             return Err(H3DACError::SessionExpired);
         }
 
         // Encrypt payload
-        let payload_bytes = serde_json::to_vec(&payload)?;
-        let (encrypted, nonce) = encrypt_payload(&payload_bytes, &session.session_key)?;
+        let (encrypted, nonce) = encrypt_payload(payload_bytes, &session.session_key)?;
 
         // Create signature for the encrypted payload
         let signature = sign_message(&encrypted, &self.private_key)?;
@@ -148,7 +305,126 @@ impl H3DACClient {
             })
             .await?;
 
-        Ok(response.data.unwrap_or(serde_json::Value::Null))
+        open_response(session, &nonce, response)
+    }
+
+    /// Upload a payload too large for one request in encrypted, signed chunks
+    ///
+    /// Reads `reader` to its end, holding one chunk in memory at a time, and
+    /// finalizes the upload with a signed manifest hash the gateway checks
+    /// against the chunks it received.
+    ///
+    /// # Arguments
+    /// * `reader` - Payload source
+    /// * `chunk_size` - Plaintext bytes per chunk (default [`DEFAULT_CHUNK_SIZE`])
+    pub async fn upload<R: AsyncRead + Unpin>(
+        &self,
+        mut reader: R,
+        chunk_size: Option<usize>,
+    ) -> Result<UploadReceipt> {
+        let session = self
+            .session
+            .as_ref()
+            .ok_or(H3DACError::NotAuthenticated)?;
+
+        if self.is_expired(session) {
+            return Err(H3DACError::SessionExpired);
+        }
+
+        let chunk_size = chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE).max(1) as u64;
+        let upload_id = self.http_client.start_upload(&session.client_id).await?;
+
+        let mut chunk_hashes = Vec::new();
+        let mut size = 0u64;
+        let mut chunk = Vec::new();
+        loop {
+            chunk.clear();
+            (&mut reader).take(chunk_size).read_to_end(&mut chunk).await?;
+            if chunk.is_empty() {
+                break;
+            }
+
+            let index = chunk_hashes.len() as u64;
+            let (encrypted, nonce) = encrypt_payload(&chunk, &session.session_key)?;
+            let signature = sign_message(
+                &chunk_message(&upload_id, index, &encrypted),
+                &self.private_key,
+            )?;
+            self.http_client
+                .upload_chunk(
+                    &upload_id,
+                    ChunkRequest {
+                        client_id: session.client_id.clone(),
+                        index,
+                        encrypted: hex::encode(&encrypted),
+                        nonce: hex::encode(&nonce),
+                        signature: hex::encode(&signature),
+                    },
+                )
+                .await?;
+
+            chunk_hashes.push(hash_data(&encrypted));
+            size += chunk.len() as u64;
+        }
+
+        let manifest = manifest_hash(&upload_id, size, &chunk_hashes);
+        let receipt = self
+            .http_client
+            .complete_upload(
+                &upload_id,
+                CompleteUploadRequest {
+                    client_id: session.client_id.clone(),
+                    chunks: chunk_hashes.len() as u64,
+                    size,
+                    manifest_hash: hex::encode(&manifest),
+                    signature: hex::encode(sign_message(&manifest, &self.private_key)?),
+                },
+            )
+            .await?;
+
+        if receipt.manifest_hash != hex::encode(&manifest) {
+            return Err(H3DACError::InvalidResponse(
+                "Gateway confirmed another manifest".to_string(),
+            ));
+        }
+
+        Ok(receipt)
+    }
+
+    /// Replace the key this client signs with
+    ///
+    /// The rotation is signed by both the current and the new key. The
+    /// client switches to `new_private_key` only once the gateway has
+    /// accepted it; on error it keeps signing with the current key. The
+    /// session key is unchanged, but sessions exported before the rotation
+    /// can only be restored with the old key.
+    pub async fn rotate_key(&mut self, new_private_key: SecretKey) -> Result<()> {
+        let session = self
+            .session
+            .as_ref()
+            .ok_or(H3DACError::NotAuthenticated)?;
+
+        if self.is_expired(session) {
+            return Err(H3DACError::SessionExpired);
+        }
+
+        let new_public_key = get_public_key(&new_private_key);
+        let timestamp = self.clock.now_ms();
+        let message = rotation_message(&session.client_id, &new_public_key, timestamp);
+
+        self.http_client
+            .rotate_key(RotateKeyRequest {
+                client_id: session.client_id.clone(),
+                new_pub_key: hex::encode(new_public_key.serialize()),
+                timestamp,
+                signature: hex::encode(sign_message(&message, &self.private_key)?),
+                new_signature: hex::encode(sign_message(&message, &new_private_key)?),
+            })
+            .await?;
+
+        self.private_key = new_private_key;
+        self.public_key = new_public_key;
+        Ok(())
     }
 
     /// Verify if the session is still valid
@@ -158,12 +434,7 @@ impl H3DACClient {
             .as_ref()
             .ok_or(H3DACError::NotAuthenticated)?;
 
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64;
-
-        if now > session.expires_at {
+        if self.is_expired(session) {
             return Ok(false);
         }
 
@@ -173,13 +444,161 @@ impl H3DACClient {
     }
 
     /// Get on-chain proof status
+    ///
+    /// With an RPC endpoint set (`with_rpc`), `verified` tells whether the
+    /// proof transaction was checked on chain.
     pub async fn get_proof_status(&self) -> Result<ProofStatus> {
         let session = self
             .session
             .as_ref()
             .ok_or(H3DACError::NotAuthenticated)?;
 
-        self.http_client.get_proof_status(&session.client_id).await
+        let mut status = self.http_client.get_proof_status(&session.client_id).await?;
+        if self.rpc.is_some() {
+            status.verified = self.verify_proof(&status).await?;
+        }
+        Ok(status)
+    }
+
+    /// Today's usage and remaining quota
+    ///
+    /// Check [`UsageReport::allows`] before large sends to stay under the
+    /// quota instead of being rejected with `QuotaExceeded`.
+    pub async fn usage(&self) -> Result<UsageReport> {
+        let session = self
+            .session
+            .as_ref()
+            .ok_or(H3DACError::NotAuthenticated)?;
+
+        self.http_client
+            .get_usage(&session.client_id, &hex::encode(&session.session_key))
+            .await
+    }
+
+    /// Wait until the session's proof exists
+    ///
+    /// Polls [`get_proof_status`](Self::get_proof_status) every `interval`,
+    /// and also right away whenever the gateway pushes an event, if it
+    /// offers an event stream. With an RPC configured, the proof must also
+    /// be verified on chain.
+    ///
+    /// # Returns
+    /// The proof status with its block time and transaction hash, or
+    /// `Err(H3DACError::Timeout)` once `timeout` elapsed without one
+    pub async fn wait_for_proof(
+        &self,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<ProofStatus> {
+        tokio::time::timeout(timeout, self.poll_proof(interval))
+            .await
+            .map_err(|_| H3DACError::Timeout(format!("No proof within {:?}", timeout)))?
+    }
+
+    async fn poll_proof(&self, interval: Duration) -> Result<ProofStatus> {
+        let mut events = self.subscribe_events().await.ok();
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        ticker.tick().await;
+
+        loop {
+            let status = self.get_proof_status().await?;
+            if status.exists && (self.rpc.is_none() || status.verified) {
+                return Ok(status);
+            }
+
+            // Fall back to plain polling once the stream ends
+            let ended = match events.as_mut() {
+                Some(stream) => tokio::select! {
+                    _ = ticker.tick() => false,
+                    event = stream.next() => !matches!(event, Some(Ok(_))),
+                },
+                None => {
+                    ticker.tick().await;
+                    false
+                }
+            };
+            if ended {
+                events = None;
+            }
+        }
+    }
+
+    /// Check a proof against the chain
+    ///
+    /// Fetches the proof transaction and its receipt from the RPC endpoint
+    /// and checks that it succeeded and records the hash of this session's
+    /// key.
+    ///
+    /// # Returns
+    /// `false` if the proof has no transaction yet, the transaction is
+    /// unknown or pending, or it does not record the session hash
+    pub async fn verify_proof(&self, status: &ProofStatus) -> Result<bool> {
+        let session = self
+            .session
+            .as_ref()
+            .ok_or(H3DACError::NotAuthenticated)?;
+        let rpc = self
+            .rpc
+            .as_ref()
+            .ok_or_else(|| H3DACError::RpcError("No RPC endpoint configured".to_string()))?;
+
+        let Some(tx_hash) = status.tx_hash.as_deref().filter(|_| status.exists) else {
+            return Ok(false);
+        };
+        let Some(tx) = rpc.get_transaction(tx_hash).await? else {
+            return Ok(false);
+        };
+        let Some(receipt) = rpc.get_transaction_receipt(tx_hash).await? else {
+            return Ok(false);
+        };
+
+        Ok(records_hash(&tx, &receipt, &hash_data(&session.session_key), rpc.contract()))
+    }
+
+    /// Subscribe to events the gateway pushes for the current session
+    ///
+    /// Proof confirmations and session notices arrive as they happen instead
+    /// of having to poll `get_proof_status` or `verify_session`.
+    pub async fn subscribe_events(&self) -> Result<EventStream> {
+        let session = self
+            .session
+            .as_ref()
+            .ok_or(H3DACError::NotAuthenticated)?;
+
+        if self.is_expired(session) {
+            return Err(H3DACError::SessionExpired);
+        }
+
+        self.http_client
+            .subscribe_events(&session.client_id, &hex::encode(&session.session_key))
+            .await
+    }
+
+    /// Export the current session for storage
+    ///
+    /// Lets short-lived processes such as CLI tools reuse a session with
+    /// [`restore_session`](Self::restore_session) instead of authenticating
+    /// on every run. The session key is encrypted under the client's key.
+    pub fn export_session(&self) -> Result<ExportedSession> {
+        let session = self
+            .session
+            .as_ref()
+            .ok_or(H3DACError::NotAuthenticated)?;
+
+        ExportedSession::seal(session, &self.private_key)
+    }
+
+    /// Restore a session exported by a client with the same key
+    pub fn restore_session(&mut self, exported: &ExportedSession) -> Result<AuthSession> {
+        let session = exported.open(&self.private_key)?;
+
+        if self.is_expired(&session) {
+            return Err(H3DACError::SessionExpired);
+        }
+
+        self.session = Some(session.clone());
+        Ok(session)
     }
 
     /// Clear current session
@@ -189,21 +608,80 @@ impl H3DACClient {
 
     /// Check if currently authenticated
     pub fn is_authenticated(&self) -> bool {
-        if let Some(session) = &self.session {
-            let now = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_millis() as u64;
-            now < session.expires_at
-        } else {
-            false
+        match &self.session {
+            Some(session) => !self.is_expired(session),
+            None => false,
         }
     }
 }
 
+/// Message both keys sign to rotate from one to the other
+fn rotation_message(client_id: &str, new_public_key: &PublicKey, timestamp: u64) -> Vec<u8> {
+    let mut message = b"H3DAC-rotate".to_vec();
+    message.extend_from_slice(client_id.as_bytes());
+    message.extend_from_slice(&new_public_key.serialize());
+    message.extend_from_slice(&timestamp.to_be_bytes());
+    message
+}
+
+/// Message the gateway signs for an encrypted response
+///
+/// Covers the nonce of the request answered, so a response cannot be
+/// replayed as the answer to another request.
+fn response_message(request_nonce: &[u8], nonce: &[u8], ciphertext: &[u8]) -> Vec<u8> {
+    let mut message = b"H3DAC-response".to_vec();
+    message.extend_from_slice(request_nonce);
+    message.extend_from_slice(nonce);
+    message.extend_from_slice(ciphertext);
+    message
+}
+
+/// Plaintext of the response to the request sent with `request_nonce`
+///
+/// An `encrypted` response must carry the gateway's signature over
+/// [`response_message`] and decrypt under the session key; its plaintext is
+/// JSON. Plaintext responses are refused if the session encrypts responses.
+fn open_response<T: DeserializeOwned>(
+    session: &AuthSession,
+    request_nonce: &[u8],
+    response: PayloadResponse,
+) -> Result<T> {
+    let Some(encrypted) = response.encrypted else {
+        if session.encrypted_responses {
+            return Err(H3DACError::InvalidResponse(
+                "Plaintext response in a session with encrypted responses".to_string(),
+            ));
+        }
+        return Ok(serde_json::from_value(
+            response.data.unwrap_or(serde_json::Value::Null),
+        )?);
+    };
+    let decode = |field: Option<String>, name: &str| {
+        let value = field.ok_or_else(|| {
+            H3DACError::InvalidResponse(format!("Encrypted response without {}", name))
+        })?;
+        hex::decode(value)
+            .map_err(|e| H3DACError::InvalidResponse(format!("Invalid {}: {}", name, e)))
+    };
+    let ciphertext = hex::decode(&encrypted)
+        .map_err(|e| H3DACError::InvalidResponse(format!("Invalid ciphertext: {}", e)))?;
+    let nonce = decode(response.nonce, "nonce")?;
+    let signature = decode(response.signature, "signature")?;
+
+    let message = response_message(request_nonce, &nonce, &ciphertext);
+    if !verify_signature(&message, &signature, &session.gateway_pub_key)? {
+        return Err(H3DACError::CryptoError(
+            "Invalid gateway signature on response".to_string(),
+        ));
+    }
+    let plaintext = decrypt_payload(&ciphertext, &session.session_key, &nonce)?;
+    Ok(serde_json::from_slice(&plaintext)?)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock::json_gateway;
     use crate::crypto::generate_private_key;
 
     #[test]
@@ -213,6 +691,428 @@ mod tests {
         assert!(!client.get_public_key_hex().is_empty());
     }
 
+    #[test]
+    fn test_session_expiry_with_skew() {
+        use crate::clock::ManualClock;
+
+        let clock = Arc::new(ManualClock::new(10_000));
+        let mut client = H3DACClient::new(generate_private_key(), None)
+            .with_clock(clock.clone())
+            .with_clock_skew(1_000);
+        client.session = Some(AuthSession {
+            session_key: vec![0u8; 32],
+            client_id: "test".to_string(),
+            expires_at: 10_000,
+            gateway_pub_key: get_public_key(&generate_private_key()),
+            encrypted_responses: false,
+        });
+
+        clock.advance(500);
+        assert!(client.is_authenticated());
+        clock.advance(1_000);
+        assert!(!client.is_authenticated());
+    }
+
+    #[tokio::test]
+    async fn test_verify_proof_without_transaction() {
+        let mut client = H3DACClient::new(generate_private_key(), None);
+        client.session = Some(AuthSession {
+            session_key: vec![0u8; 32],
+            client_id: "test".to_string(),
+            expires_at: u64::MAX,
+            gateway_pub_key: get_public_key(&generate_private_key()),
+            encrypted_responses: false,
+        });
+        let status = ProofStatus {
+            exists: true,
+            block_time: Some(1_000),
+            tx_hash: None,
+            verified: false,
+        };
+        assert!(matches!(
+            client.verify_proof(&status).await,
+            Err(H3DACError::RpcError(_))
+        ));
+
+        // No transaction to check yet; the RPC endpoint is never contacted
+        let client = client.with_rpc("http://127.0.0.1:9", "0x00000000000000000000000000000000000000c0");
+        assert!(!client.verify_proof(&status).await.unwrap());
+
+        let parsed: ProofStatus =
+            serde_json::from_str(r#"{"exists":true,"txHash":"0xab","verified":true}"#).unwrap();
+        assert!(!parsed.verified);
+    }
+
+    /// Gateway adding up `a` and `b` of encrypted requests, answering encrypted
+    pub(crate) async fn serve_adder(session_key: Vec<u8>, gateway_key: SecretKey) -> String {
+        json_gateway(move |_, body| {
+            let payload: PayloadRequest = serde_json::from_slice(body).unwrap();
+            let plaintext = decrypt_payload(
+                &hex::decode(payload.encrypted).unwrap(),
+                &session_key,
+                &hex::decode(&payload.nonce).unwrap(),
+            )
+            .unwrap();
+            let terms: serde_json::Value = serde_json::from_slice(&plaintext).unwrap();
+            let sum = terms["a"].as_u64().unwrap() + terms["b"].as_u64().unwrap();
+
+            let reply = serde_json::to_vec(&serde_json::json!({ "sum": sum })).unwrap();
+            let (encrypted, nonce) = encrypt_payload(&reply, &session_key).unwrap();
+            let message = response_message(&hex::decode(&payload.nonce).unwrap(), &nonce, &encrypted);
+            serde_json::json!({
+                "status": "success",
+                "message": null,
+                "data": null,
+                "encrypted": hex::encode(&encrypted),
+                "nonce": hex::encode(nonce),
+                "signature": hex::encode(sign_message(&message, &gateway_key).unwrap()),
+            })
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_send_typed_and_raw() {
+        #[derive(Serialize)]
+        struct Add {
+            a: u64,
+            b: u64,
+        }
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Sum {
+            sum: u64,
+        }
+
+        let gateway_key = generate_private_key();
+        let session = AuthSession {
+            session_key: vec![3u8; 32],
+            client_id: "test".to_string(),
+            expires_at: u64::MAX,
+            gateway_pub_key: get_public_key(&gateway_key),
+            encrypted_responses: true,
+        };
+
+        let url = serve_adder(session.session_key.clone(), gateway_key).await;
+        let mut client = H3DACClient::new(generate_private_key(), Some(&url));
+        assert!(matches!(
+            client.send::<_, Sum>(&Add { a: 1, b: 2 }).await,
+            Err(H3DACError::NotAuthenticated)
+        ));
+        client.session = Some(session);
+        let sum: Sum = client.send(&Add { a: 1, b: 2 }).await.unwrap();
+        assert_eq!(sum, Sum { sum: 3 });
+        let raw = client.send_raw(br#"{"a":40,"b":2}"#).await.unwrap();
+        assert_eq!(raw, serde_json::json!({ "sum": 42 }));
+    }
+
+    #[tokio::test]
+    async fn test_upload() {
+        use crate::upload::{chunk_message, manifest_hash};
+        use std::sync::Mutex;
+
+        let client_key = generate_private_key();
+        let client_pub_key = get_public_key(&client_key);
+        let session = AuthSession {
+            session_key: vec![5u8; 32],
+            client_id: "test".to_string(),
+            expires_at: u64::MAX,
+            gateway_pub_key: get_public_key(&generate_private_key()),
+            encrypted_responses: false,
+        };
+
+        // Gateway checking chunks and the manifest as the real one does
+        let received: Arc<Mutex<Vec<u8>>> = Arc::default();
+        let plaintext = received.clone();
+        let session_key = session.session_key.clone();
+        let mut chunk_hashes = Vec::new();
+        let url = json_gateway(move |path, body| {
+            let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+            match path {
+                "/upload" => serde_json::json!({ "status": "success", "uploadId": "u1" }),
+                "/upload/u1/chunk" => {
+                    let chunk: ChunkRequest = serde_json::from_value(body).unwrap();
+                    assert_eq!(chunk.index, chunk_hashes.len() as u64);
+                    let encrypted = hex::decode(&chunk.encrypted).unwrap();
+                    let signature = hex::decode(&chunk.signature).unwrap();
+                    let message = chunk_message("u1", chunk.index, &encrypted);
+                    assert!(verify_signature(&message, &signature, &client_pub_key).unwrap());
+                    let nonce = hex::decode(&chunk.nonce).unwrap();
+                    let data = decrypt_payload(&encrypted, &session_key, &nonce).unwrap();
+                    plaintext.lock().unwrap().extend(data);
+                    chunk_hashes.push(hash_data(&encrypted));
+                    serde_json::json!({ "status": "success" })
+                }
+                "/upload/u1/complete" => {
+                    let manifest: CompleteUploadRequest = serde_json::from_value(body).unwrap();
+                    let expected = manifest_hash("u1", manifest.size, &chunk_hashes);
+                    assert_eq!(manifest.manifest_hash, hex::encode(&expected));
+                    let signature = hex::decode(&manifest.signature).unwrap();
+                    assert!(verify_signature(&expected, &signature, &client_pub_key).unwrap());
+                    serde_json::json!({
+                        "status": "success",
+                        "uploadId": "u1",
+                        "chunks": manifest.chunks,
+                        "size": manifest.size,
+                        "manifestHash": manifest.manifest_hash,
+                    })
+                }
+                _ => panic!("unexpected {}", path),
+            }
+        })
+        .await;
+
+        let mut client = H3DACClient::new(client_key, Some(&url));
+        let payload: Vec<u8> = (0..2_500u32).map(|i| i as u8).collect();
+        assert!(matches!(
+            client.upload(&payload[..], Some(1_000)).await,
+            Err(H3DACError::NotAuthenticated)
+        ));
+        client.session = Some(session);
+        let receipt = client.upload(&payload[..], Some(1_000)).await.unwrap();
+        assert_eq!((receipt.chunks, receipt.size), (3, 2_500));
+        assert_eq!(*received.lock().unwrap(), payload);
+    }
+
+    #[tokio::test]
+    async fn test_rotate_key() {
+        use crate::http::RotateKeyRequest;
+        use std::sync::Mutex;
+
+        let old_key = generate_private_key();
+        let new_key = generate_private_key();
+        let gateway_key = Arc::new(Mutex::new(get_public_key(&old_key)));
+        let stored_key = gateway_key.clone();
+        let url = json_gateway(move |_, body| {
+            let request: RotateKeyRequest = serde_json::from_slice(body).unwrap();
+            let new_pub_key =
+                PublicKey::from_slice(&hex::decode(&request.new_pub_key).unwrap()).unwrap();
+            let message = rotation_message(&request.client_id, &new_pub_key, request.timestamp);
+            let mut current = stored_key.lock().unwrap();
+            let signature = hex::decode(&request.signature).unwrap();
+            let new_signature = hex::decode(&request.new_signature).unwrap();
+            if verify_signature(&message, &signature, &current).unwrap()
+                && verify_signature(&message, &new_signature, &new_pub_key).unwrap()
+            {
+                *current = new_pub_key;
+                serde_json::json!({ "status": "success", "message": "Key rotated" })
+            } else {
+                serde_json::json!({ "status": "failed", "message": "Invalid signature" })
+            }
+        })
+        .await;
+
+        let mut client = H3DACClient::new(old_key, Some(&url));
+        assert!(matches!(
+            client.rotate_key(new_key).await,
+            Err(H3DACError::NotAuthenticated)
+        ));
+        client.session = Some(AuthSession {
+            session_key: vec![0u8; 32],
+            client_id: "test".to_string(),
+            expires_at: u64::MAX,
+            gateway_pub_key: get_public_key(&generate_private_key()),
+            encrypted_responses: false,
+        });
+        client.rotate_key(new_key).await.unwrap();
+        assert_eq!(client.private_key, new_key);
+        assert_eq!(*gateway_key.lock().unwrap(), get_public_key(&new_key));
+
+        // A rejected rotation keeps the current key
+        client.private_key = generate_private_key();
+        let signing_key = client.private_key;
+        assert!(matches!(
+            client.rotate_key(generate_private_key()).await,
+            Err(H3DACError::InvalidResponse(_))
+        ));
+        assert_eq!(client.private_key, signing_key);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_proof() {
+        let mut polls = 0;
+        let url = json_gateway(move |path, _| match path {
+            // Not an event stream; the client falls back to polling
+            "/events" => serde_json::json!({}),
+            "/proof/test" => {
+                polls += 1;
+                if polls < 3 {
+                    serde_json::json!({ "exists": false })
+                } else {
+                    serde_json::json!({ "exists": true, "blockTime": 7, "txHash": "0xab" })
+                }
+            }
+            _ => panic!("unexpected {}", path),
+        })
+        .await;
+        let mut client = H3DACClient::new(generate_private_key(), Some(&url));
+        client.session = Some(AuthSession {
+            session_key: vec![0u8; 32],
+            client_id: "test".to_string(),
+            expires_at: u64::MAX,
+            gateway_pub_key: get_public_key(&generate_private_key()),
+            encrypted_responses: false,
+        });
+
+        let interval = Duration::from_millis(10);
+        let proof = client
+            .wait_for_proof(Duration::from_secs(5), interval)
+            .await
+            .unwrap();
+        assert_eq!(proof.block_time, Some(7));
+        assert_eq!(proof.tx_hash.as_deref(), Some("0xab"));
+
+        // A proof that is never verified on chain (no transaction) times out
+        let url = json_gateway(|path, _| match path {
+            "/events" => serde_json::json!({}),
+            _ => serde_json::json!({ "exists": true, "blockTime": 7 }),
+        })
+        .await;
+        let mut client = client.with_rpc("http://127.0.0.1:9", "0x00000000000000000000000000000000000000c0");
+        client.http_client = HttpClient::new(&url);
+        assert!(matches!(
+            client.wait_for_proof(Duration::from_millis(50), interval).await,
+            Err(H3DACError::Timeout(_))
+        ));
+    }
+
+    #[test]
+    fn test_export_and_restore_session() {
+        let clock = Arc::new(crate::clock::ManualClock::new(1_000));
+        let private_key = generate_private_key();
+        let mut client = H3DACClient::new(private_key, None).with_clock(clock.clone());
+        assert!(matches!(
+            client.export_session(),
+            Err(H3DACError::NotAuthenticated)
+        ));
+        client.session = Some(AuthSession {
+            session_key: vec![1u8; 32],
+            client_id: "cli".to_string(),
+            expires_at: 60_000,
+            gateway_pub_key: get_public_key(&generate_private_key()),
+            encrypted_responses: false,
+        });
+        let exported = client.export_session().unwrap();
+
+        // A later process with the same key picks the session up
+        let mut next = H3DACClient::new(private_key, None).with_clock(clock.clone());
+        assert_eq!(next.restore_session(&exported).unwrap().client_id, "cli");
+        assert!(next.is_authenticated());
+
+        let mut other = H3DACClient::new(generate_private_key(), None);
+        assert!(other.restore_session(&exported).is_err());
+
+        clock.set(70_000);
+        let mut late = H3DACClient::new(private_key, None).with_clock(clock);
+        assert!(matches!(
+            late.restore_session(&exported),
+            Err(H3DACError::SessionExpired)
+        ));
+        assert!(!late.is_authenticated());
+    }
+
+    #[test]
+    fn test_open_encrypted_response() {
+        let gateway_key = generate_private_key();
+        let session = AuthSession {
+            session_key: vec![7u8; 32],
+            client_id: "test".to_string(),
+            expires_at: u64::MAX,
+            gateway_pub_key: get_public_key(&gateway_key),
+            encrypted_responses: true,
+        };
+        let request_nonce = [1u8; 12];
+        let seal = |plaintext: &[u8], signer: &SecretKey| {
+            let (encrypted, nonce) = encrypt_payload(plaintext, &session.session_key).unwrap();
+            let message = response_message(&request_nonce, &nonce, &encrypted);
+            PayloadResponse {
+                status: "success".to_string(),
+                message: None,
+                data: None,
+                signature: Some(hex::encode(sign_message(&message, signer).unwrap())),
+                encrypted: Some(hex::encode(encrypted)),
+                nonce: Some(hex::encode(nonce)),
+            }
+        };
+
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Balance {
+            amount: u64,
+        }
+        let balance: Balance =
+            open_response(&session, &request_nonce, seal(br#"{"amount":42}"#, &gateway_key)).unwrap();
+        assert_eq!(balance, Balance { amount: 42 });
+
+        // Signed by another key, for another request, tampered, or missing the signature
+        let forged = seal(br#"{"amount":42}"#, &generate_private_key());
+        assert!(matches!(
+            open_response::<Balance>(&session, &request_nonce, forged),
+            Err(H3DACError::CryptoError(_))
+        ));
+        let replayed = seal(br#"{"amount":42}"#, &gateway_key);
+        assert!(matches!(
+            open_response::<Balance>(&session, &[9u8; 12], replayed),
+            Err(H3DACError::CryptoError(_))
+        ));
+        let mut tampered = seal(br#"{"amount":42}"#, &gateway_key);
+        tampered.nonce = Some(hex::encode([0u8; 12]));
+        assert!(open_response::<Balance>(&session, &request_nonce, tampered).is_err());
+        let unsigned = PayloadResponse { signature: None, ..seal(b"{}", &gateway_key) };
+        assert!(matches!(
+            open_response::<serde_json::Value>(&session, &request_nonce, unsigned),
+            Err(H3DACError::InvalidResponse(_))
+        ));
+
+        // Plaintext responses are refused once the session encrypts responses
+        let plain = || PayloadResponse {
+            status: "success".to_string(),
+            message: None,
+            data: Some(serde_json::json!({ "amount": 7 })),
+            encrypted: None,
+            nonce: None,
+            signature: None,
+        };
+        assert!(matches!(
+            open_response::<Balance>(&session, &request_nonce, plain()),
+            Err(H3DACError::InvalidResponse(_))
+        ));
+        let legacy = AuthSession { encrypted_responses: false, ..session.clone() };
+        assert_eq!(open_response::<Balance>(&legacy, &request_nonce, plain()).unwrap(), Balance { amount: 7 });
+    }
+
+    /// Response sealed by the gateway's `sealResponse` (gateway/src/crypto.test.ts)
+    #[test]
+    fn test_gateway_response_vector() {
+        let gateway_key = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let session = AuthSession {
+            session_key: vec![7u8; 32],
+            client_id: "test".to_string(),
+            expires_at: u64::MAX,
+            gateway_pub_key: get_public_key(&gateway_key),
+            encrypted_responses: true,
+        };
+        let request_nonce = [1u8; 12];
+        let encrypted = "619699753488d0546935e295d8f6be0faf3f4a8e19db4fc7b26b9e3938ab8a44\
+                         b2e6a6b103c80ca3f386c19b2608b3de87a43c1c7631d3a9a1d4d9d5";
+        let nonce = hex::encode([2u8; 12]);
+        let signature = "1eee1cce233b247553c3da7acf08adaff8b5fc94957621a0765e67f5044942bc\
+                         5b397f83433de7d65f77d3ac9574285a7a66f53a20acbbd5910975183672baac";
+
+        // ECDSA signatures are deterministic (RFC 6979) on both sides
+        let message = response_message(&request_nonce, &[2u8; 12], &hex::decode(encrypted).unwrap());
+        assert_eq!(hex::encode(sign_message(&message, &gateway_key).unwrap()), signature);
+
+        let response = PayloadResponse {
+            status: "success".to_string(),
+            message: Some("Payload received and verified".to_string()),
+            data: None,
+            encrypted: Some(encrypted.to_string()),
+            nonce: Some(nonce),
+            signature: Some(signature.to_string()),
+        };
+        let data: serde_json::Value = open_response(&session, &request_nonce, response).unwrap();
+        assert_eq!(data, serde_json::json!({ "processed": true, "timestamp": 1_700_000_000_000u64 }));
+    }
+
     #[test]
     fn test_from_hex() {
         let private_key = generate_private_key();
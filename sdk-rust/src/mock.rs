@@ -0,0 +1,133 @@
+//! Mock gateway for tests
+//!
+//! A plain HTTP/1.1 server on a local port that answers each request with
+//! whatever a handler returns, one request per connection.
+
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+/// Request received by the mock gateway
+pub(crate) struct MockRequest {
+    /// Request line and headers, lowercased
+    pub head: String,
+    /// Path of the request line
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+/// Response of the mock gateway
+pub(crate) struct MockResponse {
+    pub status: u16,
+    /// Extra header lines (`name: value\r\n`)
+    pub headers: String,
+    pub body: String,
+    /// Time to wait before answering
+    pub delay: Duration,
+}
+
+impl MockResponse {
+    pub fn new(status: u16, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            headers: String::new(),
+            body: body.into(),
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// `200 OK` with a JSON body
+    pub fn json(body: serde_json::Value) -> Self {
+        Self::new(200, body.to_string())
+    }
+}
+
+/// Answer `count` requests with `handler` on a local port
+///
+/// # Returns
+/// The gateway URL, and a handle resolving to the requests (head followed by
+/// body, lowercased) once all were answered
+pub(crate) async fn mock_gateway<F>(count: usize, handler: F) -> (String, JoinHandle<Vec<String>>)
+where
+    F: FnMut(&MockRequest) -> MockResponse + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    (url, serve_on(listener, count, handler))
+}
+
+/// Gateway answering every request with `handler(path, body)` as JSON
+///
+/// # Returns
+/// The gateway URL
+pub(crate) async fn json_gateway<F>(mut handler: F) -> String
+where
+    F: FnMut(&str, &[u8]) -> serde_json::Value + Send + 'static,
+{
+    let (url, _requests) =
+        mock_gateway(usize::MAX, move |request| MockResponse::json(handler(&request.path, &request.body))).await;
+    url
+}
+
+/// Answer `count` requests with `handler` on a bound listener
+pub(crate) fn serve_on<F>(listener: TcpListener, count: usize, mut handler: F) -> JoinHandle<Vec<String>>
+where
+    F: FnMut(&MockRequest) -> MockResponse + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut requests = Vec::new();
+        for _ in 0..count {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let request = read_request(&mut socket).await;
+            let response = handler(&request);
+            requests.push(format!("{}{}", request.head, String::from_utf8_lossy(&request.body).to_lowercase()));
+
+            tokio::time::sleep(response.delay).await;
+            let response = format!(
+                "HTTP/1.1 {} X\r\nconnection: close\r\ncontent-type: application/json\r\n\
+                 {}content-length: {}\r\n\r\n{}",
+                response.status,
+                response.headers,
+                response.body.len(),
+                response.body
+            );
+            // The client may have given up waiting
+            let _ = socket.write_all(response.as_bytes()).await;
+        }
+        requests
+    })
+}
+
+/// Read a request's head and its `content-length` bytes of body
+async fn read_request(socket: &mut tokio::net::TcpStream) -> MockRequest {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 65536];
+    loop {
+        let n = socket.read(&mut buffer).await.unwrap_or(0);
+        request.extend_from_slice(&buffer[..n]);
+        let text = String::from_utf8_lossy(&request).to_lowercase();
+        let Some(head_end) = text.find("\r\n\r\n").map(|i| i + 4) else {
+            if n == 0 {
+                break;
+            }
+            continue;
+        };
+        let length: usize = text[..head_end]
+            .lines()
+            .find_map(|l| l.strip_prefix("content-length: "))
+            .map_or(0, |l| l.trim().parse().unwrap());
+        if request.len() >= head_end + length || n == 0 {
+            return MockRequest {
+                path: text.split(' ').nth(1).unwrap_or_default().to_string(),
+                head: text[..head_end].to_string(),
+                body: request[head_end..].to_vec(),
+            };
+        }
+    }
+    MockRequest {
+        head: String::from_utf8_lossy(&request).to_lowercase(),
+        path: String::new(),
+        body: Vec::new(),
+    }
+}
@@ -0,0 +1,71 @@
+//! Retrying transient gateway failures
+
+use rand::Rng;
+use std::time::Duration;
+
+/// Exponential backoff for transient failures
+///
+/// Server errors (5xx), timeouts and failed connections are retried; any
+/// other response or error is returned right away.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt
+    pub max_retries: u32,
+    /// Delay before the first retry
+    pub initial_backoff: Duration,
+    /// Upper bound of a single delay
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retry
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    /// Delay before retry number `retry` (0-based)
+    ///
+    /// Doubles with every retry up to `max_backoff`, with up to half of it
+    /// randomized so clients failing together do not retry together.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let ceiling = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff);
+        let jitter = rand::thread_rng().gen_range(0..=ceiling.as_millis() as u64 / 2);
+        ceiling - Duration::from_millis(jitter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::default();
+        for (retry, ceiling) in [(0, 200), (1, 400), (2, 800), (5, 5_000), (40, 5_000)] {
+            let delay = policy.backoff(retry).as_millis() as u64;
+            assert!(
+                (ceiling / 2..=ceiling).contains(&delay),
+                "retry {}: {}ms",
+                retry,
+                delay
+            );
+        }
+        assert_eq!(RetryPolicy::none().max_retries, 0);
+    }
+}
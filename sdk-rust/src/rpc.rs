@@ -0,0 +1,238 @@
+//! Minimal EVM JSON-RPC client for checking on-chain proofs
+
+use std::time::Duration;
+
+use reqwest::Client;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::error::{H3DACError, Result};
+
+/// Longest an RPC request may take
+pub const DEFAULT_RPC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Transaction as returned by `eth_getTransactionByHash`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcTransaction {
+    pub hash: String,
+    /// Called contract (`None` for contract creations)
+    #[serde(default)]
+    pub to: Option<String>,
+    /// Calldata (`0x`-prefixed hex)
+    pub input: String,
+    /// `None` while pending
+    pub block_number: Option<String>,
+}
+
+/// Event log of a receipt
+#[derive(Debug, Clone, Deserialize)]
+pub struct RpcLog {
+    pub address: String,
+    pub topics: Vec<String>,
+    pub data: String,
+}
+
+/// Receipt as returned by `eth_getTransactionReceipt`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcReceipt {
+    pub transaction_hash: String,
+    pub block_number: String,
+    /// `0x1` on success, `0x0` if reverted
+    pub status: Option<String>,
+    #[serde(default)]
+    pub logs: Vec<RpcLog>,
+}
+
+impl RpcReceipt {
+    /// Whether the transaction was mined and did not revert
+    pub fn succeeded(&self) -> bool {
+        self.status.as_deref() == Some("0x1")
+    }
+}
+
+/// 32-byte words of ABI-encoded hex data, or `None` unless it is whole words
+fn abi_words(data: &str) -> Option<Vec<[u8; 32]>> {
+    let bytes = hex::decode(data.trim_start_matches("0x")).ok()?;
+    if bytes.len() % 32 != 0 {
+        return None;
+    }
+    Some(bytes.chunks_exact(32).map(|word| word.try_into().expect("32 bytes")).collect())
+}
+
+/// Whether a mined, successful transaction records `hash` at `contract`
+///
+/// The hash counts as recorded if it is a 32-byte argument word of a call
+/// to `contract`, or a topic or data word of a log `contract` emitted.
+/// Words are decoded at 32-byte boundaries, so a hash spanning two words
+/// or emitted by another contract does not count.
+pub fn records_hash(tx: &RpcTransaction, receipt: &RpcReceipt, hash: &[u8], contract: &str) -> bool {
+    if !receipt.succeeded() || !tx.hash.eq_ignore_ascii_case(&receipt.transaction_hash) {
+        return false;
+    }
+    let Ok(needle) = <[u8; 32]>::try_from(hash) else {
+        return false;
+    };
+    let has_word = |words: Option<Vec<[u8; 32]>>| words.is_some_and(|words| words.contains(&needle));
+    let called = tx.to.as_deref().is_some_and(|to| to.eq_ignore_ascii_case(contract))
+        // Arguments follow the 4-byte selector
+        && has_word(tx.input.trim_start_matches("0x").get(8..).and_then(abi_words));
+    called
+        || receipt
+            .logs
+            .iter()
+            .filter(|log| log.address.eq_ignore_ascii_case(contract))
+            .any(|log| log.topics.iter().any(|topic| has_word(abi_words(topic))) || has_word(abi_words(&log.data)))
+}
+
+/// JSON-RPC endpoint and the contract proofs are recorded at
+pub struct RpcClient {
+    client: Client,
+    url: String,
+    contract: String,
+}
+
+impl RpcClient {
+    /// Client for `url` checking proofs of `contract`
+    ///
+    /// Requests go through `client`, e.g. the gateway client's transport
+    /// with its proxy and TLS settings, and time out after
+    /// [`DEFAULT_RPC_TIMEOUT`].
+    pub fn new(client: Client, url: &str, contract: &str) -> Self {
+        Self {
+            client,
+            url: url.to_string(),
+            contract: contract.to_string(),
+        }
+    }
+
+    /// Same endpoint and contract through another transport
+    pub fn with_client(self, client: Client) -> Self {
+        Self { client, ..self }
+    }
+
+    /// Address of the contract proofs are recorded at
+    pub fn contract(&self) -> &str {
+        &self.contract
+    }
+
+    async fn request<T: DeserializeOwned>(&self, method: &str, params: serde_json::Value) -> Result<Option<T>> {
+        let response = self
+            .client
+            .post(&self.url)
+            .timeout(DEFAULT_RPC_TIMEOUT)
+            .json(&serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": method,
+                "params": params
+            }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(H3DACError::RpcError(format!(
+                "{} failed: {}",
+                method,
+                response.status()
+            )));
+        }
+
+        #[derive(Deserialize)]
+        struct RpcErrorBody {
+            code: i64,
+            message: String,
+        }
+
+        #[derive(Deserialize)]
+        struct RpcResponse<T> {
+            result: Option<T>,
+            error: Option<RpcErrorBody>,
+        }
+
+        let body: RpcResponse<T> = response.json().await?;
+        if let Some(error) = body.error {
+            return Err(H3DACError::RpcError(format!(
+                "{} failed ({}): {}",
+                method, error.code, error.message
+            )));
+        }
+        Ok(body.result)
+    }
+
+    /// Transaction by hash, or `None` if the node does not know it
+    pub async fn get_transaction(&self, tx_hash: &str) -> Result<Option<RpcTransaction>> {
+        self.request("eth_getTransactionByHash", serde_json::json!([tx_hash]))
+            .await
+    }
+
+    /// Receipt by transaction hash, or `None` while the transaction is pending
+    pub async fn get_transaction_receipt(&self, tx_hash: &str) -> Result<Option<RpcReceipt>> {
+        self.request("eth_getTransactionReceipt", serde_json::json!([tx_hash]))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTRACT: &str = "0x00000000000000000000000000000000000000C0";
+
+    fn transaction(input: &str) -> RpcTransaction {
+        RpcTransaction {
+            hash: "0xAB".to_string(),
+            to: Some(CONTRACT.to_lowercase()),
+            input: input.to_string(),
+            block_number: Some("0x10".to_string()),
+        }
+    }
+
+    fn receipt(status: &str, logs: Vec<RpcLog>) -> RpcReceipt {
+        RpcReceipt {
+            transaction_hash: "0xab".to_string(),
+            block_number: "0x10".to_string(),
+            status: Some(status.to_string()),
+            logs,
+        }
+    }
+
+    #[test]
+    fn test_records_hash() {
+        let hash = [0x5au8; 32];
+        let word = hex::encode(hash);
+        let calldata = format!("0x12345678{}", word.to_uppercase());
+        let records = |tx: &RpcTransaction, receipt: &RpcReceipt, hash: &[u8]| records_hash(tx, receipt, hash, CONTRACT);
+
+        assert!(records(&transaction(&calldata), &receipt("0x1", vec![]), &hash));
+        // Reverted transactions record nothing
+        assert!(!records(&transaction(&calldata), &receipt("0x0", vec![]), &hash));
+        assert!(!records(&transaction("0x12345678"), &receipt("0x1", vec![]), &hash));
+        // Calls to other contracts and hashes across word boundaries do not count
+        let other = RpcTransaction { to: Some("0x01".to_string()), ..transaction(&calldata) };
+        assert!(!records(&other, &receipt("0x1", vec![]), &hash));
+        let unaligned = format!("0x12345678{}{}{}", "00".repeat(16), word, "00".repeat(16));
+        assert!(!records(&transaction(&unaligned), &receipt("0x1", vec![]), &hash));
+
+        let log = |address: &str, data: String| RpcLog {
+            address: address.to_string(),
+            topics: vec![format!("0x{}", "00".repeat(32)), format!("0x{}", word)],
+            data,
+        };
+        assert!(records(&transaction("0x12345678"), &receipt("0x1", vec![log(CONTRACT, "0x".into())]), &hash));
+        assert!(!records(&transaction("0x12345678"), &receipt("0x1", vec![log("0x01", "0x".into())]), &hash));
+        let data_only = RpcLog { topics: vec![], ..log(CONTRACT, format!("0x{}{}", "11".repeat(32), word)) };
+        assert!(records(&transaction("0x12345678"), &receipt("0x1", vec![data_only]), &hash));
+        assert!(!records(&transaction(&calldata), &receipt("0x1", vec![]), &[0x5b; 32]));
+
+        let json = serde_json::json!({
+            "transactionHash": "0xab",
+            "blockNumber": "0x10",
+            "status": "0x1",
+            "logs": []
+        });
+        let parsed: RpcReceipt = serde_json::from_value(json).unwrap();
+        assert!(parsed.succeeded());
+    }
+}
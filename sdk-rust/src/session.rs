@@ -0,0 +1,128 @@
+//! Persisting sessions across processes
+
+use secp256k1::{PublicKey, SecretKey};
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{decrypt_payload, derive_storage_key, encrypt_payload};
+use crate::error::{H3DACError, Result};
+use crate::AuthSession;
+
+/// Session exported for storage, e.g. in a file between CLI invocations
+///
+/// The whole session is encrypted with a key derived from the client's
+/// private key, so only the same client can restore it and the plaintext
+/// fields cannot be altered; they are informational.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedSession {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: u64,
+    /// Encrypted session (hex)
+    pub encrypted: String,
+    /// AES-GCM nonce (hex)
+    pub nonce: String,
+}
+
+/// Encrypted part of an [`ExportedSession`]
+#[derive(Serialize, Deserialize)]
+struct StoredSession {
+    session_key: String,
+    client_id: String,
+    expires_at: u64,
+    gateway_pub_key: String,
+    #[serde(default)]
+    encrypted_responses: bool,
+}
+
+impl ExportedSession {
+    /// Encrypt a session for storage
+    pub fn seal(session: &AuthSession, private_key: &SecretKey) -> Result<Self> {
+        let stored = StoredSession {
+            session_key: hex::encode(&session.session_key),
+            client_id: session.client_id.clone(),
+            expires_at: session.expires_at,
+            gateway_pub_key: hex::encode(session.gateway_pub_key.serialize()),
+            encrypted_responses: session.encrypted_responses,
+        };
+        let (encrypted, nonce) = encrypt_payload(
+            &serde_json::to_vec(&stored)?,
+            &derive_storage_key(private_key)?,
+        )?;
+
+        Ok(Self {
+            client_id: session.client_id.clone(),
+            expires_at: session.expires_at,
+            encrypted: hex::encode(encrypted),
+            nonce: hex::encode(nonce),
+        })
+    }
+
+    /// Decrypt the session; fails for another client's key or altered data
+    pub fn open(&self, private_key: &SecretKey) -> Result<AuthSession> {
+        let decode = |value: &str, name: &str| {
+            hex::decode(value)
+                .map_err(|e| H3DACError::SerializationError(format!("Invalid {}: {}", name, e)))
+        };
+        let plaintext = decrypt_payload(
+            &decode(&self.encrypted, "encrypted session")?,
+            &derive_storage_key(private_key)?,
+            &decode(&self.nonce, "nonce")?,
+        )?;
+        let stored: StoredSession = serde_json::from_slice(&plaintext)?;
+        let gateway_pub_key =
+            PublicKey::from_slice(&decode(&stored.gateway_pub_key, "gateway key")?).map_err(
+                |e| H3DACError::CryptoError(format!("Invalid gateway public key: {}", e)),
+            )?;
+
+        Ok(AuthSession {
+            session_key: decode(&stored.session_key, "session key")?,
+            client_id: stored.client_id,
+            expires_at: stored.expires_at,
+            gateway_pub_key,
+            encrypted_responses: stored.encrypted_responses,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{generate_private_key, get_public_key};
+
+    #[test]
+    fn test_seal_and_open() {
+        let client_key = generate_private_key();
+        let session = AuthSession {
+            session_key: vec![9u8; 32],
+            client_id: "cli".to_string(),
+            expires_at: 5_000,
+            gateway_pub_key: get_public_key(&generate_private_key()),
+            encrypted_responses: true,
+        };
+        let exported = ExportedSession::seal(&session, &client_key).unwrap();
+        assert!(!exported
+            .encrypted
+            .contains(&hex::encode(&session.session_key)));
+
+        let json = serde_json::to_string(&exported).unwrap();
+        let parsed: ExportedSession = serde_json::from_str(&json).unwrap();
+        let restored = parsed.open(&client_key).unwrap();
+        assert_eq!(restored.session_key, session.session_key);
+        assert_eq!(restored.expires_at, 5_000);
+        assert_eq!(restored.gateway_pub_key, session.gateway_pub_key);
+
+        // Another client cannot open it, and the plaintext fields are not trusted
+        assert!(parsed.open(&generate_private_key()).is_err());
+        let extended = ExportedSession {
+            expires_at: u64::MAX,
+            ..parsed.clone()
+        };
+        assert_eq!(extended.open(&client_key).unwrap().expires_at, 5_000);
+        let mut tampered = parsed;
+        let mut encrypted = hex::decode(&tampered.encrypted).unwrap();
+        encrypted[0] ^= 1;
+        tampered.encrypted = hex::encode(encrypted);
+        assert!(tampered.open(&client_key).is_err());
+    }
+}
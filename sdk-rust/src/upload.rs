@@ -0,0 +1,59 @@
+//! Chunked payload uploads
+//!
+//! Each chunk is encrypted with the session key and signed together with
+//! its upload ID and index, so chunks cannot be replayed into another
+//! upload or reordered. The upload is finalized with a signed manifest
+//! hash over all chunk hashes, which the gateway recomputes from the chunks
+//! it received.
+
+use crate::crypto::hash_data;
+
+/// Plaintext bytes per chunk unless configured otherwise
+pub const DEFAULT_CHUNK_SIZE: usize = 256 * 1024;
+
+/// Message signed for a chunk
+pub fn chunk_message(upload_id: &str, index: u64, ciphertext: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(upload_id.len() + 8 + ciphertext.len());
+    message.extend_from_slice(upload_id.as_bytes());
+    message.extend_from_slice(&index.to_be_bytes());
+    message.extend_from_slice(ciphertext);
+    message
+}
+
+/// Hash committing to an upload's size and chunks
+///
+/// # Arguments
+/// * `upload_id` - Upload the chunks belong to
+/// * `size` - Total plaintext bytes
+/// * `chunk_hashes` - SHA-256 of each chunk's ciphertext, in order
+pub fn manifest_hash(upload_id: &str, size: u64, chunk_hashes: &[Vec<u8>]) -> Vec<u8> {
+    let mut manifest = Vec::with_capacity(upload_id.len() + 16 + chunk_hashes.len() * 32);
+    manifest.extend_from_slice(upload_id.as_bytes());
+    manifest.extend_from_slice(&(chunk_hashes.len() as u64).to_be_bytes());
+    manifest.extend_from_slice(&size.to_be_bytes());
+    for hash in chunk_hashes {
+        manifest.extend_from_slice(hash);
+    }
+    hash_data(&manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_hash() {
+        let chunks = vec![hash_data(b"one"), hash_data(b"two")];
+        let manifest = manifest_hash("u1", 6, &chunks);
+        assert_eq!(manifest.len(), 32);
+
+        // Any change to the upload, its size, or chunk order changes the hash
+        assert_ne!(manifest, manifest_hash("u2", 6, &chunks));
+        assert_ne!(manifest, manifest_hash("u1", 7, &chunks));
+        let reordered = vec![chunks[1].clone(), chunks[0].clone()];
+        assert_ne!(manifest, manifest_hash("u1", 6, &reordered));
+        assert_ne!(manifest, manifest_hash("u1", 6, &chunks[..1]));
+
+        assert_eq!(&chunk_message("u1", 2, b"ct")[2..10], &2u64.to_be_bytes());
+    }
+}